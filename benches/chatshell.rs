@@ -0,0 +1,135 @@
+//! Benchmarks of what runs on every key and every chunk of output.
+//!
+//! Compare a change with main, whose baseline `compare.sh` saves first:
+//!
+//! ```bash
+//! benches/compare.sh
+//! ```
+
+use chatshell::config::TerminalConfig;
+use chatshell::hooks::{create_default_hooks, HookManager};
+use chatshell::output::OutputProcessor;
+use chatshell::terminal::KeyInput;
+use chatshell::window::WindowManager;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use crossterm::event::{KeyCode, KeyModifiers};
+
+fn keys() -> Vec<KeyInput> {
+    [
+        (KeyCode::Char('a'), KeyModifiers::empty()),
+        (KeyCode::Char('a'), KeyModifiers::CONTROL),
+        (KeyCode::Char('a'), KeyModifiers::ALT),
+        (KeyCode::Char('c'), KeyModifiers::CONTROL | KeyModifiers::SHIFT),
+        (KeyCode::Up, KeyModifiers::empty()),
+        (KeyCode::F(1), KeyModifiers::empty()),
+        (KeyCode::F(12), KeyModifiers::empty()),
+        (KeyCode::Enter, KeyModifiers::ALT),
+        (KeyCode::Tab, KeyModifiers::empty()),
+    ]
+    .into_iter()
+    .map(|(code, modifiers)| KeyInput::new(code, modifiers))
+    .collect()
+}
+
+fn key_encoding(c: &mut Criterion) {
+    let codes: Vec<(KeyCode, KeyModifiers)> = keys().iter().map(|key| (key.code, key.modifiers)).collect();
+    c.bench_function("key_encoding", |b| {
+        b.iter(|| {
+            for &(code, modifiers) in &codes {
+                black_box(KeyInput::new(code, modifiers));
+            }
+        })
+    });
+}
+
+fn chord_matching(c: &mut Criterion) {
+    let patterns = ["ctrl+a", "ctrl+c", "alt+enter", "ctrl+shift+c", "f1", "f12", "up", "home", "ctrl+a t"];
+    let keys = keys();
+    c.bench_function("chord_matching", |b| {
+        b.iter(|| {
+            for key in &keys {
+                for pattern in &patterns {
+                    black_box(key.matches_pattern(pattern));
+                }
+            }
+        })
+    });
+}
+
+fn hook_dispatch(c: &mut Criterion) {
+    // Keys no default hook is bound to, so none opens a popup
+    let typed: Vec<KeyInput> = "git status && make -j8"
+        .chars()
+        .map(|c| KeyInput::new(KeyCode::Char(c), KeyModifiers::empty()))
+        .collect();
+    let mut group = c.benchmark_group("hook_dispatch");
+    group.throughput(Throughput::Elements(typed.len() as u64));
+    let mut defaults = HookManager::from_configs(create_default_hooks());
+    group.bench_function("default_hooks", |b| {
+        b.iter(|| {
+            for key in &typed {
+                black_box(defaults.process_key(key).ok());
+            }
+        })
+    });
+    let mut empty = HookManager::new();
+    group.bench_function("no_hooks", |b| {
+        b.iter(|| {
+            for key in &typed {
+                black_box(empty.process_key(key).ok());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn typing(c: &mut Criterion) {
+    let typed: Vec<KeyInput> = "The quick brown fox jumps over the lazy dog. "
+        .repeat(100)
+        .chars()
+        .map(|c| KeyInput::new(KeyCode::Char(c), KeyModifiers::empty()))
+        .collect();
+    let mut group = c.benchmark_group("typing");
+    group.throughput(Throughput::Elements(typed.len() as u64));
+    let mut hooks = HookManager::new();
+    group.bench_function("long_document", |b| {
+        b.iter(|| {
+            for key in &typed {
+                black_box(hooks.process_key(key).ok());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn output_pipeline(c: &mut Criterion) {
+    let mut chunk = Vec::new();
+    for n in 0..200 {
+        chunk.extend_from_slice(format!("\x1b[32m{:>4}\x1b[0m  drwxr-xr-x  src/module_{}.rs  \x1b[1;34m4.0K\x1b[0m\r\n", n, n).as_bytes());
+    }
+    chunk.extend_from_slice(b"\x1b]133;A\x07$ \x1b]133;B\x07");
+    let mut group = c.benchmark_group("output_pipeline");
+    group.throughput(Throughput::Bytes(chunk.len() as u64));
+    let mut output = OutputProcessor::new(TerminalConfig::default());
+    output.resize(120, 40);
+    group.bench_function("colored_listing", |b| b.iter(|| black_box(output.process(&chunk))));
+    group.finish();
+}
+
+fn popup_rendering(c: &mut Criterion) {
+    let windows = WindowManager { terminal_size: (120, 40), ..WindowManager::default() };
+    let text: String = (0..30)
+        .map(|n| format!("{:>2}. error[E0308]: mismatched types in src/handler_{}.rs, 日本語のメッセージ\n", n, n))
+        .collect();
+    let mut screen = Vec::with_capacity(64 * 1024);
+    c.bench_function("popup_rendering", |b| {
+        b.iter(|| {
+            screen.clear();
+            windows.render_popup(&mut screen, "Build errors", &text).unwrap();
+            black_box(screen.len())
+        })
+    });
+}
+
+criterion_group!(benches, key_encoding, chord_matching, hook_dispatch, typing, output_pipeline, popup_rendering);
+criterion_main!(benches);
@@ -0,0 +1,18 @@
+#!/bin/sh
+# Compares the benchmarks of the working tree with those of a commit, main
+# unless another is given; further arguments, like a benchmark's name, go
+# to criterion. The commit is checked out in a temporary worktree that
+# shares the target directory, where criterion keeps the baseline.
+set -eu
+
+base=${1:-main}
+[ $# -gt 0 ] && shift
+root=$(git rev-parse --show-toplevel)
+export CARGO_TARGET_DIR="${CARGO_TARGET_DIR:-$root/target}"
+tree=$(mktemp -d)
+trap 'git -C "$root" worktree remove --force "$tree"' EXIT
+
+git -C "$root" worktree add --detach "$tree" "$base"
+(cd "$tree" && cargo bench --bench chatshell -- --save-baseline "$base" "$@")
+cd "$root"
+cargo bench --bench chatshell -- --baseline "$base" "$@"
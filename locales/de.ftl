@@ -0,0 +1,304 @@
+# Deutsche Texte von ChatShell. Fehlende Texte werden auf Englisch angezeigt.
+
+## Sitzung
+
+session-started = ChatShell gestartet. Strg+; zeigt die Hilfe.
+session-shell-ended = Shell-Prozess beendet.
+session-device-gone = Serielles Gerät { $device } ist nicht mehr da.
+session-summarizing = Sitzung wird in { $path } zusammengefasst...
+automation-timeout = Startautomatisierung abgebrochen: { $pattern } ist nicht rechtzeitig erschienen.
+
+## Popups
+
+popup-close = ESC schließt
+popup-close-pin = ESC schließt · p heftet an
+toast-title = ChatShell
+popup-error = Fehler
+popup-more-lines = ... { $count } weitere Zeilen
+popup-more-rows = ... { $count } weitere Zeilen
+draft-offer = Ungesicherter Entwurf von { $time }: Strg+R stellt ihn wieder her
+input-search = (Suche) { $query }: { $match }
+editor-hint = ESC schließen · Änderungen bleiben erhalten
+live-popup-hint = läuft…
+notes-title = Notizen: { $session }
+full-screen-title = Vollbildprogramm
+full-screen-held-back = { $hook } tippt Text, den das Vollbildprogramm als Tasten lesen würde, und lief deshalb nicht. Mit when = { "{" } alt_screen = true { "}" } läuft es auch hier.
+full-screen-input-held-back = { $hook } hat Text getippt, den das Vollbildprogramm als Tasten lesen würde, und er wurde weggelassen. Mit when = { "{" } alt_screen = true { "}" } wird er auch hier getippt.
+menu-hint = ESC schließen · ↑↓ auswählen · Enter wählen
+confirm-yes = Ja
+confirm-no = Nein
+confirm-hint = y ja · n nein · ESC nein
+
+## Fehler
+
+error-title-config = Konfigurationsfehler
+error-title-pty = Shell-Fehler
+error-title-llm = Assistentenfehler
+error-title-ui = Anzeigefehler
+error-title-plugin = Hook-Fehler
+error-reference = Referenz { $reference } in { $log }
+error-reference-unlogged = Referenz { $reference }
+error-copy = c kopiert die Details
+unknown-keys-title = Unbekannte Konfigurationsschlüssel
+unknown-keys-intro = Diese Schlüssel der Konfiguration sind unbekannt und wurden ausgelassen:
+unknown-key-suggestion = { $key } (meinten Sie { $suggestion }?)
+error-unknown-function = Unbekannte Funktion: { $name }
+error-unknown-builtin = Unbekanntes Builtin: { $name }
+error-needs-database = Dafür wird eine psql-, mysql- oder sqlite3-Sitzung gebraucht.
+error-needs-llm = Dafür wird ein [llm]-Befehl in der Konfiguration gebraucht.
+error-capture-timeout = Die Shell hat den Befehl nicht rechtzeitig ausgeführt.
+
+## Hook-Funktionen
+
+help-title = Hilfe
+help-text =
+    === ChatShell-Hilfe ===
+
+    ChatShell ist eine transparente Hülle um die Shell.
+    Alle Tastendrücke gehen an die Shell darunter.
+
+    Besondere Tastenkombinationen lösen Hooks aus:
+help-close = ESC schließt dieses Fenster.
+cheatsheet-title = ChatShell-Tastenkürzel
+cheatsheet-keys = Tasten
+cheatsheet-action = Aktion
+time-title = Uhrzeit
+convert-time-title = Zeit umrechnen
+convert-time-prompt = Epoch oder Zeit, leer für jetzt:
+config-title = Konfiguration
+config-text =
+    === Aktuelle Hook-Konfiguration ===
+
+    Name: { $name }
+    Taste: { $key }
+    Aktion: { $action }
+    Aktiv: { $enabled }
+config-description = Beschreibung: { $description }
+command-title = Befehl: { $command }
+command-failed =
+    Befehl fehlgeschlagen:
+    { $stderr }
+command-no-output = Befehl erfolgreich ausgeführt (keine Ausgabe)
+command-finished = { $command } ist fertig
+command-timed-out = Der Befehl brauchte länger als { $seconds } s und wurde beendet.
+switch-shell-title = Shell wechseln
+tail-title = Tail
+tail-following = folgt
+tail-paused = angehalten
+tail-hint = ESC schließen · Leertaste anhalten · ↑↓ blättern · / hervorheben
+highlight-title = Hervorheben
+highlight-prompt = Regex zum Hervorheben (leer zum Löschen):
+
+## Assistent
+
+ask-title = Fragen
+ask-prompt = Was brauchst du?
+assistant-title = Assistent
+chat-empty = Fragen an den Assistenten und seine Antworten erscheinen hier.
+suggestion-title = Vorschlag verwenden
+suggestion-careful = Vorsicht: Dies { $reason }
+suggestion-run = Ausführen
+suggestion-type = Nur eintippen
+suggestion-confirm = Trotzdem ausführen?
+
+## Warum ein Vorschlag bestätigt werden muss
+
+risk-deletes-recursively = löscht Dateien rekursiv
+risk-formats-filesystem = formatiert ein Dateisystem
+risk-writes-raw-device = schreibt direkt auf ein Gerät
+risk-fork-bomb = ist eine Fork-Bombe
+risk-opens-permissions = öffnet Berechtigungen rekursiv
+risk-forces = erzwingt eine zerstörerische Aktion
+risk-shuts-down = fährt den Rechner herunter
+risk-reboots = startet den Rechner neu
+risk-deletes-tree = löscht einen Verzeichnisbaum
+risk-deletes-files = löscht Dateien
+risk-runs-shell = führt einen Shell-Befehl aus
+risk-exits-repl = beendet die REPL
+risk-drops-object = löscht ein Datenbankobjekt
+risk-truncates = löscht alle Zeilen einer Tabelle
+risk-alters-table = ändert eine Tabellendefinition
+risk-changes-every-row = ändert alle Zeilen einer Tabelle
+risk-erases-flash = löscht Flash-Speicher
+risk-saves-environment = überschreibt die gespeicherte Umgebung des Geräts
+
+## Datenbanksitzungen
+
+explain-title = Abfrage erklären
+last-result-title = Letztes Ergebnis
+last-result-rows = Letztes Ergebnis ({ $count } Zeilen)
+no-query = In der letzten Ausgabe wurde keine Abfrage gefunden.
+
+## Befehle wiederholen und beobachten
+
+rerun-title = Wiederholen
+rerun-command-title = Wiederholt: { $command }
+rerun-no-command = An dieser Eingabeaufforderung wurde kein vorheriger Befehl gefunden.
+rerun-unchanged = Ausgabe unverändert.
+rerun-skipped = @@ { $count } unveränderte Zeilen @@
+watch-title = Beobachten
+watch-command-prompt = Befehl, der im Hintergrund laufen soll:
+watch-interval-prompt = Intervall (Standard 2s):
+
+## Lesezeichen
+
+bookmark-title = Lesezeichen
+bookmark-prompt = Name (leer zum Nummerieren):
+
+## Temporäre Tastenbelegungen
+
+bind-temp-title = Taste belegen
+bind-temp-key-prompt = Taste, z. B. f9 oder ctrl+alt+d:
+bind-temp-action-prompt = Befehl oder Aktion:
+bind-temp-lifetime-prompt = Wie lange, z. B. 1h (leer für diese Sitzung):
+bind-temp-bad-key = Keine Tastenkombination: { $key }
+
+## Hook-Verwaltung
+
+hook-manager-title = Hooks
+hook-manager-count = { $enabled } von { $count } aktiv
+hook-manager-trigger = TASTE/EREIGNIS
+hook-manager-name = NAME
+hook-manager-action = AKTION
+hook-manager-hint = ESC schließen · ↑↓ auswählen · Leertaste an/aus
+
+## Befehlspalette
+
+palette-title = Aktionen
+palette-hint = ESC schließen · ↑↓ auswählen · Enter ausführen · tippen zum Filtern
+palette-none = Keine Treffer.
+action-show-help = Hilfe anzeigen
+action-show-time = Uhrzeit anzeigen
+action-convert-time = Zeitstempel umrechnen
+action-ask = Den Assistenten fragen
+action-toggle-chat = Chat mit dem Assistenten ein- oder ausblenden
+action-explain-query = Letzte Abfrage erklären
+action-show-last-result = Letztes Abfrageergebnis anzeigen
+action-rerun-and-diff = Letzten Befehl erneut ausführen und vergleichen
+action-watch-command = Befehl beobachten
+action-split-pane = Seitenbereich öffnen
+action-focus-pane = Fokus zwischen Shell und Bereich wechseln
+action-close-pane = Seitenbereich schließen
+action-undo-input = Eingetippte Eingabe zurücknehmen
+action-paste-clipboard = Zwischenablage einfügen
+action-bookmark = Diese Stelle merken
+action-wayback = Durch frühere Bildschirme blättern
+action-search-scrollback = Ausgabe der Shell durchsuchen
+action-copy-mode = Ausgabe der Shell kopieren oder kommentieren
+action-reopen-last-popup = Geschlossenes Fenster wieder öffnen
+action-move-note = Angeheftete Notiz in die nächste Ecke schieben
+action-close-note = Angeheftete Notiz schließen
+action-process-picker = Prozesse
+action-ports = Ports und Verbindungen
+action-env-diff = Umgebung der Shell vergleichen
+action-reload-env = Umgebung neu laden
+action-switch-shell = Shell wechseln
+action-clear-screen = Bildschirm leeren
+action-hook-manager = Hooks ein- und ausschalten
+action-bind-temp = Taste für diese Sitzung belegen
+action-notes = Notizen zu dieser Sitzung
+action-report-bug = Fehlerbericht schreiben
+action-show-config = Konfiguration dieses Hooks anzeigen
+action-palette = Befehlspalette
+
+## Berechtigungen
+
+permission-prompt = { $who } erlauben, { $what }?
+permission-allow = Erlauben
+permission-deny = Verweigern
+permission-denied = { $who } hat keine Erlaubnis, { $what }. Das lässt sich in permissions.toml ändern.
+permission-execute = Befehle auszuführen
+permission-scrollback = die Ausgabe der Shell zu sehen
+permission-network = Daten von diesem Rechner zu senden
+permission-clipboard = die Zwischenablage zu nutzen
+principal-plugin = Plugin { $name }
+principal-companion = Begleitprozess { $name }
+principal-pack = Hook-Paket { $name }
+principal-project = Projekt-Hooks in { $name }
+
+## Fehlerberichte
+
+report-bug-title = Fehlerbericht
+report-bug-without-screen = Bericht ohne den Bildschirm schreiben
+report-bug-with-screen = Bericht mit dem aktuellen Bildschirm schreiben
+report-bug-written = { $path } geschrieben
+    Geheimnisse in der Konfiguration wurden entfernt; vor dem Teilen bitte prüfen.
+
+## Rückblick
+
+wayback-title = Rückblick
+wayback-empty = Noch keine Bildschirme aufgezeichnet.
+wayback-position = Rückblick { $position }/{ $count } · { $time }
+wayback-full-screen = Vollbild
+wayback-hint = ESC schließen · ←→ älter/neuer · Pos1/Ende ältester/neuester
+
+## Ausgabe durchsuchen
+
+scrollback-title = Ausgabe durchsuchen
+scrollback-prompt = Text oder /Regex/:
+scrollback-none = Nichts in der Ausgabe der Shell passt zu { $query }.
+scrollback-position = { $query } · { $position }/{ $count }
+scrollback-copied = Zeile kopiert
+scrollback-hint = ESC schließen · ↑↓ auswählen · Enter/c Zeile kopieren · / neu suchen
+
+## Kopiermodus
+
+copy-mode-title = Kopiermodus
+copy-mode-empty = Die Shell hat noch nichts ausgegeben.
+copy-mode-position = Kopiermodus · Zeile { $line }/{ $count }
+copy-mode-copied = { $count ->
+    [one] Eine Zeile kopiert
+   *[other] { $count } Zeilen kopiert
+}
+copy-mode-comment-title = Kommentar
+copy-mode-comment-prompt = Zu { $count ->
+    [one] dieser Zeile
+   *[other] diesen { $count } Zeilen
+}:
+copy-mode-commented = Kommentar gespeichert
+copy-mode-hint = ESC schließen · ↑↓ bewegen · v auswählen · y kopieren · c kommentieren
+
+## Geschlossene Fenster
+
+reopen-title = Geschlossene Fenster
+reopen-empty = Noch keine Fenster geschlossen.
+reopen-position = { $title } · { $position }/{ $count } · geschlossen { $time }
+reopen-hint = ←→ älter/neuer
+
+## Prozesse
+
+processes-title = Prozesse
+processes-shell = { $count } von der Shell gestartet
+processes-mine = { $count } eigene
+processes-command = BEFEHL
+processes-none = Keine passenden Prozesse.
+processes-sent = { $signal } an { $pid } gesendet
+processes-hint = ESC schließen · Tab Shell/alle · Strg+T/K/Z TERM/KILL/STOP
+
+## Ports
+
+ports-title = Ports
+ports-count = { $listening } lauschend · { $connected } verbunden
+ports-local = LOKAL
+ports-remote = GEGENSTELLE
+ports-listening = (lauscht)
+ports-none = Keine Sockets der Prozesse dieser Sitzung.
+ports-copied = { $address } kopiert
+ports-hint = ESC schließen · ↑↓ wählen · c Adresse kopieren · k/K TERM/KILL
+
+## Umgebungsvergleich
+
+env-diff-title = Umgebung
+env-diff-live = Die Shell jetzt: + nur in der Shell, - nur in chatshell
+env-diff-started = Die Shell beim Start: + nur in der Shell, - nur in chatshell
+env-diff-reordered = (gleiche Einträge, andere Reihenfolge)
+env-diff-same = Die Umgebung der Shell stimmt mit der von chatshell überein.
+env-diff-unavailable = Die Umgebung dieser Sitzung kann nicht gelesen werden.
+env-reloaded = Umgebung neu geladen: { $count } Variablen exportiert
+
+## Große Einfügungen
+
+paste-large = { $kb } KB einfügen
+paste-type = In die Shell tippen
+paste-path = In einer Datei speichern und deren Pfad tippen
+paste-editor = In einer Datei speichern und diese in $EDITOR öffnen
@@ -0,0 +1,305 @@
+# Built-in text of ChatShell. To translate it, copy this file to
+# ~/.config/chatshell/locales/<locale>.ftl and translate the values.
+
+## Session
+
+session-started = ChatShell started. Press Ctrl+; for help.
+session-shell-ended = Shell process ended.
+session-device-gone = Serial device { $device } is gone.
+session-summarizing = Summarizing session to { $path }...
+automation-timeout = Startup automation stopped: { $pattern } didn't appear in time.
+
+## Popups
+
+popup-close = Press ESC to close
+popup-close-pin = ESC closes · p pins
+toast-title = ChatShell
+popup-error = Error
+popup-more-lines = ... { $count } more lines
+popup-more-rows = ... { $count } more rows
+draft-offer = Unsaved draft from { $time }: ctrl+r restores it
+input-search = (search) { $query }: { $match }
+editor-hint = ESC close · changes are kept
+live-popup-hint = running…
+notes-title = Notes: { $session }
+full-screen-title = Full-screen program
+full-screen-held-back = { $hook } types text, which the full-screen program would take as keys, so it didn't run. Set when = { "{" } alt_screen = true { "}" } to run it here.
+full-screen-input-held-back = { $hook } typed text, which the full-screen program would take as keys, so it was left out. Set when = { "{" } alt_screen = true { "}" } to type it here.
+menu-hint = ESC close · ↑↓ select · Enter pick
+confirm-yes = Yes
+confirm-no = No
+confirm-hint = y yes · n no · ESC no
+
+## Errors
+
+error-title-config = Config Error
+error-title-pty = Shell Error
+error-title-llm = Assistant Error
+error-title-ui = Display Error
+error-title-plugin = Hook Error
+error-reference = Reference { $reference } in { $log }
+error-reference-unlogged = Reference { $reference }
+error-copy = Press c to copy the details
+unknown-keys-title = Unknown config keys
+unknown-keys-intro = These keys of the config aren't known and were left out:
+unknown-key-suggestion = { $key } (did you mean { $suggestion }?)
+error-unknown-function = Unknown function: { $name }
+error-unknown-builtin = Unknown builtin: { $name }
+error-needs-database = This needs a psql, mysql or sqlite3 session.
+error-needs-llm = This needs an [llm] command in the config.
+error-capture-timeout = Timed out waiting for the shell to run the command.
+
+## Hook functions
+
+help-title = Help
+help-text =
+    === ChatShell Help ===
+
+    This is a transparent shell wrapper.
+    All keystrokes are passed through to the underlying shell.
+
+    Special key combinations can trigger hooks:
+help-close = Press ESC to close this window.
+cheatsheet-title = ChatShell keybindings
+cheatsheet-keys = Keys
+cheatsheet-action = Action
+time-title = Current Time
+convert-time-title = Convert Time
+convert-time-prompt = Epoch or time, empty for now:
+config-title = Configuration
+config-text =
+    === Current Hook Configuration ===
+
+    Name: { $name }
+    Key: { $key }
+    Action: { $action }
+    Enabled: { $enabled }
+config-description = Description: { $description }
+command-title = Command: { $command }
+command-failed =
+    Command failed:
+    { $stderr }
+command-no-output = Command executed successfully (no output)
+command-finished = { $command } finished
+command-timed-out = The command took longer than { $seconds }s and was stopped.
+switch-shell-title = Switch Shell
+tail-title = Tail
+tail-following = following
+tail-paused = paused
+tail-hint = ESC close · space pause · ↑↓ scroll · / highlight
+highlight-title = Highlight
+highlight-prompt = Regex to highlight (empty to clear):
+
+## Assistant
+
+ask-title = Ask
+ask-prompt = What do you need?
+assistant-title = Assistant
+chat-empty = Questions to the assistant and its answers show up here.
+suggestion-title = Use Suggestion
+suggestion-careful = Careful: this { $reason }
+suggestion-run = Run it
+suggestion-type = Type it without running
+suggestion-confirm = Run it anyway?
+
+## Why a suggestion needs confirmation
+
+risk-deletes-recursively = deletes files recursively
+risk-formats-filesystem = formats a filesystem
+risk-writes-raw-device = writes to a raw device
+risk-fork-bomb = fork bomb
+risk-opens-permissions = opens up permissions recursively
+risk-forces = forces a destructive operation
+risk-shuts-down = shuts the machine down
+risk-reboots = reboots the machine
+risk-deletes-tree = deletes a directory tree
+risk-deletes-files = deletes files
+risk-runs-shell = runs a shell command
+risk-exits-repl = exits the REPL
+risk-drops-object = drops a database object
+risk-truncates = deletes every row of a table
+risk-alters-table = changes a table definition
+risk-changes-every-row = changes every row of a table
+risk-erases-flash = erases flash memory
+risk-saves-environment = overwrites the saved environment of the device
+
+## Database sessions
+
+explain-title = Explain Query
+last-result-title = Last Result
+last-result-rows = Last Result ({ $count } rows)
+no-query = No query found in the recent output.
+
+## Re-running and watching commands
+
+rerun-title = Rerun
+rerun-command-title = Rerun: { $command }
+rerun-no-command = No previous command found at this prompt.
+rerun-unchanged = Output unchanged.
+rerun-skipped = @@ { $count } unchanged lines @@
+watch-title = Watch
+watch-command-prompt = Command to run in the background:
+watch-interval-prompt = Interval (default 2s):
+
+## Bookmarks
+
+bookmark-title = Bookmark
+bookmark-prompt = Name (empty to number it):
+
+## Temporary bindings
+
+bind-temp-title = Bind a Key
+bind-temp-key-prompt = Key, e.g. f9 or ctrl+alt+d:
+bind-temp-action-prompt = Command or action to run:
+bind-temp-lifetime-prompt = For how long, e.g. 1h (empty for this session):
+bind-temp-bad-key = Not a key combination: { $key }
+
+## Hook manager
+
+hook-manager-title = Hooks
+hook-manager-count = { $enabled } of { $count } on
+hook-manager-trigger = KEY/EVENT
+hook-manager-name = NAME
+hook-manager-action = ACTION
+hook-manager-hint = ESC close · ↑↓ select · space on/off
+
+## Command palette
+
+palette-title = Actions
+palette-hint = ESC close · ↑↓ select · Enter run · type to filter
+palette-none = Nothing matches.
+action-show-help = Show help
+action-show-time = Show the time
+action-convert-time = Convert a timestamp
+action-ask = Ask the assistant
+action-toggle-chat = Show or hide the chat with the assistant
+action-explain-query = Explain the last query
+action-show-last-result = Show the last query result
+action-rerun-and-diff = Re-run the last command and diff its output
+action-watch-command = Watch a command
+action-split-pane = Open the side pane
+action-focus-pane = Switch focus between shell and pane
+action-close-pane = Close the side pane
+action-undo-input = Undo typed input
+action-paste-clipboard = Paste the clipboard
+action-bookmark = Bookmark this point
+action-wayback = Step back through recent screens
+action-search-scrollback = Search the shell's output
+action-copy-mode = Copy or comment on the shell's output
+action-reopen-last-popup = Reopen a closed popup
+action-move-note = Move the pinned note to the next corner
+action-close-note = Close the pinned note
+action-process-picker = Processes
+action-ports = Ports and connections
+action-env-diff = Compare the shell's environment
+action-reload-env = Reload the environment
+action-switch-shell = Switch shell
+action-clear-screen = Clear the screen
+action-hook-manager = Turn hooks on and off
+action-bind-temp = Bind a key for this session
+action-notes = Notes for this session
+action-report-bug = Write a bug report
+action-show-config = Show this hook's configuration
+action-palette = Command palette
+
+## Permissions
+
+permission-prompt = Allow { $who } to { $what }?
+permission-allow = Allow
+permission-deny = Deny
+permission-denied = { $who } may not { $what }. Change it in permissions.toml.
+permission-execute = run commands
+permission-scrollback = see what the shell prints
+permission-network = send data off this machine
+permission-clipboard = use the clipboard
+principal-plugin = plugin { $name }
+principal-companion = companion { $name }
+principal-pack = hook pack { $name }
+principal-project = the project hooks in { $name }
+
+## Bug reports
+
+report-bug-title = Bug Report
+report-bug-without-screen = Write the report without the screen
+report-bug-with-screen = Write the report with the current screen
+report-bug-written = Wrote { $path }
+    Secrets in the config were redacted; look it over before sharing.
+
+## Wayback
+
+wayback-title = Wayback
+wayback-empty = No screens recorded yet.
+wayback-position = Wayback { $position }/{ $count } · { $time }
+wayback-full-screen = full-screen
+wayback-hint = ESC close · ←→ older/newer · Home/End oldest/newest
+
+## Scrollback search
+
+scrollback-title = Search Output
+scrollback-prompt = Text, or /regex/:
+scrollback-none = Nothing in the shell's output matches { $query }.
+scrollback-position = { $query } · { $position }/{ $count }
+scrollback-copied = Copied the line
+scrollback-hint = ESC close · ↑↓ select · Enter/c copy line · / search again
+
+## Copy mode
+
+copy-mode-title = Copy Mode
+copy-mode-empty = The shell hasn't printed anything yet.
+copy-mode-position = Copy mode · line { $line }/{ $count }
+copy-mode-copied = Copied { $count ->
+    [one] a line
+   *[other] { $count } lines
+}
+copy-mode-comment-title = Comment
+copy-mode-comment-prompt = On { $count ->
+    [one] this line
+   *[other] these { $count } lines
+}:
+copy-mode-commented = Comment saved
+copy-mode-hint = ESC close · ↑↓ move · v select · y copy · c comment
+
+## Closed popups
+
+reopen-title = Closed popups
+reopen-empty = No popups closed yet.
+reopen-position = { $title } · { $position }/{ $count } · closed { $time }
+reopen-hint = ←→ older/newer
+
+## Processes
+
+processes-title = Processes
+processes-shell = { $count } started by the shell
+processes-mine = { $count } of yours
+processes-command = COMMAND
+processes-none = No matching processes.
+processes-sent = Sent { $signal } to { $pid }
+processes-hint = ESC close · tab shell/all · ctrl+t/k/z TERM/KILL/STOP
+
+## Ports
+
+ports-title = Ports
+ports-count = { $listening } listening · { $connected } connected
+ports-local = LOCAL
+ports-remote = REMOTE
+ports-listening = (listening)
+ports-none = No sockets held by the session's processes.
+ports-copied = Copied { $address }
+ports-hint = ESC close · ↑↓ select · c copy address · k/K TERM/KILL
+
+## Environment diff
+
+env-diff-title = Environment
+env-diff-live = The shell now: + only in the shell, - only in chatshell
+env-diff-started = The shell as started: + only in the shell, - only in chatshell
+env-diff-reordered = (same entries, reordered)
+env-diff-same = The shell's environment matches chatshell's.
+env-diff-unavailable = The environment of this session can't be read.
+env-reloaded = Environment reloaded: { $count } variables exported
+
+## Large pastes
+
+paste-large = Pasting { $kb } KB
+paste-type = Type it into the shell
+paste-path = Save it to a file and type the file's path
+paste-editor = Save it to a file and open that in $EDITOR
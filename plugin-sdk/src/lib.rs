@@ -0,0 +1,174 @@
+//! Write chatshell plugins in Rust. Build a `cdylib` for
+//! `wasm32-unknown-unknown` and put the `.wasm` in the `plugins` directory
+//! next to chatshell's config:
+//!
+//! ```ignore
+//! use chatshell_plugin::{action, export_plugin, Plugin};
+//!
+//! #[derive(Default)]
+//! struct Deploy;
+//!
+//! impl Plugin for Deploy {
+//!     fn keys() -> Vec<String> {
+//!         vec!["f9".to_string()]
+//!     }
+//!
+//!     fn on_key(&mut self, _key: &str) -> Vec<String> {
+//!         vec![action::inject("make deploy\n")]
+//!     }
+//! }
+//!
+//! export_plugin!(Deploy);
+//! ```
+//!
+//! Plugins get no imports, so they can't reach files, the network or the
+//! clock; all they can do is reply to chatshell with actions for it to run.
+
+use std::cell::RefCell;
+
+/// A plugin. One value of it lives as long as the session.
+pub trait Plugin: Default {
+    /// The keys to bind to the plugin, written as in a hook's
+    /// `key_combination`.
+    fn keys() -> Vec<String>;
+
+    /// One of `keys`, as written there, was pressed. Returns the actions
+    /// to run, each written as a hook's `action`, or `print` lines; none
+    /// leaves the key to the shell.
+    fn on_key(&mut self, key: &str) -> Vec<String>;
+
+    /// The shell printed `text`, escape sequences and all.
+    fn on_output(&mut self, _text: &str) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// Actions for replies, written as a hook's `action`.
+pub mod action {
+    /// Types `text` into the shell.
+    pub fn inject(text: &str) -> String {
+        let mut action = String::from("inject:");
+        for c in text.chars() {
+            match c {
+                '\n' => action.push_str("\\n"),
+                '\r' => action.push_str("\\r"),
+                '\t' => action.push_str("\\t"),
+                '\x1b' => action.push_str("\\e"),
+                '\\' => action.push_str("\\\\"),
+                c => action.push(c),
+            }
+        }
+        action
+    }
+
+    /// Runs `command` with `/bin/sh` outside the shell and shows its output.
+    pub fn command(command: &str) -> String {
+        format!("cmd:{}", command.replace('\n', " "))
+    }
+
+    /// Runs one of chatshell's builtins, like `ask` or `watch_command`.
+    pub fn builtin(name: &str) -> String {
+        format!("builtin:{}", name)
+    }
+
+    /// Shows `text`, one reply line per line of it, where the hook's
+    /// `output` says.
+    pub fn print(text: &str) -> String {
+        text.lines().map(|line| format!("print:{}", line)).collect::<Vec<_>>().join("\n")
+    }
+}
+
+thread_local! {
+    // The last reply, kept until the next call so chatshell can read it
+    static REPLY: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Room for `len` bytes of input. Used by `export_plugin!`.
+#[doc(hidden)]
+pub fn alloc(len: i32) -> i32 {
+    let mut buffer = Vec::<u8>::with_capacity(len.max(0) as usize);
+    let ptr = buffer.as_mut_ptr();
+    std::mem::forget(buffer);
+    ptr as usize as i32
+}
+
+/// Takes back the input chatshell wrote at `ptr`. Used by `export_plugin!`.
+///
+/// # Safety
+///
+/// `ptr` and `len` must be those of a call to `alloc` that chatshell then
+/// wrote `len` bytes to.
+#[doc(hidden)]
+pub unsafe fn input(ptr: i32, len: i32) -> String {
+    let len = len.max(0) as usize;
+    let bytes = Vec::from_raw_parts(ptr as usize as *mut u8, len, len);
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+/// Keeps `lines` as the reply and returns where it is. Used by
+/// `export_plugin!`.
+#[doc(hidden)]
+pub fn reply(lines: Vec<String>) -> i64 {
+    let text = lines.join("\n");
+    if text.is_empty() {
+        return 0;
+    }
+    REPLY.with(|reply| {
+        let mut reply = reply.borrow_mut();
+        *reply = text.into_bytes();
+        pack(reply.as_ptr() as usize as u32, reply.len() as u32)
+    })
+}
+
+/// The pointer in the high 32 bits and the length in the low ones.
+fn pack(ptr: u32, len: u32) -> i64 {
+    ((ptr as u64) << 32 | len as u64) as i64
+}
+
+/// Exports the functions chatshell calls, for a type implementing
+/// `Plugin`.
+#[macro_export]
+macro_rules! export_plugin {
+    ($plugin:ty) => {
+        thread_local! {
+            static PLUGIN: ::std::cell::RefCell<$plugin> = ::std::cell::RefCell::new(<$plugin>::default());
+        }
+
+        #[no_mangle]
+        pub extern "C" fn chatshell_alloc(len: i32) -> i32 {
+            $crate::alloc(len)
+        }
+
+        #[no_mangle]
+        pub extern "C" fn chatshell_keys() -> i64 {
+            $crate::reply(<$plugin as $crate::Plugin>::keys())
+        }
+
+        #[no_mangle]
+        pub extern "C" fn chatshell_on_key(ptr: i32, len: i32) -> i64 {
+            let key = unsafe { $crate::input(ptr, len) };
+            $crate::reply(PLUGIN.with(|plugin| $crate::Plugin::on_key(&mut *plugin.borrow_mut(), &key)))
+        }
+
+        #[no_mangle]
+        pub extern "C" fn chatshell_on_output(ptr: i32, len: i32) -> i64 {
+            let text = unsafe { $crate::input(ptr, len) };
+            $crate::reply(PLUGIN.with(|plugin| $crate::Plugin::on_output(&mut *plugin.borrow_mut(), &text)))
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_actions() {
+        assert_eq!(action::inject("echo \"a\\b\"\n"), "inject:echo \"a\\\\b\"\\n");
+        assert_eq!(action::command("make\ntest"), "cmd:make test");
+        assert_eq!(action::builtin("ask"), "builtin:ask");
+        assert_eq!(action::print("one\ntwo"), "print:one\nprint:two");
+        assert_eq!(pack(0x10, 3), 0x10_0000_0003);
+        assert_eq!(reply(Vec::new()), 0);
+    }
+}
@@ -0,0 +1,102 @@
+use anyhow::Result;
+
+use crate::config::HookConfig;
+use crate::hooks::HookContext;
+use crate::permissions::Permission;
+
+/// What an action needs of the session to do anything.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    /// An `[llm] command` to ask.
+    Llm,
+    /// A psql, mysql or sqlite3 session.
+    Database,
+}
+
+impl Capability {
+    pub fn present(self, ctx: &HookContext) -> bool {
+        match self {
+            Capability::Llm => ctx.load_config().is_ok_and(|config| config.llm.command.is_some()),
+            Capability::Database => ctx.session_mode.database().is_some(),
+        }
+    }
+
+    /// The message saying the session lacks it.
+    pub fn missing_message(self) -> &'static str {
+        match self {
+            Capability::Llm => "error-needs-llm",
+            Capability::Database => "error-needs-database",
+        }
+    }
+}
+
+/// Runs an action for the hook bound to it.
+pub type Handler = fn(&HookConfig, &mut HookContext) -> Result<()>;
+
+/// Something chatshell can do, which hooks bind keys to by name.
+#[derive(Debug)]
+pub struct Action {
+    /// The name hooks give as their `action`, like `builtin:ask`.
+    pub name: &'static str,
+    /// The message describing it.
+    pub description: &'static str,
+    pub handler: Handler,
+    pub needs: &'static [Capability],
+    /// What plugins and hook packs have to be allowed to run it.
+    pub permissions: &'static [Permission],
+    /// False for actions that only make sense run by a hook of their own,
+    /// which the palette leaves out.
+    pub listed: bool,
+}
+
+impl Action {
+    pub fn new(name: &'static str, description: &'static str, handler: Handler) -> Self {
+        Action { name, description, handler, needs: &[], permissions: &[], listed: true }
+    }
+
+    pub fn needs(self, needs: &'static [Capability]) -> Self {
+        Action { needs, ..self }
+    }
+
+    pub fn permissions(self, permissions: &'static [Permission]) -> Self {
+        Action { permissions, ..self }
+    }
+
+    pub fn unlisted(self) -> Self {
+        Action { listed: false, ..self }
+    }
+
+    /// The first of its capabilities the session lacks.
+    pub fn missing(&self, ctx: &HookContext) -> Option<Capability> {
+        self.needs.iter().copied().find(|capability| !capability.present(ctx))
+    }
+}
+
+/// The named actions, in the order the palette lists them.
+#[derive(Debug, Default)]
+pub struct ActionRegistry {
+    actions: Vec<Action>,
+}
+
+impl ActionRegistry {
+    /// Adds `action`, replacing one of the same name.
+    pub fn register(&mut self, action: Action) {
+        match self.actions.iter_mut().find(|existing| existing.name == action.name) {
+            Some(existing) => *existing = action,
+            None => self.actions.push(action),
+        }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Action> {
+        self.actions.iter().find(|action| action.name == name)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Action> {
+        self.actions.iter()
+    }
+
+    /// The actions to offer in the palette.
+    pub fn listed(&self) -> impl Iterator<Item = &Action> {
+        self.actions.iter().filter(|action| action.listed)
+    }
+}
@@ -0,0 +1,118 @@
+use crate::config::AutomationStep;
+use crate::hooks::expand_escapes;
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+struct Step {
+    wait_for: Option<Regex>,
+    send: String,
+    timeout: Duration,
+}
+
+/// The `[[automation.startup]]` steps of a shell just started: each waits
+/// for its pattern in the shell's output, then types its keys, so that a
+/// login through a jump host can be answered as it goes.
+#[derive(Debug, Default)]
+pub struct StartupScript {
+    steps: VecDeque<Step>,
+    // When the step at the front started waiting
+    since: Option<Instant>,
+}
+
+impl StartupScript {
+    pub fn new(steps: &[AutomationStep]) -> Result<Self> {
+        let steps = steps
+            .iter()
+            .map(|step| {
+                let wait_for = step.wait_for.as_deref()
+                    .map(|pattern| Regex::new(pattern).with_context(|| format!("Invalid wait_for pattern: {}", pattern)))
+                    .transpose()?;
+                Ok(Step { wait_for, send: expand_escapes(&step.send), timeout: Duration::from_secs(step.timeout_secs) })
+            })
+            .collect::<Result<_>>()?;
+        Ok(StartupScript { steps, since: None })
+    }
+
+    /// True once every step has run, or the script gave up.
+    pub fn is_done(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Looks for the patterns in `lines` the shell finished and the line
+    /// it is on, and returns the keys to send for the steps that matched,
+    /// along with those of the steps after them that don't wait.
+    pub fn observe(&mut self, lines: &[String], current_line: &str, now: Instant) -> Vec<String> {
+        let output: Vec<&str> = lines.iter().map(String::as_str).chain([current_line]).collect();
+        let mut sent = Vec::new();
+        // Each step only looks at the output after the one before matched
+        let mut from = 0;
+        while let Some(step) = self.steps.front() {
+            if let Some(pattern) = &step.wait_for {
+                match output[from..].iter().position(|line| pattern.is_match(line)) {
+                    Some(found) => from += found + 1,
+                    None => break,
+                }
+            }
+            sent.push(self.steps.pop_front().expect("just looked").send);
+            self.since = Some(now);
+        }
+        self.since.get_or_insert(now);
+        sent
+    }
+
+    /// Gives up on the steps left once the one waiting has waited longer
+    /// than its timeout, and says which pattern it waited for.
+    pub fn expire(&mut self, now: Instant) -> Option<String> {
+        let step = self.steps.front()?;
+        let since = *self.since.get_or_insert(now);
+        if now.duration_since(since) < step.timeout {
+            return None;
+        }
+        let pattern = step.wait_for.as_ref().map_or_else(String::new, |pattern| pattern.to_string());
+        self.steps.clear();
+        Some(pattern)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(wait_for: Option<&str>, send: &str) -> AutomationStep {
+        AutomationStep { wait_for: wait_for.map(str::to_string), send: send.to_string(), timeout_secs: 10 }
+    }
+
+    #[test]
+    fn test_startup_script() {
+        let steps = [
+            step(Some("^Welcome to bastion"), r"2fa-helper\r"),
+            step(Some("Verification code:"), r"\r"),
+            step(None, r"ssh db-1\r"),
+            step(Some(r"db-1 \$ $"), r"cd /srv\r"),
+        ];
+        let start = Instant::now();
+        let mut script = StartupScript::new(&steps).unwrap();
+        assert!(script.observe(&["Last login: today".to_string()], "", start).is_empty());
+        assert_eq!(script.observe(&["Welcome to bastion-2".to_string()], "", start), vec!["2fa-helper\r"]);
+        assert_eq!(script.observe(&[], "Verification code: ", start), vec!["\r", "ssh db-1\r"]);
+        assert!(!script.is_done());
+
+        // Steps can match one after the other in the same output, but not
+        // on the same line
+        let mut script = StartupScript::new(&steps).unwrap();
+        let banner = ["Welcome to bastion-2".to_string()];
+        assert_eq!(script.observe(&banner, "Verification code: ", start), vec!["2fa-helper\r", "\r", "ssh db-1\r"]);
+        assert_eq!(script.observe(&[], "db-1 $ ", start), vec!["cd /srv\r"]);
+
+        let mut script = StartupScript::new(&steps).unwrap();
+        script.observe(&banner, "", start);
+        assert_eq!(script.expire(start + Duration::from_secs(9)), None);
+        assert_eq!(script.expire(start + Duration::from_secs(10)), Some("Verification code:".to_string()));
+        assert!(script.is_done());
+
+        assert!(StartupScript::new(&[step(Some("("), "")]).is_err());
+    }
+}
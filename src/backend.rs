@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use tokio::io::unix::AsyncFd;
+
+/// What the session's keystrokes go to and its output comes from: a shell
+/// in a pseudo-terminal, or a device on a serial line.
+pub trait Backend: std::fmt::Debug + Send {
+    /// An independently owned handle to read output from and write input to.
+    fn try_clone_io(&self) -> Result<File>;
+
+    /// Like `try_clone_io`, for async tasks. Once it exists, all I/O on the
+    /// backend should go through it; see `AsyncIo::new`.
+    fn async_io(&self) -> Result<AsyncIo> {
+        AsyncIo::new(self.try_clone_io()?)
+    }
+
+    /// Tells the other side the size of the terminal, where it can be told.
+    fn resize(&self, rows: u16, cols: u16) -> Result<()>;
+
+    /// False once the shell has exited or the device is gone.
+    fn is_alive(&self) -> bool;
+
+    /// True unless a program the shell started has the terminal.
+    fn in_foreground(&self) -> bool;
+
+    /// The working directory of the shell, if there is one to ask.
+    fn cwd(&self) -> Option<PathBuf>;
+
+    /// The process id of the shell, if there is one.
+    fn pid(&self) -> Option<i32> {
+        None
+    }
+
+    /// The process group that has the terminal: the shell's, or that of
+    /// the program it runs.
+    fn foreground_pid(&self) -> Option<i32> {
+        None
+    }
+
+    /// Ends the shell, if it is still running.
+    fn terminate(&mut self);
+}
+
+/// A backend's descriptor watched by the tokio reactor, so reads and writes
+/// wait until it is ready instead of blocking a worker thread. Clones share
+/// the descriptor.
+#[derive(Debug, Clone)]
+pub struct AsyncIo {
+    fd: Arc<AsyncFd<File>>,
+    hung_up: Arc<AtomicBool>,
+}
+
+impl AsyncIo {
+    /// Takes over `file` and makes it non-blocking. The flag belongs to the
+    /// open file, so duplicates of the descriptor made before or after
+    /// become non-blocking as well. Must be called within a tokio runtime.
+    pub fn new(file: File) -> Result<Self> {
+        Self::watching(file, Arc::default())
+    }
+
+    /// Like `new`, setting `hung_up` once a read reaches the end of file or
+    /// fails, for backends that can't tell otherwise that the other side
+    /// has gone.
+    pub fn watching(file: File, hung_up: Arc<AtomicBool>) -> Result<Self> {
+        let fd = file.as_raw_fd();
+        let flags = unsafe { nix::libc::fcntl(fd, nix::libc::F_GETFL) };
+        if flags == -1 || unsafe { nix::libc::fcntl(fd, nix::libc::F_SETFL, flags | nix::libc::O_NONBLOCK) } == -1 {
+            return Err(io::Error::last_os_error()).with_context(|| "Failed to make I/O non-blocking");
+        }
+        let fd = AsyncFd::new(file).with_context(|| "Failed to watch I/O for readiness")?;
+        Ok(AsyncIo { fd: Arc::new(fd), hung_up })
+    }
+
+    /// Waits for output and reads what there is of it. Returns 0 at end of
+    /// file; a PTY whose shell has exited fails with EIO instead.
+    pub async fn read(&self, buffer: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let mut guard = self.fd.readable().await?;
+            match guard.try_io(|fd| fd.get_ref().read(buffer)) {
+                Ok(result) => {
+                    if !matches!(result, Ok(n) if n > 0) {
+                        self.hung_up.store(true, Ordering::Relaxed);
+                    }
+                    return result;
+                }
+                // Someone else read it first
+                Err(_would_block) => continue,
+            }
+        }
+    }
+
+    /// Writes all of `data`, waiting whenever the other side is behind.
+    pub async fn write_all(&self, mut data: &[u8]) -> io::Result<()> {
+        while !data.is_empty() {
+            let mut guard = self.fd.writable().await?;
+            match guard.try_io(|fd| fd.get_ref().write(data)) {
+                Ok(Ok(0)) => return Err(io::ErrorKind::WriteZero.into()),
+                Ok(Ok(n)) => data = &data[n..],
+                Ok(Err(e)) => return Err(e),
+                Err(_would_block) => continue,
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_async_io_over_pty() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let io = AsyncIo::new(File::from(pty.master)).unwrap();
+        let mut shell = File::from(pty.slave);
+
+        // Without output, a read waits rather than blocking or failing
+        let mut buffer = [0u8; 16];
+        let waiting = tokio::time::timeout(Duration::from_millis(50), io.read(&mut buffer)).await;
+        assert!(waiting.is_err());
+
+        shell.write_all(b"$ ").unwrap();
+        let n = io.read(&mut buffer).await.unwrap();
+        assert_eq!(&buffer[..n], b"$ ");
+
+        io.clone().write_all(b"ls\n").await.unwrap();
+        let n = shell.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"ls\n");
+    }
+}
@@ -0,0 +1,198 @@
+use anyhow::Result;
+
+use crate::i18n::tr;
+use crate::transcript::escape_html;
+
+/// The formats `chatshell cheatsheet` writes.
+pub const FORMATS: [&str; 3] = ["md", "svg", "png"];
+
+/// Margin around the sheet, in pixels.
+const MARGIN: usize = 16;
+/// Blank columns between the keys and what they do.
+const GAP: usize = 3;
+/// Width and height of a character in the SVG, for its monospace font.
+const SVG_CELL: (f32, f32) = (8.4, 20.0);
+
+const BACKGROUND: [u8; 3] = [0xff, 0xff, 0xff];
+const TEXT: [u8; 3] = [0x22, 0x22, 0x22];
+const KEYS: [u8; 3] = [0x1f, 0x5f, 0xbf];
+const RULE: [u8; 3] = [0xbb, 0xbb, 0xbb];
+
+/// The keys bound and what each does, as the help lists them.
+pub struct Cheatsheet {
+    title: String,
+    headings: (String, String),
+    bindings: Vec<(String, String)>,
+}
+
+impl Cheatsheet {
+    /// A sheet of `bindings`, keys first, with headings in the user's language.
+    pub fn new(bindings: Vec<(String, String)>) -> Self {
+        Self {
+            title: tr("cheatsheet-title"),
+            headings: (tr("cheatsheet-keys"), tr("cheatsheet-action")),
+            bindings,
+        }
+    }
+
+    /// The sheet in `format`, one of `FORMATS`.
+    pub fn render(&self, format: &str) -> Result<Vec<u8>> {
+        match format {
+            "md" => Ok(self.markdown().into_bytes()),
+            "svg" => Ok(self.svg().into_bytes()),
+            "png" => self.png(),
+            _ => anyhow::bail!("Unknown format: {}", format),
+        }
+    }
+
+    pub fn markdown(&self) -> String {
+        let cell = |text: &str| text.replace('|', "\\|");
+        let mut markdown = format!("# {}\n\n", self.title);
+        markdown.push_str(&format!("| {} | {} |\n| --- | --- |\n", cell(&self.headings.0), cell(&self.headings.1)));
+        for (keys, description) in &self.bindings {
+            markdown.push_str(&format!("| {} | {} |\n", cell(&code_span(keys)), cell(description)));
+        }
+        markdown
+    }
+
+    pub fn svg(&self) -> String {
+        let (columns, rows) = self.size();
+        let (cell_width, cell_height) = SVG_CELL;
+        let width = MARGIN as f32 * 2.0 + columns as f32 * cell_width;
+        let height = MARGIN as f32 * 2.0 + rows as f32 * cell_height;
+        let action_x = MARGIN as f32 + (self.keys_width() + GAP) as f32 * cell_width;
+        let color = |[r, g, b]: [u8; 3]| format!("#{:02x}{:02x}{:02x}", r, g, b);
+        let text = |x: f32, row: usize, fill: [u8; 3], content: &str| {
+            let y = MARGIN as f32 + row as f32 * cell_height + cell_height * 0.75;
+            format!("<text x=\"{}\" y=\"{}\" fill=\"{}\">{}</text>\n", x, y, color(fill), escape_html(content))
+        };
+
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\" \
+             font-family=\"monospace\" font-size=\"14\" xml:space=\"preserve\">\n",
+            w = width,
+            h = height
+        );
+        svg.push_str(&format!("<rect width=\"100%\" height=\"100%\" fill=\"{}\"/>\n", color(BACKGROUND)));
+        svg.push_str(&text(MARGIN as f32, 0, TEXT, &self.title));
+        svg.push_str(&text(MARGIN as f32, 2, TEXT, &self.headings.0));
+        svg.push_str(&text(action_x, 2, TEXT, &self.headings.1));
+        let rule_y = MARGIN as f32 + 3.0 * cell_height;
+        svg.push_str(&format!(
+            "<line x1=\"{}\" y1=\"{y}\" x2=\"{}\" y2=\"{y}\" stroke=\"{}\"/>\n",
+            MARGIN,
+            width - MARGIN as f32,
+            color(RULE),
+            y = rule_y
+        ));
+        for (row, (keys, description)) in self.bindings.iter().enumerate() {
+            svg.push_str(&text(MARGIN as f32, row + 3, KEYS, keys));
+            svg.push_str(&text(action_x, row + 3, TEXT, description));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+
+    /// The SVG drawn with the system's monospace font.
+    #[cfg(feature = "png")]
+    pub fn png(&self) -> Result<Vec<u8>> {
+        use anyhow::Context;
+        use resvg::{tiny_skia, usvg};
+
+        let mut options = usvg::Options::default();
+        let fonts = options.fontdb_mut();
+        fonts.load_system_fonts();
+        // Generic families name fixed fonts, which may not be installed
+        let monospace = fonts.faces().find(|face| face.monospaced).and_then(|face| face.families.first()).map(|(family, _)| family.clone());
+        if let Some(family) = monospace {
+            fonts.set_monospace_family(family);
+        }
+        let tree = usvg::Tree::from_str(&self.svg(), &options).context("Failed to read the cheatsheet SVG")?;
+        let size = tree.size().to_int_size();
+        let mut pixmap = tiny_skia::Pixmap::new(size.width(), size.height()).context("Cheatsheet too large to draw")?;
+        resvg::render(&tree, tiny_skia::Transform::default(), &mut pixmap.as_mut());
+        pixmap.encode_png().context("Failed to encode the cheatsheet PNG")
+    }
+
+    #[cfg(not(feature = "png"))]
+    pub fn png(&self) -> Result<Vec<u8>> {
+        anyhow::bail!("Can't write a PNG: chatshell was built without the png feature")
+    }
+
+    fn keys_width(&self) -> usize {
+        self.bindings
+            .iter()
+            .map(|(keys, _)| keys.chars().count())
+            .chain([self.headings.0.chars().count()])
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Columns and rows of characters the sheet takes: the title, a blank
+    /// row, the headings and a row per binding.
+    fn size(&self) -> (usize, usize) {
+        let descriptions = self
+            .bindings
+            .iter()
+            .map(|(_, description)| description.chars().count())
+            .chain([self.headings.1.chars().count()])
+            .max()
+            .unwrap_or(0);
+        let columns = (self.keys_width() + GAP + descriptions).max(self.title.chars().count());
+        (columns, self.bindings.len() + 3)
+    }
+}
+
+/// `text` as inline code, with a fence longer than any backticks in it.
+fn code_span(text: &str) -> String {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest + 1);
+    if longest == 0 {
+        format!("{}{}{}", fence, text, fence)
+    } else {
+        format!("{} {} {}", fence, text, fence)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sheet() -> Cheatsheet {
+        Cheatsheet {
+            title: "Keys".into(),
+            headings: ("Keys".into(), "Action".into()),
+            bindings: vec![
+                ("ctrl+;".into(), "Show help".into()),
+                ("ctrl+|".into(), "a | b <c>".into()),
+                ("f`2".into(), "Tick".into()),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_formats() {
+        let sheet = sheet();
+        let markdown = sheet.markdown();
+        assert!(markdown.contains("| `ctrl+;` | Show help |\n"));
+        assert!(markdown.contains("| `ctrl+\\|` | a \\| b <c> |\n"));
+        assert!(markdown.contains("| `` f`2 `` | Tick |\n"));
+
+        let svg = sheet.svg();
+        assert!(svg.starts_with("<svg "));
+        assert!(svg.contains(">a | b &lt;c&gt;</text>"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert!(sheet.render("pdf").is_err());
+    }
+
+    #[cfg(feature = "png")]
+    #[test]
+    fn test_png() {
+        let png = sheet().render("png").unwrap();
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        // The IHDR chunk has the size of the SVG, 18 columns by 6 rows
+        let width = u32::from_be_bytes(png[16..20].try_into().unwrap());
+        let height = u32::from_be_bytes(png[20..24].try_into().unwrap());
+        assert_eq!((width, height), (183, 152));
+    }
+}
@@ -0,0 +1,291 @@
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::config::TimeConfig;
+
+/// Where the session gets the time from. Timers, idle hooks and command
+/// durations read it here rather than from the system, so tests can move
+/// time on without sleeping.
+pub trait Clock: fmt::Debug + Send + Sync {
+    /// The time to measure how long things take with.
+    fn now(&self) -> Instant;
+
+    /// The time of day, for conditions on it and for timestamps.
+    fn local(&self) -> DateTime<Local>;
+}
+
+/// The system's clock. It is read through tokio, so in tests with paused
+/// time it only moves with `tokio::time::advance`.
+#[derive(Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        tokio::time::Instant::now().into_std()
+    }
+
+    fn local(&self) -> DateTime<Local> {
+        Local::now()
+    }
+}
+
+/// A clock that stands still until it is advanced.
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    local: DateTime<Local>,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// A clock stopped at `local` o'clock.
+    pub fn at(local: DateTime<Local>) -> Self {
+        MockClock { start: Instant::now(), local, elapsed: Mutex::new(Duration::ZERO) }
+    }
+
+    /// Moves the clock on by `by`.
+    pub fn advance(&self, by: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += by;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::at(Local::now())
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn local(&self) -> DateTime<Local> {
+        self.local + self.elapsed()
+    }
+}
+
+/// A zone times are shown in: the machine's own, or one from the tz
+/// database like `Europe/Berlin`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Zone {
+    Local,
+    Named(Tz),
+}
+
+impl Zone {
+    /// The zone called `name`, with `local` for the machine's own.
+    pub fn parse(name: &str) -> Result<Zone> {
+        if name.eq_ignore_ascii_case("local") {
+            return Ok(Zone::Local);
+        }
+        name.parse::<Tz>()
+            .map(Zone::Named)
+            .map_err(|_| anyhow::anyhow!("Unknown time zone '{}'", name))
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Zone::Local => "local",
+            Zone::Named(tz) => tz.name(),
+        }
+    }
+
+    /// `time` in this zone, written with a chrono `format`.
+    pub fn format(&self, time: DateTime<Utc>, format: &str) -> String {
+        match self {
+            Zone::Local => time.with_timezone(&Local).format(format).to_string(),
+            Zone::Named(tz) => time.with_timezone(tz).format(format).to_string(),
+        }
+    }
+
+    /// The instant `time` stands for on a clock in this zone; None for times
+    /// a clock change skips.
+    fn resolve(&self, time: NaiveDateTime) -> Option<DateTime<Utc>> {
+        match self {
+            Zone::Local => Local.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc)),
+            Zone::Named(tz) => tz.from_local_datetime(&time).earliest().map(|t| t.with_timezone(&Utc)),
+        }
+    }
+}
+
+/// The zones of `[time]`, local time and UTC first.
+pub fn zones(config: &TimeConfig) -> Result<Vec<Zone>> {
+    let mut zones = vec![Zone::Local, Zone::Named(Tz::UTC)];
+    for name in &config.zones {
+        let zone = Zone::parse(name).context("in [time] zones")?;
+        if !zones.contains(&zone) {
+            zones.push(zone);
+        }
+    }
+    Ok(zones)
+}
+
+/// The instant `text` stands for: seconds or milliseconds since the epoch,
+/// or a date and time. Dates and times are local unless they end in an
+/// offset or a zone name; empty or `now` is `now`.
+pub fn parse_time(text: &str, now: DateTime<Utc>) -> Result<DateTime<Utc>> {
+    let text = text.trim();
+    if text.is_empty() || text.eq_ignore_ascii_case("now") {
+        return Ok(now);
+    }
+    if let Some(time) = parse_epoch(text) {
+        return time.with_context(|| format!("'{}' is out of range", text));
+    }
+    if let Ok(time) = DateTime::parse_from_rfc3339(text) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let (naive, zone) = match text.rsplit_once(' ').map(|(time, zone)| (time, Zone::parse(zone))) {
+        Some((time, Ok(zone))) => (time, zone),
+        _ => (text, Zone::Local),
+    };
+    let Some(naive) = parse_naive(naive.trim()) else {
+        bail!("'{}' isn't a time: try 1700000000, 2024-03-01 09:30 or 2024-03-01T09:30:00Z", text);
+    };
+    zone.resolve(naive)
+        .with_context(|| format!("{} doesn't happen in {}, the clocks skip it", naive, zone.name()))
+}
+
+// Seconds have ten digits until 2286 and milliseconds thirteen, so twelve
+// or more digits are milliseconds
+fn parse_epoch(text: &str) -> Option<Option<DateTime<Utc>>> {
+    let digits = text.strip_prefix('-').unwrap_or(text);
+    if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let value: i64 = text.parse().ok()?;
+    Some(if digits.len() >= 12 {
+        DateTime::from_timestamp_millis(value)
+    } else {
+        DateTime::from_timestamp(value, 0)
+    })
+}
+
+fn parse_naive(text: &str) -> Option<NaiveDateTime> {
+    const FORMATS: [&str; 4] = ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M"];
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| NaiveDate::parse_from_str(text, "%Y-%m-%d").ok()?.and_hms_opt(0, 0, 0))
+}
+
+/// `time` in each of the forms it can be inserted as, each with a label:
+/// epoch seconds and milliseconds, RFC 3339 and each of `zones`.
+pub fn conversions(time: DateTime<Utc>, zones: &[Zone], format: &str) -> Vec<(String, String)> {
+    let mut forms = vec![
+        ("epoch".to_string(), time.timestamp().to_string()),
+        ("epoch ms".to_string(), time.timestamp_millis().to_string()),
+        ("RFC 3339".to_string(), time.to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)),
+    ];
+    forms.extend(zones.iter().map(|zone| (zone.name().to_string(), zone.format(time, format))));
+    forms
+}
+
+/// Label and value pairs as aligned lines.
+pub fn format_table(forms: &[(String, String)]) -> Vec<String> {
+    let width = forms.iter().map(|(label, _)| label.chars().count()).max().unwrap_or(0);
+    forms.iter().map(|(label, value)| format!("{:<width$}  {}", label, value)).collect()
+}
+
+/// The last word of `line` that reads as a time, like an epoch just typed
+/// at the prompt or printed in a log line.
+pub fn find_timestamp(line: &str) -> Option<String> {
+    line.split_whitespace()
+        .rev()
+        .map(|word| word.trim_matches(|c: char| matches!(c, '"' | '\'' | '@' | ',' | ';' | '(' | ')' | '[' | ']' | '{' | '}')))
+        .find(|word| {
+            let epoch = (9..=13).contains(&word.len()) && word.chars().all(|c| c.is_ascii_digit());
+            epoch || DateTime::parse_from_rfc3339(word).is_ok()
+        })
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn utc(text: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(text).unwrap().with_timezone(&Utc)
+    }
+
+    #[test]
+    fn test_mock_clock() {
+        let noon = Local.with_ymd_and_hms(2024, 3, 1, 12, 0, 0).unwrap();
+        let clock = MockClock::at(noon);
+        let start = clock.now();
+        assert_eq!(clock.now(), start);
+        clock.advance(Duration::from_secs(90));
+        assert_eq!(clock.now() - start, Duration::from_secs(90));
+        assert_eq!(clock.local().format("%H:%M:%S").to_string(), "12:01:30");
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_system_clock_follows_paused_time() {
+        let clock = SystemClock;
+        let start = clock.now();
+        tokio::time::advance(Duration::from_secs(3600)).await;
+        assert_eq!(clock.now() - start, Duration::from_secs(3600));
+    }
+
+    #[test]
+    fn test_parse_time() {
+        let now = utc("2024-03-01T12:00:00Z");
+        assert_eq!(parse_time("", now).unwrap(), now);
+        assert_eq!(parse_time(" now ", now).unwrap(), now);
+        assert_eq!(parse_time("1700000000", now).unwrap(), utc("2023-11-14T22:13:20Z"));
+        assert_eq!(parse_time("1700000000123", now).unwrap().timestamp_millis(), 1700000000123);
+        assert_eq!(parse_time("2024-03-01T09:30:00+01:00", now).unwrap(), utc("2024-03-01T08:30:00Z"));
+        assert_eq!(parse_time("2024-03-01 09:30 America/New_York", now).unwrap(), utc("2024-03-01T14:30:00Z"));
+        assert_eq!(parse_time("2024-07-01 UTC", now).unwrap(), utc("2024-07-01T00:00:00Z"));
+        assert!(parse_time("yesterday", now).is_err());
+        // Clocks in Berlin skip from 2:00 to 3:00 that night
+        let skipped = parse_time("2024-03-31 02:30 Europe/Berlin", now).unwrap_err();
+        assert!(format!("{:#}", skipped).contains("skip"));
+    }
+
+    #[test]
+    fn test_zones() {
+        let config = TimeConfig {
+            zones: vec!["Asia/Tokyo".to_string(), "UTC".to_string()],
+            ..TimeConfig::default()
+        };
+        let zones = zones(&config).unwrap();
+        let names: Vec<&str> = zones.iter().map(Zone::name).collect();
+        assert_eq!(names, vec!["local", "UTC", "Asia/Tokyo"]);
+
+        let time = utc("2023-11-14T22:13:20Z");
+        let forms = conversions(time, &zones[1..], "%Y-%m-%d %H:%M %Z");
+        assert_eq!(format_table(&forms), vec![
+            "epoch       1700000000",
+            "epoch ms    1700000000000",
+            "RFC 3339    2023-11-14T22:13:20Z",
+            "UTC         2023-11-14 22:13 UTC",
+            "Asia/Tokyo  2023-11-15 07:13 JST",
+        ]);
+
+        let config = TimeConfig {
+            zones: vec!["Mars/Olympus".to_string()],
+            ..TimeConfig::default()
+        };
+        assert!(format!("{:#}", super::zones(&config).unwrap_err()).contains("Unknown time zone 'Mars/Olympus'"));
+    }
+
+    #[test]
+    fn test_find_timestamp() {
+        assert_eq!(find_timestamp("$ date -d @1700000000"), Some("1700000000".to_string()));
+        assert_eq!(find_timestamp("$ echo 42"), None);
+        assert_eq!(
+            find_timestamp("{\"ts\": 1699999999, \"at\": \"2024-03-01T09:30:00Z\"}"),
+            Some("2024-03-01T09:30:00Z".to_string())
+        );
+        assert_eq!(find_timestamp("$ ls -la"), None);
+    }
+}
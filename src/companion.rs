@@ -0,0 +1,306 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::CompanionConfig;
+use crate::output::CommandMark;
+
+/// How long a key waits for a companion's answer unless its config says
+/// otherwise.
+const DEFAULT_REPLY_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// How long a companion gets to exit on its own when the session ends.
+const EXIT_GRACE: Duration = Duration::from_millis(200);
+
+/// How many of the last lines a companion wrote to stderr are kept to say
+/// why it exited.
+const STDERR_LINES: usize = 5;
+
+/// What chatshell tells a companion, one JSON object per line, with its
+/// kind in `event`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    /// The shell started.
+    Start { shell: String, pid: Option<i32> },
+    /// One of its keys was pressed; answer with `reply_to` set to `id`.
+    Key { id: u64, key: String },
+    /// The shell printed `text`, escape sequences and all.
+    Output { text: String },
+    CommandStarted { command: String },
+    CommandFinished { command: String, exit_code: Option<i32>, duration_ms: u64 },
+    /// The session ends; the companion is stopped shortly after.
+    Exit,
+}
+
+impl Event {
+    pub fn from_mark(mark: &CommandMark) -> Self {
+        match mark {
+            CommandMark::Started { command } => Event::CommandStarted { command: command.clone() },
+            CommandMark::Finished { command, exit_code, duration } => Event::CommandFinished {
+                command: command.clone(),
+                exit_code: *exit_code,
+                duration_ms: duration.as_millis() as u64,
+            },
+        }
+    }
+}
+
+/// A line a companion wrote, in answer to a key or on its own.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct Response {
+    /// The `id` of the key event it answers.
+    pub reply_to: Option<u64>,
+    /// True to keep the key from the shell.
+    pub consume: bool,
+    /// Text to show where the hook's `output` says.
+    pub popup: Option<String>,
+    /// Text to type into the shell, as is.
+    pub inject: Option<String>,
+    /// An action to run, written as a hook's `action`.
+    pub action: Option<String>,
+}
+
+/// Something a companion said outside of a key's answer.
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Response { companion: String, response: Response },
+    /// A line that isn't a response, with why.
+    Invalid { companion: String, error: String },
+    /// It closed its stdout, with the last lines of its stderr.
+    Exited { companion: String, stderr: Vec<String> },
+}
+
+/// A program from `[[companions]]` running alongside the shell.
+pub struct Companion {
+    pub name: String,
+    pub keys: Vec<String>,
+    /// True if it wants the shell's output.
+    pub output: bool,
+    child: Child,
+    // Lines for the writer thread, so a companion that stops reading
+    // never holds up the session
+    events: Option<mpsc::Sender<String>>,
+    replies: mpsc::Receiver<Response>,
+    reply_timeout: Duration,
+    next_id: u64,
+}
+
+impl std::fmt::Debug for Companion {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Companion").field("name", &self.name).field("keys", &self.keys).finish()
+    }
+}
+
+/// Starts every companion in `configs`. One that fails to start is left
+/// out, with the error saying why.
+pub fn spawn_all(configs: &[CompanionConfig], messages: &UnboundedSender<Message>) -> (Vec<Companion>, Vec<anyhow::Error>) {
+    let mut companions = Vec::new();
+    let mut errors = Vec::new();
+    for config in configs {
+        match Companion::spawn(config, messages.clone()) {
+            Ok(companion) => companions.push(companion),
+            Err(e) => errors.push(e),
+        }
+    }
+    (companions, errors)
+}
+
+impl Companion {
+    /// Starts `config.command`. What it says other than answers to keys
+    /// goes to `messages`.
+    pub fn spawn(config: &CompanionConfig, messages: UnboundedSender<Message>) -> Result<Self> {
+        let mut child = Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&config.command)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("Failed to start companion {}", config.name))?;
+        let mut stdin = child.stdin.take().context("Companion stdin unavailable")?;
+        let stdout = child.stdout.take().context("Companion stdout unavailable")?;
+        let stderr = child.stderr.take().context("Companion stderr unavailable")?;
+
+        let (events, lines) = mpsc::channel::<String>();
+        std::thread::spawn(move || {
+            for line in lines {
+                if writeln!(stdin, "{}", line).and_then(|_| stdin.flush()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let stderr_tail = std::thread::spawn(move || {
+            let mut tail = Vec::new();
+            for line in BufReader::new(stderr).lines().map_while(|line| line.ok()) {
+                if tail.len() == STDERR_LINES {
+                    tail.remove(0);
+                }
+                tail.push(line);
+            }
+            tail
+        });
+
+        let (reply_tx, replies) = mpsc::channel();
+        let name = config.name.clone();
+        std::thread::spawn(move || read_responses(name, stdout, stderr_tail, reply_tx, messages));
+
+        Ok(Companion {
+            name: config.name.clone(),
+            keys: config.keys.clone(),
+            output: config.output,
+            child,
+            events: Some(events),
+            replies,
+            reply_timeout: config.reply_timeout_ms.map_or(DEFAULT_REPLY_TIMEOUT, Duration::from_millis),
+            next_id: 1,
+        })
+    }
+
+    /// Tells the companion of `event`, without waiting for it to read it.
+    pub fn send(&self, event: &Event) -> Result<()> {
+        let line = serde_json::to_string(event)?;
+        self.events
+            .as_ref()
+            .and_then(|events| events.send(line).ok())
+            .with_context(|| format!("Companion {} has stopped", self.name))
+    }
+
+    /// Tells the companion `key`, as written in its key list, was pressed
+    /// and waits for its answer. One that doesn't answer in time leaves the
+    /// key to the shell.
+    pub fn key(&mut self, key: &str) -> Result<Response> {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.send(&Event::Key { id, key: key.to_string() })?;
+        let deadline = Instant::now() + self.reply_timeout;
+        loop {
+            let left = deadline.saturating_duration_since(Instant::now());
+            match self.replies.recv_timeout(left) {
+                Ok(response) if response.reply_to == Some(id) => return Ok(response),
+                // A late answer to a key that already went to the shell
+                Ok(_) => continue,
+                Err(mpsc::RecvTimeoutError::Timeout) => return Ok(Response::default()),
+                Err(mpsc::RecvTimeoutError::Disconnected) => {
+                    anyhow::bail!("Companion {} has stopped", self.name)
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Companion {
+    fn drop(&mut self) {
+        let _ = self.send(&Event::Exit);
+        // Closes its stdin once the writer is done
+        self.events = None;
+        let deadline = Instant::now() + EXIT_GRACE;
+        while Instant::now() < deadline {
+            if !matches!(self.child.try_wait(), Ok(None)) {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Reads the companion's stdout until it closes, passing answers to keys
+/// to `replies` and everything else to `messages`.
+fn read_responses(
+    companion: String,
+    stdout: std::process::ChildStdout,
+    stderr_tail: JoinHandle<Vec<String>>,
+    replies: mpsc::Sender<Response>,
+    messages: UnboundedSender<Message>,
+) {
+    for line in BufReader::new(stdout).lines().map_while(|line| line.ok()) {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message = match serde_json::from_str::<Response>(&line) {
+            Ok(response) if response.reply_to.is_some() => {
+                let _ = replies.send(response);
+                continue;
+            }
+            Ok(response) => Message::Response { companion: companion.clone(), response },
+            Err(e) => Message::Invalid { companion: companion.clone(), error: format!("{}: {}", e, line) },
+        };
+        let _ = messages.send(message);
+    }
+    let stderr = stderr_tail.join().unwrap_or_default();
+    let _ = messages.send(Message::Exited { companion, stderr });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(command: &str) -> CompanionConfig {
+        CompanionConfig {
+            name: "test".to_string(),
+            command: command.to_string(),
+            keys: vec!["f9".to_string()],
+            output: true,
+            reply_timeout_ms: Some(2000),
+        }
+    }
+
+    #[test]
+    fn test_companions_answer_keys_and_events() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let script = r#"while read line; do
+            case "$line" in
+              *'"key"'*) printf '%s\n' '{"reply_to":1,"consume":true,"inject":"ls\n"}' ;;
+              *'"command_finished"'*) echo '{"popup":"done"}'; echo 'not json' ;;
+            esac
+          done"#;
+        let mut companion = Companion::spawn(&config(script), tx).unwrap();
+
+        let response = companion.key("f9").unwrap();
+        assert!(response.consume);
+        assert_eq!(response.inject.as_deref(), Some("ls\n"));
+
+        let mark = CommandMark::Finished { command: "make".to_string(), exit_code: Some(2), duration: Duration::from_millis(1500) };
+        companion.send(&Event::from_mark(&mark)).unwrap();
+        let message = rx.blocking_recv().unwrap();
+        assert_eq!(message, Message::Response {
+            companion: "test".to_string(),
+            response: Response { popup: Some("done".to_string()), ..Response::default() },
+        });
+        assert!(matches!(rx.blocking_recv().unwrap(), Message::Invalid { error, .. } if error.ends_with("not json")));
+    }
+
+    #[test]
+    fn test_events_are_json_lines() {
+        let event = Event::CommandFinished { command: "make".to_string(), exit_code: None, duration_ms: 20 };
+        assert_eq!(
+            serde_json::to_string(&event).unwrap(),
+            r#"{"event":"command_finished","command":"make","exit_code":null,"duration_ms":20}"#
+        );
+        assert_eq!(serde_json::to_string(&Event::Exit).unwrap(), r#"{"event":"exit"}"#);
+    }
+
+    #[test]
+    fn test_silent_and_exiting_companions() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut slow = Companion::spawn(&CompanionConfig { reply_timeout_ms: Some(50), ..config("sleep 5") }, tx.clone()).unwrap();
+        let started = Instant::now();
+        assert_eq!(slow.key("f9").unwrap(), Response::default());
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        let _gone = Companion::spawn(&config("echo 'no such thing' >&2; exit 1"), tx).unwrap();
+        assert_eq!(rx.blocking_recv().unwrap(), Message::Exited {
+            companion: "test".to_string(),
+            stderr: vec!["no such thing".to_string()],
+        });
+    }
+}
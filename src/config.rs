@@ -1,61 +1,1305 @@
+use chrono::NaiveTime;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
 use std::path::Path;
+use std::time::Duration;
 use anyhow::{Context, Result};
+use crate::output::TerminalQuery;
+use crate::pane::SplitDirection;
+use crate::permissions::Principal;
+use crate::render::DEFAULT_MAX_FPS;
+use crate::repl::ReplKind;
+use crate::shell::{is_valid_env_name, parse_env_assignments};
+use crate::storage::StorageBackend;
+use crate::watch::parse_interval;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub shell: ShellConfig,
     pub hooks: Vec<HookConfig>,
+    #[serde(default)]
+    pub terminal: TerminalConfig,
+    #[serde(default)]
+    pub profiles: HashMap<String, ProfileConfig>,
+    /// Additional named shells (or REPLs) offered by `builtin:switch_shell`.
+    #[serde(default)]
+    pub shells: BTreeMap<String, ShellConfig>,
+    #[serde(default)]
+    pub llm: LlmConfig,
+    /// Bundled hook sets to load before `hooks`, e.g. `["database"]`.
+    #[serde(default)]
+    pub presets: Vec<String>,
+    #[serde(default)]
+    pub pane: PaneConfig,
+    #[serde(default)]
+    pub journal: JournalConfig,
+    #[serde(default)]
+    pub history: HistoryConfig,
+    #[serde(default)]
+    pub i18n: I18nConfig,
+    #[serde(default)]
+    pub wayback: WaybackConfig,
+    #[serde(default)]
+    pub keys: KeysConfig,
+    #[serde(default)]
+    pub low_bandwidth: LowBandwidthConfig,
+    #[serde(default)]
+    pub render: RenderConfig,
+    /// Checks run before the shell starts, which can set its variables or
+    /// keep it from starting.
+    #[serde(default)]
+    pub pre_spawn: Vec<PreSpawnConfig>,
+    #[serde(default)]
+    pub hook_packs: HookPacksConfig,
+    /// Programs run alongside the shell that hooks can hand keys to.
+    #[serde(default)]
+    pub companions: Vec<CompanionConfig>,
+    #[serde(default)]
+    pub time: TimeConfig,
+    #[serde(default)]
+    pub paste: PasteConfig,
+    #[serde(default)]
+    pub scrollback: ScrollbackConfig,
+    #[serde(default)]
+    pub automation: AutomationConfig,
+    #[serde(default)]
+    pub ui: UiConfig,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ShellConfig {
     pub command: String,
     pub args: Vec<String>,
     pub env: Option<HashMap<String, String>>,
+    /// File of `KEY=VALUE` lines re-read by `builtin:reload_env`.
+    #[serde(default)]
+    pub env_file: Option<String>,
+    /// Commands typed into the shell once its first prompt appears.
+    #[serde(default)]
+    pub startup_commands: Vec<String>,
+    #[serde(default)]
+    pub on_exit: ExitPolicy,
+    /// The interpreter this command runs, when it is a REPL rather than a
+    /// shell. Detected from `command` when unset.
+    #[serde(default)]
+    pub repl: Option<ReplKind>,
+    /// Which of chatshell's own variables the shell starts with. `env` is
+    /// applied on top either way.
+    #[serde(default)]
+    pub env_policy: EnvPolicy,
+    /// The `TERM` to give the shell instead of the host's. Without it a
+    /// missing or unknown `TERM` becomes `xterm-256color`.
+    #[serde(default)]
+    pub term_override: Option<String>,
+    /// Put the `ssh` wrapper of `chatshell init` first on the shell's
+    /// `PATH`, so remote shells can tell they run under chatshell.
+    #[serde(default)]
+    pub ssh_wrapper: bool,
+}
+
+/// Which variables of chatshell's environment reach the shell, set in
+/// `[shell.env_policy]`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EnvPolicy {
+    pub inherit: EnvInherit,
+    /// Variables to leave out even though `inherit` takes them. A name
+    /// ending in `*` covers every variable starting with what's before it.
+    pub remove: Vec<String>,
+}
+
+/// `inherit = "all"`, `"none"` or a list of variable names.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum EnvInherit {
+    Mode(InheritMode),
+    /// Only the variables named, which may end in `*` like in `remove`.
+    Only(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InheritMode {
+    All,
+    None,
+}
+
+impl Default for EnvInherit {
+    fn default() -> Self {
+        EnvInherit::Mode(InheritMode::All)
+    }
+}
+
+impl EnvPolicy {
+    /// True if the shell keeps chatshell's variable `key`.
+    pub fn keeps(&self, key: &str) -> bool {
+        let inherited = match &self.inherit {
+            EnvInherit::Mode(InheritMode::All) => true,
+            EnvInherit::Mode(InheritMode::None) => false,
+            EnvInherit::Only(names) => names.iter().any(|name| env_name_matches(name, key)),
+        };
+        inherited && !self.remove.iter().any(|name| env_name_matches(name, key))
+    }
+}
+
+fn env_name_matches(name: &str, key: &str) -> bool {
+    match name.strip_suffix('*') {
+        Some(prefix) => key.starts_with(prefix),
+        None => name == key,
+    }
+}
+
+/// What to do when the wrapped shell exits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitPolicy {
+    /// Exit chatshell as well.
+    #[default]
+    Close,
+    /// Start a fresh shell in the same session.
+    Respawn,
+    /// Ask whether to start a fresh shell.
+    Prompt,
+}
+
+/// Keys typed in answer to what the shell prints.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutomationConfig {
+    /// Run in order each time a shell starts, before `startup_commands`.
+    pub startup: Vec<AutomationStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AutomationStep {
+    /// A regex to wait for in the shell's output; without one, the keys
+    /// are sent as soon as the step before has run.
+    pub wait_for: Option<String>,
+    /// The keys to send, with the escapes of `inject:`.
+    pub send: String,
+    /// How long to wait for the pattern before giving up on the steps
+    /// left, in seconds.
+    pub timeout_secs: u64,
+}
+
+impl Default for AutomationStep {
+    fn default() -> Self {
+        AutomationStep { wait_for: None, send: String::new(), timeout_secs: 30 }
+    }
+}
+
+/// Extra settings applied when chatshell is started with `--profile <name>`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProfileConfig {
+    pub startup_commands: Vec<String>,
+}
+
+/// The assistant behind `builtin:ask`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LlmConfig {
+    /// Shell command that reads the prompt on stdin and prints the reply.
+    /// The system prompt is passed in `$CHATSHELL_SYSTEM_PROMPT`.
+    pub command: Option<String>,
+    /// Extra instructions appended to the built-in system prompt.
+    pub system_prompt: Option<String>,
+    /// Tells the assistant the session's notes along with the system
+    /// prompt.
+    pub include_notes: bool,
+    /// Share of the screen's width given to the chat view, in percent.
+    pub chat_size: u16,
+}
+
+impl Default for LlmConfig {
+    fn default() -> Self {
+        LlmConfig {
+            command: None,
+            system_prompt: None,
+            include_notes: false,
+            chat_size: 35,
+        }
+    }
+}
+
+/// The work log kept by summarizing sessions.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JournalConfig {
+    /// Summarize the session with the [llm] when chatshell exits.
+    pub summarize_on_exit: bool,
+    /// Markdown file the summaries are appended to.
+    pub path: String,
+    /// How long exiting waits for the summary before giving up on it, in
+    /// seconds.
+    pub timeout_secs: u64,
+}
+
+impl Default for JournalConfig {
+    fn default() -> Self {
+        JournalConfig {
+            summarize_on_exit: false,
+            path: "~/.local/share/chatshell/journal.md".to_string(),
+            timeout_secs: 30,
+        }
+    }
+}
+
+/// The record of commands run and LLM calls made, read by
+/// `chatshell report`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HistoryConfig {
+    pub enabled: bool,
+    pub backend: StorageBackend,
+    /// The database file, or directory for `jsonl`. Defaults to a location
+    /// under `~/.local/share/chatshell` that depends on the backend.
+    pub path: Option<String>,
+    /// Also keeps what each session printed, in color, and what the
+    /// assistant was asked, in `transcripts/<session>.jsonl` next to the
+    /// config, for `chatshell export <session>`.
+    pub transcripts: bool,
+    /// Keeps what is entered in input popups in `input_history.toml` next
+    /// to the config, for up, down and ctrl+r in later popups.
+    pub inputs: bool,
+}
+
+impl HistoryConfig {
+    pub fn path(&self) -> String {
+        self.path.clone().unwrap_or_else(|| self.backend.default_path().to_string())
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        HistoryConfig {
+            enabled: true,
+            backend: StorageBackend::default(),
+            path: None,
+            transcripts: false,
+            inputs: true,
+        }
+    }
+}
+
+/// The auxiliary pane opened by `builtin:split_pane`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaneConfig {
+    /// Command run in the pane; the shell's command when unset.
+    pub command: Option<String>,
+    pub args: Vec<String>,
+    pub split: SplitDirection,
+    /// Share of the screen given to the pane, in percent.
+    pub size: u16,
+}
+
+impl Default for PaneConfig {
+    fn default() -> Self {
+        PaneConfig {
+            command: None,
+            args: Vec::new(),
+            split: SplitDirection::Horizontal,
+            size: 30,
+        }
+    }
+}
+
+impl PaneConfig {
+    /// What to run in the pane, starting from the session's shell.
+    pub fn shell_config(&self, shell: &ShellConfig) -> ShellConfig {
+        match &self.command {
+            Some(command) => ShellConfig {
+                command: command.clone(),
+                args: self.args.clone(),
+                env: shell.env.clone(),
+                env_policy: shell.env_policy.clone(),
+                term_override: shell.term_override.clone(),
+                ssh_wrapper: shell.ssh_wrapper,
+                ..Default::default()
+            },
+            None => ShellConfig {
+                startup_commands: Vec::new(),
+                ..shell.clone()
+            },
+        }
+    }
+}
+
+/// The clocks of `fn:show_time` and `builtin:convert_time`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeConfig {
+    /// Zones shown after local time and UTC, like `America/New_York`.
+    pub zones: Vec<String>,
+    /// How times in those zones are written, in chrono's `strftime` syntax.
+    pub format: String,
+}
+
+impl Default for TimeConfig {
+    fn default() -> Self {
+        TimeConfig {
+            zones: Vec::new(),
+            format: "%Y-%m-%d %H:%M:%S %Z".to_string(),
+        }
+    }
+}
+
+/// How text pasted into the terminal or typed by hooks reaches the shell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PasteConfig {
+    /// Above this many kilobytes, ask whether to type the text or save it
+    /// to a file; 0 never asks.
+    pub large_kb: u64,
+}
+
+impl Default for PasteConfig {
+    fn default() -> Self {
+        PasteConfig { large_kb: 64 }
+    }
+}
+
+/// How popups and panels look.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UiConfig {
+    pub theme: ThemeConfig,
+    pub accessibility: AccessibilityConfig,
+    pub highlight: HighlightConfig,
+}
+
+/// Colors to draw popups and panels in instead of the default blues, as
+/// names like `dark_blue`, `#rrggbb` or numbers of the 256-color palette.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub border_fg: Option<String>,
+    pub border_bg: Option<String>,
+    pub text_fg: Option<String>,
+    pub text_bg: Option<String>,
+    pub selected_fg: Option<String>,
+    pub selected_bg: Option<String>,
+    pub highlight_fg: Option<String>,
+    pub highlight_bg: Option<String>,
+    pub hint: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AccessibilityConfig {
+    /// The least contrast ratio between a color and its background, from 1
+    /// to 21; WCAG asks for 4.5 for text. 0 checks nothing.
+    pub min_contrast: f64,
+    /// Mark what colors alone would tell: colored lines in bold, matches
+    /// underlined and the selected row with `›`.
+    pub markers: bool,
+}
+
+/// Syntax highlighting of code blocks in popups.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HighlightConfig {
+    pub enabled: bool,
+    /// One of the themes syntect comes with, like `base16-ocean.dark` or
+    /// `InspiredGitHub`.
+    pub theme: String,
+}
+
+impl Default for HighlightConfig {
+    fn default() -> Self {
+        HighlightConfig { enabled: true, theme: "base16-ocean.dark".to_string() }
+    }
+}
+
+/// The shell's output kept for `builtin:search_scrollback`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ScrollbackConfig {
+    /// How many lines to keep; 0 keeps none.
+    pub lines: usize,
+}
+
+impl Default for ScrollbackConfig {
+    fn default() -> Self {
+        ScrollbackConfig { lines: 10000 }
+    }
+}
+
+/// The recent screens kept for `builtin:wayback`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WaybackConfig {
+    /// How many screens to keep; 0 keeps none.
+    pub keep: usize,
+    /// The least time between two snapshots, in seconds.
+    pub interval_secs: u64,
+}
+
+impl Default for WaybackConfig {
+    fn default() -> Self {
+        WaybackConfig {
+            keep: 20,
+            interval_secs: 5,
+        }
+    }
+}
+
+/// How keys are read for hooks, including how presses are told apart for
+/// hooks bound to `ctrl+c ctrl+c` or `hold ctrl+k`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct KeysConfig {
+    /// The most time between the two presses of a double press, in
+    /// milliseconds.
+    pub double_press_ms: u64,
+    /// Ask terminals that speak the kitty keyboard protocol for keys legacy
+    /// encoding can't tell apart, like ctrl+shift+enter or super+k.
+    pub kitty_protocol: bool,
+    /// How long the prefix of a chord binding like `ctrl+a t` waits for
+    /// its second key before it goes to the shell, in milliseconds.
+    pub chord_timeout_ms: u64,
+    /// Ends chatshell at once wherever it is pressed, even in a popup,
+    /// for when a hook or overlay doesn't let go. Hooks can't take it.
+    pub escape_hatch: String,
+    /// What the escape hatch does with the shell.
+    pub escape_hatch_policy: HatchPolicy,
+}
+
+impl Default for KeysConfig {
+    fn default() -> Self {
+        KeysConfig {
+            double_press_ms: 400,
+            kitty_protocol: false,
+            chord_timeout_ms: 1000,
+            escape_hatch: "ctrl+alt+q".to_string(),
+            escape_hatch_policy: HatchPolicy::default(),
+        }
+    }
+}
+
+/// What happens to the shell when the escape hatch is pulled.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HatchPolicy {
+    /// Leave it be: it is hung up on as its terminal closes, as when a
+    /// terminal window is closed, and jobs that ignore that keep running.
+    #[default]
+    Detach,
+    /// Kill it and everything it started.
+    Kill,
 }
 
+/// Settings for slow or high-latency links, where every byte drawn over
+/// the link costs.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LowBandwidthConfig {
+    pub enabled: bool,
+    /// How long shell output is gathered before it is drawn, in
+    /// milliseconds.
+    pub coalesce_ms: u64,
+    /// Redraw only the lines of the watch panel that changed.
+    pub delta_refresh: bool,
+}
+
+impl Default for LowBandwidthConfig {
+    fn default() -> Self {
+        LowBandwidthConfig {
+            enabled: false,
+            coalesce_ms: 50,
+            delta_refresh: false,
+        }
+    }
+}
+
+/// A command run with `/bin/sh` before the shell starts. Lines it prints
+/// like `KEY=VALUE` are set in the shell; if it fails, the shell doesn't
+/// start and what it printed says why.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PreSpawnConfig {
+    pub name: String,
+    pub command: String,
+    /// How long it may take, in seconds; 10 if unset.
+    #[serde(default)]
+    pub timeout_secs: Option<u64>,
+}
+
+/// A program run with `/bin/sh` for the whole session, which is told of
+/// keys, output and commands as JSON lines on its stdin and answers on its
+/// stdout.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CompanionConfig {
+    pub name: String,
+    pub command: String,
+    /// Keys to hand to it, written as in a hook's `key_combination`.
+    #[serde(default)]
+    pub keys: Vec<String>,
+    /// True to send it everything the shell prints.
+    #[serde(default)]
+    pub output: bool,
+    /// How long a key waits for its answer before going to the shell, in
+    /// milliseconds; 500 if unset.
+    #[serde(default)]
+    pub reply_timeout_ms: Option<u64>,
+}
+
+/// Where `chatshell hooks add` looks up packs given by name.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HookPacksConfig {
+    /// Path or http(s) URL of the registry index, a TOML file with a
+    /// `[packs.<name>]` table for each pack.
+    pub registry: Option<String>,
+}
+
+/// How the pane and watch panel are drawn over the shell.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderConfig {
+    /// The most times a second they are drawn again; 0 draws on every
+    /// change.
+    pub max_fps: u32,
+}
+
+impl Default for RenderConfig {
+    fn default() -> Self {
+        RenderConfig { max_fps: DEFAULT_MAX_FPS }
+    }
+}
+
+/// The language of built-in text.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct I18nConfig {
+    /// A language tag such as `de` or `pt-BR`. Taken from `LC_ALL`,
+    /// `LC_MESSAGES` or `LANG` when unset.
+    pub locale: Option<String>,
+    /// Replacement text for single messages, by message id.
+    pub messages: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct HookConfig {
     pub name: String,
+    /// Empty for hooks that run on an `event` instead of a key.
+    #[serde(default)]
     pub key_combination: String,
-    pub action: String,
+    pub action: HookAction,
     pub description: Option<String>,
     pub enabled: bool,
+    #[serde(default)]
+    pub when: HookCondition,
+    /// Which of the hooks bound to the same key gets it first: higher
+    /// priorities first, then hooks in the order they are configured.
+    #[serde(default)]
+    pub priority: i32,
+    /// What the shell does that runs the hook, for hooks not bound to a key.
+    #[serde(default)]
+    pub event: Option<HookEvent>,
+    /// Where the result of a `cmd:` or `fn:` action goes.
+    #[serde(default)]
+    pub output: HookOutput,
+    /// How long a `cmd:` action may run before it is killed, in
+    /// milliseconds; no limit if unset. For a `lua:` action, how long each
+    /// of its commands and prompts may take; 30 seconds if unset.
+    #[serde(default)]
+    pub timeout_ms: Option<u64>,
+    /// How the `{name}` placeholders of a `cmd:` action are asked for, by
+    /// name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub args: BTreeMap<String, ArgConfig>,
+    /// Runs the hook this often, for hooks not bound to a key.
+    #[serde(default)]
+    pub interval: Option<Interval>,
+    /// Runs the hook once no key has been pressed for this long, and again
+    /// only after the next key.
+    #[serde(default)]
+    pub idle: Option<Interval>,
+    /// Reads what a `cmd:` action prints as JSON asking for popups, keys,
+    /// menus or actions, showing it as text if it isn't.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub structured: bool,
+    /// Runs with what was picked in a menu the `structured` action asked
+    /// for, instead of typing it.
+    #[serde(default)]
+    pub on_select: Option<OnSelect>,
+    /// A question answered yes or no before the action runs, for hooks
+    /// that are hard to undo.
+    #[serde(default)]
+    pub confirm: Option<String>,
+    /// Who the hook runs as: the plugin, companion or hook pack it came
+    /// from, or None for the user's own hooks. Never read from a file.
+    #[serde(skip)]
+    pub principal: Option<Principal>,
+}
+
+/// A command run with a picked menu choice, as `{ command = "git checkout" }`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OnSelect {
+    pub command: String,
+    /// How the command gets the choice.
+    #[serde(default)]
+    pub pass: SelectionPass,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SelectionPass {
+    /// Quoted, as the command's last argument.
+    #[default]
+    Argv,
+    /// On standard input, ending with a newline.
+    Stdin,
+}
+
+impl OnSelect {
+    /// The command to run for `selection` and what goes to its standard
+    /// input.
+    pub fn command_for(&self, selection: &str) -> (String, Option<String>) {
+        match self.pass {
+            SelectionPass::Argv => (format!("{} {}", self.command, crate::shell::single_quote(selection)), None),
+            SelectionPass::Stdin => (self.command.clone(), Some(format!("{}\n", selection))),
+        }
+    }
+}
+
+/// What a hook does: one action, or a list of them run in turn that stops
+/// at the first one to fail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum HookAction {
+    Single(String),
+    Chain(Vec<String>),
+}
+
+impl HookAction {
+    /// The actions in the order they run.
+    pub fn steps(&self) -> &[String] {
+        match self {
+            HookAction::Single(action) => std::slice::from_ref(action),
+            HookAction::Chain(steps) => steps,
+        }
+    }
+
+    /// The same actions, each changed by `f`.
+    pub fn map(&self, f: impl Fn(&str) -> String) -> HookAction {
+        match self {
+            HookAction::Single(action) => HookAction::Single(f(action)),
+            HookAction::Chain(steps) => HookAction::Chain(steps.iter().map(|step| f(step)).collect()),
+        }
+    }
+}
+
+impl Default for HookAction {
+    fn default() -> Self {
+        HookAction::Single(String::new())
+    }
+}
+
+impl From<&str> for HookAction {
+    fn from(action: &str) -> Self {
+        HookAction::Single(action.to_string())
+    }
+}
+
+impl From<String> for HookAction {
+    fn from(action: String) -> Self {
+        HookAction::Single(action)
+    }
+}
+
+impl PartialEq<&str> for HookAction {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, HookAction::Single(action) if action == other)
+    }
+}
+
+impl fmt::Display for HookAction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.steps().join(" → "))
+    }
+}
+
+/// A length of time written like `500ms`, `30s`, `5m` or `1h`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Interval(pub Duration);
+
+impl TryFrom<String> for Interval {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        parse_interval(&value).map(Interval).map_err(|e| e.to_string())
+    }
+}
+
+impl From<Interval> for String {
+    fn from(interval: Interval) -> Self {
+        let millis = interval.0.as_millis();
+        if millis.is_multiple_of(3_600_000) {
+            format!("{}h", millis / 3_600_000)
+        } else if millis.is_multiple_of(60_000) {
+            format!("{}m", millis / 60_000)
+        } else if millis.is_multiple_of(1000) {
+            format!("{}s", millis / 1000)
+        } else {
+            format!("{}ms", millis)
+        }
+    }
+}
+
+/// How a placeholder of a `cmd:` action is asked for before it runs.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ArgConfig {
+    /// What the input asks; the placeholder's name if unset.
+    pub label: Option<String>,
+    /// Text the input starts with.
+    pub default: Option<String>,
+    /// Keeps what was entered in the input history, which up, down and
+    /// ctrl+r recall.
+    pub history: bool,
+}
+
+impl Default for ArgConfig {
+    fn default() -> Self {
+        ArgConfig { label: None, default: None, history: true }
+    }
+}
+
+/// Something the session or the shell does that hooks can run on. Command
+/// events are known from the shell's `OSC 133` marks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HookEvent {
+    /// A command line was entered and starts running.
+    OnCommandStart,
+    /// The command finished.
+    OnCommandEnd,
+    /// chatshell started, with its shell.
+    OnStart,
+    /// The shell exited, before it is respawned or the session ends.
+    OnExit,
+    /// The terminal was resized, once it keeps its new size.
+    OnResize,
+}
+
+impl HookEvent {
+    pub const ALL: [HookEvent; 5] = [
+        HookEvent::OnCommandStart,
+        HookEvent::OnCommandEnd,
+        HookEvent::OnStart,
+        HookEvent::OnExit,
+        HookEvent::OnResize,
+    ];
+
+    /// The event's name, as written in the config.
+    pub fn name(self) -> &'static str {
+        match self {
+            HookEvent::OnCommandStart => "on_command_start",
+            HookEvent::OnCommandEnd => "on_command_end",
+            HookEvent::OnStart => "on_start",
+            HookEvent::OnExit => "on_exit",
+            HookEvent::OnResize => "on_resize",
+        }
+    }
+
+    /// The event called `name` in the config.
+    pub fn from_name(name: &str) -> Option<Self> {
+        HookEvent::ALL.into_iter().find(|event| event.name() == name)
+    }
+}
+
+/// Where a hook's result goes, written as `popup`, `inline`, `inject`,
+/// `notify`, `toast`, `pane`, `silent` or `file:<path>`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub enum HookOutput {
+    /// A popup that waits for a key.
+    #[default]
+    Popup,
+    /// Printed into the terminal above the prompt.
+    Inline,
+    /// Typed at the shell's prompt, without running it.
+    Inject,
+    /// Appended to a file.
+    File(String),
+    /// A desktop notification, through the terminal.
+    Notify,
+    /// A note in a corner of the screen that goes away by itself.
+    Toast,
+    /// Printed in the split pane, which is opened if it isn't.
+    Pane,
+    Silent,
+}
+
+impl TryFrom<String> for HookOutput {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        if let Some(path) = value.strip_prefix("file:") {
+            if path.is_empty() {
+                return Err("file: needs a path, e.g. file:~/hooks.log".to_string());
+            }
+            return Ok(HookOutput::File(path.to_string()));
+        }
+        match value.as_str() {
+            "popup" => Ok(HookOutput::Popup),
+            "inline" => Ok(HookOutput::Inline),
+            "inject" => Ok(HookOutput::Inject),
+            "notify" => Ok(HookOutput::Notify),
+            "toast" => Ok(HookOutput::Toast),
+            "pane" => Ok(HookOutput::Pane),
+            "silent" => Ok(HookOutput::Silent),
+            _ => Err(format!(
+                "unknown output '{}', expected popup, inline, inject, notify, toast, pane, silent or file:<path>",
+                value
+            )),
+        }
+    }
+}
+
+impl From<HookOutput> for String {
+    fn from(output: HookOutput) -> Self {
+        match output {
+            HookOutput::Popup => "popup".to_string(),
+            HookOutput::Inline => "inline".to_string(),
+            HookOutput::Inject => "inject".to_string(),
+            HookOutput::File(path) => format!("file:{}", path),
+            HookOutput::Notify => "notify".to_string(),
+            HookOutput::Toast => "toast".to_string(),
+            HookOutput::Pane => "pane".to_string(),
+            HookOutput::Silent => "silent".to_string(),
+        }
+    }
+}
+
+/// When a hook may fire. While it may not, its key goes to the shell as if
+/// the hook weren't there.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct HookCondition {
+    /// Only at the shell's prompt: not while a program runs in the
+    /// foreground or a command is half-entered. For hooks that type text,
+    /// which a running program would read instead.
+    pub at_prompt: bool,
+    /// Only while the shell's directory matches this glob, like
+    /// `~/src/**`. `*` stays within a directory and `**` doesn't.
+    pub cwd: Option<String>,
+    /// Only while the shell's directory, or one above it, holds this file
+    /// or directory, like `.git` or `Cargo.toml`.
+    pub inside: Option<String>,
+    /// Only while all of these variables are set, in the environment the
+    /// shell or the program in the foreground was started with.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub env: Vec<String>,
+    /// Only while one of these programs is in the foreground, by name.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub program: Vec<String>,
+    /// Only between these local times, like `09:00-17:30`, which may run
+    /// past midnight.
+    pub time: Option<TimeRange>,
+    /// Only while a full-screen program like vim is open if true, only
+    /// while none is if false. Hooks that type text are held back in
+    /// full-screen programs unless this is set.
+    pub alt_screen: Option<bool>,
+}
+
+/// Local times of day written like `09:00-17:30`, from the first up to the
+/// second.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct TimeRange {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeRange {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+impl TryFrom<String> for TimeRange {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M");
+        match value.split_once('-').map(|(start, end)| (parse(start), parse(end))) {
+            Some((Ok(start), Ok(end))) => Ok(TimeRange { start, end }),
+            _ => Err(format!("invalid time range '{}', expected e.g. 09:00-17:30", value)),
+        }
+    }
+}
+
+impl From<TimeRange> for String {
+    fn from(range: TimeRange) -> Self {
+        format!("{}-{}", range.start.format("%H:%M"), range.end.format("%H:%M"))
+    }
+}
+
+/// What to do with a query the child sends to the terminal.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum QueryPolicy {
+    /// Pass the query through to the outer terminal.
+    Forward,
+    /// Answer the query ourselves and hide it from the outer terminal.
+    Spoof,
+    /// Swallow the query without answering.
+    Drop,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QueryPolicies {
+    pub enq: QueryPolicy,
+    pub primary_da: QueryPolicy,
+    pub secondary_da: QueryPolicy,
+    pub status_report: QueryPolicy,
+    pub cursor_position: QueryPolicy,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalConfig {
+    pub queries: QueryPolicies,
+    pub answerback: String,
+    pub primary_da_response: String,
+    pub secondary_da_response: String,
+}
+
+impl Default for QueryPolicies {
+    fn default() -> Self {
+        QueryPolicies {
+            enq: QueryPolicy::Forward,
+            primary_da: QueryPolicy::Forward,
+            secondary_da: QueryPolicy::Forward,
+            status_report: QueryPolicy::Forward,
+            cursor_position: QueryPolicy::Forward,
+        }
+    }
+}
+
+impl QueryPolicies {
+    pub fn policy_for(&self, query: TerminalQuery) -> QueryPolicy {
+        match query {
+            TerminalQuery::Enq => self.enq,
+            TerminalQuery::PrimaryDeviceAttributes => self.primary_da,
+            TerminalQuery::SecondaryDeviceAttributes => self.secondary_da,
+            TerminalQuery::StatusReport => self.status_report,
+            TerminalQuery::CursorPosition => self.cursor_position,
+        }
+    }
+}
+
+impl Default for TerminalConfig {
+    fn default() -> Self {
+        TerminalConfig {
+            queries: QueryPolicies::default(),
+            answerback: String::new(),
+            // VT220 with ANSI color, the same identity xterm reports
+            primary_da_response: "\x1b[?62;22c".to_string(),
+            secondary_da_response: "\x1b[>1;10;0c".to_string(),
+        }
+    }
+}
+
+impl Default for ShellConfig {
+    fn default() -> Self {
+        ShellConfig {
+            command: "/bin/bash".to_string(),
+            args: vec!["-i".to_string()], // Interactive mode
+            env: None,
+            env_file: None,
+            startup_commands: Vec::new(),
+            on_exit: ExitPolicy::Close,
+            repl: None,
+            env_policy: EnvPolicy::default(),
+            term_override: None,
+            ssh_wrapper: false,
+        }
+    }
+}
+
+impl ShellConfig {
+    /// The REPL this shell config runs, if any.
+    pub fn repl_kind(&self) -> Option<ReplKind> {
+        self.repl.or_else(|| ReplKind::detect(&self.command))
+    }
+
+    /// The variables to (re-)export into a running session: `env` from the
+    /// config followed by the contents of `env_file`, which wins on conflicts.
+    /// Fails on a name in `env` that isn't a valid variable name, since it
+    /// would be typed into the shell as is.
+    pub fn session_env(&self) -> Result<Vec<(String, String)>> {
+        let mut vars: Vec<(String, String)> = self.env.clone().unwrap_or_default().into_iter().collect();
+        if let Some((key, _)) = vars.iter().find(|(key, _)| !is_valid_env_name(key)) {
+            anyhow::bail!("Invalid variable name in [shell.env]: {:?}", key);
+        }
+        vars.sort();
+
+        if let Some(env_file) = &self.env_file {
+            let path = expand_home(env_file);
+            let content = fs::read_to_string(&path)
+                .with_context(|| format!("Failed to read env file: {}", path))?;
+            for (key, value) in parse_env_assignments(&content) {
+                vars.retain(|(existing, _)| *existing != key);
+                vars.push((key, value));
+            }
+        }
+
+        Ok(vars)
+    }
+}
+
+/// Expands a leading `~/` to the user's home directory.
+pub fn expand_home(path: &str) -> String {
+    match (path.strip_prefix("~/"), std::env::var_os("HOME")) {
+        (Some(rest), Some(home)) => format!("{}/{}", home.to_string_lossy(), rest),
+        _ => path.to_string(),
+    }
+}
+
+/// `text`, a config or `pack.toml`, with the `enabled` line of the
+/// `[[hooks]]` entry named `name` set to `enabled`, and everything else as
+/// it was written. None if there is no such entry.
+pub fn set_hook_enabled(text: &str, name: &str, enabled: bool) -> Option<String> {
+    let mut lines: Vec<String> = text.lines().map(str::to_string).collect();
+    // For the entry being read: whether it is the one, and its enabled line
+    let mut entry: Option<(bool, Option<usize>)> = None;
+    // False inside a table of the entry, such as [hooks.when]
+    let mut own_keys = false;
+    let mut found = None;
+    for (index, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with('[') {
+            if let Some((true, Some(enabled_line))) = entry {
+                found = Some(enabled_line);
+                break;
+            }
+            if trimmed == "[[hooks]]" {
+                entry = Some((false, None));
+                own_keys = true;
+            } else if trimmed.starts_with("[hooks.") || trimmed.starts_with("[[hooks.") {
+                own_keys = false;
+            } else {
+                entry = None;
+            }
+            continue;
+        }
+        let (Some((matches, enabled_line)), true) = (entry.as_mut(), own_keys) else {
+            continue;
+        };
+        let Ok(table) = trimmed.parse::<toml::Table>() else {
+            continue;
+        };
+        if table.get("name").and_then(toml::Value::as_str) == Some(name) {
+            *matches = true;
+        }
+        if table.contains_key("enabled") {
+            *enabled_line = Some(index);
+        }
+    }
+    if let (None, Some((true, Some(enabled_line)))) = (found, entry) {
+        found = Some(enabled_line);
+    }
+
+    let index = found?;
+    let indent: String = lines[index].chars().take_while(|c| c.is_whitespace()).collect();
+    lines[index] = format!("{}enabled = {}", indent, enabled);
+    let mut text_out = lines.join("\n");
+    if text.ends_with('\n') {
+        text_out.push('\n');
+    }
+    Some(text_out)
 }
 
 impl Default for Config {
     fn default() -> Self {
         Config {
-            shell: ShellConfig {
-                command: "/bin/bash".to_string(),
-                args: vec!["-i".to_string()], // Interactive mode
-                env: None,
-            },
+            shell: ShellConfig::default(),
             hooks: vec![
                 HookConfig {
                     name: "example_hook".to_string(),
                     key_combination: "ctrl+;".to_string(),
-                    action: "echo 'Hook triggered!'".to_string(),
+                    action: "echo 'Hook triggered!'".into(),
                     description: Some("Example hook for Ctrl+;".to_string()),
                     enabled: true,
+                    ..Default::default()
                 },
             ],
+            terminal: TerminalConfig::default(),
+            profiles: HashMap::new(),
+            shells: BTreeMap::new(),
+            llm: LlmConfig::default(),
+            presets: Vec::new(),
+            pane: PaneConfig::default(),
+            journal: JournalConfig::default(),
+            history: HistoryConfig::default(),
+            i18n: I18nConfig::default(),
+            wayback: WaybackConfig::default(),
+            keys: KeysConfig::default(),
+            low_bandwidth: LowBandwidthConfig::default(),
+            render: RenderConfig::default(),
+            pre_spawn: Vec::new(),
+            hook_packs: HookPacksConfig::default(),
+            companions: Vec::new(),
+            time: TimeConfig::default(),
+            paste: PasteConfig::default(),
+            scrollback: ScrollbackConfig::default(),
+            automation: AutomationConfig::default(),
+            ui: UiConfig::default(),
+        }
+    }
+}
+
+/// A key chatshell doesn't know, left out so that the rest of the file
+/// still loads.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownKey {
+    /// Where it is, e.g. `hooks[2].strucutred`.
+    pub path: String,
+    /// The known key next to it that it's a likely typo of.
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.suggestion {
+            Some(suggestion) => write!(f, "{} (did you mean {}?)", self.path, suggestion),
+            None => f.write_str(&self.path),
+        }
+    }
+}
+
+/// Parses `text` as `T`, leaving out the keys `T` has no field for instead
+/// of failing on them, and tells which those were.
+pub fn from_str_lenient<T: DeserializeOwned + Serialize>(text: &str) -> Result<(T, Vec<UnknownKey>)> {
+    let mut ignored = Vec::new();
+    let parsed: Result<T, _> = serde_ignored::deserialize(toml::Deserializer::new(text), |path| ignored.push(key_path(&path)));
+    let parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err(e) if ignored.is_empty() => return Err(e.into()),
+        // Such as a required key, misspelled
+        Err(e) => {
+            let ignored: Vec<String> = ignored.iter().map(|path| join_path(path)).collect();
+            anyhow::bail!("{} (left out as unknown: {})", e.message().trim_end(), ignored.join(", "));
+        }
+    };
+    // The keys of a table it knows are the ones it is written back with
+    let known = serde_json::to_value(&parsed).unwrap_or_default();
+    let unknown = ignored
+        .iter()
+        .map(|path| {
+            let suggestion = match path.split_last() {
+                Some((PathPart::Key(key), table)) => {
+                    let table = table.iter().fold(&known, |value, part| match part {
+                        PathPart::Key(name) => &value[name.as_str()],
+                        PathPart::Index(i) => &value[*i],
+                    });
+                    let expected: Vec<String> = table.as_object().map_or(Vec::new(), |table| table.keys().cloned().collect());
+                    closest_key(key, &expected)
+                }
+                _ => None,
+            };
+            UnknownKey { path: join_path(path), suggestion }
+        })
+        .collect();
+    Ok((parsed, unknown))
+}
+
+enum PathPart {
+    Key(String),
+    Index(usize),
+}
+
+fn key_path(path: &serde_ignored::Path) -> Vec<PathPart> {
+    use serde_ignored::Path;
+    match path {
+        Path::Root => Vec::new(),
+        Path::Seq { parent, index } => {
+            let mut parts = key_path(parent);
+            parts.push(PathPart::Index(*index));
+            parts
+        }
+        Path::Map { parent, key } => {
+            let mut parts = key_path(parent);
+            parts.push(PathPart::Key(key.clone()));
+            parts
         }
+        Path::Some { parent } | Path::NewtypeStruct { parent } | Path::NewtypeVariant { parent } => key_path(parent),
     }
 }
 
+// Like `hooks[2].when`
+fn join_path(parts: &[PathPart]) -> String {
+    let mut path = String::new();
+    for part in parts {
+        match part {
+            PathPart::Key(name) if path.is_empty() => path.push_str(name),
+            PathPart::Key(name) => path.push_str(&format!(".{}", name)),
+            PathPart::Index(i) => path.push_str(&format!("[{}]", i)),
+        }
+    }
+    path
+}
+
+/// The one of `expected` that `key` is a likely typo of: a few edits away,
+/// ignoring case and `-` for `_`.
+fn closest_key(key: &str, expected: &[String]) -> Option<String> {
+    let normalized = key.to_lowercase().replace('-', "_");
+    let limit = (normalized.chars().count() / 3).max(1);
+    expected
+        .iter()
+        .map(|name| (edit_distance(&normalized, name), name))
+        .filter(|(distance, _)| *distance <= limit)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, name)| name.clone())
+}
+
+/// The edits it takes to turn `a` into `b`, counting a swap of two
+/// neighbouring characters as one.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut rows = vec![(0..=b.len()).collect::<Vec<_>>()];
+    for i in 1..=a.len() {
+        let mut row = vec![i; b.len() + 1];
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            row[j] = (rows[i - 1][j] + 1).min(row[j - 1] + 1).min(rows[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(rows[i - 2][j - 2] + 1);
+            }
+        }
+        rows.push(row);
+    }
+    rows[a.len()][b.len()]
+}
+
 impl Config {
     pub fn load_from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Config::load_with_unknown_keys(path).map(|(config, _)| config)
+    }
+
+    /// Loads the config at `path` like `load_from_file`, along with the
+    /// keys in it that were left out as unknown.
+    pub fn load_with_unknown_keys<P: AsRef<Path>>(path: P) -> Result<(Self, Vec<UnknownKey>)> {
         let content = fs::read_to_string(&path)
             .with_context(|| format!("Failed to read config file: {:?}", path.as_ref()))?;
         
-        let config: Config = toml::from_str(&content)
-            .with_context(|| "Failed to parse config file")?;
-        
-        Ok(config)
+        from_str_lenient(&content)
+            .with_context(|| "Failed to parse config file")
     }
 
     pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
@@ -68,6 +1312,18 @@ impl Config {
         Ok(())
     }
 
+    /// The startup commands for a session: the shell's own followed by those
+    /// of the selected profile.
+    pub fn startup_commands(&self, profile: Option<&str>) -> Result<Vec<String>> {
+        let mut commands = self.shell.startup_commands.clone();
+        if let Some(name) = profile {
+            let profile = self.profiles.get(name)
+                .with_context(|| format!("Unknown profile: {}", name))?;
+            commands.extend(profile.startup_commands.iter().cloned());
+        }
+        Ok(commands)
+    }
+
     pub fn get_default_config_path() -> String {
         if let Some(home) = std::env::var_os("HOME") {
             format!("{}/.config/chatshell/config.toml", home.to_string_lossy())
@@ -100,6 +1356,53 @@ impl Config {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_unknown_keys_are_left_out() {
+        let (config, unknown) = from_str_lenient::<Config>(concat!(
+            "[shell]\n",
+            "command = \"/bin/zsh\"\n",
+            "args = [\"-l\"]\n",
+            "startup_command = [\"ls\"]\n",
+            "[[hooks]]\n",
+            "name = \"git\"\n",
+            "key_combination = \"ctrl+g\"\n",
+            "action = \"cmd:git status\"\n",
+            "enabled = true\n",
+            "timout_ms = 500\n",
+            "[hooks.when]\n",
+            "at-prompt = true\n",
+            "[[hooks]]\n",
+            "name = \"log\"\n",
+            "action = \"cmd:git log\"\n",
+            "enabled = true\n",
+            "timout_ms = 500\n",
+            "[shells.fish]\n",
+            "command = \"fish\"\n",
+            "args = []\n",
+            "[colour]\n",
+            "theme = \"dark\"\n",
+        ))
+        .unwrap();
+        assert_eq!(config.shell.args, ["-l"]);
+        assert_eq!(config.hooks[0].name, "git");
+        assert_eq!(config.shells["fish"].command, "fish");
+        let found: Vec<(&str, Option<&str>)> = unknown.iter().map(|key| (key.path.as_str(), key.suggestion.as_deref())).collect();
+        assert_eq!(found.len(), 5, "{:?}", found);
+        assert!(found.contains(&("shell.startup_command", Some("startup_commands"))));
+        assert!(found.contains(&("hooks[0].timout_ms", Some("timeout_ms"))));
+        assert!(found.contains(&("hooks[1].timout_ms", Some("timeout_ms"))));
+        assert!(found.contains(&("hooks[0].when.at-prompt", Some("at_prompt"))));
+        assert!(found.contains(&("colour", None)));
+
+        // Other mistakes still fail, pointing at a typo that may be why
+        let error = from_str_lenient::<Config>("[shell]\ncomand = \"/bin/zsh\"\n").unwrap_err().to_string();
+        assert!(error.contains("missing field `command`") && error.contains("left out as unknown: shell.comand"), "{}", error);
+        assert!(from_str_lenient::<Config>("[shell]\ncommand = 1\n").is_err());
+        let saved = toml::to_string(&Config { hooks: crate::hooks::create_default_hooks(), ..Config::default() }).unwrap();
+        assert_eq!(from_str_lenient::<Config>(&saved).unwrap().1, []);
+        assert_eq!(edit_distance("strucutred", "structured"), 1);
+    }
+
     #[test]
     fn test_default_config() {
         let config = Config::default();
@@ -115,4 +1418,208 @@ mod tests {
         
         assert_eq!(config.shell.command, deserialized.shell.command);
     }
+
+    #[test]
+    fn test_session_env_prefers_env_file() {
+        let mut env_file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut env_file, b"TOKEN=new\nREGION=eu\n").unwrap();
+
+        let shell = ShellConfig {
+            env: Some([("TOKEN".to_string(), "old".to_string())].into()),
+            env_file: Some(env_file.path().to_string_lossy().to_string()),
+            ..Default::default()
+        };
+
+        let vars = shell.session_env().unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                ("TOKEN".to_string(), "new".to_string()),
+                ("REGION".to_string(), "eu".to_string()),
+            ]
+        );
+
+        let shell = ShellConfig {
+            env: Some([("X; rm -rf ~".to_string(), "1".to_string())].into()),
+            ..Default::default()
+        };
+        assert!(shell.session_env().is_err());
+    }
+
+    #[test]
+    fn test_profile_startup_commands() {
+        let mut config = Config::default();
+        config.shell.startup_commands = vec!["source ~/.work_env".to_string()];
+        config.profiles.insert(
+            "projects".to_string(),
+            ProfileConfig { startup_commands: vec!["cd ~/projects".to_string()] },
+        );
+
+        assert_eq!(config.startup_commands(None).unwrap().len(), 1);
+        assert_eq!(
+            config.startup_commands(Some("projects")).unwrap(),
+            vec!["source ~/.work_env".to_string(), "cd ~/projects".to_string()]
+        );
+        assert!(config.startup_commands(Some("missing")).is_err());
+    }
+
+    #[test]
+    fn test_named_shells() {
+        let config: Config = toml::from_str(
+            "hooks = []\n[shell]\ncommand = \"/bin/bash\"\nargs = []\n\n\
+             [shells.python]\ncommand = \"python3\"\nargs = [\"-q\"]\n\n\
+             [shells.nu]\ncommand = \"nu\"\nargs = []\n",
+        )
+        .unwrap();
+
+        let names: Vec<&String> = config.shells.keys().collect();
+        assert_eq!(names, vec!["nu", "python"]);
+        assert_eq!(config.shells["python"].args, vec!["-q".to_string()]);
+    }
+
+    #[test]
+    fn test_exit_policy_parsing() {
+        let shell: ShellConfig = toml::from_str(
+            "command = \"/bin/sh\"\nargs = []\non_exit = \"respawn\"\n",
+        )
+        .unwrap();
+        assert_eq!(shell.on_exit, ExitPolicy::Respawn);
+    }
+
+    #[test]
+    fn test_hook_output_parsing() {
+        let hook = |output: &str| {
+            toml::from_str::<HookConfig>(&format!(
+                "name = \"h\"\nkey_combination = \"ctrl+g\"\naction = \"cmd:date\"\nenabled = true\n{}",
+                output
+            ))
+        };
+        assert_eq!(hook("").unwrap().output, HookOutput::Popup);
+        assert_eq!(hook("output = \"inject\"").unwrap().output, HookOutput::Inject);
+        assert_eq!(hook("output = \"toast\"").unwrap().output, HookOutput::Toast);
+        assert_eq!(hook("output = \"pane\"").unwrap().output, HookOutput::Pane);
+        assert_eq!(hook("output = \"file:~/hooks.log\"").unwrap().output, HookOutput::File("~/hooks.log".to_string()));
+        assert!(hook("output = \"file:\"").is_err());
+        assert!(hook("output = \"speaker\"").is_err());
+        assert_eq!(String::from(HookOutput::File("/tmp/x".to_string())), "file:/tmp/x");
+    }
+
+    #[test]
+    fn test_hook_conditions() {
+        let when: HookCondition = toml::from_str(r#"
+            cwd = "~/src/**"
+            env = ["AWS_PROFILE"]
+            time = "22:00-06:00"
+        "#).unwrap();
+        assert_eq!(when.cwd.as_deref(), Some("~/src/**"));
+        assert_eq!(when.env, vec!["AWS_PROFILE"]);
+        let night = when.time.unwrap();
+        let at = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+        assert!(night.contains(at("23:30")));
+        assert!(night.contains(at("05:59")));
+        assert!(!night.contains(at("06:00")));
+        assert!(!night.contains(at("12:00")));
+        assert!(TimeRange::try_from("09:00-17:30".to_string()).unwrap().contains(at("09:00")));
+        assert_eq!(String::from(night), "22:00-06:00");
+        assert!(toml::from_str::<HookCondition>("time = \"9am-5pm\"").is_err());
+
+        // Unset conditions are left out of a saved config
+        assert_eq!(toml::to_string(&HookCondition::default()).unwrap(), "at_prompt = false\n");
+    }
+
+    #[test]
+    fn test_timed_hooks() {
+        let hook: HookConfig = toml::from_str("name = \"status\"\naction = \"cmd:git status\"\nenabled = true\ninterval = \"30s\"\nidle = \"5m\"\n").unwrap();
+        assert_eq!(hook.interval, Some(Interval(Duration::from_secs(30))));
+        assert_eq!(hook.idle, Some(Interval(Duration::from_secs(300))));
+        assert!(toml::from_str::<HookConfig>("name = \"h\"\naction = \"x\"\nenabled = true\nidle = \"soon\"\n").is_err());
+        assert_eq!(String::from(Interval(Duration::from_secs(300))), "5m");
+        assert_eq!(String::from(Interval(Duration::from_millis(1500))), "1500ms");
+        assert_eq!(String::from(Interval(Duration::from_secs(7200))), "2h");
+    }
+
+    #[test]
+    fn test_confirmed_hooks() {
+        let hook: HookConfig = toml::from_str("name = \"push\"\naction = \"cmd:git push --force\"\nenabled = true\nconfirm = \"Force push?\"\n").unwrap();
+        assert_eq!(hook.confirm.as_deref(), Some("Force push?"));
+        // Hooks that don't ask don't say so when saved
+        let hook = HookConfig { confirm: None, ..hook };
+        assert!(!toml::to_string(&hook).unwrap().contains("confirm"));
+    }
+
+    #[test]
+    fn test_on_select() {
+        let hook: HookConfig = toml::from_str("name = \"branches\"\naction = \"cmd:./branches\"\nenabled = true\non_select = { command = \"git checkout\" }\n").unwrap();
+        let on_select = hook.on_select.unwrap();
+        assert_eq!(on_select.command_for("it's"), ("git checkout 'it'\\''s'".to_string(), None));
+        let on_select = OnSelect { pass: SelectionPass::Stdin, ..on_select };
+        assert_eq!(on_select.command_for("main"), ("git checkout".to_string(), Some("main\n".to_string())));
+        assert!(toml::from_str::<OnSelect>("command = \"x\"\npass = \"env\"\n").is_err());
+    }
+
+    #[test]
+    fn test_set_hook_enabled() {
+        let text = "# My hooks\n[[hooks]]\nname = \"ask\"\nkey_combination = \"ctrl+g\"\naction = \"builtin:ask\"\nenabled = true # on\n\n[[hooks]]\nname = \"time\"\nkey_combination = \"ctrl+t\"\nenabled = false\naction = \"fn:show_time\"\n\n[hooks.when]\nenabled = true\n\n[llm]\nname = \"time\"\nenabled = false\n";
+        let changed = set_hook_enabled(text, "time", true).unwrap();
+        assert_eq!(changed, text.replace("enabled = false\naction", "enabled = true\naction"));
+        let changed = set_hook_enabled(&changed, "ask", false).unwrap();
+        assert!(changed.starts_with("# My hooks\n[[hooks]]\nname = \"ask\"\nkey_combination = \"ctrl+g\"\naction = \"builtin:ask\"\nenabled = false\n"));
+        let config: toml::Table = changed.parse().unwrap();
+        assert_eq!(config["hooks"][1]["enabled"].as_bool(), Some(true));
+        assert_eq!(config["llm"]["enabled"].as_bool(), Some(false));
+        assert_eq!(set_hook_enabled(text, "missing", true), None);
+    }
+
+    #[test]
+    fn test_env_policy() {
+        let shell: ShellConfig = toml::from_str("command = \"/bin/sh\"\nargs = []\n").unwrap();
+        assert_eq!(shell.env_policy, EnvPolicy::default());
+        assert!(shell.env_policy.keeps("AWS_SECRET_ACCESS_KEY"));
+
+        let shell: ShellConfig = toml::from_str(
+            "command = \"/bin/sh\"\nargs = []\n[env_policy]\nremove = [\"AWS_*\", \"PYTHONPATH\"]\n",
+        )
+        .unwrap();
+        assert!(!shell.env_policy.keeps("AWS_SECRET_ACCESS_KEY"));
+        assert!(!shell.env_policy.keeps("PYTHONPATH"));
+        assert!(shell.env_policy.keeps("PYTHONHOME"));
+
+        let shell: ShellConfig = toml::from_str(
+            "command = \"/bin/sh\"\nargs = []\n[env_policy]\ninherit = [\"HOME\", \"TERM\", \"LC_*\"]\nremove = [\"LC_ALL\"]\n",
+        )
+        .unwrap();
+        assert!(shell.env_policy.keeps("TERM"));
+        assert!(shell.env_policy.keeps("LC_TIME"));
+        assert!(!shell.env_policy.keeps("LC_ALL"));
+        assert!(!shell.env_policy.keeps("PATH"));
+
+        let shell: ShellConfig = toml::from_str("command = \"/bin/sh\"\nargs = []\n[env_policy]\ninherit = \"none\"\n").unwrap();
+        assert!(!shell.env_policy.keeps("HOME"));
+        assert!(toml::from_str::<ShellConfig>("command = \"/bin/sh\"\nargs = []\n[env_policy]\ninherit = \"some\"\n").is_err());
+    }
+
+    #[test]
+    fn test_repl_kind_override() {
+        let shell: ShellConfig = toml::from_str(
+            "command = \"/usr/bin/env\"\nargs = [\"python3\"]\nrepl = \"python\"\n",
+        )
+        .unwrap();
+        assert_eq!(shell.repl_kind(), Some(ReplKind::Python));
+
+        let shell = ShellConfig { command: "psql".to_string(), ..Default::default() };
+        assert_eq!(shell.repl_kind(), Some(ReplKind::Psql));
+        assert_eq!(ShellConfig::default().repl_kind(), None);
+    }
+
+    #[test]
+    fn test_terminal_section_is_optional() {
+        let config: Config = toml::from_str(
+            "hooks = []\n[shell]\ncommand = \"/bin/sh\"\nargs = []\n\n[terminal.queries]\nenq = \"spoof\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(config.shell.on_exit, ExitPolicy::Close);
+        assert_eq!(config.terminal.queries.enq, QueryPolicy::Spoof);
+        assert_eq!(config.terminal.queries.primary_da, QueryPolicy::Forward);
+    }
 }
\ No newline at end of file
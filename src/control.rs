@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::UnixListener;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::config::HookEvent;
+
+/// Environment variable exported to the wrapped shell so that `chatshell`
+/// commands run inside it can find the running instance.
+pub const SOCKET_ENV_VAR: &str = "CHATSHELL_SOCKET";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    SetEnv { key: String, value: String },
+    /// Marks the current point of the session; numbered if unnamed.
+    Bookmark { name: Option<String> },
+    /// An expect script of what was typed between two bookmarks.
+    Export { from: Option<String>, to: Option<String> },
+    /// Runs the hooks bound to a key combination as if it had been pressed.
+    Trigger { key: String },
+    /// Runs the hooks on an event as if the shell had marked it.
+    TriggerEvent { event: HookEvent, command_line: Option<String>, exit_code: Option<i32> },
+    /// Binds a key to an action for this session only, or for a while.
+    Bind { key: String, action: String, lifetime_secs: Option<u64> },
+    /// Saves what can be picked up again of the session under a name.
+    SaveState { name: String },
+    /// Brings back a state saved under a name.
+    LoadState { name: String },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ControlResponse {
+    pub ok: bool,
+    pub message: Option<String>,
+}
+
+impl ControlResponse {
+    pub fn ok() -> Self {
+        ControlResponse { ok: true, message: None }
+    }
+
+    pub fn with_message(message: impl Into<String>) -> Self {
+        ControlResponse {
+            ok: true,
+            message: Some(message.into()),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        ControlResponse {
+            ok: false,
+            message: Some(message.into()),
+        }
+    }
+}
+
+/// A request received on the control socket, waiting for the main loop to
+/// handle it and send back a response.
+#[derive(Debug)]
+pub struct ControlMessage {
+    pub request: ControlRequest,
+    pub reply: oneshot::Sender<ControlResponse>,
+}
+
+pub fn default_socket_path() -> PathBuf {
+    let pid = std::process::id();
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join(format!("chatshell-{}.sock", pid)),
+        None => {
+            let uid = nix::unistd::getuid();
+            std::env::temp_dir().join(format!("chatshell-{}-{}.sock", uid, pid))
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ControlServer {
+    path: PathBuf,
+}
+
+impl ControlServer {
+    /// Binds the socket and starts accepting connections; requests are
+    /// delivered on the returned channel.
+    pub fn start(path: PathBuf) -> Result<(Self, mpsc::UnboundedReceiver<ControlMessage>)> {
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("Failed to bind control socket {:?}", path))?;
+        let (tx, rx) = mpsc::unbounded_channel();
+
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let tx = tx.clone();
+                tokio::spawn(async move {
+                    let _ = Self::serve_connection(stream, tx).await;
+                });
+            }
+        });
+
+        Ok((ControlServer { path }, rx))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    async fn serve_connection(
+        stream: tokio::net::UnixStream,
+        tx: mpsc::UnboundedSender<ControlMessage>,
+    ) -> Result<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = tokio::io::BufReader::new(reader).lines();
+
+        while let Some(line) = lines.next_line().await? {
+            let response = match serde_json::from_str::<ControlRequest>(&line) {
+                Ok(request) => {
+                    let (reply, response) = oneshot::channel();
+                    tx.send(ControlMessage { request, reply })?;
+                    response.await.unwrap_or_else(|_| ControlResponse::error("chatshell is shutting down"))
+                }
+                Err(e) => ControlResponse::error(format!("Invalid request: {}", e)),
+            };
+
+            let mut encoded = serde_json::to_string(&response)?;
+            encoded.push('\n');
+            writer.write_all(encoded.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for ControlServer {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Sends one request to a running instance and waits for its response.
+pub fn send_request<P: AsRef<Path>>(path: P, request: &ControlRequest) -> Result<ControlResponse> {
+    let mut stream = UnixStream::connect(&path)
+        .with_context(|| format!("Failed to connect to chatshell at {:?}", path.as_ref()))?;
+
+    let mut encoded = serde_json::to_string(request)?;
+    encoded.push('\n');
+    stream.write_all(encoded.as_bytes())
+        .with_context(|| "Failed to send control request")?;
+
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)
+        .with_context(|| "Failed to read control response")?;
+
+    serde_json::from_str(&line).with_context(|| "Invalid control response")
+}
+
+/// Resolves the socket of the instance to talk to: an explicit path wins,
+/// otherwise the one advertised to the wrapped shell.
+pub fn resolve_socket_path(explicit: Option<&String>) -> Result<PathBuf> {
+    if let Some(path) = explicit {
+        return Ok(PathBuf::from(path));
+    }
+    std::env::var_os(SOCKET_ENV_VAR)
+        .map(PathBuf::from)
+        .ok_or_else(|| anyhow::anyhow!(
+            "{} is not set; run this inside a chatshell session or pass --socket",
+            SOCKET_ENV_VAR
+        ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_request_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("control.sock");
+        let (_server, mut rx) = ControlServer::start(path.clone()).unwrap();
+
+        tokio::spawn(async move {
+            while let Some(message) = rx.recv().await {
+                let _ = message.reply.send(ControlResponse::ok());
+            }
+        });
+
+        let request = ControlRequest::SetEnv {
+            key: "TOKEN".to_string(),
+            value: "secret".to_string(),
+        };
+        let response = tokio::task::spawn_blocking(move || send_request(&path, &request))
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(response.ok);
+    }
+}
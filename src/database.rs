@@ -0,0 +1,276 @@
+use crate::repl::ReplKind;
+
+/// A query found in the session transcript, with what it printed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryRun {
+    pub query: String,
+    pub error: Option<String>,
+    pub output: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PromptLine<'a> {
+    Primary(&'a str),
+    Continuation(&'a str),
+}
+
+/// Splits a transcript line into its prompt and the text typed after it.
+fn parse_prompt(kind: ReplKind, line: &str) -> Option<PromptLine<'_>> {
+    match kind {
+        ReplKind::Psql => {
+            // %/%R%x%# : dbname, state (= ^ ! primary, - * ' " $ ( continued),
+            // optional transaction status, then # or >
+            let end = line.find("# ").into_iter().chain(line.find("> ")).min()?;
+            let prefix = &line[..end];
+            let text = &line[end + 2..];
+            let mut chars = prefix.chars().rev();
+            let mut state = chars.next()?;
+            if matches!(state, '*' | '!' | '?') {
+                if let Some(before) = chars.clone().next().filter(|c| "=^!-'\"$(".contains(*c)) {
+                    state = before;
+                    chars.next();
+                }
+            }
+            let name: String = chars.collect();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                return None;
+            }
+            match state {
+                '=' | '^' | '!' => Some(PromptLine::Primary(text)),
+                '-' | '*' | '\'' | '"' | '$' | '(' => Some(PromptLine::Continuation(text)),
+                _ => None,
+            }
+        }
+        ReplKind::Mysql => {
+            if let Some(text) = line.strip_prefix("mysql> ") {
+                return Some(PromptLine::Primary(text));
+            }
+            if line.starts_with("MariaDB [") {
+                return line.find("]> ").map(|i| PromptLine::Primary(&line[i + 3..]));
+            }
+            let trimmed = line.trim_start();
+            ["-> ", "'> ", "\"> ", "`> ", "/*> "]
+                .iter()
+                .find_map(|prompt| trimmed.strip_prefix(prompt))
+                .filter(|_| trimmed.len() < line.len())
+                .map(PromptLine::Continuation)
+        }
+        ReplKind::Sqlite => {
+            if let Some(text) = line.strip_prefix("sqlite> ") {
+                return Some(PromptLine::Primary(text));
+            }
+            line.trim_start().strip_prefix("...> ").map(PromptLine::Continuation)
+        }
+        ReplKind::Python | ReplKind::Node => None,
+    }
+}
+
+fn is_error_line(kind: ReplKind, line: &str) -> bool {
+    match kind {
+        ReplKind::Psql => line.starts_with("ERROR:") || line.starts_with("FATAL:"),
+        ReplKind::Mysql => line.starts_with("ERROR "),
+        ReplKind::Sqlite => {
+            line.starts_with("Error:") || line.starts_with("Parse error") || line.starts_with("Runtime error")
+        }
+        ReplKind::Python | ReplKind::Node => false,
+    }
+}
+
+/// Finds the last SQL statement typed at the prompt (skipping backslash and
+/// dot meta-commands) and the output and error that followed it.
+pub fn last_query(kind: ReplKind, lines: &[String]) -> Option<QueryRun> {
+    let prompts: Vec<Option<PromptLine>> = lines.iter().map(|line| parse_prompt(kind, line)).collect();
+
+    let start = prompts.iter().rposition(|prompt| match prompt {
+        Some(PromptLine::Primary(text)) => {
+            let text = text.trim();
+            !text.is_empty() && !text.starts_with('\\') && !text.starts_with('.')
+        }
+        _ => false,
+    })?;
+
+    let mut query = Vec::new();
+    let mut next = start;
+    while let Some(Some(PromptLine::Primary(text) | PromptLine::Continuation(text))) = prompts.get(next) {
+        if next > start && matches!(prompts[next], Some(PromptLine::Primary(_))) {
+            break;
+        }
+        query.push(text.trim_end());
+        next += 1;
+    }
+
+    let output: Vec<String> = lines[next..]
+        .iter()
+        .zip(&prompts[next..])
+        .take_while(|(_, prompt)| !matches!(prompt, Some(PromptLine::Primary(_))))
+        .map(|(line, _)| line.clone())
+        .collect();
+    let error = output
+        .iter()
+        .position(|line| is_error_line(kind, line))
+        .map(|i| output[i..].join("\n").trim_end().to_string());
+
+    Some(QueryRun {
+        query: query.join("\n"),
+        error,
+        output,
+    })
+}
+
+/// Table names that follow FROM, JOIN, UPDATE, INTO or TABLE in a query.
+pub fn referenced_tables(query: &str) -> Vec<String> {
+    const KEYWORDS: [&str; 5] = ["from", "join", "update", "into", "table"];
+    let words: Vec<&str> = query.split_whitespace().collect();
+    let mut tables: Vec<String> = Vec::new();
+
+    for pair in words.windows(2) {
+        if !KEYWORDS.contains(&pair[0].to_lowercase().as_str()) {
+            continue;
+        }
+        let name = pair[1].trim_matches(|c: char| matches!(c, ';' | ',' | '(' | ')' | '"' | '`'));
+        let is_identifier = !name.is_empty()
+            && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+            && !name.starts_with(|c: char| c.is_ascii_digit());
+        if is_identifier && !tables.iter().any(|t| t == name) {
+            tables.push(name.to_string());
+        }
+    }
+    tables
+}
+
+/// Commands that print the definition of `tables`, or list all tables when
+/// none are known.
+pub fn schema_commands(kind: ReplKind, tables: &[String]) -> Vec<String> {
+    if tables.is_empty() {
+        return match kind {
+            ReplKind::Psql => vec!["\\d".to_string()],
+            ReplKind::Mysql => vec!["SHOW TABLES;".to_string()],
+            ReplKind::Sqlite => vec![".tables".to_string()],
+            ReplKind::Python | ReplKind::Node => Vec::new(),
+        };
+    }
+    tables
+        .iter()
+        .filter_map(|table| match kind {
+            ReplKind::Psql => Some(format!("\\d {}", table)),
+            ReplKind::Mysql => Some(format!("DESCRIBE {};", table)),
+            ReplKind::Sqlite => Some(format!(".schema {}", table)),
+            ReplKind::Python | ReplKind::Node => None,
+        })
+        .collect()
+}
+
+/// Parses a psql (aligned) or mysql (boxed) result table into rows, the
+/// first of which is the header.
+pub fn parse_result_table(output: &[String]) -> Option<Vec<Vec<String>>> {
+    let split = |line: &str| -> Vec<String> {
+        line.split('|').map(|cell| cell.trim().to_string()).collect()
+    };
+
+    // mysql: +----+ borders around | cell | rows
+    if output.iter().any(|line| line.starts_with("+-")) {
+        let rows: Vec<Vec<String>> = output
+            .iter()
+            .filter_map(|line| line.trim_end().strip_prefix('|')?.strip_suffix('|'))
+            .map(split)
+            .collect();
+        return if rows.is_empty() { None } else { Some(rows) };
+    }
+
+    // psql: header, ---+--- separator, rows, then "(N rows)"
+    let separator = output.iter().position(|line| {
+        let line = line.trim();
+        line.contains('-') && line.chars().all(|c| c == '-' || c == '+')
+    })?;
+    let header = output.get(separator.checked_sub(1)?)?;
+    let mut rows = vec![split(header)];
+    rows.extend(
+        output[separator + 1..]
+            .iter()
+            .take_while(|line| !line.trim().is_empty() && !line.starts_with('('))
+            .map(|line| split(line)),
+    );
+    Some(rows)
+}
+
+/// Lays rows out as an aligned table with a rule under the header.
+pub fn format_table(rows: &[Vec<String>]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths: Vec<usize> = (0..columns)
+        .map(|i| {
+            rows.iter()
+                .filter_map(|row| row.get(i))
+                .map(|cell| cell.chars().count())
+                .max()
+                .unwrap_or(0)
+        })
+        .collect();
+
+    let render = |row: &Vec<String>| -> String {
+        widths
+            .iter()
+            .enumerate()
+            .map(|(i, width)| {
+                let cell = row.get(i).map(String::as_str).unwrap_or("");
+                format!("{:<width$}", cell, width = *width)
+            })
+            .collect::<Vec<_>>()
+            .join(" | ")
+            .trim_end()
+            .to_string()
+    };
+
+    let mut lines = Vec::new();
+    if let Some((header, body)) = rows.split_first() {
+        lines.push(render(header));
+        lines.push(widths.iter().map(|w| "-".repeat(*w)).collect::<Vec<_>>().join("-+-"));
+        lines.extend(body.iter().map(render));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_last_psql_query_and_error() {
+        let transcript = lines(
+            "shop=# SELECT 1;\n ?column?\n----------\n        1\n(1 row)\n\n\
+             shop=# \\dt\nDid not find any relations.\n\
+             shop=*# SELECT name\nshop-*# FROM custmers;\n\
+             ERROR:  relation \"custmers\" does not exist\nLINE 2: FROM custmers;\n             ^",
+        );
+        let run = last_query(ReplKind::Psql, &transcript).unwrap();
+        assert_eq!(run.query, "SELECT name\nFROM custmers;");
+        assert_eq!(
+            run.error.unwrap(),
+            "ERROR:  relation \"custmers\" does not exist\nLINE 2: FROM custmers;\n             ^"
+        );
+        assert_eq!(referenced_tables(&run.query), vec!["custmers"]);
+    }
+
+    #[test]
+    fn test_last_mysql_query() {
+        let transcript = lines("mysql> select *\n    -> from t;\n+----+\n| id |\n+----+\n|  1 |\n+----+\n1 row in set (0.00 sec)");
+        let run = last_query(ReplKind::Mysql, &transcript).unwrap();
+        assert_eq!(run.query, "select *\nfrom t;");
+        assert_eq!(run.error, None);
+        assert_eq!(
+            parse_result_table(&run.output).unwrap(),
+            vec![vec!["id".to_string()], vec!["1".to_string()]]
+        );
+    }
+
+    #[test]
+    fn test_psql_result_table() {
+        let output = lines(" id | name\n----+-------\n  1 | alice\n  2 | bob\n(2 rows)");
+        let rows = parse_result_table(&output).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(format_table(&rows), "id | name\n---+------\n1  | alice\n2  | bob");
+    }
+}
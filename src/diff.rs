@@ -0,0 +1,218 @@
+use std::collections::BTreeMap;
+
+/// One line of a line-by-line diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiffLine {
+    Same(String),
+    Removed(String),
+    Added(String),
+    /// Unchanged lines left out of a condensed diff.
+    Skipped(usize),
+}
+
+/// How a variable differs between two environments.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EnvChange {
+    Added { key: String, value: String },
+    Removed { key: String, value: String },
+    Changed { key: String, old: String, new: String },
+}
+
+/// Longer inputs are compared as a single replaced block rather than paying
+/// for the quadratic table.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// Diffs two sequences of lines by their longest common subsequence.
+pub fn diff_lines(old: &[String], new: &[String]) -> Vec<DiffLine> {
+    // Matching ends don't need the table
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let old_mid = &old[prefix..old.len() - suffix];
+    let new_mid = &new[prefix..new.len() - suffix];
+
+    let mut diff: Vec<DiffLine> = old[..prefix].iter().cloned().map(DiffLine::Same).collect();
+
+    if old_mid.len() * new_mid.len() > MAX_DIFF_CELLS {
+        diff.extend(old_mid.iter().cloned().map(DiffLine::Removed));
+        diff.extend(new_mid.iter().cloned().map(DiffLine::Added));
+    } else {
+        // lcs[i][j]: length of the LCS of old_mid[i..] and new_mid[j..]
+        let (n, m) = (old_mid.len(), new_mid.len());
+        let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+        for i in (0..n).rev() {
+            for j in (0..m).rev() {
+                lcs[i][j] = if old_mid[i] == new_mid[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+
+        let (mut i, mut j) = (0, 0);
+        while i < n || j < m {
+            if i < n && j < m && old_mid[i] == new_mid[j] {
+                diff.push(DiffLine::Same(old_mid[i].clone()));
+                i += 1;
+                j += 1;
+            } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+                diff.push(DiffLine::Removed(old_mid[i].clone()));
+                i += 1;
+            } else {
+                diff.push(DiffLine::Added(new_mid[j].clone()));
+                j += 1;
+            }
+        }
+    }
+
+    diff.extend(old[old.len() - suffix..].iter().cloned().map(DiffLine::Same));
+    diff
+}
+
+/// Keeps `context` unchanged lines around each change and replaces the
+/// rest with `Skipped` markers.
+pub fn condense(diff: Vec<DiffLine>, context: usize) -> Vec<DiffLine> {
+    let changed: Vec<usize> = diff
+        .iter()
+        .enumerate()
+        .filter(|(_, line)| matches!(line, DiffLine::Added(_) | DiffLine::Removed(_)))
+        .map(|(i, _)| i)
+        .collect();
+    let near_change = |i: usize| changed.iter().any(|&c| c.abs_diff(i) <= context);
+
+    let mut condensed = Vec::new();
+    let mut skipped = 0;
+    for (i, line) in diff.into_iter().enumerate() {
+        if matches!(line, DiffLine::Same(_)) && !near_change(i) {
+            skipped += 1;
+            continue;
+        }
+        if skipped > 0 {
+            condensed.push(DiffLine::Skipped(skipped));
+            skipped = 0;
+        }
+        condensed.push(line);
+    }
+    if skipped > 0 {
+        condensed.push(DiffLine::Skipped(skipped));
+    }
+    condensed
+}
+
+pub fn has_changes(diff: &[DiffLine]) -> bool {
+    diff.iter().any(|line| matches!(line, DiffLine::Added(_) | DiffLine::Removed(_)))
+}
+
+/// The variables `new` adds, drops or changes compared to `old`, by name.
+pub fn diff_env(old: &BTreeMap<String, String>, new: &BTreeMap<String, String>) -> Vec<EnvChange> {
+    let mut changes = Vec::new();
+    for (key, value) in old {
+        match new.get(key) {
+            None => changes.push(EnvChange::Removed { key: key.clone(), value: value.clone() }),
+            Some(new) if new != value => {
+                changes.push(EnvChange::Changed { key: key.clone(), old: value.clone(), new: new.clone() })
+            }
+            Some(_) => {}
+        }
+    }
+    for (key, value) in new {
+        if !old.contains_key(key) {
+            changes.push(EnvChange::Added { key: key.clone(), value: value.clone() });
+        }
+    }
+    changes.sort_by(|a, b| a.key().cmp(b.key()));
+    changes
+}
+
+impl EnvChange {
+    pub fn key(&self) -> &str {
+        match self {
+            EnvChange::Added { key, .. } | EnvChange::Removed { key, .. } | EnvChange::Changed { key, .. } => key,
+        }
+    }
+}
+
+/// The entries of a `:`-separated list like `PATH` that `new` drops and
+/// adds compared to `old`, or None when either value isn't such a list.
+pub fn diff_list(old: &str, new: &str) -> Option<(Vec<String>, Vec<String>)> {
+    if !old.contains(':') && !new.contains(':') {
+        return None;
+    }
+    let old: Vec<&str> = old.split(':').collect();
+    let new: Vec<&str> = new.split(':').collect();
+    let removed = old.iter().filter(|entry| !new.contains(entry)).map(|entry| entry.to_string()).collect();
+    let added = new.iter().filter(|entry| !old.contains(entry)).map(|entry| entry.to_string()).collect();
+    Some((removed, added))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_diff_lines() {
+        let diff = diff_lines(&lines("a\nb\nc\nd"), &lines("a\nc\nx\nd"));
+        assert_eq!(
+            diff,
+            vec![
+                DiffLine::Same("a".to_string()),
+                DiffLine::Removed("b".to_string()),
+                DiffLine::Same("c".to_string()),
+                DiffLine::Added("x".to_string()),
+                DiffLine::Same("d".to_string()),
+            ]
+        );
+        assert!(!has_changes(&diff_lines(&lines("a\nb"), &lines("a\nb"))));
+    }
+
+    #[test]
+    fn test_condense_keeps_context() {
+        let old = lines("1\n2\n3\n4\n5\n6\n7");
+        let new = lines("1\n2\n3\n4\n5\n6\nseven");
+        let condensed = condense(diff_lines(&old, &new), 1);
+        assert_eq!(
+            condensed,
+            vec![
+                DiffLine::Skipped(5),
+                DiffLine::Same("6".to_string()),
+                DiffLine::Removed("7".to_string()),
+                DiffLine::Added("seven".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_diff_env() {
+        let env = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect()
+        };
+        let old = env(&[("HOME", "/home/me"), ("LANG", "C"), ("PATH", "/usr/bin:/bin")]);
+        let new = env(&[("HOME", "/home/me"), ("PATH", "/opt/bin:/usr/bin"), ("VIRTUAL_ENV", "/venv")]);
+        assert_eq!(
+            diff_env(&old, &new),
+            vec![
+                EnvChange::Removed { key: "LANG".to_string(), value: "C".to_string() },
+                EnvChange::Changed {
+                    key: "PATH".to_string(),
+                    old: "/usr/bin:/bin".to_string(),
+                    new: "/opt/bin:/usr/bin".to_string()
+                },
+                EnvChange::Added { key: "VIRTUAL_ENV".to_string(), value: "/venv".to_string() },
+            ]
+        );
+        assert_eq!(
+            diff_list("/usr/bin:/bin", "/opt/bin:/usr/bin"),
+            Some((vec!["/bin".to_string()], vec!["/opt/bin".to_string()]))
+        );
+        assert_eq!(diff_list("C", "en_US.UTF-8"), None);
+    }
+}
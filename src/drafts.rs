@@ -0,0 +1,121 @@
+use anyhow::{Context, Result};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+/// How often text typed into an input popup is saved.
+pub const AUTOSAVE_INTERVAL: Duration = Duration::from_secs(3);
+
+/// Where drafts are kept. They only need to outlive a crashed session, so
+/// they go with the other runtime files.
+pub fn drafts_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("chatshell-drafts"),
+        None => {
+            let uid = nix::unistd::getuid();
+            std::env::temp_dir().join(format!("chatshell-drafts-{}", uid))
+        }
+    }
+}
+
+/// `text` lowercased, with anything but letters and digits as single
+/// dashes, to name files by.
+pub fn slug(text: &str) -> String {
+    let slug: String = text
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    slug.split('-').filter(|part| !part.is_empty()).collect::<Vec<_>>().join("-")
+}
+
+/// The text of an input popup, saved while it is typed so that it survives
+/// the terminal or chatshell dying. Each kind of popup has one draft.
+#[derive(Debug)]
+pub struct Draft {
+    path: PathBuf,
+    saved: String,
+    saved_at: Option<Instant>,
+}
+
+impl Draft {
+    /// The draft of the popup with `title` and `label`.
+    pub fn new(dir: &Path, title: &str, label: &str) -> Self {
+        Draft {
+            path: dir.join(format!("{}--{}.txt", slug(title), slug(label))),
+            saved: String::new(),
+            saved_at: None,
+        }
+    }
+
+    /// Text left from a popup that wasn't closed, with when it was saved.
+    pub fn load(&self) -> Option<(String, SystemTime)> {
+        let text = fs::read_to_string(&self.path).ok().filter(|text| !text.is_empty())?;
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        Some((text, modified))
+    }
+
+    /// Saves `text` if it changed and the last save was a while ago.
+    pub fn autosave(&mut self, text: &str, now: Instant) -> Result<()> {
+        let due = self.saved_at.is_none_or(|at| now.duration_since(at) >= AUTOSAVE_INTERVAL);
+        if text == self.saved || !due {
+            return Ok(());
+        }
+        self.saved_at = Some(now);
+        self.save(text)
+    }
+
+    fn save(&mut self, text: &str) -> Result<()> {
+        self.saved = text.to_string();
+        if text.is_empty() {
+            self.discard();
+            return Ok(());
+        }
+        if let Some(dir) = self.path.parent() {
+            DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(dir)
+                .with_context(|| format!("Failed to create drafts directory: {}", dir.display()))?;
+        }
+        // Drafts may hold anything typed, so only the user can read them
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .with_context(|| format!("Failed to save draft: {}", self.path.display()))
+    }
+
+    /// Removes the draft once the popup is closed.
+    pub fn discard(&self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draft_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let start = Instant::now();
+        let mut draft = Draft::new(dir.path(), "Ask", "What do you need?");
+        assert!(draft.path.ends_with("ask--what-do-you-need.txt"));
+
+        draft.autosave("why is", start).unwrap();
+        draft.autosave("why is the build red", start + Duration::from_secs(1)).unwrap();
+        let reopened = Draft::new(dir.path(), "Ask", "What do you need?");
+        assert_eq!(reopened.load().unwrap().0, "why is");
+
+        draft.autosave("why is the build red", start + AUTOSAVE_INTERVAL).unwrap();
+        assert_eq!(reopened.load().unwrap().0, "why is the build red");
+
+        reopened.discard();
+        assert!(draft.load().is_none());
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::Local;
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::i18n::tr;
+
+/// An error that happened while the session runs, by the part of chatshell
+/// it came from. Errors that don't end the session are shown in a popup and
+/// written to the debug log instead of stderr, which would garble the screen
+/// in raw mode.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or applying the config file.
+    Config(anyhow::Error),
+    /// The pseudo-terminal and the shell running in it.
+    Pty(anyhow::Error),
+    /// Running the `[llm]` command.
+    Llm(anyhow::Error),
+    /// Drawing popups, panels and the pane.
+    Ui(anyhow::Error),
+    /// A hook's action.
+    Plugin(anyhow::Error),
+}
+
+impl Error {
+    /// `error` as it was raised, if it is one of ours, or else put in the
+    /// category of the caller that caught it.
+    pub fn categorize(error: anyhow::Error, category: fn(anyhow::Error) -> Error) -> Error {
+        match error.downcast::<Error>() {
+            Ok(error) => error,
+            Err(error) => category(error),
+        }
+    }
+
+    /// Puts `context` in front of the message, keeping the category.
+    pub fn context<C>(self, context: C) -> Error
+    where
+        C: fmt::Display + Send + Sync + 'static,
+    {
+        match self {
+            Error::Config(e) => Error::Config(e.context(context)),
+            Error::Pty(e) => Error::Pty(e.context(context)),
+            Error::Llm(e) => Error::Llm(e.context(context)),
+            Error::Ui(e) => Error::Ui(e.context(context)),
+            Error::Plugin(e) => Error::Plugin(e.context(context)),
+        }
+    }
+
+    pub fn category(&self) -> &'static str {
+        match self {
+            Error::Config(_) => "config",
+            Error::Pty(_) => "pty",
+            Error::Llm(_) => "llm",
+            Error::Ui(_) => "ui",
+            Error::Plugin(_) => "plugin",
+        }
+    }
+
+    /// The title of the popup that shows this error.
+    pub fn title(&self) -> String {
+        tr(&format!("error-title-{}", self.category()))
+    }
+
+    fn inner(&self) -> &anyhow::Error {
+        match self {
+            Error::Config(e) | Error::Pty(e) | Error::Llm(e) | Error::Ui(e) | Error::Plugin(e) => e,
+        }
+    }
+
+    /// The message with each of its causes on a line of its own.
+    pub fn details(&self) -> String {
+        let mut details = format!("{} error: {}", self.category(), self.inner());
+        for cause in self.inner().chain().skip(1) {
+            details.push_str(&format!("\n  caused by: {}", cause));
+        }
+        details
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:#}", self.inner())
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.inner().source()
+    }
+}
+
+/// Where errors are logged: `$XDG_STATE_HOME/chatshell/debug.log`, by
+/// default under `~/.local/state`.
+pub fn debug_log_path() -> PathBuf {
+    let state = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(crate::config::expand_home("~/.local/state")),
+    };
+    state.join("chatshell").join("debug.log")
+}
+
+/// The debug log of a session. Every error gets a reference to quote, which
+/// finds its details in the log. The default log keeps nothing.
+#[derive(Debug, Default)]
+pub struct ErrorLog {
+    path: Option<PathBuf>,
+    session: u32,
+    next_id: u64,
+    // Messages already shown, so an error that repeats only pops up once
+    shown: HashSet<String>,
+}
+
+impl ErrorLog {
+    pub fn new(path: PathBuf) -> Self {
+        ErrorLog {
+            path: Some(path),
+            session: std::process::id(),
+            next_id: 1,
+            shown: HashSet::new(),
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Writes `error` to the log and returns its reference. A log that
+    /// can't be written doesn't stop the error from being shown.
+    pub fn record(&mut self, error: &Error) -> String {
+        let reference = format!("E{}-{}", self.session, self.next_id);
+        self.next_id += 1;
+
+        let Some(path) = &self.path else {
+            return reference;
+        };
+        let entry = format!(
+            "{} [{}] {}\n",
+            Local::now().format("%Y-%m-%d %H:%M:%S"),
+            reference,
+            error.details()
+        );
+        if let Some(dir) = path.parent() {
+            let _ = fs::create_dir_all(dir);
+        }
+        let _ = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| file.write_all(entry.as_bytes()));
+        reference
+    }
+
+    /// True the first time an error with this message is seen.
+    pub fn first_time(&mut self, error: &Error) -> bool {
+        self.shown.insert(format!("{}: {}", error.category(), error))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Context;
+
+    #[test]
+    fn test_error_log() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut log = ErrorLog::new(dir.path().join("chatshell").join("debug.log"));
+
+        let failed: anyhow::Result<()> = Err(anyhow::anyhow!("exit status 127"));
+        let error = Error::Llm(failed.context("LLM command failed").unwrap_err());
+        let first = log.record(&error);
+        let second = log.record(&error);
+        assert_ne!(first, second);
+        assert!(log.first_time(&error));
+        assert!(!log.first_time(&error));
+
+        let content = fs::read_to_string(log.path().unwrap()).unwrap();
+        assert!(content.contains(&format!("[{}] llm error: LLM command failed\n  caused by: exit status 127", first)));
+
+        // Our errors keep their category through anyhow
+        let config: anyhow::Error = Error::Config(anyhow::anyhow!("bad key")).into();
+        let config = Error::categorize(config, Error::Plugin).context("Hook 'reload' failed");
+        assert_eq!(config.category(), "config");
+        assert_eq!(config.to_string(), "Hook 'reload' failed: bad key");
+        assert_eq!(Error::categorize(anyhow::anyhow!("boom"), Error::Plugin).category(), "plugin");
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::Result;
+use chrono::Local;
+use regex::Regex;
+use std::sync::OnceLock;
+
+use crate::history::Annotation;
+
+/// Steps kept for export; older ones are dropped.
+const MAX_ENTRIES: usize = 2000;
+
+/// How long the exported script waits for each prompt, in seconds.
+const TIMEOUT_SECS: u32 = 30;
+
+/// One line typed at a prompt: what was on the cursor's line before the
+/// first key, and the line that was entered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Step {
+    pub prompt: String,
+    pub input: String,
+    /// True if nothing was echoed, as at a password prompt, so the input
+    /// is the keys as typed.
+    pub hidden: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    Step(Step),
+    Bookmark(String),
+    Note(Annotation),
+}
+
+/// What is exported between two bookmarks: the steps, and the comments
+/// made on the output along the way.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Part<'a> {
+    Step(&'a Step),
+    Note(&'a Annotation),
+}
+
+impl<'a> Part<'a> {
+    pub fn step(&self) -> Option<&'a Step> {
+        match self {
+            Part::Step(step) => Some(step),
+            Part::Note(_) => None,
+        }
+    }
+}
+
+// A step being typed
+#[derive(Debug)]
+struct Pending {
+    prompt: String,
+    typed: String,
+}
+
+/// What was typed in the session and at which prompts, with bookmarks that
+/// delimit the parts to export.
+#[derive(Debug, Default)]
+pub struct InteractionLog {
+    entries: Vec<Entry>,
+    pending: Option<Pending>,
+    bookmarks: usize,
+}
+
+impl InteractionLog {
+    /// Records keys typed into the shell. `current_line` is the line the
+    /// cursor is on: the prompt before the first key, and the prompt with
+    /// the echo of what was typed once Enter is pressed.
+    pub fn typed(&mut self, bytes: &[u8], current_line: &str) {
+        for c in String::from_utf8_lossy(bytes).chars() {
+            let pending = self.pending.get_or_insert_with(|| Pending {
+                prompt: current_line.to_string(),
+                typed: String::new(),
+            });
+            match c {
+                '\r' => {
+                    let pending = self.pending.take().expect("just inserted");
+                    // The echo shows history recall, completion and edits
+                    // that the keys alone don't
+                    let echoed = current_line
+                        .strip_prefix(pending.prompt.as_str())
+                        .map(str::trim_end)
+                        .filter(|echoed| !echoed.is_empty());
+                    let step = Step {
+                        input: echoed.map(str::to_string).unwrap_or_else(|| pending.typed.clone()),
+                        hidden: echoed.is_none() && !pending.typed.is_empty(),
+                        prompt: pending.prompt,
+                    };
+                    self.push(Entry::Step(step));
+                }
+                '\x7f' | '\x08' => {
+                    pending.typed.pop();
+                }
+                c => pending.typed.push(c),
+            }
+        }
+    }
+
+    /// Adds a bookmark and returns its name; unnamed ones are numbered.
+    pub fn bookmark(&mut self, name: Option<String>) -> String {
+        self.bookmarks += 1;
+        let name = name.unwrap_or_else(|| self.bookmarks.to_string());
+        self.push(Entry::Bookmark(name.clone()));
+        name
+    }
+
+    /// Adds a comment made on the output, exported after the steps before
+    /// it.
+    pub fn annotate(&mut self, annotation: Annotation) {
+        self.push(Entry::Note(annotation));
+    }
+
+    fn push(&mut self, entry: Entry) {
+        self.entries.push(entry);
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    fn position(&self, name: &str) -> Result<usize> {
+        self.entries
+            .iter()
+            .rposition(|entry| *entry == Entry::Bookmark(name.to_string()))
+            .ok_or_else(|| anyhow::anyhow!("No bookmark named {}", name))
+    }
+
+    /// The steps and notes from bookmark `from` to bookmark `to`. Without
+    /// names, those between the last two bookmarks, or after the only one.
+    /// The `chatshell` commands that set bookmarks and export are left out.
+    pub fn segment(&self, from: Option<&str>, to: Option<&str>) -> Result<Vec<Part<'_>>> {
+        let marks: Vec<usize> = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| matches!(entry, Entry::Bookmark(_)))
+            .map(|(i, _)| i)
+            .collect();
+        let (start, end) = match (from, to) {
+            (Some(from), Some(to)) => (self.position(from)?, self.position(to)?),
+            (Some(from), None) => (self.position(from)?, self.entries.len()),
+            (None, Some(to)) => {
+                let end = self.position(to)?;
+                let start = marks.iter().rev().find(|mark| **mark < end).copied();
+                (start.ok_or_else(|| anyhow::anyhow!("No bookmark before {}", to))?, end)
+            }
+            (None, None) => match marks.as_slice() {
+                [] => anyhow::bail!("No bookmarks yet; set one with `chatshell bookmark` or builtin:bookmark"),
+                [only] => (*only, self.entries.len()),
+                [.., before, last] => (*before, *last),
+            },
+        };
+        if start > end {
+            anyhow::bail!("The bookmarks are in the wrong order");
+        }
+
+        Ok(self.entries[start..end]
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::Step(step) if step.input.starts_with("chatshell ") => None,
+                Entry::Step(step) => Some(Part::Step(step)),
+                Entry::Note(annotation) => Some(Part::Note(annotation)),
+                Entry::Bookmark(_) => None,
+            })
+            .collect())
+    }
+}
+
+/// What to wait for before typing a step. Shell prompts change with the
+/// directory, so only their end is matched; questions are matched whole.
+fn expected(prompt: &str) -> &str {
+    static SHELL_PROMPT: OnceLock<Regex> = OnceLock::new();
+    let shell_prompt = SHELL_PROMPT.get_or_init(|| Regex::new(r"[$#%>] ?$").unwrap());
+    match shell_prompt.find(prompt) {
+        Some(end) => &prompt[end.start()..],
+        None => prompt.trim_start(),
+    }
+}
+
+fn is_secret_prompt(prompt: &str) -> bool {
+    let prompt = prompt.to_lowercase();
+    ["password", "passphrase", "passcode", "token", "secret", "pin:"]
+        .iter()
+        .any(|word| prompt.contains(word))
+}
+
+/// `text` as the inside of a double-quoted Tcl string.
+fn tcl_quote(text: &str) -> String {
+    let mut quoted = String::new();
+    for c in text.chars() {
+        match c {
+            '\\' | '"' | '[' | ']' | '$' | '{' | '}' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            // Octal takes at most three digits, unlike \x
+            c if (c as u32) < 0x20 || c == '\x7f' => quoted.push_str(&format!("\\{:03o}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted
+}
+
+/// An expect script that starts a shell and repeats the steps of `parts`,
+/// with their notes as comments. Input typed at password prompts is taken
+/// from environment variables instead of being written into the script.
+pub fn expect_script(parts: &[Part]) -> String {
+    let mut script = format!(
+        "#!/usr/bin/expect -f\n\
+         # Recorded with chatshell on {}. Check what each step waits for\n\
+         # before running it unattended.\n\
+         set timeout {}\n\
+         spawn $env(SHELL)\n",
+        Local::now().format("%Y-%m-%d %H:%M"),
+        TIMEOUT_SECS
+    );
+    let mut secrets = 0;
+    for part in parts {
+        let step = match part {
+            Part::Step(step) => step,
+            Part::Note(annotation) => {
+                script.push_str(&note_comment(annotation));
+                continue;
+            }
+        };
+        script.push('\n');
+        let expected = expected(&step.prompt);
+        if !expected.is_empty() {
+            script.push_str(&format!("expect -exact \"{}\"\n", tcl_quote(expected)));
+        }
+        if step.hidden && is_secret_prompt(&step.prompt) {
+            secrets += 1;
+            script.push_str(&format!("# Typed at a password prompt; set SECRET_{} before running\n", secrets));
+            script.push_str(&format!("send -- \"$env(SECRET_{})\\r\"\n", secrets));
+        } else {
+            script.push_str(&format!("send -- \"{}\\r\"\n", tcl_quote(&step.input)));
+        }
+    }
+    script.push_str("\n# Hand the session over; use `expect eof` to wait for it to end instead\ninteract\n");
+    script
+}
+
+/// `annotation` as script comments: the comment, then the lines it is on.
+fn note_comment(annotation: &Annotation) -> String {
+    let mut comment = String::from("\n");
+    for line in annotation.comment.lines() {
+        comment.push_str(&format!("# Note: {}\n", line));
+    }
+    for line in &annotation.lines {
+        comment.push_str(&format!("#   | {}\n", line));
+    }
+    comment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_segments_between_bookmarks() {
+        let mut log = InteractionLog::default();
+        log.typed(b"ls", "$ ");
+        log.typed(b"\r", "$ ls");
+        assert_eq!(log.bookmark(None), "1");
+        // Echoed as typed, then recalled from history and run
+        log.typed(b"mkae", "~/src$ ");
+        log.typed(b"\x7f\x7fke\r", "~/src$ make");
+        log.typed(b"\x1b[A", "~/src$ ");
+        log.typed(b"\r", "~/src$ make");
+        log.typed(b"chatshell bookmark deploy", "~/src$ ");
+        log.typed(b"\r", "~/src$ chatshell bookmark deploy");
+        log.bookmark(Some("deploy".to_string()));
+        log.typed(b"ssh-add", "~/src$ ");
+        log.typed(b"\r", "~/src$ ssh-add");
+        // Nothing is echoed at the passphrase prompt
+        log.typed(b"hunter2\r", "Enter passphrase for key: ");
+
+        let steps: Vec<&Step> = log.segment(None, None).unwrap().iter().filter_map(Part::step).collect();
+        let inputs: Vec<&str> = steps.iter().map(|step| step.input.as_str()).collect();
+        assert_eq!(inputs, ["make", "make"]);
+        assert_eq!(steps[0].prompt, "~/src$ ");
+
+        log.annotate(Annotation {
+            at: 0,
+            session: String::new(),
+            lines: vec!["Identity added: /home/me/.ssh/id_ed25519".to_string()],
+            comment: "Key loaded".to_string(),
+        });
+        let parts = log.segment(Some("deploy"), None).unwrap();
+        assert_eq!(parts.len(), 3);
+        assert!(parts[1].step().unwrap().hidden);
+        assert!(log.segment(Some("missing"), None).is_err());
+        assert!(InteractionLog::default().segment(None, None).is_err());
+
+        let script = expect_script(&parts);
+        assert!(script.contains("expect -exact \"\\$ \"\nsend -- \"ssh-add\\r\"\n"));
+        assert!(script.contains("expect -exact \"Enter passphrase for key: \"\n"));
+        assert!(script.contains("send -- \"$env(SECRET_1)\\r\"\n"));
+        assert!(!script.contains("hunter2"));
+        assert!(script.contains("# Note: Key loaded\n#   | Identity added: /home/me/.ssh/id_ed25519\n"));
+    }
+
+    #[test]
+    fn test_tcl_quoting() {
+        assert_eq!(tcl_quote("echo \"$HOME\" [x]"), "echo \\\"\\$HOME\\\" \\[x\\]");
+        assert_eq!(tcl_quote("\x03"), "\\003");
+        assert_eq!(expected("user@host:~/src$ "), "$ ");
+        assert_eq!(expected("Continue? [y/N] "), "Continue? [y/N] ");
+    }
+}
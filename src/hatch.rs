@@ -0,0 +1,146 @@
+use anyhow::{Context, Result};
+use crossterm::event::{DisableMouseCapture, Event, KeyEventKind, PopKeyboardEnhancementFlags};
+use crossterm::style::ResetColor;
+use crossterm::terminal::{disable_raw_mode, LeaveAlternateScreen};
+use crossterm::{cursor, execute};
+use nix::sys::signal::Signal;
+use std::io::{stdout, Write};
+use std::sync::Mutex;
+
+use crate::config::HatchPolicy;
+use crate::process;
+use crate::terminal::{split_chord, split_trigger, KeyInput, Press};
+
+/// What the escape hatch needs to leave: the key that pulls it, what to do
+/// with the shell and what the terminal was switched into.
+#[derive(Debug)]
+struct Hatch {
+    key: String,
+    policy: HatchPolicy,
+    shell: Option<i32>,
+    keyboard_enhanced: bool,
+}
+
+static HATCH: Mutex<Option<Hatch>> = Mutex::new(None);
+
+fn with_hatch<T>(f: impl FnOnce(&mut Option<Hatch>) -> T) -> T {
+    f(&mut HATCH.lock().unwrap_or_else(|e| e.into_inner()))
+}
+
+/// Makes `key` end chatshell as soon as the terminal reports it, before
+/// hooks, plugins or overlays see it, even while a hook is stuck. It must be
+/// a single plain press, not a chord or a double or long press, which a
+/// wedged session may never finish.
+pub fn arm(key: &str, policy: HatchPolicy) -> Result<()> {
+    let single = split_chord(key).is_none() && split_trigger(key).0 == Press::Single;
+    let parsed = KeyInput::parse(key).filter(|_| single).with_context(|| format!("Not a single key: {}", key))?;
+    let key = parsed.pattern().unwrap_or_else(|| key.to_string());
+    with_hatch(|hatch| {
+        let (shell, keyboard_enhanced) = hatch.as_ref().map_or((None, false), |hatch| (hatch.shell, hatch.keyboard_enhanced));
+        *hatch = Some(Hatch { key, policy, shell, keyboard_enhanced });
+    });
+    Ok(())
+}
+
+/// Notes the shell the hatch ends, or that there is none, as with a serial
+/// device.
+pub fn set_shell(pid: Option<i32>) {
+    with_hatch(|hatch| {
+        if let Some(hatch) = hatch {
+            hatch.shell = pid;
+        }
+    });
+}
+
+/// Notes whether the kitty keyboard protocol is on, to be turned off again.
+pub fn set_keyboard_enhanced(enhanced: bool) {
+    with_hatch(|hatch| {
+        if let Some(hatch) = hatch {
+            hatch.keyboard_enhanced = enhanced;
+        }
+    });
+}
+
+/// True if `event` is the press of the armed escape hatch key.
+pub fn matches(event: &Event) -> bool {
+    let Event::Key(key) = event else {
+        return false;
+    };
+    key.kind != KeyEventKind::Release
+        && with_hatch(|hatch| hatch.as_ref().is_some_and(|hatch| KeyInput::from_event(*key).matches_pattern(&hatch.key)))
+}
+
+/// Pulls the hatch if `event` is its key; doesn't return then.
+pub fn check(event: &Event) {
+    if matches(event) {
+        pull();
+    }
+}
+
+/// Gives the terminal back, hangs up on or kills the shell as the policy
+/// says, and exits, without waiting on anything that may be stuck.
+fn pull() -> ! {
+    let (shell, policy, keyboard_enhanced) =
+        with_hatch(|hatch| hatch.as_ref().map_or((None, HatchPolicy::Detach, false), |hatch| (hatch.shell, hatch.policy, hatch.keyboard_enhanced)));
+    let mut stdout = stdout();
+    if keyboard_enhanced {
+        let _ = execute!(stdout, PopKeyboardEnhancementFlags);
+    }
+    // The full screen as scroll region, in case the split pane set one
+    let _ = write!(stdout, "\x1b[r");
+    let _ = execute!(stdout, ResetColor, LeaveAlternateScreen, DisableMouseCapture, cursor::Show);
+    let _ = disable_raw_mode();
+
+    if let (Some(shell), HatchPolicy::Kill) = (shell, policy) {
+        // Background jobs are in process groups of their own
+        let processes = process::list();
+        let _ = process::send_signal(shell, Signal::SIGKILL);
+        for (_, child) in process::descendants(&processes, shell) {
+            let _ = process::send_signal(child.pid, Signal::SIGKILL);
+        }
+    }
+    // Detached, the shell is hung up on as the terminal closes, like when
+    // its window is closed
+    let _ = writeln!(stdout, "\r\nchatshell: escape hatch pulled, exiting");
+    let _ = stdout.flush();
+    std::process::exit(1);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+    #[test]
+    fn test_escape_hatch_key() {
+        assert!(arm("ctrl+a q", HatchPolicy::Detach).is_err());
+        assert!(arm("esc esc", HatchPolicy::Detach).is_err());
+        assert!(arm("ctrl+nothing", HatchPolicy::Detach).is_err());
+
+        arm("ctrl+alt+q", HatchPolicy::Kill).unwrap();
+        let press = |code, modifiers| Event::Key(KeyEvent::new(code, modifiers));
+        assert!(matches(&press(KeyCode::Char('q'), KeyModifiers::CONTROL | KeyModifiers::ALT)));
+        assert!(!matches(&press(KeyCode::Char('q'), KeyModifiers::CONTROL)));
+        let mut release = KeyEvent::new(KeyCode::Char('q'), KeyModifiers::CONTROL | KeyModifiers::ALT);
+        release.kind = KeyEventKind::Release;
+        assert!(!matches(&Event::Key(release)));
+        assert!(!matches(&Event::Resize(80, 24)));
+    }
+
+    #[test]
+    fn test_keys_are_only_read_past_the_hatch() {
+        // Split so this file doesn't match itself
+        let read = ["event::", "read()"].concat();
+        let poll = ["event::", "poll("].concat();
+        let src = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src");
+        let mut reads = Vec::new();
+        for entry in std::fs::read_dir(&src).unwrap() {
+            let path = entry.unwrap().path();
+            let source = std::fs::read_to_string(&path).unwrap();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            reads.extend(source.matches(&read).chain(source.matches(&poll)).map(|_| name.clone()));
+        }
+        // Only the reader thread, which checks for the hatch first
+        assert_eq!(reads, vec!["terminal.rs".to_string(); 2], "keys read without terminal::read_event miss the escape hatch");
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use nix::sys::wait::WaitStatus;
+use std::io::{IsTerminal, Write};
+use std::path::PathBuf;
+use tokio::io::AsyncReadExt;
+
+use crate::backend::Backend;
+use crate::config::Config;
+use crate::history::CommandRecorder;
+use crate::output::OutputProcessor;
+use crate::pty::PtySession;
+use crate::transcript::{self, Entry, TranscriptFile};
+
+/// The size the shell is told when there is no terminal to ask.
+const DEFAULT_SIZE: (u16, u16) = (80, 24);
+
+/// End of transmission, which ends the shell's input at its prompt.
+const EOF: u8 = 0x04;
+
+/// True if stdout isn't a terminal, as under a CI job or in a pipe, so
+/// there is no screen to draw popups on or keys to read in raw mode.
+pub fn needed() -> bool {
+    !std::io::stdout().is_terminal()
+}
+
+/// The columns and rows the shell gets: `COLUMNS` and `LINES` where they
+/// are set to sizes, 80 by 24 otherwise.
+pub fn size(columns: Option<&str>, lines: Option<&str>) -> (u16, u16) {
+    let parse = |value: Option<&str>| value.and_then(|value| value.trim().parse::<u16>().ok()).filter(|&n| n > 0);
+    (parse(columns).unwrap_or(DEFAULT_SIZE.0), parse(lines).unwrap_or(DEFAULT_SIZE.1))
+}
+
+/// Runs the shell of `config` with its output passed through to stdout as
+/// it comes, and stdin passed to it a line at a time after
+/// `startup_commands`. Nothing is drawn and no hooks run, but the lines
+/// read go to the history as commands, and the output to the transcript
+/// in `transcript_path` if there is one. Once stdin ends the shell is told
+/// so, like ctrl+d at its prompt. Returns the shell's exit code.
+pub async fn run(config: &Config, startup_commands: &[String], transcript_path: Option<PathBuf>) -> Result<i32> {
+    let pty = PtySession::spawn(&config.shell).context("Failed to spawn shell process")?;
+    let (cols, rows) = size(std::env::var("COLUMNS").ok().as_deref(), std::env::var("LINES").ok().as_deref());
+    pty.resize(rows, cols)?;
+    let io = pty.async_io()?;
+    let mut output = OutputProcessor::new(config.terminal.clone());
+    output.resize(cols, rows);
+    let mut recorder = CommandRecorder::open(&config.history).unwrap_or_else(|e| {
+        eprintln!("Command history is off: {:#}", e);
+        None
+    });
+    let mut transcript = transcript_path.and_then(|path| {
+        TranscriptFile::open(&path).map_err(|e| eprintln!("Transcripts are off: {:#}", e)).ok()
+    });
+    if transcript.is_some() {
+        output.keep_colored_lines();
+    }
+    let mut transcript_flush = tokio::time::interval(transcript::FLUSH_INTERVAL);
+    // Typed ahead of the shell's prompt, which reads them in order
+    for command in startup_commands {
+        io.write_all(format!("{}\n", command).as_bytes()).await.context("Failed to write to shell")?;
+    }
+
+    let mut stdin = tokio::io::stdin();
+    let mut stdout = std::io::stdout();
+    let mut input_open = true;
+    let mut input = vec![0; 4096];
+    let mut buffer = vec![0; 8192];
+    loop {
+        tokio::select! {
+            read = io.read(&mut buffer) => {
+                // A PTY whose shell has exited fails with EIO
+                let n = match read {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => n,
+                };
+                stdout.write_all(&buffer[..n]).context("Failed to write to stdout")?;
+                stdout.flush().context("Failed to flush stdout")?;
+                let processed = output.process(&buffer[..n]);
+                if let Some(history) = recorder.as_mut() {
+                    if let Err(e) = history.output(&processed.lines, &processed.exit_statuses) {
+                        eprintln!("Command history is off: {:#}", e);
+                        recorder = None;
+                    }
+                }
+                if let Some(file) = transcript.as_mut().filter(|_| !processed.colored_lines.is_empty()) {
+                    if let Err(e) = file.append(&Entry::Output { lines: processed.colored_lines }) {
+                        eprintln!("The transcript is off: {:#}", e);
+                        transcript = None;
+                    }
+                }
+                if !processed.replies.is_empty() {
+                    io.write_all(&processed.replies).await.context("Failed to write to shell")?;
+                }
+            }
+            read = stdin.read(&mut input), if input_open => {
+                let n = read.unwrap_or(0);
+                if n == 0 {
+                    input_open = false;
+                    io.write_all(&[EOF]).await.context("Failed to write to shell")?;
+                    continue;
+                }
+                for line in input[..n].split_inclusive(|&b| b == b'\n') {
+                    // The line is the command; the screen's echo lags behind
+                    if let Some(history) = recorder.as_mut().filter(|_| line.ends_with(b"\n")) {
+                        let _ = history.entered_line(&String::from_utf8_lossy(line), pty.cwd());
+                    }
+                    io.write_all(line).await.context("Failed to write to shell")?;
+                }
+            }
+            _ = transcript_flush.tick() => flush(&mut transcript),
+        }
+    }
+    flush(&mut transcript);
+
+    Ok(match pty.wait_for_child()? {
+        WaitStatus::Exited(_, code) => code,
+        WaitStatus::Signaled(_, signal, _) => 128 + signal as i32,
+        _ => 0,
+    })
+}
+
+/// Writes out what was added to `transcript`, giving it up if that fails.
+fn flush(transcript: &mut Option<TranscriptFile>) {
+    if let Some(Err(e)) = transcript.as_mut().map(TranscriptFile::flush) {
+        eprintln!("The transcript is off: {:#}", e);
+        *transcript = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size_without_a_terminal() {
+        assert_eq!(size(None, None), (80, 24));
+        assert_eq!(size(Some("132"), Some("50")), (132, 50));
+        assert_eq!(size(Some("wide"), Some("0")), (80, 24));
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::Result;
+use crossterm::style::Color;
+use std::ops::Range;
+
+/// Bytes of a line drawn in a color of their own.
+pub type Span = (Range<usize>, Color);
+
+/// Colors code by its language, for code blocks in popups.
+pub struct Highlighter {
+    #[cfg(feature = "highlight")]
+    syntaxes: std::sync::OnceLock<syntect::parsing::SyntaxSet>,
+    #[cfg(feature = "highlight")]
+    theme: syntect::highlighting::Theme,
+    // Whether the terminal takes 24-bit colors, or only the 256 of xterm
+    #[cfg(feature = "highlight")]
+    truecolor: bool,
+}
+
+impl std::fmt::Debug for Highlighter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Highlighter").finish_non_exhaustive()
+    }
+}
+
+impl Highlighter {
+    /// A highlighter with one of the themes syntect comes with, in the
+    /// colors `COLORTERM` says the terminal has.
+    #[cfg(feature = "highlight")]
+    pub fn new(theme: &str) -> Result<Self> {
+        let mut themes = syntect::highlighting::ThemeSet::load_defaults().themes;
+        let Some(theme) = themes.remove(theme) else {
+            let names: Vec<&str> = themes.keys().map(String::as_str).collect();
+            anyhow::bail!("No highlighting theme named {} (there are {})", theme, names.join(", "));
+        };
+        Ok(Highlighter { syntaxes: std::sync::OnceLock::new(), theme, truecolor: truecolor() })
+    }
+
+    #[cfg(not(feature = "highlight"))]
+    pub fn new(_theme: &str) -> Result<Self> {
+        anyhow::bail!("chatshell was built without the highlight feature")
+    }
+
+    /// The colors of each line of `code` in `language`, named as after a
+    /// code fence (`rust`, `sh`, `py`), or None if it isn't one known.
+    #[cfg(feature = "highlight")]
+    pub fn highlight(&self, code: &[&str], language: &str) -> Option<Vec<Vec<Span>>> {
+        use syntect::easy::HighlightLines;
+        use syntect::parsing::SyntaxSet;
+
+        // Loading the syntaxes takes a moment, so only once code is shown
+        let syntaxes = self.syntaxes.get_or_init(SyntaxSet::load_defaults_nonewlines);
+        let syntax = syntaxes.find_syntax_by_token(language)?;
+        let mut lines = HighlightLines::new(syntax, &self.theme);
+        let mut colored = Vec::with_capacity(code.len());
+        for line in code {
+            let regions = lines.highlight_line(line, syntaxes).ok()?;
+            let mut start = 0;
+            let mut spans = Vec::with_capacity(regions.len());
+            for (style, text) in regions {
+                let color = style.foreground;
+                spans.push((start..start + text.len(), self.color(color.r, color.g, color.b)));
+                start += text.len();
+            }
+            colored.push(spans);
+        }
+        Some(colored)
+    }
+
+    #[cfg(not(feature = "highlight"))]
+    pub fn highlight(&self, _code: &[&str], _language: &str) -> Option<Vec<Vec<Span>>> {
+        None
+    }
+
+    #[cfg(feature = "highlight")]
+    fn color(&self, r: u8, g: u8, b: u8) -> Color {
+        if self.truecolor {
+            Color::Rgb { r, g, b }
+        } else {
+            Color::AnsiValue(nearest_ansi(r, g, b))
+        }
+    }
+}
+
+#[cfg(feature = "highlight")]
+fn truecolor() -> bool {
+    std::env::var("COLORTERM").is_ok_and(|value| value == "truecolor" || value == "24bit")
+}
+
+/// The color of xterm's 256 closest to `r`, `g`, `b`, from its color cube
+/// or its greys.
+pub fn nearest_ansi(r: u8, g: u8, b: u8) -> u8 {
+    const LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+    let level = |c: u8| (0..6).min_by_key(|&i| LEVELS[i].abs_diff(c)).unwrap_or(0);
+    let (lr, lg, lb) = (level(r), level(g), level(b));
+    let cube = (16 + 36 * lr + 6 * lg + lb) as u8;
+    let average = (r as u32 + g as u32 + b as u32) / 3;
+    let grey = 232 + ((average.saturating_sub(8) / 10).min(23)) as u8;
+
+    let distance = |index: u8| {
+        let (cr, cg, cb) = crate::theme::rgb(Color::AnsiValue(index));
+        let d = |a: u8, b: u8| (a as i32 - b as i32).pow(2);
+        d(cr, r) + d(cg, g) + d(cb, b)
+    };
+    if distance(grey) < distance(cube) {
+        grey
+    } else {
+        cube
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_nearest_ansi() {
+        assert_eq!(nearest_ansi(255, 0, 0), 196);
+        assert_eq!(nearest_ansi(0, 0, 0), 16);
+        assert_eq!(nearest_ansi(128, 128, 128), 244);
+        assert_eq!(nearest_ansi(95, 135, 175), 67);
+    }
+
+    #[cfg(feature = "highlight")]
+    #[test]
+    fn test_highlight_code() {
+        let highlighter = Highlighter::new("base16-ocean.dark").unwrap();
+        let code = ["fn main() {", "    let answer = 42;", "}"];
+        let lines = highlighter.highlight(&code, "rust").unwrap();
+        assert_eq!(lines.len(), 3);
+        for (line, spans) in code.iter().zip(&lines) {
+            // The spans cover the line, in order
+            let covered: String = spans.iter().map(|(range, _)| &line[range.clone()]).collect();
+            assert_eq!(&covered, line);
+        }
+        // `fn` and `main` aren't the same color
+        assert!(lines[0].len() > 1);
+        assert_ne!(lines[0][0].1, lines[0][lines[0].len() - 1].1);
+
+        assert!(highlighter.highlight(&code, "no-such-language").is_none());
+        assert!(Highlighter::new("no-such-theme").is_err());
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::Result;
+use chrono::Local;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::HistoryConfig;
+use crate::storage::{self, Storage};
+
+/// Prompt endings tried when the prompt a command was typed at isn't known.
+const PROMPT_ENDINGS: [&str; 5] = ["$ ", "# ", "% ", "> ", "❯ "];
+
+/// A command typed at the shell's prompt.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandRecord {
+    /// Unix time, in seconds.
+    pub started_at: i64,
+    /// Tells apart commands of chatshell sessions running side by side.
+    pub session: String,
+    pub command: String,
+    pub cwd: Option<String>,
+    /// Root of the git repository `cwd` is in.
+    pub repo: Option<String>,
+    /// Known only for shells that report it (`OSC 133 ; D`).
+    pub exit_status: Option<i32>,
+}
+
+/// A comment on lines of the shell's output, made in copy mode.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Annotation {
+    /// Unix time, in seconds.
+    pub at: i64,
+    pub session: String,
+    /// The lines commented on, as they were printed.
+    pub lines: Vec<String>,
+    pub comment: String,
+}
+
+/// One call of the LLM command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LlmUsage {
+    /// Unix time, in seconds.
+    pub at: i64,
+    /// What the call was for, e.g. `ask`.
+    pub purpose: String,
+    pub prompt_tokens: u64,
+    pub reply_tokens: u64,
+}
+
+impl LlmUsage {
+    /// The LLM command doesn't report what it used, so tokens are estimated
+    /// at four characters each. The prompt may come in parts, e.g. the
+    /// system prompt and the user's.
+    pub fn estimate(purpose: &str, prompt: &[&str], reply: &str) -> Self {
+        let tokens = |chars: usize| chars.div_ceil(4) as u64;
+        LlmUsage {
+            at: Local::now().timestamp(),
+            purpose: purpose.to_string(),
+            prompt_tokens: tokens(prompt.iter().map(|part| part.chars().count()).sum()),
+            reply_tokens: tokens(reply.chars().count()),
+        }
+    }
+}
+
+/// Records an LLM call, if history is enabled. A failure to record it
+/// doesn't concern the caller, so it is ignored.
+pub fn record_llm_usage(config: &HistoryConfig, purpose: &str, prompt: &[&str], reply: &str) {
+    if !config.enabled {
+        return;
+    }
+    if let Ok(mut storage) = storage::open(config) {
+        let _ = storage.add_llm_usage(&LlmUsage::estimate(purpose, prompt, reply));
+    }
+}
+
+/// The working directory of process `pid`, where the system tells.
+pub fn process_cwd(pid: i32) -> Option<PathBuf> {
+    fs::read_link(format!("/proc/{}/cwd", pid)).ok()
+}
+
+/// The root of the git repository containing `dir`.
+pub fn repo_root(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors().find(|dir| dir.join(".git").exists()).map(Path::to_path_buf)
+}
+
+/// The command typed on `line`, with the prompt taken off. Commands typed
+/// with a leading space are left out, as shells do with `ignorespace`.
+fn command_text(prompt: Option<&str>, line: &str) -> Option<String> {
+    // Enter on an empty prompt
+    if prompt.is_some_and(|prompt| prompt.trim_end() == line.trim_end()) {
+        return None;
+    }
+    let typed = match prompt.and_then(|prompt| line.strip_prefix(prompt)) {
+        Some(typed) => typed,
+        None => PROMPT_ENDINGS
+            .iter()
+            .filter_map(|ending| line.find(ending).map(|i| &line[i + ending.len()..]))
+            .max_by_key(|typed| typed.len())
+            .unwrap_or(line),
+    };
+    if typed.starts_with(' ') || typed.trim().is_empty() {
+        return None;
+    }
+    Some(typed.trim().to_string())
+}
+
+#[derive(Debug)]
+struct PendingCommand {
+    started_at: i64,
+    cwd: Option<PathBuf>,
+    prompt: Option<String>,
+}
+
+/// Records the commands typed into the shell. A command is named by the
+/// next line the shell finishes after Enter, its echo, as input can run
+/// ahead of the screen.
+///
+/// Storage is append-only, so the last command is held back until its exit
+/// status arrives or the next command is entered.
+#[derive(Debug)]
+pub struct CommandRecorder {
+    storage: Box<dyn Storage>,
+    session: String,
+    // The line the first key of the next command was typed on
+    prompt: Option<String>,
+    pending: Option<PendingCommand>,
+    unsaved: Option<CommandRecord>,
+}
+
+impl CommandRecorder {
+    pub fn new(storage: Box<dyn Storage>) -> Self {
+        CommandRecorder {
+            storage,
+            session: format!("{}-{}", std::process::id(), Local::now().timestamp()),
+            prompt: None,
+            pending: None,
+            unsaved: None,
+        }
+    }
+
+    /// A recorder for the history `config` describes, or None if history
+    /// is off.
+    pub fn open(config: &HistoryConfig) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+        Ok(Some(CommandRecorder::new(storage::open(config)?)))
+    }
+
+    fn save(&mut self) -> Result<()> {
+        match self.unsaved.take() {
+            Some(record) => self.storage.add_command(&record),
+            None => Ok(()),
+        }
+    }
+
+    /// Notes the screen's current line before a key is sent to the shell.
+    /// For the first key of a command, that is the prompt.
+    pub fn key_typed(&mut self, current_line: &str) {
+        if self.prompt.is_none() {
+            self.prompt = Some(current_line.to_string());
+        }
+    }
+
+    /// Enter was sent to the shell, which was in `cwd`.
+    pub fn entered(&mut self, cwd: Option<PathBuf>) -> Result<()> {
+        self.pending = Some(PendingCommand {
+            started_at: Local::now().timestamp(),
+            cwd,
+            prompt: self.prompt.take(),
+        });
+        self.save()
+    }
+
+    /// `line` was typed into the shell, which was in `cwd`, without a
+    /// screen to read the command off. The line is the command, left out
+    /// like one echoed with a leading space.
+    pub fn entered_line(&mut self, line: &str, cwd: Option<PathBuf>) -> Result<()> {
+        self.prompt = None;
+        self.pending = None;
+        self.save()?;
+        if !line.starts_with(' ') && !line.trim().is_empty() {
+            self.unsaved = Some(self.record(Local::now().timestamp(), line.trim().to_string(), cwd));
+        }
+        Ok(())
+    }
+
+    fn record(&self, started_at: i64, command: String, cwd: Option<PathBuf>) -> CommandRecord {
+        let repo = cwd.as_deref().and_then(repo_root);
+        CommandRecord {
+            started_at,
+            session: self.session.clone(),
+            command,
+            cwd: cwd.map(|cwd| cwd.to_string_lossy().to_string()),
+            repo: repo.map(|repo| repo.to_string_lossy().to_string()),
+            exit_status: None,
+        }
+    }
+
+    /// Takes in the lines and exit statuses of a chunk of shell output.
+    pub fn output(&mut self, lines: &[String], exit_statuses: &[i32]) -> Result<()> {
+        if let Some(line) = lines.first() {
+            if let Some(pending) = self.pending.take() {
+                if let Some(command) = command_text(pending.prompt.as_deref(), line) {
+                    let record = self.record(pending.started_at, command, pending.cwd);
+                    self.save()?;
+                    self.unsaved = Some(record);
+                }
+            }
+        }
+        if let (Some(record), Some(status)) = (self.unsaved.as_mut(), exit_statuses.last()) {
+            record.exit_status = Some(*status);
+            return self.save();
+        }
+        Ok(())
+    }
+
+    /// Stores `annotation` with the commands of this session.
+    pub fn annotate(&mut self, annotation: &Annotation) -> Result<()> {
+        self.storage.add_annotation(&Annotation { session: self.session.clone(), ..annotation.clone() })
+    }
+}
+
+impl Drop for CommandRecorder {
+    fn drop(&mut self) {
+        let _ = self.save();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_text() {
+        assert_eq!(command_text(Some("~/app$ "), "~/app$ git status"), Some("git status".to_string()));
+        assert_eq!(command_text(None, "me@host:~$ echo a > b"), Some("echo a > b".to_string()));
+        assert_eq!(command_text(Some("$ "), "$  export TOKEN=x"), None);
+        assert_eq!(command_text(Some("$ "), "$"), None);
+    }
+
+    #[test]
+    fn test_recorder_stores_commands() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir(dir.path().join(".git")).unwrap();
+        fs::create_dir(dir.path().join("src")).unwrap();
+        let storage = storage::JsonlStorage::open(&dir.path().join("history")).unwrap();
+        let mut recorder = CommandRecorder::new(Box::new(storage));
+
+        recorder.key_typed("$ ");
+        recorder.key_typed("$ m");
+        recorder.entered(Some(dir.path().join("src"))).unwrap();
+        recorder.output(&["$ make".to_string(), "error".to_string()], &[]).unwrap();
+        assert!(recorder.storage.commands_since(0).unwrap().is_empty());
+        recorder.output(&[], &[2]).unwrap();
+        recorder.key_typed("$ ");
+        recorder.entered(None).unwrap();
+        recorder.output(&["$".to_string()], &[0]).unwrap();
+        recorder.key_typed("$ ");
+        recorder.entered(None).unwrap();
+        recorder.output(&["$ ls".to_string()], &[]).unwrap();
+        drop(recorder);
+
+        let storage = storage::JsonlStorage::open(&dir.path().join("history")).unwrap();
+        let commands = storage.commands_since(0).unwrap();
+        assert_eq!(commands.len(), 2);
+        assert_eq!(commands[0].command, "make");
+        assert_eq!(commands[0].exit_status, Some(2));
+        assert_eq!(commands[0].repo.as_deref(), dir.path().to_str());
+        assert_eq!(commands[1].exit_status, None);
+    }
+
+    #[test]
+    fn test_recorder_takes_lines_as_typed() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = storage::JsonlStorage::open(&dir.path().join("history")).unwrap();
+        let mut recorder = CommandRecorder::new(Box::new(storage));
+
+        // Whatever the screen shows, as input runs ahead of it
+        recorder.entered_line("make test\n", None).unwrap();
+        recorder.output(&["$ echo unrelated".to_string()], &[1]).unwrap();
+        recorder.entered_line(" secret\n", None).unwrap();
+        recorder.entered_line("ls\n", None).unwrap();
+        drop(recorder);
+
+        let storage = storage::JsonlStorage::open(&dir.path().join("history")).unwrap();
+        let commands: Vec<_> = storage.commands_since(0).unwrap().into_iter().map(|record| (record.command, record.exit_status)).collect();
+        assert_eq!(commands, [("make test".to_string(), Some(1)), ("ls".to_string(), None)]);
+    }
+}
@@ -0,0 +1,421 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::config::{self, expand_home, HookConfig, UnknownKey};
+use crate::permissions::Principal;
+
+/// The file at the top of a pack that declares its hooks.
+pub const PACK_FILE: &str = "pack.toml";
+
+/// Stands for the pack's installed directory in its hooks' actions, so
+/// they can run the scripts it ships.
+pub const PACK_DIR_PLACEHOLDER: &str = "{pack}";
+
+/// What a pack declares in its `pack.toml`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PackManifest {
+    /// Taken from the repository's name if unset.
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub hooks: Vec<HookConfig>,
+}
+
+/// A pack listed in the registry index.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegistryEntry {
+    /// Git URL of the pack.
+    pub url: String,
+    /// Tag, branch or commit to install; the default branch if unset.
+    #[serde(default)]
+    pub rev: Option<String>,
+    /// Checksum the fetched pack must have, as `chatshell hooks add` prints it.
+    #[serde(default)]
+    pub sha256: Option<String>,
+    #[serde(default)]
+    pub description: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Registry {
+    pub packs: BTreeMap<String, RegistryEntry>,
+}
+
+impl Registry {
+    /// Reads the index from a file, or with `curl` from an http(s) URL.
+    pub fn load(location: &str) -> Result<Self> {
+        let text = if location.starts_with("http://") || location.starts_with("https://") {
+            let output = Command::new("curl")
+                .args(["-fsSL", location])
+                .output()
+                .with_context(|| "Failed to run curl to fetch the hook registry")?;
+            if !output.status.success() {
+                anyhow::bail!(
+                    "Failed to fetch the hook registry {}: {}",
+                    location,
+                    String::from_utf8_lossy(&output.stderr).trim()
+                );
+            }
+            String::from_utf8_lossy(&output.stdout).to_string()
+        } else {
+            let path = expand_home(location);
+            fs::read_to_string(&path).with_context(|| format!("Failed to read the hook registry {}", path))?
+        };
+        toml::from_str(&text).with_context(|| format!("Failed to parse the hook registry {}", location))
+    }
+}
+
+/// A pack checked out into a temporary directory, removed when dropped.
+#[derive(Debug)]
+pub struct FetchedPack {
+    pub name: String,
+    pub manifest: PackManifest,
+    pub checksum: String,
+    /// Keys of its `pack.toml` this chatshell doesn't know, which were
+    /// left out.
+    pub unknown_keys: Vec<UnknownKey>,
+    dir: PathBuf,
+}
+
+impl Drop for FetchedPack {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl FetchedPack {
+    /// Clones the pack at `url`, checked out at `rev` if given.
+    /// Neither may start with `-`, which git would take for an option.
+    pub fn fetch(url: &str, rev: Option<&str>) -> Result<Self> {
+        if url.starts_with('-') || rev.is_some_and(|rev| rev.starts_with('-')) {
+            anyhow::bail!("Invalid pack source {:?}: it may not start with -", rev.filter(|rev| rev.starts_with('-')).unwrap_or(url));
+        }
+        let dir = std::env::temp_dir().join(format!(
+            "chatshell-pack-{}-{}",
+            std::process::id(),
+            chrono::Utc::now().timestamp_nanos_opt().unwrap_or_default()
+        ));
+        // Owned from here on, so a failed fetch still cleans up
+        let mut pack = FetchedPack {
+            name: String::new(),
+            manifest: PackManifest::default(),
+            checksum: String::new(),
+            unknown_keys: Vec::new(),
+            dir,
+        };
+
+        let dir = pack.dir.to_string_lossy().to_string();
+        match rev {
+            // A commit can't be cloned directly, so clone everything
+            Some(rev) => {
+                git(&["clone", "--quiet", "--", url, &dir])?;
+                git(&["-C", &dir, "checkout", "--quiet", rev, "--"])?;
+            }
+            None => git(&["clone", "--quiet", "--depth", "1", "--", url, &dir])?,
+        }
+
+        let manifest_path = pack.dir.join(PACK_FILE);
+        let text = fs::read_to_string(&manifest_path)
+            .with_context(|| format!("{} has no {}; is it a hook pack?", url, PACK_FILE))?;
+        // A pack may be written for a newer chatshell, whose keys are left out
+        (pack.manifest, pack.unknown_keys) = config::from_str_lenient(&text).with_context(|| format!("Failed to parse {} of {}", PACK_FILE, url))?;
+        pack.name = match &pack.manifest.name {
+            Some(name) => name.clone(),
+            None => name_from_url(url),
+        };
+        if !valid_pack_name(&pack.name) {
+            anyhow::bail!("Invalid pack name {:?}: use letters, digits, - and _", pack.name);
+        }
+        pack.checksum = checksum(&pack.dir)?;
+        Ok(pack)
+    }
+
+    /// Fails unless the pack has the `expected` checksum.
+    pub fn verify_checksum(&self, expected: &str) -> Result<()> {
+        if !self.checksum.eq_ignore_ascii_case(expected.trim()) {
+            anyhow::bail!(
+                "Checksum mismatch for {}: expected {}, got {}",
+                self.name,
+                expected.trim(),
+                self.checksum
+            );
+        }
+        Ok(())
+    }
+
+    /// Fails unless the checked out commit has a good signature.
+    pub fn verify_signature(&self) -> Result<()> {
+        let dir = self.dir.to_string_lossy();
+        git(&["-C", &dir, "verify-commit", "HEAD"])
+            .with_context(|| format!("The commit of {} isn't signed by a trusted key", self.name))
+    }
+
+    /// What installing the pack does: its hooks and the keys they take,
+    /// marking keys that `existing` hooks already use.
+    pub fn summary(&self, existing: &[HookConfig]) -> String {
+        let mut summary = format!("Hook pack {} ({})\n", self.name, self.manifest.description.as_deref().unwrap_or("no description"));
+        for hook in &self.manifest.hooks {
+            let trigger = match hook.event {
+                Some(event) => event.name().to_string(),
+                None => hook.key_combination.clone(),
+            };
+            summary.push_str(&format!("  {:<16} {} -> {}", trigger, hook.name, hook.action));
+            if !hook.enabled {
+                summary.push_str(" (disabled)");
+            }
+            let taken: Vec<&str> = existing
+                .iter()
+                .filter(|other| {
+                    hook.event.is_none()
+                        && other.enabled
+                        && other.key_combination.eq_ignore_ascii_case(&hook.key_combination)
+                        && !other.name.starts_with(&format!("{}/", self.name))
+                })
+                .map(|other| other.name.as_str())
+                .collect();
+            if !taken.is_empty() {
+                summary.push_str(&format!(" [already bound to {}]", taken.join(", ")));
+            }
+            summary.push('\n');
+        }
+        if !self.unknown_keys.is_empty() {
+            let keys: Vec<String> = self.unknown_keys.iter().map(UnknownKey::to_string).collect();
+            summary.push_str(&format!("Left out as unknown: {}\n", keys.join(", ")));
+        }
+        summary.push_str(&format!("sha256 {}\n", self.checksum));
+        summary
+    }
+
+    /// Copies the pack into `packs_dir`, replacing an installed pack of the
+    /// same name, and returns where it went.
+    pub fn install(&self, packs_dir: &Path) -> Result<PathBuf> {
+        let target = packs_dir.join(&self.name);
+        let staging = packs_dir.join(format!(".{}.new", self.name));
+        let _ = fs::remove_dir_all(&staging);
+        copy_tree(&self.dir, &staging)
+            .with_context(|| format!("Failed to copy {} to {}", self.name, staging.display()))?;
+        if target.exists() {
+            fs::remove_dir_all(&target).with_context(|| format!("Failed to remove {}", target.display()))?;
+        }
+        fs::rename(&staging, &target).with_context(|| format!("Failed to install {}", target.display()))?;
+        Ok(target)
+    }
+}
+
+/// Where packs are installed: `hooks.d` next to the config.
+pub fn packs_dir(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join("hooks.d")
+}
+
+/// The hooks of the packs installed in `packs_dir`, named `<pack>/<hook>`.
+/// A pack whose `pack.toml` fails to load is left out, with the error
+/// saying why.
+pub fn installed_hooks(packs_dir: &Path) -> Result<(Vec<HookConfig>, Vec<anyhow::Error>)> {
+    let mut hooks = Vec::new();
+    let mut errors = Vec::new();
+    for (name, dir) in installed_packs(packs_dir)? {
+        match pack_hooks(&name, dir) {
+            Ok(pack) => hooks.extend(pack),
+            Err(e) => errors.push(e.context(format!("Failed to load hook pack {}", name))),
+        }
+    }
+    Ok((hooks, errors))
+}
+
+fn pack_hooks(name: &str, dir: PathBuf) -> Result<Vec<HookConfig>> {
+    let manifest_path = dir.join(PACK_FILE);
+    let text = fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Failed to read {}", manifest_path.display()))?;
+    // Its unknown keys were listed when it was installed
+    let (manifest, _): (PackManifest, _) = config::from_str_lenient(&text)?;
+    // Actions run from the shell's directory, so the path must be absolute
+    let dir = fs::canonicalize(&dir).unwrap_or(dir);
+    let pack_dir = dir.to_string_lossy();
+    Ok(manifest.hooks.into_iter().map(|hook| HookConfig {
+        name: format!("{}/{}", name, hook.name),
+        action: hook.action.map(|step| step.replace(PACK_DIR_PLACEHOLDER, &pack_dir)),
+        principal: Some(Principal::Pack(name.to_string())),
+        ..hook
+    }).collect())
+}
+
+/// The names and directories of the installed packs, by name.
+pub fn installed_packs(packs_dir: &Path) -> Result<Vec<(String, PathBuf)>> {
+    let entries = match fs::read_dir(packs_dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", packs_dir.display())),
+    };
+    let mut packs: Vec<(String, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| (entry.file_name().to_string_lossy().to_string(), entry.path()))
+        // Skips half-installed packs, which start with a dot
+        .filter(|(name, path)| valid_pack_name(name) && path.join(PACK_FILE).is_file())
+        .collect();
+    packs.sort();
+    Ok(packs)
+}
+
+/// Uninstalls the pack `name`.
+pub fn remove(packs_dir: &Path, name: &str) -> Result<()> {
+    let dir = packs_dir.join(name);
+    if !valid_pack_name(name) || !dir.join(PACK_FILE).is_file() {
+        anyhow::bail!("No hook pack named {} is installed", name);
+    }
+    fs::remove_dir_all(&dir).with_context(|| format!("Failed to remove {}", dir.display()))
+}
+
+fn valid_pack_name(name: &str) -> bool {
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn name_from_url(url: &str) -> String {
+    let last = url.trim_end_matches('/').rsplit(['/', ':']).next().unwrap_or(url);
+    last.strip_suffix(".git").unwrap_or(last).to_string()
+}
+
+fn git(args: &[&str]) -> Result<()> {
+    let output = Command::new("git")
+        .args(args)
+        .output()
+        .with_context(|| "Failed to run git")?;
+    if !output.status.success() {
+        anyhow::bail!("git {} failed: {}", args[0], String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+/// The SHA-256 of a pack's files, leaving out `.git`: of each file's path
+/// relative to `dir`, a NUL, its length as 8 big-endian bytes and its
+/// content, in order of path.
+pub fn checksum(dir: &Path) -> Result<String> {
+    let mut files = Vec::new();
+    collect_files(dir, Path::new(""), &mut files)?;
+    files.sort();
+    let mut data = Vec::new();
+    for relative in files {
+        let content = fs::read(dir.join(&relative))
+            .with_context(|| format!("Failed to read {}", relative.display()))?;
+        data.extend_from_slice(relative.to_string_lossy().as_bytes());
+        data.push(0);
+        data.extend_from_slice(&(content.len() as u64).to_be_bytes());
+        data.extend_from_slice(&content);
+    }
+    Ok(Sha256::digest(&data).iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn collect_files(root: &Path, relative: &Path, files: &mut Vec<PathBuf>) -> Result<()> {
+    for entry in fs::read_dir(root.join(relative))? {
+        let entry = entry?;
+        let path = relative.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if entry.file_name() != ".git" {
+                collect_files(root, &path, files)?;
+            }
+        } else if file_type.is_file() {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+fn copy_tree(from: &Path, to: &Path) -> Result<()> {
+    fs::create_dir_all(to)?;
+    for entry in fs::read_dir(from)? {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        let target = to.join(entry.file_name());
+        if file_type.is_dir() {
+            if entry.file_name() != ".git" {
+                copy_tree(&entry.path(), &target)?;
+            }
+        } else if file_type.is_file() {
+            // Keeps the mode, so scripts stay executable
+            fs::copy(entry.path(), &target)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_name_from_url() {
+        assert_eq!(name_from_url("https://github.com/me/git-helpers.git"), "git-helpers");
+        assert_eq!(name_from_url("git@github.com:me/k8s_hooks"), "k8s_hooks");
+        assert_eq!(name_from_url("file:///srv/packs/ops/"), "ops");
+    }
+
+    #[test]
+    fn test_fetch_and_install_a_pack() {
+        let repo = tempfile::tempdir().unwrap();
+        fs::write(
+            repo.path().join(PACK_FILE),
+            "name = \"git-helpers\"\ndescription = \"Git helpers\"\n\n[[hooks]]\nname = \"status\"\nkey_combination = \"alt+g\"\naction = \"cmd:{pack}/status.sh\"\nenabled = true\nconfrim = \"Sure?\"\n",
+        )
+        .unwrap();
+        fs::write(repo.path().join("status.sh"), "#!/bin/sh\ngit status\n").unwrap();
+        let path = repo.path().to_string_lossy().to_string();
+        for args in [
+            vec!["init", "--quiet", &path],
+            vec!["-C", &path, "add", "."],
+            vec!["-C", &path, "-c", "user.name=t", "-c", "user.email=t@t", "commit", "--quiet", "-m", "pack"],
+        ] {
+            git(&args).unwrap();
+        }
+
+        let pack = FetchedPack::fetch(&format!("file://{}/", path), None).unwrap();
+        assert_eq!(pack.name, "git-helpers");
+        assert_eq!(pack.checksum, checksum(repo.path()).unwrap());
+        pack.verify_checksum(&pack.checksum.to_uppercase()).unwrap();
+        assert!(pack.verify_checksum("00").is_err());
+        // Values git would read as options are refused before it runs
+        let error = FetchedPack::fetch("--upload-pack=touch pwned", None).unwrap_err();
+        assert!(error.to_string().contains("may not start with -"), "{}", error);
+        assert!(FetchedPack::fetch(&format!("file://{}/", path), Some("--orphan")).is_err());
+
+        let existing = HookConfig {
+            name: "mine".to_string(),
+            key_combination: "Alt+G".to_string(),
+            action: "builtin:ask".into(),
+            enabled: true,
+            ..Default::default()
+        };
+        let summary = pack.summary(&[existing]);
+        assert!(summary.contains("alt+g"));
+        assert!(summary.contains("status -> cmd:{pack}/status.sh [already bound to mine]"));
+        assert!(summary.contains("Left out as unknown: hooks[0].confrim (did you mean confirm?)"), "{}", summary);
+
+        let packs = tempfile::tempdir().unwrap();
+        let installed = pack.install(packs.path()).unwrap();
+        assert!(!installed.join(".git").exists());
+        let (hooks, errors) = installed_hooks(packs.path()).unwrap();
+        assert!(errors.is_empty());
+        assert_eq!(hooks.len(), 1);
+        assert_eq!(hooks[0].name, format!("{}/status", pack.name));
+        assert_eq!(hooks[0].principal, Some(Principal::Pack(pack.name.clone())));
+        assert_eq!(hooks[0].action.to_string(), format!("cmd:{}/status.sh", installed.display()));
+
+        // A broken pack is left out rather than keeping the others from loading
+        let broken = packs.path().join("broken");
+        fs::create_dir(&broken).unwrap();
+        fs::write(broken.join(PACK_FILE), "hooks = 3\n").unwrap();
+        let (hooks, errors) = installed_hooks(packs.path()).unwrap();
+        assert_eq!(hooks.len(), 1);
+        assert!(format!("{:#}", errors[0]).contains("hook pack broken"), "{:#}", errors[0]);
+        fs::remove_dir_all(&broken).unwrap();
+
+        remove(packs.path(), &pack.name).unwrap();
+        assert!(installed_hooks(packs.path()).unwrap().0.is_empty());
+        assert!(remove(packs.path(), "../etc").is_err());
+    }
+}
@@ -1,9 +1,53 @@
-use crate::config::HookConfig;
-use crate::terminal::KeyInput;
-use crate::window::WindowManager;
+use crate::actions::{Action, ActionRegistry, Capability};
+use crate::bugreport;
+use crate::clock::{self, Clock, SystemClock};
+use crate::companion::{Companion, Event, Message, Response};
+use crate::config::{expand_home, set_hook_enabled, Config, HistoryConfig, HookCondition, HookConfig, HookEvent, HookOutput, Interval, LlmConfig, OnSelect, ShellConfig, UnknownKey};
+use crate::errors::{debug_log_path, Error, ErrorLog};
+use crate::i18n::{tr, tr_args};
+use crate::database;
+use crate::diff::{self, DiffLine, EnvChange};
+use crate::highlight::Highlighter;
+use crate::history;
+use crate::hookpack;
+use crate::llm;
+use crate::notepad::{self, NOTES_PLACEHOLDER};
+use crate::lua;
+use crate::output::{capture_markers, Capture, CommandMark};
+use crate::pane::Layout;
+use crate::permissions::{Permission, Permissions, Principal};
+use crate::plugin::{Plugin, Reply};
+use crate::repl::{ReplKind, SessionMode};
+use crate::screen::Snapshot;
+use crate::scrollback::Scrollback;
+use crate::process;
+use crate::project::Project;
+use crate::structured::{self, MenuItem, Request};
+use crate::shell::{last_command, parse_env_output, single_quote, ShellDialect};
+use crate::tail::Tail;
+use crate::watch::{self, DEFAULT_INTERVAL};
+use crossterm::event::{KeyCode, KeyModifiers};
+use crossterm::style::Color;
+use crate::terminal::{clipboard_sequence, split_chord, split_trigger, KeyInput, Press};
+use crate::text;
+use crate::transcript::{self, TranscriptFile};
+use crate::window::{ChatMessage, Note, PopupHandle, Style, WindowManager, LIVE_POPUP_DELAY, TOAST_LIFETIME};
 use anyhow::{Context, Result};
-use std::collections::HashMap;
-use std::process::{Command, Stdio};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+
+/// How long the prefix of a chord waits for its second key unless
+/// `[keys] chord_timeout_ms` says otherwise.
+const DEFAULT_CHORD_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Builtins that type into the shell, held back in full-screen programs
+/// like `inject:` actions.
+const TYPING_BUILTINS: &[&str] = &["paste_clipboard", "undo_input", "convert_time"];
 
 pub type HookAction = Box<dyn Fn(&KeyInput) -> Result<bool> + Send + Sync>;
 
@@ -12,260 +56,2925 @@ pub enum ActionType {
     Command(String),
     Function(String),
     Builtin(String),
+    /// Follow a file, or a command's output after `!`, in an overlay.
+    Tail(String),
+    /// Type text into the shell as if the user had, escapes expanded.
+    Inject(String),
+    /// Run a Lua script, from a file or written inline.
+    Lua(String),
+    /// Pass the key to a WebAssembly plugin and run what it replies.
+    Plugin(String),
+    /// Pass the key to a companion process and do what it answers.
+    Companion(String),
+    /// Show text in a popup, escapes expanded.
+    Popup(String),
+    /// Show text as a desktop notification, escapes expanded.
+    Notify(String),
+}
+
+/// How far a step of a hook's action got.
+enum Step {
+    /// It ran, consuming the key or not, and the next step can run.
+    Continue(bool),
+    /// The steps after it don't run now: they wait for its command to
+    /// succeed, or it was cancelled.
+    Stop,
+}
+
+/// Side effects a hook asks the running session to perform once the hook
+/// has returned.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionAction {
+    SetEnv { key: String, value: String },
+    /// Replace the running shell with a new one.
+    SwitchShell(ShellConfig),
+    /// Type text into the shell as if the user had.
+    SendInput(String),
+    /// Paste text into the shell, marked as a paste while it has bracketed
+    /// paste on.
+    Paste(String),
+    /// Type text once the shell has printed its next prompt.
+    SendInputAtPrompt(String),
+    /// Erase what was last typed on the user's behalf, unless it has been
+    /// run or edited since.
+    UndoInput,
+    /// Type `input` and collect the output printed between the markers of
+    /// capture `id`.
+    Capture { id: u64, input: String },
+    /// Run `command` every `interval` outside the shell and show its output
+    /// in a corner panel, replacing any running watch.
+    Watch { command: String, interval: Duration },
+    StopWatch,
+    /// Mark the current point of the session for `chatshell export`.
+    Bookmark(Option<String>),
+    /// Run a `cmd:` action in the background and show what it printed
+    /// once it is done.
+    RunCommand(HookCommand),
+    /// Ask the `[llm]` in the background, and answer with
+    /// `HookManager::llm_reply` once it is done.
+    AskLlm(LlmRequest),
+    /// List the hooks to turn them on or off, with `HookManager::manage_hooks`.
+    ManageHooks,
+    /// Pick an action or a hook to run, with `HookManager::show_palette`.
+    ShowPalette,
+    /// Show the help with the keys bound now, with `HookManager::show_help`.
+    ShowHelp(HookOutput),
+    /// Search the shell's output, with `HookManager::search_scrollback`.
+    SearchScrollback,
+    /// Browse the shell's output to copy or comment on lines, with
+    /// `HookManager::copy_mode`.
+    CopyMode,
+    /// Bind `key` to `action` until the session ends, or `lifetime` is up.
+    BindTemporary { key: String, action: String, lifetime: Option<Duration> },
+    /// Open the auxiliary pane configured in `[pane]`.
+    OpenPane,
+    /// Send keys to the pane instead of the shell, or back.
+    FocusPane,
+    ClosePane,
+    /// Open the chat view beside the shell, or close it.
+    ToggleChat,
+    /// Print text in the split pane, opening it first if need be.
+    ShowInPane { title: String, text: String },
+    /// Keep a popup pinned with `p`, or a toast, drawn in a free corner of
+    /// the screen.
+    PinNote(Note),
+    /// Move the newest pinned note to the next corner.
+    MoveNote,
+    CloseNote,
+}
+
+/// The command of a `cmd:` action, run with `/bin/sh` outside the shell so
+/// the session keeps going while it does.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct HookCommand {
+    pub command: String,
+    /// Variables set for the command only.
+    pub env: Vec<(String, String)>,
+    /// Show nothing unless the command prints something or fails.
+    pub quiet: bool,
+    /// Where what it printed goes.
+    pub output: HookOutput,
+    /// How long it may run before it is killed.
+    pub timeout: Option<Duration>,
+    /// The hook that runs it, as whom what it asks for runs.
+    pub hook: String,
+    /// The step its hook's chain goes on with once it has succeeded.
+    pub then: Option<usize>,
+    /// Read what it prints as JSON requests, see `structured::Request`.
+    pub structured: bool,
+    /// What runs with a choice picked in a menu it asks for.
+    pub on_select: Option<OnSelect>,
+    /// Written to its standard input, which is empty otherwise.
+    pub input: Option<String>,
 }
 
+/// Why a `cmd:` action was killed before it finished.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stopped {
+    TimedOut,
+    /// ESC was pressed while it ran.
+    Cancelled,
+}
+
+/// A finished `cmd:` action.
 #[derive(Debug)]
-pub struct HookManager {
-    hooks: HashMap<String, Hook>,
-    window_manager: WindowManager,
+pub struct CommandResult {
+    pub command: HookCommand,
+    pub output: Result<std::process::Output>,
+    pub stopped: Option<Stopped>,
+}
+
+impl HookCommand {
+    pub async fn run(self) -> CommandResult {
+        self.run_until(std::future::pending(), None).await
+    }
+
+    /// True if what the command prints can be shown while it runs, as it
+    /// pops up in the end.
+    pub fn streams(&self) -> bool {
+        self.output == HookOutput::Popup && !self.structured
+    }
+
+    /// Runs the command until it finishes, runs out of time or `cancelled`
+    /// completes. A command that is stopped is killed with everything it
+    /// started, as it runs in a process group of its own. What it prints
+    /// goes to `popup` as well while it runs, if there is one.
+    pub async fn run_until(self, cancelled: impl std::future::Future<Output = ()>, popup: Option<&PopupHandle>) -> CommandResult {
+        let child = tokio::process::Command::new("/bin/sh")
+            .arg("-c")
+            .arg(&self.command)
+            .envs(self.env.iter().map(|(key, value)| (key, value)))
+            .stdin(if self.input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .process_group(0)
+            .kill_on_drop(true)
+            .spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                let output = Err(e).with_context(|| format!("Failed to execute command: {}", self.command));
+                return CommandResult { command: self, output, stopped: None };
+            }
+        };
+        if let (Some(mut stdin), Some(input)) = (child.stdin.take(), self.input.clone()) {
+            // A command that doesn't read it all must not block on it
+            tokio::spawn(async move {
+                use tokio::io::AsyncWriteExt;
+                let _ = stdin.write_all(input.as_bytes()).await;
+            });
+        }
+        let pid = child.id();
+        let limit = async {
+            match self.timeout {
+                Some(timeout) => tokio::time::sleep(timeout).await,
+                None => std::future::pending().await,
+            }
+        };
+        let stdout = popup.and_then(|_| child.stdout.take());
+        let finished = async {
+            match (stdout, popup) {
+                (Some(stdout), Some(popup)) => {
+                    let (streamed, output) = tokio::join!(stream_output(stdout, popup), child.wait_with_output());
+                    output.map(|output| std::process::Output { stdout: streamed, ..output })
+                }
+                _ => child.wait_with_output().await,
+            }
+        };
+        let (output, stopped) = tokio::select! {
+            output = finished => {
+                (output.with_context(|| format!("Failed to execute command: {}", self.command)), None)
+            }
+            _ = limit => (Err(anyhow::anyhow!("Timed out: {}", self.command)), Some(Stopped::TimedOut)),
+            _ = cancelled => (Err(anyhow::anyhow!("Cancelled: {}", self.command)), Some(Stopped::Cancelled)),
+        };
+        if let (Some(pid), Some(_)) = (pid, stopped) {
+            let _ = nix::sys::signal::killpg(nix::unistd::Pid::from_raw(pid as i32), nix::sys::signal::Signal::SIGKILL);
+        }
+        CommandResult { command: self, output, stopped }
+    }
+}
+
+/// Reads all of `stdout`, passing it on to `popup` as it comes once the
+/// command has run for `LIVE_POPUP_DELAY`.
+async fn stream_output(mut stdout: tokio::process::ChildStdout, popup: &PopupHandle) -> Vec<u8> {
+    use tokio::io::AsyncReadExt;
+    let shown = tokio::time::Instant::now() + LIVE_POPUP_DELAY;
+    let mut collected = Vec::new();
+    let mut passed = 0;
+    let mut buffer = [0; 4096];
+    loop {
+        let waiting = passed == 0 && tokio::time::Instant::now() < shown;
+        tokio::select! {
+            read = stdout.read(&mut buffer) => match read {
+                Ok(0) | Err(_) => break,
+                Ok(n) => collected.extend_from_slice(&buffer[..n]),
+            },
+            // Show what came before the delay even if nothing follows
+            _ = tokio::time::sleep_until(shown), if waiting => {}
+        }
+        if tokio::time::Instant::now() >= shown {
+            let end = passed + text::whole_chars(&collected[passed..]);
+            if end > passed {
+                popup.append(&String::from_utf8_lossy(&collected[passed..end]));
+                passed = end;
+            }
+        }
+    }
+    collected
+}
+
+impl CommandResult {
+    /// True if the command ran to the end and exited with 0.
+    pub fn succeeded(&self) -> bool {
+        self.stopped.is_none() && self.output.as_ref().is_ok_and(|output| output.status.success())
+    }
+
+    pub fn failed(&self) -> bool {
+        self.stopped == Some(Stopped::TimedOut) || self.output.as_ref().is_ok_and(|output| !output.status.success())
+    }
+
+    /// Whether the command succeeded without printing anything.
+    fn printed_nothing(&self) -> bool {
+        self.succeeded() && self.output.as_ref().is_ok_and(|output| String::from_utf8_lossy(&output.stdout).trim().is_empty())
+    }
+
+    /// What to show for the result, if anything. A command cancelled with
+    /// ESC shows nothing.
+    pub fn content(&self) -> Result<Option<String>> {
+        match (self.stopped, self.command.timeout) {
+            (Some(Stopped::TimedOut), Some(timeout)) => {
+                let seconds = format!("{:.1}", timeout.as_secs_f64());
+                return Ok(Some(tr_args("command-timed-out", &[("seconds", seconds.into())])));
+            }
+            (Some(_), _) => return Ok(None),
+            (None, _) => {}
+        }
+        let output = self.output.as_ref().map_err(|e| anyhow::anyhow!("{:#}", e))?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Ok(Some(tr_args("command-failed", &[("stderr", stderr.into())])));
+        }
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        Ok(match stdout.trim() {
+            "" if self.command.quiet => None,
+            "" => Some(tr("command-no-output")),
+            text => Some(text.to_string()),
+        })
+    }
 }
 
+/// A question to the `[llm]`, answered on a thread of its own while the
+/// session goes on. The reply streams into the chat view if it was open
+/// when asked, or else into a live popup.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LlmRequest {
+    pub llm: LlmConfig,
+    pub history: HistoryConfig,
+    pub system_prompt: String,
+    pub prompt: String,
+    /// What asked, as the usage history names it.
+    pub purpose: String,
+    /// The title of the popup the reply is shown in.
+    pub title: String,
+    /// The question as the chat and the transcript keep it.
+    pub question: String,
+    pub in_chat: bool,
+}
+
+/// What the `[llm]` answered to a request.
 #[derive(Debug)]
-pub struct Hook {
-    pub config: HookConfig,
-    pub action: ActionType,
+pub struct LlmReply {
+    pub request: LlmRequest,
+    pub reply: Result<String>,
 }
 
-impl Hook {
-    pub fn new(config: HookConfig) -> Self {
-        let action = Self::parse_action(&config.action);
-        Hook { config, action }
+impl LlmRequest {
+    /// Runs the LLM command to the end, handing what it writes to
+    /// `on_output` as it comes: right away in the chat, and in a popup once
+    /// it has taken `LIVE_POPUP_DELAY`. Blocks, so it runs off the session.
+    pub fn run(self, mut on_output: impl FnMut(&str)) -> LlmReply {
+        let shown = Instant::now() + LIVE_POPUP_DELAY;
+        let mut held = String::new();
+        let reply = llm::stream(&self.llm, &self.system_prompt, &self.prompt, |text| {
+            held.push_str(text);
+            if self.in_chat || Instant::now() >= shown {
+                on_output(&std::mem::take(&mut held));
+            }
+        });
+        LlmReply { request: self, reply }
     }
+}
 
-    fn parse_action(action_str: &str) -> ActionType {
-        if action_str.starts_with("cmd:") {
-            ActionType::Command(action_str[4..].to_string())
-        } else if action_str.starts_with("fn:") {
-            ActionType::Function(action_str[3..].to_string())
-        } else if action_str.starts_with("builtin:") {
-            ActionType::Builtin(action_str[8..].to_string())
-        } else {
-            // Default to command
-            ActionType::Command(action_str.to_string())
+/// What to do with the output of a capture once it has arrived.
+#[derive(Debug, Clone, PartialEq)]
+enum CaptureFollowUp {
+    ExplainQuery { query: String, error: Option<String> },
+    RerunDiff { command: String, previous: Vec<String> },
+    EnvDiff,
+}
+
+/// Everything a hook can use while it executes.
+#[derive(Debug)]
+pub struct HookContext {
+    pub window_manager: WindowManager,
+    pub config_path: Option<String>,
+    /// What the shell is running, kept up to date by the session.
+    pub session_mode: SessionMode,
+    /// The line the shell's cursor is on, normally its prompt.
+    pub current_line: String,
+    /// The last lines the shell printed, oldest first.
+    pub recent_lines: Vec<String>,
+    /// True while a program or command has the shell's terminal.
+    pub shell_busy: bool,
+    /// The shell's process id; None when the session runs a serial device.
+    pub shell_pid: Option<i32>,
+    /// The process group that has the shell's terminal.
+    pub foreground_pid: Option<i32>,
+    /// The shell's working directory.
+    pub cwd: Option<PathBuf>,
+    /// Recent screens of the shell, oldest first.
+    pub snapshots: Vec<Snapshot>,
+    /// True while a full-screen program has the alternate screen.
+    pub alternate_screen: bool,
+    /// Where errors are logged before they are shown.
+    pub errors: ErrorLog,
+    /// The plugins loaded from the plugins directory, by name.
+    pub plugins: HashMap<String, Plugin>,
+    /// The companion processes still running, by name.
+    pub companions: HashMap<String, Companion>,
+    /// What plugins and hook packs were allowed to do.
+    pub permissions: Permissions,
+    /// The plugin or hook pack whose hook is running, if not the user's.
+    principal: Option<Principal>,
+    /// The hook whose actions are running, and whether its `when` names
+    /// `alt_screen`; text it types is held back in full-screen programs
+    /// unless it does.
+    running_hook: Option<(String, bool)>,
+    actions: Vec<SessionAction>,
+    next_capture_id: u64,
+    pending_captures: HashMap<u64, CaptureFollowUp>,
+    // The command last run by rerun_and_diff and its output
+    last_rerun: Option<(String, Vec<String>)>,
+    watching: bool,
+    /// Where the time comes from, for time windows and timestamps.
+    pub clock: Arc<dyn Clock>,
+    /// The file with the notes of the session, for `builtin:notes`.
+    pub notes_path: Option<PathBuf>,
+    /// The session's transcript, which the shell's output and questions
+    /// to the assistant with its answers go into.
+    pub transcript: Option<TranscriptFile>,
+}
+
+impl HookContext {
+    pub fn new(window_manager: WindowManager) -> Self {
+        HookContext {
+            window_manager,
+            config_path: None,
+            session_mode: SessionMode::default(),
+            current_line: String::new(),
+            recent_lines: Vec::new(),
+            shell_busy: false,
+            shell_pid: None,
+            foreground_pid: None,
+            cwd: None,
+            snapshots: Vec::new(),
+            alternate_screen: false,
+            errors: ErrorLog::default(),
+            plugins: HashMap::new(),
+            companions: HashMap::new(),
+            permissions: Permissions::default(),
+            principal: None,
+            running_hook: None,
+            actions: Vec::new(),
+            next_capture_id: 1,
+            pending_captures: HashMap::new(),
+            last_rerun: None,
+            watching: false,
+            clock: Arc::new(SystemClock),
+            notes_path: None,
+            transcript: None,
         }
     }
 
-    pub fn matches(&self, key: &KeyInput) -> bool {
-        if !self.config.enabled {
-            return false;
+    /// The notes of the session, empty if it has none.
+    pub fn notes(&self) -> Result<String> {
+        match &self.notes_path {
+            Some(path) => notepad::load(path),
+            None => Ok(String::new()),
+        }
+    }
+
+    /// Asks the LLM in the background, for `answer` to show the reply. The
+    /// chat keeps it under `question`, and shows it as it comes while open.
+    fn ask_llm(&mut self, config: &Config, purpose: &str, system_prompt: String, prompt: String, title: &str, question: &str) -> Result<()> {
+        self.window_manager.chat.ask(question);
+        self.push_action(SessionAction::AskLlm(LlmRequest {
+            llm: config.llm.clone(),
+            history: config.history.clone(),
+            system_prompt,
+            prompt,
+            purpose: purpose.to_string(),
+            title: title.to_string(),
+            question: question.to_string(),
+            in_chat: self.window_manager.chat.is_open(),
+        }));
+        self.window_manager.draw_chat()
+    }
+
+    /// Shows what the LLM answered to a request of `ask_llm`, keeps it in
+    /// the chat, the usage history and the transcript, and offers to run
+    /// the command it suggests.
+    fn answer(&mut self, reply: LlmReply) -> Result<()> {
+        let LlmReply { request, reply } = reply;
+        let reply = self.keep_in_chat(reply);
+        self.window_manager.draw_chat()?;
+        let reply = match reply {
+            Ok(reply) => reply,
+            Err(e) => {
+                self.report_error(Error::Llm(e));
+                return Ok(());
+            }
+        };
+        history::record_llm_usage(&request.history, &request.purpose, &[&request.system_prompt, &request.prompt], &reply);
+        self.record_exchange(&request.question, &reply);
+        self.show_reply(&request.title, &reply)?;
+        self.offer_suggestion(&reply)
+    }
+
+    /// Puts the whole of `reply` in the chat, or takes the question out if
+    /// there is none.
+    fn keep_in_chat(&mut self, reply: Result<String>) -> Result<String> {
+        let chat = &mut self.window_manager.chat;
+        match &reply {
+            Ok(reply) => {
+                if let Some(message) = chat.messages.back_mut() {
+                    message.answer = reply.clone();
+                }
+            }
+            Err(_) => chat.discard_last(),
         }
-        key.matches_pattern(&self.config.key_combination)
+        reply
     }
 
-    pub fn execute(&self, key: &KeyInput, window_manager: &mut WindowManager) -> Result<bool> {
-        match &self.action {
-            ActionType::Command(cmd) => self.execute_command(cmd, window_manager),
-            ActionType::Function(func_name) => self.execute_function(func_name, key, window_manager),
-            ActionType::Builtin(builtin_name) => self.execute_builtin(builtin_name, key, window_manager),
+    /// Shows an answer of the assistant in a popup, unless the chat view
+    /// already shows it.
+    fn show_reply(&mut self, title: &str, reply: &str) -> Result<()> {
+        if self.window_manager.chat.is_open() {
+            return Ok(());
         }
+        self.window_manager.show_markdown_popup(title, reply)
     }
 
-    fn execute_command(&self, cmd: &str, window_manager: &mut WindowManager) -> Result<bool> {
-        let output = Command::new("/bin/sh")
-            .arg("-c")
-            .arg(cmd)
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .with_context(|| format!("Failed to execute command: {}", cmd))?;
+    /// Adds a question to the assistant and its answer to the session's
+    /// transcript, if one is kept.
+    fn record_exchange(&mut self, question: &str, answer: &str) {
+        let exchange = transcript::Entry::Exchange { question: question.to_string(), answer: answer.to_string() };
+        self.transcribe(&exchange);
+    }
 
-        let content = if !output.status.success() {
-            let stderr = String::from_utf8_lossy(&output.stderr);
-            format!("Command failed:\n{}", stderr)
-        } else {
-            let stdout = String::from_utf8_lossy(&output.stdout);
-            if stdout.trim().is_empty() {
-                "Command executed successfully (no output)".to_string()
-            } else {
-                stdout.trim().to_string()
+    /// Adds `entry` to the session's transcript, if one is kept. One that
+    /// fails is given up for the rest of the session.
+    pub fn transcribe(&mut self, entry: &transcript::Entry) {
+        let Some(transcript) = self.transcript.as_mut() else {
+            return;
+        };
+        if let Err(e) = transcript.append(entry) {
+            self.transcript = None;
+            self.report_background_error(Error::Config(e.context("The transcript is off")));
+        }
+    }
+
+    /// The system prompt for the session, with its notes when `[llm]
+    /// include_notes` is on.
+    fn system_prompt(&self, config: &Config) -> String {
+        let mut prompt = llm::system_prompt(&config.llm, self.session_mode);
+        if config.llm.include_notes {
+            if let Some(notes) = notepad::llm_context(&self.notes().unwrap_or_default()) {
+                prompt.push_str("\n\n");
+                prompt.push_str(&notes);
+            }
+        }
+        prompt
+    }
+
+    /// True if the shell is waiting at its prompt for a new command.
+    pub fn at_prompt(&self) -> bool {
+        !self.shell_busy && !self.session_mode.at_continuation(&self.current_line)
+    }
+
+    /// True if the session is as `when` says a hook needs, so that it may
+    /// run.
+    pub fn allows(&self, when: &HookCondition) -> bool {
+        if when.at_prompt && !self.at_prompt() {
+            return false;
+        }
+        if let Some(pattern) = &when.cwd {
+            let pattern = expand_home(pattern);
+            if !self.cwd.as_ref().is_some_and(|cwd| glob_matches(&pattern, &cwd.to_string_lossy())) {
+                return false;
+            }
+        }
+        if let Some(name) = &when.inside {
+            if !self.cwd.as_ref().is_some_and(|cwd| cwd.ancestors().any(|dir| dir.join(name).exists())) {
+                return false;
             }
+        }
+        if !when.env.is_empty() {
+            let pid = self.foreground_pid.or(self.shell_pid);
+            let environ = pid.and_then(|pid| process::environ(pid).ok()).unwrap_or_default();
+            if !when.env.iter().all(|name| environ.get(name).is_some_and(|value| !value.is_empty())) {
+                return false;
+            }
+        }
+        if !when.program.is_empty() {
+            let name = self.foreground_pid.and_then(process::name);
+            if !name.is_some_and(|name| when.program.contains(&name)) {
+                return false;
+            }
+        }
+        if when.alt_screen.is_some_and(|open| open != self.alternate_screen) {
+            return false;
+        }
+        when.time.is_none_or(|range| range.contains(self.clock.local().time()))
+    }
+
+    /// True if `hook` would type text into a full-screen program, which
+    /// would read it as commands, without its `when` saying it should.
+    fn held_back_by_full_screen(&self, hook: &Hook) -> bool {
+        self.alternate_screen && hook.config.when.alt_screen.is_none() && hook.types_text()
+    }
+
+    /// Logs `error` and shows it with its reference in the log.
+    pub fn report_error(&mut self, error: Error) {
+        let reference = self.errors.record(&error);
+        if let Err(e) = self.window_manager.show_error(&error, &reference, self.errors.path()) {
+            // Nowhere left to show it; the log has it
+            self.errors.record(&Error::Ui(e));
+        }
+    }
+
+    /// Like `report_error`, for errors of work done in the background, which
+    /// can repeat with every chunk of output. Repeats are only logged.
+    pub fn report_background_error(&mut self, error: Error) {
+        if self.errors.first_time(&error) {
+            self.report_error(error);
+        } else {
+            self.errors.record(&error);
+        }
+    }
+
+    /// Fails unless the running hook may do what `permission` covers. The
+    /// user's own hooks may do anything; a plugin or hook pack is asked
+    /// about the first time.
+    pub fn require(&mut self, permission: Permission) -> Result<()> {
+        let Some(principal) = self.principal.clone() else {
+            return Ok(());
         };
+        let allowed = match self.permissions.decision(&principal, permission) {
+            Some(allowed) => allowed,
+            None => self.ask_permission(&principal, permission)?,
+        };
+        if !allowed {
+            anyhow::bail!(tr_args("permission-denied", &[
+                ("who", principal.describe().into()),
+                ("what", tr(permission.message()).into()),
+            ]));
+        }
+        Ok(())
+    }
 
-        // Show result in popup window
-        window_manager.show_popup(&format!("Command: {}", cmd), &content)?;
+    /// Asks the user whether `principal` may do what `permission` covers.
+    /// ESC refuses until the session ends, and it is asked again next time.
+    pub fn ask_permission(&mut self, principal: &Principal, permission: Permission) -> Result<bool> {
+        let title = tr_args("permission-prompt", &[
+            ("who", principal.describe().into()),
+            ("what", tr(permission.message()).into()),
+        ]);
+        let items = [tr("permission-allow"), tr("permission-deny")];
+        let Some(choice) = self.window_manager.show_menu(&title, &items)? else {
+            self.permissions.refuse_for_session(principal, permission);
+            return Ok(false);
+        };
+        let allowed = choice == 0;
+        self.permissions.record(principal, permission, allowed)?;
+        Ok(allowed)
+    }
 
-        // Return true to indicate the hook consumed the key event
-        Ok(true)
+    pub fn push_action(&mut self, action: SessionAction) {
+        if let (SessionAction::SendInput(_), true, Some((hook, false))) = (&action, self.alternate_screen, &self.running_hook) {
+            let text = tr_args("full-screen-input-held-back", &[("hook", hook.as_str().into())]);
+            let note = Note::toast(&tr("full-screen-title"), &text, self.clock.now());
+            if !matches!(self.actions.last(), Some(SessionAction::PinNote(last)) if last.lines == note.lines) {
+                self.actions.push(SessionAction::PinNote(note));
+            }
+            return;
+        }
+        self.actions.push(action);
     }
 
-    fn execute_function(&self, func_name: &str, _key: &KeyInput, window_manager: &mut WindowManager) -> Result<bool> {
-        match func_name {
-            "show_help" => {
-                let content = "=== ChatShell Help ===\n\nThis is a transparent shell wrapper.\nAll keystrokes are passed through to the underlying shell.\n\nSpecial key combinations can trigger hooks:\n- Ctrl+; : Show this help\n- Ctrl+T : Show current time\n- Ctrl+Shift+C : Show config info\n\nPress ESC to close this window.";
-                window_manager.show_popup("Help", content)?;
-                Ok(true)
+    /// Runs `f` as part of `hook`, unless another hook already is.
+    fn as_hook<T>(&mut self, hook: &HookConfig, f: impl FnOnce(&mut Self) -> T) -> T {
+        let running = self.running_hook.clone().or_else(|| Some((hook.name.clone(), hook.when.alt_screen.is_some())));
+        let outer = std::mem::replace(&mut self.running_hook, running);
+        let result = f(self);
+        self.running_hook = outer;
+        result
+    }
+
+    /// Sends the result of a hook where its `output` says.
+    pub fn deliver(&mut self, output: &HookOutput, title: &str, content: &str) -> Result<()> {
+        match output {
+            HookOutput::Popup => self.window_manager.show_popup(title, content),
+            HookOutput::Inline => self.window_manager.print_inline(title, content),
+            // A program running in the shell would read it instead
+            HookOutput::Inject if self.shell_busy => self.window_manager.show_popup(title, content),
+            HookOutput::Inject => {
+                // On one line, so that nothing runs before the user says so
+                let text = content.split_whitespace().collect::<Vec<_>>().join(" ");
+                self.push_action(SessionAction::SendInput(text));
+                Ok(())
             }
-            "show_time" => {
-                let now = chrono::Utc::now();
-                let content = format!("Current time:\n{}\n\nLocal time:\n{}", 
-                    now.format("%Y-%m-%d %H:%M:%S UTC"),
-                    chrono::Local::now().format("%Y-%m-%d %H:%M:%S %Z"));
-                window_manager.show_popup("Current Time", &content)?;
-                Ok(true)
+            HookOutput::File(path) => append_result(&expand_home(path), &self.clock.local(), title, content),
+            HookOutput::Notify => self.window_manager.show_notification(title, content),
+            HookOutput::Toast => {
+                self.push_action(SessionAction::PinNote(Note::toast(title, content, self.clock.now())));
+                Ok(())
             }
-            _ => {
-                let content = format!("Unknown function: {}", func_name);
-                window_manager.show_popup("Error", &content)?;
-                Ok(false)
+            HookOutput::Pane => {
+                self.push_action(SessionAction::ShowInPane { title: title.to_string(), text: content.to_string() });
+                Ok(())
             }
+            HookOutput::Silent => Ok(()),
         }
     }
 
-    fn execute_builtin(&self, builtin_name: &str, _key: &KeyInput, window_manager: &mut WindowManager) -> Result<bool> {
-        match builtin_name {
-            "clear_screen" => {
-                // For clear screen, we don't need a popup - just execute the action
-                print!("\x1B[2J\x1B[H"); // ANSI clear screen and move cursor to home
-                Ok(true)
+    /// Does what a `structured` command of `hook` asked for, in the order
+    /// of the requests and of their fields. A plain menu choice runs
+    /// `on_select` if there is one, instead of being typed.
+    fn run_requests(&mut self, hook: &str, output: &HookOutput, title: &str, on_select: Option<&OnSelect>, requests: Vec<Request>) -> Result<()> {
+        let key = KeyInput::new(KeyCode::Null, KeyModifiers::empty());
+        for request in requests {
+            let title = request.title.as_deref().unwrap_or(title);
+            if let Some(text) = &request.popup {
+                self.deliver(output, title, text)?;
             }
-            "show_config" => {
-                let content = format!("=== Current Hook Configuration ===\n\nName: {}\nKey: {}\nAction: {}\nEnabled: {}\n{}",
-                    self.config.name,
-                    self.config.key_combination,
-                    self.config.action,
-                    self.config.enabled,
-                    self.config.description.as_ref().map(|d| format!("Description: {}", d)).unwrap_or_default()
-                );
-                window_manager.show_popup("Configuration", &content)?;
-                Ok(true)
+            if let Some(text) = &request.notify {
+                self.window_manager.show_notification(title, text)?;
+            }
+            if let Some(text) = request.send_keys {
+                self.push_action(SessionAction::SendInput(text));
+            }
+            if let Some(items) = request.menu {
+                let labels: Vec<String> = items.iter().map(|item| item.label().to_string()).collect();
+                if let Some(index) = self.window_manager.show_menu(title, &labels)? {
+                    match &items[index] {
+                        MenuItem::Text(text) => match on_select {
+                            Some(on_select) => {
+                                let (command, input) = on_select.command_for(text);
+                                self.push_action(SessionAction::RunCommand(HookCommand {
+                                    command,
+                                    env: vec![("CHATSHELL_SELECTION".to_string(), text.clone())],
+                                    quiet: true,
+                                    output: output.clone(),
+                                    hook: hook.to_string(),
+                                    input,
+                                    ..Default::default()
+                                }));
+                            }
+                            None => self.push_action(SessionAction::SendInput(text.clone())),
+                        },
+                        MenuItem::Choice(choice) => {
+                            if let Some(text) = &choice.send_keys {
+                                self.push_action(SessionAction::SendInput(text.clone()));
+                            }
+                            if let Some(action) = &choice.action {
+                                run_action(hook, action, &key, self)?;
+                            }
+                        }
+                    }
+                }
+            }
+            if let Some(action) = &request.action {
+                run_action(hook, action, &key, self)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs `commands` in the shell and calls `follow_up` with their output.
+    fn start_capture(&mut self, commands: &[String], follow_up: CaptureFollowUp) {
+        let id = self.next_capture_id;
+        self.next_capture_id += 1;
+        let (begin, end) = capture_markers(id);
+        let input = self.session_mode.marked_input(commands, &begin, &end);
+        self.pending_captures.insert(id, follow_up);
+        self.push_action(SessionAction::Capture { id, input });
+    }
+
+    fn finish_capture(&mut self, follow_up: CaptureFollowUp, lines: Vec<String>) -> Result<()> {
+        match follow_up {
+            CaptureFollowUp::ExplainQuery { query, error } => {
+                let schema: Vec<String> = lines
+                    .into_iter()
+                    .filter(|line| !line.contains("Pager usage is"))
+                    .collect();
+                self.explain_query(&query, error.as_deref(), &schema.join("\n"))
+            }
+            CaptureFollowUp::RerunDiff { command, previous } => {
+                let changes = diff::diff_lines(&previous, &lines);
+                self.last_rerun = Some((command.clone(), lines));
+                self.show_diff(&command, changes)
             }
-            "toggle_hook" => {
-                let content = "Hook toggle not implemented in this context";
-                window_manager.show_popup("Toggle Hook", content)?;
-                Ok(false)
+            CaptureFollowUp::EnvDiff => self.show_env_diff(&parse_env_output(&lines), true),
+        }
+    }
+
+    /// Shows how the shell's environment differs from chatshell's own:
+    /// `live` when it is what the shell has now, not what it started with.
+    fn show_env_diff(&mut self, shell_env: &BTreeMap<String, String>, live: bool) -> Result<()> {
+        let own: BTreeMap<String, String> = std::env::vars().collect();
+        // `_` is the last command run and always differs
+        let changes: Vec<EnvChange> = diff::diff_env(&own, shell_env)
+            .into_iter()
+            .filter(|change| change.key() != "_")
+            .collect();
+        if changes.is_empty() {
+            return self.window_manager.show_popup(&tr("env-diff-title"), &tr("env-diff-same"));
+        }
+
+        let source = if live { tr("env-diff-live") } else { tr("env-diff-started") };
+        let mut lines: Vec<(String, Option<Color>)> = vec![(source, Some(Color::Cyan)), (String::new(), None)];
+        for change in changes {
+            match change {
+                EnvChange::Added { key, value } => lines.push((format!("+ {}={}", key, value), Some(Color::Green))),
+                EnvChange::Removed { key, value } => lines.push((format!("- {}={}", key, value), Some(Color::Red))),
+                EnvChange::Changed { key, old, new } => {
+                    lines.push((format!("~ {}", key), Some(Color::Yellow)));
+                    match diff::diff_list(&old, &new) {
+                        Some((removed, added)) if removed.is_empty() && added.is_empty() => {
+                            lines.push((format!("    {}", tr("env-diff-reordered")), None));
+                        }
+                        Some((removed, added)) => {
+                            lines.extend(removed.into_iter().map(|entry| (format!("    - {}", entry), Some(Color::Red))));
+                            lines.extend(added.into_iter().map(|entry| (format!("    + {}", entry), Some(Color::Green))));
+                        }
+                        None => {
+                            lines.push((format!("    - {}", old), Some(Color::Red)));
+                            lines.push((format!("    + {}", new), Some(Color::Green)));
+                        }
+                    }
+                }
             }
-            _ => {
-                let content = format!("Unknown builtin: {}", builtin_name);
-                window_manager.show_popup("Error", &content)?;
-                Ok(false)
+        }
+
+        // Values can be long and span lines; show each on one row, cut short
+        let width = self.window_manager.max_content_width();
+        for (line, _) in lines.iter_mut() {
+            let flat = line.replace('\n', "\\n");
+            *line = if flat.chars().count() > width {
+                format!("{}…", flat.chars().take(width - 1).collect::<String>())
+            } else {
+                flat
+            };
+        }
+        let max_lines = self.window_manager.max_content_lines();
+        if lines.len() > max_lines {
+            let hidden = lines.len() - max_lines + 1;
+            lines.truncate(max_lines - 1);
+            lines.push((tr_args("popup-more-lines", &[("count", hidden.into())]), Some(Color::Cyan)));
+        }
+        self.window_manager.show_colored_popup(&tr("env-diff-title"), lines)
+    }
+
+    fn show_diff(&mut self, command: &str, changes: Vec<DiffLine>) -> Result<()> {
+        let title = tr_args("rerun-command-title", &[("command", command.into())]);
+        if !diff::has_changes(&changes) {
+            return self.window_manager.show_popup(&title, &tr("rerun-unchanged"));
+        }
+
+        let mut lines: Vec<(String, Option<Color>)> = diff::condense(changes, 3)
+            .into_iter()
+            .map(|line| match line {
+                DiffLine::Same(text) => (format!("  {}", text), None),
+                DiffLine::Removed(text) => (format!("- {}", text), Some(Color::Red)),
+                DiffLine::Added(text) => (format!("+ {}", text), Some(Color::Green)),
+                DiffLine::Skipped(count) => (tr_args("rerun-skipped", &[("count", count.into())]), Some(Color::Cyan)),
+            })
+            .collect();
+        let max_lines = self.window_manager.max_content_lines();
+        if lines.len() > max_lines {
+            let hidden = lines.len() - max_lines + 1;
+            lines.truncate(max_lines - 1);
+            lines.push((tr_args("popup-more-lines", &[("count", hidden.into())]), Some(Color::Cyan)));
+        }
+        self.window_manager.show_colored_popup(&title, lines)
+    }
+
+    fn explain_query(&mut self, query: &str, error: Option<&str>, schema: &str) -> Result<()> {
+        let config = self.load_config()?;
+        let mut prompt = match error {
+            Some(error) => format!(
+                "This query failed. Explain why and how to fix it.\n\nQuery:\n{}\n\nError:\n{}",
+                query, error
+            ),
+            None => format!("Explain what this query does and how it could be improved.\n\nQuery:\n{}", query),
+        };
+        prompt.push_str(&format!("\n\nSchema:\n{}", schema));
+
+        let system_prompt = self.system_prompt(&config);
+        self.ask_llm(&config, "explain_query", system_prompt, prompt, &tr("explain-title"), query)
+    }
+
+    /// Offers to run or type the first code block of an LLM reply, warning
+    /// first if it looks destructive.
+    fn offer_suggestion(&mut self, reply: &str) -> Result<()> {
+        let mode = self.session_mode;
+        let Some(code) = llm::extract_code_block(reply) else {
+            return Ok(());
+        };
+        let commands = mode.split_commands(&code);
+        let Some((last, rest)) = commands.split_last() else {
+            return Ok(());
+        };
+
+        let risk = commands.iter().find_map(|command| mode.risk(command));
+        let title = match &risk {
+            Some(reason) => tr_args("suggestion-careful", &[("reason", reason.as_str().into())]),
+            None => tr("suggestion-title"),
+        };
+        let items = vec![tr("suggestion-run"), tr("suggestion-type")];
+        let Some(choice) = self.window_manager.show_menu(&title, &items)? else {
+            return Ok(());
+        };
+        // Running something destructive is asked about once more, with
+        // what it runs in full
+        if choice == 0 && risk.is_some() {
+            let message = format!("{}\n\n{}", commands.join("\n"), tr("suggestion-confirm"));
+            if !self.window_manager.show_confirm(&title, &message, false)? {
+                return Ok(());
             }
         }
+
+        let mut input = String::new();
+        for command in rest {
+            input.push_str(&mode.command_input(command));
+        }
+        if choice == 0 {
+            input.push_str(&mode.command_input(last));
+        } else {
+            input.push_str(&last.replace('\n', "\r"));
+        }
+
+        if mode.at_continuation(&self.current_line) {
+            // Throw away the half-entered command first, so the suggestion
+            // isn't appended to it
+            self.push_action(SessionAction::SendInput("\x03".to_string()));
+            self.push_action(SessionAction::SendInputAtPrompt(input));
+        } else {
+            self.push_action(SessionAction::SendInput(input));
+        }
+        Ok(())
+    }
+
+    /// Loads the current config from disk, so hooks see edits made since
+    /// the session started.
+    pub fn load_config(&self) -> Result<Config> {
+        let config_path = self.config_path.clone()
+            .unwrap_or_else(Config::get_default_config_path);
+        Config::load_from_file(&config_path).map_err(|e| Error::Config(e).into())
     }
 }
 
-impl HookManager {
-    pub fn new() -> Self {
-        HookManager {
-            hooks: HashMap::new(),
-            window_manager: WindowManager::default(),
+impl lua::ScriptHost for HookContext {
+    fn popup(&mut self, title: &str, text: &str) -> Result<()> {
+        self.window_manager.show_popup(title, text)
+    }
+
+    fn inject(&mut self, text: &str) {
+        self.push_action(SessionAction::SendInput(text.to_string()));
+    }
+
+    fn llm_prompt(&mut self, prompt: &str, timeout: Duration) -> Result<String> {
+        let config = self.load_config()?;
+        let system_prompt = self.system_prompt(&config);
+        llm::complete_within(&config.llm, &system_prompt, prompt, timeout)
+    }
+
+    fn copy(&mut self, text: &str) -> Result<()> {
+        let mut stdout = std::io::stdout();
+        stdout.write_all(clipboard_sequence(text).as_bytes())?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn permit(&mut self, permission: Permission) -> Result<()> {
+        self.require(permission)
+    }
+}
+
+#[derive(Debug)]
+pub struct HookManager {
+    hooks: HashMap<String, Hook>,
+    context: HookContext,
+    next_order: usize,
+    /// The prefix of a chord that was pressed and when, waiting for the key
+    /// that completes it.
+    chord: Option<(KeyInput, Instant)>,
+    chord_timeout: Duration,
+    /// Hooks bound for this session only, and when they expire.
+    temporary: HashMap<String, Option<Instant>>,
+    /// The project whose hooks are in use.
+    project: Option<ActiveProject>,
+    /// When the session started, which `interval` hooks count from until
+    /// they first run.
+    started: Instant,
+    /// When a key was last pressed, which `idle` hooks count from.
+    last_key: Instant,
+    /// When each `interval` or `idle` hook last ran, by name.
+    timer_runs: HashMap<String, Instant>,
+}
+
+/// A project's `.chatshell.toml` in use, with the hooks it added and those
+/// they replaced, which come back when the shell leaves the project.
+#[derive(Debug)]
+struct ActiveProject {
+    path: PathBuf,
+    added: Vec<String>,
+    replaced: Vec<Hook>,
+}
+
+/// What a key does to chord bindings like `ctrl+a t`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChordKey {
+    /// The key has nothing to do with a chord.
+    Pass,
+    /// The key is the prefix of a chord and waits for the next one.
+    Armed,
+    /// The key completes a chord started by the prefix.
+    Complete(KeyInput),
+    /// The key completes no chord; the prefix goes to the shell before it.
+    Broken(KeyInput),
+    /// The prefix was pressed again, which types it once.
+    Repeated(KeyInput),
+}
+
+#[derive(Debug)]
+pub struct Hook {
+    pub config: HookConfig,
+    /// The steps of the hook's action, in the order they run.
+    pub actions: Vec<ActionType>,
+    // When the hook was added, to break ties between equal priorities
+    order: usize,
+}
+
+impl Hook {
+    pub fn new(config: HookConfig) -> Self {
+        let actions = config.action.steps().iter().map(|step| Self::parse_action(step)).collect();
+        Hook { config, actions, order: 0 }
+    }
+
+    fn parse_action(action_str: &str) -> ActionType {
+        if let Some(cmd) = action_str.strip_prefix("cmd:") {
+            ActionType::Command(cmd.to_string())
+        } else if let Some(func) = action_str.strip_prefix("fn:") {
+            ActionType::Function(func.to_string())
+        } else if let Some(builtin) = action_str.strip_prefix("builtin:") {
+            ActionType::Builtin(builtin.to_string())
+        } else if let Some(source) = action_str.strip_prefix("tail:") {
+            ActionType::Tail(source.to_string())
+        } else if let Some(text) = action_str.strip_prefix("inject:") {
+            ActionType::Inject(expand_escapes(text))
+        } else if let Some(source) = action_str.strip_prefix("lua:") {
+            ActionType::Lua(source.to_string())
+        } else if let Some(name) = action_str.strip_prefix("plugin:") {
+            ActionType::Plugin(name.trim().to_string())
+        } else if let Some(name) = action_str.strip_prefix("companion:") {
+            ActionType::Companion(name.trim().to_string())
+        } else if let Some(text) = action_str.strip_prefix("popup:") {
+            ActionType::Popup(expand_escapes(text))
+        } else if let Some(text) = action_str.strip_prefix("notify:") {
+            ActionType::Notify(expand_escapes(text))
+        } else {
+            // Default to command
+            ActionType::Command(action_str.to_string())
         }
     }
 
-    pub fn from_configs(configs: Vec<HookConfig>) -> Self {
-        let mut manager = Self::new();
-        for config in configs {
-            manager.add_hook(config);
+    /// True if the hook types text into the shell.
+    pub fn types_text(&self) -> bool {
+        self.config.output == HookOutput::Inject
+            || self.actions.iter().any(|action| match action {
+                ActionType::Inject(_) => true,
+                ActionType::Builtin(name) => TYPING_BUILTINS.contains(&name.as_str()),
+                _ => false,
+            })
+    }
+
+    pub fn matches(&self, key: &KeyInput) -> bool {
+        if !self.config.enabled {
+            return false;
         }
-        manager
+        key.matches_trigger(&self.config.key_combination)
     }
 
-    pub fn add_hook(&mut self, config: HookConfig) {
-        let hook = Hook::new(config.clone());
-        self.hooks.insert(config.name.clone(), hook);
+    /// The press the hook is bound to.
+    pub fn press(&self) -> Press {
+        split_trigger(&self.config.key_combination).0
     }
 
-    pub fn remove_hook(&mut self, name: &str) -> bool {
-        self.hooks.remove(name).is_some()
+    /// The prefix and the key of a chord binding like `ctrl+a t`.
+    pub fn chord(&self) -> Option<(String, String)> {
+        split_chord(&self.config.key_combination)
     }
 
-    pub fn get_hook(&self, name: &str) -> Option<&Hook> {
-        self.hooks.get(name)
+    /// True if the hook is bound to the chord `prefix` then `key`.
+    pub fn matches_chord(&self, prefix: &KeyInput, key: &KeyInput) -> bool {
+        self.config.enabled
+            && self.chord().is_some_and(|(first, second)| prefix.matches_pattern(&first) && key.matches_pattern(&second))
+    }
+
+    /// Runs the hook for `event`. Commands learn about it from
+    /// `CHATSHELL_EVENT` and the variables in `env`; other actions run as
+    /// if a key had been pressed.
+    pub fn execute_event(&self, event: HookEvent, env: &[(String, String)], ctx: &mut HookContext) -> Result<()> {
+        match self.actions.first() {
+            Some(ActionType::Command(cmd)) => {
+                let outer = std::mem::replace(&mut ctx.principal, self.config.principal.clone());
+                let allowed = ctx.require(Permission::Execute);
+                ctx.principal = outer;
+                allowed?;
+                self.execute_event_command(cmd, event, env, ctx);
+                Ok(())
+            }
+            _ => self.execute(&KeyInput::new(KeyCode::Null, KeyModifiers::empty()), ctx).map(|_| ()),
+        }
+    }
+
+    /// Runs `cmd` for an event. It only shows a popup when it has something
+    /// to say, so quiet hooks stay out of the way of every command.
+    fn execute_event_command(&self, cmd: &str, event: HookEvent, env: &[(String, String)], ctx: &mut HookContext) {
+        let mut env = env.to_vec();
+        env.insert(0, ("CHATSHELL_EVENT".to_string(), event.name().to_string()));
+        ctx.push_action(SessionAction::RunCommand(HookCommand {
+            command: cmd.to_string(),
+            env,
+            quiet: true,
+            output: self.config.output.clone(),
+            timeout: self.config.timeout_ms.map(Duration::from_millis),
+            hook: self.config.name.clone(),
+            then: self.rest_of_chain(0),
+            structured: self.config.structured,
+            on_select: self.config.on_select.clone(),
+            ..Default::default()
+        }));
+    }
+
+    /// Runs the hook's action, as the plugin or hook pack it came from if
+    /// it did, or as whoever ran it. With a `confirm` question, nothing
+    /// runs unless it is answered yes.
+    pub fn execute(&self, key: &KeyInput, ctx: &mut HookContext) -> Result<bool> {
+        if let Some(question) = &self.config.confirm {
+            let title = self.config.description.as_deref().unwrap_or(&self.config.name);
+            if !ctx.window_manager.show_confirm(title, question, false)? {
+                return Ok(true);
+            }
+        }
+        self.execute_from(0, key, ctx)
+    }
+
+    /// Runs the steps of the hook's action from `first` on, stopping at the
+    /// first that fails. A `cmd:` step runs in the background, and the
+    /// steps after it run once it has succeeded.
+    pub fn execute_from(&self, first: usize, key: &KeyInput, ctx: &mut HookContext) -> Result<bool> {
+        let principal = self.config.principal.clone().or_else(|| ctx.principal.clone());
+        let outer = std::mem::replace(&mut ctx.principal, principal);
+        let result = ctx.as_hook(&self.config, |ctx| {
+            let mut result = Ok(false);
+            for (index, action) in self.actions.iter().enumerate().skip(first) {
+                match self.execute_action(index, action, key, ctx) {
+                    Ok(Step::Continue(consumed)) => result = result.map(|done| done || consumed),
+                    Ok(Step::Stop) => return Ok(true),
+                    Err(e) if self.actions.len() > 1 => {
+                        return Err(e.context(format!("Step {} of {} failed", index + 1, self.actions.len())));
+                    }
+                    Err(e) => return Err(e),
+                }
+            }
+            result
+        });
+        ctx.principal = outer;
+        result
+    }
+
+    /// Where the steps after step `index` start, if there are any.
+    fn rest_of_chain(&self, index: usize) -> Option<usize> {
+        (index + 1 < self.actions.len()).then_some(index + 1)
+    }
+
+    fn execute_action(&self, index: usize, action: &ActionType, key: &KeyInput, ctx: &mut HookContext) -> Result<Step> {
+        let consumed = match action {
+            ActionType::Command(cmd) => {
+                ctx.require(Permission::Execute)?;
+                // ESC on one of its placeholders runs nothing
+                let Some(command) = self.ask_args(cmd, ctx)? else {
+                    return Ok(Step::Stop);
+                };
+                ctx.push_action(SessionAction::RunCommand(HookCommand {
+                    command,
+                    output: self.config.output.clone(),
+                    timeout: self.config.timeout_ms.map(Duration::from_millis),
+                    hook: self.config.name.clone(),
+                    then: self.rest_of_chain(index),
+                    structured: self.config.structured,
+                    on_select: self.config.on_select.clone(),
+                    ..Default::default()
+                }));
+                return Ok(Step::Stop);
+            }
+            ActionType::Function(name) => self.execute_named(&format!("fn:{}", name), "error-unknown-function", ctx),
+            ActionType::Builtin(name) => self.execute_named(&format!("builtin:{}", name), "error-unknown-builtin", ctx),
+            ActionType::Tail(source) => {
+                if source.starts_with('!') {
+                    ctx.require(Permission::Execute)?;
+                }
+                self.execute_tail(source, &mut ctx.window_manager)
+            }
+            ActionType::Inject(text) => {
+                ctx.require(Permission::Execute)?;
+                ctx.push_action(SessionAction::SendInput(text.clone()));
+                Ok(true)
+            }
+            ActionType::Lua(source) => {
+                // A script can do anything a command can
+                ctx.require(Permission::Execute)?;
+                let timeout = self.config.timeout_ms.map_or(lua::CALL_TIMEOUT, Duration::from_millis);
+                let result = lua::run(source, key, timeout, ctx)?;
+                if !result.printed.is_empty() {
+                    ctx.deliver(&self.config.output, &self.config.name, result.printed.trim_end())?;
+                }
+                Ok(result.consumed)
+            }
+            ActionType::Plugin(name) => {
+                let plugin = ctx.plugins.get_mut(name).with_context(|| format!("No plugin named {}", name))?;
+                let reply = plugin.on_key(&self.config.key_combination)?;
+                // A plugin with nothing to say leaves the key to the shell
+                let consumed = !reply.is_empty();
+                // The reply is the plugin's, even from a hook the config rebound
+                let outer = ctx.principal.replace(Principal::Plugin(name.clone()));
+                let result = self.run_reply(reply, key, ctx);
+                ctx.principal = outer;
+                result?;
+                Ok(consumed)
+            }
+            ActionType::Companion(name) => {
+                let companion = ctx.companions.get_mut(name).with_context(|| format!("No companion named {}", name))?;
+                let response = companion.key(&self.config.key_combination)?;
+                let consumed = response.consume;
+                let outer = ctx.principal.replace(Principal::Companion(name.clone()));
+                let result = self.run_response(response, key, ctx);
+                ctx.principal = outer;
+                result?;
+                Ok(consumed)
+            }
+            ActionType::Popup(text) => {
+                ctx.window_manager.show_popup(&self.config.name, text)?;
+                Ok(true)
+            }
+            ActionType::Notify(text) => {
+                ctx.window_manager.show_notification(&self.config.name, text)?;
+                Ok(true)
+            }
+        }?;
+        Ok(Step::Continue(consumed))
+    }
+
+    /// Asks for each `{name}` placeholder in `command` and returns it with
+    /// what was entered in their place, quoted as one word. None if ESC was
+    /// pressed.
+    fn ask_args(&self, command: &str, ctx: &mut HookContext) -> Result<Option<String>> {
+        let title = self.config.description.clone().unwrap_or_else(|| self.config.name.clone());
+        let mut values = HashMap::new();
+        for name in placeholders(command) {
+            if name == NOTES_PLACEHOLDER {
+                values.insert(name, ctx.notes()?);
+                continue;
+            }
+            let arg = self.config.args.get(&name).cloned().unwrap_or_default();
+            let label = arg.label.as_deref().unwrap_or(&name);
+            let initial = arg.default.as_deref().unwrap_or("");
+            let Some(value) = ctx.window_manager.show_input_with(&title, label, initial, arg.history.then_some(label))? else {
+                return Ok(None);
+            };
+            values.insert(name, value);
+        }
+        Ok(Some(fill_placeholders(command, |name| values.get(name).cloned())))
+    }
+
+    /// Does what a companion answered, in the order of its fields.
+    fn run_response(&self, response: Response, key: &KeyInput, ctx: &mut HookContext) -> Result<()> {
+        if let Some(text) = response.popup {
+            ctx.deliver(&self.config.output, &self.config.name, &text)?;
+        }
+        if let Some(text) = response.inject {
+            ctx.require(Permission::Execute)?;
+            ctx.push_action(SessionAction::SendInput(text));
+        }
+        if let Some(action) = response.action {
+            if action.starts_with("companion:") {
+                anyhow::bail!("Companions can't run companions, as {} asked", action);
+            }
+            run_action(&self.config.name, &action, key, ctx)?;
+        }
+        Ok(())
+    }
+
+    /// Shows what a plugin printed and runs the actions it asked for.
+    fn run_reply(&self, reply: Reply, key: &KeyInput, ctx: &mut HookContext) -> Result<()> {
+        if !reply.printed.is_empty() {
+            ctx.deliver(&self.config.output, &self.config.name, &reply.printed.join("\n"))?;
+        }
+        for action in reply.actions {
+            if action.starts_with("plugin:") {
+                anyhow::bail!("Plugins can't run plugins, as {} asked", action);
+            }
+            run_action(&self.config.name, &action, key, ctx)?;
+        }
+        Ok(())
+    }
+
+    fn execute_tail(&self, source: &str, window_manager: &mut WindowManager) -> Result<bool> {
+        match Tail::open(source) {
+            Ok(mut tail) => window_manager.show_tail(&format!("tail {}", source), &mut tail)?,
+            Err(e) => window_manager.show_popup(&tr("tail-title"), &format!("{:#}", e))?,
+        }
+        Ok(true)
+    }
+
+    /// Runs the registered action `name`, if the session has what it needs.
+    fn execute_named(&self, name: &str, unknown: &str, ctx: &mut HookContext) -> Result<bool> {
+        let Some(action) = actions().get(name) else {
+            let content = tr_args(unknown, &[("name", name.split_once(':').map_or(name, |(_, name)| name).into())]);
+            ctx.window_manager.show_popup(&tr("popup-error"), &content)?;
+            return Ok(false);
+        };
+        if let Some(missing) = action.missing(ctx) {
+            ctx.window_manager.show_popup(&tr("popup-error"), &tr(missing.missing_message()))?;
+            return Ok(true);
+        }
+        for permission in action.permissions {
+            ctx.require(*permission)?;
+        }
+        (action.handler)(&self.config, ctx)?;
+        Ok(true)
+    }
+}
+
+/// The actions hooks bind keys to by name, with `fn:` and `builtin:`.
+pub fn actions() -> &'static ActionRegistry {
+    static ACTIONS: OnceLock<ActionRegistry> = OnceLock::new();
+    ACTIONS.get_or_init(|| {
+        let mut registry = ActionRegistry::default();
+        let actions = [
+            Action::new("fn:show_help", "action-show-help", show_help),
+            Action::new("fn:show_time", "action-show-time", show_time),
+            Action::new("builtin:convert_time", "action-convert-time", convert_time),
+            Action::new("builtin:ask", "action-ask", ask_assistant)
+                .needs(&[Capability::Llm])
+                .permissions(&[Permission::Scrollback, Permission::Network]),
+            Action::new("builtin:toggle_chat", "action-toggle-chat", |_, ctx| push(ctx, SessionAction::ToggleChat))
+                .needs(&[Capability::Llm]),
+            Action::new("builtin:explain_query", "action-explain-query", explain_last_query)
+                .needs(&[Capability::Database, Capability::Llm])
+                .permissions(&[Permission::Scrollback, Permission::Network]),
+            Action::new("builtin:show_last_result", "action-show-last-result", show_last_result)
+                .needs(&[Capability::Database])
+                .permissions(&[Permission::Scrollback]),
+            Action::new("builtin:rerun_and_diff", "action-rerun-and-diff", rerun_and_diff)
+                .permissions(&[Permission::Execute, Permission::Scrollback]),
+            Action::new("builtin:watch_command", "action-watch-command", watch_command).permissions(&[Permission::Execute]),
+            Action::new("builtin:split_pane", "action-split-pane", |_, ctx| push(ctx, SessionAction::OpenPane)),
+            Action::new("builtin:focus_pane", "action-focus-pane", |_, ctx| push(ctx, SessionAction::FocusPane)),
+            Action::new("builtin:close_pane", "action-close-pane", |_, ctx| push(ctx, SessionAction::ClosePane)),
+            Action::new("builtin:undo_input", "action-undo-input", |_, ctx| push(ctx, SessionAction::UndoInput)),
+            Action::new("builtin:paste_clipboard", "action-paste-clipboard", paste_clipboard)
+                .permissions(&[Permission::Clipboard, Permission::Execute]),
+            Action::new("builtin:bookmark", "action-bookmark", bookmark),
+            Action::new("builtin:wayback", "action-wayback", |_, ctx| {
+                ctx.window_manager.show_wayback(&ctx.snapshots, ctx.alternate_screen)
+            })
+            .permissions(&[Permission::Scrollback]),
+            Action::new("builtin:search_scrollback", "action-search-scrollback", |_, ctx| {
+                push(ctx, SessionAction::SearchScrollback)
+            })
+            .permissions(&[Permission::Scrollback]),
+            Action::new("builtin:copy_mode", "action-copy-mode", |_, ctx| push(ctx, SessionAction::CopyMode))
+                .permissions(&[Permission::Scrollback]),
+            Action::new("builtin:reopen_last_popup", "action-reopen-last-popup", |_, ctx| {
+                ctx.window_manager.reopen_closed_popups()
+            }),
+            Action::new("builtin:move_note", "action-move-note", |_, ctx| push(ctx, SessionAction::MoveNote)),
+            Action::new("builtin:close_note", "action-close-note", |_, ctx| push(ctx, SessionAction::CloseNote)),
+            Action::new("builtin:process_picker", "action-process-picker", |_, ctx| {
+                ctx.window_manager.show_process_picker(ctx.shell_pid)
+            }),
+            Action::new("builtin:ports", "action-ports", |_, ctx| ctx.window_manager.show_ports(ctx.shell_pid)),
+            Action::new("builtin:env_diff", "action-env-diff", env_diff).permissions(&[Permission::Execute]),
+            Action::new("builtin:reload_env", "action-reload-env", reload_env).permissions(&[Permission::Execute]),
+            Action::new("builtin:switch_shell", "action-switch-shell", switch_shell).permissions(&[Permission::Execute]),
+            Action::new("builtin:clear_screen", "action-clear-screen", |_, _| {
+                print!("\x1B[2J\x1B[H"); // ANSI clear screen and move cursor to home
+                Ok(())
+            }),
+            Action::new("builtin:hook_manager", "action-hook-manager", |_, ctx| push(ctx, SessionAction::ManageHooks)),
+            Action::new("builtin:bind_temp", "action-bind-temp", bind_temporary),
+            Action::new("builtin:notes", "action-notes", edit_notes),
+            Action::new("builtin:report_bug", "action-report-bug", report_bug).permissions(&[Permission::Scrollback]),
+            // About the hook that runs it, so not much use from the palette
+            Action::new("builtin:show_config", "action-show-config", show_config).unlisted(),
+            Action::new("builtin:toggle_hook", "action-hook-manager", |_, ctx| push(ctx, SessionAction::ManageHooks))
+                .unlisted(),
+            Action::new("builtin:palette", "action-palette", |_, ctx| push(ctx, SessionAction::ShowPalette)).unlisted(),
+        ];
+        for action in actions {
+            registry.register(action);
+        }
+        registry
+    })
+}
+
+fn push(ctx: &mut HookContext, action: SessionAction) -> Result<()> {
+    ctx.push_action(action);
+    Ok(())
+}
+
+fn show_help(hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    push(ctx, SessionAction::ShowHelp(hook.output.clone()))
+}
+
+fn show_time(hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let config = ctx.load_config()?.time;
+    let now = ctx.clock.local().with_timezone(&chrono::Utc);
+    let clocks: Vec<(String, String)> = clock::zones(&config)?
+        .iter()
+        .map(|zone| (zone.name().to_string(), zone.format(now, &config.format)))
+        .collect();
+    ctx.deliver(&hook.output, &tr("time-title"), &clock::format_table(&clocks).join("\n"))
+}
+
+/// Shows a timestamp in each configured zone and as an epoch, and types the
+/// one picked. Starts with the last time on the shell's line, if any.
+fn convert_time(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let config = ctx.load_config()?.time;
+    let zones = clock::zones(&config)?;
+    let initial = clock::find_timestamp(&ctx.current_line).unwrap_or_default();
+    let title = tr("convert-time-title");
+    let prompt = tr("convert-time-prompt");
+    let Some(text) = ctx.window_manager.show_input_with(&title, &prompt, &initial, Some(&prompt))? else {
+        return Ok(());
+    };
+    let time = match clock::parse_time(&text, ctx.clock.local().with_timezone(&chrono::Utc)) {
+        Ok(time) => time,
+        Err(e) => return ctx.window_manager.show_popup(&tr("popup-error"), &format!("{:#}", e)),
+    };
+    let mut forms = clock::conversions(time, &zones, &config.format);
+    if let Some(index) = ctx.window_manager.show_menu(&title, &clock::format_table(&forms))? {
+        ctx.push_action(SessionAction::SendInput(forms.swap_remove(index).1));
+    }
+    Ok(())
+}
+
+fn show_config(hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let mut content = tr_args("config-text", &[
+        ("name", hook.name.as_str().into()),
+        ("key", hook.key_combination.as_str().into()),
+        ("action", hook.action.to_string().into()),
+        ("enabled", hook.enabled.to_string().into()),
+    ]);
+    if let Some(description) = &hook.description {
+        content.push('\n');
+        content.push_str(&tr_args("config-description", &[("description", description.as_str().into())]));
+    }
+    ctx.window_manager.show_popup(&tr("config-title"), &content)
+}
+
+fn reload_env(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let config = ctx.load_config()?;
+    let env = config.shell.session_env()?;
+    let text = tr_args("env-reloaded", &[("count", env.len().into())]);
+    for (key, value) in env {
+        ctx.push_action(SessionAction::SetEnv { key, value });
+    }
+    ctx.window_manager.show_toast(&text, TOAST_LIFETIME);
+    Ok(())
+}
+
+fn switch_shell(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let config = ctx.load_config()?;
+    let mut shells: Vec<(String, ShellConfig)> = vec![("default".to_string(), config.shell)];
+    shells.extend(config.shells);
+
+    let items: Vec<String> = shells
+        .iter()
+        .map(|(name, shell)| format!("{} ({} {})", name, shell.command, shell.args.join(" ")))
+        .collect();
+    if let Some(index) = ctx.window_manager.show_menu(&tr("switch-shell-title"), &items)? {
+        let (_, shell) = shells.swap_remove(index);
+        ctx.push_action(SessionAction::SwitchShell(shell));
+    }
+    Ok(())
+}
+
+/// Pastes the text on the system clipboard into the shell. Over SSH the
+/// clipboard is the one of the machine chatshell runs on.
+#[cfg(feature = "clipboard")]
+fn paste_clipboard(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let text = arboard::Clipboard::new()
+        .and_then(|mut clipboard| clipboard.get_text())
+        .context("Failed to read the clipboard")?;
+    if !text.is_empty() {
+        ctx.push_action(SessionAction::Paste(text));
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn paste_clipboard(_hook: &HookConfig, _ctx: &mut HookContext) -> Result<()> {
+    anyhow::bail!("chatshell was built without the clipboard feature")
+}
+
+fn bookmark(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    // ESC cancels; an empty name numbers the bookmark
+    if let Some(name) = ctx.window_manager.show_input_or_empty(&tr("bookmark-title"), &tr("bookmark-prompt"))? {
+        let name = Some(name.trim().to_string()).filter(|name| !name.is_empty());
+        ctx.push_action(SessionAction::Bookmark(name));
+    }
+    Ok(())
+}
+
+/// Writes a bug report bundle next to the debug log, with the screen
+/// only if the user agrees to share it.
+fn report_bug(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let items = [tr("report-bug-without-screen"), tr("report-bug-with-screen")];
+    let Some(choice) = ctx.window_manager.show_menu(&tr("report-bug-title"), &items)? else {
+        return Ok(());
+    };
+    let screen = ctx.snapshots.last().map_or(&ctx.recent_lines, |snapshot| &snapshot.lines);
+    let config_path = ctx.config_path.clone().unwrap_or_else(Config::get_default_config_path);
+    let config_path = Path::new(&config_path);
+    let files = bugreport::collect(
+        config_path.exists().then_some(config_path),
+        (choice == 1).then_some(screen.as_slice()),
+        false,
+    );
+    let log_path = debug_log_path();
+    let dir = log_path.parent().unwrap_or(Path::new("."));
+    let path = bugreport::write_report(dir, &files)?;
+    let content = tr_args("report-bug-written", &[("path", path.display().to_string().into())]);
+    ctx.window_manager.show_popup(&tr("report-bug-title"), &content)?;
+    Ok(())
+}
+
+/// Opens the session's notes for editing; its key closes them again.
+fn edit_notes(hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let Some(path) = ctx.notes_path.clone() else {
+        return Ok(());
+    };
+    let notes = notepad::load(&path)?;
+    let session = path.file_stem().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let title = tr_args("notes-title", &[("session", session.into())]);
+    ctx.window_manager.show_editor(&title, &notes, &hook.key_combination, |text| notepad::save(&path, text))?;
+    Ok(())
+}
+
+/// Asks the LLM a question about the session, which offers to type the
+/// command it suggests once it has answered.
+fn ask_assistant(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let config = ctx.load_config()?;
+    let Some(question) = ctx.window_manager.show_input(&tr("ask-title"), &tr("ask-prompt"))? else {
+        return Ok(());
+    };
+
+    let system_prompt = ctx.system_prompt(&config);
+    let mut prompt = question.clone();
+    if !ctx.current_line.trim().is_empty() {
+        prompt.push_str(&format!("\n\nThe terminal's current line is: {}", ctx.current_line));
+    }
+    ctx.ask_llm(&config, "ask", system_prompt, prompt, &tr("assistant-title"), &question)
+}
+
+/// Explains the last SQL query of a database session, with the schema of
+/// the tables it uses fetched from the database itself.
+fn explain_last_query(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let Some(kind) = ctx.session_mode.database() else {
+        return Ok(());
+    };
+    let Some(run) = database::last_query(kind, &ctx.recent_lines) else {
+        return ctx.window_manager.show_popup(&tr("explain-title"), &tr("no-query"));
+    };
+
+    let mut commands = database::schema_commands(kind, &database::referenced_tables(&run.query));
+    if kind == ReplKind::Psql {
+        // Keep long table definitions out of the pager
+        commands.insert(0, "\\pset pager off".to_string());
+        commands.push("\\pset pager on".to_string());
+    }
+    ctx.start_capture(&commands, CaptureFollowUp::ExplainQuery { query: run.query, error: run.error });
+    Ok(())
+}
+
+/// Runs the last command again and shows how its output changed since
+/// the previous run.
+fn rerun_and_diff(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let Some((command, output)) = last_command(&ctx.current_line, &ctx.recent_lines) else {
+        return ctx.window_manager.show_popup(&tr("rerun-title"), &tr("rerun-no-command"));
+    };
+
+    // Compare against our own last run of the command when there is one,
+    // since its output was captured exactly
+    let previous = match ctx.last_rerun.take() {
+        Some((last, lines)) if last == command => lines,
+        _ => output,
+    };
+    let commands = vec![command.clone()];
+    ctx.start_capture(&commands, CaptureFollowUp::RerunDiff { command, previous });
+    Ok(())
+}
+
+/// Compares the shell's environment with chatshell's. At a shell prompt
+/// `env` is run so exports made since the shell started count; anywhere
+/// else the environment the shell was started with is read from /proc.
+fn env_diff(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    match ctx.session_mode {
+        SessionMode::Shell(dialect) if ctx.at_prompt() => {
+            // Nushell's own `env` is a table; run the external command
+            let command = if dialect == ShellDialect::Nu { "^env" } else { "env" };
+            ctx.start_capture(&[command.to_string()], CaptureFollowUp::EnvDiff);
+            Ok(())
+        }
+        _ => match ctx.shell_pid {
+            Some(pid) => ctx.show_env_diff(&process::environ(pid)?, false),
+            None => ctx.window_manager.show_popup(&tr("env-diff-title"), &tr("env-diff-unavailable")),
+        },
+    }
+}
+
+/// Asks for a key, an action and how long to keep them bound.
+fn bind_temporary(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let title = tr("bind-temp-title");
+    let Some(key) = ctx.window_manager.show_input(&title, &tr("bind-temp-key-prompt"))? else {
+        return Ok(());
+    };
+    let key = key.trim().to_string();
+    if KeyInput::parse(&key).is_none() {
+        let content = tr_args("bind-temp-bad-key", &[("key", key.as_str().into())]);
+        return ctx.window_manager.show_popup(&title, &content);
+    }
+    let Some(action) = ctx.window_manager.show_input(&title, &tr("bind-temp-action-prompt"))? else {
+        return Ok(());
+    };
+    let Some(lifetime) = ctx.window_manager.show_input_or_empty(&title, &tr("bind-temp-lifetime-prompt"))? else {
+        return Ok(());
+    };
+    let lifetime = match lifetime.trim() {
+        "" => None,
+        text => match watch::parse_interval(text) {
+            Ok(lifetime) => Some(lifetime),
+            Err(e) => return ctx.window_manager.show_popup(&title, &e.to_string()),
+        },
+    };
+    ctx.push_action(SessionAction::BindTemporary { key, action: action.trim().to_string(), lifetime });
+    Ok(())
+}
+
+/// Starts watching a command in a corner panel, or stops the watch that
+/// is running.
+fn watch_command(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    if ctx.watching {
+        ctx.watching = false;
+        ctx.push_action(SessionAction::StopWatch);
+        return Ok(());
+    }
+    let Some(command) = ctx.window_manager.show_input(&tr("watch-title"), &tr("watch-command-prompt"))? else {
+        return Ok(());
+    };
+    let interval = match ctx.window_manager.show_input(&tr("watch-title"), &tr("watch-interval-prompt"))? {
+        Some(text) => match watch::parse_interval(&text) {
+            Ok(interval) => interval,
+            Err(e) => return ctx.window_manager.show_popup(&tr("watch-title"), &e.to_string()),
+        },
+        None => DEFAULT_INTERVAL,
+    };
+    ctx.watching = true;
+    ctx.push_action(SessionAction::Watch { command: command.trim().to_string(), interval });
+    Ok(())
+}
+
+fn show_last_result(_hook: &HookConfig, ctx: &mut HookContext) -> Result<()> {
+    let Some(kind) = ctx.session_mode.database() else {
+        return Ok(());
+    };
+    let Some(run) = database::last_query(kind, &ctx.recent_lines) else {
+        return ctx.window_manager.show_popup(&tr("last-result-title"), &tr("no-query"));
+    };
+
+    if let Some(error) = &run.error {
+        return ctx.window_manager.show_popup(&tr("last-result-title"), &format!("{}\n\n{}", run.query, error));
+    }
+    let Some(rows) = database::parse_result_table(&run.output) else {
+        return ctx.window_manager.show_popup(&tr("last-result-title"), &run.output.join("\n"));
+    };
+
+    let max_lines = ctx.window_manager.max_content_lines();
+    let mut lines: Vec<String> = database::format_table(&rows).lines().map(str::to_string).collect();
+    if lines.len() > max_lines {
+        let hidden = lines.len() - max_lines + 1;
+        lines.truncate(max_lines - 1);
+        lines.push(tr_args("popup-more-rows", &[("count", hidden.into())]));
+    }
+    let title = tr_args("last-result-rows", &[("count", (rows.len() - 1).into())]);
+    ctx.window_manager.show_popup(&title, &lines.join("\n"))
+}
+
+/// True if `path` matches the glob `pattern`, in which `?` and `*` match
+/// within a directory and `**` matches across them.
+fn glob_matches(pattern: &str, path: &str) -> bool {
+    fn matches(pattern: &[char], path: &[char]) -> bool {
+        match pattern {
+            [] => path.is_empty(),
+            // `a/**` also matches `a`, and `a/**/b` matches `a/b`
+            ['/', '*', '*', rest @ ..] if rest.first().is_none_or(|c| *c == '/') => {
+                matches(rest, path) || path.first() == Some(&'/') && matches(&pattern[1..], &path[1..])
+            }
+            ['*', '*', rest @ ..] => (0..=path.len()).any(|skip| matches(rest, &path[skip..])),
+            ['*', rest @ ..] => (0..=path.len())
+                .take_while(|skip| *skip == 0 || path[skip - 1] != '/')
+                .any(|skip| matches(rest, &path[skip..])),
+            ['?', rest @ ..] => path.first().is_some_and(|c| *c != '/') && matches(rest, &path[1..]),
+            [c, rest @ ..] => path.first() == Some(c) && matches(rest, &path[1..]),
+        }
+    }
+    let pattern: Vec<char> = pattern.trim_end_matches('/').chars().collect();
+    let path: Vec<char> = path.trim_end_matches('/').chars().collect();
+    matches(&pattern, &path)
+}
+
+/// The names of the `{name}` placeholders in a `cmd:` action, each once, in
+/// the order they first appear. Names are letters, digits and `_`, so
+/// `${HOME}`, `{}` and `awk '{print $1}'` aren't placeholders.
+fn placeholders(command: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    fill_placeholders(command, |name| {
+        if !names.iter().any(|known| known == name) {
+            names.push(name.to_string());
+        }
+        None
+    });
+    names
+}
+
+/// `command` with its `{name}` placeholders filled in from the defaults of
+/// `hook`'s `args`, to run it without asking. Err names a placeholder that
+/// has no default.
+pub fn fill_arg_defaults(hook: &HookConfig, command: &str) -> std::result::Result<String, String> {
+    let default = |name: &str| hook.args.get(name).and_then(|arg| arg.default.clone());
+    if let Some(name) = placeholders(command).into_iter().find(|name| default(name).is_none()) {
+        return Err(name);
+    }
+    Ok(fill_placeholders(command, default))
+}
+
+/// `command` with each placeholder `value` gives text for replaced by it,
+/// quoted as one word for where it stands: between single quotes, double
+/// quotes or neither.
+fn fill_placeholders(command: &str, mut value: impl FnMut(&str) -> Option<String>) -> String {
+    let mut filled = String::new();
+    let mut quoting = Quoting::None;
+    let mut rest = command;
+    while let Some(open) = rest.find('{') {
+        let (before, after) = rest.split_at(open);
+        filled.push_str(before);
+        quoting = quoting.after(before);
+        let name = after[1..].find('}').map(|close| &after[1..close + 1]);
+        // What the session fills in has a prefix no arg's name can have
+        let is_name = name.map(|name| name.strip_prefix("session:").unwrap_or(name)).is_some_and(|name| {
+            name.chars().next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+        });
+        match name.filter(|_| is_name && !before.ends_with('$')).and_then(|name| Some((name, value(name)?))) {
+            Some((name, text)) => {
+                filled.push_str(&quoting.quote(&text));
+                rest = &after[name.len() + 2..];
+            }
+            None => {
+                filled.push('{');
+                rest = &after[1..];
+            }
+        }
+    }
+    filled.push_str(rest);
+    filled
+}
+
+/// What a placeholder in a shell command stands between.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Quoting {
+    None,
+    Single,
+    Double,
+}
+
+impl Quoting {
+    /// The quoting at the end of `text`, which starts in this one.
+    fn after(mut self, text: &str) -> Self {
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            self = match (self, c) {
+                (Quoting::None | Quoting::Double, '\\') => {
+                    chars.next();
+                    self
+                }
+                (Quoting::None, '\'') => Quoting::Single,
+                (Quoting::None, '"') => Quoting::Double,
+                (Quoting::Single, '\'') | (Quoting::Double, '"') => Quoting::None,
+                _ => self,
+            };
+        }
+        self
+    }
+
+    /// `value` as one word, for where it stands.
+    fn quote(self, value: &str) -> String {
+        match self {
+            Quoting::None => single_quote(value),
+            // Closes the quotes around an escaped one
+            Quoting::Single => value.replace('\'', "'\\''"),
+            Quoting::Double => {
+                let mut quoted = String::new();
+                for c in value.chars() {
+                    if matches!(c, '\\' | '"' | '$' | '`') {
+                        quoted.push('\\');
+                    }
+                    quoted.push(c);
+                }
+                quoted
+            }
+        }
+    }
+}
+
+/// Expands `\n`, `\r`, `\t`, `\e` (ESC) and `\\` in an `inject:` action.
+/// Other backslashes are kept as they are.
+pub(crate) fn expand_escapes(text: &str) -> String {
+    let mut expanded = String::new();
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => expanded.push('\n'),
+            Some('r') => expanded.push('\r'),
+            Some('t') => expanded.push('\t'),
+            Some('e') => expanded.push('\x1b'),
+            Some('\\') => expanded.push('\\'),
+            Some(other) => {
+                expanded.push('\\');
+                expanded.push(other);
+            }
+            None => expanded.push('\\'),
+        }
+    }
+    expanded
+}
+
+/// Appends a hook's result to the file at `path`, after its time and title.
+fn append_result(path: &str, now: &chrono::DateTime<chrono::Local>, title: &str, content: &str) -> Result<()> {
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open {}", path))?;
+    let stamp = now.format("%Y-%m-%d %H:%M:%S");
+    writeln!(file, "[{}] {}\n{}\n", stamp, title, content.trim_end()).with_context(|| format!("Failed to write {}", path))
+}
+
+
+impl HookManager {
+    pub fn new() -> Self {
+        HookManager {
+            hooks: HashMap::new(),
+            context: HookContext::new(WindowManager::default()),
+            next_order: 0,
+            chord: None,
+            chord_timeout: DEFAULT_CHORD_TIMEOUT,
+            temporary: HashMap::new(),
+            project: None,
+            started: SystemClock.now(),
+            last_key: SystemClock.now(),
+            timer_runs: HashMap::new(),
+        }
+    }
+
+    pub fn from_configs(configs: Vec<HookConfig>) -> Self {
+        let mut manager = Self::new();
+        for config in configs {
+            manager.add_hook(config);
+        }
+        manager
+    }
+
+    /// Adds a hook, replacing one of the same name. Among hooks of equal
+    /// priority it comes after those added before it.
+    pub fn add_hook(&mut self, config: HookConfig) {
+        let hook = Hook { order: self.next_order, ..Hook::new(config.clone()) };
+        self.next_order += 1;
+        self.hooks.insert(config.name.clone(), hook);
+    }
+
+
+    /// Binds the keys `plugin` wants to it, as hooks named
+    /// `plugin:<name>:<key>`. A hook of that name already in the config
+    /// takes the key's place, so the config can rebind or turn it off.
+    pub fn add_plugin(&mut self, plugin: Plugin) {
+        for key in &plugin.keys {
+            let name = format!("plugin:{}:{}", plugin.name, key);
+            if self.hooks.contains_key(&name) {
+                continue;
+            }
+            self.add_hook(HookConfig {
+                name,
+                key_combination: key.clone(),
+                action: format!("plugin:{}", plugin.name).into(),
+                description: Some(format!("From plugin {}", plugin.name)),
+                enabled: true,
+                principal: Some(Principal::Plugin(plugin.name.clone())),
+                ..Default::default()
+            });
+        }
+        self.context.plugins.insert(plugin.name.clone(), plugin);
+    }
+
+    /// True if a plugin wants to see the shell's output.
+    pub fn has_output_plugins(&self) -> bool {
+        self.context.plugins.values().any(Plugin::wants_output)
+    }
+
+    /// The plugins and companions that want the shell's output, which is
+    /// only shown to them if they may see the scrollback.
+    fn output_principals(&self) -> Vec<Principal> {
+        let plugins = self.context.plugins.values().filter(|plugin| plugin.wants_output());
+        let companions = self.context.companions.values().filter(|companion| companion.output);
+        plugins
+            .map(|plugin| Principal::Plugin(plugin.name.clone()))
+            .chain(companions.map(|companion| Principal::Companion(companion.name.clone())))
+            .collect()
+    }
+
+    fn sees_output(&self, principal: &Principal) -> bool {
+        self.context.permissions.decision(principal, Permission::Scrollback) == Some(true)
+    }
+
+    /// True if a plugin or companion wants the shell's output and wasn't
+    /// asked yet whether it may see it.
+    pub fn output_permissions_pending(&self) -> bool {
+        self.output_principals()
+            .iter()
+            .any(|principal| self.context.permissions.decision(principal, Permission::Scrollback).is_none())
+    }
+
+    /// Asks about each plugin and companion in `output_permissions_pending`.
+    pub fn ask_output_permissions(&mut self) -> Result<()> {
+        for principal in self.output_principals() {
+            if self.context.permissions.decision(&principal, Permission::Scrollback).is_none() {
+                self.context.ask_permission(&principal, Permission::Scrollback)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Shows `text` the shell printed to the plugins that want it and
+    /// returns their replies, by plugin, for `run_plugin_replies`.
+    pub fn plugin_output(&mut self, text: &str) -> Vec<(String, Reply)> {
+        let mut replies = Vec::new();
+        let mut errors = Vec::new();
+        let allowed: Vec<String> = self
+            .context
+            .plugins
+            .keys()
+            .filter(|name| self.sees_output(&Principal::Plugin(name.to_string())))
+            .cloned()
+            .collect();
+        for (name, plugin) in self.context.plugins.iter_mut().filter(|(name, _)| allowed.contains(name)) {
+            match plugin.on_output(text) {
+                Ok(reply) if !reply.is_empty() => replies.push((name.clone(), reply)),
+                Ok(_) => {}
+                Err(e) => errors.push(e),
+            }
+        }
+        for error in errors {
+            self.context.report_background_error(Error::categorize(error, Error::Plugin));
+        }
+        replies.sort_by(|a, b| a.0.cmp(&b.0));
+        replies
+    }
+
+    pub fn run_plugin_replies(&mut self, replies: Vec<(String, Reply)>) {
+        let key = KeyInput::new(KeyCode::Null, KeyModifiers::empty());
+        for (name, reply) in replies {
+            let hook = transient_hook(&format!("plugin:{}", name), &format!("plugin:{}", name));
+            let outer = self.context.principal.replace(Principal::Plugin(name.clone()));
+            let result = hook.run_reply(reply, &key, &mut self.context);
+            self.context.principal = outer;
+            if let Err(e) = result {
+                let error = Error::categorize(e, Error::Plugin).context(format!("Plugin '{}' failed", name));
+                self.context.report_error(error);
+            }
+        }
+    }
+
+    /// Binds the keys of `companion` to it, as hooks named
+    /// `companion:<name>:<key>`, which the config can override like a
+    /// plugin's.
+    pub fn add_companion(&mut self, companion: Companion) {
+        for key in &companion.keys {
+            let name = format!("companion:{}:{}", companion.name, key);
+            if self.hooks.contains_key(&name) {
+                continue;
+            }
+            self.add_hook(HookConfig {
+                name,
+                key_combination: key.clone(),
+                action: format!("companion:{}", companion.name).into(),
+                description: Some(format!("From companion {}", companion.name)),
+                enabled: true,
+                principal: Some(Principal::Companion(companion.name.clone())),
+                ..Default::default()
+            });
+        }
+        self.context.companions.insert(companion.name.clone(), companion);
+    }
+
+    /// Tells every companion of `event`.
+    pub fn notify_companions(&mut self, event: &Event) {
+        let mut errors = Vec::new();
+        for companion in self.context.companions.values() {
+            if let Err(e) = companion.send(event) {
+                errors.push(e);
+            }
+        }
+        for error in errors {
+            self.context.report_background_error(Error::categorize(error, Error::Plugin));
+        }
+    }
+
+    pub fn set_permissions(&mut self, permissions: Permissions) {
+        self.context.permissions = permissions;
+    }
+
+    /// Sends the companions `exit` and stops them.
+    pub fn stop_companions(&mut self) {
+        self.context.companions.clear();
+    }
+
+    /// True if a companion wants to see the shell's output.
+    pub fn has_output_companions(&self) -> bool {
+        self.context.companions.values().any(|companion| companion.output)
+    }
+
+    /// Shows `text` the shell printed to the companions that want it.
+    /// Their answers come back as messages.
+    pub fn companion_output(&mut self, text: &str) {
+        let event = Event::Output { text: text.to_string() };
+        let mut errors = Vec::new();
+        let companions = self.context.companions.values().filter(|companion| companion.output);
+        for companion in companions.filter(|companion| self.sees_output(&Principal::Companion(companion.name.clone()))) {
+            if let Err(e) = companion.send(&event) {
+                errors.push(e);
+            }
+        }
+        for error in errors {
+            self.context.report_background_error(Error::categorize(error, Error::Plugin));
+        }
+    }
+
+    /// Does what a companion said on its own. One that exited is dropped
+    /// along with the hooks handing it keys.
+    pub fn companion_message(&mut self, message: Message) {
+        let key = KeyInput::new(KeyCode::Null, KeyModifiers::empty());
+        let result = match message {
+            Message::Response { companion, response } => {
+                let action = format!("companion:{}", companion);
+                let outer = self.context.principal.replace(Principal::Companion(companion.clone()));
+                let hook = transient_hook(&action, &action);
+                let result = self.context.as_hook(&hook.config, |ctx| hook.run_response(response, &key, ctx));
+                self.context.principal = outer;
+                result.with_context(|| format!("Companion '{}' failed", companion))
+            }
+            Message::Invalid { companion, error } => {
+                Err(anyhow::anyhow!("Companion '{}' wrote something other than a response: {}", companion, error))
+            }
+            Message::Exited { companion, stderr } => {
+                self.context.companions.remove(&companion);
+                self.hooks.retain(|_, hook| {
+                    !hook.actions.iter().any(|action| matches!(action, ActionType::Companion(name) if *name == companion))
+                });
+                match stderr.is_empty() {
+                    true => Err(anyhow::anyhow!("Companion '{}' exited", companion)),
+                    false => Err(anyhow::anyhow!("Companion '{}' exited: {}", companion, stderr.join("\n"))),
+                }
+            }
+        };
+        if let Err(e) = result {
+            self.context.report_error(Error::categorize(e, Error::Plugin));
+        }
+    }
+
+    /// Binds `key` to `action` for this session, ahead of configured hooks
+    /// on the same key, until `expires` if given. Binding the key again
+    /// replaces it. Returns the hook's name.
+    pub fn bind_temporary(&mut self, key: &str, action: impl Into<crate::config::HookAction>, expires: Option<Instant>) -> String {
+        let name = format!("temp:{}", key.trim().to_lowercase());
+        self.add_hook(HookConfig {
+            name: name.clone(),
+            key_combination: key.trim().to_string(),
+            action: action.into(),
+            description: Some("Bound for this session".to_string()),
+            enabled: true,
+            priority: i32::MAX,
+            ..Default::default()
+        });
+        self.temporary.insert(name.clone(), expires);
+        name
+    }
+
+    /// The hooks bound for this session, and when each expires.
+    pub fn temporary_bindings(&self) -> Vec<(&HookConfig, Option<Instant>)> {
+        ordered(&self.hooks)
+            .into_iter()
+            .filter_map(|hook| self.temporary.get(&hook.config.name).map(|expires| (&hook.config, *expires)))
+            .collect()
+    }
+
+    /// True if `name` was bound for this session only.
+    pub fn is_temporary(&self, name: &str) -> bool {
+        self.temporary.contains_key(name)
+    }
+
+    /// Turns a hook on or off for this session, leaving its file alone.
+    /// False if there's no hook of that name.
+    pub fn set_enabled(&mut self, name: &str, enabled: bool) -> bool {
+        match self.hooks.get_mut(name) {
+            Some(hook) => {
+                hook.config.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the temporary hooks whose time is up.
+    pub fn expire_temporary(&mut self, now: Instant) {
+        let expired: Vec<String> = self
+            .temporary
+            .iter()
+            .filter(|(_, expires)| expires.is_some_and(|expires| expires <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in expired {
+            self.temporary.remove(&name);
+            self.hooks.remove(&name);
+        }
+    }
+
+    /// The `.chatshell.toml` whose hooks are in use, if any.
+    pub fn project_path(&self) -> Option<&Path> {
+        self.project.as_ref().map(|project| project.path.as_path())
+    }
+
+    /// Switches to the hooks of the project file at `path`, found for the
+    /// shell's directory, or back to the config's own with None. A project
+    /// is used only once it was allowed to run commands, which is asked the
+    /// first time.
+    pub fn use_project(&mut self, path: Option<PathBuf>) -> Result<()> {
+        if self.project_path() == path.as_deref() {
+            return Ok(());
+        }
+        self.leave_project();
+        let Some(path) = path else {
+            return Ok(());
+        };
+        let project = Project::load(&path)?;
+        if !project.unknown_keys.is_empty() {
+            let keys: Vec<String> = project.unknown_keys.iter().map(UnknownKey::to_string).collect();
+            let error = anyhow::anyhow!("Unknown keys of {} left out: {}", path.display(), keys.join(", "));
+            self.context.report_background_error(Error::Config(error));
+        }
+        let principal = Principal::Project { path: path.display().to_string(), sha256: project.sha256 };
+        let allowed = match self.context.permissions.decision(&principal, Permission::Execute) {
+            Some(allowed) => allowed,
+            None => self.context.ask_permission(&principal, Permission::Execute)?,
+        };
+        if allowed {
+            let hooks = project.hooks.into_iter().map(|hook| HookConfig { principal: Some(principal.clone()), ..hook });
+            self.enter_project(path, hooks.collect());
+        }
+        Ok(())
+    }
+
+    /// Adds the hooks of a project, setting aside those with the same name
+    /// or on the same key. Hooks bound for the session stay.
+    fn enter_project(&mut self, path: PathBuf, hooks: Vec<HookConfig>) {
+        let same_key = |a: &HookConfig, b: &HookConfig| {
+            !a.key_combination.trim().is_empty()
+                && a.event.is_none()
+                && b.event.is_none()
+                && a.key_combination.trim().eq_ignore_ascii_case(b.key_combination.trim())
+        };
+        let mut added: Vec<String> = Vec::new();
+        let mut replaced = Vec::new();
+        for config in hooks {
+            let clashing: Vec<String> = self
+                .hooks
+                .values()
+                .filter(|hook| !self.temporary.contains_key(&hook.config.name) && !added.contains(&hook.config.name))
+                .filter(|hook| hook.config.name == config.name || same_key(&hook.config, &config))
+                .map(|hook| hook.config.name.clone())
+                .collect();
+            replaced.extend(clashing.iter().filter_map(|name| self.hooks.remove(name)));
+            added.push(config.name.clone());
+            self.add_hook(config);
+        }
+        self.project = Some(ActiveProject { path, added, replaced });
+    }
+
+    fn leave_project(&mut self) {
+        let Some(project) = self.project.take() else {
+            return;
+        };
+        for name in &project.added {
+            self.hooks.remove(name);
+        }
+        for hook in project.replaced {
+            self.hooks.insert(hook.config.name.clone(), hook);
+        }
+    }
+
+    pub fn remove_hook(&mut self, name: &str) -> bool {
+        self.hooks.remove(name).is_some()
+    }
+
+    pub fn get_hook(&self, name: &str) -> Option<&Hook> {
+        self.hooks.get(name)
+    }
+
+    pub fn get_hook_mut(&mut self, name: &str) -> Option<&mut Hook> {
+        self.hooks.get_mut(name)
+    }
+
+    /// Keeps the session's notes in `path`.
+    pub fn set_notes_path(&mut self, path: PathBuf) {
+        self.context.notes_path = Some(path);
+    }
+
+    /// Keeps the questions to the assistant and its answers in
+    /// `transcript`, which the session adds its output to with
+    /// `transcribe`.
+    pub fn set_transcript(&mut self, transcript: TranscriptFile) {
+        self.context.transcript = Some(transcript);
+    }
+
+    pub fn transcribe(&mut self, entry: &transcript::Entry) {
+        self.context.transcribe(entry);
+    }
+
+    /// Writes what was added to the transcript since the last time.
+    pub fn flush_transcript(&mut self) {
+        let Some(transcript) = self.context.transcript.as_mut() else {
+            return;
+        };
+        if let Err(e) = transcript.flush() {
+            self.context.transcript = None;
+            self.context.report_background_error(Error::Config(e.context("The transcript is off")));
+        }
+    }
+
+    pub fn set_config_path(&mut self, path: &str) {
+        self.context.config_path = Some(path.to_string());
+    }
+
+    /// Where what is entered in input popups is kept; None keeps nothing.
+    pub fn set_input_history(&mut self, path: Option<PathBuf>) {
+        self.context.window_manager.input_history = path;
+    }
+
+    pub fn set_session_mode(&mut self, mode: SessionMode) {
+        self.context.session_mode = mode;
+    }
+
+    /// Updates what hooks see of the shell's output.
+    pub fn set_screen(&mut self, current_line: String, recent_lines: Vec<String>) {
+        self.context.window_manager.cursor_line = current_line.clone();
+        self.context.current_line = current_line;
+        self.context.recent_lines = recent_lines;
+    }
+
+    /// Reads the time from `clock` from now on, which also starts the
+    /// session's timers over.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.started = clock.now();
+        self.last_key = self.started;
+        self.context.window_manager.clock = clock.clone();
+        self.context.clock = clock;
+    }
+
+    /// Updates the text popups put back when they close.
+    pub fn set_screen_rows(&mut self, rows: Vec<String>) {
+        self.context.window_manager.screen_rows = rows;
+    }
+
+    /// True if a popup was closed since this was last asked.
+    pub fn take_popup_closed(&mut self) -> bool {
+        std::mem::take(&mut self.context.window_manager.popup_closed)
+    }
+
+    /// Updates the recent screens `builtin:wayback` shows.
+    pub fn set_snapshots(&mut self, snapshots: Vec<Snapshot>, alternate_screen: bool) {
+        self.context.snapshots = snapshots;
+        self.context.alternate_screen = alternate_screen;
+    }
+
+    /// Tells hooks whether a program or command has the shell's terminal.
+    pub fn set_shell_busy(&mut self, busy: bool) {
+        self.context.shell_busy = busy;
+    }
+
+    /// Tells hooks which process the shell is.
+    pub fn set_shell_pid(&mut self, pid: Option<i32>) {
+        self.context.shell_pid = pid;
+    }
+
+    /// Tells hooks what has the shell's terminal and where the shell is.
+    pub fn set_foreground(&mut self, pid: Option<i32>, cwd: Option<PathBuf>) {
+        self.context.foreground_pid = pid;
+        self.context.cwd = cwd;
+    }
+
+    /// Sets how popups are drawn.
+    pub fn set_style(&mut self, style: Style) {
+        self.context.window_manager.style = style;
+    }
+
+    /// Sets what colors code blocks in LLM replies, if anything.
+    pub fn set_highlighter(&mut self, highlighter: Option<Highlighter>) {
+        self.context.window_manager.highlighter = highlighter;
+    }
+
+    /// Sets where errors are logged.
+    pub fn set_error_log(&mut self, errors: ErrorLog) {
+        self.context.errors = errors;
+    }
+
+    /// Logs and shows an error of the session; see `HookContext::report_error`.
+    pub fn report_error(&mut self, error: Error) {
+        self.context.report_error(error);
+    }
+
+    /// Logs an error of background work and shows it the first time.
+    pub fn report_background_error(&mut self, error: Error) {
+        self.context.report_background_error(error);
+    }
+
+    /// Tells popups which rows show inline images.
+    pub fn set_image_regions(&mut self, regions: Vec<(u16, u16)>) {
+        self.context.window_manager.avoid_rows = regions;
+    }
+
+    /// Where the chat view is drawn, if it is open.
+    pub fn chat_layout(&self) -> Option<Layout> {
+        self.context.window_manager.chat.layout
+    }
+
+    /// Draws the chat view in `layout`, opening it or moving it there, or
+    /// with None takes it off the screen.
+    pub fn set_chat_layout(&mut self, layout: Option<Layout>) -> Result<()> {
+        let window_manager = &mut self.context.window_manager;
+        if layout.is_none() {
+            window_manager.clear_chat()?;
+        }
+        window_manager.chat.layout = layout;
+        window_manager.draw_chat()
+    }
+
+    pub fn draw_chat(&self) -> Result<()> {
+        self.context.window_manager.draw_chat()
+    }
+
+    /// The questions to the assistant and their answers, oldest first,
+    /// leaving out one still being answered.
+    pub fn chat_messages(&self) -> Vec<ChatMessage> {
+        self.context.window_manager.chat.messages.iter()
+            .filter(|message| !message.answer.is_empty())
+            .cloned()
+            .collect()
+    }
+
+    /// Adds earlier questions and answers to the conversation, as from a
+    /// saved state.
+    pub fn restore_chat(&mut self, messages: impl IntoIterator<Item = ChatMessage>) -> Result<()> {
+        let window_manager = &mut self.context.window_manager;
+        for message in messages {
+            window_manager.chat.ask(&message.question);
+            window_manager.chat.append(&message.answer);
+        }
+        window_manager.draw_chat()
+    }
+
+    /// Adds more of the assistant's answer to the chat view, as an
+    /// `AskLlm` request sends it.
+    pub fn append_chat(&mut self, text: &str) -> Result<()> {
+        let window_manager = &mut self.context.window_manager;
+        window_manager.chat.append(text);
+        window_manager.draw_chat()
+    }
+
+    /// Shows what the `[llm]` answered to an `AskLlm` request, and offers
+    /// to run the command it suggests.
+    pub fn llm_reply(&mut self, reply: LlmReply) -> Result<()> {
+        self.context.answer(reply)
+    }
+
+    /// Hands a finished capture to the hook that started it.
+    pub fn complete_capture(&mut self, capture: Capture) -> Result<()> {
+        match self.context.pending_captures.remove(&capture.id) {
+            Some(follow_up) => self.context.finish_capture(follow_up, capture.lines),
+            None => Ok(()),
+        }
+    }
+
+    /// Gives up on a capture whose output never arrived.
+    pub fn cancel_capture(&mut self, id: u64) -> Result<()> {
+        if self.context.pending_captures.remove(&id).is_some() {
+            self.context.window_manager.show_popup(&tr("popup-error"), &tr("error-capture-timeout"))?;
+        }
+        Ok(())
+    }
+
+    /// The names of the enabled hooks bound to `key`, in the order they
+    /// get it.
+    pub fn matching_hooks(&self, key: &KeyInput) -> Vec<&str> {
+        ordered(&self.hooks)
+            .into_iter()
+            .filter(|hook| hook.matches(key))
+            .map(|hook| hook.config.name.as_str())
+            .collect()
+    }
+
+    /// The hooks bound to `keys`, enabled or not, in the order a press
+    /// would run them. `keys` is written like a `key_combination`, so it
+    /// can be a double or long press or a chord. None if it isn't a key.
+    pub fn bound_hooks(&self, keys: &str) -> Option<Vec<&HookConfig>> {
+        let key = KeyInput::parse(keys);
+        let chord = match key {
+            Some(_) => None,
+            None => {
+                let (prefix, key) = keys.trim().split_once(char::is_whitespace)?;
+                Some((KeyInput::parse(prefix)?, KeyInput::parse(key.trim())?))
+            }
+        };
+        let mut hooks: Vec<&Hook> = ordered(&self.hooks)
+            .into_iter()
+            .filter(|hook| hook.config.event.is_none())
+            .filter(|hook| match (&key, &chord) {
+                (Some(key), _) => key.matches_trigger(&hook.config.key_combination),
+                (None, Some((prefix, key))) => hook
+                    .chord()
+                    .is_some_and(|(first, second)| prefix.matches_pattern(&first) && key.matches_pattern(&second)),
+                (None, None) => false,
+            })
+            .collect();
+        hooks.sort_by_key(|hook| hook.press() != Press::Double);
+        Some(hooks.into_iter().map(|hook| &hook.config).collect())
+    }
+
+    /// True if some enabled hook would handle `key`.
+    pub fn matches_any(&self, key: &KeyInput) -> bool {
+        self.hooks.values().any(|hook| hook.matches(key))
+    }
+
+    /// Sends what a background `cmd:` action printed where the hook's
+    /// `output` says. Errors aren't typed at the prompt; they pop up.
+    pub fn show_command_result(&mut self, result: CommandResult) -> Result<()> {
+        // A chain goes on only after a command that succeeded
+        let then = result.command.then.filter(|_| result.succeeded());
+        let name = result.command.hook.clone();
+        let title = tr_args("command-title", &[("command", result.command.command.as_str().into())]);
+        let requests = match &result.output {
+            Ok(output) if result.command.structured && result.succeeded() => {
+                structured::parse(&String::from_utf8_lossy(&output.stdout))
+            }
+            _ => None,
+        };
+        // Held back in full-screen programs like what the hook types itself
+        let hook = match self.hooks.get(&name) {
+            Some(hook) => hook.config.clone(),
+            None => transient_hook(&name, "").config,
+        };
+        self.context.as_hook(&hook, |ctx| -> Result<()> {
+            match requests {
+                Some(requests) => ctx.run_requests(&name, &result.command.output, &title, result.command.on_select.as_ref(), requests)?,
+                None => match result.content()? {
+                    // A popup only saying it's done isn't worth a key to close
+                    Some(_) if matches!(result.command.output, HookOutput::Popup) && result.printed_nothing() => {
+                        let text = tr_args("command-finished", &[("command", result.command.command.as_str().into())]);
+                        ctx.window_manager.show_toast(&text, TOAST_LIFETIME);
+                    }
+                    Some(content) => {
+                        let output = match result.command.output {
+                            HookOutput::Inject if result.failed() => HookOutput::Popup,
+                            output => output,
+                        };
+                        ctx.deliver(&output, &title, &content)?;
+                    }
+                    None => {}
+                },
+            }
+            Ok(())
+        })?;
+        let Some(step) = then else {
+            return Ok(());
+        };
+        // The hook may have gone with its project or a reloaded config
+        let Some(hook) = self.hooks.get(&name) else {
+            return Ok(());
+        };
+        let key = KeyInput::new(KeyCode::Null, KeyModifiers::empty());
+        hook.execute_from(step, &key, &mut self.context)
+            .map(|_| ())
+            .with_context(|| format!("Hook '{}' failed", name))
+    }
+
+    /// Returns the session actions queued by hooks since the last call.
+    pub fn take_actions(&mut self) -> Vec<SessionAction> {
+        let pinned = self.context.window_manager.take_pinned();
+        self.context.actions.extend(pinned.into_iter().map(SessionAction::PinNote));
+        std::mem::take(&mut self.context.actions)
+    }
+
+    pub fn enable_hook(&mut self, name: &str, enabled: bool) -> bool {
+        if let Some(hook) = self.hooks.get_mut(name) {
+            hook.config.enabled = enabled;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The key patterns enabled hooks are bound to a long press of.
+    pub fn long_press_patterns(&self) -> Vec<String> {
+        self.hooks.values()
+            .filter(|hook| hook.config.enabled && hook.press() == Press::Long)
+            .map(|hook| split_trigger(&hook.config.key_combination).1)
+            .collect()
+    }
+
+    /// Sets how long the prefix of a chord waits for its second key.
+    pub fn set_chord_timeout(&mut self, timeout: Duration) {
+        self.chord_timeout = timeout;
+    }
+
+    /// Follows `key` through chord bindings. The prefix of a chord is held
+    /// back until the next key shows whether it completes one.
+    pub fn chord_key(&mut self, key: &KeyInput, now: Instant) -> ChordKey {
+        match self.chord.take() {
+            Some((prefix, _)) if self.hooks.values().any(|hook| hook.matches_chord(&prefix, key)) => {
+                ChordKey::Complete(prefix)
+            }
+            Some((prefix, _)) if prefix.same_key(key) => ChordKey::Repeated(prefix),
+            Some((prefix, _)) => ChordKey::Broken(prefix),
+            None => {
+                let prefix = |hook: &Hook| hook.config.enabled && hook.chord().is_some_and(|(first, _)| key.matches_pattern(&first));
+                if self.hooks.values().any(prefix) {
+                    self.chord = Some((key.clone(), now));
+                    ChordKey::Armed
+                } else {
+                    ChordKey::Pass
+                }
+            }
+        }
+    }
+
+    /// The prefix of a chord that waited too long for its second key, to
+    /// be passed on to the shell as it is.
+    pub fn expire_chord(&mut self, now: Instant) -> Option<KeyInput> {
+        match &self.chord {
+            Some((_, since)) if now.duration_since(*since) >= self.chord_timeout => {
+                self.chord.take().map(|(prefix, _)| prefix)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn process_key(&mut self, key: &KeyInput) -> Result<bool> {
+        // A double press goes to the hooks bound to one before those bound
+        // to the key itself, whatever their priority
+        let mut hooks: Vec<&Hook> = ordered(&self.hooks).into_iter().filter(|hook| hook.matches(key)).collect();
+        hooks.sort_by_key(|hook| hook.press() != Press::Double);
+        Ok(run_hooks(hooks, key, &mut self.context))
+    }
+
+    /// The names of the enabled hooks that run on `event`, in order.
+    pub fn event_hooks(&self, event: HookEvent) -> Vec<&str> {
+        ordered(&self.hooks)
+            .into_iter()
+            .filter(|hook| hook.config.enabled && hook.config.event == Some(event))
+            .map(|hook| hook.config.name.as_str())
+            .collect()
+    }
+
+    /// Runs every hook on the event `mark` stands for.
+    pub fn process_command_mark(&mut self, mark: &CommandMark) {
+        self.process_event(mark.event(), &mark.env());
+    }
+
+    /// Runs every hook on `event`, telling their commands `env`. Unlike
+    /// keys, events aren't consumed, so one hook doesn't keep the next from
+    /// running.
+    pub fn process_event(&mut self, event: HookEvent, env: &[(String, String)]) {
+        let hooks = ordered(&self.hooks)
+            .into_iter()
+            .filter(|hook| hook.config.enabled && hook.config.event == Some(event));
+        for hook in hooks {
+            if let Err(e) = hook.execute_event(event, env, &mut self.context) {
+                let error = Error::categorize(e, Error::Plugin).context(format!("Hook '{}' failed", hook.config.name));
+                self.context.report_error(error);
+            }
+        }
+    }
+
+    /// Notes that a key was pressed, which starts the time of `idle` hooks
+    /// over.
+    pub fn key_pressed(&mut self, now: Instant) {
+        self.last_key = now;
+    }
+
+    /// When the next `interval` or `idle` hook is due, if there is one.
+    pub fn next_timer(&self) -> Option<Instant> {
+        self.hooks.values().filter(|hook| hook.config.enabled).filter_map(|hook| self.timer_due(hook)).min()
+    }
+
+    fn timer_due(&self, hook: &Hook) -> Option<Instant> {
+        let last_run = self.timer_runs.get(&hook.config.name).copied();
+        let interval = hook.config.interval.map(|Interval(every)| last_run.unwrap_or(self.started) + every);
+        // Once for each time the keyboard is left alone
+        let idle = hook
+            .config
+            .idle
+            .filter(|_| last_run.is_none_or(|run| run < self.last_key))
+            .map(|Interval(after)| self.last_key + after);
+        interval.into_iter().chain(idle).min()
+    }
+
+    /// Runs the `interval` and `idle` hooks due at `now`. Like events, they
+    /// aren't consumed, so each of them runs. One held back by its
+    /// condition waits for its next turn.
+    pub fn run_timers(&mut self, now: Instant) {
+        let due: Vec<String> = ordered(&self.hooks)
+            .into_iter()
+            .filter(|hook| hook.config.enabled && self.timer_due(hook).is_some_and(|due| due <= now))
+            .map(|hook| hook.config.name.clone())
+            .collect();
+        let key = KeyInput::new(KeyCode::Null, KeyModifiers::empty());
+        for name in due {
+            self.timer_runs.insert(name.clone(), now);
+            let Some(hook) = self.hooks.get(&name) else {
+                continue;
+            };
+            if !self.context.allows(&hook.config.when) || self.context.held_back_by_full_screen(hook) {
+                continue;
+            }
+            if let Err(e) = hook.execute(&key, &mut self.context) {
+                let error = Error::categorize(e, Error::Plugin).context(format!("Hook '{}' failed", name));
+                // One that keeps failing is shown once
+                self.context.report_background_error(error);
+            }
+        }
+    }
+
+    /// Runs the hooks bound to the chord `prefix` then `key`.
+    pub fn process_chord(&mut self, prefix: &KeyInput, key: &KeyInput) -> Result<bool> {
+        let hooks = ordered(&self.hooks).into_iter().filter(|hook| hook.matches_chord(prefix, key)).collect();
+        Ok(run_hooks(hooks, key, &mut self.context))
+    }
+
+    /// Searches the shell's output, and copies a line picked.
+    pub fn search_scrollback(&mut self, scrollback: &Scrollback) -> Result<()> {
+        self.context.window_manager.show_scrollback_search(scrollback)
+    }
+
+    /// Browses `scrollback` to copy lines or comment on them, marking the
+    /// lines in `annotated`, and returns the comments made.
+    pub fn copy_mode(&mut self, scrollback: &Scrollback, annotated: &[Range<usize>]) -> Result<Vec<(Range<usize>, String)>> {
+        let alternate_screen = self.context.alternate_screen;
+        self.context.window_manager.show_copy_mode(scrollback.lines(), scrollback.first_line(), annotated, alternate_screen)
+    }
+
+    /// Lists the hooks to turn them on or off. A change applies at once
+    /// and is saved to the file the hook came from, except for hooks bound
+    /// for the session.
+    pub fn manage_hooks(&mut self) -> Result<()> {
+        let mut configs: Vec<HookConfig> = self.list_hooks().into_iter().cloned().collect();
+        let config_path = self.context.config_path.clone().unwrap_or_else(Config::get_default_config_path);
+        let config_path = Path::new(&config_path);
+        let temporary = &self.temporary;
+        let project = self.project.as_ref();
+        self.context.window_manager.show_hook_manager(&mut configs, &mut |hook| {
+            // Hooks bound for the session aren't in any file
+            if temporary.contains_key(&hook.name) {
+                return Ok(());
+            }
+            match project.filter(|project| project.added.contains(&hook.name)) {
+                Some(project) => save_hook_enabled(&project.path, hook),
+                None => save_hook_enabled(config_path, hook),
+            }
+        })?;
+        for config in configs {
+            if let Some(hook) = self.hooks.get_mut(&config.name) {
+                hook.config.enabled = config.enabled;
+            }
+        }
+        Ok(())
     }
 
-    pub fn get_hook_mut(&mut self, name: &str) -> Option<&mut Hook> {
-        self.hooks.get_mut(name)
+    /// What each bound key does, as the keys and a description, for the
+    /// help and `chatshell cheatsheet`. With `in_session`, leaves out the
+    /// actions the session lacks what they need for, as the palette does.
+    pub fn key_bindings(&self, in_session: bool) -> Vec<(String, String)> {
+        let (_, _, entries) = palette_entries(&self.hooks, in_session.then_some(&self.context));
+        entries
+            .into_iter()
+            .filter(|(_, keys)| !keys.trim().is_empty())
+            .map(|(description, keys)| (keys, description))
+            .collect()
     }
 
-    pub fn enable_hook(&mut self, name: &str, enabled: bool) -> bool {
-        if let Some(hook) = self.hooks.get_mut(name) {
-            hook.config.enabled = enabled;
-            true
-        } else {
-            false
+    /// Shows the help, listing the keys bound now.
+    pub fn show_help(&mut self, output: &HookOutput) -> Result<()> {
+        let mut text = tr("help-text");
+        text.push_str("\n\n");
+        for line in clock::format_table(&self.key_bindings(true)) {
+            text.push_str(&format!("  {}\n", line));
         }
+        text.push('\n');
+        text.push_str(&tr("help-close"));
+        self.context.deliver(output, &tr("help-title"), &text)
     }
 
-    pub fn process_key(&mut self, key: &KeyInput) -> Result<bool> {
-        for hook in self.hooks.values() {
-            if hook.matches(key) {
-                match hook.execute(key, &mut self.window_manager) {
-                    Ok(consumed) => {
-                        if consumed {
-                            return Ok(true); // Key was consumed by hook
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Hook '{}' execution failed: {}", hook.config.name, e);
-                        // Continue processing other hooks
-                    }
-                }
-            }
-        }
-        Ok(false) // No hook consumed the key
+    /// Lists the named actions and the hooks running anything else, with
+    /// the keys bound to each, and runs the one picked.
+    pub fn show_palette(&mut self) -> Result<()> {
+        let (listed, others, entries) = palette_entries(&self.hooks, Some(&self.context));
+        let Some(index) = self.context.window_manager.show_palette(&entries)? else {
+            return Ok(());
+        };
+        let key = KeyInput::new(KeyCode::Null, KeyModifiers::empty());
+        let result = match index.checked_sub(listed.len()) {
+            Some(other) => others[other].execute(&key, &mut self.context),
+            None => run_action("palette", listed[index].name, &key, &mut self.context),
+        };
+        result.map(|_| ())
     }
 
     pub fn list_hooks(&self) -> Vec<&HookConfig> {
-        self.hooks.values().map(|h| &h.config).collect()
+        ordered(&self.hooks).into_iter().map(|h| &h.config).collect()
     }
 
     pub fn list_enabled_hooks(&self) -> Vec<&HookConfig> {
-        self.hooks
-            .values()
+        ordered(&self.hooks)
+            .into_iter()
             .filter(|h| h.config.enabled)
             .map(|h| &h.config)
             .collect()
     }
 }
 
+/// The named actions and the hooks running anything else, with the keys
+/// bound to each: what the palette lists, and with keys, the help and the
+/// cheatsheet. Without a session `context`, actions are listed whatever
+/// they need.
+fn palette_entries<'a>(hooks: &'a HashMap<String, Hook>, context: Option<&HookContext>) -> (Vec<&'static Action>, Vec<&'a Hook>, Vec<(String, String)>) {
+    let bound: Vec<&Hook> = ordered(hooks)
+        .into_iter()
+        .filter(|hook| hook.config.enabled && hook.config.event.is_none() && hook.config.action != "builtin:palette")
+        .collect();
+    let keys = |action: &str| -> String {
+        let keys: Vec<&str> = bound
+            .iter()
+            .filter(|hook| hook.config.action == action)
+            .map(|hook| hook.config.key_combination.as_str())
+            .collect();
+        keys.join(", ")
+    };
+    // Left out where the session lacks what they need, like a database
+    let listed: Vec<&Action> = actions()
+        .listed()
+        .filter(|action| context.is_none_or(|context| action.missing(context).is_none()))
+        .collect();
+    let others: Vec<&Hook> = bound
+        .iter()
+        .filter(|hook| !actions().listed().any(|action| hook.config.action == action.name))
+        .copied()
+        .collect();
+    let mut entries: Vec<(String, String)> =
+        listed.iter().map(|action| (tr(action.description), keys(action.name))).collect();
+    entries.extend(others.iter().map(|hook| {
+        let name = match &hook.config.description {
+            Some(description) => format!("{}: {}", hook.config.name, description),
+            None => hook.config.name.clone(),
+        };
+        (name, hook.config.key_combination.clone())
+    }));
+    (listed, others, entries)
+}
+
+/// The hooks in the order they get a key: by priority, highest first, then
+/// in the order they were added.
+fn ordered(hooks: &HashMap<String, Hook>) -> Vec<&Hook> {
+    let mut hooks: Vec<&Hook> = hooks.values().collect();
+    hooks.sort_by_key(|hook| (std::cmp::Reverse(hook.config.priority), hook.order));
+    hooks
+}
+
+/// A hook running `action` for something other than a configured hook,
+/// like the palette or a plugin.
+fn transient_hook(name: &str, action: &str) -> Hook {
+    Hook::new(HookConfig {
+        name: name.to_string(),
+        action: action.into(),
+        enabled: true,
+        ..Default::default()
+    })
+}
+
+fn run_action(name: &str, action: &str, key: &KeyInput, context: &mut HookContext) -> Result<bool> {
+    transient_hook(name, action).execute(key, context)
+}
+
+/// Runs `hooks` for `key` until one consumes it, and tells whether one did.
+fn run_hooks(hooks: Vec<&Hook>, key: &KeyInput, context: &mut HookContext) -> bool {
+    for hook in hooks {
+        // A hook held back by its condition leaves the key to the shell
+        if !context.allows(&hook.config.when) {
+            continue;
+        }
+        if context.held_back_by_full_screen(hook) {
+            let text = tr_args("full-screen-held-back", &[("hook", hook.config.name.as_str().into())]);
+            let note = Note::toast(&tr("full-screen-title"), &text, context.clock.now());
+            context.push_action(SessionAction::PinNote(note));
+            continue;
+        }
+        match hook.execute(key, context) {
+            Ok(true) => return true, // Key was consumed by hook
+            Ok(false) => {}
+            Err(e) => {
+                let error = Error::categorize(e, Error::Plugin)
+                    .context(format!("Hook '{}' failed", hook.config.name));
+                context.report_error(error);
+                // Continue processing other hooks
+            }
+        }
+    }
+    false // No hook consumed the key
+}
+
+impl Default for HookManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 // Built-in hook functions that can be referenced in config
 pub fn create_default_hooks() -> Vec<HookConfig> {
     vec![
         HookConfig {
             name: "help".to_string(),
             key_combination: "ctrl+;".to_string(),
-            action: "fn:show_help".to_string(),
+            action: "fn:show_help".into(),
             description: Some("Show help information".to_string()),
             enabled: true,
+            ..Default::default()
         },
         HookConfig {
             name: "time".to_string(),
             key_combination: "ctrl+t".to_string(),
-            action: "fn:show_time".to_string(),
+            action: "fn:show_time".into(),
             description: Some("Show current time".to_string()),
             enabled: false, // Disabled by default
+            ..Default::default()
         },
         HookConfig {
             name: "clear".to_string(),
             key_combination: "ctrl+l".to_string(),
-            action: "builtin:clear_screen".to_string(),
+            action: "builtin:clear_screen".into(),
             description: Some("Clear screen".to_string()),
             enabled: false, // Let normal Ctrl+L pass through by default
+            ..Default::default()
         },
         HookConfig {
             name: "config_info".to_string(),
             key_combination: "ctrl+shift+c".to_string(),
-            action: "builtin:show_config".to_string(),
+            action: "builtin:show_config".into(),
             description: Some("Show configuration info".to_string()),
             enabled: true,
+            ..Default::default()
+        },
+        HookConfig {
+            name: "palette".to_string(),
+            key_combination: "ctrl+shift+p".to_string(),
+            action: "builtin:palette".into(),
+            description: Some("Pick an action to run".to_string()),
+            enabled: true,
+            ..Default::default()
         },
     ]
 }
 
+/// The hooks of a bundled preset.
+pub fn preset_hooks(name: &str) -> Option<Vec<HookConfig>> {
+    let hook = |name: &str, key: &str, action: &str, description: &str| HookConfig {
+        name: name.to_string(),
+        key_combination: key.to_string(),
+        action: action.into(),
+        description: Some(description.to_string()),
+        enabled: true,
+        ..Default::default()
+    };
+
+    match name {
+        "database" => Some(vec![
+            hook("explain_query", "alt+e", "builtin:explain_query", "Explain the last query or its error"),
+            hook("last_result", "alt+r", "builtin:show_last_result", "Show the last result as a table"),
+            hook("ask", "alt+a", "builtin:ask", "Ask the assistant"),
+        ]),
+        _ => None,
+    }
+}
+
+/// Saves whether `hook` is on in the file it came from: the `pack.toml` of
+/// a pack's `<pack>/<hook>`, otherwise the config, where a preset's hook
+/// gets an entry of its own.
+fn save_hook_enabled(config_path: &Path, hook: &HookConfig) -> Result<()> {
+    let (path, name) = match hook.name.split_once('/') {
+        Some((pack, name)) => (hookpack::packs_dir(config_path).join(pack).join(hookpack::PACK_FILE), name),
+        None => (config_path.to_path_buf(), hook.name.as_str()),
+    };
+    let text = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let text = match set_hook_enabled(&text, name, hook.enabled) {
+        Some(text) => text,
+        None if path == config_path => {
+            #[derive(serde::Serialize)]
+            struct Entry<'a> {
+                hooks: [&'a HookConfig; 1],
+            }
+            let entry = toml::to_string(&Entry { hooks: [hook] }).with_context(|| "Failed to write the hook")?;
+            format!("{}\n{}", text, entry)
+        }
+        None => anyhow::bail!("No hook {} in {}", name, path.display()),
+    };
+    std::fs::write(&path, text).with_context(|| format!("Failed to write {}", path.display()))
+}
+
+/// The hooks of the config's presets and of the packs installed in
+/// `packs_dir`, followed by its own, which replace earlier hooks of the
+/// same name. Packs that fail to load are left out, with the errors
+/// saying why.
+pub fn configured_hooks(config: &Config, packs_dir: &Path) -> Result<(Vec<HookConfig>, Vec<anyhow::Error>)> {
+    let mut hooks = Vec::new();
+    for preset in &config.presets {
+        hooks.extend(preset_hooks(preset).with_context(|| format!("Unknown preset: {}", preset))?);
+    }
+    let (pack_hooks, errors) = hookpack::installed_hooks(packs_dir)?;
+    hooks.extend(pack_hooks);
+    hooks.extend(config.hooks.iter().cloned());
+    Ok((hooks, errors))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::terminal::KeyInput;
     use crossterm::event::{KeyCode, KeyModifiers};
+    use crate::window::PopupUpdate;
+    use crate::pane::SplitDirection;
+
+    fn test_hook(name: &str, keys: &str, action: &str) -> HookConfig {
+        HookConfig { name: name.to_string(), key_combination: keys.to_string(), action: action.into(), enabled: true, ..Default::default() }
+    }
 
     #[test]
     fn test_hook_matching() {
-        let config = HookConfig {
-            name: "test".to_string(),
-            key_combination: "ctrl+;".to_string(),
-            action: "echo test".to_string(),
-            description: None,
-            enabled: true,
-        };
+        let config = test_hook("test", "ctrl+;", "echo test");
 
         let hook = Hook::new(config);
         let key = KeyInput::new(KeyCode::Char(';'), KeyModifiers::CONTROL);
@@ -274,15 +2983,398 @@ mod tests {
     }
 
     #[test]
-    fn test_hook_manager() {
+    fn test_double_and_long_press_hooks() {
+        let hook = |name: &str, keys: &str| test_hook(name, keys, "echo test");
+        let manager = HookManager::from_configs(vec![
+            hook("picker", "ctrl+c ctrl+c"),
+            hook("notes", "hold ctrl+k"),
+            hook("kill_line", "ctrl+k"),
+        ]);
+
+        let ctrl_c = KeyInput::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert!(!manager.matches_any(&ctrl_c));
+        assert_eq!(manager.matching_hooks(&KeyInput::parse("ctrl+c ctrl+c").unwrap()), vec!["picker"]);
+        assert_eq!(manager.matching_hooks(&KeyInput::parse("ctrl+k").unwrap()), vec!["kill_line"]);
+        assert_eq!(manager.matching_hooks(&KeyInput::parse("hold ctrl+k").unwrap()), vec!["notes"]);
+        assert_eq!(manager.long_press_patterns(), vec!["ctrl+k".to_string()]);
+    }
+
+    #[test]
+    fn test_bound_hooks() {
+        let hook = |name: &str, keys: &str, enabled: bool| HookConfig { enabled, ..test_hook(name, keys, "echo test") };
+        let manager = HookManager::from_configs(vec![
+            hook("old_log", "ctrl+shift+l", false),
+            hook("log", "ctrl+shift+l", true),
+            hook("tail", "ctrl+a t", true),
+            hook("picker", "ctrl+c ctrl+c", true),
+        ]);
+        let names = |keys: &str| manager.bound_hooks(keys).map(|hooks| hooks.iter().map(|hook| hook.name.clone()).collect::<Vec<_>>());
+        assert_eq!(names("ctrl+shift+l"), Some(vec!["old_log".to_string(), "log".to_string()]));
+        assert_eq!(names("ctrl+a t"), Some(vec!["tail".to_string()]));
+        assert_eq!(names("ctrl+c ctrl+c"), Some(vec!["picker".to_string()]));
+        assert_eq!(names("ctrl+c"), Some(Vec::new()));
+        assert_eq!(names("ctrl+nope"), None);
+    }
+
+    #[test]
+    fn test_hooks_run_by_priority_then_order() {
+        let hook = |name: &str, priority: i32| HookConfig { priority, ..test_hook(name, "ctrl+g", "echo test") };
+        let mut manager = HookManager::from_configs(vec![hook("zeta", 0), hook("alpha", 0), hook("urgent", 10), hook("late", -1)]);
+        let key = KeyInput::parse("ctrl+g").unwrap();
+        assert_eq!(manager.matching_hooks(&key), vec!["urgent", "zeta", "alpha", "late"]);
+
+        // A hook that replaces another goes after the ones added before it
+        manager.add_hook(hook("zeta", 0));
+        assert_eq!(manager.matching_hooks(&key), vec!["urgent", "alpha", "zeta", "late"]);
+        let names: Vec<&str> = manager.list_hooks().iter().map(|config| config.name.as_str()).collect();
+        assert_eq!(names, vec!["urgent", "alpha", "zeta", "late"]);
+    }
+
+    /// Runs the commands the hooks queued, as the session does.
+    async fn run_commands(manager: &mut HookManager) -> Vec<CommandResult> {
+        let mut results = Vec::new();
+        for action in manager.take_actions() {
+            if let SessionAction::RunCommand(command) = action {
+                results.push(command.run().await);
+            }
+        }
+        results
+    }
+
+    #[tokio::test]
+    async fn test_action_chains() {
+        let config: Config = toml::from_str(concat!(
+            "[shell]\ncommand = \"/bin/sh\"\nargs = []\n\n",
+            "[[hooks]]\nname = \"build\"\nkey_combination = \"ctrl+o\"\n",
+            "action = [\"inject:make\", \"cmd:true\", \"inject: done\"]\nenabled = true\noutput = \"silent\"\n\n",
+            "[[hooks]]\nname = \"fails\"\nkey_combination = \"ctrl+f\"\n",
+            "action = [\"cmd:exit 2\", \"inject:never\"]\nenabled = true\noutput = \"silent\"\n\n",
+            "[[hooks]]\nname = \"broken\"\nkey_combination = \"ctrl+b\"\n",
+            "action = [\"plugin:missing\", \"inject:never\"]\nenabled = true\n",
+        ))
+        .unwrap();
+        assert_eq!(config.hooks[0].action.steps().len(), 3);
+        assert_eq!(config.hooks[0].action.to_string(), "inject:make → cmd:true → inject: done");
+        let mut manager = HookManager::from_configs(config.hooks);
+        let ctrl = |c| KeyInput::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+
+        // The steps after a command wait for it to succeed
+        assert!(manager.process_key(&ctrl('o')).unwrap());
+        let actions = manager.take_actions();
+        assert_eq!(actions[0], SessionAction::SendInput("make".to_string()));
+        let SessionAction::RunCommand(command) = actions[1].clone() else {
+            panic!("expected a command, got {:?}", actions[1]);
+        };
+        assert_eq!(command.then, Some(2));
+        assert_eq!(actions.len(), 2);
+        manager.show_command_result(command.run().await).unwrap();
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput(" done".to_string())]);
+
+        assert!(manager.process_key(&ctrl('f')).unwrap());
+        for result in run_commands(&mut manager).await {
+            manager.show_command_result(result).unwrap();
+        }
+        assert!(manager.take_actions().is_empty());
+
+        let key = ctrl('b');
+        let error = manager.hooks["broken"].execute(&key, &mut manager.context).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("Step 1 of 2 failed: No plugin named missing"));
+        assert!(manager.take_actions().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_structured_results() {
         let mut manager = HookManager::new();
-        let config = HookConfig {
-            name: "test".to_string(),
-            key_combination: "ctrl+a".to_string(),
-            action: "builtin:clear_screen".to_string(),
-            description: None,
-            enabled: true,
+        let command = |command: &str| HookCommand {
+            command: command.to_string(),
+            output: HookOutput::Inject,
+            hook: "deploy".to_string(),
+            structured: true,
+            ..Default::default()
+        };
+
+        let json = r#"printf '{"send_keys": "kubectl get pods"}\n{"popup": "typed", "action": "inject: -w"}'"#;
+        manager.show_command_result(command(json).run().await).unwrap();
+        assert_eq!(manager.take_actions(), vec![
+            SessionAction::SendInput("kubectl get pods".to_string()),
+            SessionAction::SendInput("typed".to_string()),
+            SessionAction::SendInput(" -w".to_string()),
+        ]);
+
+        // Other output is text as usual
+        manager.show_command_result(command("echo '{\"status\": 1}'").run().await).unwrap();
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput("{\"status\": 1}".to_string())]);
+
+        // Keys for a full-screen program are held back, with one note
+        manager.set_snapshots(Vec::new(), true);
+        let json = r#"printf '{"send_keys": "q"}\n{"send_keys": "q"}'"#;
+        manager.show_command_result(command(json).run().await).unwrap();
+        let actions = manager.take_actions();
+        assert!(matches!(&actions[..], [SessionAction::PinNote(note)] if note.lines[0].0.contains("deploy")));
+    }
+
+    #[tokio::test]
+    async fn test_command_input() {
+        let command = |input: Option<&str>| HookCommand {
+            command: "cat".to_string(),
+            quiet: true,
+            output: HookOutput::Inject,
+            timeout: Some(Duration::from_secs(5)),
+            input: input.map(str::to_string),
+            ..Default::default()
+        };
+        assert_eq!(command(Some("feature/login\n")).run().await.content().unwrap(), Some("feature/login".to_string()));
+        // Without input it reads nothing rather than waiting
+        assert_eq!(command(None).run().await.content().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_event_hooks_see_the_command() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("event");
+        let config: Config = toml::from_str(&format!(
+            "[shell]\ncommand = \"/bin/sh\"\nargs = []\n\n[[hooks]]\nname = \"notify\"\nevent = \"on_command_end\"\naction = \"cmd:printf '%s|%s|%s' \\\"$CHATSHELL_COMMAND\\\" \\\"$CHATSHELL_EXIT_CODE\\\" \\\"$CHATSHELL_EVENT\\\" > {}\"\nenabled = true\n",
+            out.display()
+        ))
+        .unwrap();
+        assert_eq!(config.hooks[0].event, Some(HookEvent::OnCommandEnd));
+        assert_eq!(config.hooks[0].key_combination, "");
+
+        let mut manager = HookManager::from_configs(config.hooks);
+        assert_eq!(manager.event_hooks(HookEvent::OnCommandEnd), vec!["notify"]);
+        assert!(manager.event_hooks(HookEvent::OnCommandStart).is_empty());
+        // An event hook has no key
+        assert!(!manager.matches_any(&KeyInput::new(KeyCode::Null, KeyModifiers::empty())));
+
+        manager.process_command_mark(&CommandMark::Started { command: "make".to_string() });
+        assert!(run_commands(&mut manager).await.is_empty());
+        manager.process_command_mark(&CommandMark::Finished {
+            command: "make".to_string(),
+            exit_code: Some(2),
+            duration: Duration::from_secs(3),
+        });
+        let results = run_commands(&mut manager).await;
+        assert_eq!(std::fs::read_to_string(&out).unwrap(), "make|2|on_command_end");
+        // Quiet and successful, so no popup
+        assert_eq!(results[0].content().unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_session_event_hooks() {
+        let mut manager = HookManager::new();
+        for (name, event) in [("started", "on_start"), ("resized", "on_resize")] {
+            manager.add_hook(HookConfig {
+                name: name.to_string(),
+                action: "cmd:echo \"$CHATSHELL_EVENT $CHATSHELL_COLUMNS\"".into(),
+                enabled: true,
+                event: HookEvent::from_name(event),
+                ..Default::default()
+            });
+        }
+        assert_eq!(HookEvent::from_name("on_resize"), Some(HookEvent::OnResize));
+        assert_eq!(HookEvent::from_name("on_reload"), None);
+
+        let env = [("CHATSHELL_COLUMNS".to_string(), "120".to_string())];
+        manager.process_event(HookEvent::OnResize, &env);
+        let results = run_commands(&mut manager).await;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].content().unwrap().as_deref(), Some("on_resize 120"));
+        assert!(manager.event_hooks(HookEvent::OnExit).is_empty());
+    }
+
+    #[test]
+    fn test_results_go_to_their_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let log = dir.path().join("hooks.log");
+        let mut manager = HookManager::new();
+        let result = |command: &str, output: HookOutput| CommandResult {
+            command: HookCommand { command: command.to_string(), output, ..Default::default() },
+            output: std::process::Command::new("/bin/sh").arg("-c").arg(command).output().map_err(Into::into),
+            stopped: None,
+        };
+
+        let to_file = HookOutput::File(log.display().to_string());
+        manager.show_command_result(result("echo one", to_file.clone())).unwrap();
+        manager.show_command_result(result("echo two", to_file)).unwrap();
+        let logged = std::fs::read_to_string(&log).unwrap();
+        assert!(logged.contains("] Command: echo one\none\n\n["));
+        assert!(logged.ends_with("] Command: echo two\ntwo\n\n"));
+
+        manager.show_command_result(result("printf 'git\\n status'", HookOutput::Inject)).unwrap();
+        manager.show_command_result(result("echo gone", HookOutput::Silent)).unwrap();
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput("git status".to_string())]);
+
+        manager.show_command_result(result("echo built", HookOutput::Pane)).unwrap();
+        manager.show_command_result(result("printf 'deployed\\nv2'", HookOutput::Toast)).unwrap();
+        let actions = manager.take_actions();
+        assert!(matches!(&actions[0], SessionAction::ShowInPane { text, .. } if text == "built"));
+        let SessionAction::PinNote(toast) = &actions[1] else {
+            panic!("expected a toast, got {:?}", actions[1]);
+        };
+        assert_eq!(toast.lines, vec![("deployed".to_string(), None), ("v2".to_string(), None)]);
+        assert!(!toast.expired(Instant::now()));
+        assert!(toast.expired(Instant::now() + TOAST_LIFETIME));
+
+        // A popup with nothing to read but that the command is done is a toast
+        manager.show_command_result(result("true", HookOutput::Popup)).unwrap();
+        let actions = manager.take_actions();
+        let [SessionAction::PinNote(toast)] = actions.as_slice() else {
+            panic!("expected a toast, got {:?}", actions);
+        };
+        assert_eq!(toast.lines, vec![(tr_args("command-finished", &[("command", "true".into())]), None)]);
+    }
+
+    #[tokio::test]
+    async fn test_commands_run_in_the_background() {
+        let mut manager = HookManager::new();
+        manager.add_hook(test_hook("slow", "ctrl+g", "cmd:sleep 0.2; echo done"));
+
+        // The key is handled without waiting for the command
+        let started = Instant::now();
+        assert!(manager.process_key(&KeyInput::new(KeyCode::Char('g'), KeyModifiers::CONTROL)).unwrap());
+        assert!(started.elapsed() < Duration::from_millis(200));
+
+        let results = run_commands(&mut manager).await;
+        assert_eq!(results[0].content().unwrap().as_deref(), Some("done"));
+
+        let silent = HookCommand {
+            command: "true".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(silent.run().await.content().unwrap(), Some(tr("command-no-output")));
+    }
+
+    #[tokio::test]
+    async fn test_quick_commands_pop_up_at_the_end() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let popup = PopupHandle::new(1, tx);
+        let command = HookCommand { command: "echo quick".to_string(), ..Default::default() };
+        let result = command.run_until(std::future::pending(), Some(&popup)).await;
+        assert_eq!(result.content().unwrap().as_deref(), Some("quick"));
+        assert!(rx.try_recv().is_err());
+    }
+
+    // The paused clock jumps over the delay while the command waits
+    #[tokio::test(start_paused = true)]
+    async fn test_command_output_streams_into_a_popup() {
+        let command = |command: &str| HookCommand {
+            command: command.to_string(),
+            ..Default::default()
+        };
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let popup = PopupHandle::new(1, tx);
+
+        // What came before the delay shows once it is over, while the
+        // command waits for a line through a fifo
+        let dir = tempfile::tempdir().unwrap();
+        let fifo = dir.path().join("fifo");
+        assert!(std::process::Command::new("mkfifo").arg(&fifo).status().unwrap().success());
+        let started = tokio::time::Instant::now();
+        let slow = command(&format!("echo one; read line < {}; echo two", fifo.display()));
+        assert!(slow.streams());
+        let release = async {
+            let (_, update) = rx.recv().await.unwrap();
+            assert_eq!(update, PopupUpdate::Append("one\n".to_string()));
+            assert!(started.elapsed() >= LIVE_POPUP_DELAY);
+            tokio::task::spawn_blocking(move || std::fs::write(fifo, "\n")).await.unwrap().unwrap();
+        };
+        let (result, ()) = tokio::join!(slow.run_until(std::future::pending(), Some(&popup)), release);
+        assert_eq!(result.content().unwrap().as_deref(), Some("one\ntwo"));
+        assert_eq!(rx.try_recv().unwrap().1, PopupUpdate::Append("two\n".to_string()));
+        assert!(!HookCommand { structured: true, ..command("x") }.streams());
+    }
+
+    #[test]
+    fn test_llm_replies_are_kept_in_the_transcript() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("session.jsonl");
+        let mut manager = HookManager::new();
+        manager.set_transcript(TranscriptFile::open(&path).unwrap());
+        manager.set_chat_layout(Layout::split((80, 24), SplitDirection::Vertical, 35)).unwrap();
+
+        let request = LlmRequest {
+            llm: LlmConfig { command: Some("cat".to_string()), ..Default::default() },
+            history: HistoryConfig { enabled: false, ..Default::default() },
+            system_prompt: String::new(),
+            prompt: "SELECT 1".to_string(),
+            purpose: "explain_query".to_string(),
+            title: "Explain".to_string(),
+            question: "SELECT 1".to_string(),
+            in_chat: true,
         };
+        let mut streamed = String::new();
+        let reply = request.run(|text| streamed.push_str(text));
+        assert_eq!(streamed, "SELECT 1");
+        manager.llm_reply(reply).unwrap();
+        manager.flush_transcript();
+
+        let exchange = transcript::Entry::Exchange { question: "SELECT 1".to_string(), answer: "SELECT 1".to_string() };
+        assert_eq!(transcript::load(&path).unwrap()[1..], [exchange]);
+    }
+
+    #[tokio::test]
+    async fn test_commands_are_stopped_with_what_they_started() {
+        let dir = tempfile::tempdir().unwrap();
+        let marker = dir.path().join("late");
+        let command = |timeout: Option<u64>| HookCommand {
+            // The background sleep is in the command's process group too
+            command: format!("(sleep 0.5; touch {}) & sleep 5", marker.display()),
+            output: HookOutput::Inject,
+            timeout: timeout.map(Duration::from_millis),
+            ..Default::default()
+        };
+
+        let started = Instant::now();
+        let result = command(Some(100)).run().await;
+        assert!(started.elapsed() < Duration::from_millis(400));
+        assert_eq!(result.stopped, Some(Stopped::TimedOut));
+        assert!(result.failed());
+        assert_eq!(result.content().unwrap(), Some(tr_args("command-timed-out", &[("seconds", "0.1".into())])));
+
+        let result = command(None).run_until(tokio::time::sleep(Duration::from_millis(50)), None).await;
+        assert_eq!(result.stopped, Some(Stopped::Cancelled));
+        assert_eq!(result.content().unwrap(), None);
+
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        assert!(!marker.exists());
+    }
+
+    #[test]
+    fn test_chord_bindings() {
+        let hook = |name: &str, keys: &str| test_hook(name, keys, "echo test");
+        let mut manager = HookManager::from_configs(vec![hook("tail", "ctrl+a t"), hook("picker", "ctrl+c ctrl+c")]);
+        manager.set_chord_timeout(Duration::from_millis(500));
+        let key = |c| KeyInput::new(KeyCode::Char(c), KeyModifiers::empty());
+        let ctrl_a = KeyInput::new(KeyCode::Char('a'), KeyModifiers::CONTROL);
+        let start = Instant::now();
+
+        // A chord's keys don't match on their own; a double press is no chord
+        assert!(!manager.matches_any(&ctrl_a));
+        assert!(!manager.matches_any(&key('t')));
+        assert_eq!(manager.get_hook("picker").unwrap().chord(), None);
+        assert_eq!(manager.chord_key(&KeyInput::new(KeyCode::Char('c'), KeyModifiers::CONTROL), start), ChordKey::Pass);
+
+        assert_eq!(manager.chord_key(&ctrl_a, start), ChordKey::Armed);
+        assert_eq!(manager.chord_key(&key('t'), start), ChordKey::Complete(ctrl_a.clone()));
+        assert!(manager.get_hook("tail").unwrap().matches_chord(&ctrl_a, &key('t')));
+        assert_eq!(manager.chord_key(&key('t'), start), ChordKey::Pass);
+
+        assert_eq!(manager.chord_key(&ctrl_a, start), ChordKey::Armed);
+        assert_eq!(manager.chord_key(&key('x'), start), ChordKey::Broken(ctrl_a.clone()));
+        assert_eq!(manager.chord_key(&ctrl_a, start), ChordKey::Armed);
+        assert_eq!(manager.chord_key(&ctrl_a, start), ChordKey::Repeated(ctrl_a.clone()));
+
+        // An unanswered prefix goes to the shell after the timeout
+        assert_eq!(manager.chord_key(&ctrl_a, start), ChordKey::Armed);
+        assert_eq!(manager.expire_chord(start + Duration::from_millis(400)), None);
+        assert_eq!(manager.expire_chord(start + Duration::from_millis(500)), Some(ctrl_a));
+        assert_eq!(manager.chord_key(&key('t'), start), ChordKey::Pass);
+    }
+
+    #[test]
+    fn test_hook_manager() {
+        let mut manager = HookManager::new();
+        let config = test_hook("test", "ctrl+a", "builtin:clear_screen");
 
         manager.add_hook(config);
         assert!(manager.get_hook("test").is_some());
@@ -300,5 +3392,514 @@ mod tests {
 
         let action = Hook::parse_action("builtin:clear_screen");
         assert!(matches!(action, ActionType::Builtin(_)));
+
+        let action = Hook::parse_action("tail:!journalctl -f");
+        assert!(matches!(action, ActionType::Tail(source) if source == "!journalctl -f"));
+
+        let action = Hook::parse_action(r"inject:git status\n");
+        assert!(matches!(action, ActionType::Inject(text) if text == "git status\n"));
+
+        let action = Hook::parse_action("lua:~/.config/chatshell/deploy.lua");
+        assert!(matches!(action, ActionType::Lua(source) if source == "~/.config/chatshell/deploy.lua"));
+
+        let action = Hook::parse_action("companion:notes");
+        assert!(matches!(action, ActionType::Companion(name) if name == "notes"));
+    }
+
+    #[test]
+    fn test_placeholders() {
+        let command = "grep -rn {pattern} {file} | awk '{print $1}' | xargs -I{} echo {} ${HOME} {file}";
+        assert_eq!(placeholders(command), vec!["pattern".to_string(), "file".to_string()]);
+        let values = HashMap::from([("pattern", "it's"), ("file", "src/a b.rs")]);
+        assert_eq!(
+            fill_placeholders(command, |name| values.get(name).map(|value| value.to_string())),
+            "grep -rn 'it'\\''s' 'src/a b.rs' | awk '{print $1}' | xargs -I{} echo {} ${HOME} 'src/a b.rs'"
+        );
+        // Placeholders already in quotes are filled in for them
+        let values = HashMap::from([("x", "it's \"$HOME\"")]);
+        let fill = |command: &str| fill_placeholders(command, |name| values.get(name).map(|value| value.to_string()));
+        assert_eq!(fill("echo '{x}' \"{x}\" {x}"), "echo 'it'\\''s \"$HOME\"' \"it's \\\"\\$HOME\\\"\" 'it'\\''s \"$HOME\"'");
+        assert_eq!(fill("echo \"it's\" '{x}' \\'{x}"), "echo \"it's\" 'it'\\''s \"$HOME\"' \\''it'\\''s \"$HOME\"'");
+        assert!(placeholders("echo {} {1x} {a-b} {").is_empty());
+
+        let mut hook: HookConfig = toml::from_str("name = \"grep\"\naction = \"cmd:x\"\nenabled = true\nargs = { pattern = { default = \"TODO\" } }\n").unwrap();
+        assert_eq!(fill_arg_defaults(&hook, "grep -rn {pattern} ."), Ok("grep -rn 'TODO' .".to_string()));
+        hook.args.clear();
+        assert_eq!(fill_arg_defaults(&hook, "grep -rn {pattern} ."), Err("pattern".to_string()));
+    }
+
+    #[test]
+    fn test_session_notes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = HookContext::new(WindowManager::default());
+        let hook = transient_hook("ticket", "cmd:x");
+        let mut config = Config::default();
+        config.llm.include_notes = true;
+        assert_eq!(hook.ask_args("echo {session:notes}", &mut ctx).unwrap().as_deref(), Some("echo ''"));
+        assert_eq!(ctx.system_prompt(&config), llm::system_prompt(&config.llm, ctx.session_mode));
+
+        let path = dir.path().join("notes").join("default.md");
+        notepad::save(&path, "OPS-4711").unwrap();
+        ctx.notes_path = Some(path);
+        assert_eq!(hook.ask_args("echo {session:notes}", &mut ctx).unwrap().as_deref(), Some("echo 'OPS-4711'"));
+        // A hook's own `notes` arg is asked for as usual
+        assert_eq!(placeholders("echo {session:notes} {notes} {other:notes}"), ["session:notes", "notes"]);
+        assert!(ctx.system_prompt(&config).ends_with("\n\nThe user keeps these notes for the session:\nOPS-4711"));
+        config.llm.include_notes = false;
+        assert!(!ctx.system_prompt(&config).contains("OPS-4711"));
+    }
+
+    #[test]
+    fn test_expand_escapes() {
+        assert_eq!(expand_escapes(r"a\tb\r\n"), "a\tb\r\n");
+        assert_eq!(expand_escapes(r"\e[A"), "\x1b[A");
+        assert_eq!(expand_escapes(r"C:\\dir \d\"), "C:\\dir \\d\\");
+    }
+
+    #[test]
+    fn test_hook_changes_are_saved_where_the_hook_came_from() {
+        let dir = tempfile::tempdir().unwrap();
+        let config_path = dir.path().join("config.toml");
+        std::fs::write(
+            &config_path,
+            "presets = [\"database\"]\n\n[shell]\ncommand = \"/bin/sh\"\nargs = []\n\n# Mine\n[[hooks]]\nname = \"time\"\nkey_combination = \"ctrl+t\"\naction = \"fn:show_time\"\nenabled = true\n",
+        )
+        .unwrap();
+        let pack = dir.path().join("hooks.d").join("git");
+        std::fs::create_dir_all(&pack).unwrap();
+        std::fs::write(
+            pack.join(hookpack::PACK_FILE),
+            "[[hooks]]\nname = \"status\"\nkey_combination = \"alt+g\"\naction = \"cmd:git status\"\nenabled = true\n",
+        )
+        .unwrap();
+
+        let config = Config::load_from_file(&config_path).unwrap();
+        let packs_dir = hookpack::packs_dir(&config_path);
+        for mut hook in configured_hooks(&config, &packs_dir).unwrap().0 {
+            if ["time", "git/status", "ask"].contains(&hook.name.as_str()) {
+                hook.enabled = false;
+                save_hook_enabled(&config_path, &hook).unwrap();
+            }
+        }
+
+        let config = Config::load_from_file(&config_path).unwrap();
+        assert!(std::fs::read_to_string(&config_path).unwrap().contains("# Mine\n"));
+        let manager = HookManager::from_configs(configured_hooks(&config, &packs_dir).unwrap().0);
+        for (name, enabled) in [("time", false), ("git/status", false), ("ask", false), ("explain_query", true)] {
+            assert_eq!(manager.get_hook(name).unwrap().config.enabled, enabled, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_temporary_hooks_expire() {
+        let mut manager = HookManager::from_configs(vec![HookConfig { priority: 10, ..test_hook("configured", "f9", "inject:configured") }]);
+        let start = Instant::now();
+        let name = manager.bind_temporary("F9", r"inject:make deploy\n", Some(start + Duration::from_secs(3600)));
+        assert_eq!(name, "temp:f9");
+
+        let f9 = KeyInput::new(KeyCode::F(9), KeyModifiers::empty());
+        assert!(manager.process_key(&f9).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput("make deploy\n".to_string())]);
+
+        manager.expire_temporary(start + Duration::from_secs(3599));
+        assert!(manager.get_hook("temp:f9").is_some());
+        manager.expire_temporary(start + Duration::from_secs(3600));
+        assert!(manager.get_hook("temp:f9").is_none());
+        assert!(manager.process_key(&f9).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput("configured".to_string())]);
+    }
+
+    #[test]
+    fn test_interval_and_idle_hooks() {
+        let hook = |name: &str, action: &str, interval: Option<u64>, idle: Option<u64>| HookConfig {
+            name: name.to_string(),
+            action: action.into(),
+            enabled: true,
+            interval: interval.map(|secs| Interval(Duration::from_secs(secs))),
+            idle: idle.map(|secs| Interval(Duration::from_secs(secs))),
+            ..Default::default()
+        };
+        let mut manager = HookManager::from_configs(vec![
+            hook("status", "inject:status", Some(30), None),
+            hook("away", "inject:away", None, Some(300)),
+        ]);
+        let start = manager.started;
+        manager.key_pressed(start);
+        let at = |secs: u64| start + Duration::from_secs(secs);
+        assert_eq!(manager.next_timer(), Some(at(30)));
+        assert!(!manager.matches_any(&KeyInput::new(KeyCode::Null, KeyModifiers::empty())));
+
+        manager.run_timers(at(29));
+        assert!(manager.take_actions().is_empty());
+        manager.run_timers(at(30));
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput("status".to_string())]);
+        assert_eq!(manager.next_timer(), Some(at(60)));
+
+        // Idle hooks run once until the next key
+        manager.key_pressed(at(40));
+        manager.run_timers(at(340));
+        assert!(manager.take_actions().contains(&SessionAction::SendInput("away".to_string())));
+        manager.run_timers(at(700));
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput("status".to_string())]);
+        manager.key_pressed(at(701));
+        assert_eq!(manager.next_timer(), Some(at(730)));
+        manager.run_timers(at(1001));
+        assert!(manager.take_actions().contains(&SessionAction::SendInput("away".to_string())));
+    }
+
+    #[test]
+    fn test_timers_and_time_windows_follow_the_clock() {
+        use crate::clock::MockClock;
+        use chrono::TimeZone;
+
+        let config: HookConfig = toml::from_str(
+            "name = \"late\"\naction = \"inject:late\"\nenabled = true\ninterval = \"60s\"\nwhen = { time = \"22:00-06:00\" }\n",
+        )
+        .unwrap();
+        let clock = Arc::new(MockClock::at(chrono::Local.with_ymd_and_hms(2026, 3, 2, 21, 58, 30).unwrap()));
+        let mut manager = HookManager::from_configs(vec![config]);
+        manager.set_clock(clock.clone());
+        assert_eq!(manager.next_timer(), Some(clock.now() + Duration::from_secs(60)));
+
+        // Due at 21:59:30, outside its window, so it waits for its next turn
+        clock.advance(Duration::from_secs(60));
+        manager.run_timers(clock.now());
+        assert!(manager.take_actions().is_empty());
+        clock.advance(Duration::from_secs(60));
+        manager.run_timers(clock.now());
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput("late".to_string())]);
+    }
+
+    #[test]
+    fn test_show_time_reads_the_clock() {
+        use crate::clock::MockClock;
+        use chrono::TimeZone;
+
+        let config_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = Config::default();
+        config.time.format = "%Y-%m-%d %H:%M".to_string();
+        config.save_to_file(config_file.path()).unwrap();
+        let clock = Arc::new(MockClock::at(chrono::Utc.with_ymd_and_hms(2026, 3, 2, 21, 58, 30).unwrap().with_timezone(&chrono::Local)));
+        let mut manager = HookManager::new();
+        manager.set_config_path(&config_file.path().to_string_lossy());
+        manager.set_clock(clock.clone());
+        manager.add_hook(HookConfig { output: HookOutput::Toast, ..test_hook("time", "ctrl+t", "fn:show_time") });
+
+        clock.advance(Duration::from_secs(60));
+        assert!(manager.process_key(&KeyInput::parse("ctrl+t").unwrap()).unwrap());
+        let actions = manager.take_actions();
+        let SessionAction::PinNote(toast) = &actions[0] else {
+            panic!("expected a toast, got {:?}", actions[0]);
+        };
+        assert_eq!(toast.lines[1], ("UTC    2026-03-02 21:59".to_string(), None));
+        assert_eq!(toast.expires, Some(clock.now() + TOAST_LIFETIME));
+    }
+
+    #[test]
+    fn test_chat_is_kept_without_an_unfinished_answer() {
+        let mut manager = HookManager::from_configs(vec![]);
+        let earlier = ChatMessage { question: "Why is the pod pending?".to_string(), answer: "No node has room.".to_string() };
+        manager.restore_chat([earlier.clone()]).unwrap();
+        manager.context.window_manager.chat.ask("And now?");
+        assert_eq!(manager.chat_messages(), vec![earlier]);
+    }
+
+    #[test]
+    fn test_projects_replace_hooks_while_inside() {
+        let mut manager = HookManager::from_configs(vec![
+            test_hook("build", "ctrl+b", "cmd:npm run build"),
+            test_hook("notes", "f2", "inject:notes"),
+            test_hook("help", "f1", "fn:show_help"),
+        ]);
+        manager.bind_temporary("ctrl+b", "inject:temp", None);
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(crate::project::PROJECT_FILE);
+        std::fs::write(&path, r#"
+            [[hooks]]
+            name = "cargo"
+            key_combination = "Ctrl+B"
+            action = "cmd:cargo build"
+            enabled = true
+
+            [[hooks]]
+            name = "notes"
+            key_combination = "f3"
+            action = "inject:project notes"
+            enabled = true
+        "#).unwrap();
+        let sha256 = Project::load(&path).unwrap().sha256;
+        let project = Principal::Project { path: path.display().to_string(), sha256 };
+        manager.context.permissions.record(&project, Permission::Execute, true).unwrap();
+
+        manager.use_project(Some(path.clone())).unwrap();
+        assert_eq!(manager.project_path(), Some(path.as_path()));
+        assert!(manager.get_hook("build").is_none());
+        assert_eq!(manager.get_hook("cargo").unwrap().config.action, "cmd:cargo build");
+        // They run as the project, which has to ask for anything else
+        assert_eq!(manager.get_hook("cargo").unwrap().config.principal, Some(project.clone()));
+        assert_eq!(manager.get_hook("help").unwrap().config.principal, None);
+        assert_eq!(manager.get_hook("notes").unwrap().config.key_combination, "f3");
+        assert!(manager.get_hook("help").is_some());
+        assert!(manager.get_hook("temp:ctrl+b").is_some());
+
+        manager.use_project(None).unwrap();
+        assert_eq!(manager.project_path(), None);
+        assert!(manager.get_hook("cargo").is_none());
+        assert_eq!(manager.get_hook("build").unwrap().config.action, "cmd:npm run build");
+        assert_eq!(manager.get_hook("notes").unwrap().config.key_combination, "f2");
+
+        // A project that may not run commands isn't used
+        manager.context.permissions.record(&project, Permission::Execute, false).unwrap();
+        manager.use_project(Some(path)).unwrap();
+        assert_eq!(manager.project_path(), None);
+        assert!(manager.get_hook("build").is_some());
+    }
+
+    #[test]
+    fn test_action_registry() {
+        let mut names: Vec<&str> = actions().iter().map(|action| action.name).collect();
+        names.sort();
+        names.dedup();
+        assert_eq!(names.len(), actions().iter().count());
+        for action in actions().iter() {
+            assert!(
+                matches!(Hook::parse_action(action.name), ActionType::Builtin(_) | ActionType::Function(_)),
+                "{}",
+                action.name
+            );
+            assert_ne!(tr(action.description), action.description, "no text for {}", action.description);
+        }
+        assert!(actions().get("builtin:show_config").is_some_and(|action| !action.listed));
+
+        // Database actions wait for a database session
+        let mut ctx = HookContext::new(WindowManager::default());
+        let explain = actions().get("builtin:explain_query").unwrap();
+        assert_eq!(explain.missing(&ctx), Some(Capability::Database));
+        ctx.session_mode = SessionMode::Repl(ReplKind::Sqlite);
+        assert_eq!(actions().get("builtin:show_last_result").unwrap().missing(&ctx), None);
+
+        // The palette opens from the default hooks, after the key is handled
+        let mut manager = HookManager::from_configs(create_default_hooks());
+        let key = KeyInput::new(KeyCode::Char('p'), KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        assert!(manager.process_key(&key).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::ShowPalette]);
+    }
+
+    #[test]
+    fn test_key_bindings() {
+        let mut hooks = create_default_hooks();
+        hooks.extend(preset_hooks("database").unwrap());
+        let mut manager = HookManager::from_configs(hooks);
+        let help = ("ctrl+;".to_string(), tr("action-show-help"));
+        let explain = ("alt+e".to_string(), tr("action-explain-query"));
+
+        // The help in a session leaves out what the session can't run
+        let in_session = manager.key_bindings(true);
+        assert!(in_session.contains(&help));
+        assert!(!in_session.contains(&explain));
+        assert!(in_session.iter().all(|(keys, _)| !keys.is_empty()));
+        let all = manager.key_bindings(false);
+        assert!(all.contains(&help) && all.contains(&explain));
+
+        let key = KeyInput::new(KeyCode::Char(';'), KeyModifiers::CONTROL);
+        assert!(manager.process_key(&key).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::ShowHelp(HookOutput::Popup)]);
+    }
+
+    #[test]
+    fn test_reload_env_queues_exports() {
+        let mut env_file = tempfile::NamedTempFile::new().unwrap();
+        std::io::Write::write_all(&mut env_file, b"TOKEN=rotated\n").unwrap();
+        let config_file = tempfile::NamedTempFile::new().unwrap();
+        let mut config = Config::default();
+        config.shell.env_file = Some(env_file.path().to_string_lossy().to_string());
+        config.save_to_file(config_file.path()).unwrap();
+
+        let mut manager = HookManager::new();
+        manager.set_config_path(&config_file.path().to_string_lossy());
+        manager.add_hook(test_hook("reload", "ctrl+e", "builtin:reload_env"));
+
+        let key = KeyInput::new(KeyCode::Char('e'), KeyModifiers::CONTROL);
+        assert!(manager.process_key(&key).unwrap());
+        let actions = manager.take_actions();
+        assert_eq!(
+            actions[0],
+            SessionAction::SetEnv {
+                key: "TOKEN".to_string(),
+                value: "rotated".to_string(),
+            }
+        );
+        // A toast says it's done, without a popup to close
+        assert!(matches!(&actions[1], SessionAction::PinNote(toast) if toast.expires.is_some()));
+        assert_eq!(actions.len(), 2);
+        assert!(manager.take_actions().is_empty());
+    }
+
+    #[test]
+    fn test_plugins_and_packs_need_permission() {
+        let mut ctx = HookContext::new(WindowManager::default());
+        let key = KeyInput::new(KeyCode::F(9), KeyModifiers::empty());
+        let pack = Principal::Pack("git".to_string());
+        let deploy = Principal::Plugin("deploy".to_string());
+        ctx.permissions.record(&pack, Permission::Execute, false).unwrap();
+        ctx.permissions.record(&deploy, Permission::Execute, true).unwrap();
+        let hook_as = |principal: &Principal, name: &str, action: &str| {
+            Hook::new(HookConfig { principal: Some(principal.clone()), ..test_hook(name, "f9", action) })
+        };
+
+        let error = hook_as(&pack, "git/status", "cmd:git status").execute(&key, &mut ctx).unwrap_err();
+        assert!(format!("{:#}", error).contains("hook pack git may not run commands"), "{:#}", error);
+        let mark = CommandMark::Started { command: "make".to_string() };
+        assert!(hook_as(&pack, "git/on_start", "cmd:git fetch").execute_event(mark.event(), &mark.env(), &mut ctx).is_err());
+        assert!(hook_as(&pack, "git/script", "lua:chatshell.inject('rm -rf ~')").execute(&key, &mut ctx).is_err());
+        assert!(ctx.actions.is_empty());
+
+        // Names don't make a hook someone else's; only where it came from does
+        assert!(transient_hook("git/status", "cmd:git status").execute(&key, &mut ctx).unwrap());
+        assert!(hook_as(&deploy, "plugin:deploy:f9", r"inject:make\n").execute(&key, &mut ctx).unwrap());
+        assert_eq!(std::mem::take(&mut ctx.actions).len(), 2);
+        assert_eq!(ctx.principal, None);
+
+        // Registry actions say what they need
+        ctx.permissions.record(&pack, Permission::Scrollback, false).unwrap();
+        let error = hook_as(&pack, "git/back", "builtin:wayback").execute(&key, &mut ctx).unwrap_err();
+        assert!(format!("{:#}", error).contains("may not see what the shell prints"));
+    }
+
+    #[test]
+    fn test_at_prompt_hooks_wait_for_the_prompt() {
+        let config: HookConfig = toml::from_str(r#"
+            name = "undo"
+            key_combination = "ctrl+o"
+            action = "builtin:undo_input"
+            enabled = true
+            when = { at_prompt = true }
+        "#).unwrap();
+        assert!(config.when.at_prompt);
+        let mut manager = HookManager::from_configs(vec![config]);
+        let key = KeyInput::new(KeyCode::Char('o'), KeyModifiers::CONTROL);
+
+        manager.set_shell_busy(true);
+        assert!(!manager.process_key(&key).unwrap());
+        manager.set_shell_busy(false);
+        manager.set_screen("$ for f in *; do".to_string(), Vec::new());
+        assert!(manager.process_key(&key).unwrap());
+        manager.set_screen("> ".to_string(), Vec::new());
+        assert!(!manager.process_key(&key).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::UndoInput]);
+    }
+
+    #[test]
+    fn test_glob_matches() {
+        assert!(glob_matches("/home/me/src/**", "/home/me/src"));
+        assert!(glob_matches("/home/me/src/**", "/home/me/src/app/lib/"));
+        assert!(glob_matches("/home/me/src/*", "/home/me/src/app"));
+        assert!(!glob_matches("/home/me/src/*", "/home/me/src/app/lib"));
+        assert!(glob_matches("/srv/**/logs", "/srv/logs"));
+        assert!(glob_matches("/srv/**/logs", "/srv/web/api/logs"));
+        assert!(glob_matches("/tmp/build-?", "/tmp/build-1"));
+        assert!(!glob_matches("/tmp/build-?", "/tmp/build-10"));
+        assert!(!glob_matches("/home/me/src/**", "/home/me/srcs"));
+    }
+
+    #[test]
+    fn test_conditional_hooks() {
+        let dir = tempfile::tempdir().unwrap();
+        let repo = dir.path().join("repo");
+        std::fs::create_dir_all(repo.join(".git")).unwrap();
+        std::fs::create_dir_all(repo.join("src")).unwrap();
+        let hooks = vec![
+            toml::from_str::<HookConfig>(r#"
+                name = "git_log"
+                key_combination = "ctrl+r"
+                action = "builtin:undo_input"
+                enabled = true
+                priority = 1
+                when = { inside = ".git" }
+            "#).unwrap(),
+            toml::from_str::<HookConfig>(r#"
+                name = "search"
+                key_combination = "ctrl+r"
+                action = "builtin:close_note"
+                enabled = true
+            "#).unwrap(),
+        ];
+        let mut manager = HookManager::from_configs(hooks);
+        let key = KeyInput::new(KeyCode::Char('r'), KeyModifiers::CONTROL);
+
+        manager.set_foreground(None, Some(repo.join("src")));
+        assert!(manager.process_key(&key).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::UndoInput]);
+        manager.set_foreground(None, Some(dir.path().to_path_buf()));
+        assert!(manager.process_key(&key).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::CloseNote]);
+
+        // Conditions on what runs are read from /proc
+        let pid = std::process::id() as i32;
+        let mut context = HookContext::new(WindowManager::default());
+        context.foreground_pid = Some(pid);
+        context.cwd = Some(repo.clone());
+        let name = process::name(pid).unwrap();
+        let when = |toml: &str| toml::from_str::<HookCondition>(toml).unwrap();
+        assert!(context.allows(&when(&format!("program = [\"{}\"]", name))));
+        assert!(!context.allows(&when("program = [\"no-such-program\"]")));
+        assert!(context.allows(&when("env = [\"PATH\"]")));
+        assert!(!context.allows(&when("env = [\"PATH\", \"CHATSHELL_NO_SUCH_VARIABLE\"]")));
+        assert!(context.allows(&when(&format!("cwd = \"{}/**\"", repo.display()))));
+    }
+
+    #[test]
+    fn test_full_screen_programs() {
+        let hook = |name: &str, action: &str, when: &str| {
+            toml::from_str::<HookConfig>(&format!(
+                "name = \"{}\"\nkey_combination = \"f5\"\naction = \"{}\"\nenabled = true\nwhen = {{ {} }}\n",
+                name, action, when
+            ))
+            .unwrap()
+        };
+        let key = KeyInput::new(KeyCode::F(5), KeyModifiers::NONE);
+        let mut manager = HookManager::from_configs(vec![hook("snippet", "inject:git status\\n", "")]);
+        manager.set_snapshots(Vec::new(), true);
+        assert!(!manager.process_key(&key).unwrap());
+        let actions = manager.take_actions();
+        assert!(matches!(&actions[..], [SessionAction::PinNote(note)] if note.lines[0].0.contains("snippet")));
+
+        // Set either way, the condition decides instead
+        let mut manager = HookManager::from_configs(vec![
+            hook("outside", "builtin:paste_clipboard", "alt_screen = false"),
+            hook("vim_snippet", "inject::wq\\r", "alt_screen = true"),
+        ]);
+        manager.set_snapshots(Vec::new(), true);
+        assert!(manager.process_key(&key).unwrap());
+        assert_eq!(manager.take_actions(), vec![SessionAction::SendInput(":wq\r".to_string())]);
+        assert!(!Hook::new(hook("popup", "popup:hi", "")).types_text());
+        assert!(Hook::new(hook("time", "builtin:convert_time", "")).types_text());
+
+        // Text a script types is held back too, though it can't be told beforehand
+        #[cfg(feature = "lua")]
+        {
+            let mut manager = HookManager::from_configs(vec![hook("script", "lua:chatshell.inject('q')", "")]);
+            manager.set_snapshots(Vec::new(), true);
+            manager.process_key(&key).unwrap();
+            let actions = manager.take_actions();
+            assert!(matches!(&actions[..], [SessionAction::PinNote(note)] if note.lines[0].0.contains("script")));
+        }
+    }
+
+    #[test]
+    fn test_preset_hooks_are_overridable() {
+        let mut config = Config {
+            presets: vec!["database".to_string()],
+            hooks: vec![test_hook("ask", "ctrl+g", "builtin:ask")],
+            ..Default::default()
+        };
+
+        let packs = tempfile::tempdir().unwrap();
+        let manager = HookManager::from_configs(configured_hooks(&config, packs.path()).unwrap().0);
+        assert_eq!(manager.get_hook("ask").unwrap().config.key_combination, "ctrl+g");
+        assert!(manager.get_hook("explain_query").is_some());
+
+        config.presets = vec!["missing".to_string()];
+        assert!(configured_hooks(&config, packs.path()).is_err());
     }
-}
\ No newline at end of file
+}
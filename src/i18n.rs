@@ -0,0 +1,177 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::fs;
+use std::path::Path;
+use std::sync::{OnceLock, RwLock};
+use unic_langid::LanguageIdentifier;
+
+pub use fluent_bundle::FluentValue;
+
+use crate::config::I18nConfig;
+
+/// Catalogs compiled in, by locale. English has every message.
+const BUILT_IN: &[(&str, &str)] = &[
+    ("en", include_str!("../locales/en.ftl")),
+    ("de", include_str!("../locales/de.ftl")),
+];
+
+static CATALOG: RwLock<Option<Catalog>> = RwLock::new(None);
+static ENGLISH: OnceLock<Catalog> = OnceLock::new();
+
+/// The locale of the environment, from `LC_ALL`, `LC_MESSAGES` or `LANG`
+/// such as `de_DE.UTF-8`, as a language tag (`de-DE`).
+pub fn locale_from_env() -> Option<String> {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|name| std::env::var(name).ok())
+        .find(|value| !value.is_empty())
+        .and_then(|value| {
+            let tag = value.split(['.', '@']).next().unwrap_or_default().replace('_', "-");
+            (!tag.is_empty() && tag != "C" && tag != "POSIX").then_some(tag)
+        })
+}
+
+/// The locale tags to look for a catalog under, most specific first:
+/// `pt-BR` then `pt`.
+fn candidates(locale: &str) -> Vec<String> {
+    let mut candidates = vec![locale.to_string()];
+    if let Some((language, _)) = locale.split_once('-') {
+        candidates.push(language.to_string());
+    }
+    candidates
+}
+
+fn bundle(locale: &str, source: String) -> Option<FluentBundle<FluentResource>> {
+    let langid: LanguageIdentifier = locale.parse().unwrap_or_default();
+    // A catalog with mistakes still gives the messages that parsed
+    let resource = FluentResource::try_new(source).unwrap_or_else(|(resource, _)| resource);
+    let mut bundle = FluentBundle::new_concurrent(vec![langid]);
+    // Bidi isolation marks would show up as stray characters in a terminal
+    bundle.set_use_isolating(false);
+    bundle.add_resource(resource).ok()?;
+    Some(bundle)
+}
+
+/// The overrides of the config as Fluent source, so they can use the same
+/// `{ $variables }` as the messages they replace.
+fn overrides_source(config: &I18nConfig) -> String {
+    config
+        .messages
+        .iter()
+        .map(|(id, text)| format!("{} =\n    {}\n", id, text.replace('\n', "\n    ")))
+        .collect()
+}
+
+/// The messages of one locale, falling back to English for those it lacks.
+pub struct Catalog {
+    // Looked up in order
+    bundles: Vec<FluentBundle<FluentResource>>,
+}
+
+impl Catalog {
+    /// The built-in English messages.
+    pub fn english() -> Self {
+        Catalog {
+            bundles: bundle("en", BUILT_IN[0].1.to_string()).into_iter().collect(),
+        }
+    }
+
+    /// The catalog for `config`: its overrides, then a catalog of the locale
+    /// from `locales_dir`, then a built-in one, then English.
+    pub fn load(config: &I18nConfig, locales_dir: &Path) -> Self {
+        let locale = config.locale.clone().or_else(locale_from_env).unwrap_or_else(|| "en".to_string());
+        let mut bundles: Vec<_> = bundle(&locale, overrides_source(config)).into_iter().collect();
+        for candidate in candidates(&locale) {
+            let path = locales_dir.join(format!("{}.ftl", candidate));
+            if let Ok(source) = fs::read_to_string(&path) {
+                bundles.extend(bundle(&candidate, source));
+            }
+            if let Some((_, source)) = BUILT_IN.iter().find(|(tag, _)| *tag == candidate) {
+                bundles.extend(bundle(&candidate, source.to_string()));
+            }
+        }
+        bundles.extend(Catalog::english().bundles);
+        Catalog { bundles }
+    }
+
+    /// The text of message `id`, or the id itself if no catalog has it.
+    pub fn format(&self, id: &str, args: Option<&FluentArgs>) -> String {
+        for bundle in &self.bundles {
+            if let Some(pattern) = bundle.get_message(id).and_then(|message| message.value()) {
+                let mut errors = Vec::new();
+                return bundle.format_pattern(pattern, args, &mut errors).into_owned();
+            }
+        }
+        id.to_string()
+    }
+}
+
+impl std::fmt::Debug for Catalog {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Catalog").field("bundles", &self.bundles.len()).finish()
+    }
+}
+
+/// Makes `config` the source of built-in text for the rest of the process.
+/// Until then, text is in English.
+pub fn init(config: &I18nConfig, locales_dir: &Path) {
+    let catalog = Catalog::load(config, locales_dir);
+    if let Ok(mut current) = CATALOG.write() {
+        *current = Some(catalog);
+    }
+}
+
+fn format(id: &str, args: Option<&FluentArgs>) -> String {
+    if let Ok(current) = CATALOG.read() {
+        if let Some(catalog) = current.as_ref() {
+            return catalog.format(id, args);
+        }
+    }
+    ENGLISH.get_or_init(Catalog::english).format(id, args)
+}
+
+/// The text of message `id` in the user's language.
+pub fn tr(id: &str) -> String {
+    format(id, None)
+}
+
+/// Like `tr`, for a message with `{ $variables }`.
+pub fn tr_args(id: &str, args: &[(&str, FluentValue)]) -> String {
+    let mut fluent_args = FluentArgs::new();
+    for (name, value) in args {
+        fluent_args.set(*name, value.clone());
+    }
+    format(id, Some(&fluent_args))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_catalog_lookup() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("de.ftl"), "ask-title = Frage\nbroken = {\n").unwrap();
+        let mut config = I18nConfig {
+            locale: Some("de-AT".to_string()),
+            ..Default::default()
+        };
+        config.messages.insert("ask-prompt".to_string(), "Was gibt's, { $name }?".to_string());
+        let catalog = Catalog::load(&config, dir.path());
+
+        let mut args = FluentArgs::new();
+        args.set("name", "Sam");
+        // Override, user catalog, built-in catalog, English, missing
+        assert_eq!(catalog.format("ask-prompt", Some(&args)), "Was gibt's, Sam?");
+        assert_eq!(catalog.format("ask-title", None), "Frage");
+        assert_eq!(catalog.format("suggestion-run", None), "Ausführen");
+        assert_eq!(catalog.format("missing-id", None), "missing-id");
+
+        let english = Catalog::english();
+        let mut count = FluentArgs::new();
+        count.set("count", 3);
+        assert_eq!(english.format("rerun-skipped", Some(&count)), "@@ 3 unchanged lines @@");
+        assert!(english.format("help-text", None).contains("\n\nThis is a transparent shell wrapper.\n"));
+        assert_eq!(candidates("pt-BR"), vec!["pt-BR".to_string(), "pt".to_string()]);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::output::SequenceKind;
+
+/// Inline image protocols. Their sequences reach the outer terminal intact;
+/// this only reads enough of them to guess the rows an image covers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageProtocol {
+    Sixel,
+    Kitty,
+    Iterm2,
+}
+
+/// An image the child drew.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InlineImage {
+    pub protocol: ImageProtocol,
+    /// Height in rows, when the sequence gives one.
+    pub rows: Option<u16>,
+    /// Height as a percentage of the screen (iTerm2 only).
+    pub percent: Option<u16>,
+    /// Height in pixels, from the sequence or the PNG it carries.
+    pub pixels: Option<u32>,
+}
+
+impl InlineImage {
+    /// Recognizes a sequence that displays an image. Kitty transmissions
+    /// that only store an image, and continuation chunks, don't count.
+    pub fn from_sequence(kind: SequenceKind, raw: &[u8]) -> Option<Self> {
+        let body = raw.get(2..)?;
+        let body = body.strip_suffix(b"\x1b\\").or_else(|| body.strip_suffix(b"\x07")).unwrap_or(body);
+        match kind {
+            SequenceKind::Dcs => parse_sixel(body),
+            SequenceKind::Apc => parse_kitty(body.strip_prefix(b"G")?),
+            SequenceKind::Osc => parse_iterm2(body.strip_prefix(b"1337;File=")?),
+            _ => None,
+        }
+    }
+
+    /// Rows covered on a screen of `screen_rows` rows whose cells are
+    /// `cell_height` pixels tall, if that can be worked out.
+    pub fn height_in_rows(&self, cell_height: Option<u16>, screen_rows: u16) -> Option<u16> {
+        if let Some(rows) = self.rows {
+            return Some(rows);
+        }
+        if let Some(percent) = self.percent {
+            return Some(((screen_rows as u32 * percent as u32).div_ceil(100)) as u16);
+        }
+        let cell_height = cell_height.filter(|height| *height > 0)? as u32;
+        self.pixels.map(|pixels| pixels.div_ceil(cell_height).min(u16::MAX as u32) as u16)
+    }
+}
+
+fn parse_number(text: &[u8]) -> Option<u32> {
+    std::str::from_utf8(text).ok()?.trim().parse().ok()
+}
+
+/// `DCS P1;P2;P3 q "Pan;Pad;Ph;Pv <data>`; the raster attributes are optional.
+fn parse_sixel(body: &[u8]) -> Option<InlineImage> {
+    let start = body.iter().position(|b| !(b.is_ascii_digit() || *b == b';'))?;
+    let data = body[start..].strip_prefix(b"q")?;
+    let pixels = data.strip_prefix(b"\"").and_then(|raster| {
+        let end = raster.iter().position(|b| !(b.is_ascii_digit() || *b == b';')).unwrap_or(raster.len());
+        let values: Vec<&[u8]> = raster[..end].split(|b| *b == b';').collect();
+        parse_number(values.get(3)?)
+    });
+    Some(InlineImage { protocol: ImageProtocol::Sixel, rows: None, percent: None, pixels })
+}
+
+/// `APC G key=value,...;payload`
+fn parse_kitty(body: &[u8]) -> Option<InlineImage> {
+    let (control, payload) = match body.iter().position(|b| *b == b';') {
+        Some(i) => (&body[..i], &body[i + 1..]),
+        None => (body, &[][..]),
+    };
+    let mut action = b't';
+    let mut image = InlineImage { protocol: ImageProtocol::Kitty, rows: None, percent: None, pixels: None };
+    let mut format = 32;
+    for pair in control.split(|b| *b == b',') {
+        let Some((key, value)) = pair.split_first().filter(|(_, rest)| rest.first() == Some(&b'=')) else {
+            continue;
+        };
+        let value = &value[1..];
+        match key {
+            b'a' => action = *value.first()?,
+            b'r' => image.rows = parse_number(value).map(|rows| rows as u16),
+            b'v' => image.pixels = parse_number(value),
+            b'f' => format = parse_number(value)?,
+            _ => {}
+        }
+    }
+    if !matches!(action, b'T' | b'p') {
+        return None;
+    }
+    if format == 100 && image.pixels.is_none() {
+        image.pixels = png_height(payload);
+    }
+    Some(image)
+}
+
+/// `OSC 1337 ; File=key=value;...:payload`
+fn parse_iterm2(body: &[u8]) -> Option<InlineImage> {
+    let colon = body.iter().position(|b| *b == b':')?;
+    let (arguments, payload) = (&body[..colon], &body[colon + 1..]);
+    let mut inline = false;
+    let mut image = InlineImage { protocol: ImageProtocol::Iterm2, rows: None, percent: None, pixels: None };
+    for pair in arguments.split(|b| *b == b';') {
+        let Some(eq) = pair.iter().position(|b| *b == b'=') else {
+            continue;
+        };
+        let (key, value) = (&pair[..eq], &pair[eq + 1..]);
+        match key {
+            b"inline" => inline = value == b"1",
+            b"height" => {
+                if let Some(px) = value.strip_suffix(b"px") {
+                    image.pixels = parse_number(px);
+                } else if let Some(percent) = value.strip_suffix(b"%") {
+                    image.percent = parse_number(percent).map(|p| p as u16);
+                } else {
+                    image.rows = parse_number(value).map(|rows| rows as u16);
+                }
+            }
+            _ => {}
+        }
+    }
+    if !inline {
+        return None;
+    }
+    if image.rows.is_none() && image.percent.is_none() && image.pixels.is_none() {
+        image.pixels = png_height(payload);
+    }
+    Some(image)
+}
+
+/// The height in a base64 encoded PNG's header.
+fn png_height(base64: &[u8]) -> Option<u32> {
+    // Signature (8), IHDR length and type (8), width (4), height (4)
+    let header = decode_base64(base64.get(..32)?)?;
+    if !header.starts_with(b"\x89PNG\r\n\x1a\n") || &header[12..16] != b"IHDR" {
+        return None;
+    }
+    Some(u32::from_be_bytes(header[20..24].try_into().ok()?))
+}
+
+fn decode_base64(text: &[u8]) -> Option<Vec<u8>> {
+    let value = |c: u8| -> Option<u32> {
+        Some(match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        } as u32)
+    };
+    let mut bytes = Vec::new();
+    for quad in text.chunks_exact(4) {
+        let n = quad.iter().try_fold(0u32, |n, c| Some(n << 6 | value(*c)?))?;
+        bytes.extend_from_slice(&n.to_be_bytes()[1..]);
+    }
+    Some(bytes)
+}
+
+/// The height of the terminal's cells in pixels, if it says.
+pub fn cell_height() -> Option<u16> {
+    let window = crossterm::terminal::window_size().ok()?;
+    (window.rows > 0).then(|| window.height / window.rows)
+}
+
+/// Rows of the screen taken by images, following them as output scrolls
+/// the screen. Without a screen model this is a guess: line feeds are
+/// assumed to go down one row at a time, and anything that clears the
+/// screen forgets all images.
+#[derive(Debug, Default)]
+pub struct ImageTracker {
+    // (top, bottom) rows, inclusive
+    regions: Vec<(u16, u16)>,
+    cursor_row: u16,
+    screen_rows: u16,
+}
+
+impl ImageTracker {
+    pub fn set_screen_rows(&mut self, rows: u16) {
+        self.screen_rows = rows;
+    }
+
+    /// Records an image covering `top..=bottom`, with the cursor left on
+    /// `bottom`.
+    pub fn add(&mut self, top: u16, bottom: u16) {
+        self.regions.push((top.min(bottom), bottom));
+        self.cursor_row = bottom;
+    }
+
+    /// Moves the images along with output written after them.
+    pub fn observe(&mut self, display: &[u8]) {
+        if self.regions.is_empty() {
+            return;
+        }
+        const CLEARS: [&[u8]; 5] = [b"\x1b[2J", b"\x1b[3J", b"\x1bc", b"\x1b[?1049h", b"\x1b[?47h"];
+        if CLEARS.iter().any(|clear| display.windows(clear.len()).any(|w| w == *clear)) {
+            self.regions.clear();
+            return;
+        }
+
+        for _ in display.iter().filter(|b| **b == b'\n') {
+            if self.cursor_row + 1 < self.screen_rows {
+                self.cursor_row += 1;
+                continue;
+            }
+            // At the bottom: the screen scrolls up a row
+            self.regions.retain(|(_, bottom)| *bottom > 0);
+            for (top, bottom) in &mut self.regions {
+                *top = top.saturating_sub(1);
+                *bottom -= 1;
+            }
+        }
+    }
+
+    pub fn regions(&self) -> Vec<(u16, u16)> {
+        self.regions.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_recognizes_images() {
+        let sixel = InlineImage::from_sequence(SequenceKind::Dcs, b"\x1bP0;1q\"1;1;40;60#0~~-\x1b\\").unwrap();
+        assert_eq!(sixel.protocol, ImageProtocol::Sixel);
+        assert_eq!(sixel.height_in_rows(Some(20), 24), Some(3));
+
+        let kitty = InlineImage::from_sequence(SequenceKind::Apc, b"\x1b_Ga=T,f=100,r=5;AAAA\x1b\\").unwrap();
+        assert_eq!(kitty.height_in_rows(None, 24), Some(5));
+        assert_eq!(InlineImage::from_sequence(SequenceKind::Apc, b"\x1b_Gm=0;AAAA\x1b\\"), None);
+
+        // A 1x30 PNG header
+        let png = b"iVBORw0KGgoAAAANSUhEUgAAAAEAAAAeCAYAAAA";
+        let mut osc = b"\x1b]1337;File=inline=1:".to_vec();
+        osc.extend_from_slice(png);
+        osc.push(0x07);
+        let iterm = InlineImage::from_sequence(SequenceKind::Osc, &osc).unwrap();
+        assert_eq!(iterm.pixels, Some(30));
+        assert_eq!(InlineImage::from_sequence(SequenceKind::Osc, b"\x1b]1337;File=inline=0:AAAA\x07"), None);
+    }
+
+    #[test]
+    fn test_tracker_scrolls_images() {
+        let mut tracker = ImageTracker::default();
+        tracker.set_screen_rows(10);
+        tracker.add(5, 8);
+        tracker.observe(b"a\nb\nc\n");
+        assert_eq!(tracker.regions(), vec![(3, 6)]);
+        tracker.observe(b"\x1b[2J");
+        assert!(tracker.regions().is_empty());
+    }
+}
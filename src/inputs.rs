@@ -0,0 +1,128 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::drafts::slug;
+
+/// The file next to the config that keeps what was entered in input
+/// popups.
+pub const INPUT_HISTORY_FILE: &str = "input_history.toml";
+
+/// How many entries each kind of input keeps.
+pub const HISTORY_LIMIT: usize = 50;
+
+const HEADER: &str = "# What was entered in chatshell's input popups, oldest first. Delete an\n\
+                      # entry or the file to forget it.\n\n";
+
+pub fn input_history_path(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join(INPUT_HISTORY_FILE)
+}
+
+/// What was entered in each kind of input popup, like the questions for
+/// the assistant or the values of a hook's placeholders, kept across
+/// sessions.
+#[derive(Debug, Default)]
+pub struct InputHistory {
+    path: Option<PathBuf>,
+    entries: BTreeMap<String, Vec<String>>,
+}
+
+impl InputHistory {
+    /// Reads the history at `path`, which is empty until something is
+    /// entered.
+    pub fn load(path: &Path) -> Result<Self> {
+        let entries = match fs::read_to_string(path) {
+            Ok(text) => toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(InputHistory { path: Some(path.to_path_buf()), entries })
+    }
+
+    /// What was entered in the popup with `title` and `label`, oldest
+    /// first.
+    pub fn entries(&self, title: &str, label: &str) -> &[String] {
+        self.entries.get(&kind(title, label)).map_or(&[], Vec::as_slice)
+    }
+
+    /// Adds `text` as the newest entry of its popup, moving it there if it
+    /// was entered before, and saves the history.
+    pub fn add(&mut self, title: &str, label: &str, text: &str) -> Result<()> {
+        let entries = self.entries.entry(kind(title, label)).or_default();
+        entries.retain(|entry| entry != text);
+        entries.push(text.to_string());
+        if entries.len() > HISTORY_LIMIT {
+            entries.remove(0);
+        }
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let text = format!("{}{}", HEADER, toml::to_string(&self.entries)?);
+        // Questions and arguments may hold anything, so only the user can
+        // read them
+        OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+fn kind(title: &str, label: &str) -> String {
+    format!("{}--{}", slug(title), slug(label))
+}
+
+/// The newest of `entries` before `before` that contains `query`, ignoring
+/// case, as ctrl+r finds in a shell. Searching again from a match finds
+/// the one before it.
+pub fn search(entries: &[String], query: &str, before: usize) -> Option<usize> {
+    let query = query.to_lowercase();
+    (0..before.min(entries.len())).rev().find(|&i| entries[i].to_lowercase().contains(&query))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_input_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = input_history_path(&dir.path().join("config.toml"));
+        let mut history = InputHistory::load(&path).unwrap();
+        assert!(history.entries("Ask", "What do you need?").is_empty());
+        history.add("Ask", "What do you need?", "why is the build red").unwrap();
+        history.add("Ask", "What do you need?", "explain this error").unwrap();
+        history.add("Ask", "What do you need?", "why is the build red").unwrap();
+        history.add("deploy", "branch", "main").unwrap();
+
+        // Kept across sessions, the same text once, newest last
+        let history = InputHistory::load(&path).unwrap();
+        assert_eq!(history.entries("Ask", "What do you need?"), ["explain this error", "why is the build red"]);
+        assert_eq!(history.entries("deploy", "branch"), ["main"]);
+        assert!(fs::read_to_string(&path).unwrap().starts_with("# What was entered"));
+
+        let entries = history.entries("Ask", "What do you need?");
+        assert_eq!(search(entries, "", entries.len()), Some(1));
+        assert_eq!(search(entries, "ERROR", entries.len()), Some(0));
+        assert_eq!(search(entries, "e", 1), Some(0));
+        assert_eq!(search(entries, "e", 0), None);
+        assert_eq!(search(entries, "nothing", entries.len()), None);
+    }
+
+    #[test]
+    fn test_input_history_limit() {
+        let mut history = InputHistory::default();
+        for i in 0..HISTORY_LIMIT + 5 {
+            history.add("Watch", "Command", &format!("make {}", i)).unwrap();
+        }
+        let entries = history.entries("Watch", "Command");
+        assert_eq!(entries.len(), HISTORY_LIMIT);
+        assert_eq!(entries[0], "make 5");
+    }
+}
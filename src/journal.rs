@@ -0,0 +1,222 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::{expand_home, Config};
+use crate::history;
+use crate::llm;
+
+/// Output lines kept from the start and from the end of each command.
+const KEEP_OUTPUT_LINES: usize = 5;
+
+/// Commands kept for the summary; older ones are dropped.
+const MAX_COMMANDS: usize = 500;
+
+/// Longest line kept, in characters.
+const MAX_LINE_CHARS: usize = 200;
+
+const SYSTEM_PROMPT: &str = "You write entries for a developer's work log. You are given a condensed \
+transcript of a terminal session: each command line as typed at the prompt, followed by the start and \
+end of its output. Summarize what was worked on and what came of it in a few short Markdown bullet \
+points. Mention repositories, services and notable failures by name. Reply with the bullet points only.";
+
+#[derive(Debug, Default)]
+struct TranscriptCommand {
+    // None until the shell echoes the command line
+    line: Option<String>,
+    head: Vec<String>,
+    tail: Vec<String>,
+    omitted: usize,
+}
+
+/// The commands of a session with the start and end of their output.
+#[derive(Debug)]
+pub struct Transcript {
+    started: DateTime<Local>,
+    commands: Vec<TranscriptCommand>,
+}
+
+fn clip(line: &str) -> String {
+    line.chars().take(MAX_LINE_CHARS).collect()
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Transcript {
+            started: Local::now(),
+            commands: Vec::new(),
+        }
+    }
+
+    /// Starts a new command when Enter is pressed. The next line the shell
+    /// finishes, the echo of the command line with its prompt, names it.
+    /// Input can run ahead of the echo, so the screen's current line isn't
+    /// reliable at this point.
+    pub fn command_entered(&mut self) {
+        if self.commands.last().is_some_and(|command| command.line.is_none()) {
+            return;
+        }
+        self.commands.push(TranscriptCommand::default());
+        if self.commands.len() > MAX_COMMANDS {
+            self.commands.remove(0);
+        }
+    }
+
+    /// Adds output lines to the last command entered.
+    pub fn output(&mut self, lines: &[String]) {
+        let Some(command) = self.commands.last_mut() else {
+            return;
+        };
+        for line in lines {
+            if command.line.is_none() {
+                command.line = Some(clip(line.trim_end()));
+                continue;
+            }
+            if command.head.len() < KEEP_OUTPUT_LINES {
+                command.head.push(clip(line));
+                continue;
+            }
+            command.tail.push(clip(line));
+            if command.tail.len() > KEEP_OUTPUT_LINES {
+                command.tail.remove(0);
+                command.omitted += 1;
+            }
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.iter().all(|command| command.line.is_none())
+    }
+
+    /// The transcript as text for the LLM.
+    pub fn condensed(&self) -> String {
+        let mut text = String::new();
+        for command in &self.commands {
+            let Some(line) = &command.line else {
+                continue;
+            };
+            text.push_str(line);
+            text.push('\n');
+            for line in &command.head {
+                text.push_str(&format!("    {}\n", line));
+            }
+            if command.omitted > 0 {
+                text.push_str(&format!("    [... {} lines ...]\n", command.omitted));
+            }
+            for line in &command.tail {
+                text.push_str(&format!("    {}\n", line));
+            }
+        }
+        text
+    }
+}
+
+impl Default for Transcript {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Summarizes the session with the LLM and appends the summary to the
+/// journal file under a dated heading. An LLM that takes longer than the
+/// journal's `timeout_secs` is stopped, so that exiting doesn't hang on it.
+pub fn write_summary(config: &Config, transcript: &Transcript) -> Result<()> {
+    let condensed = transcript.condensed();
+    let timeout = Duration::from_secs(config.journal.timeout_secs);
+    let summary = llm::complete_within(&config.llm, SYSTEM_PROMPT, &condensed, timeout)
+        .with_context(|| "Failed to summarize the session")?;
+    history::record_llm_usage(&config.history, "journal", &[SYSTEM_PROMPT, &condensed], &summary);
+    append_entry(&expand_home(&config.journal.path), transcript.started, Local::now(), &summary)
+}
+
+fn append_entry(path: &str, started: DateTime<Local>, ended: DateTime<Local>, summary: &str) -> Result<()> {
+    if let Some(parent) = Path::new(path).parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create journal directory: {}", parent.display()))?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open journal: {}", path))?;
+
+    let minutes = (ended - started).num_minutes();
+    writeln!(
+        file,
+        "## {} ({} min)\n\n{}\n",
+        started.format("%Y-%m-%d %H:%M"),
+        minutes,
+        summary.trim()
+    )
+    .with_context(|| format!("Failed to write journal: {}", path))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{HistoryConfig, JournalConfig, LlmConfig};
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn test_transcript_keeps_head_and_tail() {
+        let mut transcript = Transcript::new();
+        transcript.output(&lines("before any command"));
+        transcript.command_entered();
+        let numbers: Vec<String> = (1..=20).map(|n| n.to_string()).collect();
+        transcript.output(&[vec!["~/app$ seq 20".to_string()], numbers].concat());
+        transcript.command_entered();
+
+        assert_eq!(
+            transcript.condensed(),
+            "~/app$ seq 20\n    1\n    2\n    3\n    4\n    5\n    [... 10 lines ...]\n    16\n    17\n    18\n    19\n    20\n"
+        );
+    }
+
+    #[test]
+    fn test_write_summary_appends_entry() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = Config {
+            journal: JournalConfig {
+                path: dir.path().join("log/journal.md").to_string_lossy().to_string(),
+                ..Default::default()
+            },
+            llm: LlmConfig {
+                command: Some("grep -c . >/dev/null; echo '- fixed the build'".to_string()),
+                system_prompt: None,
+                include_notes: false,
+                ..Default::default()
+            },
+            history: HistoryConfig {
+                enabled: false,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let mut transcript = Transcript::new();
+        transcript.command_entered();
+        transcript.output(&lines("$ make"));
+
+        write_summary(&config, &transcript).unwrap();
+        write_summary(&config, &transcript).unwrap();
+        let content = fs::read_to_string(&config.journal.path).unwrap();
+        assert_eq!(content.matches("- fixed the build").count(), 2);
+        assert!(content.starts_with("## "));
+
+        // A summary that doesn't come in time is given up on
+        let slow = Config {
+            journal: JournalConfig { timeout_secs: 0, ..config.journal.clone() },
+            llm: LlmConfig { command: Some("sleep 5".to_string()), ..config.llm.clone() },
+            ..config.clone()
+        };
+        let error = write_summary(&slow, &transcript).unwrap_err();
+        assert!(format!("{:#}", error).contains("timed out after 0s"));
+        assert_eq!(fs::read_to_string(&config.journal.path).unwrap(), content);
+    }
+}
@@ -1,5 +1,56 @@
+pub mod actions;
+pub mod automation;
+pub mod backend;
+pub mod bugreport;
+pub mod cheatsheet;
+pub mod clock;
+pub mod companion;
 pub mod config;
+pub mod control;
+pub mod database;
+pub mod diff;
+pub mod drafts;
+pub mod errors;
+pub mod expect;
+pub mod hatch;
+pub mod headless;
+pub mod highlight;
+pub mod history;
+pub mod hookpack;
 pub mod hooks;
+pub mod i18n;
+pub mod images;
+pub mod inputs;
+pub mod journal;
+pub mod llm;
+pub mod lua;
+pub mod markdown;
+pub mod notepad;
+pub mod output;
+pub mod pane;
+pub mod paste;
+pub mod plugin;
+pub mod permissions;
+pub mod ports;
+pub mod prespawn;
+pub mod process;
+pub mod project;
 pub mod pty;
+pub mod render;
+pub mod repl;
+pub mod report;
+pub mod screen;
+pub mod scrollback;
+pub mod serial;
+pub mod shell;
+pub mod ssh;
+pub mod state;
+pub mod storage;
+pub mod structured;
+pub mod tail;
 pub mod terminal;
-pub mod window;
\ No newline at end of file
+pub mod text;
+pub mod theme;
+pub mod transcript;
+pub mod watch;
+pub mod window;
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use std::io::{Read, Write};
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::config::LlmConfig;
+use crate::process::Watchdog;
+use crate::repl::SessionMode;
+use crate::text;
+
+/// Environment variable carrying the system prompt to the LLM command.
+pub const SYSTEM_PROMPT_ENV_VAR: &str = "CHATSHELL_SYSTEM_PROMPT";
+
+/// The system prompt for a session: the built-in one for its mode followed
+/// by the user's extra instructions.
+pub fn system_prompt(config: &LlmConfig, mode: SessionMode) -> String {
+    let mut prompt = mode.system_prompt();
+    if let Some(extra) = &config.system_prompt {
+        prompt.push_str("\n\n");
+        prompt.push_str(extra);
+    }
+    prompt
+}
+
+/// Runs the configured LLM command with `prompt` on stdin and returns what
+/// it printed.
+pub fn complete(config: &LlmConfig, system_prompt: &str, prompt: &str) -> Result<String> {
+    run(config, system_prompt, prompt, None, |_| {})
+}
+
+/// Like `complete`, but kills the command if it hasn't answered within
+/// `timeout`, for callers that wait on it.
+pub fn complete_within(config: &LlmConfig, system_prompt: &str, prompt: &str, timeout: Duration) -> Result<String> {
+    run(config, system_prompt, prompt, Some(timeout), |_| {})
+}
+
+/// Like `complete`, handing what the command prints to `on_output` as it
+/// comes, for a reply to be shown while it is written.
+pub fn stream(config: &LlmConfig, system_prompt: &str, prompt: &str, on_output: impl FnMut(&str)) -> Result<String> {
+    run(config, system_prompt, prompt, None, on_output)
+}
+
+fn run(config: &LlmConfig, system_prompt: &str, prompt: &str, timeout: Option<Duration>, mut on_output: impl FnMut(&str)) -> Result<String> {
+    let command = config.command.as_deref()
+        .with_context(|| "No LLM configured; set [llm] command in the config")?;
+
+    let mut child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .env(SYSTEM_PROMPT_ENV_VAR, system_prompt)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Failed to run LLM command: {}", command))?;
+    let watchdog = timeout.map(|timeout| Watchdog::start(child.id(), timeout));
+
+    // Write and read on the side, so that neither a long prompt nor a
+    // command with a lot to say on stderr stops it while stdout is read
+    let writer = child.stdin.take().map(|mut stdin| {
+        let prompt = prompt.to_string();
+        std::thread::spawn(move || stdin.write_all(prompt.as_bytes()))
+    });
+    let stderr = child.stderr.take().map(|mut stderr| {
+        std::thread::spawn(move || {
+            let mut text = Vec::new();
+            let _ = stderr.read_to_end(&mut text);
+            text
+        })
+    });
+    let mut reply = Vec::new();
+    if let Some(mut stdout) = child.stdout.take() {
+        let mut buffer = [0; 4096];
+        let mut passed = 0;
+        loop {
+            let n = stdout.read(&mut buffer).with_context(|| "Failed to read LLM reply")?;
+            if n == 0 {
+                break;
+            }
+            reply.extend_from_slice(&buffer[..n]);
+            let end = passed + text::whole_chars(&reply[passed..]);
+            if end > passed {
+                on_output(&String::from_utf8_lossy(&reply[passed..end]));
+                passed = end;
+            }
+        }
+    }
+    let status = child.wait().with_context(|| "Failed to read LLM reply")?;
+    if let (Some(watchdog), Some(timeout)) = (watchdog, timeout) {
+        if watchdog.stop() {
+            anyhow::bail!("LLM command timed out after {}s", timeout.as_secs_f64());
+        }
+    }
+    if !status.success() {
+        let stderr = stderr.and_then(|thread| thread.join().ok()).unwrap_or_default();
+        anyhow::bail!(
+            "LLM command failed: {}",
+            String::from_utf8_lossy(&stderr).trim()
+        );
+    }
+    if let Some(Err(error)) = writer.and_then(|thread| thread.join().ok()) {
+        return Err(error).with_context(|| "Failed to send prompt to LLM command");
+    }
+    Ok(String::from_utf8_lossy(&reply).trim().to_string())
+}
+
+/// The contents of the first fenced code block in a reply.
+pub fn extract_code_block(reply: &str) -> Option<String> {
+    let mut lines = reply.lines().skip_while(|line| !line.trim_start().starts_with("```"));
+    lines.next()?;
+    let code: Vec<&str> = lines
+        .take_while(|line| !line.trim_start().starts_with("```"))
+        .collect();
+    let code = code.join("\n");
+    if code.trim().is_empty() {
+        None
+    } else {
+        Some(code)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::ReplKind;
+
+    #[test]
+    fn test_extract_code_block() {
+        let reply = "Try this:\n```sql\nSELECT *\nFROM t;\n```\nor not.";
+        assert_eq!(extract_code_block(reply), Some("SELECT *\nFROM t;".to_string()));
+        assert_eq!(extract_code_block("no code here"), None);
+    }
+
+    #[test]
+    fn test_complete_passes_system_prompt() {
+        let config = LlmConfig {
+            command: Some("printf '%s|' \"$CHATSHELL_SYSTEM_PROMPT\"; cat".to_string()),
+            system_prompt: Some("Be terse.".to_string()),
+            include_notes: false,
+            ..Default::default()
+        };
+        let system = system_prompt(&config, SessionMode::Repl(ReplKind::Python));
+        let reply = complete(&config, &system, "hi").unwrap();
+        assert!(reply.contains("Python REPL"));
+        assert!(reply.ends_with("Be terse.|hi"));
+
+        let mut streamed = String::new();
+        let reply = stream(&config, "", "hello", |text| streamed.push_str(text)).unwrap();
+        assert_eq!(reply, "|hello");
+        assert_eq!(streamed, "|hello");
+
+        let config = LlmConfig { command: Some("sleep 5 & cat; wait".to_string()), ..Default::default() };
+        assert_eq!(complete_within(&config, "", "hi", Duration::from_millis(200)).unwrap_err().to_string(), "LLM command timed out after 0.2s");
+    }
+
+    #[test]
+    fn test_long_prompt_and_reply_dont_block() {
+        // Fills the stdout pipe before reading a prompt that fills stdin's
+        let config = LlmConfig { command: Some("seq 100000; wc -c".to_string()), ..Default::default() };
+        let prompt = "x".repeat(1 << 20);
+        let reply = complete_within(&config, "", &prompt, Duration::from_secs(10)).unwrap();
+        assert!(reply.starts_with("1\n2\n"));
+        assert!(reply.ends_with("\n1048576"));
+    }
+}
@@ -0,0 +1,309 @@
+use anyhow::Result;
+#[cfg(feature = "lua")]
+use {
+    anyhow::Context,
+    crossterm::event::{KeyCode, KeyModifiers},
+    mlua::{HookTriggers, Lua, Value},
+    std::cell::RefCell,
+    std::time::Instant,
+    std::os::unix::process::CommandExt,
+    std::path::Path,
+    std::process::{Command, Stdio},
+    crate::config::expand_home,
+    crate::process::Watchdog,
+};
+use std::time::Duration;
+
+use crate::permissions::Permission;
+use crate::terminal::KeyInput;
+
+/// How long a script's `run_command` or `llm_prompt` may take when its
+/// hook sets no `timeout_ms`, as the session waits for it.
+pub const CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What a `lua:` action can ask of the session.
+pub trait ScriptHost {
+    fn popup(&mut self, title: &str, text: &str) -> Result<()>;
+    /// Types `text` into the shell once the action has returned.
+    fn inject(&mut self, text: &str);
+    /// Sends `prompt` to the configured LLM and returns its reply, if it
+    /// comes within `timeout`.
+    fn llm_prompt(&mut self, prompt: &str, timeout: Duration) -> Result<String>;
+    /// Puts `text` on the clipboard.
+    fn copy(&mut self, text: &str) -> Result<()>;
+    /// Fails unless the script may do what `permission` covers.
+    fn permit(&mut self, _permission: Permission) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// What running a script came to.
+#[derive(Debug, Default, PartialEq)]
+pub struct ScriptResult {
+    /// False if the script returned `false`, passing the key on to the shell.
+    pub consumed: bool,
+    /// What it printed with `print`.
+    pub printed: String,
+}
+
+/// Runs a `lua:` action, given as a path to a `.lua` file or as the code
+/// itself, with a `chatshell` table for talking to the session:
+///
+/// - `chatshell.key`: the key that ran it, with `name`, `ctrl`, `alt` and `shift`
+/// - `chatshell.popup([title,] text)`
+/// - `chatshell.run_command(command)`: runs it with `/bin/sh` and returns a
+///   table of `stdout`, `stderr` and `status`
+/// - `chatshell.inject(text)`: types text into the shell
+/// - `chatshell.llm_prompt(prompt)`: the reply of the `[llm]`
+/// - `chatshell.copy(text)`: puts text on the clipboard
+///
+/// A script, command or prompt that takes longer than `timeout` fails, as
+/// the session waits on the script.
+#[cfg(feature = "lua")]
+pub fn run(source: &str, key: &KeyInput, timeout: Duration, host: &mut dyn ScriptHost) -> Result<ScriptResult> {
+    let (name, code) = load_source(source)?;
+    let host = RefCell::new(host);
+    let printed = RefCell::new(String::new());
+    let lua = Lua::new();
+    // Checked every so many instructions, so an endless loop ends too
+    let deadline = Instant::now() + timeout;
+    lua.set_hook(HookTriggers::new().every_nth_instruction(10_000), move |_, _| {
+        if Instant::now() > deadline {
+            return Err(mlua::Error::RuntimeError(format!("Ran longer than {}s", timeout.as_secs_f64())));
+        }
+        Ok(())
+    });
+
+    let consumed = lua
+        .scope(|scope| {
+            let api = lua.create_table()?;
+            api.set("key", key_table(&lua, key)?)?;
+            api.set(
+                "popup",
+                scope.create_function(|_, (first, second): (String, Option<String>)| {
+                    let (title, text) = match second {
+                        Some(text) => (first, text),
+                        None => ("Lua".to_string(), first),
+                    };
+                    host.borrow_mut().popup(&title, &text).map_err(mlua::Error::external)
+                })?,
+            )?;
+            api.set(
+                "inject",
+                scope.create_function(|_, text: String| {
+                    let mut host = host.borrow_mut();
+                    host.permit(Permission::Execute).map_err(mlua::Error::external)?;
+                    host.inject(&text);
+                    Ok(())
+                })?,
+            )?;
+            api.set(
+                "llm_prompt",
+                scope.create_function(|_, prompt: String| {
+                    let mut host = host.borrow_mut();
+                    host.permit(Permission::Network).map_err(mlua::Error::external)?;
+                    host.llm_prompt(&prompt, timeout).map_err(mlua::Error::external)
+                })?,
+            )?;
+            api.set(
+                "copy",
+                scope.create_function(|_, text: String| {
+                    let mut host = host.borrow_mut();
+                    host.permit(Permission::Clipboard).map_err(mlua::Error::external)?;
+                    host.copy(&text).map_err(mlua::Error::external)
+                })?,
+            )?;
+            api.set(
+                "run_command",
+                scope.create_function(|lua, command: String| {
+                    host.borrow_mut().permit(Permission::Execute).map_err(mlua::Error::external)?;
+                    run_command(lua, command, timeout)
+                })?,
+            )?;
+            lua.globals().set("chatshell", api)?;
+
+            // Printing would write over the terminal, so it is collected
+            lua.globals().set(
+                "print",
+                scope.create_function(|lua, values: mlua::Variadic<Value>| {
+                    let mut line = Vec::new();
+                    for value in values {
+                        line.push(lua.coerce_string(value)?.map_or("nil".into(), |text| text.to_string_lossy().to_string()));
+                    }
+                    let mut printed = printed.borrow_mut();
+                    printed.push_str(&line.join("\t"));
+                    printed.push('\n');
+                    Ok(())
+                })?,
+            )?;
+
+            let value: Value = lua.load(code.as_str()).set_name(name.as_str()).eval()?;
+            Ok(!matches!(value, Value::Boolean(false)))
+        })
+        .map_err(|e| anyhow::anyhow!("{}", e))
+        .with_context(|| format!("Lua action {} failed", name))?;
+
+    Ok(ScriptResult { consumed, printed: printed.into_inner() })
+}
+
+#[cfg(not(feature = "lua"))]
+pub fn run(_source: &str, _key: &KeyInput, _timeout: Duration, _host: &mut dyn ScriptHost) -> Result<ScriptResult> {
+    anyhow::bail!("Can't run lua: actions: chatshell was built without the lua feature")
+}
+
+/// The name and code of the script: a file if `source` names one, or
+/// ends in `.lua`, else the code itself.
+#[cfg(feature = "lua")]
+fn load_source(source: &str) -> Result<(String, String)> {
+    let path = expand_home(source.trim());
+    if path.ends_with(".lua") || (!path.contains('\n') && Path::new(&path).is_file()) {
+        let code = std::fs::read_to_string(&path).with_context(|| format!("Failed to read {}", path))?;
+        Ok((path, code))
+    } else {
+        Ok(("inline".to_string(), source.to_string()))
+    }
+}
+
+#[cfg(feature = "lua")]
+fn key_table<'lua>(lua: &'lua Lua, key: &KeyInput) -> mlua::Result<mlua::Table<'lua>> {
+    let table = lua.create_table()?;
+    table.set("name", key_name(key.code))?;
+    table.set("ctrl", key.modifiers.contains(KeyModifiers::CONTROL))?;
+    table.set("alt", key.modifiers.contains(KeyModifiers::ALT))?;
+    table.set("shift", key.modifiers.contains(KeyModifiers::SHIFT))?;
+    Ok(table)
+}
+
+/// The key as it is written in a `key_combination`, without modifiers.
+#[cfg(feature = "lua")]
+fn key_name(code: KeyCode) -> String {
+    match code {
+        KeyCode::Char(' ') => "space".to_string(),
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(number) => format!("f{}", number),
+        KeyCode::Esc => "escape".to_string(),
+        code => format!("{:?}", code).to_lowercase(),
+    }
+}
+
+#[cfg(feature = "lua")]
+fn run_command<'lua>(lua: &'lua Lua, command: String, timeout: Duration) -> mlua::Result<mlua::Table<'lua>> {
+    let child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&command)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .map_err(mlua::Error::external)?;
+    let watchdog = Watchdog::start(child.id(), timeout);
+    let output = child.wait_with_output().map_err(mlua::Error::external)?;
+    if watchdog.stop() {
+        return Err(mlua::Error::external(anyhow::anyhow!("Timed out after {}s: {}", timeout.as_secs_f64(), command)));
+    }
+    let table = lua.create_table()?;
+    table.set("stdout", String::from_utf8_lossy(&output.stdout).to_string())?;
+    table.set("stderr", String::from_utf8_lossy(&output.stderr).to_string())?;
+    table.set("status", output.status.code())?;
+    Ok(table)
+}
+
+#[cfg(all(test, feature = "lua"))]
+mod tests {
+    use super::*;
+
+    #[derive(Default)]
+    struct Host {
+        popups: Vec<(String, String)>,
+        injected: Vec<String>,
+        copied: Vec<String>,
+        denied: Vec<Permission>,
+    }
+
+    impl ScriptHost for Host {
+        fn popup(&mut self, title: &str, text: &str) -> Result<()> {
+            self.popups.push((title.to_string(), text.to_string()));
+            Ok(())
+        }
+
+        fn inject(&mut self, text: &str) {
+            self.injected.push(text.to_string());
+        }
+
+        fn llm_prompt(&mut self, prompt: &str, _timeout: Duration) -> Result<String> {
+            Ok(format!("reply to {}", prompt))
+        }
+
+        fn copy(&mut self, text: &str) -> Result<()> {
+            self.copied.push(text.to_string());
+            Ok(())
+        }
+
+        fn permit(&mut self, permission: Permission) -> Result<()> {
+            match self.denied.contains(&permission) {
+                true => anyhow::bail!("{} denied", permission.key()),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn test_scripts_use_the_api() {
+        let key = KeyInput::new(KeyCode::F(9), KeyModifiers::CONTROL);
+        let mut host = Host::default();
+        let script = r#"
+            local result = chatshell.run_command("echo hi; exit 3")
+            chatshell.popup("Key", chatshell.key.name .. " " .. tostring(chatshell.key.ctrl))
+            chatshell.popup(result.stdout .. result.status)
+            chatshell.inject(chatshell.llm_prompt("what now"))
+            chatshell.copy("clip")
+            print("done", 1)
+        "#;
+        let result = run(script, &key, CALL_TIMEOUT, &mut host).unwrap();
+        assert_eq!(result, ScriptResult { consumed: true, printed: "done\t1\n".to_string() });
+        assert_eq!(
+            host.popups,
+            vec![
+                ("Key".to_string(), "f9 true".to_string()),
+                ("Lua".to_string(), "hi\n3".to_string()),
+            ]
+        );
+        assert_eq!(host.injected, vec!["reply to what now".to_string()]);
+        assert_eq!(host.copied, vec!["clip".to_string()]);
+
+        // A script not allowed to run commands stops at the first one
+        let mut host = Host { denied: vec![Permission::Execute], ..Host::default() };
+        let error = run("chatshell.copy('a'); chatshell.run_command('touch /tmp/x')", &key, CALL_TIMEOUT, &mut host).unwrap_err();
+        assert!(format!("{:#}", error).contains("execute denied"));
+        assert_eq!(host.copied, vec!["a".to_string()]);
+
+        assert!(!run("return false", &key, CALL_TIMEOUT, &mut host).unwrap().consumed);
+        let error = run("chatshell.nothing()", &key, CALL_TIMEOUT, &mut host).unwrap_err();
+        assert!(format!("{:#}", error).contains("Lua action inline failed"));
+
+        // A command that runs too long is killed, along with what it started
+        let started = std::time::Instant::now();
+        let error = run("chatshell.run_command('sleep 5 & wait')", &key, Duration::from_millis(200), &mut Host::default()).unwrap_err();
+        assert!(format!("{:#}", error).contains("Timed out after 0.2s: sleep 5 & wait"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        // So does a script that never returns
+        let started = std::time::Instant::now();
+        let error = run("while true do end", &key, Duration::from_millis(200), &mut Host::default()).unwrap_err();
+        assert!(format!("{:#}", error).contains("Ran longer than 0.2s"));
+        assert!(started.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_scripts_load_from_files() {
+        let mut file = tempfile::Builder::new().suffix(".lua").tempfile().unwrap();
+        std::io::Write::write_all(&mut file, b"chatshell.inject('ls\\n')").unwrap();
+        let mut host = Host::default();
+        let key = KeyInput::new(KeyCode::Char('l'), KeyModifiers::ALT);
+        run(&file.path().to_string_lossy(), &key, CALL_TIMEOUT, &mut host).unwrap();
+        assert_eq!(host.injected, vec!["ls\n".to_string()]);
+
+        assert!(run("/no/such/script.lua", &key, CALL_TIMEOUT, &mut host).is_err());
+    }
+}
@@ -1,37 +1,182 @@
-mod config;
-mod pty;
-mod terminal;
-mod hooks;
-mod window;
-
 use anyhow::{Context, Result};
 use clap::{Arg, Command};
-use crossterm::event::Event;
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyboardEnhancementFlags};
 use futures::stream::StreamExt;
-use nix::sys::signal::Signal;
-use std::io::{Read, Write};
-use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::collections::HashMap;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 use tokio::select;
 
-use config::Config;
-use hooks::{HookManager, create_default_hooks};
-use pty::PtySession;
-use terminal::{Terminal, KeyInput};
+use chatshell::automation::StartupScript;
+use chatshell::backend::{AsyncIo, Backend};
+use chatshell::bugreport;
+use chatshell::cheatsheet::{self, Cheatsheet};
+use chatshell::clock::{Clock, SystemClock};
+use chatshell::config::{expand_home, Config, ExitPolicy, HookEvent, ShellConfig, UnknownKey};
+use chatshell::control::{self, ControlMessage, ControlRequest, ControlResponse, ControlServer};
+use chatshell::errors::{debug_log_path, Error, ErrorLog};
+use chatshell::expect::{self, InteractionLog};
+use chatshell::highlight::Highlighter;
+use chatshell::hatch;
+use chatshell::headless;
+use chatshell::history::{Annotation, CommandRecorder};
+use chatshell::hookpack::{self, FetchedPack, Registry};
+use chatshell::i18n::{self, tr, tr_args};
+use chatshell::hooks::{configured_hooks, fill_arg_defaults, ChordKey, CommandResult, HookCommand, HookManager, LlmReply, SessionAction, create_default_hooks};
+use chatshell::images::{self, ImageTracker, InlineImage};
+use chatshell::inputs;
+use chatshell::journal::{self, Transcript};
+use chatshell::notepad;
+use chatshell::output::{CommandMark, OutputProcessor};
+use chatshell::pane::{Layout, Pane, SplitDirection};
+use chatshell::paste::{self, LargePaste};
+use chatshell::permissions::{self, Permissions};
+use chatshell::plugin;
+use chatshell::companion;
+use chatshell::prespawn::{self, Veto};
+use chatshell::project;
+use chatshell::pty::PtySession;
+use chatshell::render::{Region, Renderer};
+use chatshell::serial::{SerialConfig, SerialSession};
+use chatshell::repl::SessionMode;
+use chatshell::report::{self, Report};
+use chatshell::shell::{is_valid_env_name, unsubmitted_chars, KeyHold, ShellDialect, StartupQueue};
+use chatshell::ssh;
+use chatshell::state::{self, SavedBinding, SavedMessage, SavedNote, SavedWatch, SessionState};
+use chatshell::storage;
+use chatshell::terminal::{KeyInput, KeyTiming, Terminal};
+use chatshell::theme::Theme;
+use chatshell::transcript::{self, TranscriptFile};
+use chatshell::watch::{self, Watch, WatchOutput};
+use chatshell::window::{ChatHandle, ChatMessage, Corner, LivePopup, Note, PopupHandle, PopupUpdate, Style, Window, WindowManager};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+
+/// How long the shell must stay quiet after its first output before startup
+/// commands are typed.
+const STARTUP_SETTLE: Duration = Duration::from_millis(300);
+
+/// In low-bandwidth mode, how long shell output must stop before the pane
+/// and watch panel it may have drawn over are drawn again.
+const OVERLAY_SETTLE: Duration = Duration::from_millis(500);
+
+/// The most output gathered into one write in low-bandwidth mode.
+const MAX_COALESCED: usize = 64 * 1024;
+
+/// A shell that exits sooner than this after starting is not respawned.
+const MIN_SHELL_LIFETIME: Duration = Duration::from_secs(1);
+
+/// How long keys typed after a hook's deferred input wait for it to go out.
+const KEY_HOLD_LIMIT: Duration = Duration::from_secs(2);
+
+/// How long a hook waits for the output of a command it ran in the shell.
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(30);
+
+// How long the terminal has to keep its size before on_resize hooks run,
+// so dragging a window's edge runs them once
+const RESIZE_SETTLE: Duration = Duration::from_millis(300);
+
+// How long commands still running when the session ends, like those of
+// on_exit hooks, get to finish
+const EXIT_COMMANDS_GRACE: Duration = Duration::from_secs(5);
 
 #[derive(Debug)]
 struct ChatShell {
     config: Config,
+    // Keys of the config that were left out, to show once the session is up
+    unknown_keys: Vec<UnknownKey>,
     terminal: Terminal,
-    pty: PtySession,
+    backend: Box<dyn Backend>,
+    // The device given with --serial, while it is what the session runs
+    serial: Option<SerialConfig>,
+    // The config the shell was spawned with, reused when respawning it
+    spawn_config: ShellConfig,
+    // Read by the output reader and written by the input writer; replaced
+    // when the shell is respawned
+    shell_io: Arc<Mutex<AsyncIo>>,
+    shell_started: Instant,
+    // Where the session reads the time, shared with the hooks and output
+    clock: Arc<dyn Clock>,
     hook_manager: HookManager,
+    output: OutputProcessor,
+    dialect: ShellDialect,
+    startup: StartupQueue,
+    startup_commands: Vec<String>,
+    // The `[[automation.startup]]` steps left for the shell, run ahead of
+    // its startup commands
+    automation: StartupScript,
+    // Tells double and long presses apart
+    key_timing: KeyTiming,
+    // Keys typed while a hook's input waits for the next prompt
+    held_keys: KeyHold<KeyInput>,
+    // Characters the last input typed on the user's behalf left on the
+    // command line, for builtin:undo_input
+    undoable: usize,
+    output_tx: UnboundedSender<Vec<u8>>,
+    output_rx: Option<UnboundedReceiver<Vec<u8>>>,
+    // Held so the socket file is removed when the session ends
+    _control: ControlServer,
+    control_rx: Option<UnboundedReceiver<ControlMessage>>,
+    // Draws the watch panel; popups are drawn by the hook manager
+    windows: WindowManager,
+    watch: Option<Watch>,
+    watch_panel: Option<Window>,
+    // Popups pinned as notes, oldest first, and where they were last drawn
+    notes: Vec<(Note, Corner)>,
+    // Where `chatshell state save` puts states
+    states_dir: PathBuf,
+    note_windows: Vec<Window>,
+    // Tracks what of the pane and watch panel needs drawing again
+    renderer: Renderer,
+    next_watch_id: u64,
+    watch_tx: UnboundedSender<WatchOutput>,
+    watch_rx: Option<UnboundedReceiver<WatchOutput>>,
+    // Results of `cmd:` actions running in the background
+    command_tx: UnboundedSender<CommandResult>,
+    command_rx: Option<UnboundedReceiver<CommandResult>>,
+    // Stops a `cmd:` action still running; ESC sends to them all
+    running_commands: Vec<tokio::sync::oneshot::Sender<()>>,
+    // Popups that show what is streamed into them, by id
+    live_popups: HashMap<u64, LivePopup>,
+    next_popup_id: u64,
+    popup_tx: UnboundedSender<(u64, PopupUpdate)>,
+    popup_rx: Option<UnboundedReceiver<(u64, PopupUpdate)>>,
+    // Answers of the LLM, and what streams into the chat view meanwhile
+    llm_tx: UnboundedSender<LlmReply>,
+    llm_rx: Option<UnboundedReceiver<LlmReply>>,
+    chat_tx: UnboundedSender<String>,
+    chat_rx: Option<UnboundedReceiver<String>>,
+    // What companion processes say other than answers to keys
+    companion_rx: Option<UnboundedReceiver<companion::Message>>,
+    // The shell's directory when its project file was last looked for
+    cwd: Option<PathBuf>,
+    // When the terminal was last resized, until on_resize hooks have run
+    resized_at: Option<Instant>,
+    pane: Option<Pane>,
+    images: ImageTracker,
+    transcript: Transcript,
+    interactions: InteractionLog,
+    // The scrollback lines commented on in copy mode
+    annotations: Vec<Range<usize>>,
+    // None when history is off or its database can't be opened
+    recorder: Option<CommandRecorder>,
+    pane_tx: UnboundedSender<Vec<u8>>,
+    pane_rx: Option<UnboundedReceiver<Vec<u8>>>,
+    // Told of SIGWINCH by the signal task
+    resize_rx: Option<UnboundedReceiver<()>>,
     running: Arc<AtomicBool>,
 }
 
 impl ChatShell {
-    pub async fn new(config_path: Option<String>) -> Result<Self> {
+    pub async fn new(
+        config_path: Option<String>,
+        profile: Option<String>,
+        session: &str,
+        mut style: Style,
+        serial: Option<SerialConfig>,
+    ) -> Result<Self> {
         // Load or create configuration
         let config_path = if let Some(path) = config_path {
             path
@@ -39,8 +184,52 @@ impl ChatShell {
             Config::ensure_config_exists()?
         };
 
-        let config = Config::load_from_file(&config_path)
+        let (config, unknown_keys) = Config::load_with_unknown_keys(&config_path)
             .with_context(|| format!("Failed to load config from {}", config_path))?;
+        let startup_commands = config.startup_commands(profile.as_deref())?;
+        let config_dir = Path::new(&config_path).parent().unwrap_or(Path::new("."));
+        i18n::init(&config.i18n, &config_dir.join("locales"));
+        let (theme, theme_changes) = Theme::from_config(&config.ui).context("Invalid [ui.theme]")?;
+        for change in theme_changes {
+            eprintln!("Theme color changed for contrast: {}", change);
+        }
+        style.theme = theme;
+        style.markers = config.ui.accessibility.markers;
+        hatch::arm(&config.keys.escape_hatch, config.keys.escape_hatch_policy).context("Invalid [keys] escape_hatch")?;
+
+        let recorder = CommandRecorder::open(&config.history).unwrap_or_else(|e| {
+            eprintln!("Command history is off: {:#}", e);
+            None
+        });
+
+        // The wrapper is written by `chatshell init`, or here the first time
+        if config.shell.ssh_wrapper && !ssh::default_wrapper_dir().join("ssh").exists() {
+            if let Err(e) = ssh::install_wrapper(&ssh::default_wrapper_dir()) {
+                eprintln!("The ssh wrapper is off: {:#}", e);
+            }
+        }
+
+        let (plugins, plugin_errors) = plugin::load_all(&plugin::plugins_dir(Path::new(&config_path)));
+        for error in plugin_errors {
+            eprintln!("Plugin left out: {:#}", error);
+        }
+        let permissions = Permissions::load(permissions::permissions_path(Path::new(&config_path)))
+            .unwrap_or_else(|e| {
+                eprintln!("Permissions are asked again this session: {:#}", e);
+                Permissions::default()
+            });
+        let (companion_tx, companion_rx) = tokio::sync::mpsc::unbounded_channel::<companion::Message>();
+        let (companions, companion_errors) = companion::spawn_all(&config.companions, &companion_tx);
+        for error in companion_errors {
+            eprintln!("Companion left out: {:#}", error);
+        }
+
+        // Checks that may keep the shell from starting, while their message
+        // can still be printed plainly
+        let checked_env = match &serial {
+            Some(_) => Vec::new(),
+            None => prespawn::run_checks(&config.pre_spawn, &config.shell.command)?,
+        };
 
         // Initialize terminal
         let mut terminal = Terminal::new()
@@ -50,35 +239,180 @@ impl ChatShell {
         terminal.enter_raw_mode()
             .with_context(|| "Failed to enter raw mode")?;
 
-        // Spawn shell process
-        let pty = PtySession::spawn(&config.shell)
-            .with_context(|| "Failed to spawn shell process")?;
+        // Start the control socket and advertise it to the shell
+        let (control, control_rx) = ControlServer::start(control::default_socket_path())?;
+        let mut shell_config = config.shell.clone();
+        let env = shell_config.env.get_or_insert_with(Default::default);
+        env.extend(checked_env);
+        env.insert(control::SOCKET_ENV_VAR.to_string(), control.path().to_string_lossy().to_string());
+
+        // Open the serial device, or spawn the shell process
+        let (backend, session_mode): (Box<dyn Backend>, _) = match &serial {
+            Some(serial) => (Box::new(SerialSession::open(serial)?), SessionMode::Device),
+            None => {
+                let pty = PtySession::spawn(&shell_config)
+                    .with_context(|| "Failed to spawn shell process")?;
+                (Box::new(pty), SessionMode::detect(&config.shell.command, config.shell.repl))
+            }
+        };
+        hatch::set_shell(backend.pid());
 
         // Set up signal handling
         let running = Arc::new(AtomicBool::new(true));
-        Self::setup_signal_handlers(running.clone())?;
+        let (resize_tx, resize_rx) = tokio::sync::mpsc::unbounded_channel::<()>();
+        Self::setup_signal_handlers(running.clone(), resize_tx)?;
 
         // Initialize hook manager
-        let hook_manager = HookManager::from_configs(config.hooks.clone());
+        let packs_dir = hookpack::packs_dir(Path::new(&config_path));
+        let (hooks, pack_errors) = configured_hooks(&config, &packs_dir)?;
+        for error in pack_errors {
+            eprintln!("Hook pack left out: {:#}", error);
+        }
+        let mut hook_manager = HookManager::from_configs(hooks);
+        hook_manager.set_config_path(&config_path);
+        let input_history = config.history.inputs.then(|| inputs::input_history_path(Path::new(&config_path)));
+        hook_manager.set_input_history(input_history.clone());
+        hook_manager.set_notes_path(notepad::notes_path(&notepad::notes_dir(Path::new(&config_path)), session)?);
+        let transcript_path = match config.history.transcripts {
+            true => Some(transcript::transcript_path(&transcript::transcripts_dir(Path::new(&config_path)), session)?),
+            false => None,
+        };
+        let transcript = transcript_path.and_then(|path| {
+            TranscriptFile::open(&path).map_err(|e| eprintln!("Transcripts are off: {:#}", e)).ok()
+        });
+        let transcribing = transcript.is_some();
+        if let Some(transcript) = transcript {
+            hook_manager.set_transcript(transcript);
+        }
+        hook_manager.set_session_mode(session_mode);
+        hook_manager.set_error_log(ErrorLog::new(debug_log_path()));
+        hook_manager.set_style(style);
+        // Left out of the build, highlighting is simply off
+        if config.ui.highlight.enabled && cfg!(feature = "highlight") {
+            let highlighter = Highlighter::new(&config.ui.highlight.theme).context("Invalid [ui.highlight]")?;
+            hook_manager.set_highlighter(Some(highlighter));
+        }
+        hook_manager.set_chord_timeout(Duration::from_millis(config.keys.chord_timeout_ms));
+        hook_manager.set_permissions(permissions);
+        for plugin in plugins {
+            hook_manager.add_plugin(plugin);
+        }
+        for companion in companions {
+            hook_manager.add_companion(companion);
+        }
+        hook_manager.notify_companions(&companion::Event::Start { shell: shell_config.command.clone(), pid: backend.pid() });
+        let mut key_timing = KeyTiming::new(Duration::from_millis(config.keys.double_press_ms));
+        let long_press = hook_manager.long_press_patterns();
+        let mut keyboard = KeyboardEnhancementFlags::empty();
+        if config.keys.kitty_protocol {
+            keyboard |= KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES;
+        }
+        if !long_press.is_empty() {
+            keyboard |= KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+        }
+        // Long presses can only be told on terminals that report releases
+        if terminal.enhance_keyboard(keyboard)? && !long_press.is_empty() {
+            key_timing.set_long_press(long_press);
+        }
+        let clock: Arc<dyn Clock> = Arc::new(SystemClock);
+        hook_manager.set_clock(clock.clone());
+        let mut output = OutputProcessor::new(config.terminal.clone());
+        output.set_clock(clock.clone());
+        output.set_wayback(&config.wayback);
+        output.set_scrollback(config.scrollback.lines);
+        if transcribing {
+            output.keep_colored_lines();
+        }
+        let dialect = ShellDialect::detect(&config.shell.command);
+        let startup = StartupQueue::new(startup_commands.clone(), STARTUP_SETTLE);
+        let automation = StartupScript::new(&config.automation.startup).context("Invalid [automation]")?;
+        let shell_io = Arc::new(Mutex::new(backend.async_io()?));
+        let (output_tx, output_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+        let (watch_tx, watch_rx) = tokio::sync::mpsc::unbounded_channel::<WatchOutput>();
+        let (command_tx, command_rx) = tokio::sync::mpsc::unbounded_channel::<CommandResult>();
+        let (popup_tx, popup_rx) = tokio::sync::mpsc::unbounded_channel::<(u64, PopupUpdate)>();
+        let (llm_tx, llm_rx) = tokio::sync::mpsc::unbounded_channel::<LlmReply>();
+        let (chat_tx, chat_rx) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let (pane_tx, pane_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
 
         // Resize PTY to match terminal size
         let (cols, rows) = terminal.size()?;
-        pty.resize_pty(rows, cols)?;
+        backend.resize(rows, cols)?;
+        output.resize(cols, rows);
+        let mut images = ImageTracker::default();
+        images.set_screen_rows(rows);
+        let renderer = Renderer::new(config.render.max_fps);
 
         Ok(ChatShell {
             config,
             terminal,
-            pty,
+            backend,
+            serial,
+            spawn_config: shell_config,
+            shell_io,
+            shell_started: clock.now(),
+            clock: clock.clone(),
             hook_manager,
+            output,
+            dialect,
+            startup,
+            startup_commands,
+            automation,
+            key_timing,
+            held_keys: KeyHold::new(KEY_HOLD_LIMIT),
+            undoable: 0,
+            output_tx,
+            output_rx: Some(output_rx),
+            _control: control,
+            control_rx: Some(control_rx),
+            windows: WindowManager {
+                style,
+                input_history,
+                clock,
+                ..WindowManager::default()
+            },
+            watch: None,
+            watch_panel: None,
+            notes: Vec::new(),
+            states_dir: state::states_dir(Path::new(&config_path)),
+            unknown_keys,
+            note_windows: Vec::new(),
+            renderer,
+            next_watch_id: 1,
+            watch_tx,
+            watch_rx: Some(watch_rx),
+            command_tx,
+            command_rx: Some(command_rx),
+            running_commands: Vec::new(),
+            live_popups: HashMap::new(),
+            next_popup_id: 1,
+            popup_tx,
+            popup_rx: Some(popup_rx),
+            llm_tx,
+            llm_rx: Some(llm_rx),
+            chat_tx,
+            chat_rx: Some(chat_rx),
+            companion_rx: Some(companion_rx),
+            cwd: None,
+            resized_at: None,
+            pane: None,
+            images,
+            transcript: Transcript::new(),
+            interactions: InteractionLog::default(),
+            annotations: Vec::new(),
+            recorder,
+            pane_tx,
+            pane_rx: Some(pane_rx),
+            resize_rx: Some(resize_rx),
             running,
         })
     }
 
-    fn setup_signal_handlers(running: Arc<AtomicBool>) -> Result<()> {
+    fn setup_signal_handlers(running: Arc<AtomicBool>, resize_tx: UnboundedSender<()>) -> Result<()> {
         let running_clone = running.clone();
         
         // Handle SIGINT (Ctrl+C) and SIGTERM gracefully
-        let mut signals = signal_hook_tokio::Signals::new(&[
+        let mut signals = signal_hook_tokio::Signals::new([
             signal_hook::consts::SIGINT,
             signal_hook::consts::SIGTERM,
             signal_hook::consts::SIGWINCH, // Window resize
@@ -92,8 +426,8 @@ impl ChatShell {
                         break;
                     }
                     signal_hook::consts::SIGWINCH => {
-                        // Handle window resize - this would need to be communicated
-                        // to the main loop to resize the PTY
+                        // The main loop asks the terminal for its new size
+                        let _ = resize_tx.send(());
                     }
                     _ => {}
                 }
@@ -104,53 +438,19 @@ impl ChatShell {
     }
 
     pub async fn run(&mut self) -> Result<()> {
-        println!("ChatShell started. Press Ctrl+; for help.");
+        println!("{}", tr("session-started"));
         
         // Create channels for communication between tasks
         let (input_tx, mut input_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
-        let (output_tx, mut output_rx) = tokio::sync::mpsc::unbounded_channel::<Vec<u8>>();
+        let mut output_rx = self.output_rx.take()
+            .with_context(|| "Output channel already in use")?;
 
         // Task to read from shell and send to terminal
-        let pty_fd = self.pty.master.as_raw_fd();
-        let output_tx_clone = output_tx.clone();
-        let running_clone = self.running.clone();
-        
-        tokio::spawn(async move {
-            let mut buffer = [0u8; 4096];
-            loop {
-                if !running_clone.load(Ordering::Relaxed) {
-                    break;
-                }
-
-                // Use blocking read with non-blocking fd
-                let mut file = unsafe { std::fs::File::from_raw_fd(pty_fd) };
-                match file.read(&mut buffer) {
-                    Ok(n) if n > 0 => {
-                        if output_tx_clone.send(buffer[..n].to_vec()).is_err() {
-                            break;
-                        }
-                    }
-                    Ok(_) => {
-                        // EOF - shell process ended
-                        running_clone.store(false, Ordering::Relaxed);
-                        break;
-                    }
-                    Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
-                        // No data available, continue
-                    }
-                    Err(_) => {
-                        // Read error
-                        running_clone.store(false, Ordering::Relaxed);
-                        break;
-                    }
-                }
-                std::mem::forget(file); // Don't close the fd
-                tokio::time::sleep(Duration::from_millis(1)).await;
-            }
-        });
+        self.spawn_output_reader()?;
 
-        // Task to write to shell from input queue
-        let pty_fd_write = self.pty.master.as_raw_fd();
+        // Task to write to shell from input queue. The writer handle is
+        // swapped out when the shell is respawned.
+        let shell_io = self.shell_io.clone();
         let running_clone = self.running.clone();
         
         tokio::spawn(async move {
@@ -159,166 +459,2223 @@ impl ChatShell {
                     break;
                 }
 
-                let mut file = unsafe { std::fs::File::from_raw_fd(pty_fd_write) };
-                if file.write_all(&data).is_err() {
-                    running_clone.store(false, Ordering::Relaxed);
-                    break;
+                // Input sent while no shell is running is dropped
+                let writer = shell_io.lock().map(|io| io.clone()).ok();
+                if let Some(writer) = writer {
+                    let _ = writer.write_all(&data).await;
                 }
-                std::mem::forget(file); // Don't close the fd
             }
         });
 
+        let mut control_rx = self.control_rx.take()
+            .with_context(|| "Control channel already in use")?;
+        let mut watch_rx = self.watch_rx.take()
+            .with_context(|| "Watch channel already in use")?;
+        let mut command_rx = self.command_rx.take()
+            .with_context(|| "Command channel already in use")?;
+        let mut popup_rx = self.popup_rx.take()
+            .with_context(|| "Popup channel already in use")?;
+        let mut llm_rx = self.llm_rx.take()
+            .with_context(|| "LLM channel already in use")?;
+        let mut chat_rx = self.chat_rx.take()
+            .with_context(|| "Chat channel already in use")?;
+        let mut companion_rx = self.companion_rx.take()
+            .with_context(|| "Companion channel already in use")?;
+        let mut pane_rx = self.pane_rx.take()
+            .with_context(|| "Pane channel already in use")?;
+        let mut resize_rx = self.resize_rx.take()
+            .with_context(|| "Resize channel already in use")?;
+        let mut liveness = tokio::time::interval(Duration::from_millis(100));
+        let mut transcript_flush = tokio::time::interval(transcript::FLUSH_INTERVAL);
+
+        if self.config.low_bandwidth.enabled {
+            self.renderer.set_settle(Some(OVERLAY_SETTLE));
+        }
+
+        let env = self.start_env();
+        if let Err(e) = self.run_event_hooks(HookEvent::OnStart, &env, &input_tx) {
+            self.hook_manager.report_background_error(Error::categorize(e, Error::Plugin));
+        }
+        if let Err(e) = self.show_unknown_keys() {
+            self.hook_manager.report_background_error(Error::Ui(e));
+        }
+
         // Main event loop
         while self.running.load(Ordering::Relaxed) {
+            // Terminal input is polled every few milliseconds, which is
+            // often enough to catch each frame
+            self.draw_frame();
+            let next_timer = self.hook_manager.next_timer();
             select! {
                 // Handle terminal input
                 _ = self.handle_terminal_input(&input_tx) => {},
                 
                 // Handle shell output
                 output = output_rx.recv() => {
-                    if let Some(data) = output {
-                        if self.terminal.write(&data).is_err() {
+                    if let Some(mut data) = output {
+                        if self.config.low_bandwidth.enabled {
+                            // Fewer, larger writes
+                            let deadline = tokio::time::Instant::now()
+                                + Duration::from_millis(self.config.low_bandwidth.coalesce_ms);
+                            while data.len() < MAX_COALESCED {
+                                match tokio::time::timeout_at(deadline, output_rx.recv()).await {
+                                    Ok(Some(more)) => data.extend(more),
+                                    _ => break,
+                                }
+                            }
+                        }
+                        self.startup.observe_output(self.clock.now());
+                        let processed = self.output.process(&data);
+                        if !self.automation.is_done() {
+                            let current_line = self.output.current_line();
+                            for keys in self.automation.observe(&processed.lines, &current_line, self.clock.now()) {
+                                let _ = input_tx.send(keys.into_bytes());
+                            }
+                        }
+                        self.transcript.output(&processed.lines);
+                        if !processed.colored_lines.is_empty() {
+                            self.hook_manager.transcribe(&transcript::Entry::Output { lines: processed.colored_lines.clone() });
+                        }
+                        if let Some(recorder) = self.recorder.as_mut() {
+                            if let Err(e) = recorder.output(&processed.lines, &processed.exit_statuses) {
+                                self.recorder = None;
+                                self.hook_manager.report_background_error(Error::Config(e.context("Command history is off")));
+                            }
+                        }
+                        if !processed.replies.is_empty() {
+                            let _ = input_tx.send(processed.replies);
+                        }
+                        let display = match self.split_layout() {
+                            Some(layout) => layout.confine_output(&processed.display),
+                            None => processed.display,
+                        };
+                        if self.terminal.write(&display).is_err() {
                             break;
                         }
+                        if processed.images.is_empty() {
+                            self.images.observe(&display);
+                        } else {
+                            self.track_images(self.image_rows(&processed.images));
+                        }
+                        if self.split_layout().is_some() || self.watch.is_some() {
+                            self.renderer.mark_shell_output(self.clock.now());
+                        }
+                        if self.hook_manager.output_permissions_pending() {
+                            if let Err(e) = self.ask_output_permissions() {
+                                self.hook_manager.report_background_error(Error::Ui(e));
+                            }
+                        }
+                        if self.hook_manager.has_output_plugins() {
+                            if let Err(e) = self.run_output_plugins(&display, &input_tx) {
+                                self.hook_manager.report_background_error(Error::Pty(e));
+                            }
+                        }
+                        if self.hook_manager.has_output_companions() {
+                            self.hook_manager.companion_output(&String::from_utf8_lossy(&display));
+                        }
+                        if !processed.captures.is_empty() {
+                            self.hook_manager.set_screen(self.output.current_line(), self.output.recent_lines());
+                            self.hook_manager.set_image_regions(self.images.regions());
+                            for capture in processed.captures {
+                                if let Err(e) = self.hook_manager.complete_capture(capture) {
+                                    self.hook_manager.report_error(Error::categorize(e, Error::Plugin));
+                                }
+                            }
+                            self.apply_session_actions(&input_tx);
+                        }
+                        for mark in processed.command_marks {
+                            self.hook_manager.notify_companions(&companion::Event::from_mark(&mark));
+                            if let Err(e) = self.run_event_hooks(mark.event(), &mark.env(), &input_tx) {
+                                self.hook_manager.report_background_error(Error::Pty(e));
+                            }
+                        }
                     } else {
                         break; // Channel closed
                     }
                 }
                 
-                // Check if child process is still alive
-                _ = tokio::time::sleep(Duration::from_millis(100)) => {
-                    if !self.pty.is_child_alive() {
-                        println!("\rShell process ended.");
-                        break;
+                // Handle requests from `chatshell` commands run inside the session
+                Some(message) = control_rx.recv() => {
+                    let response = self.handle_control_request(message.request, &input_tx);
+                    let _ = message.reply.send(response);
+                }
+
+                // The outer terminal was resized; crossterm may report it
+                // too, but whichever comes second finds nothing to do
+                Some(()) = resize_rx.recv() => {
+                    let (cols, rows) = self.terminal.size()?;
+                    self.handle_resize(cols, rows)?;
+                }
+
+                // Output of the program in the auxiliary pane. Anything left
+                // over from a closed pane is dropped.
+                Some(data) = pane_rx.recv() => {
+                    if let Some(pane) = self.pane.as_mut() {
+                        match pane.process(&data) {
+                            Ok(()) => self.renderer.mark(Region::Pane),
+                            Err(e) => self.hook_manager.report_background_error(Error::Ui(e.context("Pane error"))),
+                        }
                     }
                 }
-            }
-        }
 
-        self.cleanup().await?;
-        Ok(())
-    }
+                // Show the latest output of the watched command
+                Some(update) = watch_rx.recv() => {
+                    if let Some(watch) = self.watch.as_mut().filter(|watch| watch.id == update.id) {
+                        watch.update(update.lines);
+                        self.renderer.mark(Region::WatchContent);
+                    }
+                }
 
-    async fn handle_terminal_input(&mut self, input_tx: &tokio::sync::mpsc::UnboundedSender<Vec<u8>>) -> Result<()> {
-        // Check for terminal events with a short timeout
-        if self.terminal.poll_event(Duration::from_millis(10))? {
-            match self.terminal.read_event()? {
-                Event::Key(key_event) => {
-                    let key_input = KeyInput::from_event(key_event);
-                    
-                    // Check if any hook should handle this key
-                    match self.hook_manager.process_key(&key_input) {
-                        Ok(true) => {
-                            // Hook consumed the key, don't forward to shell
-                            return Ok(());
+                // More of what streams into a live popup came in
+                Some((id, update)) = popup_rx.recv() => {
+                    if let Err(e) = self.update_live_popup(id, update) {
+                        self.hook_manager.report_background_error(Error::Ui(e));
+                    }
+                }
+
+                // A hook's command finished; keys went to the shell meanwhile
+                Some(result) = command_rx.recv() => {
+                    // Its live popup closes before the result pops up
+                    while let Ok((id, update)) = popup_rx.try_recv() {
+                        if let Err(e) = self.update_live_popup(id, update) {
+                            self.hook_manager.report_background_error(Error::Ui(e));
                         }
-                        Ok(false) => {
-                            // No hook consumed the key, forward to shell
+                    }
+                    if let Err(e) = self.show_command_result(result) {
+                        self.hook_manager.report_error(Error::categorize(e, Error::Plugin));
+                    }
+                    self.apply_session_actions(&input_tx);
+                }
+
+                // More of the LLM's answer for the chat view came in
+                Some(text) = chat_rx.recv() => {
+                    if let Err(e) = self.hook_manager.append_chat(&text) {
+                        self.hook_manager.report_background_error(Error::Ui(e));
+                    }
+                }
+
+                // The LLM answered; keys went to the shell meanwhile
+                Some(reply) = llm_rx.recv() => {
+                    while let Ok((id, update)) = popup_rx.try_recv() {
+                        if let Err(e) = self.update_live_popup(id, update) {
+                            self.hook_manager.report_background_error(Error::Ui(e));
                         }
-                        Err(e) => {
-                            eprintln!("Hook processing error: {}", e);
-                            // Continue and forward to shell
+                    }
+                    while let Ok(text) = chat_rx.try_recv() {
+                        if let Err(e) = self.hook_manager.append_chat(&text) {
+                            self.hook_manager.report_background_error(Error::Ui(e));
                         }
                     }
+                    if let Err(e) = self.show_llm_reply(reply) {
+                        self.hook_manager.report_error(Error::categorize(e, Error::Llm));
+                    }
+                    self.apply_session_actions(&input_tx);
+                }
 
-                    // Forward key to shell
-                    if !key_input.raw_bytes.is_empty() {
-                        input_tx.send(key_input.raw_bytes)?;
+                // A companion said something on its own, or exited
+                Some(message) = companion_rx.recv() => {
+                    if let Err(e) = self.run_companion_message(message) {
+                        self.hook_manager.report_background_error(Error::Pty(e));
                     }
+                    self.apply_session_actions(&input_tx);
                 }
-                Event::Resize(cols, rows) => {
-                    // Resize PTY to match new terminal size
-                    if let Err(e) = self.pty.resize_pty(rows, cols) {
-                        eprintln!("Failed to resize PTY: {}", e);
+
+                // An `interval` or `idle` hook is due
+                _ = tokio::time::sleep_until(next_timer.unwrap_or_else(Instant::now).into()), if next_timer.is_some() => {
+                    if let Err(e) = self.run_timers() {
+                        self.hook_manager.report_background_error(Error::categorize(e, Error::Ui));
                     }
+                    self.apply_session_actions(&input_tx);
                 }
-                _ => {
-                    // Ignore other events (mouse, etc.)
+
+                _ = transcript_flush.tick() => self.hook_manager.flush_transcript(),
+
+                // Check if child process is still alive
+                _ = liveness.tick() => {
+                    for id in self.output.expire_captures(self.clock.now(), CAPTURE_TIMEOUT) {
+                        let _ = self.hook_manager.cancel_capture(id);
+                    }
+                    if self.pane.as_ref().is_some_and(|pane| !pane.is_alive()) {
+                        let _ = self.close_pane();
+                    }
+                    if let Err(e) = self.follow_directory() {
+                        self.hook_manager.report_background_error(Error::categorize(e, Error::Config));
+                    }
+                    if let Err(e) = self.expire_toasts() {
+                        self.hook_manager.report_background_error(Error::Ui(e.context("Failed to draw notes")));
+                    }
+                    if self.resized_at.is_some_and(|at| at.elapsed() >= RESIZE_SETTLE) {
+                        self.resized_at = None;
+                        let env = self.resize_env();
+                        if let Err(e) = self.run_event_hooks(HookEvent::OnResize, &env, &input_tx) {
+                            self.hook_manager.report_background_error(Error::categorize(e, Error::Plugin));
+                        }
+                    }
+                    if !self.backend.is_alive() {
+                        if let Err(e) = self.run_event_hooks(HookEvent::OnExit, &[], &input_tx) {
+                            self.hook_manager.report_background_error(Error::categorize(e, Error::Plugin));
+                        }
+                    }
+                    if !self.backend.is_alive() && !self.handle_shell_exit()? {
+                        let message = match &self.serial {
+                            Some(serial) => tr_args("session-device-gone", &[("device", serial.device.as_str().into())]),
+                            None => tr("session-shell-ended"),
+                        };
+                        println!("\r{}", message);
+                        break;
+                    }
+                }
+            }
+
+            let live_popup_closed = std::mem::take(&mut self.windows.popup_closed);
+            if self.hook_manager.take_popup_closed() || live_popup_closed {
+                // What a popup covered of the pane or chat view is blank now
+                self.renderer.mark(Region::Pane);
+                if self.output.in_alternate_screen() {
+                    self.request_repaint();
+                }
+            }
+            if let Some(pattern) = self.automation.expire(self.clock.now()) {
+                let e = anyhow::anyhow!(tr_args("automation-timeout", &[("pattern", pattern.into())]));
+                self.hook_manager.report_background_error(Error::Config(e));
+            }
+            if !self.startup.is_empty() && self.automation.is_done() {
+                for command in self.startup.take_ready(self.clock.now()) {
+                    self.undoable = unsubmitted_chars(&command);
+                    let _ = input_tx.send(command.into_bytes());
+                }
+            }
+            let now = self.clock.now();
+            self.hook_manager.expire_temporary(now);
+            if let Some(prefix) = self.hook_manager.expire_chord(now) {
+                if let Err(e) = self.forward_key(prefix, &input_tx) {
+                    self.hook_manager.report_background_error(Error::Pty(e));
+                }
+            }
+            let expired = self.key_timing.expire(now);
+            for key_input in expired.into_iter().chain(self.held_keys.release(now, !self.startup.is_empty())) {
+                if let Err(e) = self.handle_key(key_input, &input_tx) {
+                    self.hook_manager.report_background_error(Error::Pty(e));
                 }
             }
         }
+
+        // Commands of on_exit hooks, like one logging the session, finish
+        // before the session ends
+        let deadline = self.clock.now() + EXIT_COMMANDS_GRACE;
+        while self.clock.now() < deadline {
+            self.running_commands.retain(|cancel| !cancel.is_closed());
+            if self.running_commands.is_empty() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+
+        self.cleanup().await?;
         Ok(())
     }
 
-    async fn cleanup(&mut self) -> Result<()> {
-        // Signal the shell to terminate gracefully
-        if self.pty.is_child_alive() {
-            let _ = self.pty.send_signal(Signal::SIGTERM);
-            
-            // Give it a moment to terminate
-            tokio::time::sleep(Duration::from_millis(100)).await;
-            
-            // Force kill if still alive
-            if self.pty.is_child_alive() {
-                let _ = self.pty.send_signal(Signal::SIGKILL);
+    /// True while a program or command has the shell's terminal, as told by
+    /// its foreground process group, the alternate screen or `OSC 133` marks.
+    fn shell_busy(&self) -> bool {
+        !self.backend.in_foreground() || self.output.in_alternate_screen() || self.output.command_running()
+    }
+
+    /// Runs the hooks bound to `key_input` and passes the key on to the
+    /// shell, or the focused pane, unless one of them consumed it.
+    ///
+    /// Keys typed while a hook's input waits for the shell's next prompt
+    /// are handled once it has been sent.
+    fn handle_key(&mut self, key_input: KeyInput, input_tx: &UnboundedSender<Vec<u8>>) -> Result<()> {
+        self.hook_manager.key_pressed(self.clock.now());
+        let Some(key_input) = self.held_keys.hold(key_input) else {
+            return Ok(());
+        };
+        if key_input.code == KeyCode::Esc && key_input.modifiers.is_empty() && self.cancel_commands() {
+            return Ok(());
+        }
+        // The prefix of a chord waits for the key after it
+        let prefix = match self.hook_manager.chord_key(&key_input, self.clock.now()) {
+            ChordKey::Armed => return Ok(()),
+            ChordKey::Repeated(prefix) => return self.forward_key(prefix, input_tx),
+            ChordKey::Broken(prefix) => {
+                self.forward_key(prefix, input_tx)?;
+                None
+            }
+            ChordKey::Complete(prefix) => Some(prefix),
+            ChordKey::Pass => None,
+        };
+
+        // Check if any hook should handle this key
+        let matched = prefix.is_some() || self.hook_manager.matches_any(&key_input);
+        if matched {
+            self.prepare_hooks()?;
+        }
+        let result = match &prefix {
+            Some(prefix) => self.hook_manager.process_chord(prefix, &key_input),
+            None => self.hook_manager.process_key(&key_input),
+        };
+        if matched {
+            self.terminal.pause_key_events(false)?;
+        }
+        let deferred = self.apply_session_actions(input_tx);
+        match result {
+            Ok(true) => {
+                // Hook consumed the key, don't forward to shell, and don't
+                // let later keys overtake what it typed
+                if deferred {
+                    self.held_keys.start(self.clock.now());
+                }
+                return Ok(());
+            }
+            Ok(false) => {
+                // No hook consumed the key, forward to shell
+            }
+            Err(e) => {
+                self.hook_manager.report_error(Error::categorize(e, Error::Plugin));
+                // Continue and forward to shell
             }
         }
 
-        // Restore terminal state
-        self.terminal.leave_raw_mode()?;
+        // A chord whose hooks all passed types both its keys
+        if let Some(prefix) = prefix {
+            self.forward_key(prefix, input_tx)?;
+        }
+        self.forward_key(key_input, input_tx)
+    }
+
+    /// Shows hooks the session as it is now, before they run. Key reports
+    /// stay paused until the caller resumes them.
+    fn prepare_hooks(&mut self) -> Result<()> {
+        self.hook_manager.set_shell_busy(self.shell_busy());
+        self.hook_manager.set_shell_pid(self.backend.pid());
+        self.hook_manager.set_foreground(self.backend.foreground_pid(), self.backend.cwd());
+        self.hook_manager.set_screen(self.output.current_line(), self.output.recent_lines());
+        self.hook_manager.set_snapshots(self.output.snapshots(), self.output.in_alternate_screen());
+        self.hook_manager.set_screen_rows(self.output.screen_rows());
+        self.hook_manager.set_image_regions(self.images.regions());
+        // Popups clear whole rows, notes included
+        self.renderer.mark(Region::Notes);
+        // Popups read keys themselves and expect presses only
+        self.terminal.pause_key_events(true)
+    }
+
+    /// Stops the `cmd:` actions still running. Returns false if there were
+    /// none.
+    fn cancel_commands(&mut self) -> bool {
+        self.running_commands.retain(|cancel| !cancel.is_closed());
+        let cancelled = !self.running_commands.is_empty();
+        for cancel in self.running_commands.drain(..) {
+            let _ = cancel.send(());
+        }
+        cancelled
+    }
+
+    /// Lists the keys of the config that were left out as unknown, which
+    /// are usually typos.
+    fn show_unknown_keys(&mut self) -> Result<()> {
+        if self.unknown_keys.is_empty() {
+            return Ok(());
+        }
+        let mut lines = vec![tr("unknown-keys-intro"), String::new()];
+        for key in std::mem::take(&mut self.unknown_keys) {
+            lines.push(match &key.suggestion {
+                Some(suggestion) => tr_args("unknown-key-suggestion", &[("key", key.path.into()), ("suggestion", suggestion.as_str().into())]),
+                None => key.path,
+            });
+        }
+        self.windows.show_popup(&tr("unknown-keys-title"), &lines.join("\n"))
+    }
+
+    /// Starts a live popup titled `title`, which is drawn as what the
+    /// returned handle is sent comes in.
+    fn open_live_popup(&mut self, title: &str) -> PopupHandle {
+        let id = self.next_popup_id;
+        self.next_popup_id += 1;
+        self.live_popups.insert(id, LivePopup::new(title));
+        PopupHandle::new(id, self.popup_tx.clone())
+    }
+
+    fn update_live_popup(&mut self, id: u64, update: PopupUpdate) -> Result<()> {
+        let Some(popup) = self.live_popups.get_mut(&id) else {
+            return Ok(());
+        };
+        if !popup.apply(update) {
+            return self.windows.draw_live_popup(popup);
+        }
+        let mut popup = self.live_popups.remove(&id).expect("was just found");
+        self.windows.screen_rows = self.output.screen_rows();
+        self.windows.close_live_popup(&mut popup)
+    }
+
+    fn show_command_result(&mut self, result: CommandResult) -> Result<()> {
+        self.prepare_hooks()?;
+        let shown = self.hook_manager.show_command_result(result);
+        self.terminal.pause_key_events(false)?;
+        shown
+    }
+
+    fn show_llm_reply(&mut self, reply: LlmReply) -> Result<()> {
+        self.prepare_hooks()?;
+        let shown = self.hook_manager.llm_reply(reply);
+        self.terminal.pause_key_events(false)?;
+        shown
+    }
+
+    /// Switches to the hooks of the project the shell's directory is in,
+    /// once the shell is back at its prompt after changing it.
+    fn follow_directory(&mut self) -> Result<()> {
+        if self.shell_busy() {
+            return Ok(());
+        }
+        let cwd = self.backend.cwd();
+        if cwd == self.cwd {
+            return Ok(());
+        }
+        self.cwd = cwd;
+        let path = self.cwd.as_deref().and_then(project::find);
+        if path.as_deref() == self.hook_manager.project_path() {
+            return Ok(());
+        }
+        self.prepare_hooks()?;
+        let result = self.hook_manager.use_project(path);
+        self.terminal.pause_key_events(false)?;
+        result
+    }
+
+    /// Runs the `interval` and `idle` hooks that are due.
+    fn run_timers(&mut self) -> Result<()> {
+        self.prepare_hooks()?;
+        self.hook_manager.run_timers(self.clock.now());
+        self.terminal.pause_key_events(false)
+    }
+
+    /// Runs the hooks on `event`, telling their commands `env`.
+    fn run_event_hooks(&mut self, event: HookEvent, env: &[(String, String)], input_tx: &UnboundedSender<Vec<u8>>) -> Result<()> {
+        if self.hook_manager.event_hooks(event).is_empty() {
+            return Ok(());
+        }
+        self.prepare_hooks()?;
+        self.hook_manager.process_event(event, env);
+        self.terminal.pause_key_events(false)?;
+        self.apply_session_actions(input_tx);
         Ok(())
     }
-}
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    let matches = Command::new("chatshell")
-        .version("0.1.0")
-        .about("A transparent shell wrapper with hooks and plugins")
-        .arg(
-            Arg::new("config")
-                .short('c')
-                .long("config")
-                .value_name("FILE")
-                .help("Configuration file path")
-        )
-        .arg(
-            Arg::new("shell")
-                .short('s')
-                .long("shell")
-                .value_name("SHELL")
-                .help("Shell command to run (overrides config)")
-        )
-        .arg(
-            Arg::new("create-config")
-                .long("create-config")
-                .help("Create a default configuration file and exit")
-                .action(clap::ArgAction::SetTrue)
-        )
-        .get_matches();
+    /// What on_start hooks' commands are told.
+    fn start_env(&self) -> Vec<(String, String)> {
+        vec![("CHATSHELL_SHELL".to_string(), self.spawn_config.command.clone())]
+    }
 
-    // Handle create-config option
-    if matches.get_flag("create-config") {
-        let config_path = Config::ensure_config_exists()?;
-        
-        // Also create a config with default hooks
-        let mut config = Config::default();
-        config.hooks = create_default_hooks();
-        config.save_to_file(&config_path)?;
-        
-        println!("Created configuration file at: {}", config_path);
-        println!("Edit this file to customize your shell and hooks.");
-        return Ok(());
+    /// What on_resize hooks' commands are told.
+    fn resize_env(&self) -> Vec<(String, String)> {
+        let (cols, rows) = self.windows.terminal_size;
+        vec![
+            ("CHATSHELL_COLUMNS".to_string(), cols.to_string()),
+            ("CHATSHELL_LINES".to_string(), rows.to_string()),
+        ]
     }
 
-    // Create and run ChatShell
-    let config_path = matches.get_one::<String>("config").cloned();
-    let mut shell = ChatShell::new(config_path).await?;
+    /// Shows the plugins what the shell printed, and runs what they reply.
+    fn run_output_plugins(&mut self, display: &[u8], input_tx: &UnboundedSender<Vec<u8>>) -> Result<()> {
+        let replies = self.hook_manager.plugin_output(&String::from_utf8_lossy(display));
+        if replies.is_empty() {
+            return Ok(());
+        }
+        self.prepare_hooks()?;
+        self.hook_manager.run_plugin_replies(replies);
+        self.terminal.pause_key_events(false)?;
+        self.apply_session_actions(input_tx);
+        Ok(())
+    }
 
-    // Override shell if specified in command line
-    if let Some(shell_cmd) = matches.get_one::<String>("shell") {
-        shell.config.shell.command = shell_cmd.clone();
-        shell.config.shell.args = vec!["-i".to_string()]; // Interactive mode
+    /// Asks whether the plugins and companions that want the shell's
+    /// output may see it.
+    fn ask_output_permissions(&mut self) -> Result<()> {
+        self.prepare_hooks()?;
+        let asked = self.hook_manager.ask_output_permissions();
+        self.terminal.pause_key_events(false)?;
+        asked
     }
 
-    // Run the shell wrapper
-    match shell.run().await {
-        Ok(()) => Ok(()),
-        Err(e) => {
-            eprintln!("ChatShell error: {}", e);
-            std::process::exit(1);
+    fn run_companion_message(&mut self, message: companion::Message) -> Result<()> {
+        self.prepare_hooks()?;
+        self.hook_manager.companion_message(message);
+        self.terminal.pause_key_events(false)
+    }
+
+    /// Passes a key on to the shell, or to the pane while it has focus.
+    fn forward_key(&mut self, key_input: KeyInput, input_tx: &UnboundedSender<Vec<u8>>) -> Result<()> {
+        if key_input.raw_bytes.is_empty() {
+            return Ok(());
         }
+        match self.pane.as_mut().filter(|pane| pane.focused) {
+            Some(pane) => pane.write(&key_input.raw_bytes)?,
+            None => {
+                // Anything typed makes the last input not ours to erase
+                self.undoable = 0;
+                let entered = key_input.raw_bytes.contains(&b'\r');
+                if entered {
+                    self.transcript.command_entered();
+                }
+                // Keys typed into full-screen programs aren't commands
+                if !self.output.in_alternate_screen() {
+                    self.interactions.typed(&key_input.raw_bytes, &self.output.current_line());
+                }
+                if let Some(recorder) = self.recorder.as_mut().filter(|_| !self.output.in_alternate_screen()) {
+                    recorder.key_typed(&self.output.current_line());
+                    if entered {
+                        let _ = recorder.entered(self.backend.cwd());
+                    }
+                }
+                input_tx.send(key_input.raw_bytes)?;
+            }
+        }
+        Ok(())
     }
+
+    async fn handle_terminal_input(&mut self, input_tx: &UnboundedSender<Vec<u8>>) -> Result<()> {
+        // Check for terminal events with a short timeout
+        if self.terminal.poll_event(Duration::from_millis(10))? {
+            match self.terminal.read_event()? {
+                Event::Key(key_event) => {
+                    for key_input in self.key_timing.key_event(key_event, self.clock.now()) {
+                        self.handle_key(key_input, input_tx)?;
+                    }
+                }
+                Event::Resize(cols, rows) => {
+                    self.handle_resize(cols, rows)?;
+                }
+                // Only arrives while the shell has bracketed paste on, as
+                // the terminal marks pastes only then
+                Event::Paste(text) => {
+                    self.send_text(text, true, input_tx)?;
+                }
+                _ => {
+                    // Ignore other events (mouse, etc.)
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Fits the pane, the shell and the watch panel to a terminal of
+    /// `cols` by `rows`, unless it already has that size.
+    fn handle_resize(&mut self, cols: u16, rows: u16) -> Result<()> {
+        if self.windows.terminal_size == (cols, rows) {
+            return Ok(());
+        }
+        self.windows.terminal_size = (cols, rows);
+        self.resized_at = Some(self.clock.now());
+        // Terminals reflow differently; forget where images were
+        self.images = ImageTracker::default();
+        self.images.set_screen_rows(rows);
+        if let Err(e) = self.layout_pane() {
+            self.hook_manager.report_background_error(Error::Ui(e.context("Failed to lay out pane")));
+        }
+        if let Err(e) = self.layout_chat() {
+            self.hook_manager.report_background_error(Error::Ui(e.context("Failed to lay out the chat view")));
+        }
+
+        // Resize PTY to match new terminal size
+        let (cols, rows) = self.shell_area()?;
+        self.output.resize(cols, rows);
+        if let Err(e) = self.backend.resize(rows, cols) {
+            self.hook_manager.report_background_error(Error::Pty(e));
+        }
+        if self.watch.is_some() {
+            // The old panel position no longer means anything
+            self.watch_panel = None;
+            self.draw_watch_panel();
+        }
+        self.note_windows.clear();
+        self.renderer.mark(Region::Notes);
+        Ok(())
+    }
+
+    /// Has the full-screen program in the shell draw itself again, colors
+    /// and all, by narrowing its terminal for a moment. Closed popups only
+    /// put back the text under them.
+    fn request_repaint(&mut self) {
+        let repainted = self.shell_area().and_then(|(cols, rows)| {
+            self.backend.resize(rows, cols.saturating_sub(1).max(1))?;
+            self.backend.resize(rows, cols)
+        });
+        if let Err(e) = repainted {
+            self.hook_manager.report_background_error(Error::Pty(e));
+        }
+    }
+
+    fn spawn_output_reader(&self) -> Result<()> {
+        let reader = self.shell_io.lock()
+            .map_err(|_| anyhow::anyhow!("Shell I/O lock poisoned"))?
+            .clone();
+        let output_tx = self.output_tx.clone();
+
+        tokio::spawn(async move {
+            let mut buffer = [0u8; 4096];
+            loop {
+                match reader.read(&mut buffer).await {
+                    Ok(n) if n > 0 => {
+                        if output_tx.send(buffer[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    _ => {
+                        // EOF or read error - this shell has gone away
+                        break;
+                    }
+                }
+            }
+        });
+
+        Ok(())
+    }
+
+    /// Applies the `on_exit` policy after the shell has exited. Returns true
+    /// if a new shell was started and the session should continue.
+    fn handle_shell_exit(&mut self) -> Result<bool> {
+        // There's nothing to start in place of a device
+        if self.serial.is_some() {
+            return Ok(false);
+        }
+        let respawn = match self.config.shell.on_exit {
+            ExitPolicy::Close => false,
+            ExitPolicy::Respawn => {
+                if self.shell_started.elapsed() < MIN_SHELL_LIFETIME {
+                    // Don't spin on a shell that cannot start
+                    self.terminal.write(b"\r\nShell exited right after starting; not respawning.\r\n")?;
+                    false
+                } else {
+                    true
+                }
+            }
+            ExitPolicy::Prompt => self.confirm_respawn()?,
+        };
+
+        if respawn {
+            let shell_config = self.spawn_config.clone();
+            if let Err(e) = self.start_shell(shell_config) {
+                let Some(veto) = e.downcast_ref::<Veto>() else {
+                    return Err(e);
+                };
+                let message = format!("\nNot starting a new shell: the {} check failed.\n\n{}\n", veto.name, veto.message);
+                // Raw mode doesn't turn \n into \r\n
+                self.terminal.write(message.replace('\n', "\r\n").as_bytes())?;
+                return Ok(false);
+            }
+        }
+        Ok(respawn)
+    }
+
+    fn confirm_respawn(&mut self) -> Result<bool> {
+        self.terminal.write(b"\r\nShell exited. Start a new shell? [y/N] ")?;
+        loop {
+            if let Event::Key(key_event) = self.terminal.read_event()? {
+                if key_event.kind == KeyEventKind::Release {
+                    continue;
+                }
+                let answer = matches!(key_event.code, KeyCode::Char('y') | KeyCode::Char('Y'));
+                self.terminal.write(b"\r\n")?;
+                return Ok(answer);
+            }
+        }
+    }
+
+    /// Starts a new shell in place of the current one, which is terminated
+    /// if it is still running.
+    fn start_shell(&mut self, mut shell_config: ShellConfig) -> Result<()> {
+        let checked_env = prespawn::run_checks(&self.config.pre_spawn, &shell_config.command)?;
+        shell_config.env.get_or_insert_with(Default::default).extend(checked_env);
+        let pty = PtySession::spawn(&shell_config)
+            .with_context(|| "Failed to spawn shell process")?;
+        hatch::set_shell(Some(pty.child_pid.as_raw()));
+        let (cols, rows) = self.shell_area()?;
+        pty.resize_pty(rows, cols)?;
+
+        let io = pty.async_io()?;
+        if let Ok(mut shell_io) = self.shell_io.lock() {
+            *shell_io = io;
+        }
+        self.backend = Box::new(pty);
+        self.serial = None;
+        self.shell_started = self.clock.now();
+        self.dialect = ShellDialect::detect(&shell_config.command);
+        self.hook_manager.set_session_mode(SessionMode::detect(&shell_config.command, shell_config.repl));
+        self.spawn_config = shell_config;
+        self.startup = StartupQueue::new(self.startup_commands.clone(), STARTUP_SETTLE);
+        // Checked when the session started
+        self.automation = StartupScript::new(&self.config.automation.startup).unwrap_or_default();
+
+        self.spawn_output_reader()
+    }
+
+    /// Returns true if some of the input waits for the shell's next prompt.
+    fn apply_session_actions(&mut self, input_tx: &UnboundedSender<Vec<u8>>) -> bool {
+        let mut deferred = false;
+        // An action can queue more, like a hook run from the palette
+        loop {
+            let actions = self.hook_manager.take_actions();
+            if actions.is_empty() {
+                return deferred;
+            }
+            for action in actions {
+                deferred |= matches!(action, SessionAction::SendInputAtPrompt(_));
+                if let Err(e) = self.apply_session_action(action, input_tx) {
+                    let error = Error::categorize(e, Error::Pty).context("Session action failed");
+                    self.hook_manager.report_error(error);
+                }
+            }
+        }
+    }
+
+    /// Types text pasted into the terminal, or that a hook sends, into the
+    /// shell. Text over `paste.large_kb` can be saved to a file instead,
+    /// since typing megabytes through the PTY is slow and some line editors
+    /// fall over; then only the file's path, or a command opening it in
+    /// `$EDITOR`, is typed. ESC sends nothing.
+    fn send_text(&mut self, text: String, pasted: bool, input_tx: &UnboundedSender<Vec<u8>>) -> Result<()> {
+        // A serial device can't open files saved here
+        let mut choice = LargePaste::Type;
+        if self.serial.is_none() && paste::is_large(&text, self.config.paste.large_kb) {
+            let title = tr_args("paste-large", &[("kb", (text.len() / 1024).into())]);
+            let items: Vec<String> = LargePaste::ALL.iter().map(|choice| tr(choice.message())).collect();
+            match self.windows.show_menu(&title, &items)? {
+                Some(index) => choice = LargePaste::ALL[index],
+                None => return Ok(()),
+            }
+        }
+        let text = match choice {
+            LargePaste::Type if pasted && self.output.bracketed_paste() => paste::bracketed(&text),
+            LargePaste::Type => text,
+            LargePaste::Path => self.dialect.quote(&paste::save(&paste::paste_dir(), &text)?.to_string_lossy()),
+            LargePaste::Editor => self.dialect.editor_line(&paste::save(&paste::paste_dir(), &text)?),
+        };
+        self.undoable = unsubmitted_chars(&text);
+        input_tx.send(text.into_bytes())?;
+        Ok(())
+    }
+
+    fn apply_session_action(&mut self, action: SessionAction, input_tx: &UnboundedSender<Vec<u8>>) -> Result<()> {
+        match action {
+            SessionAction::SetEnv { key, value } => {
+                self.undoable = 0;
+                input_tx.send(self.dialect.export_line(&key, &value).into_bytes())?;
+            }
+            SessionAction::SwitchShell(mut shell_config) => {
+                // The new shell keeps the session's control socket
+                let socket = self.spawn_config.env.as_ref()
+                    .and_then(|env| env.get(control::SOCKET_ENV_VAR))
+                    .cloned();
+                if let Some(socket) = socket {
+                    shell_config.env.get_or_insert_with(Default::default)
+                        .insert(control::SOCKET_ENV_VAR.to_string(), socket);
+                }
+                self.startup_commands = shell_config.startup_commands.clone();
+                self.start_shell(shell_config)?;
+            }
+            SessionAction::SendInput(text) => {
+                self.send_text(text, false, input_tx)?;
+            }
+            SessionAction::Paste(text) => {
+                self.send_text(text, true, input_tx)?;
+            }
+            SessionAction::SendInputAtPrompt(text) => {
+                self.startup.push_after_output(text);
+            }
+            SessionAction::UndoInput => {
+                let count = std::mem::take(&mut self.undoable);
+                if count > 0 {
+                    input_tx.send("\x7f".repeat(count).into_bytes())?;
+                }
+            }
+            SessionAction::Capture { id, input } => {
+                self.output.begin_capture(id);
+                self.undoable = 0;
+                input_tx.send(input.into_bytes())?;
+            }
+            SessionAction::Watch { command, interval } => {
+                let id = self.next_watch_id;
+                self.next_watch_id += 1;
+                self.watch = Some(Watch::start(id, command, interval, self.watch_tx.clone()));
+            }
+            SessionAction::StopWatch => {
+                self.watch = None;
+                if let Some(panel) = self.watch_panel.take() {
+                    self.windows.clear_panel(&panel)?;
+                }
+            }
+            SessionAction::Bookmark(name) => {
+                self.interactions.bookmark(name);
+            }
+            SessionAction::RunCommand(command) => {
+                let popup = command.streams().then(|| {
+                    let title = tr_args("command-title", &[("command", command.command.as_str().into())]);
+                    self.open_live_popup(&title)
+                });
+                let command_tx = self.command_tx.clone();
+                let (cancel_tx, cancel_rx) = tokio::sync::oneshot::channel::<()>();
+                self.running_commands.push(cancel_tx);
+                tokio::spawn(async move {
+                    let cancelled = async {
+                        if cancel_rx.await.is_err() {
+                            std::future::pending::<()>().await;
+                        }
+                    };
+                    let result = command.run_until(cancelled, popup.as_ref()).await;
+                    if let Some(popup) = popup {
+                        popup.finish();
+                    }
+                    let _ = command_tx.send(result);
+                });
+            }
+            SessionAction::AskLlm(request) => {
+                let chat = ChatHandle::new(self.chat_tx.clone());
+                let popup = (!request.in_chat).then(|| self.open_live_popup(&request.title));
+                let llm_tx = self.llm_tx.clone();
+                // A thread rather than the blocking pool, which would hold
+                // up exit until the LLM answers
+                std::thread::spawn(move || {
+                    let reply = request.run(|text| match &popup {
+                        Some(popup) => popup.append(text),
+                        None => chat.append(text),
+                    });
+                    if let Some(popup) = popup {
+                        popup.finish();
+                    }
+                    let _ = llm_tx.send(reply);
+                });
+            }
+            SessionAction::OpenPane => {
+                self.open_pane()?;
+            }
+            SessionAction::FocusPane => {
+                if let Some(pane) = self.pane.as_mut() {
+                    pane.focused = !pane.focused;
+                    pane.draw()?;
+                }
+            }
+            SessionAction::ClosePane => {
+                self.close_pane()?;
+            }
+            SessionAction::ToggleChat => {
+                match self.hook_manager.chat_layout() {
+                    Some(_) => self.close_chat()?,
+                    None => self.open_chat()?,
+                }
+            }
+            SessionAction::ShowInPane { title, text } => {
+                self.open_pane()?;
+                if let Some(pane) = self.pane.as_mut() {
+                    pane.print(&title, &text)?;
+                    pane.draw()?;
+                }
+            }
+            SessionAction::PinNote(note) => {
+                self.pin_note(note)?;
+            }
+            SessionAction::MoveNote => {
+                self.move_note()?;
+            }
+            SessionAction::CloseNote => {
+                if self.notes.pop().is_some() {
+                    self.redraw_notes()?;
+                }
+            }
+            SessionAction::BindTemporary { key, action, lifetime } => {
+                self.hook_manager.bind_temporary(&key, action, lifetime.map(|lifetime| self.clock.now() + lifetime));
+            }
+            SessionAction::ManageHooks => {
+                self.prepare_hooks()?;
+                let managed = self.hook_manager.manage_hooks();
+                self.terminal.pause_key_events(false)?;
+                managed?;
+            }
+            SessionAction::ShowPalette => {
+                self.prepare_hooks()?;
+                let picked = self.hook_manager.show_palette();
+                self.terminal.pause_key_events(false)?;
+                picked?;
+            }
+            SessionAction::ShowHelp(output) => {
+                self.prepare_hooks()?;
+                let shown = self.hook_manager.show_help(&output);
+                self.terminal.pause_key_events(false)?;
+                shown?;
+            }
+            SessionAction::CopyMode => {
+                self.prepare_hooks()?;
+                let comments = self.hook_manager.copy_mode(self.output.scrollback(), &self.annotations);
+                self.terminal.pause_key_events(false)?;
+                for (range, comment) in comments? {
+                    self.annotate(range, comment);
+                }
+            }
+            SessionAction::SearchScrollback => {
+                self.prepare_hooks()?;
+                let searched = self.hook_manager.search_scrollback(self.output.scrollback());
+                self.terminal.pause_key_events(false)?;
+                searched?;
+            }
+        }
+        Ok(())
+    }
+
+    /// The height of the tallest of `images`, if all of them have a known
+    /// size.
+    fn image_rows(&self, images: &[InlineImage]) -> Option<u16> {
+        let cell_height = images::cell_height();
+        let screen_rows = self.windows.terminal_size.1;
+        images
+            .iter()
+            .map(|image| image.height_in_rows(cell_height, screen_rows))
+            .collect::<Option<Vec<u16>>>()?
+            .into_iter()
+            .max()
+    }
+
+    /// Remembers the rows taken by images just written, which end on the
+    /// cursor's row as the output processor followed it. The shell's part
+    /// of the screen starts at the top, so its rows are the terminal's.
+    fn track_images(&mut self, height: Option<u16>) {
+        let (_, after) = self.output.cursor();
+        let top = match height {
+            Some(height) => after.saturating_sub(height),
+            // Without a size, the image may reach the top
+            None => 0,
+        };
+        self.images.add(top, after);
+    }
+
+    /// The size of the shell's part of the screen, as `(cols, rows)`.
+    fn shell_area(&self) -> Result<(u16, u16)> {
+        match self.split_layout() {
+            Some(layout) => Ok((layout.main.width, layout.main.height)),
+            None => self.terminal.size(),
+        }
+    }
+
+    /// How the screen is shared with the pane or the chat view, whichever
+    /// is open. They take turns at the one split.
+    fn split_layout(&self) -> Option<Layout> {
+        self.pane.as_ref().map(|pane| pane.layout).or(self.hook_manager.chat_layout())
+    }
+
+    fn open_pane(&mut self) -> Result<()> {
+        if self.pane.is_some() {
+            return Ok(());
+        }
+        self.close_chat()?;
+        let size = self.terminal.size()?;
+        let layout = Layout::split(size, self.config.pane.split, self.config.pane.size)
+            .with_context(|| "The terminal is too small to split")?;
+        let shell_config = self.config.pane.shell_config(&self.spawn_config);
+        let mut pane = Pane::open(&shell_config, &self.config.terminal, layout, self.pane_tx.clone())?;
+        pane.style = self.windows.style;
+
+        // Scroll the shell's text out of the way of the pane, keeping the
+        // cursor on the same text line
+        if layout.pane.y > 0 {
+            let lines = layout.pane.height;
+            let scroll = format!("{}\x1b[{}A", "\n".repeat(lines as usize), lines);
+            self.terminal.write(scroll.as_bytes())?;
+        }
+        self.terminal.write(layout.confine_sequence().as_bytes())?;
+        self.backend.resize(layout.main.height, layout.main.width)?;
+        self.output.resize(layout.main.width, layout.main.height);
+        pane.draw()?;
+        self.pane = Some(pane);
+        Ok(())
+    }
+
+    fn close_pane(&mut self) -> Result<()> {
+        let Some(pane) = self.pane.take() else {
+            return Ok(());
+        };
+        self.terminal.write(pane.layout.release_sequence().as_bytes())?;
+        pane.clear()?;
+        let (cols, rows) = self.terminal.size()?;
+        self.output.resize(cols, rows);
+        self.backend.resize(rows, cols)
+    }
+
+    /// Fits the pane to a resized terminal, closing it if there is no
+    /// longer room for it.
+    fn layout_pane(&mut self) -> Result<()> {
+        let Some(pane) = self.pane.as_mut() else {
+            return Ok(());
+        };
+        let size = self.terminal.size()?;
+        match Layout::split(size, self.config.pane.split, self.config.pane.size) {
+            Some(layout) => {
+                pane.set_layout(layout)?;
+                self.terminal.write(layout.confine_sequence().as_bytes())?;
+                pane.draw()
+            }
+            None => self.close_pane(),
+        }
+    }
+
+    /// Shows the conversation with the assistant in a column right of the
+    /// shell, which gets the rest of the width, closing the pane first.
+    fn open_chat(&mut self) -> Result<()> {
+        self.close_pane()?;
+        let size = self.terminal.size()?;
+        let layout = Layout::split(size, SplitDirection::Vertical, self.config.llm.chat_size)
+            .with_context(|| "The terminal is too small to split")?;
+        self.terminal.write(layout.confine_sequence().as_bytes())?;
+        self.backend.resize(layout.main.height, layout.main.width)?;
+        self.output.resize(layout.main.width, layout.main.height);
+        self.hook_manager.set_chat_layout(Some(layout))
+    }
+
+    fn close_chat(&mut self) -> Result<()> {
+        let Some(layout) = self.hook_manager.chat_layout() else {
+            return Ok(());
+        };
+        self.terminal.write(layout.release_sequence().as_bytes())?;
+        self.hook_manager.set_chat_layout(None)?;
+        let (cols, rows) = self.terminal.size()?;
+        self.output.resize(cols, rows);
+        self.backend.resize(rows, cols)
+    }
+
+    /// Fits the chat view to a resized terminal, closing it if there is no
+    /// longer room for it.
+    fn layout_chat(&mut self) -> Result<()> {
+        if self.hook_manager.chat_layout().is_none() {
+            return Ok(());
+        }
+        let size = self.terminal.size()?;
+        match Layout::split(size, SplitDirection::Vertical, self.config.llm.chat_size) {
+            Some(layout) => {
+                self.terminal.write(layout.confine_sequence().as_bytes())?;
+                self.hook_manager.set_chat_layout(Some(layout))
+            }
+            None => self.close_chat(),
+        }
+    }
+
+    /// Draws what changed in the pane and watch panel, if a frame is due.
+    fn draw_frame(&mut self) {
+        let Some(regions) = self.renderer.take_frame(self.clock.now()) else {
+            return;
+        };
+        for region in regions {
+            match region {
+                Region::Pane => {
+                    if let Some(Err(e)) = self.pane.as_ref().map(Pane::draw) {
+                        self.hook_manager.report_background_error(Error::Ui(e.context("Pane error")));
+                    }
+                    if let Err(e) = self.hook_manager.draw_chat() {
+                        self.hook_manager.report_background_error(Error::Ui(e.context("Failed to draw the chat view")));
+                    }
+                }
+                Region::WatchPanel => self.draw_watch_panel(),
+                Region::WatchContent => self.refresh_watch_panel(),
+                Region::Notes => {
+                    if let Err(e) = self.draw_notes() {
+                        self.hook_manager.report_background_error(Error::Ui(e.context("Failed to draw notes")));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Shows a new output of the watched command, redrawing only the lines
+    /// that changed if `delta_refresh` is on.
+    fn refresh_watch_panel(&mut self) {
+        let low_bandwidth = &self.config.low_bandwidth;
+        let (Some(watch), Some(previous)) = (&self.watch, &self.watch_panel) else {
+            return self.draw_watch_panel();
+        };
+        if !(low_bandwidth.enabled && low_bandwidth.delta_refresh) {
+            return self.draw_watch_panel();
+        }
+        match self.windows.update_panel(previous, &watch.title(), watch.panel_lines(false)) {
+            // A smaller panel leaves some of the old one to blank
+            Ok(panel) if panel.height < previous.height || panel.width < previous.width => self.draw_watch_panel(),
+            Ok(panel) => self.watch_panel = Some(panel),
+            Err(e) => self.hook_manager.report_background_error(Error::Ui(e.context("Failed to draw watch panel"))),
+        }
+    }
+
+    fn draw_watch_panel(&mut self) {
+        let Some(watch) = &self.watch else {
+            return;
+        };
+        // Change highlights are left out on a slow link
+        let lines = || watch.panel_lines(!self.config.low_bandwidth.enabled);
+        let result = self.windows.draw_panel(&watch.title(), lines()).and_then(|panel| {
+            // Blank whatever the previous, larger panel covered
+            if let Some(previous) = self.watch_panel.take() {
+                if previous.height > panel.height || previous.width > panel.width {
+                    self.windows.clear_panel(&previous)?;
+                    self.windows.draw_panel(&watch.title(), lines())?;
+                }
+            }
+            Ok(panel)
+        });
+        match result {
+            Ok(panel) => self.watch_panel = Some(panel),
+            Err(e) => self.hook_manager.report_background_error(Error::Ui(e.context("Failed to draw watch panel"))),
+        }
+    }
+
+    /// The corners no note or the watch panel is in, clockwise from the
+    /// top right.
+    fn free_corners(&self) -> Vec<Corner> {
+        let watch = self.watch.is_some().then_some(Corner::TopRight);
+        let taken: Vec<Corner> = self.notes.iter().map(|(_, corner)| *corner).chain(watch).collect();
+        Corner::ALL.into_iter().filter(|corner| !taken.contains(corner)).collect()
+    }
+
+    /// Draws `note` in a free corner, taking the oldest toast's, or else
+    /// the oldest note's, when none is left.
+    fn pin_note(&mut self, note: Note) -> Result<()> {
+        let oldest = self.notes.iter().position(|(note, _)| note.expires.is_some()).unwrap_or(0);
+        let corner = match self.free_corners().first() {
+            Some(corner) => *corner,
+            None if self.notes.is_empty() => Corner::BottomRight,
+            None => self.notes.remove(oldest).1,
+        };
+        self.notes.push((note, corner));
+        self.redraw_notes()
+    }
+
+    /// Moves the newest note to the next free corner clockwise.
+    fn move_note(&mut self) -> Result<()> {
+        let free = self.free_corners();
+        let Some((_, corner)) = self.notes.last_mut() else {
+            return Ok(());
+        };
+        let mut next = corner.next();
+        while next != *corner && !free.contains(&next) {
+            next = next.next();
+        }
+        *corner = next;
+        self.redraw_notes()
+    }
+
+    /// Takes down the toasts whose time is up.
+    fn expire_toasts(&mut self) -> Result<()> {
+        let now = self.clock.now();
+        if !self.notes.iter().any(|(note, _)| note.expired(now)) {
+            return Ok(());
+        }
+        self.notes.retain(|(note, _)| !note.expired(now));
+        self.redraw_notes()
+    }
+
+    /// Blanks the notes where they were and draws them where they are now.
+    fn redraw_notes(&mut self) -> Result<()> {
+        for window in std::mem::take(&mut self.note_windows) {
+            self.windows.clear_panel(&window)?;
+        }
+        self.draw_notes()
+    }
+
+    fn draw_notes(&mut self) -> Result<()> {
+        self.note_windows = self
+            .notes
+            .iter()
+            .map(|(note, corner)| self.windows.draw_note(note, *corner))
+            .collect::<Result<_>>()?;
+        Ok(())
+    }
+
+    /// Keeps a comment made in copy mode on the scrollback lines `range`,
+    /// in the transcript for `chatshell export` and in the history.
+    fn annotate(&mut self, range: Range<usize>, comment: String) {
+        let first = self.output.scrollback().first_line();
+        let lines = self.output.scrollback().lines()
+            .range(range.start.saturating_sub(first)..range.end.saturating_sub(first))
+            .cloned()
+            .collect();
+        let annotation = Annotation { at: self.clock.local().timestamp(), session: String::new(), lines, comment };
+        self.hook_manager.transcribe(&transcript::Entry::Annotation {
+            at: annotation.at,
+            lines: annotation.lines.clone(),
+            comment: annotation.comment.clone(),
+        });
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.annotate(&annotation) {
+                self.hook_manager.report_background_error(Error::Config(e));
+            }
+        }
+        self.interactions.annotate(annotation);
+        self.annotations.push(range);
+    }
+
+    /// What `chatshell state save` keeps of the session.
+    fn session_state(&self) -> SessionState {
+        let now = self.clock.now();
+        let hooks = self.hook_manager.list_hooks().into_iter()
+            .filter(|hook| !self.hook_manager.is_temporary(&hook.name))
+            .map(|hook| (hook.name.clone(), hook.enabled))
+            .collect();
+        let bindings = self.hook_manager.temporary_bindings().into_iter()
+            .map(|(hook, expires)| SavedBinding {
+                key: hook.key_combination.clone(),
+                action: hook.action.clone(),
+                remaining_secs: expires.map(|expires| expires.saturating_duration_since(now).as_secs().max(1)),
+            })
+            .collect();
+        let notes = self.notes.iter()
+            .filter(|(note, _)| note.expires.is_none())
+            .map(|(note, _)| SavedNote {
+                title: note.title.clone(),
+                lines: note.lines.iter().map(|(line, _)| line.clone()).collect(),
+            })
+            .collect();
+        let watch = self.watch.as_ref().map(|watch| SavedWatch {
+            command: watch.command.clone(),
+            interval_ms: watch.interval.as_millis() as u64,
+        });
+        let chat = self.hook_manager.chat_messages().into_iter()
+            .map(|message| SavedMessage { question: message.question, answer: message.answer })
+            .collect();
+        SessionState { hooks, bindings, notes, watch, chat }
+    }
+
+    /// Brings back a saved state on top of the session: hooks are turned
+    /// on or off for the session only, and the notes join those pinned
+    /// now. Returns what was done, in a line.
+    fn restore_state(&mut self, state: SessionState) -> Result<String> {
+        let mut summary = state.summary();
+        let missing: Vec<&str> = state.hooks.iter()
+            .filter(|(name, enabled)| !self.hook_manager.set_enabled(name, **enabled))
+            .map(|(name, _)| name.as_str())
+            .collect();
+        if !missing.is_empty() {
+            summary.push_str(&format!("; no longer configured: {}", missing.join(", ")));
+        }
+        let now = self.clock.now();
+        for binding in state.bindings {
+            let expires = binding.remaining_secs.map(|secs| now + Duration::from_secs(secs));
+            self.hook_manager.bind_temporary(&binding.key, binding.action, expires);
+        }
+        for note in state.notes {
+            let lines = note.lines.into_iter().map(|line| (line, None)).collect();
+            self.pin_note(Note { title: note.title, lines, expires: None })?;
+        }
+        if let Some(watch) = state.watch {
+            let id = self.next_watch_id;
+            self.next_watch_id += 1;
+            let interval = Duration::from_millis(watch.interval_ms);
+            self.watch = Some(Watch::start(id, watch.command, interval, self.watch_tx.clone()));
+        }
+        let chat = state.chat.into_iter().map(|message| ChatMessage { question: message.question, answer: message.answer });
+        self.hook_manager.restore_chat(chat)?;
+        Ok(summary)
+    }
+
+    fn handle_control_request(&mut self, request: ControlRequest, input_tx: &UnboundedSender<Vec<u8>>) -> ControlResponse {
+        let action = match request {
+            ControlRequest::SetEnv { key, value } => {
+                if !is_valid_env_name(&key) {
+                    return ControlResponse::error(format!("Invalid variable name: {}", key));
+                }
+                SessionAction::SetEnv { key, value }
+            }
+            ControlRequest::Bookmark { name } => {
+                let name = self.interactions.bookmark(name);
+                return ControlResponse::with_message(format!("Bookmark {}", name));
+            }
+            ControlRequest::Export { from, to } => {
+                return match self.interactions.segment(from.as_deref(), to.as_deref()) {
+                    Ok(steps) => ControlResponse::with_message(expect::expect_script(&steps)),
+                    Err(e) => ControlResponse::error(e.to_string()),
+                };
+            }
+            ControlRequest::Trigger { key } => {
+                let Some(key_input) = KeyInput::parse(&key) else {
+                    return ControlResponse::error(format!("Not a key combination: {}", key));
+                };
+                let hooks = self.hook_manager.matching_hooks(&key_input).join(", ");
+                if hooks.is_empty() {
+                    return ControlResponse::error(format!("No enabled hook is bound to {}", key));
+                }
+                return match self.handle_key(key_input, input_tx) {
+                    Ok(()) => ControlResponse::with_message(format!("{}: {}", key, hooks)),
+                    Err(e) => ControlResponse::error(e.to_string()),
+                };
+            }
+            ControlRequest::Bind { key, action, lifetime_secs } => {
+                if KeyInput::parse(&key).is_none() {
+                    return ControlResponse::error(format!("Not a key combination: {}", key));
+                }
+                let lifetime = lifetime_secs.map(Duration::from_secs);
+                let name = self.hook_manager.bind_temporary(&key, action.as_str(), lifetime.map(|lifetime| self.clock.now() + lifetime));
+                let until = match lifetime {
+                    Some(lifetime) => {
+                        let until = self.clock.local() + chrono::Duration::from_std(lifetime).unwrap_or_default();
+                        format!("until {}", until.format("%H:%M"))
+                    }
+                    None => "for this session".to_string(),
+                };
+                return ControlResponse::with_message(format!("{}: {} {}", name, action, until));
+            }
+            ControlRequest::SaveState { name } => {
+                let state = self.session_state();
+                return match state.save(&self.states_dir, &name) {
+                    Ok(path) => ControlResponse::with_message(format!("Saved {} to {}: {}", name, path.display(), state.summary())),
+                    Err(e) => ControlResponse::error(format!("{:#}", e)),
+                };
+            }
+            ControlRequest::LoadState { name } => {
+                return match SessionState::load(&self.states_dir, &name).and_then(|state| self.restore_state(state)) {
+                    Ok(summary) => ControlResponse::with_message(format!("Loaded {}: {}", name, summary)),
+                    Err(e) => ControlResponse::error(format!("{:#}", e)),
+                };
+            }
+            ControlRequest::TriggerEvent { event, command_line, exit_code } => {
+                let name = event.name();
+                let hooks = self.hook_manager.event_hooks(event).join(", ");
+                if hooks.is_empty() {
+                    return ControlResponse::error(format!("No enabled hook runs on {}", name));
+                }
+                let command = command_line.unwrap_or_default();
+                let env = match event {
+                    HookEvent::OnCommandStart => CommandMark::Started { command }.env(),
+                    HookEvent::OnCommandEnd => CommandMark::Finished { command, exit_code, duration: Duration::ZERO }.env(),
+                    HookEvent::OnStart => self.start_env(),
+                    HookEvent::OnExit => Vec::new(),
+                    HookEvent::OnResize => self.resize_env(),
+                };
+                return match self.run_event_hooks(event, &env, input_tx) {
+                    Ok(()) => ControlResponse::with_message(format!("{}: {}", name, hooks)),
+                    Err(e) => ControlResponse::error(e.to_string()),
+                };
+            }
+        };
+
+        match self.apply_session_action(action, input_tx) {
+            Ok(()) => ControlResponse::ok(),
+            Err(e) => ControlResponse::error(e.to_string()),
+        }
+    }
+
+    async fn cleanup(&mut self) -> Result<()> {
+        // Signal the shell to terminate gracefully
+        self.backend.terminate();
+        self.hook_manager.stop_companions();
+        self.hook_manager.flush_transcript();
+
+        // Restore terminal state
+        self.terminal.pause_key_events(true)?;
+        self.terminal.leave_raw_mode()?;
+
+        if self.config.journal.summarize_on_exit && !self.transcript.is_empty() {
+            println!("{}", tr_args("session-summarizing", &[("path", self.config.journal.path.as_str().into())]));
+            if let Err(e) = journal::write_summary(&self.config, &self.transcript) {
+                eprintln!("{:#}", e);
+            }
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let matches = Command::new("chatshell")
+        .version("0.1.0")
+        .about("A transparent shell wrapper with hooks and plugins")
+        .arg(
+            Arg::new("config")
+                .short('c')
+                .long("config")
+                .value_name("FILE")
+                .help("Configuration file path")
+        )
+        .arg(
+            Arg::new("shell")
+                .short('s')
+                .long("shell")
+                .value_name("SHELL")
+                .help("Shell command to run (overrides config)")
+        )
+        .arg(
+            Arg::new("serial")
+                .long("serial")
+                .value_name("DEVICE")
+                .conflicts_with("shell")
+                .help("Serial device to open instead of a shell, e.g. /dev/ttyUSB0")
+        )
+        .arg(
+            Arg::new("baud")
+                .long("baud")
+                .value_name("RATE")
+                .requires("serial")
+                .value_parser(clap::value_parser!(u32))
+                .default_value("115200")
+                .help("Speed of the serial device")
+        )
+        .arg(
+            Arg::new("profile")
+                .short('p')
+                .long("profile")
+                .value_name("NAME")
+                .help("Profile whose startup commands to run")
+        )
+        .arg(
+            Arg::new("session")
+                .long("session")
+                .value_name("NAME")
+                .default_value(notepad::DEFAULT_SESSION)
+                .help("Name of the session, whose notes builtin:notes keeps")
+        )
+        .arg(
+            Arg::new("plain")
+                .long("plain")
+                .visible_alias("no-ui")
+                .help("Draw popups without colors or box drawing and print notifications as plain lines")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("low-bandwidth")
+                .long("low-bandwidth")
+                .help("Draw less, for slow or high-latency links (see [low_bandwidth] in the config)")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("single-thread")
+                .long("single-thread")
+                .help("Run everything on one thread, for small machines and containers limited to one CPU")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .arg(
+            Arg::new("create-config")
+                .long("create-config")
+                .help("Create a default configuration file and exit")
+                .action(clap::ArgAction::SetTrue)
+        )
+        .subcommand(
+            Command::new("env")
+                .about("Manage environment variables of a running session")
+                .subcommand_required(true)
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Control socket of the session (defaults to $CHATSHELL_SOCKET)")
+                )
+                .subcommand(
+                    Command::new("set")
+                        .about("Export variables into the running shell")
+                        .arg(
+                            Arg::new("assignments")
+                                .value_name("KEY=VALUE")
+                                .required(true)
+                                .num_args(1..)
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("bookmark")
+                .about("Mark the current point of a running session for export")
+                .arg(Arg::new("name").value_name("NAME").help("Name of the bookmark (numbered if left out)"))
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Control socket of the session (defaults to $CHATSHELL_SOCKET)")
+                )
+        )
+        .subcommand(
+            Command::new("export")
+                .about("Print an expect script of what was typed between two bookmarks, or a session's transcript")
+                .arg(
+                    Arg::new("session")
+                        .value_name("SESSION")
+                        .help("Session whose transcript to print, as named with --session (needs [history] transcripts = true)")
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(clap::builder::PossibleValuesParser::new(std::iter::once("expect").chain(transcript::FORMATS)))
+                        .help("expect for the running session's bookmarks (the default without SESSION); html, markdown or text (the default) for a transcript")
+                )
+                .arg(
+                    Arg::new("from")
+                        .long("from")
+                        .value_name("BOOKMARK")
+                        .help("Bookmark the steps start at (defaults to the second to last)")
+                )
+                .arg(
+                    Arg::new("to")
+                        .long("to")
+                        .value_name("BOOKMARK")
+                        .help("Bookmark the steps end at (defaults to the last, or now)")
+                )
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Control socket of the session (defaults to $CHATSHELL_SOCKET)")
+                )
+        )
+        .subcommand(
+            Command::new("trigger")
+                .about("Run the hooks bound to a key or event in a running session, as if it happened")
+                .arg(
+                    Arg::new("key")
+                        .long("key")
+                        .value_name("KEYS")
+                        .required_unless_present("event")
+                        .conflicts_with("event")
+                        .help("Key combination, written as in the config, e.g. ctrl+shift+l")
+                )
+                .arg(
+                    Arg::new("event")
+                        .long("event")
+                        .value_name("NAME")
+                        .value_parser(HookEvent::ALL.map(HookEvent::name))
+                        .help("Event whose hooks to run")
+                )
+                .arg(
+                    Arg::new("command")
+                        .long("command")
+                        .value_name("COMMAND")
+                        .requires("event")
+                        .help("Command line the event is about")
+                )
+                .arg(
+                    Arg::new("exit-code")
+                        .long("exit-code")
+                        .value_name("N")
+                        .value_parser(clap::value_parser!(i32))
+                        .allow_negative_numbers(true)
+                        .requires("event")
+                        .help("Exit status of the finished command")
+                )
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Control socket of the session (defaults to $CHATSHELL_SOCKET)")
+                )
+        )
+        .subcommand(
+            Command::new("bind")
+                .about("Bind a key to an action in a running session, without saving it to the config")
+                .arg(Arg::new("key").value_name("KEYS").required(true).help("Key combination, e.g. f9 or ctrl+alt+d"))
+                .arg(Arg::new("action").value_name("ACTION").required(true).help("Action, written as in the config"))
+                .arg(
+                    Arg::new("for")
+                        .long("for")
+                        .value_name("DURATION")
+                        .help("How long to keep it, e.g. 30m or 1h (defaults to the rest of the session)")
+                )
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .help("Control socket of the session (defaults to $CHATSHELL_SOCKET)")
+                )
+        )
+        .subcommand(
+            Command::new("state")
+                .about("Save what a running session has set up, or bring it back")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("save")
+                        .about("Save the hooks turned on or off, temporary bindings, pinned notes and watch")
+                        .arg(Arg::new("name").value_name("NAME").required(true))
+                )
+                .subcommand(
+                    Command::new("load")
+                        .about("Bring back a saved state, leaving the shell as it is")
+                        .arg(Arg::new("name").value_name("NAME").required(true))
+                )
+                .arg(
+                    Arg::new("socket")
+                        .long("socket")
+                        .value_name("PATH")
+                        .global(true)
+                        .help("Control socket of the session (defaults to $CHATSHELL_SOCKET)")
+                )
+        )
+        .subcommand(
+            Command::new("init")
+                .about("Write the ssh wrapper that tells remote shells they run under chatshell")
+                .arg(
+                    Arg::new("dir")
+                        .long("dir")
+                        .value_name("DIR")
+                        .help("Directory to write it to (defaults to ~/.local/share/chatshell/bin)")
+                )
+        )
+        .subcommand(
+            Command::new("report")
+                .about("Summarize usage from the history database")
+                .arg(
+                    Arg::new("since")
+                        .long("since")
+                        .value_name("PERIOD")
+                        .default_value("7d")
+                        .help("Start of the period: a span such as 12h, 7d or 2w, or a date")
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(["text", "json"])
+                        .default_value("text")
+                        .help("Output format")
+                )
+        )
+        .subcommand(
+            Command::new("hooks")
+                .about("Install or remove hook packs, or test key bindings")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("add")
+                        .about("Fetch a hook pack from a git URL or the registry, show its keys and install it")
+                        .arg(Arg::new("source").value_name("URL_OR_NAME").required(true))
+                        .arg(
+                            Arg::new("rev")
+                                .long("rev")
+                                .value_name("REV")
+                                .help("Tag, branch or commit to install")
+                        )
+                        .arg(
+                            Arg::new("sha256")
+                                .long("sha256")
+                                .value_name("HEX")
+                                .help("Checksum the pack must have")
+                        )
+                        .arg(
+                            Arg::new("verify-signature")
+                                .long("verify-signature")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Require a commit signed by a key git trusts")
+                        )
+                        .arg(
+                            Arg::new("yes")
+                                .long("yes")
+                                .short('y')
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Install without asking")
+                        )
+                )
+                .subcommand(
+                    Command::new("remove")
+                        .about("Uninstall a hook pack")
+                        .arg(Arg::new("name").value_name("NAME").required(true))
+                )
+                .subcommand(
+                    Command::new("test")
+                        .about("Show which hooks a key would run, without a session")
+                        .arg(Arg::new("key").value_name("KEYS").required(true).help("Key combination, e.g. ctrl+shift+l or ctrl+a t"))
+                        .arg(
+                            Arg::new("run")
+                                .long("run")
+                                .action(clap::ArgAction::SetTrue)
+                                .help("Run the cmd: steps of the hook that fires and print what they print")
+                        )
+                )
+        )
+        .subcommand(
+            Command::new("cheatsheet")
+                .about("Write the keys bound by the presets, hook packs and config, and what each does")
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .value_name("FORMAT")
+                        .value_parser(cheatsheet::FORMATS)
+                        .default_value("md")
+                        .help("Output format")
+                )
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .short('o')
+                        .value_name("FILE")
+                        .help("File to write it to (defaults to stdout)")
+                )
+        )
+        .subcommand(
+            Command::new("bugreport")
+                .about("Write a tarball with the debug log, redacted config and terminal details for a bug report")
+                .arg(
+                    Arg::new("output")
+                        .long("output")
+                        .value_name("DIR")
+                        .help("Directory to write it to (defaults to the current one)")
+                )
+        )
+        .get_matches();
+
+    let mut runtime = if matches.get_flag("single-thread") {
+        tokio::runtime::Builder::new_current_thread()
+    } else {
+        tokio::runtime::Builder::new_multi_thread()
+    };
+    let runtime = runtime.enable_all().build().context("Failed to start the async runtime")?;
+    runtime.block_on(run(matches))
+}
+
+async fn run(matches: clap::ArgMatches) -> Result<()> {
+    if let Some(("env", env_matches)) = matches.subcommand() {
+        return run_env_command(env_matches);
+    }
+    if let Some(("bookmark", bookmark_matches)) = matches.subcommand() {
+        let request = ControlRequest::Bookmark {
+            name: bookmark_matches.get_one::<String>("name").cloned(),
+        };
+        return run_control_command(bookmark_matches, &request);
+    }
+    if let Some(("export", export_matches)) = matches.subcommand() {
+        let format = export_matches.get_one::<String>("format").map(String::as_str);
+        if let Some(session) = export_matches.get_one::<String>("session") {
+            return run_export_command(matches.get_one::<String>("config"), session, format.unwrap_or("text"));
+        }
+        if let Some(format) = format.filter(|format| *format != "expect") {
+            anyhow::bail!("Name the session whose transcript to export, e.g. chatshell export {} --format {}", notepad::DEFAULT_SESSION, format);
+        }
+        let request = ControlRequest::Export {
+            from: export_matches.get_one::<String>("from").cloned(),
+            to: export_matches.get_one::<String>("to").cloned(),
+        };
+        return run_control_command(export_matches, &request);
+    }
+    if let Some(("trigger", trigger_matches)) = matches.subcommand() {
+        let request = match trigger_matches.get_one::<String>("event") {
+            Some(event) => ControlRequest::TriggerEvent {
+                event: HookEvent::from_name(event).unwrap_or(HookEvent::OnCommandEnd),
+                command_line: trigger_matches.get_one::<String>("command").cloned(),
+                exit_code: trigger_matches.get_one::<i32>("exit-code").copied(),
+            },
+            None => ControlRequest::Trigger {
+                key: trigger_matches.get_one::<String>("key").cloned().unwrap_or_default(),
+            },
+        };
+        return run_control_command(trigger_matches, &request);
+    }
+    if let Some(("bind", bind_matches)) = matches.subcommand() {
+        let lifetime = bind_matches.get_one::<String>("for").map(|text| watch::parse_interval(text)).transpose()?;
+        let request = ControlRequest::Bind {
+            key: bind_matches.get_one::<String>("key").cloned().unwrap_or_default(),
+            action: bind_matches.get_one::<String>("action").cloned().unwrap_or_default(),
+            lifetime_secs: lifetime.map(|lifetime| lifetime.as_secs().max(1)),
+        };
+        return run_control_command(bind_matches, &request);
+    }
+    if let Some(("state", state_matches)) = matches.subcommand() {
+        let request = match state_matches.subcommand() {
+            Some(("save", matches)) => ControlRequest::SaveState { name: matches.get_one::<String>("name").cloned().unwrap_or_default() },
+            Some(("load", matches)) => ControlRequest::LoadState { name: matches.get_one::<String>("name").cloned().unwrap_or_default() },
+            _ => return Ok(()),
+        };
+        let (_, matches) = state_matches.subcommand().expect("a subcommand is required");
+        return run_control_command(matches, &request);
+    }
+    if let Some(("report", report_matches)) = matches.subcommand() {
+        return run_report_command(matches.get_one::<String>("config"), report_matches);
+    }
+    if let Some(("init", init_matches)) = matches.subcommand() {
+        return run_init_command(init_matches);
+    }
+    if let Some(("hooks", hooks_matches)) = matches.subcommand() {
+        if let Some(("test", test_matches)) = hooks_matches.subcommand() {
+            return run_hooks_test(matches.get_one::<String>("config"), test_matches).await;
+        }
+        return run_hooks_command(matches.get_one::<String>("config"), hooks_matches);
+    }
+    if let Some(("cheatsheet", cheatsheet_matches)) = matches.subcommand() {
+        return run_cheatsheet_command(matches.get_one::<String>("config"), cheatsheet_matches);
+    }
+    if let Some(("bugreport", bugreport_matches)) = matches.subcommand() {
+        return run_bugreport_command(matches.get_one::<String>("config"), bugreport_matches);
+    }
+
+    // Handle create-config option
+    if matches.get_flag("create-config") {
+        let config_path = Config::ensure_config_exists()?;
+        
+        // Also create a config with default hooks
+        let config = Config {
+            hooks: create_default_hooks(),
+            ..Default::default()
+        };
+        config.save_to_file(&config_path)?;
+        
+        println!("Created configuration file at: {}", config_path);
+        println!("Edit this file to customize your shell and hooks.");
+        return Ok(());
+    }
+
+    // Without a terminal on stdout there is nothing to draw on; pass the
+    // shell through instead of leaving the descriptor half set up
+    if headless::needed() {
+        return run_headless(&matches).await;
+    }
+
+    // Create and run ChatShell
+    let config_path = matches.get_one::<String>("config").cloned();
+    let profile = matches.get_one::<String>("profile").cloned();
+    let session = matches.get_one::<String>("session").expect("has a default");
+    let style = Style::detect(matches.get_flag("plain"));
+    let serial = matches.get_one::<String>("serial").map(|device| SerialConfig {
+        device: device.clone(),
+        baud: *matches.get_one::<u32>("baud").expect("has a default"),
+    });
+    let mut shell = match ChatShell::new(config_path, profile, session, style, serial).await {
+        Ok(shell) => shell,
+        Err(e) => match e.downcast_ref::<Veto>() {
+            Some(veto) => {
+                eprintln!("Not starting the shell: the {} check failed.\n\n{}", veto.name, veto.message);
+                std::process::exit(1);
+            }
+            None => return Err(e),
+        },
+    };
+    if matches.get_flag("low-bandwidth") {
+        shell.config.low_bandwidth.enabled = true;
+    }
+
+    // Override shell if specified in command line
+    if let Some(shell_cmd) = matches.get_one::<String>("shell") {
+        shell.config.shell.command = shell_cmd.clone();
+        shell.config.shell.args = vec!["-i".to_string()]; // Interactive mode
+    }
+
+    // Run the shell wrapper
+    match shell.run().await {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            // Give the terminal back before printing; exit skips destructors
+            drop(shell);
+            let error = Error::categorize(e, Error::Pty);
+            let log = debug_log_path();
+            let reference = ErrorLog::new(log.clone()).record(&error);
+            eprintln!("ChatShell error: {} (reference {} in {})", error, reference, log.display());
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn run_headless(matches: &clap::ArgMatches) -> Result<()> {
+    if matches.get_one::<String>("serial").is_some() {
+        anyhow::bail!("--serial needs a terminal, and stdout is not one");
+    }
+    // Nothing is drawn, so there is nothing for them to change
+    for flag in ["plain", "low-bandwidth"] {
+        if matches.get_flag(flag) {
+            anyhow::bail!("--{} changes what is drawn, and without a terminal on stdout nothing is", flag);
+        }
+    }
+    let config_path = match matches.get_one::<String>("config") {
+        Some(path) => path.clone(),
+        None => Config::ensure_config_exists()?,
+    };
+    let mut config = Config::load_from_file(&config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path))?;
+    if let Some(shell_cmd) = matches.get_one::<String>("shell") {
+        config.shell.command = shell_cmd.clone();
+        config.shell.args = vec!["-i".to_string()];
+    }
+    let startup_commands = config.startup_commands(matches.get_one::<String>("profile").map(String::as_str))?;
+    let session = matches.get_one::<String>("session").expect("has a default");
+    let transcript_path = match config.history.transcripts {
+        true => Some(transcript::transcript_path(&transcript::transcripts_dir(Path::new(&config_path)), session)?),
+        false => None,
+    };
+    eprintln!("chatshell: stdout is not a terminal, so the shell runs without hooks or popups");
+    let code = headless::run(&config, &startup_commands, transcript_path).await?;
+    std::process::exit(code);
+}
+
+fn run_init_command(matches: &clap::ArgMatches) -> Result<()> {
+    let dir = match matches.get_one::<String>("dir") {
+        Some(dir) => PathBuf::from(expand_home(dir)),
+        None => ssh::default_wrapper_dir(),
+    };
+    let path = ssh::install_wrapper(&dir)?;
+    println!("Wrote {}", path.display());
+    if matches.get_one::<String>("dir").is_none() {
+        println!("\nPut it first on the shell's PATH with:\n\n[shell]\nssh_wrapper = true");
+    } else {
+        println!("\nPut {} first on the PATH of shells inside chatshell.", dir.display());
+    }
+    println!("\nThen, in the remote ~/.bashrc:\n\n{}", ssh::remote_snippet());
+    Ok(())
+}
+
+fn run_env_command(matches: &clap::ArgMatches) -> Result<()> {
+    let socket = control::resolve_socket_path(matches.get_one::<String>("socket"))?;
+
+    if let Some(("set", set_matches)) = matches.subcommand() {
+        for assignment in set_matches.get_many::<String>("assignments").into_iter().flatten() {
+            let (key, value) = assignment.split_once('=')
+                .with_context(|| format!("Expected KEY=VALUE, got: {}", assignment))?;
+            let request = ControlRequest::SetEnv {
+                key: key.to_string(),
+                value: value.to_string(),
+            };
+
+            let response = control::send_request(&socket, &request)?;
+            if !response.ok {
+                anyhow::bail!(response.message.unwrap_or_else(|| "Request failed".to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends `request` to the session and prints what it answers.
+fn run_control_command(matches: &clap::ArgMatches, request: &ControlRequest) -> Result<()> {
+    let socket = control::resolve_socket_path(matches.get_one::<String>("socket"))?;
+    let response = control::send_request(&socket, request)?;
+    if !response.ok {
+        anyhow::bail!(response.message.unwrap_or_else(|| "Request failed".to_string()));
+    }
+    if let Some(message) = response.message {
+        print!("{}", message);
+        if !message.ends_with('\n') {
+            println!();
+        }
+    }
+    Ok(())
+}
+
+fn run_report_command(config_path: Option<&String>, matches: &clap::ArgMatches) -> Result<()> {
+    let config_path = match config_path {
+        Some(path) => path.clone(),
+        None => Config::ensure_config_exists()?,
+    };
+    let config = Config::load_from_file(&config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path))?;
+
+    let until = chrono::Local::now();
+    let since = report::parse_since(matches.get_one::<String>("since").map(String::as_str).unwrap_or("7d"), until)?;
+    let storage = storage::open(&config.history)?;
+    let commands = storage.commands_since(since.timestamp())?;
+    let usage = storage.llm_usage_since(since.timestamp())?;
+    let report = Report::build(since, until, &commands, &usage);
+
+    match matches.get_one::<String>("format").map(String::as_str) {
+        Some("json") => println!("{}", report.to_json()?),
+        _ => print!("{}", report.to_text()),
+    }
+    Ok(())
+}
+
+/// Prints the transcript of `session` as `format`.
+fn run_export_command(config_path: Option<&String>, session: &str, format: &str) -> Result<()> {
+    let Some(exporter) = transcript::exporter(format) else {
+        anyhow::bail!("An expect script comes from the bookmarks of the running session; leave out the session name");
+    };
+    let config_path = match config_path {
+        Some(path) => path.clone(),
+        None => Config::ensure_config_exists()?,
+    };
+    let path = transcript::transcript_path(&transcript::transcripts_dir(Path::new(&config_path)), session)?;
+    if !path.exists() {
+        anyhow::bail!("No transcript of session '{}' at {}; set [history] transcripts = true to keep them", session, path.display());
+    }
+    print!("{}", exporter.export(&transcript::load(&path)?));
+    Ok(())
+}
+
+fn run_hooks_command(config_path: Option<&String>, matches: &clap::ArgMatches) -> Result<()> {
+    let config_path = match config_path {
+        Some(path) => path.clone(),
+        None => Config::ensure_config_exists()?,
+    };
+    let packs_dir = hookpack::packs_dir(Path::new(&config_path));
+    if let Some(("remove", remove_matches)) = matches.subcommand() {
+        let name = remove_matches.get_one::<String>("name").map(String::as_str).unwrap_or_default();
+        hookpack::remove(&packs_dir, name)?;
+        println!("Removed {}", name);
+        return Ok(());
+    }
+    let Some(("add", matches)) = matches.subcommand() else {
+        return Ok(());
+    };
+    let config = Config::load_from_file(&config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path))?;
+
+    // A URL or path is cloned as is; anything else is a name in the registry
+    let source = matches.get_one::<String>("source").cloned().unwrap_or_default();
+    let mut rev = matches.get_one::<String>("rev").cloned();
+    let mut sha256 = matches.get_one::<String>("sha256").cloned();
+    let url = if source.contains("://") || source.contains('@') || source.contains('/') || source.ends_with(".git") {
+        source
+    } else {
+        let registry = config.hook_packs.registry.as_deref()
+            .with_context(|| format!("{} isn't a git URL and no [hook_packs] registry is configured", source))?;
+        let entry = Registry::load(registry)?.packs.remove(&source)
+            .with_context(|| format!("No pack named {} in the registry", source))?;
+        rev = rev.or(entry.rev);
+        sha256 = sha256.or(entry.sha256);
+        entry.url
+    };
+
+    let pack = FetchedPack::fetch(&url, rev.as_deref())?;
+    if let Some(expected) = &sha256 {
+        pack.verify_checksum(expected)?;
+    }
+    if matches.get_flag("verify-signature") {
+        pack.verify_signature()?;
+    }
+
+    let (mut existing, _) = configured_hooks(&config, &packs_dir)?;
+    existing.retain(|hook| !hook.name.starts_with(&format!("{}/", pack.name)));
+    print!("{}", pack.summary(&existing));
+    if sha256.is_none() {
+        println!("The checksum wasn't checked; pass --sha256 to pin it.");
+    }
+    if packs_dir.join(&pack.name).exists() {
+        println!("This replaces the installed {}.", pack.name);
+    }
+    if !matches.get_flag("yes") && !confirm("Install it?")? {
+        println!("Not installed.");
+        return Ok(());
+    }
+    let installed = pack.install(&packs_dir)?;
+    println!("Installed {} to {}", pack.name, installed.display());
+    Ok(())
+}
+
+/// Writes the keys bound outside any project, as the help in a session
+/// lists them, in a format to share.
+fn run_cheatsheet_command(config_path: Option<&String>, matches: &clap::ArgMatches) -> Result<()> {
+    use std::io::Write;
+    let config_path = match config_path {
+        Some(path) => path.clone(),
+        None => Config::ensure_config_exists()?,
+    };
+    let config = Config::load_from_file(&config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path))?;
+    let packs_dir = hookpack::packs_dir(Path::new(&config_path));
+    let (hooks, pack_errors) = configured_hooks(&config, &packs_dir)?;
+    for error in pack_errors {
+        eprintln!("Hook pack left out: {:#}", error);
+    }
+    let manager = HookManager::from_configs(hooks);
+
+    let format = matches.get_one::<String>("format").map(String::as_str).unwrap_or("md");
+    let sheet = Cheatsheet::new(manager.key_bindings(false)).render(format)?;
+    match matches.get_one::<String>("output") {
+        Some(path) => std::fs::write(path, sheet).with_context(|| format!("Failed to write {}", path))?,
+        None => std::io::stdout().write_all(&sheet).context("Failed to write the cheatsheet")?,
+    }
+    Ok(())
+}
+
+/// Prints the hooks bound to a key in the order they would run, and with
+/// `--run` runs the `cmd:` steps of the first enabled one. Conditions and
+/// project hooks depend on the session, so they are shown but not checked.
+async fn run_hooks_test(config_path: Option<&String>, matches: &clap::ArgMatches) -> Result<()> {
+    let config_path = match config_path {
+        Some(path) => path.clone(),
+        None => Config::ensure_config_exists()?,
+    };
+    let config = Config::load_from_file(&config_path)
+        .with_context(|| format!("Failed to load config from {}", config_path))?;
+    let packs_dir = hookpack::packs_dir(Path::new(&config_path));
+    let (hooks, pack_errors) = configured_hooks(&config, &packs_dir)?;
+    for error in pack_errors {
+        eprintln!("Hook pack left out: {:#}", error);
+    }
+    let manager = HookManager::from_configs(hooks);
+
+    let keys = matches.get_one::<String>("key").map(String::as_str).unwrap_or_default();
+    let hooks = manager.bound_hooks(keys).with_context(|| format!("Not a key combination: {}", keys))?;
+    if hooks.is_empty() {
+        println!("No hook is bound to {}", keys);
+        return Ok(());
+    }
+    let fires = hooks.iter().position(|hook| hook.enabled);
+    for (index, hook) in hooks.iter().enumerate() {
+        let state = match (hook.enabled, Some(index) == fires) {
+            (false, _) => "disabled",
+            (true, true) => "fires",
+            (true, false) => "if those above leave the key",
+        };
+        println!("{} ({}, priority {})", hook.name, state, hook.priority);
+        println!("  key     {}", hook.key_combination);
+        for step in hook.action.steps() {
+            println!("  action  {}", step);
+        }
+        let when: Vec<String> = toml::to_string(&hook.when)?
+            .lines()
+            .filter(|line| *line != "at_prompt = false")
+            .map(str::to_string)
+            .collect();
+        if !when.is_empty() {
+            println!("  when    {}", when.join(", "));
+        }
+        if let Some(question) = &hook.confirm {
+            println!("  confirm {}", question);
+        }
+    }
+
+    let Some(hook) = fires.map(|index| hooks[index]).filter(|_| matches.get_flag("run")) else {
+        return Ok(());
+    };
+    for step in hook.action.steps() {
+        let Some(command) = step.strip_prefix("cmd:") else {
+            println!("\nNot run, it needs a session: {}", step);
+            continue;
+        };
+        let command = match fill_arg_defaults(hook, command.trim()) {
+            Ok(command) => command,
+            Err(name) => {
+                println!("\nNot run, {{{}}} has no default to fill it in: {}", name, command.trim());
+                return Ok(());
+            }
+        };
+        println!("\n$ {}", command);
+        let result = HookCommand {
+            command,
+            output: hook.output.clone(),
+            timeout: hook.timeout_ms.map(Duration::from_millis),
+            hook: hook.name.clone(),
+            ..Default::default()
+        }
+        .run()
+        .await;
+        match &result.output {
+            Ok(output) => {
+                print!("{}", String::from_utf8_lossy(&output.stdout));
+                eprint!("{}", String::from_utf8_lossy(&output.stderr));
+                println!("exit status {}", output.status.code().unwrap_or(-1));
+            }
+            Err(e) => println!("{:#}", e),
+        }
+        // A chain stops at the first step that fails
+        if !result.succeeded() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Asks a yes/no question on the terminal; no is the default.
+fn confirm(question: &str) -> Result<bool> {
+    use std::io::{IsTerminal, Write};
+    if !std::io::stdin().is_terminal() {
+        anyhow::bail!("Not a terminal to ask on; pass --yes to go ahead");
+    }
+    print!("{} [y/N] ", question);
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(matches!(answer.trim(), "y" | "Y" | "yes"))
+}
+
+fn run_bugreport_command(config_path: Option<&String>, matches: &clap::ArgMatches) -> Result<()> {
+    // Don't create a config just to report on it
+    let config_path = config_path.cloned().unwrap_or_else(Config::get_default_config_path);
+    let config_path = Path::new(&config_path);
+    let files = bugreport::collect(config_path.exists().then_some(config_path), None, true);
+    let dir = matches.get_one::<String>("output").map_or_else(|| PathBuf::from("."), PathBuf::from);
+    let path = bugreport::write_report(&dir, &files)?;
+    println!("Wrote {}", path.display());
+    Ok(())
 }
 
 #[cfg(test)]
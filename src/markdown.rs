@@ -0,0 +1,302 @@
+use crossterm::style::Color;
+use pulldown_cmark::{CodeBlockKind, Event, Parser, Tag, TagEnd};
+use ratatui::style::Modifier;
+use std::ops::Range;
+
+const HEADING: Color = Color::Cyan;
+const CODE: Color = Color::Green;
+const QUOTE: Color = Color::Grey;
+
+/// Bytes of a line drawn bold or in italics.
+pub type Emphasis = (Range<usize>, Modifier);
+
+/// What `render` lays out: the lines with their colors, where each line
+/// is bold or in italics, and where the code blocks are, to highlight.
+#[derive(Debug, Default, PartialEq)]
+pub struct Rendered {
+    pub lines: Vec<(String, Option<Color>)>,
+    pub emphasis: Vec<Vec<Emphasis>>,
+    pub code_blocks: Vec<CodeBlock>,
+}
+
+/// A code block among the lines `render` laid out.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CodeBlock {
+    /// The language after the opening fence, or empty.
+    pub language: String,
+    /// Which lines it is.
+    pub lines: Range<usize>,
+    /// How many bytes each of its lines starts with before the code.
+    pub indent: usize,
+}
+
+/// Lays out markdown, as LLM replies come, as popup lines: headings and
+/// code blocks in their own colors, list items with bullets or numbers,
+/// quotes behind a bar, bold and emphasis in bold and italics. Inline code
+/// keeps its text without the backticks, and links show their address
+/// after it.
+pub fn render(text: &str) -> Rendered {
+    let mut layout = Layout::default();
+    for event in Parser::new(text) {
+        layout.event(event);
+    }
+    layout.flush();
+    while layout.lines.last().is_some_and(|(line, _)| line.is_empty()) {
+        layout.lines.pop();
+        layout.emphasis.pop();
+    }
+    Rendered { lines: layout.lines, emphasis: layout.emphasis, code_blocks: layout.code_blocks }
+}
+
+#[derive(Default)]
+struct Layout {
+    lines: Vec<(String, Option<Color>)>,
+    emphasis: Vec<Vec<Emphasis>>,
+    line: String,
+    // Where the line so far is bold or in italics
+    line_emphasis: Vec<Emphasis>,
+    // How many bold and emphasis tags the text is in
+    strong: usize,
+    emphasized: usize,
+    color: Option<Color>,
+    // The next number of each list the current item is in, None for bullets
+    lists: Vec<Option<u64>>,
+    // True between an item's bullet and the end of its first block
+    in_item: bool,
+    quotes: usize,
+    code: bool,
+    link: Option<String>,
+    code_blocks: Vec<CodeBlock>,
+}
+
+impl Layout {
+    fn event(&mut self, event: Event) {
+        match event {
+            Event::Start(Tag::Paragraph) if !self.in_item => self.start_block(),
+            Event::End(TagEnd::Paragraph) => {
+                self.flush();
+                self.in_item = false;
+            }
+            Event::Start(Tag::Heading { .. }) => {
+                self.start_block();
+                self.color = Some(HEADING);
+            }
+            Event::End(TagEnd::Heading(_)) => {
+                self.flush();
+                self.color = None;
+            }
+            Event::Start(Tag::CodeBlock(kind)) => {
+                if !self.in_item {
+                    self.start_block();
+                }
+                self.flush();
+                self.code = true;
+                let language = match kind {
+                    CodeBlockKind::Fenced(info) => info.split_whitespace().next().unwrap_or_default().to_string(),
+                    CodeBlockKind::Indented => String::new(),
+                };
+                let indent = "│ ".len() * self.quotes + "  ".len() * (self.lists.len() + 1);
+                let at = self.lines.len();
+                self.code_blocks.push(CodeBlock { language, lines: at..at, indent });
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                self.code = false;
+                self.in_item = false;
+                let end = self.lines.len();
+                if let Some(block) = self.code_blocks.last_mut() {
+                    block.lines.end = end;
+                }
+            }
+            Event::Start(Tag::List(start)) => {
+                if self.lists.is_empty() {
+                    self.start_block();
+                }
+                self.flush();
+                self.lists.push(start);
+            }
+            Event::End(TagEnd::List(_)) => {
+                self.flush();
+                self.lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                self.flush();
+                let indent = "  ".repeat(self.lists.len().saturating_sub(1));
+                let bullet = match self.lists.last_mut() {
+                    Some(Some(number)) => {
+                        *number += 1;
+                        format!("{}. ", *number - 1)
+                    }
+                    _ => "• ".to_string(),
+                };
+                self.line = format!("{}{}", indent, bullet);
+                self.in_item = true;
+            }
+            Event::End(TagEnd::Item) => {
+                self.flush();
+                self.in_item = false;
+            }
+            Event::Start(Tag::BlockQuote(_)) => {
+                self.start_block();
+                self.quotes += 1;
+            }
+            Event::End(TagEnd::BlockQuote(_)) => {
+                self.flush();
+                self.quotes -= 1;
+            }
+            Event::Start(Tag::Strong) => self.strong += 1,
+            Event::End(TagEnd::Strong) => self.strong = self.strong.saturating_sub(1),
+            Event::Start(Tag::Emphasis) => self.emphasized += 1,
+            Event::End(TagEnd::Emphasis) => self.emphasized = self.emphasized.saturating_sub(1),
+            Event::Start(Tag::Link { dest_url, .. }) => self.link = Some(dest_url.to_string()),
+            Event::End(TagEnd::Link) => {
+                if let Some(url) = self.link.take().filter(|url| !self.line.ends_with(url.as_str())) {
+                    self.line.push_str(&format!(" ({})", url));
+                }
+            }
+            Event::Text(text) if self.code => {
+                let indent = "  ".repeat(self.lists.len() + 1);
+                for line in text.lines() {
+                    self.push(format!("{}{}", indent, line), Some(CODE));
+                }
+            }
+            Event::Text(text) | Event::Code(text) | Event::InlineHtml(text) | Event::Html(text) => self.push_text(&text),
+            Event::SoftBreak => self.push_text(" "),
+            Event::HardBreak => self.flush(),
+            Event::Rule => {
+                self.start_block();
+                self.push("─".repeat(20), Some(QUOTE));
+            }
+            _ => {}
+        }
+    }
+
+    /// Adds `text` to the line, in bold or italics if it is inside such
+    /// tags.
+    fn push_text(&mut self, text: &str) {
+        let start = self.line.len();
+        self.line.push_str(text);
+        let mut modifier = Modifier::empty();
+        if self.strong > 0 {
+            modifier |= Modifier::BOLD;
+        }
+        if self.emphasized > 0 {
+            modifier |= Modifier::ITALIC;
+        }
+        if modifier.is_empty() {
+            return;
+        }
+        match self.line_emphasis.last_mut() {
+            Some((range, last)) if range.end == start && *last == modifier => range.end = self.line.len(),
+            _ => self.line_emphasis.push((start..self.line.len(), modifier)),
+        }
+    }
+
+    /// Ends the line so far and leaves a blank line before the next block.
+    fn start_block(&mut self) {
+        self.flush();
+        if self.lines.last().is_some_and(|(line, _)| !line.is_empty()) {
+            self.push_line(String::new(), None, Vec::new());
+        }
+    }
+
+    fn flush(&mut self) {
+        if !self.line.is_empty() {
+            let line = std::mem::take(&mut self.line);
+            let emphasis = std::mem::take(&mut self.line_emphasis);
+            self.push_line(line, self.color, emphasis);
+        }
+    }
+
+    fn push(&mut self, line: String, color: Option<Color>) {
+        self.push_line(line, color, Vec::new());
+    }
+
+    fn push_line(&mut self, line: String, color: Option<Color>, mut emphasis: Vec<Emphasis>) {
+        if self.quotes == 0 {
+            self.lines.push((line, color));
+        } else {
+            let bars = "│ ".repeat(self.quotes);
+            for (range, _) in &mut emphasis {
+                *range = range.start + bars.len()..range.end + bars.len();
+            }
+            self.lines.push((format!("{}{}", bars, line), color.or(Some(QUOTE))));
+        }
+        self.emphasis.push(emphasis);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render_markdown() {
+        let reply = "\
+## Why it fails
+
+The **index** on `users.email` is missing. See [the docs](https://example.com/idx).
+
+1. Add it:
+
+   ```sql
+   CREATE INDEX users_email ON users (email);
+   ```
+2. Run `ANALYZE`
+- nested
+  - deeper
+
+> Locks the table
+> while it builds.
+
+---
+Done.";
+        let Rendered { lines, emphasis, code_blocks } = render(reply);
+        let text: Vec<&str> = lines.iter().map(|(line, _)| line.as_str()).collect();
+        assert_eq!(
+            text,
+            vec![
+                "Why it fails",
+                "",
+                "The index on users.email is missing. See the docs (https://example.com/idx).",
+                "",
+                "1. Add it:",
+                "",
+                "    CREATE INDEX users_email ON users (email);",
+                "2. Run ANALYZE",
+                "",
+                "• nested",
+                "  • deeper",
+                "",
+                "│ Locks the table while it builds.",
+                "",
+                "────────────────────",
+                "",
+                "Done.",
+            ]
+        );
+        assert_eq!(lines[0].1, Some(HEADING));
+        assert_eq!(lines[6].1, Some(CODE));
+        assert_eq!(lines[12].1, Some(QUOTE));
+        assert_eq!(lines[2].1, None);
+        assert_eq!(code_blocks, vec![CodeBlock { language: "sql".to_string(), lines: 6..7, indent: 4 }]);
+        assert_eq!(&lines[6].0[4..], "CREATE INDEX users_email ON users (email);");
+        assert_eq!(emphasis.len(), lines.len());
+        assert_eq!(emphasis[2], vec![(4..9, Modifier::BOLD)]);
+        assert_eq!(&lines[2].0[4..9], "index");
+
+        // Plain text comes through as it is
+        let plain = Rendered { lines: vec![("ls -la".to_string(), None)], emphasis: vec![vec![]], code_blocks: vec![] };
+        assert_eq!(render("ls -la"), plain);
+
+        let Rendered { lines, emphasis, .. } = render("> **Never** *ever* ***both***\n> again");
+        assert_eq!(lines[0].0, "│ Never ever both again");
+        assert_eq!(
+            emphasis[0],
+            vec![
+                ("│ ".len()..("│ Never".len()), Modifier::BOLD),
+                ("│ Never ".len()..("│ Never ever".len()), Modifier::ITALIC),
+                ("│ Never ever ".len()..("│ Never ever both".len()), Modifier::BOLD | Modifier::ITALIC),
+            ]
+        );
+    }
+}
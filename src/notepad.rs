@@ -0,0 +1,81 @@
+use anyhow::{Context, Result};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::state::valid_name;
+
+/// The session whose notes are kept unless `--session` names another.
+pub const DEFAULT_SESSION: &str = "default";
+
+/// The placeholder in `cmd:` actions that the notes are put in for,
+/// rather than asked for. Its prefix keeps it apart from the names of
+/// hooks' `args`.
+pub const NOTES_PLACEHOLDER: &str = "session:notes";
+
+/// Where the notes of each session go: `notes` next to the config.
+pub fn notes_dir(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join("notes")
+}
+
+/// The file with the notes of the session called `session`.
+pub fn notes_path(dir: &Path, session: &str) -> Result<PathBuf> {
+    if !valid_name(session) {
+        anyhow::bail!("Not a session name: {} (use letters, digits, '-', '_' and '.')", session);
+    }
+    Ok(dir.join(format!("{}.md", session)))
+}
+
+/// The notes in `path`, empty if none were written yet.
+pub fn load(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(text) => Ok(text),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(String::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read notes: {}", path.display())),
+    }
+}
+
+/// Replaces the notes in `path` with `text`, readable only by the user.
+pub fn save(path: &Path, text: &str) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create notes directory: {}", dir.display()))?;
+    }
+    OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)
+        .and_then(|mut file| file.write_all(text.as_bytes()))
+        .with_context(|| format!("Failed to save notes: {}", path.display()))
+}
+
+/// What the assistant is told of `notes`, added to its system prompt.
+/// None if there are none.
+pub fn llm_context(notes: &str) -> Option<String> {
+    let notes = notes.trim();
+    (!notes.is_empty()).then(|| format!("The user keeps these notes for the session:\n{}", notes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notes_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = notes_dir(&dir.path().join("config.toml"));
+        assert!(notes_path(&notes, "../elsewhere").is_err());
+        let path = notes_path(&notes, "incident-4711").unwrap();
+        assert_eq!(load(&path).unwrap(), "");
+        assert_eq!(llm_context(""), None);
+
+        save(&path, "db-02.internal\nOPS-4711").unwrap();
+        assert_eq!(load(&path).unwrap(), "db-02.internal\nOPS-4711");
+        assert_eq!(
+            llm_context(&load(&path).unwrap()).as_deref(),
+            Some("The user keeps these notes for the session:\ndb-02.internal\nOPS-4711")
+        );
+    }
+}
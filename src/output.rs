@@ -0,0 +1,905 @@
+use crate::clock::{Clock, SystemClock};
+use crate::config::{HookEvent, QueryPolicy, TerminalConfig, WaybackConfig};
+use crate::images::{cell_height, InlineImage};
+use crate::screen::{Screen, Snapshot, Wayback};
+use crate::scrollback::Scrollback;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const ESC: u8 = 0x1b;
+const BEL: u8 = 0x07;
+const ENQ: u8 = 0x05;
+
+/// Longest line kept for `OutputProcessor::current_line`.
+const MAX_LINE_LEN: usize = 1024;
+
+/// How many finished lines `OutputProcessor::recent_lines` keeps.
+const RECENT_LINES: usize = 500;
+
+/// Lines kept per capture; anything beyond is dropped.
+const MAX_CAPTURE_LINES: usize = 5000;
+
+/// Longest run of SGR sequences kept for a colored line; a longer one
+/// starts over from the last sequence.
+const MAX_SGR_LEN: usize = 128;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SequenceKind {
+    Csi,
+    Osc,
+    Dcs,
+    Apc,
+    Pm,
+    Sos,
+    Escape,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Segment {
+    Text(Vec<u8>),
+    /// A C0 control byte that is reported on its own (currently only ENQ).
+    Control(u8),
+    Sequence { kind: SequenceKind, raw: Vec<u8> },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ScanState {
+    Ground,
+    Escape,
+    EscapeIntermediate,
+    Csi,
+    String(SequenceKind),
+    StringEscape(SequenceKind),
+}
+
+/// Splits the child's output into plain text and complete escape sequences.
+///
+/// Sequences may be split across reads, so anything incomplete is held back
+/// until the rest of it arrives.
+#[derive(Debug)]
+pub struct SequenceScanner {
+    state: ScanState,
+    pending: Vec<u8>,
+}
+
+impl SequenceScanner {
+    pub fn new() -> Self {
+        SequenceScanner {
+            state: ScanState::Ground,
+            pending: Vec::new(),
+        }
+    }
+
+    pub fn scan(&mut self, data: &[u8]) -> Vec<Segment> {
+        let mut segments = Vec::new();
+        let mut text = Vec::new();
+
+        for &byte in data {
+            match self.state {
+                ScanState::Ground => match byte {
+                    ESC => {
+                        Self::flush_text(&mut text, &mut segments);
+                        self.pending.push(byte);
+                        self.state = ScanState::Escape;
+                    }
+                    ENQ => {
+                        Self::flush_text(&mut text, &mut segments);
+                        segments.push(Segment::Control(byte));
+                    }
+                    _ => text.push(byte),
+                },
+                ScanState::Escape => {
+                    self.pending.push(byte);
+                    self.state = match byte {
+                        b'[' => ScanState::Csi,
+                        b']' => ScanState::String(SequenceKind::Osc),
+                        b'P' => ScanState::String(SequenceKind::Dcs),
+                        b'_' => ScanState::String(SequenceKind::Apc),
+                        b'^' => ScanState::String(SequenceKind::Pm),
+                        b'X' => ScanState::String(SequenceKind::Sos),
+                        0x20..=0x2f => ScanState::EscapeIntermediate,
+                        _ => {
+                            self.emit(SequenceKind::Escape, &mut segments);
+                            ScanState::Ground
+                        }
+                    };
+                }
+                ScanState::EscapeIntermediate => {
+                    self.pending.push(byte);
+                    if !(0x20..=0x2f).contains(&byte) {
+                        self.emit(SequenceKind::Escape, &mut segments);
+                        self.state = ScanState::Ground;
+                    }
+                }
+                ScanState::Csi => {
+                    self.pending.push(byte);
+                    if (0x40..=0x7e).contains(&byte) {
+                        self.emit(SequenceKind::Csi, &mut segments);
+                        self.state = ScanState::Ground;
+                    }
+                }
+                ScanState::String(kind) => {
+                    self.pending.push(byte);
+                    if byte == ESC {
+                        self.state = ScanState::StringEscape(kind);
+                    } else if byte == BEL && kind == SequenceKind::Osc {
+                        self.emit(kind, &mut segments);
+                        self.state = ScanState::Ground;
+                    }
+                }
+                ScanState::StringEscape(kind) => {
+                    self.pending.push(byte);
+                    self.state = if byte == b'\\' {
+                        self.emit(kind, &mut segments);
+                        ScanState::Ground
+                    } else {
+                        ScanState::String(kind)
+                    };
+                }
+            }
+        }
+
+        Self::flush_text(&mut text, &mut segments);
+        segments
+    }
+
+    fn emit(&mut self, kind: SequenceKind, segments: &mut Vec<Segment>) {
+        segments.push(Segment::Sequence {
+            kind,
+            raw: std::mem::take(&mut self.pending),
+        });
+    }
+
+    fn flush_text(text: &mut Vec<u8>, segments: &mut Vec<Segment>) {
+        if !text.is_empty() {
+            segments.push(Segment::Text(std::mem::take(text)));
+        }
+    }
+}
+
+impl Default for SequenceScanner {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalQuery {
+    Enq,
+    PrimaryDeviceAttributes,
+    SecondaryDeviceAttributes,
+    StatusReport,
+    CursorPosition,
+}
+
+impl TerminalQuery {
+    pub fn from_segment(segment: &Segment) -> Option<Self> {
+        match segment {
+            Segment::Control(ENQ) => Some(TerminalQuery::Enq),
+            Segment::Sequence { kind: SequenceKind::Csi, raw } => match &raw[2..] {
+                b"c" | b"0c" => Some(TerminalQuery::PrimaryDeviceAttributes),
+                b">c" | b">0c" => Some(TerminalQuery::SecondaryDeviceAttributes),
+                b"5n" => Some(TerminalQuery::StatusReport),
+                b"6n" => Some(TerminalQuery::CursorPosition),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+/// The result of processing one chunk of child output.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ProcessedOutput {
+    /// Bytes to write to the outer terminal.
+    pub display: Vec<u8>,
+    /// Bytes to write back to the child, as if the terminal had sent them.
+    pub replies: Vec<u8>,
+    /// Captures whose end marker arrived in this output.
+    pub captures: Vec<Capture>,
+    /// Inline images drawn by this output. Their sequences are in `display`
+    /// unchanged.
+    pub images: Vec<InlineImage>,
+    /// Lines this output finished, as plain text.
+    pub lines: Vec<String>,
+    /// The same lines with their SGR color sequences, outside the
+    /// alternate screen and once asked for with
+    /// `OutputProcessor::keep_colored_lines`.
+    pub colored_lines: Vec<String>,
+    /// Exit statuses of commands, from shells that report them with
+    /// `OSC 133 ; D ; <status>`.
+    pub exit_statuses: Vec<i32>,
+    /// Commands this output marked as starting or finished.
+    pub command_marks: Vec<CommandMark>,
+}
+
+/// A command the shell marked with `OSC 133` as starting or finished.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandMark {
+    /// `C`: the command line was entered and runs now.
+    Started { command: String },
+    /// `D`, or the next prompt: the command finished. Only `D` carries the
+    /// exit status.
+    Finished { command: String, exit_code: Option<i32>, duration: Duration },
+}
+
+/// Output lines printed between a pair of marker lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Capture {
+    pub id: u64,
+    pub lines: Vec<String>,
+}
+
+/// The markers that delimit capture `id`. A marker counts when it ends a
+/// line, which allows for a prompt printed in front of it; the command that
+/// prints it must not contain it literally, or its echo would match too.
+pub fn capture_markers(id: u64) -> (String, String) {
+    (format!("__CS_B_{}__", id), format!("__CS_E_{}__", id))
+}
+
+/// The letter of a `OSC 133` shell integration mark: `A` prompt, `B` input,
+/// `C` command output and `D` command finished.
+fn shell_mark(raw: &[u8]) -> Option<u8> {
+    raw.get(2..)?.strip_prefix(b"133;")?.first().copied()
+}
+
+/// The status in a `OSC 133 ; D ; <status>` command-finished mark.
+fn exit_status(raw: &[u8]) -> Option<i32> {
+    let body = raw.get(2..)?.strip_prefix(b"133;D;")?;
+    let end = body.iter().position(|b| !b.is_ascii_digit()).unwrap_or(body.len());
+    std::str::from_utf8(&body[..end]).ok()?.parse().ok()
+}
+
+/// True if `line` is the echo of a command that prints capture markers.
+pub fn is_marker_echo(line: &str) -> bool {
+    line.match_indices("S_B_").any(|(i, _)| {
+        let rest = &line[i + 4..];
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        digits > 0 && rest[digits..].starts_with("__")
+    })
+}
+
+impl CommandMark {
+    /// The hook event this mark fires.
+    pub fn event(&self) -> HookEvent {
+        match self {
+            CommandMark::Started { .. } => HookEvent::OnCommandStart,
+            CommandMark::Finished { .. } => HookEvent::OnCommandEnd,
+        }
+    }
+
+    /// What hooks' commands are told of the command, as `CHATSHELL_*`
+    /// variables.
+    pub fn env(&self) -> Vec<(String, String)> {
+        match self {
+            CommandMark::Started { command } => vec![("CHATSHELL_COMMAND".to_string(), command.clone())],
+            CommandMark::Finished { command, exit_code, duration } => {
+                let mut env = vec![
+                    ("CHATSHELL_COMMAND".to_string(), command.clone()),
+                    ("CHATSHELL_DURATION_MS".to_string(), duration.as_millis().to_string()),
+                ];
+                if let Some(code) = exit_code {
+                    env.push(("CHATSHELL_EXIT_CODE".to_string(), code.to_string()));
+                }
+                env
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ActiveCapture {
+    id: u64,
+    begin: String,
+    end: String,
+    started_at: Instant,
+    inside: bool,
+    lines: Vec<String>,
+}
+
+/// Rebuilds the plain text of the lines the child prints, following
+/// carriage returns, backspaces and the cursor movements line editors use
+/// to redraw a prompt.
+#[derive(Debug, Default)]
+struct LineTracker {
+    line: Vec<char>,
+    col: usize,
+    recent: VecDeque<String>,
+    /// Whether to keep the lines with their colors too.
+    colors: bool,
+    // The SGR sequences each character of `line` was printed with, and
+    // those in effect now
+    styles: Vec<String>,
+    sgr: String,
+    colored: Vec<String>,
+}
+
+impl LineTracker {
+    /// Feeds text and returns the lines it completed.
+    fn text(&mut self, text: &[u8]) -> Vec<String> {
+        let mut finished = Vec::new();
+        for c in String::from_utf8_lossy(text).chars() {
+            match c {
+                '\n' => {
+                    let line: String = self.line.iter().collect();
+                    let line = line.trim_end().to_string();
+                    self.recent.push_back(line.clone());
+                    if self.recent.len() > RECENT_LINES {
+                        self.recent.pop_front();
+                    }
+                    if self.colors {
+                        let colored = self.colored_line(line.chars().count());
+                        self.colored.push(colored);
+                    }
+                    finished.push(line);
+                    self.line.clear();
+                    self.styles.clear();
+                    self.col = 0;
+                }
+                '\r' => self.col = 0,
+                '\x08' => self.col = self.col.saturating_sub(1),
+                c if c.is_control() => {}
+                c => {
+                    if self.col < self.line.len() {
+                        self.line[self.col] = c;
+                    } else if self.line.len() < MAX_LINE_LEN {
+                        self.line.push(c);
+                    } else {
+                        continue;
+                    }
+                    if self.colors {
+                        self.styles.resize(self.line.len(), String::new());
+                        self.styles[self.col].clone_from(&self.sgr);
+                    }
+                    self.col += 1;
+                }
+            }
+        }
+        finished
+    }
+
+    /// Applies the CSI sequences that move within or erase the line.
+    fn sequence(&mut self, raw: &[u8]) {
+        let Some((&command, params)) = raw.get(2..).and_then(|rest| rest.split_last()) else {
+            return;
+        };
+        if command == b'm' {
+            self.color(raw, params);
+            return;
+        }
+        let count = std::str::from_utf8(params).ok()
+            .and_then(|p| p.parse::<usize>().ok())
+            .unwrap_or(1)
+            .max(1);
+        match command {
+            b'C' => self.col = (self.col + count).min(self.line.len()),
+            b'D' => self.col = self.col.saturating_sub(count),
+            b'G' => self.col = (count - 1).min(self.line.len()),
+            b'K' if params.is_empty() || params == b"0" => {
+                self.line.truncate(self.col);
+                self.styles.truncate(self.col);
+            }
+            _ => {}
+        }
+    }
+
+    /// Follows the SGR sequence `raw` for the colored lines.
+    fn color(&mut self, raw: &[u8], params: &[u8]) {
+        if !self.colors {
+            return;
+        }
+        let raw = String::from_utf8_lossy(raw);
+        if params.is_empty() || params == b"0" {
+            self.sgr.clear();
+        } else if params.starts_with(b"0;") || self.sgr.len() > MAX_SGR_LEN {
+            self.sgr = raw.into_owned();
+        } else {
+            self.sgr.push_str(&raw);
+        }
+    }
+
+    /// The first `len` characters of the line, with the SGR sequences they
+    /// were printed with.
+    fn colored_line(&self, len: usize) -> String {
+        let mut line = String::new();
+        let mut current = "";
+        for (c, style) in self.line.iter().zip(self.styles.iter().map(String::as_str).chain(std::iter::repeat(""))).take(len) {
+            if style != current {
+                if !current.is_empty() {
+                    line.push_str("\x1b[0m");
+                }
+                line.push_str(style);
+                current = style;
+            }
+            line.push(*c);
+        }
+        if !current.is_empty() {
+            line.push_str("\x1b[0m");
+        }
+        line
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputProcessor {
+    scanner: SequenceScanner,
+    config: TerminalConfig,
+    lines: LineTracker,
+    captures: Vec<ActiveCapture>,
+    alternate_screen: bool,
+    bracketed_paste: bool,
+    // The command that runs and since when, between `C` and its end
+    running: Option<(String, Instant)>,
+    // Where the prompt ends and typing starts, from the last `B` mark
+    input_col: Option<usize>,
+    screen: Screen,
+    wayback: Wayback,
+    scrollback: Scrollback,
+    clock: Arc<dyn Clock>,
+}
+
+impl OutputProcessor {
+    pub fn new(config: TerminalConfig) -> Self {
+        OutputProcessor {
+            scanner: SequenceScanner::new(),
+            config,
+            lines: LineTracker::default(),
+            captures: Vec::new(),
+            alternate_screen: false,
+            bracketed_paste: false,
+            running: None,
+            input_col: None,
+            screen: Screen::default(),
+            wayback: Wayback::default(),
+            scrollback: Scrollback::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sets the clock commands are timed and screens kept by.
+    pub fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+        self.clock = clock;
+    }
+
+    /// Sets how many recent screens are kept, and how often.
+    pub fn set_wayback(&mut self, config: &WaybackConfig) {
+        self.wayback = Wayback::new(config);
+    }
+
+    /// Sets how many lines of output are kept to search.
+    pub fn set_scrollback(&mut self, lines: usize) {
+        self.scrollback = Scrollback::new(lines);
+    }
+
+    /// Has `process` return the lines with their colors too, for a
+    /// transcript.
+    pub fn keep_colored_lines(&mut self) {
+        self.lines.colors = true;
+    }
+
+    /// The lines the shell printed outside full-screen programs.
+    pub fn scrollback(&self) -> &Scrollback {
+        &self.scrollback
+    }
+
+    /// Follows a resize of the shell's part of the terminal.
+    pub fn resize(&mut self, cols: u16, rows: u16) {
+        self.screen.resize(cols as usize, rows as usize);
+    }
+
+    /// Recent screens, oldest first.
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.wayback.snapshots()
+    }
+
+    /// The plain text of the line the cursor is on, usually the prompt and
+    /// whatever has been typed after it.
+    pub fn current_line(&self) -> String {
+        self.lines.line.iter().collect()
+    }
+
+    /// The text of each row of the screen as it is now.
+    pub fn screen_rows(&self) -> Vec<String> {
+        self.screen.rows()
+    }
+
+    /// True while a full-screen program has the alternate screen.
+    pub fn in_alternate_screen(&self) -> bool {
+        self.alternate_screen
+    }
+
+    /// Where the cursor is on the shell's screen, as `(col, row)` from 0.
+    pub fn cursor(&self) -> (u16, u16) {
+        let (col, row) = self.screen.cursor();
+        (col as u16, row as u16)
+    }
+
+    /// True while the shell or a program in it has asked for pastes to be
+    /// marked, as readline and most editors do.
+    pub fn bracketed_paste(&self) -> bool {
+        self.bracketed_paste
+    }
+
+    /// True between a command starting and finishing, for shells that mark
+    /// both with `OSC 133`.
+    pub fn command_running(&self) -> bool {
+        self.running.is_some()
+    }
+
+    /// The command line entered at the last prompt, without the prompt
+    /// when the shell marked where it ends.
+    fn entered_command(&self) -> String {
+        // The echo of Enter has usually finished the line by now
+        let line = match self.lines.line.iter().all(|c| c.is_whitespace()) {
+            true => self.lines.recent.back().cloned().unwrap_or_default(),
+            false => self.current_line(),
+        };
+        let command: String = line.chars().skip(self.input_col.unwrap_or(0)).collect();
+        command.trim().to_string()
+    }
+
+    /// The last lines of plain text the child printed, oldest first.
+    pub fn recent_lines(&self) -> Vec<String> {
+        self.lines.recent.iter().cloned().collect()
+    }
+
+    /// Starts collecting the lines printed between the markers of `id`.
+    pub fn begin_capture(&mut self, id: u64) {
+        let (begin, end) = capture_markers(id);
+        self.captures.push(ActiveCapture {
+            id,
+            begin,
+            end,
+            started_at: self.clock.now(),
+            inside: false,
+            lines: Vec::new(),
+        });
+    }
+
+    /// Drops captures whose end marker hasn't shown up within `timeout` and
+    /// returns their ids.
+    pub fn expire_captures(&mut self, now: Instant, timeout: Duration) -> Vec<u64> {
+        let (expired, active) = std::mem::take(&mut self.captures)
+            .into_iter()
+            .partition(|capture| now.duration_since(capture.started_at) >= timeout);
+        self.captures = active;
+        expired.into_iter().map(|capture: ActiveCapture| capture.id).collect()
+    }
+
+    fn capture_line(&mut self, line: &str, finished: &mut Vec<Capture>) {
+        let mut i = 0;
+        while i < self.captures.len() {
+            let capture = &mut self.captures[i];
+            if !capture.inside {
+                capture.inside = line.ends_with(&capture.begin);
+            } else if line.ends_with(&capture.end) {
+                let capture = self.captures.remove(i);
+                finished.push(Capture { id: capture.id, lines: capture.lines });
+                continue;
+            } else if capture.lines.len() < MAX_CAPTURE_LINES {
+                capture.lines.push(line.to_string());
+            }
+            i += 1;
+        }
+    }
+
+    pub fn process(&mut self, data: &[u8]) -> ProcessedOutput {
+        let mut output = ProcessedOutput::default();
+
+        for segment in self.scanner.scan(data) {
+            if let Some(query) = TerminalQuery::from_segment(&segment) {
+                match self.config.queries.policy_for(query) {
+                    QueryPolicy::Forward => {}
+                    QueryPolicy::Spoof => {
+                        output.replies.extend(self.spoofed_reply(query));
+                        continue;
+                    }
+                    QueryPolicy::Drop => continue,
+                }
+            }
+
+            match segment {
+                Segment::Text(bytes) => {
+                    self.screen.text(&bytes);
+                    for line in self.lines.text(&bytes) {
+                        self.capture_line(&line, &mut output.captures);
+                        if !self.alternate_screen {
+                            self.scrollback.push(line.clone());
+                        }
+                        output.lines.push(line);
+                    }
+                    let colored = std::mem::take(&mut self.lines.colored);
+                    if !self.alternate_screen {
+                        output.colored_lines.extend(colored);
+                    }
+                    output.display.extend(bytes)
+                }
+                Segment::Sequence { kind, raw: bytes } => {
+                    match kind {
+                        SequenceKind::Csi => {
+                            self.lines.sequence(&bytes);
+                            match &bytes[2..] {
+                                b"?1049h" | b"?1047h" | b"?47h" => self.alternate_screen = true,
+                                b"?1049l" | b"?1047l" | b"?47l" => {
+                                    self.alternate_screen = false;
+                                    // Keep what the program left on screen
+                                    self.wayback.take(&self.screen, self.clock.now());
+                                }
+                                b"?2004h" => self.bracketed_paste = true,
+                                b"?2004l" => self.bracketed_paste = false,
+                                b"2J" | b"3J" if !self.screen.in_alternate() => {
+                                    self.wayback.take(&self.screen, self.clock.now());
+                                }
+                                _ => {}
+                            }
+                            self.screen.sequence(&bytes);
+                        }
+                        SequenceKind::Osc => {
+                            match shell_mark(&bytes) {
+                                Some(b'C') => {
+                                    let command = self.entered_command();
+                                    output.command_marks.push(CommandMark::Started { command: command.clone() });
+                                    self.running = Some((command, self.clock.now()));
+                                }
+                                Some(mark @ (b'A' | b'B' | b'D')) => {
+                                    if let Some((command, started)) = self.running.take() {
+                                        output.command_marks.push(CommandMark::Finished {
+                                            command,
+                                            exit_code: exit_status(&bytes),
+                                            duration: self.clock.now().duration_since(started),
+                                        });
+                                    }
+                                    match mark {
+                                        b'B' => self.input_col = Some(self.lines.col),
+                                        b'A' => self.input_col = None,
+                                        _ => {}
+                                    }
+                                }
+                                _ => {}
+                            }
+                            output.exit_statuses.extend(exit_status(&bytes))
+                        }
+                        _ => {}
+                    }
+                    if let Some(image) = InlineImage::from_sequence(kind, &bytes) {
+                        // The cursor ends below the image, when its size is known
+                        if let Some(rows) = image.height_in_rows(cell_height(), self.screen.height() as u16) {
+                            self.screen.feed_rows(rows as usize);
+                        }
+                        output.images.push(image);
+                    }
+                    output.display.extend(bytes)
+                }
+                Segment::Control(byte) => output.display.push(byte),
+            }
+        }
+        self.wayback.observe(&self.screen, self.clock.now());
+
+        output
+    }
+
+    fn spoofed_reply(&self, query: TerminalQuery) -> Vec<u8> {
+        match query {
+            TerminalQuery::Enq => self.config.answerback.clone().into_bytes(),
+            TerminalQuery::PrimaryDeviceAttributes => {
+                self.config.primary_da_response.clone().into_bytes()
+            }
+            TerminalQuery::SecondaryDeviceAttributes => {
+                self.config.secondary_da_response.clone().into_bytes()
+            }
+            TerminalQuery::StatusReport => b"\x1b[0n".to_vec(),
+            TerminalQuery::CursorPosition => {
+                // From the screen as followed so far, without asking the
+                // outer terminal and waiting on its answer
+                let (col, row) = self.screen.cursor();
+                format!("\x1b[{};{}R", row + 1, col + 1).into_bytes()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_scanner_joins_split_sequences() {
+        let mut scanner = SequenceScanner::new();
+        assert_eq!(scanner.scan(b"ab\x1b["), vec![Segment::Text(b"ab".to_vec())]);
+
+        let segments = scanner.scan(b"31mcd");
+        assert_eq!(
+            segments,
+            vec![
+                Segment::Sequence { kind: SequenceKind::Csi, raw: b"\x1b[31m".to_vec() },
+                Segment::Text(b"cd".to_vec()),
+            ]
+        );
+
+        let segments = scanner.scan(b"\x1b]0;title\x07\x1bPq#0\x1b\\");
+        assert_eq!(segments.len(), 2);
+        assert!(matches!(segments[0], Segment::Sequence { kind: SequenceKind::Osc, .. }));
+        assert!(matches!(segments[1], Segment::Sequence { kind: SequenceKind::Dcs, .. }));
+    }
+
+    #[test]
+    fn test_query_policies() {
+        let mut config = TerminalConfig::default();
+        config.queries.primary_da = QueryPolicy::Spoof;
+        config.queries.enq = QueryPolicy::Drop;
+        let mut processor = OutputProcessor::new(config);
+
+        let output = processor.process(b"x\x1b[c\x05y\x1b[>c");
+        assert_eq!(output.display, b"xy\x1b[>c".to_vec());
+        assert_eq!(output.replies, b"\x1b[?62;22c".to_vec());
+
+        // The cursor position comes from the screen as followed so far
+        let mut config = TerminalConfig::default();
+        config.queries.cursor_position = QueryPolicy::Spoof;
+        let mut processor = OutputProcessor::new(config);
+        let output = processor.process(b"one\r\ntwo\x1b[6n");
+        assert_eq!(output.replies, b"\x1b[2;4R".to_vec());
+    }
+
+    #[test]
+    fn test_images_move_the_cursor() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        processor.resize(80, 24);
+        processor.process(b"one\r\ntwo");
+        assert_eq!(processor.cursor(), (3, 1));
+        processor.process(b"\r\n\x1b]1337;File=inline=1;height=3:AAAA\x07");
+        assert_eq!(processor.cursor(), (0, 5));
+    }
+
+    #[test]
+    fn test_current_line_tracks_prompt() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        processor.process(b"for x in y:\r\n\x1b[1m... \x1b[0m");
+        assert_eq!(processor.current_line(), "... ");
+        processor.process(b"ab\x08\r\n>>> ");
+        assert_eq!(processor.current_line(), ">>> ");
+    }
+
+    #[test]
+    fn test_capture_between_marker_lines() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        processor.begin_capture(7);
+
+        let output = processor.process(b"$ printf '__CS_\\102_7__'; ls\r\n__CS_B_7__\r\na.txt\r\n\x1b[1mb");
+        assert!(output.captures.is_empty());
+        let output = processor.process(b".txt\x1b[0m\r\n> __CS_E_7__\r\n$ ");
+        assert_eq!(
+            output.captures,
+            vec![Capture { id: 7, lines: vec!["a.txt".to_string(), "b.txt".to_string()] }]
+        );
+        assert_eq!(processor.recent_lines().last().map(String::as_str), Some("> __CS_E_7__"));
+    }
+
+    #[test]
+    fn test_marker_echo() {
+        assert!(is_marker_echo("$ printf '__\\103S_B_12__\\n'; make; printf '__\\103S_E_12__\\n'"));
+        assert!(!is_marker_echo("$ echo S_B_x__"));
+    }
+
+    #[test]
+    fn test_images_pass_through_intact() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        let sixel = b"\x1bP0;1;0q\"1;1;8;12#0;2;0;0;0#0~~~~~~~~-~~~~~~~~\x1b\\";
+        let kitty = b"\x1b_Ga=T,f=100,r=2;iVBORw0KGgo=\x1b\\";
+        let mut stream = b"plot:\r\n".to_vec();
+        stream.extend_from_slice(sixel);
+        stream.extend_from_slice(kitty);
+        stream.extend_from_slice(b"\r\n$ ");
+
+        let mut display = Vec::new();
+        let mut images = Vec::new();
+        for chunk in stream.chunks(7) {
+            let output = processor.process(chunk);
+            display.extend(output.display);
+            images.extend(output.images);
+        }
+        assert_eq!(display, stream);
+        assert_eq!(images.len(), 2);
+        assert_eq!(processor.recent_lines(), vec!["plot:".to_string(), String::new()]);
+        assert_eq!(processor.current_line(), "$ ");
+    }
+
+    #[test]
+    fn test_exit_status_and_alternate_screen() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        let output = processor.process(b"oops\n\x1b]133;D;127\x07\x1b]133;A\x07$ ");
+        assert_eq!(output.exit_statuses, vec![127]);
+
+        processor.process(b"\x1b[?1049h");
+        assert!(processor.in_alternate_screen());
+        processor.process(b"~\r\n~\r\n");
+        processor.process(b"\x1b[?1049l");
+        assert!(!processor.in_alternate_screen());
+
+        assert!(!processor.bracketed_paste());
+        processor.process(b"\x1b[?2004h$ ");
+        assert!(processor.bracketed_paste());
+        processor.process(b"\x1b[?2004l");
+        assert!(!processor.bracketed_paste());
+
+        processor.process(b"make\r\n\x1b]133;C\x07");
+        assert!(processor.command_running());
+        processor.process(b"\x1b]133;D;0\x07\x1b]133;A\x07$ ");
+        assert!(!processor.command_running());
+
+        // What the full-screen program drew isn't searchable
+        let scrollback: Vec<&String> = processor.scrollback().lines().iter().collect();
+        assert_eq!(scrollback, vec!["oops", "$ make"]);
+    }
+
+    #[test]
+    fn test_command_marks() {
+        let clock = Arc::new(MockClock::default());
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        processor.set_clock(clock.clone());
+        processor.process(b"\x1b]133;A\x07user@host:~$ \x1b]133;B\x07");
+        let output = processor.process(b"make -j4\r\n\x1b]133;C\x07");
+        assert_eq!(output.command_marks, vec![CommandMark::Started { command: "make -j4".to_string() }]);
+
+        clock.advance(Duration::from_secs(5));
+        let output = processor.process(b"done\r\n\x1b]133;D;2\x07\x1b]133;A\x07$ ");
+        match output.command_marks.as_slice() {
+            [CommandMark::Finished { command, exit_code, duration }] => {
+                assert_eq!(command, "make -j4");
+                assert_eq!(*exit_code, Some(2));
+                assert_eq!(*duration, Duration::from_secs(5));
+            }
+            marks => panic!("unexpected marks: {:?}", marks),
+        }
+
+        // Without B the whole line counts; a prompt with no command ends nothing
+        let output = processor.process(b"ls\r\n\x1b]133;C\x07");
+        assert_eq!(output.command_marks, vec![CommandMark::Started { command: "$ ls".to_string() }]);
+        let output = processor.process(b"\x1b]133;A\x07$ ");
+        assert!(matches!(output.command_marks.as_slice(), [CommandMark::Finished { exit_code: None, .. }]));
+        assert!(processor.process(b"\x1b]133;D;0\x07").command_marks.is_empty());
+    }
+
+    #[test]
+    fn test_screens_kept_before_they_are_lost() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        processor.process(b"$ vim\r\n");
+        processor.process(b"\x1b[?1049h\x1b[24;1HE37: No write since last change\x1b[?1049l$ ");
+        processor.process(b"\x1b[H\x1b[2J$ ");
+
+        let snapshots = processor.snapshots();
+        assert_eq!(snapshots.len(), 3);
+        assert!(snapshots[1].alternate);
+        assert_eq!(snapshots[1].lines.last().map(String::as_str), Some("E37: No write since last change"));
+        assert_eq!(snapshots[2].lines, ["$ vim", "$"]);
+    }
+
+    #[test]
+    fn test_line_redraw_overwrites() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        processor.process(b"$ git sta\r$ ls\x1b[K");
+        assert_eq!(processor.current_line(), "$ ls");
+
+        processor.begin_capture(1);
+        assert_eq!(processor.expire_captures(Instant::now() + Duration::from_secs(60), Duration::from_secs(30)), vec![1]);
+     }
+
+    #[test]
+    fn test_colored_lines() {
+        let mut processor = OutputProcessor::new(TerminalConfig::default());
+        assert!(processor.process(b"\x1b[31mred\x1b[0m\r\n").colored_lines.is_empty());
+
+        processor.keep_colored_lines();
+        let output = processor.process(b"test \x1b[1m\x1b[32mok\x1b[m.  \r\n\x1b[33mFAIL\r\n\x1b[0mplain\r\n");
+        assert_eq!(output.lines, ["test ok.", "FAIL", "plain"]);
+        assert_eq!(output.colored_lines, ["test \x1b[1m\x1b[32mok\x1b[0m.", "\x1b[33mFAIL\x1b[0m", "plain"]);
+
+        // Overwritten in place, the new colors count
+        let output = processor.process(b"\x1b[31mwait\r\x1b[0;32mdone\r\n");
+        assert_eq!(output.colored_lines, ["\x1b[0;32mdone\x1b[0m"]);
+        let output = processor.process(b"\x1b[?1049h\x1b[34mvim\r\n\x1b[?1049l");
+        assert!(output.colored_lines.is_empty());
+    }
+}
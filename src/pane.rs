@@ -0,0 +1,357 @@
+use anyhow::{Context, Result};
+use crossterm::{
+    cursor,
+    style::{Attribute, Print, SetAttribute},
+    QueueableCommand,
+};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{stdout, Read, Write};
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::config::{QueryPolicies, QueryPolicy, ShellConfig, TerminalConfig, WaybackConfig};
+use crate::output::OutputProcessor;
+use crate::pty::PtySession;
+use crate::window::Style;
+
+/// Where the auxiliary pane goes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SplitDirection {
+    /// Below the shell.
+    #[default]
+    Horizontal,
+    /// To the right of the shell.
+    Vertical,
+}
+
+/// A screen area in 0-based cells.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+/// How the screen is shared between the shell and the pane.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Layout {
+    pub direction: SplitDirection,
+    /// The area left to the shell.
+    pub main: Rect,
+    /// The area of the pane, including its title row.
+    pub pane: Rect,
+}
+
+impl Layout {
+    /// Splits a `(cols, rows)` terminal, giving `percent` of it to the pane.
+    /// Returns None if the terminal is too small for both.
+    pub fn split(size: (u16, u16), direction: SplitDirection, percent: u16) -> Option<Layout> {
+        let (cols, rows) = size;
+        let share = |total: u16, min: u16| -> Option<u16> {
+            let part = ((total as u32 * percent.clamp(10, 90) as u32) / 100) as u16;
+            let part = part.max(min);
+            (total >= part + min).then_some(part)
+        };
+        match direction {
+            SplitDirection::Horizontal => {
+                let height = share(rows, 3)?;
+                Some(Layout {
+                    direction,
+                    main: Rect { x: 0, y: 0, width: cols, height: rows - height },
+                    pane: Rect { x: 0, y: rows - height, width: cols, height },
+                })
+            }
+            SplitDirection::Vertical => {
+                let width = share(cols, 12)?;
+                Some(Layout {
+                    direction,
+                    main: Rect { x: 0, y: 0, width: cols - width, height: rows },
+                    pane: Rect { x: cols - width, y: 0, width, height: rows },
+                })
+            }
+        }
+    }
+
+    /// Where the pane's output goes: below its title row and, in a vertical
+    /// split, right of the separator.
+    pub fn content(&self) -> Rect {
+        let indent = match self.direction {
+            SplitDirection::Horizontal => 0,
+            SplitDirection::Vertical => 1,
+        };
+        Rect {
+            x: self.pane.x + indent,
+            y: self.pane.y + 1,
+            width: self.pane.width - indent,
+            height: self.pane.height - 1,
+        }
+    }
+
+    /// Keeps the shell's output inside its area: a scroll region for a
+    /// horizontal split, left/right margins (DECLRMM) for a vertical one.
+    pub fn confine_sequence(&self) -> String {
+        match self.direction {
+            SplitDirection::Horizontal => format!("\x1b7\x1b[1;{}r\x1b8", self.main.height),
+            SplitDirection::Vertical => format!("\x1b7\x1b[?69h\x1b[1;{}s\x1b8", self.main.width),
+        }
+    }
+
+    /// Gives the whole screen back to the shell.
+    pub fn release_sequence(&self) -> &'static str {
+        match self.direction {
+            SplitDirection::Horizontal => "\x1b7\x1b[r\x1b8",
+            SplitDirection::Vertical => "\x1b7\x1b[s\x1b[?69l\x1b8",
+        }
+    }
+
+    /// Rewrites shell output that would undo `confine_sequence`. The shell
+    /// only knows its own size, so resetting its scroll region must not
+    /// reach the pane; with margins enabled `CSI s` no longer saves the
+    /// cursor, so cursor saves are sent in their ESC form.
+    pub fn confine_output(&self, display: &[u8]) -> Vec<u8> {
+        let mut output = match self.direction {
+            SplitDirection::Horizontal => {
+                let region = format!("\x1b[1;{}r", self.main.height);
+                replace_bytes(display, b"\x1b[r", region.as_bytes())
+            }
+            SplitDirection::Vertical => {
+                let output = replace_bytes(display, b"\x1b[s", b"\x1b7");
+                replace_bytes(&output, b"\x1b[u", b"\x1b8")
+            }
+        };
+        // Full and soft resets drop margins altogether
+        if contains_bytes(display, b"\x1bc") || contains_bytes(display, b"\x1b[!p") {
+            output.extend(self.confine_sequence().into_bytes());
+        }
+        output
+    }
+}
+
+fn contains_bytes(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|window| window == needle)
+}
+
+fn replace_bytes(haystack: &[u8], from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut output = Vec::with_capacity(haystack.len());
+    let mut i = 0;
+    while i < haystack.len() {
+        if haystack[i..].starts_with(from) {
+            output.extend_from_slice(to);
+            i += from.len();
+        } else {
+            output.push(haystack[i]);
+            i += 1;
+        }
+    }
+    output
+}
+
+/// A second program shown next to the shell. Its output is kept as plain
+/// lines, which suits log tails and simple commands rather than full-screen
+/// programs.
+#[derive(Debug)]
+pub struct Pane {
+    pub title: String,
+    pub focused: bool,
+    pub layout: Layout,
+    pub style: Style,
+    pty: PtySession,
+    writer: File,
+    output: OutputProcessor,
+}
+
+impl Pane {
+    /// Starts `shell_config` in a new PTY sized to the pane. Its output is
+    /// sent to `output_tx`, to be fed back through `process`.
+    pub fn open(
+        shell_config: &ShellConfig,
+        terminal: &TerminalConfig,
+        layout: Layout,
+        output_tx: UnboundedSender<Vec<u8>>,
+    ) -> Result<Self> {
+        let pty = PtySession::spawn(shell_config)
+            .with_context(|| format!("Failed to start pane command: {}", shell_config.command))?;
+        let content = layout.content();
+        pty.resize_pty(content.height, content.width)?;
+
+        let mut reader = pty.try_clone_master()?;
+        std::thread::spawn(move || {
+            let mut buffer = [0u8; 4096];
+            // Reading fails once the pane is closed and its PTY goes away
+            while let Ok(n) = reader.read(&mut buffer) {
+                if n == 0 || output_tx.send(buffer[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+
+        // Nothing of the pane reaches the outer terminal, so answer its
+        // queries here. The cursor position would be the shell's, not ours.
+        let terminal = TerminalConfig {
+            queries: QueryPolicies {
+                enq: QueryPolicy::Spoof,
+                primary_da: QueryPolicy::Spoof,
+                secondary_da: QueryPolicy::Spoof,
+                status_report: QueryPolicy::Spoof,
+                cursor_position: QueryPolicy::Drop,
+            },
+            ..terminal.clone()
+        };
+        let mut output = OutputProcessor::new(terminal);
+        // builtin:wayback only shows the shell's screens
+        output.set_wayback(&WaybackConfig { keep: 0, ..Default::default() });
+
+        Ok(Pane {
+            title: std::iter::once(shell_config.command.as_str())
+                .chain(shell_config.args.iter().map(String::as_str))
+                .collect::<Vec<_>>()
+                .join(" "),
+            focused: false,
+            writer: pty.try_clone_master()?,
+            pty,
+            layout,
+            style: Style::default(),
+            output,
+        })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)
+            .with_context(|| "Failed to write to pane")
+    }
+
+    /// Takes in output of the pane's program.
+    pub fn process(&mut self, data: &[u8]) -> Result<()> {
+        let processed = self.output.process(data);
+        if !processed.replies.is_empty() {
+            self.write(&processed.replies)?;
+        }
+        Ok(())
+    }
+
+    /// Shows a hook's `text` under its `title`, after what the pane's
+    /// program printed.
+    pub fn print(&mut self, title: &str, text: &str) -> Result<()> {
+        let text = format!("\r\n[{}]\r\n{}\r\n", title, text.trim_end().replace('\n', "\r\n"));
+        self.process(text.as_bytes())
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.pty.is_child_alive()
+    }
+
+    pub fn set_layout(&mut self, layout: Layout) -> Result<()> {
+        let content = layout.content();
+        self.pty.resize_pty(content.height, content.width)?;
+        self.layout = layout;
+        Ok(())
+    }
+
+    /// The last lines of output, wrapped to fit the pane.
+    pub fn visible_lines(&self) -> Vec<String> {
+        let Rect { width, height, .. } = self.layout.content();
+        let mut lines = self.output.recent_lines();
+        lines.push(self.output.current_line());
+
+        let mut rows: Vec<String> = Vec::new();
+        for line in lines.iter().rev() {
+            let chars: Vec<char> = line.chars().collect();
+            let mut wrapped: Vec<String> = chars
+                .chunks(width.max(1) as usize)
+                .map(|chunk| chunk.iter().collect())
+                .collect();
+            if wrapped.is_empty() {
+                wrapped.push(String::new());
+            }
+            rows.extend(wrapped.into_iter().rev());
+            if rows.len() >= height as usize {
+                break;
+            }
+        }
+        rows.truncate(height as usize);
+        rows.reverse();
+        rows
+    }
+
+    pub fn draw(&self) -> Result<()> {
+        let mut stdout = stdout();
+        let pane = self.layout.pane;
+        let content = self.layout.content();
+        let borders = self.style.borders();
+        stdout.queue(cursor::SavePosition)?;
+
+        let focus = if self.focused { " [focus]" } else { "" };
+        let mut title: String = format!("{} {}{} ", borders.horizontal, self.title, focus)
+            .chars()
+            .take(pane.width as usize)
+            .collect();
+        while title.chars().count() < pane.width as usize {
+            title.push_str(borders.horizontal);
+        }
+        stdout.queue(cursor::MoveTo(pane.x, pane.y))?;
+        if self.focused && !self.style.plain {
+            stdout.queue(SetAttribute(Attribute::Reverse))?;
+        }
+        stdout.queue(Print(title))?;
+        stdout.queue(SetAttribute(Attribute::Reset))?;
+
+        let lines = self.visible_lines();
+        for row in 0..content.height {
+            if self.layout.direction == SplitDirection::Vertical {
+                stdout.queue(cursor::MoveTo(pane.x, content.y + row))?;
+                stdout.queue(Print(borders.vertical))?;
+            }
+            let line = lines.get(row as usize).map(String::as_str).unwrap_or("");
+            let padding = (content.width as usize).saturating_sub(line.chars().count());
+            stdout.queue(cursor::MoveTo(content.x, content.y + row))?;
+            stdout.queue(Print(format!("{}{}", line, " ".repeat(padding))))?;
+        }
+
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Blanks the pane's area.
+    pub fn clear(&self) -> Result<()> {
+        let mut stdout = stdout();
+        let pane = self.layout.pane;
+        stdout.queue(cursor::SavePosition)?;
+        for row in 0..pane.height {
+            stdout.queue(cursor::MoveTo(pane.x, pane.y + row))?;
+            stdout.queue(Print(" ".repeat(pane.width as usize)))?;
+        }
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_layout() {
+        let layout = Layout::split((80, 24), SplitDirection::Horizontal, 25).unwrap();
+        assert_eq!(layout.main, Rect { x: 0, y: 0, width: 80, height: 18 });
+        assert_eq!(layout.content(), Rect { x: 0, y: 19, width: 80, height: 5 });
+
+        let layout = Layout::split((80, 24), SplitDirection::Vertical, 50).unwrap();
+        assert_eq!(layout.main.width, 40);
+        assert_eq!(layout.content(), Rect { x: 41, y: 1, width: 39, height: 23 });
+
+        assert_eq!(Layout::split((80, 5), SplitDirection::Horizontal, 30), None);
+    }
+
+    #[test]
+    fn test_confine_output() {
+        let layout = Layout::split((80, 24), SplitDirection::Horizontal, 25).unwrap();
+        assert_eq!(layout.confine_output(b"a\x1b[rb"), b"a\x1b[1;18rb");
+
+        let layout = Layout::split((80, 24), SplitDirection::Vertical, 25).unwrap();
+        assert_eq!(layout.confine_output(b"\x1b[sx\x1b[u"), b"\x1b7x\x1b8");
+    }
+}
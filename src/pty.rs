@@ -2,11 +2,21 @@ use nix::pty::forkpty;
 use nix::unistd::ForkResult;
 use nix::sys::signal::{self, Signal};
 use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
-use nix::unistd::{execvp, Pid};
-use std::ffi::CString;
+use nix::errno::Errno;
+use nix::libc::c_char;
+use nix::unistd::{tcgetpgrp, Pid};
+use std::ffi::{CString, OsString};
+use std::fs::File;
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::io::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
 use anyhow::{Context, Result};
+use crate::backend::Backend;
 use crate::config::ShellConfig;
+use crate::history::process_cwd;
+use crate::shell::{terminal_env, terminfo_known};
+use crate::ssh;
 
 #[derive(Debug)]
 pub struct PtySession {
@@ -14,8 +24,100 @@ pub struct PtySession {
     pub child_pid: Pid,
 }
 
+/// Everything the forked child needs to start the shell, worked out
+/// before the fork. The child of a multithreaded process may only make
+/// async-signal-safe calls until it execs: no allocating, and no touching
+/// the environment another thread may have locked.
+struct ShellExec {
+    program: CString,
+    args: Vec<CString>,
+    env: Vec<CString>,
+    /// Written to the PTY, followed by the reason, if the exec fails.
+    failure: String,
+}
+
+impl ShellExec {
+    fn new(shell_config: &ShellConfig, vars: impl IntoIterator<Item = (OsString, OsString)>) -> Result<Self> {
+        // Leave out what the policy doesn't pass on
+        let mut env: Vec<(OsString, OsString)> = vars.into_iter()
+            .filter(|(key, _)| shell_config.env_policy.keeps(&key.to_string_lossy()))
+            .collect();
+        let var = |env: &[(OsString, OsString)], key: &str| {
+            env.iter().find(|(k, _)| k == key).map(|(_, value)| value.to_string_lossy().into_owned())
+        };
+        let set = |env: &mut Vec<(OsString, OsString)>, key: &str, value: OsString| {
+            env.retain(|(k, _)| k != key);
+            env.push((key.into(), value));
+        };
+
+        // A TERM the shell can draw with and a locale, unless env sets them
+        let term_override = shell_config.term_override.as_deref();
+        for (key, value) in terminal_env(|key| var(&env, key), terminfo_known, term_override) {
+            set(&mut env, key, value.into());
+        }
+
+        // Set environment variables if specified
+        if let Some(vars) = &shell_config.env {
+            for (key, value) in vars {
+                set(&mut env, key, value.into());
+            }
+        }
+
+        // Applied last so a PATH from env keeps the wrapper in front
+        if shell_config.ssh_wrapper {
+            let dir = ssh::default_wrapper_dir();
+            let path = match var(&env, "PATH") {
+                Some(path) => format!("{}:{}", dir.display(), path),
+                None => dir.display().to_string(),
+            };
+            set(&mut env, "PATH", path.into());
+        }
+
+        // Looked up on the shell's PATH, as execvp would have after the
+        // variables above were set
+        let program = match shell_config.command.contains('/') {
+            true => PathBuf::from(&shell_config.command),
+            false => var(&env, "PATH").unwrap_or_else(|| "/usr/bin:/bin".to_string())
+                .split(':')
+                .map(|dir| Path::new(if dir.is_empty() { "." } else { dir }).join(&shell_config.command))
+                .find(|path| path.metadata().is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0))
+                .unwrap_or_else(|| PathBuf::from(&shell_config.command)),
+        };
+        let program = CString::new(program.into_os_string().into_vec())
+            .with_context(|| "Invalid shell command")?;
+
+        let mut args: Vec<CString> = Vec::new();
+        // argv[0] should be the command itself
+        args.push(CString::new(shell_config.command.clone()).with_context(|| "Invalid shell command")?);
+        for arg in &shell_config.args {
+            args.push(CString::new(arg.clone())
+                .with_context(|| format!("Invalid argument: {}", arg))?);
+        }
+
+        let env = env.into_iter()
+            .map(|(key, value)| {
+                let mut entry = key.into_vec();
+                entry.push(b'=');
+                entry.extend(value.into_vec());
+                CString::new(entry).with_context(|| "Invalid environment variable")
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(ShellExec {
+            program,
+            args,
+            env,
+            failure: format!("chatshell: Failed to execute shell: {}: ", shell_config.command),
+        })
+    }
+}
+
 impl PtySession {
     pub fn spawn(shell_config: &ShellConfig) -> Result<Self> {
+        let exec = ShellExec::new(shell_config, std::env::vars_os())?;
+        let args: Vec<*const c_char> = exec.args.iter().map(|arg| arg.as_ptr()).chain([std::ptr::null()]).collect();
+        let env: Vec<*const c_char> = exec.env.iter().map(|entry| entry.as_ptr()).chain([std::ptr::null()]).collect();
+
         // Create PTY pair
         let fork_result = unsafe { forkpty(None, None) };
 
@@ -30,12 +132,17 @@ impl PtySession {
                         })
                     }
                     ForkResult::Child => {
-                        // Child process - exec the shell
-                        Self::exec_shell(shell_config)
-                            .with_context(|| "Failed to exec shell")?;
-                        
-                        // This should never be reached
-                        std::process::exit(1);
+                        // Child process - exec the shell. Returning from here
+                        // would leave a second chatshell running in the PTY,
+                        // so a failure is reported on its stderr instead
+                        unsafe {
+                            nix::libc::execve(exec.program.as_ptr(), args.as_ptr(), env.as_ptr());
+                            let reason = Errno::last().desc();
+                            for part in [exec.failure.as_str(), reason, "\r\n"] {
+                                nix::libc::write(2, part.as_ptr().cast(), part.len());
+                            }
+                            nix::libc::_exit(127);
+                        }
                     }
                 }
             }
@@ -43,44 +150,23 @@ impl PtySession {
         }
     }
 
-    fn exec_shell(shell_config: &ShellConfig) -> Result<()> {
-        // Set environment variables if specified
-        if let Some(env) = &shell_config.env {
-            for (key, value) in env {
-                std::env::set_var(key, value);
-            }
-        }
-
-        // Prepare command and arguments
-        let command = CString::new(shell_config.command.clone())
-            .with_context(|| "Invalid shell command")?;
-        
-        let mut args: Vec<CString> = Vec::new();
-        args.push(command.clone()); // argv[0] should be the command itself
-        
-        for arg in &shell_config.args {
-            args.push(CString::new(arg.clone())
-                .with_context(|| format!("Invalid argument: {}", arg))?);
-        }
-
-        // Execute the shell
-        execvp(&command, &args)
-            .with_context(|| format!("Failed to execute shell: {}", shell_config.command))?;
-        
-        Ok(())
+    /// Returns an independently owned handle to the PTY master for I/O tasks,
+    /// so they never touch a descriptor that has been closed under them.
+    pub fn try_clone_master(&self) -> Result<File> {
+        let fd = self.master.try_clone()
+            .with_context(|| "Failed to duplicate PTY master")?;
+        Ok(File::from(fd))
     }
 
     pub fn write_to_shell(&self, data: &[u8]) -> Result<usize> {
         use nix::unistd::write;
         write(self.master.as_raw_fd(), data)
-            .map(|n| n as usize)
             .with_context(|| "Failed to write to shell")
     }
 
     pub fn read_from_shell(&self, buffer: &mut [u8]) -> Result<usize> {
         use nix::unistd::read;
         read(self.master.as_raw_fd(), buffer)
-            .map(|n| n as usize)
             .with_context(|| "Failed to read from shell")
     }
 
@@ -105,39 +191,101 @@ impl PtySession {
         }
     }
 
+    /// Sends SIGWINCH to the process group that has the terminal: the
+    /// shell's, or that of the program it runs. The kernel only sends it
+    /// when the size changed, so this makes programs redraw either way.
+    pub fn signal_resize(&self) -> Result<()> {
+        // Before the shell has set up its session there is no group to tell,
+        // and group 0 would mean our own
+        let Ok(group) = tcgetpgrp(self.master.as_raw_fd()) else {
+            return Ok(());
+        };
+        if group.as_raw() <= 0 {
+            return Ok(());
+        }
+        signal::killpg(group, Signal::SIGWINCH)
+            .with_context(|| "Failed to send SIGWINCH to the shell")
+    }
+
     pub fn send_signal(&self, signal: Signal) -> Result<()> {
         signal::kill(self.child_pid, signal)
             .with_context(|| format!("Failed to send signal {:?} to child process", signal))?;
         Ok(())
     }
 
-    pub fn is_child_alive(&self) -> bool {
-        match waitpid(self.child_pid, Some(WaitPidFlag::WNOHANG)) {
-            Ok(WaitStatus::StillAlive) => true,
-            _ => false,
+    /// True unless a program the shell started has the terminal. Assumed
+    /// true if the system doesn't tell.
+    pub fn shell_in_foreground(&self) -> bool {
+        match tcgetpgrp(self.master.as_raw_fd()) {
+            Ok(group) => group == self.child_pid,
+            Err(_) => true,
         }
     }
 
+    pub fn is_child_alive(&self) -> bool {
+        matches!(
+            waitpid(self.child_pid, Some(WaitPidFlag::WNOHANG)),
+            Ok(WaitStatus::StillAlive)
+        )
+    }
+
     pub fn wait_for_child(&self) -> Result<WaitStatus> {
         waitpid(self.child_pid, None)
             .with_context(|| "Failed to wait for child process")
     }
 }
 
-impl Drop for PtySession {
-    fn drop(&mut self) {
-        // Try to terminate the child process gracefully
+impl Backend for PtySession {
+    fn try_clone_io(&self) -> Result<File> {
+        self.try_clone_master()
+    }
+
+    fn resize(&self, rows: u16, cols: u16) -> Result<()> {
+        self.resize_pty(rows, cols)?;
+        // The size is set even if the group has just gone away
+        let _ = self.signal_resize();
+        Ok(())
+    }
+
+    fn is_alive(&self) -> bool {
+        self.is_child_alive()
+    }
+
+    fn in_foreground(&self) -> bool {
+        self.shell_in_foreground()
+    }
+
+    fn cwd(&self) -> Option<PathBuf> {
+        process_cwd(self.child_pid.as_raw())
+    }
+
+    fn pid(&self) -> Option<i32> {
+        Some(self.child_pid.as_raw())
+    }
+
+    fn foreground_pid(&self) -> Option<i32> {
+        tcgetpgrp(self.master.as_raw_fd()).ok().map(Pid::as_raw).filter(|pid| *pid > 0)
+    }
+
+    fn terminate(&mut self) {
         if self.is_child_alive() {
             let _ = self.send_signal(Signal::SIGTERM);
-            
+
             // Give it a moment to terminate
             std::thread::sleep(std::time::Duration::from_millis(100));
-            
+
             // Force kill if still alive
             if self.is_child_alive() {
                 let _ = self.send_signal(Signal::SIGKILL);
             }
         }
+    }
+}
+
+impl Drop for PtySession {
+    fn drop(&mut self) {
+        // Try to terminate the child process gracefully
+        self.terminate();
         
         // PtyMaster will automatically close the file descriptor when dropped
     }
@@ -147,6 +295,7 @@ impl Drop for PtySession {
 mod tests {
     use super::*;
     use crate::config::ShellConfig;
+    use std::collections::HashMap;
 
     #[test]
     fn test_pty_creation() {
@@ -154,9 +303,56 @@ mod tests {
             command: "/bin/echo".to_string(),
             args: vec!["hello".to_string()],
             env: None,
+            ..Default::default()
         };
 
         let pty = PtySession::spawn(&shell_config);
         assert!(pty.is_ok());
     }
+
+    #[test]
+    fn test_shell_exec_prepared_before_fork() {
+        let mut env = HashMap::new();
+        env.insert("EDITOR".to_string(), "vi".to_string());
+        let shell_config = ShellConfig {
+            command: "sh".to_string(),
+            args: vec!["-l".to_string()],
+            env: Some(env),
+            ..Default::default()
+        };
+        let vars = [("PATH", "/bin"), ("TERM", "xterm"), ("LANG", "de_DE.UTF-8")]
+            .map(|(key, value)| (OsString::from(key), OsString::from(value)));
+        let exec = ShellExec::new(&shell_config, vars).unwrap();
+
+        assert_eq!(exec.program.to_str().unwrap(), "/bin/sh");
+        assert_eq!(exec.args, [CString::new("sh").unwrap(), CString::new("-l").unwrap()]);
+        let env: Vec<&str> = exec.env.iter().map(|entry| entry.to_str().unwrap()).collect();
+        assert!(env.contains(&"PATH=/bin"));
+        assert!(env.contains(&"EDITOR=vi"));
+        assert!(env.contains(&"LANG=de_DE.UTF-8"));
+
+        let shell_config = ShellConfig { args: vec!["nul\0".to_string()], ..shell_config };
+        assert!(ShellExec::new(&shell_config, []).is_err());
+    }
+
+    #[test]
+    fn test_pty_exec_failure_stays_in_the_child() {
+        let shell_config = ShellConfig {
+            command: "/nonexistent/shell".to_string(),
+            ..Default::default()
+        };
+
+        // Only the parent gets here; the child reports and exits
+        let pty = PtySession::spawn(&shell_config).unwrap();
+        let mut output = Vec::new();
+        let mut buffer = [0u8; 256];
+        while let Ok(n) = nix::unistd::read(pty.master.as_raw_fd(), &mut buffer) {
+            if n == 0 {
+                break;
+            }
+            output.extend_from_slice(&buffer[..n]);
+        }
+        assert!(String::from_utf8_lossy(&output).contains("Failed to execute shell: /nonexistent/shell"));
+        assert_eq!(pty.wait_for_child().unwrap(), WaitStatus::Exited(pty.child_pid, 127));
+    }
 }
\ No newline at end of file
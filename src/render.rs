@@ -0,0 +1,135 @@
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
+
+/// Frames per second used when none is configured.
+pub const DEFAULT_MAX_FPS: u32 = 30;
+
+/// A part of the screen chatshell draws over the shell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Region {
+    /// The split pane or the chat view, border and content.
+    Pane,
+    /// The watch panel, which something else drew over.
+    WatchPanel,
+    /// Only the lines of the watch panel, after a new output.
+    WatchContent,
+    /// The notes pinned from popups.
+    Notes,
+}
+
+/// Collects what needs drawing again and decides when to draw it, so fast
+/// output costs one redraw per frame rather than one per change.
+#[derive(Debug)]
+pub struct Renderer {
+    dirty: BTreeSet<Region>,
+    // Zero draws as soon as something changes
+    frame: Duration,
+    last_frame: Option<Instant>,
+    // In low-bandwidth mode, shell output must stop this long first
+    settle: Option<Duration>,
+    shell_output_at: Option<Instant>,
+}
+
+impl Renderer {
+    /// A renderer drawing at most `max_fps` frames a second; 0 doesn't limit.
+    pub fn new(max_fps: u32) -> Self {
+        let frame = if max_fps == 0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs(1) / max_fps
+        };
+        Renderer {
+            dirty: BTreeSet::new(),
+            frame,
+            last_frame: None,
+            settle: None,
+            shell_output_at: None,
+        }
+    }
+
+    /// Holds redraws back until shell output has stopped for `settle`.
+    pub fn set_settle(&mut self, settle: Option<Duration>) {
+        self.settle = settle;
+    }
+
+    pub fn mark(&mut self, region: Region) {
+        self.dirty.insert(region);
+    }
+
+    /// Shell output may have scrolled or cleared anything drawn over it.
+    pub fn mark_shell_output(&mut self, now: Instant) {
+        self.dirty.insert(Region::Pane);
+        self.dirty.insert(Region::WatchPanel);
+        self.dirty.insert(Region::Notes);
+        self.shell_output_at = Some(now);
+    }
+
+    /// How long until the next frame is due, or None if nothing is dirty.
+    pub fn next_frame(&self, now: Instant) -> Option<Duration> {
+        if self.dirty.is_empty() {
+            return None;
+        }
+        let mut due = self.last_frame.map_or(now, |last| last + self.frame);
+        if let (Some(settle), Some(output_at)) = (self.settle, self.shell_output_at) {
+            due = due.max(output_at + settle);
+        }
+        Some(due.saturating_duration_since(now))
+    }
+
+    /// The regions to draw, if a frame is due. A redrawn panel needs no
+    /// separate content update.
+    pub fn take_frame(&mut self, now: Instant) -> Option<BTreeSet<Region>> {
+        if self.next_frame(now)? > Duration::ZERO {
+            return None;
+        }
+        let mut regions = std::mem::take(&mut self.dirty);
+        if regions.contains(&Region::WatchPanel) {
+            regions.remove(&Region::WatchContent);
+        }
+        self.last_frame = Some(now);
+        self.shell_output_at = None;
+        Some(regions)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frames_are_capped() {
+        let start = Instant::now();
+        let mut renderer = Renderer::new(10);
+        assert_eq!(renderer.next_frame(start), None);
+
+        renderer.mark(Region::Pane);
+        assert_eq!(renderer.take_frame(start), Some(BTreeSet::from([Region::Pane])));
+
+        // Changes within the frame are drawn together at its end
+        renderer.mark(Region::Pane);
+        renderer.mark(Region::WatchContent);
+        assert_eq!(renderer.next_frame(start + Duration::from_millis(40)), Some(Duration::from_millis(60)));
+        assert_eq!(renderer.take_frame(start + Duration::from_millis(40)), None);
+        renderer.mark_shell_output(start + Duration::from_millis(50));
+        assert_eq!(
+            renderer.take_frame(start + Duration::from_millis(100)),
+            Some(BTreeSet::from([Region::Pane, Region::WatchPanel, Region::Notes]))
+        );
+        assert_eq!(renderer.next_frame(start + Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn test_settle_waits_for_quiet_output() {
+        let start = Instant::now();
+        let mut renderer = Renderer::new(0);
+        renderer.set_settle(Some(Duration::from_millis(500)));
+        renderer.mark_shell_output(start);
+        renderer.mark_shell_output(start + Duration::from_millis(300));
+        assert_eq!(renderer.take_frame(start + Duration::from_millis(600)), None);
+        assert!(renderer.take_frame(start + Duration::from_millis(800)).is_some());
+
+        // Other changes aren't held back once the output has settled
+        renderer.mark(Region::WatchContent);
+        assert_eq!(renderer.next_frame(start + Duration::from_millis(900)), Some(Duration::ZERO));
+    }
+}
@@ -0,0 +1,500 @@
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::shell::ShellDialect;
+use crate::i18n::tr;
+
+/// A language interpreter wrapped instead of a shell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReplKind {
+    Python,
+    Node,
+    Psql,
+    Sqlite,
+    Mysql,
+}
+
+impl ReplKind {
+    pub fn detect(command: &str) -> Option<Self> {
+        let name = Path::new(command)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        // python3.12, python3, ipython, ...
+        let base = name.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+        match base {
+            "python" | "ipython" | "bpython" | "ptpython" => Some(ReplKind::Python),
+            "node" | "nodejs" | "deno" => Some(ReplKind::Node),
+            "psql" | "pgcli" => Some(ReplKind::Psql),
+            "sqlite" => Some(ReplKind::Sqlite),
+            "mysql" | "mariadb" | "mycli" => Some(ReplKind::Mysql),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReplKind::Python => "Python",
+            ReplKind::Node => "Node.js",
+            ReplKind::Psql => "psql (PostgreSQL)",
+            ReplKind::Sqlite => "sqlite3",
+            ReplKind::Mysql => "mysql",
+        }
+    }
+
+    fn is_sql(&self) -> bool {
+        matches!(self, ReplKind::Psql | ReplKind::Sqlite | ReplKind::Mysql)
+    }
+
+    /// Prompts the REPL prints while a command is still being entered.
+    pub fn continuation_prompts(&self) -> &'static [&'static str] {
+        match self {
+            ReplKind::Python => &["... ", "...: "],
+            ReplKind::Node => &["... ", "| "],
+            // psql shows the bracket or quote left open: db-# db(# db'#
+            ReplKind::Psql => &["-# ", "(# ", "'# ", "\"# ", "$# "],
+            ReplKind::Sqlite => &["...> "],
+            ReplKind::Mysql => &["    -> ", "    '> ", "    \"> "],
+        }
+    }
+}
+
+/// What the session is running, which decides how suggestions are phrased,
+/// split into commands and checked before being typed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SessionMode {
+    Shell(ShellDialect),
+    Repl(ReplKind),
+    /// The console of a device on a serial line, such as a bootloader or
+    /// the shell of an embedded system.
+    Device,
+}
+
+impl Default for SessionMode {
+    fn default() -> Self {
+        SessionMode::Shell(ShellDialect::Posix)
+    }
+}
+
+impl SessionMode {
+    pub fn detect(command: &str, repl: Option<ReplKind>) -> Self {
+        match repl.or_else(|| ReplKind::detect(command)) {
+            Some(kind) => SessionMode::Repl(kind),
+            None => SessionMode::Shell(ShellDialect::detect(command)),
+        }
+    }
+
+    pub fn system_prompt(&self) -> String {
+        let (setting, answer) = match self {
+            SessionMode::Shell(ShellDialect::Fish) => ("a fish shell", "fish commands"),
+            SessionMode::Shell(ShellDialect::Nu) => ("a Nushell session", "Nushell commands"),
+            SessionMode::Shell(ShellDialect::Csh) => ("a csh/tcsh shell", "csh commands"),
+            SessionMode::Shell(ShellDialect::Posix) => ("a POSIX shell (bash/zsh)", "shell commands"),
+            SessionMode::Repl(ReplKind::Python) => (
+                "an interactive Python REPL, not a shell",
+                "Python statements to type at the >>> prompt",
+            ),
+            SessionMode::Repl(ReplKind::Node) => (
+                "an interactive Node.js REPL, not a shell",
+                "JavaScript to type at the > prompt",
+            ),
+            SessionMode::Repl(ReplKind::Psql) => (
+                "psql connected to a PostgreSQL database, not a shell",
+                "SQL statements terminated with ';' or psql backslash commands",
+            ),
+            SessionMode::Repl(ReplKind::Sqlite) => (
+                "the sqlite3 shell, not a system shell",
+                "SQL statements terminated with ';' or sqlite3 dot-commands",
+            ),
+            SessionMode::Repl(ReplKind::Mysql) => (
+                "the mysql client connected to a database, not a shell",
+                "SQL statements terminated with ';'",
+            ),
+            SessionMode::Device => (
+                "the serial console of an embedded device, such as a bootloader \
+                 (U-Boot) or a firmware or embedded Linux shell, not a local shell",
+                "commands for the console the output shows",
+            ),
+        };
+
+        format!(
+            "You are an assistant embedded in a terminal. The user is working in {}. \
+             Answer briefly. When suggesting something to run, suggest {} and put \
+             exactly what should be typed in a single fenced code block.",
+            setting, answer
+        )
+    }
+
+    /// The database client the session runs, if it runs one.
+    pub fn database(&self) -> Option<ReplKind> {
+        match self {
+            SessionMode::Repl(kind @ (ReplKind::Psql | ReplKind::Mysql | ReplKind::Sqlite)) => Some(*kind),
+            _ => None,
+        }
+    }
+
+    /// True if `line` (the text after the last newline of the output) is a
+    /// continuation prompt, i.e. a command is half-entered.
+    pub fn at_continuation(&self, line: &str) -> bool {
+        match self {
+            SessionMode::Shell(_) | SessionMode::Device => line == "> ",
+            SessionMode::Repl(kind) => kind
+                .continuation_prompts()
+                .iter()
+                .any(|prompt| line.ends_with(prompt) && !line.trim().is_empty()),
+        }
+    }
+
+    /// Splits a suggestion into the units the session executes one at a time.
+    pub fn split_commands(&self, text: &str) -> Vec<String> {
+        match self {
+            SessionMode::Shell(_) | SessionMode::Device => split_lines_with_continuations(text),
+            SessionMode::Repl(ReplKind::Python) => split_python(text),
+            SessionMode::Repl(ReplKind::Node) => split_balanced(text),
+            SessionMode::Repl(kind) if kind.is_sql() => split_sql(text),
+            SessionMode::Repl(_) => split_lines_with_continuations(text),
+        }
+    }
+
+    /// The keystrokes that type and run one command unit.
+    pub fn command_input(&self, command: &str) -> String {
+        let mut input = command.replace('\n', "\r");
+        input.push('\r');
+        // A compound Python statement is only run after an empty line
+        if *self == SessionMode::Repl(ReplKind::Python) && command.contains('\n') {
+            input.push('\r');
+        }
+        input
+    }
+
+    /// The keystrokes that run `commands` with their output between the
+    /// `begin` and `end` marker lines. The markers are printed through an
+    /// escape sequence, so the echo of what is typed never contains them.
+    pub fn marked_input(&self, commands: &[String], begin: &str, end: &str) -> String {
+        let print = |marker: &str| {
+            let octal = escape_first_letter(marker, |c| format!("\\{:03o}", c as u32));
+            let hex = escape_first_letter(marker, |c| format!("\\x{:02x}", c as u32));
+            match self {
+                SessionMode::Shell(ShellDialect::Nu) => {
+                    let unicode = escape_first_letter(marker, |c| format!("\\u{{{:x}}}", c as u32));
+                    format!("print \"{}\"", unicode)
+                }
+                // Embedded Linux consoles are mostly busybox sh
+                SessionMode::Shell(_) | SessionMode::Device => format!("printf '{}\\n'", octal),
+                SessionMode::Repl(ReplKind::Python) => format!("print('{}')", octal),
+                SessionMode::Repl(ReplKind::Node) => format!("console.log('{}')", hex),
+                SessionMode::Repl(ReplKind::Psql) => format!("\\echo '{}'", octal),
+                SessionMode::Repl(ReplKind::Sqlite) => format!(".print \"{}\"", octal),
+                SessionMode::Repl(ReplKind::Mysql) => format!("\\! printf '{}\\n'", octal),
+            }
+        };
+
+        if let SessionMode::Shell(_) | SessionMode::Device = self {
+            // One line, so nothing the user types in between lands inside
+            return format!("{}; {}; {}\r", print(begin), commands.join("; "), print(end));
+        }
+        let mut input = self.command_input(&print(begin));
+        for command in commands {
+            input.push_str(&self.command_input(command));
+        }
+        input.push_str(&self.command_input(&print(end)));
+        input
+    }
+
+    /// Why running `command` deserves a confirmation, if it does, in the
+    /// user's language.
+    pub fn risk(&self, command: &str) -> Option<String> {
+        let normalized = command.split_whitespace().collect::<Vec<_>>().join(" ").to_lowercase();
+        let rules: &[(&str, &str)] = match self {
+            SessionMode::Shell(_) => &[
+                ("rm -rf", "risk-deletes-recursively"),
+                ("rm -fr", "risk-deletes-recursively"),
+                ("mkfs", "risk-formats-filesystem"),
+                ("of=/dev/", "risk-writes-raw-device"),
+                ("> /dev/sd", "risk-writes-raw-device"),
+                (":(){", "risk-fork-bomb"),
+                ("chmod -r 777", "risk-opens-permissions"),
+                ("--force", "risk-forces"),
+                ("shutdown", "risk-shuts-down"),
+                ("reboot", "risk-reboots"),
+            ],
+            SessionMode::Repl(ReplKind::Python) => &[
+                ("shutil.rmtree", "risk-deletes-tree"),
+                ("os.remove", "risk-deletes-files"),
+                ("os.unlink", "risk-deletes-files"),
+                ("os.system", "risk-runs-shell"),
+                ("subprocess", "risk-runs-shell"),
+            ],
+            SessionMode::Repl(ReplKind::Node) => &[
+                ("child_process", "risk-runs-shell"),
+                ("fs.rm", "risk-deletes-files"),
+                ("fs.unlink", "risk-deletes-files"),
+                ("process.exit", "risk-exits-repl"),
+            ],
+            SessionMode::Device => &[
+                ("erase", "risk-erases-flash"),
+                ("saveenv", "risk-saves-environment"),
+                ("rm -rf", "risk-deletes-recursively"),
+                ("of=/dev/", "risk-writes-raw-device"),
+                ("reset", "risk-reboots"),
+                ("reboot", "risk-reboots"),
+            ],
+            SessionMode::Repl(_) => &[
+                ("drop ", "risk-drops-object"),
+                ("truncate ", "risk-truncates"),
+                ("alter table", "risk-alters-table"),
+            ],
+        };
+
+        if let Some((_, reason)) = rules.iter().find(|(pattern, _)| matches_rule(&normalized, pattern)) {
+            return Some(tr(reason));
+        }
+
+        if let SessionMode::Repl(kind) = self {
+            if kind.is_sql()
+                && (normalized.starts_with("delete ") || normalized.starts_with("update "))
+                && !normalized.contains(" where ")
+            {
+                return Some(tr("risk-changes-every-row"));
+            }
+        }
+        None
+    }
+}
+
+/// Whether `pattern` occurs in `command`. A pattern that is a single word
+/// only matches whole words, so `reset` doesn't catch `printenv resetcause`.
+fn matches_rule(command: &str, pattern: &str) -> bool {
+    let is_word = |c: char| c.is_alphanumeric() || c == '_';
+    if !pattern.chars().all(is_word) {
+        return command.contains(pattern);
+    }
+    command.split(|c: char| !is_word(c)).any(|word| word == pattern)
+}
+
+/// One command per line, joining lines that end in a backslash.
+fn split_lines_with_continuations(text: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    for line in text.lines() {
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+        if !line.ends_with('\\') {
+            push_command(&mut commands, &mut current);
+        }
+    }
+    push_command(&mut commands, &mut current);
+    commands
+}
+
+/// One command per top-level statement; indented lines and the clauses that
+/// continue a compound statement belong to the statement above them.
+fn split_python(text: &str) -> Vec<String> {
+    const CLAUSES: [&str; 5] = ["else", "elif ", "except", "finally", "case "];
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut depth: i32 = 0;
+    for line in text.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let continues = line.starts_with(char::is_whitespace)
+            || CLAUSES.iter().any(|clause| line.starts_with(clause))
+            || depth > 0
+            || current.ends_with('\\');
+        if !continues {
+            push_command(&mut commands, &mut current);
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+        depth += bracket_delta(line);
+    }
+    push_command(&mut commands, &mut current);
+    commands
+}
+
+/// One command per line, joining lines while brackets are open.
+fn split_balanced(text: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut depth: i32 = 0;
+    for line in text.lines() {
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+        depth += bracket_delta(line);
+        if depth <= 0 {
+            depth = 0;
+            push_command(&mut commands, &mut current);
+        }
+    }
+    push_command(&mut commands, &mut current);
+    commands
+}
+
+/// One command per `;`-terminated statement (outside quotes), plus
+/// backslash and dot meta-commands, which end at the end of their line.
+fn split_sql(text: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if quote.is_none()
+            && current.trim().is_empty()
+            && (trimmed.starts_with('\\') || trimmed.starts_with('.'))
+        {
+            commands.push(trimmed.trim_end().to_string());
+            current.clear();
+            continue;
+        }
+
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        let mut previous = None;
+        for (i, c) in line.char_indices() {
+            match quote {
+                Some(q) if c == q => quote = None,
+                Some(_) => {}
+                None if c == '\'' || c == '"' => quote = Some(c),
+                None if c == '-' && previous == Some('-') => {
+                    // A line comment: a `;` after it does not end the statement
+                    current.push_str(&line[i..]);
+                    break;
+                }
+                None if c == ';' => {
+                    current.push(c);
+                    push_command(&mut commands, &mut current);
+                    previous = None;
+                    continue;
+                }
+                None => {}
+            }
+            current.push(c);
+            previous = Some(c);
+        }
+    }
+    push_command(&mut commands, &mut current);
+    commands
+}
+
+/// Replaces the first ASCII letter of `text` with `escape(letter)`.
+fn escape_first_letter(text: &str, escape: impl Fn(char) -> String) -> String {
+    match text.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => format!("{}{}{}", &text[..i], escape(text[i..].chars().next().unwrap_or(' ')), &text[i + 1..]),
+        None => text.to_string(),
+    }
+}
+
+fn bracket_delta(line: &str) -> i32 {
+    line.chars()
+        .map(|c| match c {
+            '(' | '[' | '{' => 1,
+            ')' | ']' | '}' => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn push_command(commands: &mut Vec<String>, current: &mut String) {
+    let command = current.trim();
+    if !command.is_empty() {
+        commands.push(command.to_string());
+    }
+    current.clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repl_detection() {
+        assert_eq!(ReplKind::detect("/usr/bin/python3.12"), Some(ReplKind::Python));
+        assert_eq!(ReplKind::detect("node"), Some(ReplKind::Node));
+        assert_eq!(ReplKind::detect("psql"), Some(ReplKind::Psql));
+        assert_eq!(ReplKind::detect("sqlite3"), Some(ReplKind::Sqlite));
+        assert_eq!(ReplKind::detect("/bin/bash"), None);
+        assert_eq!(
+            SessionMode::detect("/bin/fish", None),
+            SessionMode::Shell(ShellDialect::Fish)
+        );
+    }
+
+    #[test]
+    fn test_sql_statements_are_units() {
+        let mode = SessionMode::Repl(ReplKind::Psql);
+        assert_eq!(
+            mode.split_commands("\\dt\nSELECT 'a;b'\n  FROM t;\nSELECT 1; SELECT 2;"),
+            vec!["\\dt", "SELECT 'a;b'\n  FROM t;", "SELECT 1;", "SELECT 2;"]
+        );
+    }
+
+    #[test]
+    fn test_python_blocks_are_units() {
+        let mode = SessionMode::Repl(ReplKind::Python);
+        let commands = mode.split_commands("import os\nfor f in os.listdir():\n    print(f)\nelse:\n    pass\nx = (1,\n2)");
+        assert_eq!(
+            commands,
+            vec![
+                "import os",
+                "for f in os.listdir():\n    print(f)\nelse:\n    pass",
+                "x = (1,\n2)",
+            ]
+        );
+        assert_eq!(
+            mode.command_input(&commands[1]),
+            "for f in os.listdir():\r    print(f)\relse:\r    pass\r\r"
+        );
+    }
+
+    #[test]
+    fn test_marked_input() {
+        let psql = SessionMode::Repl(ReplKind::Psql);
+        assert_eq!(
+            psql.marked_input(&["\\d users".to_string()], "_B_1", "_E_1"),
+            "\\echo '_\\102_1'\r\\d users\r\\echo '_\\105_1'\r"
+        );
+        let shell = SessionMode::Shell(ShellDialect::Posix);
+        assert_eq!(
+            shell.marked_input(&["make test".to_string()], "B", "E"),
+            "printf '\\102\\n'; make test; printf '\\105\\n'\r"
+        );
+    }
+
+    #[test]
+    fn test_continuation_prompts() {
+        let python = SessionMode::Repl(ReplKind::Python);
+        assert!(python.at_continuation("... "));
+        assert!(!python.at_continuation(">>> "));
+        assert!(SessionMode::Repl(ReplKind::Psql).at_continuation("shop(# "));
+        assert!(!SessionMode::Repl(ReplKind::Psql).at_continuation("shop=# "));
+    }
+
+    #[test]
+    fn test_risk_rules_follow_mode() {
+        let psql = SessionMode::Repl(ReplKind::Psql);
+        assert!(psql.risk("DELETE FROM users;").is_some());
+        assert!(psql.risk("DELETE FROM users WHERE id = 1;").is_none());
+        assert!(psql.risk("rm -rf build").is_none());
+
+        let shell = SessionMode::Shell(ShellDialect::Posix);
+        assert!(shell.risk("rm  -rf build").is_some());
+        assert!(shell.risk("DELETE FROM users;").is_none());
+
+        let device = SessionMode::Device;
+        assert_eq!(device.risk("sf erase 0 0x100000").as_deref(), Some("erases flash memory"));
+        assert!(device.risk("printenv bootcmd").is_none());
+        assert!(device.risk("reset").is_some());
+        assert!(device.risk("printenv resetcause").is_none());
+        assert!(device.risk("setenv bootdelay_reset 0").is_none());
+    }
+}
@@ -0,0 +1,274 @@
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate, TimeZone};
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::history::{CommandRecord, LlmUsage};
+
+/// Time between two commands beyond which the user is taken to have been
+/// away, rather than working in the repository.
+const IDLE_CUTOFF_SECS: i64 = 10 * 60;
+
+/// Entries shown in the most used commands.
+const TOP_COMMANDS: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct CommandCount {
+    pub command: String,
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Failures {
+    /// Commands whose exit status is known.
+    pub known: usize,
+    pub failed: usize,
+    /// Share of `known` that failed, if any are known.
+    pub rate: Option<f64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RepoTime {
+    pub repo: String,
+    pub commands: usize,
+    pub minutes: i64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PurposeUsage {
+    pub purpose: String,
+    pub calls: usize,
+    pub tokens: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct LlmSummary {
+    pub calls: usize,
+    /// Token counts are estimates; see `LlmUsage::estimate`.
+    pub prompt_tokens: u64,
+    pub reply_tokens: u64,
+    pub by_purpose: Vec<PurposeUsage>,
+}
+
+/// Usage over a period, built from the history database.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub since: DateTime<Local>,
+    pub until: DateTime<Local>,
+    pub commands: usize,
+    pub top_commands: Vec<CommandCount>,
+    pub failures: Failures,
+    pub repos: Vec<RepoTime>,
+    pub llm: LlmSummary,
+}
+
+/// Parses the start of a report period: a span back from `now` such as
+/// `30m`, `12h`, `7d` or `2w`, or a date.
+pub fn parse_since(text: &str, now: DateTime<Local>) -> Result<DateTime<Local>> {
+    let text = text.trim();
+    if let Ok(date) = NaiveDate::parse_from_str(text, "%Y-%m-%d") {
+        let midnight = date.and_hms_opt(0, 0, 0).with_context(|| format!("Invalid date: {}", text))?;
+        return Local.from_local_datetime(&midnight)
+            .earliest()
+            .with_context(|| format!("Invalid date: {}", text));
+    }
+
+    let unit = text.chars().last().with_context(|| "Empty period")?;
+    let seconds = match unit {
+        'm' => 60,
+        'h' => 60 * 60,
+        'd' => 24 * 60 * 60,
+        'w' => 7 * 24 * 60 * 60,
+        _ => anyhow::bail!("Invalid period: {} (expected e.g. 12h, 7d or 2026-01-31)", text),
+    };
+    let count: i64 = text[..text.len() - unit.len_utf8()].parse()
+        .with_context(|| format!("Invalid period: {}", text))?;
+    Ok(now - chrono::Duration::seconds(count.max(0) * seconds))
+}
+
+/// The program a command runs, which is what commands are counted by.
+fn program(command: &str) -> String {
+    let mut words = command
+        .split_whitespace()
+        // Skip leading variable assignments and `sudo`
+        .skip_while(|word| word.contains('=') || *word == "sudo");
+    words.next().unwrap_or(command).to_string()
+}
+
+fn format_minutes(minutes: i64) -> String {
+    if minutes >= 60 {
+        format!("{}h {:02}m", minutes / 60, minutes % 60)
+    } else {
+        format!("{}m", minutes)
+    }
+}
+
+impl Report {
+    /// Builds the report for `since..until` from the commands and LLM calls
+    /// of that period, each oldest first.
+    pub fn build(since: DateTime<Local>, until: DateTime<Local>, commands: &[CommandRecord], usage: &[LlmUsage]) -> Self {
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for record in commands {
+            *counts.entry(program(&record.command)).or_default() += 1;
+        }
+        let mut top_commands: Vec<CommandCount> = counts
+            .into_iter()
+            .map(|(command, count)| CommandCount { command, count })
+            .collect();
+        top_commands.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.command.cmp(&b.command)));
+        top_commands.truncate(TOP_COMMANDS);
+
+        let known = commands.iter().filter(|record| record.exit_status.is_some()).count();
+        let failed = commands.iter().filter(|record| record.exit_status.is_some_and(|status| status != 0)).count();
+        let failures = Failures {
+            known,
+            failed,
+            rate: (known > 0).then(|| failed as f64 / known as f64),
+        };
+
+        // Time in a repository runs from each command to the next one of the
+        // same session, unless the user was idle in between
+        let mut repos: HashMap<String, (usize, i64)> = HashMap::new();
+        for (i, record) in commands.iter().enumerate() {
+            let Some(repo) = &record.repo else {
+                continue;
+            };
+            let next = commands[i + 1..].iter().find(|next| next.session == record.session);
+            let spent = next
+                .map(|next| next.started_at - record.started_at)
+                .filter(|gap| *gap <= IDLE_CUTOFF_SECS)
+                .unwrap_or(0);
+            let entry = repos.entry(repo.clone()).or_default();
+            entry.0 += 1;
+            entry.1 += spent;
+        }
+        let mut repos: Vec<RepoTime> = repos
+            .into_iter()
+            .map(|(repo, (commands, seconds))| RepoTime { repo, commands, minutes: seconds / 60 })
+            .collect();
+        repos.sort_by(|a, b| b.minutes.cmp(&a.minutes).then_with(|| b.commands.cmp(&a.commands)));
+
+        let mut purposes: HashMap<String, (usize, u64)> = HashMap::new();
+        for call in usage {
+            let entry = purposes.entry(call.purpose.clone()).or_default();
+            entry.0 += 1;
+            entry.1 += call.prompt_tokens + call.reply_tokens;
+        }
+        let mut by_purpose: Vec<PurposeUsage> = purposes
+            .into_iter()
+            .map(|(purpose, (calls, tokens))| PurposeUsage { purpose, calls, tokens })
+            .collect();
+        by_purpose.sort_by(|a, b| b.tokens.cmp(&a.tokens).then_with(|| a.purpose.cmp(&b.purpose)));
+
+        Report {
+            since,
+            until,
+            commands: commands.len(),
+            top_commands,
+            failures,
+            repos,
+            llm: LlmSummary {
+                calls: usage.len(),
+                prompt_tokens: usage.iter().map(|call| call.prompt_tokens).sum(),
+                reply_tokens: usage.iter().map(|call| call.reply_tokens).sum(),
+                by_purpose,
+            },
+        }
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut text = format!(
+            "Usage from {} to {}\n\n",
+            self.since.format("%Y-%m-%d %H:%M"),
+            self.until.format("%Y-%m-%d %H:%M")
+        );
+
+        text.push_str(&format!("Commands: {}\n", self.commands));
+        for entry in &self.top_commands {
+            text.push_str(&format!("  {:<20} {:>6}\n", entry.command, entry.count));
+        }
+
+        match self.failures.rate {
+            Some(rate) => text.push_str(&format!(
+                "\nFailures: {} of {} with a known exit status ({:.1}%)\n",
+                self.failures.failed,
+                self.failures.known,
+                rate * 100.0
+            )),
+            None => text.push_str("\nFailures: no exit statuses recorded (needs OSC 133 shell integration)\n"),
+        }
+
+        if !self.repos.is_empty() {
+            text.push_str(&format!("\n{:<42} {:>8} {:>9}\n", "Time in repositories:", "time", "commands"));
+            for repo in &self.repos {
+                text.push_str(&format!(
+                    "  {:<40} {:>8} {:>9}\n",
+                    repo.repo,
+                    format_minutes(repo.minutes),
+                    repo.commands
+                ));
+            }
+        }
+
+        text.push_str(&format!(
+            "\nLLM: {} calls, ~{} tokens in, ~{} tokens out (estimated)\n",
+            self.llm.calls, self.llm.prompt_tokens, self.llm.reply_tokens
+        ));
+        for purpose in &self.llm.by_purpose {
+            text.push_str(&format!("  {:<20} {:>6} calls  ~{} tokens\n", purpose.purpose, purpose.calls, purpose.tokens));
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string_pretty(self).with_context(|| "Failed to serialize report")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(started_at: i64, command: &str, repo: Option<&str>, exit_status: Option<i32>) -> CommandRecord {
+        CommandRecord {
+            started_at,
+            session: "1".to_string(),
+            command: command.to_string(),
+            cwd: None,
+            repo: repo.map(str::to_string),
+            exit_status,
+        }
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let now = Local.with_ymd_and_hms(2026, 3, 10, 12, 0, 0).unwrap();
+        assert_eq!(parse_since("7d", now).unwrap(), Local.with_ymd_and_hms(2026, 3, 3, 12, 0, 0).unwrap());
+        assert_eq!(parse_since("90m", now).unwrap(), Local.with_ymd_and_hms(2026, 3, 10, 10, 30, 0).unwrap());
+        assert_eq!(parse_since("2026-03-01", now).unwrap(), Local.with_ymd_and_hms(2026, 3, 1, 0, 0, 0).unwrap());
+        assert!(parse_since("soon", now).is_err());
+    }
+
+    #[test]
+    fn test_build_report() {
+        let now = Local::now();
+        let commands = vec![
+            command(0, "git status", Some("/src/app"), Some(0)),
+            command(60, "FOO=1 cargo test", Some("/src/app"), Some(101)),
+            command(180, "git push", Some("/src/app"), None),
+            command(3600, "ls", None, Some(0)),
+        ];
+        let usage = vec![LlmUsage { at: 0, purpose: "ask".to_string(), prompt_tokens: 100, reply_tokens: 20 }];
+        let report = Report::build(now, now, &commands, &usage);
+
+        assert_eq!(report.top_commands[0], CommandCount { command: "git".to_string(), count: 2 });
+        assert_eq!(report.top_commands[1].command, "cargo");
+        assert_eq!(report.failures.failed, 1);
+        assert_eq!(report.failures.known, 3);
+        // The hour before `ls` counts as idle
+        assert_eq!(report.repos, vec![RepoTime { repo: "/src/app".to_string(), commands: 3, minutes: 3 }]);
+        assert_eq!(report.llm.prompt_tokens, 100);
+        assert!(report.to_text().contains("Failures: 1 of 3"));
+        assert!(report.to_json().unwrap().contains("\"top_commands\""));
+    }
+}
@@ -0,0 +1,417 @@
+use chrono::{DateTime, Local};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::config::WaybackConfig;
+
+/// Size assumed until the real one is known.
+const DEFAULT_SIZE: (usize, usize) = (80, 24);
+
+/// The text on the shell's screen, rebuilt from its output. Follows the
+/// cursor movements, erases and scrolling that full-screen programs use,
+/// and keeps the alternate screen apart from the normal one. Colors and
+/// other attributes are dropped, and so are scroll regions.
+#[derive(Debug, Clone)]
+pub struct Screen {
+    cols: usize,
+    rows: usize,
+    grid: Vec<Vec<char>>,
+    row: usize,
+    col: usize,
+    // The normal screen and its cursor while the alternate one is shown
+    saved: Option<(Vec<Vec<char>>, usize, usize)>,
+}
+
+impl Default for Screen {
+    fn default() -> Self {
+        Screen::new(DEFAULT_SIZE.0, DEFAULT_SIZE.1)
+    }
+}
+
+impl Screen {
+    pub fn new(cols: usize, rows: usize) -> Self {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        Screen {
+            cols,
+            rows,
+            grid: vec![vec![' '; cols]; rows],
+            row: 0,
+            col: 0,
+            saved: None,
+        }
+    }
+
+    /// Follows a resize of the terminal. When it gets shorter, rows below
+    /// the cursor go first, then rows scroll off the top.
+    pub fn resize(&mut self, cols: usize, rows: usize) {
+        let (cols, rows) = (cols.max(1), rows.max(1));
+        // Returns the cursor's new row
+        let fit = |grid: &mut Vec<Vec<char>>, row: usize| -> usize {
+            let scrolled = (row + 1).saturating_sub(rows);
+            grid.drain(..scrolled);
+            grid.resize(rows, vec![' '; cols]);
+            for line in grid.iter_mut() {
+                line.resize(cols, ' ');
+            }
+            row - scrolled
+        };
+        self.row = fit(&mut self.grid, self.row);
+        self.col = self.col.min(cols);
+        if let Some((grid, row, col)) = self.saved.as_mut() {
+            *row = fit(grid, *row);
+            *col = (*col).min(cols);
+        }
+        self.cols = cols;
+        self.rows = rows;
+    }
+
+    /// True while a full-screen program has the alternate screen.
+    pub fn in_alternate(&self) -> bool {
+        self.saved.is_some()
+    }
+
+    /// The cursor's `(col, row)`, from 0.
+    pub fn cursor(&self) -> (usize, usize) {
+        (self.col.min(self.cols - 1), self.row)
+    }
+
+    /// The number of rows.
+    pub fn height(&self) -> usize {
+        self.rows
+    }
+
+    /// Moves the cursor down `count` rows to the start of a line, scrolling
+    /// as needed, as drawing an image that tall does.
+    pub fn feed_rows(&mut self, count: usize) {
+        for _ in 0..count {
+            self.line_feed();
+        }
+        self.col = 0;
+    }
+
+    /// The rows of the screen without trailing blanks, and without the
+    /// empty rows at the bottom.
+    pub fn lines(&self) -> Vec<String> {
+        let mut lines: Vec<String> = self
+            .grid
+            .iter()
+            .map(|line| line.iter().collect::<String>().trim_end().to_string())
+            .collect();
+        while lines.last().is_some_and(|line| line.is_empty()) {
+            lines.pop();
+        }
+        lines
+    }
+
+    /// Every row of the screen, top to bottom, without trailing blanks.
+    pub fn rows(&self) -> Vec<String> {
+        self.grid.iter().map(|line| line.iter().collect::<String>().trim_end().to_string()).collect()
+    }
+
+    /// True if nothing is on the screen.
+    pub fn is_blank(&self) -> bool {
+        self.grid.iter().all(|line| line.iter().all(|c| *c == ' '))
+    }
+
+    /// Feeds text, which may contain control characters but no escape
+    /// sequences.
+    pub fn text(&mut self, text: &[u8]) {
+        for c in String::from_utf8_lossy(text).chars() {
+            match c {
+                '\n' | '\x0b' | '\x0c' => self.line_feed(),
+                '\r' => self.col = 0,
+                '\x08' => self.col = self.col.min(self.cols - 1).saturating_sub(1),
+                '\t' => self.col = ((self.col / 8 + 1) * 8).min(self.cols - 1),
+                c if c.is_control() => {}
+                c => {
+                    // The cursor waits past the last column until the next
+                    // character wraps it
+                    if self.col >= self.cols {
+                        self.col = 0;
+                        self.line_feed();
+                    }
+                    self.grid[self.row][self.col] = c;
+                    self.col += 1;
+                }
+            }
+        }
+    }
+
+    fn line_feed(&mut self) {
+        if self.row + 1 < self.rows {
+            self.row += 1;
+        } else {
+            self.scroll_up(1);
+        }
+    }
+
+    fn scroll_up(&mut self, count: usize) {
+        let count = count.min(self.rows);
+        self.grid.drain(..count);
+        self.grid.resize(self.rows, vec![' '; self.cols]);
+    }
+
+    fn scroll_down(&mut self, count: usize) {
+        let count = count.min(self.rows);
+        self.grid.truncate(self.rows - count);
+        for _ in 0..count {
+            self.grid.insert(0, vec![' '; self.cols]);
+        }
+    }
+
+    fn blank(&mut self, row: usize, from: usize, to: usize) {
+        let to = to.min(self.cols);
+        if from < to {
+            self.grid[row][from..to].fill(' ');
+        }
+    }
+
+    /// Applies a CSI sequence, given whole from `ESC [` on.
+    pub fn sequence(&mut self, raw: &[u8]) {
+        let Some((&command, params)) = raw.get(2..).and_then(|rest| rest.split_last()) else {
+            return;
+        };
+        let Ok(params) = std::str::from_utf8(params) else {
+            return;
+        };
+        if let Some(private) = params.strip_prefix('?') {
+            if matches!(private, "1049" | "1047" | "47") {
+                match command {
+                    b'h' => self.enter_alternate(),
+                    b'l' => self.leave_alternate(),
+                    _ => {}
+                }
+            }
+            return;
+        }
+        let values: Vec<usize> = params.split(';').map(|p| p.parse().unwrap_or(0)).collect();
+        let arg = |i: usize| values.get(i).copied().unwrap_or(0);
+        // Counts and positions of 0 mean 1
+        let count = arg(0).max(1);
+        let col = self.col.min(self.cols - 1);
+        match command {
+            b'A' => self.row = self.row.saturating_sub(count),
+            b'B' | b'e' => self.row = (self.row + count).min(self.rows - 1),
+            b'C' | b'a' => self.col = (col + count).min(self.cols - 1),
+            b'D' => self.col = col.saturating_sub(count),
+            b'E' => (self.row, self.col) = ((self.row + count).min(self.rows - 1), 0),
+            b'F' => (self.row, self.col) = (self.row.saturating_sub(count), 0),
+            b'G' | b'`' => self.col = (count - 1).min(self.cols - 1),
+            b'd' => self.row = (count - 1).min(self.rows - 1),
+            b'H' | b'f' => {
+                self.row = (arg(0).max(1) - 1).min(self.rows - 1);
+                self.col = (arg(1).max(1) - 1).min(self.cols - 1);
+            }
+            b'J' => match arg(0) {
+                0 => {
+                    self.blank(self.row, col, self.cols);
+                    for row in self.row + 1..self.rows {
+                        self.blank(row, 0, self.cols);
+                    }
+                }
+                1 => {
+                    for row in 0..self.row {
+                        self.blank(row, 0, self.cols);
+                    }
+                    self.blank(self.row, 0, col + 1);
+                }
+                _ => {
+                    for row in 0..self.rows {
+                        self.blank(row, 0, self.cols);
+                    }
+                }
+            },
+            b'K' => match arg(0) {
+                0 => self.blank(self.row, col, self.cols),
+                1 => self.blank(self.row, 0, col + 1),
+                _ => self.blank(self.row, 0, self.cols),
+            },
+            b'X' => self.blank(self.row, col, col + count),
+            b'P' => {
+                let line = &mut self.grid[self.row];
+                line.drain(col..(col + count).min(self.cols));
+                line.resize(self.cols, ' ');
+            }
+            b'@' => {
+                let line = &mut self.grid[self.row];
+                for _ in 0..count.min(self.cols - col) {
+                    line.insert(col, ' ');
+                }
+                line.truncate(self.cols);
+            }
+            b'L' | b'M' => {
+                // Only the rows from the cursor down move
+                let mut below = self.grid.split_off(self.row);
+                let count = count.min(below.len());
+                if command == b'L' {
+                    below.truncate(below.len() - count);
+                    for _ in 0..count {
+                        below.insert(0, vec![' '; self.cols]);
+                    }
+                } else {
+                    below.drain(..count);
+                    below.resize(self.rows - self.row, vec![' '; self.cols]);
+                }
+                self.grid.extend(below);
+            }
+            b'S' => self.scroll_up(count),
+            b'T' => self.scroll_down(count),
+            _ => {}
+        }
+    }
+
+    fn enter_alternate(&mut self) {
+        if self.saved.is_none() {
+            let grid = std::mem::replace(&mut self.grid, vec![vec![' '; self.cols]; self.rows]);
+            self.saved = Some((grid, self.row, self.col));
+        }
+    }
+
+    fn leave_alternate(&mut self) {
+        if let Some((grid, row, col)) = self.saved.take() {
+            self.grid = grid;
+            self.row = row;
+            self.col = col;
+        }
+    }
+}
+
+/// The screen as it was at some point.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    pub taken: DateTime<Local>,
+    pub lines: Vec<String>,
+    /// True if it was the alternate screen of a full-screen program.
+    pub alternate: bool,
+}
+
+/// Recent screens, for `builtin:wayback`. A snapshot is taken at most once
+/// per interval, and only if the screen changed since the last one. Screens
+/// that are about to be lost are kept regardless: a full-screen program's
+/// when it exits, and the normal screen's when it is cleared.
+#[derive(Debug)]
+pub struct Wayback {
+    snapshots: VecDeque<Snapshot>,
+    keep: usize,
+    interval: Duration,
+    last_taken: Option<Instant>,
+}
+
+impl Default for Wayback {
+    fn default() -> Self {
+        Wayback::new(&WaybackConfig::default())
+    }
+}
+
+impl Wayback {
+    pub fn new(config: &WaybackConfig) -> Self {
+        Wayback {
+            snapshots: VecDeque::new(),
+            keep: config.keep,
+            interval: Duration::from_secs(config.interval_secs),
+            last_taken: None,
+        }
+    }
+
+    /// Takes a snapshot of `screen` if the last one is old enough.
+    pub fn observe(&mut self, screen: &Screen, now: Instant) {
+        let due = self.last_taken.is_none_or(|last| now.duration_since(last) >= self.interval);
+        if due {
+            self.take(screen, now);
+        }
+    }
+
+    /// Takes a snapshot of `screen` now, unless it is blank or unchanged.
+    pub fn take(&mut self, screen: &Screen, now: Instant) {
+        if self.keep == 0 || screen.is_blank() {
+            return;
+        }
+        let lines = screen.lines();
+        let alternate = screen.in_alternate();
+        let unchanged = self.snapshots.back()
+            .is_some_and(|last| last.lines == lines && last.alternate == alternate);
+        if unchanged {
+            return;
+        }
+        self.snapshots.push_back(Snapshot { taken: Local::now(), lines, alternate });
+        if self.snapshots.len() > self.keep {
+            self.snapshots.pop_front();
+        }
+        self.last_taken = Some(now);
+    }
+
+    /// The snapshots kept, oldest first.
+    pub fn snapshots(&self) -> Vec<Snapshot> {
+        self.snapshots.iter().cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_screen_follows_cursor() {
+        let mut screen = Screen::new(10, 3);
+        screen.text(b"one\r\ntwo\r\nthree");
+        assert_eq!(screen.lines(), ["one", "two", "three"]);
+        // Scrolls at the bottom and wraps long lines
+        screen.text(b"\r\nabcdefghijkl");
+        assert_eq!(screen.lines(), ["three", "abcdefghij", "kl"]);
+
+        screen.sequence(b"\x1b[1;3H");
+        screen.text(b"X");
+        screen.sequence(b"\x1b[2;4H");
+        screen.sequence(b"\x1b[K");
+        screen.sequence(b"\x1b[3;1H");
+        screen.sequence(b"\x1b[P");
+        assert_eq!(screen.lines(), ["thXee", "abc", "l"]);
+        assert_eq!(screen.rows(), ["thXee", "abc", "l"]);
+
+        screen.sequence(b"\x1b[2J");
+        assert!(screen.is_blank());
+    }
+
+    #[test]
+    fn test_alternate_screen_is_separate() {
+        let mut screen = Screen::new(20, 4);
+        screen.text(b"$ vim notes");
+        screen.sequence(b"\x1b[?1049h");
+        assert!(screen.in_alternate());
+        screen.sequence(b"\x1b[4;1H");
+        screen.text(b"E37: No write");
+        assert_eq!(screen.lines(), ["", "", "", "E37: No write"]);
+        assert_eq!(screen.rows(), ["", "", "", "E37: No write"]);
+
+        screen.sequence(b"\x1b[?1049l");
+        assert_eq!(screen.lines(), ["$ vim notes"]);
+        // Unlike the lines, the rows keep the blank ones at the bottom
+        assert_eq!(screen.rows(), ["$ vim notes", "", "", ""]);
+        screen.resize(8, 2);
+        assert_eq!(screen.lines(), ["$ vim no"]);
+    }
+
+    #[test]
+    fn test_wayback_rate_limit() {
+        let config = WaybackConfig { keep: 2, interval_secs: 5 };
+        let mut wayback = Wayback::new(&config);
+        let mut screen = Screen::new(20, 4);
+        let start = Instant::now();
+
+        wayback.observe(&screen, start);
+        assert!(wayback.snapshots().is_empty());
+        screen.text(b"$ make\r\n");
+        wayback.observe(&screen, start);
+        screen.text(b"error: oops\r\n");
+        wayback.observe(&screen, start + Duration::from_secs(1));
+        assert_eq!(wayback.snapshots().len(), 1);
+
+        wayback.take(&screen, start + Duration::from_secs(2));
+        screen.text(b"$ ");
+        wayback.observe(&screen, start + Duration::from_secs(7));
+        let snapshots = wayback.snapshots();
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(snapshots[0].lines, ["$ make", "error: oops"]);
+        assert_eq!(snapshots[1].lines, ["$ make", "error: oops", "$"]);
+    }
+}
@@ -0,0 +1,97 @@
+use anyhow::Result;
+use regex::{Regex, RegexBuilder};
+use std::collections::VecDeque;
+
+/// The lines the shell printed this session, oldest first, for
+/// `builtin:search_scrollback` and `builtin:copy_mode`. Full-screen
+/// programs aren't kept: what they draw doesn't come out as lines.
+#[derive(Debug)]
+pub struct Scrollback {
+    lines: VecDeque<String>,
+    limit: usize,
+    // Lines dropped from the front so far
+    dropped: usize,
+}
+
+impl Default for Scrollback {
+    fn default() -> Self {
+        Scrollback::new(crate::config::ScrollbackConfig::default().lines)
+    }
+}
+
+impl Scrollback {
+    /// Keeps the last `limit` lines; 0 keeps none.
+    pub fn new(limit: usize) -> Self {
+        Scrollback { lines: VecDeque::new(), limit, dropped: 0 }
+    }
+
+    pub fn push(&mut self, line: String) {
+        if self.limit == 0 {
+            return;
+        }
+        if self.lines.len() == self.limit {
+            self.lines.pop_front();
+            self.dropped += 1;
+        }
+        self.lines.push_back(line);
+    }
+
+    pub fn lines(&self) -> &VecDeque<String> {
+        &self.lines
+    }
+
+    /// The number of the first line kept, counting from the first line of
+    /// the session, so that lines keep their numbers as old ones go.
+    pub fn first_line(&self) -> usize {
+        self.dropped
+    }
+
+    /// The indices of the lines `pattern` matches, oldest first.
+    pub fn matches(&self, pattern: &Regex) -> Vec<usize> {
+        self.lines
+            .iter()
+            .enumerate()
+            .filter(|(_, line)| pattern.is_match(line))
+            .map(|(index, _)| index)
+            .collect()
+    }
+}
+
+/// The pattern for what was typed at the search prompt: the text itself,
+/// or a regex between slashes like `/err(or)?/`. It ignores case unless
+/// the text has capitals.
+pub fn search_pattern(query: &str) -> Result<Regex> {
+    let pattern = match query.strip_prefix('/').and_then(|rest| rest.strip_suffix('/')) {
+        Some(pattern) if !pattern.is_empty() => pattern.to_string(),
+        _ => regex::escape(query),
+    };
+    let ignore_case = !query.chars().any(char::is_uppercase);
+    Ok(RegexBuilder::new(&pattern).case_insensitive(ignore_case).build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scrollback_search() {
+        let mut scrollback = Scrollback::new(3);
+        for line in ["GET /health 200", "GET /login 500", "error: pool exhausted", "GET /health 200"] {
+            scrollback.push(line.to_string());
+        }
+        assert_eq!(scrollback.lines().len(), 3);
+        assert_eq!(scrollback.lines()[0], "GET /login 500");
+        assert_eq!(scrollback.first_line(), 1);
+
+        assert_eq!(scrollback.matches(&search_pattern("get").unwrap()), vec![0, 2]);
+        assert!(scrollback.matches(&search_pattern("Error").unwrap()).is_empty());
+        assert_eq!(scrollback.matches(&search_pattern("/ 5\\d\\d$/").unwrap()), vec![0]);
+        // Taken as text, not as a regex
+        assert!(scrollback.matches(&search_pattern("/health.").unwrap()).is_empty());
+        assert!(search_pattern("/(/").is_err());
+
+        let mut off = Scrollback::new(0);
+        off.push("anything".to_string());
+        assert!(off.lines().is_empty());
+    }
+}
@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use nix::sys::termios::{self, BaudRate, ControlFlags, SetArg};
+use std::fs::{File, OpenOptions};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::backend::{AsyncIo, Backend};
+
+/// The device to talk to instead of a shell, from `--serial` and `--baud`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SerialConfig {
+    pub device: String,
+    pub baud: u32,
+}
+
+/// The speed of most UART consoles.
+pub const DEFAULT_BAUD: u32 = 115200;
+
+/// The termios speed for `baud` bits per second, if it is a standard one.
+pub fn baud_rate(baud: u32) -> Option<BaudRate> {
+    Some(match baud {
+        1200 => BaudRate::B1200,
+        2400 => BaudRate::B2400,
+        4800 => BaudRate::B4800,
+        9600 => BaudRate::B9600,
+        19200 => BaudRate::B19200,
+        38400 => BaudRate::B38400,
+        57600 => BaudRate::B57600,
+        115200 => BaudRate::B115200,
+        230400 => BaudRate::B230400,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        460800 => BaudRate::B460800,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        921600 => BaudRate::B921600,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        1000000 => BaudRate::B1000000,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        1500000 => BaudRate::B1500000,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        2000000 => BaudRate::B2000000,
+        #[cfg(any(target_os = "linux", target_os = "android"))]
+        3000000 => BaudRate::B3000000,
+        _ => return None,
+    })
+}
+
+/// A console on a serial line, such as the UART of a development board.
+/// The device echoes and edits lines itself, so it is used raw: 8 data
+/// bits, no parity, one stop bit and no flow control.
+#[derive(Debug)]
+pub struct SerialSession {
+    port: File,
+    device: PathBuf,
+    /// Set when a read finds the line hung up, which a port that stays in
+    /// /dev (a built-in UART, a pty) does when the other end closes.
+    hung_up: Arc<AtomicBool>,
+}
+
+impl SerialSession {
+    pub fn open(config: &SerialConfig) -> Result<Self> {
+        let speed = baud_rate(config.baud)
+            .with_context(|| format!("Unsupported baud rate: {}", config.baud))?;
+        // Not as the controlling terminal, so the device hanging up doesn't
+        // send us SIGHUP
+        let port = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .custom_flags(nix::libc::O_NOCTTY)
+            .open(&config.device)
+            .with_context(|| format!("Failed to open serial device: {}", config.device))?;
+
+        let mut settings = termios::tcgetattr(&port)
+            .with_context(|| format!("Not a serial device: {}", config.device))?;
+        termios::cfmakeraw(&mut settings);
+        termios::cfsetspeed(&mut settings, speed)?;
+        settings.control_flags |= ControlFlags::CLOCAL | ControlFlags::CREAD;
+        settings.control_flags &= !(ControlFlags::CSTOPB | ControlFlags::CRTSCTS);
+        termios::tcsetattr(&port, SetArg::TCSANOW, &settings)
+            .with_context(|| format!("Failed to configure serial device: {}", config.device))?;
+
+        Ok(SerialSession {
+            port,
+            device: PathBuf::from(&config.device),
+            hung_up: Arc::default(),
+        })
+    }
+}
+
+impl Backend for SerialSession {
+    fn try_clone_io(&self) -> Result<File> {
+        self.port.try_clone()
+            .with_context(|| "Failed to duplicate serial device")
+    }
+
+    fn async_io(&self) -> Result<AsyncIo> {
+        AsyncIo::watching(self.try_clone_io()?, self.hung_up.clone())
+    }
+
+    /// A serial line has no way to tell the device the terminal size.
+    fn resize(&self, _rows: u16, _cols: u16) -> Result<()> {
+        Ok(())
+    }
+
+    /// USB serial adapters disappear from /dev when unplugged; other ports
+    /// read as hung up.
+    fn is_alive(&self) -> bool {
+        self.device.exists() && !self.hung_up.load(Ordering::Relaxed)
+    }
+
+    fn in_foreground(&self) -> bool {
+        true
+    }
+
+    fn cwd(&self) -> Option<PathBuf> {
+        None
+    }
+
+    fn terminate(&mut self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::os::unix::io::AsRawFd;
+
+    #[test]
+    fn test_serial_settings() {
+        assert_eq!(baud_rate(DEFAULT_BAUD), Some(BaudRate::B115200));
+        assert_eq!(baud_rate(9600), Some(BaudRate::B9600));
+        assert_eq!(baud_rate(12345), None);
+
+        let config = SerialConfig { device: "/nonexistent/ttyUSB9".to_string(), baud: 9600 };
+        assert!(SerialSession::open(&config).is_err());
+        // A file opens, but isn't a terminal
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let config = SerialConfig { device: file.path().display().to_string(), baud: 9600 };
+        let error = SerialSession::open(&config).unwrap_err();
+        assert!(error.to_string().starts_with("Not a serial device"));
+    }
+
+    #[test]
+    fn test_serial_session_over_pty() {
+        // The far end of a pseudo-terminal stands in for a board
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let device = std::fs::read_link(format!("/proc/self/fd/{}", pty.slave.as_raw_fd())).unwrap();
+        let config = SerialConfig { device: device.display().to_string(), baud: DEFAULT_BAUD };
+        let session = SerialSession::open(&config).unwrap();
+        assert!(session.is_alive());
+
+        let mut board = File::from(pty.master);
+        board.write_all(b"U-Boot> ").unwrap();
+        let mut io = session.try_clone_io().unwrap();
+        let mut buffer = [0u8; 16];
+        let n = io.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"U-Boot> ");
+
+        io.write_all(b"help\r").unwrap();
+        let n = board.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"help\r");
+    }
+
+    #[tokio::test]
+    async fn test_serial_session_hangs_up() {
+        let pty = nix::pty::openpty(None, None).unwrap();
+        let device = std::fs::read_link(format!("/proc/self/fd/{}", pty.slave.as_raw_fd())).unwrap();
+        let config = SerialConfig { device: device.display().to_string(), baud: DEFAULT_BAUD };
+        let session = SerialSession::open(&config).unwrap();
+        let io = session.async_io().unwrap();
+        drop(pty.master);
+
+        // A pty's node goes with it, so look at what reading found as well
+        let mut buffer = [0u8; 16];
+        assert!(!matches!(io.read(&mut buffer).await, Ok(n) if n > 0));
+        assert!(session.hung_up.load(Ordering::Relaxed));
+        assert!(!session.is_alive());
+    }
+}
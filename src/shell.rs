@@ -0,0 +1,480 @@
+use crate::output::is_marker_echo;
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// The command language spoken by the wrapped shell, used whenever chatshell
+/// has to type a command into it on the user's behalf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellDialect {
+    Posix,
+    Fish,
+    Nu,
+    Csh,
+}
+
+impl ShellDialect {
+    pub fn detect(command: &str) -> Self {
+        let name = Path::new(command)
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        match name.trim_start_matches('-') {
+            "fish" => ShellDialect::Fish,
+            "nu" | "nushell" => ShellDialect::Nu,
+            "csh" | "tcsh" => ShellDialect::Csh,
+            _ => ShellDialect::Posix,
+        }
+    }
+
+    /// Builds a command line (including the trailing Enter) that sets an
+    /// environment variable in the running shell.
+    pub fn export_line(&self, key: &str, value: &str) -> String {
+        // The leading space keeps the line out of history in shells that
+        // honour it, which matters for rotated credentials.
+        match self {
+            ShellDialect::Posix => format!(" export {}={}\r", key, single_quote(value)),
+            ShellDialect::Fish => format!(" set -gx {} {}\r", key, fish_quote(value)),
+            ShellDialect::Nu => format!(" $env.{} = {}\r", key, double_quote(value)),
+            ShellDialect::Csh => format!(" setenv {} {}\r", key, single_quote(value)),
+        }
+    }
+
+    /// `value` quoted as one word.
+    pub fn quote(&self, value: &str) -> String {
+        match self {
+            ShellDialect::Posix | ShellDialect::Csh => single_quote(value),
+            ShellDialect::Fish => fish_quote(value),
+            ShellDialect::Nu => double_quote(value),
+        }
+    }
+
+    /// A command line, left for the user to run, that opens `path` in
+    /// `$EDITOR`, or `vi` where the shell can say so.
+    pub fn editor_line(&self, path: &Path) -> String {
+        let path = self.quote(&path.to_string_lossy());
+        match self {
+            ShellDialect::Posix => format!("${{EDITOR:-vi}} {}", path),
+            ShellDialect::Fish | ShellDialect::Csh => format!("$EDITOR {}", path),
+            ShellDialect::Nu => format!("^$env.EDITOR {}", path),
+        }
+    }
+}
+
+/// Commands to type into the shell once it has printed its first prompt, or
+/// input held back until its next one.
+///
+/// The prompt is assumed to be up when the shell has produced some output and
+/// then gone quiet for `settle`.
+#[derive(Debug)]
+pub struct StartupQueue {
+    commands: VecDeque<String>,
+    settle: Duration,
+    last_output: Option<Instant>,
+}
+
+impl StartupQueue {
+    pub fn new(commands: Vec<String>, settle: Duration) -> Self {
+        StartupQueue {
+            commands: commands.into_iter().map(|command| format!("{}\r", command)).collect(),
+            settle,
+            last_output: None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commands.is_empty()
+    }
+
+    pub fn observe_output(&mut self, at: Instant) {
+        self.last_output = Some(at);
+    }
+
+    /// Queues raw input to type once the shell has printed something new and
+    /// gone quiet again, e.g. the fresh prompt after an interrupt.
+    pub fn push_after_output(&mut self, input: String) {
+        self.last_output = None;
+        self.commands.push_back(input);
+    }
+
+    /// Returns the command lines to send (each ending in Enter) once the
+    /// prompt is ready, and nothing before that or after they were sent.
+    pub fn take_ready(&mut self, now: Instant) -> Vec<String> {
+        match self.last_output {
+            Some(last) if now.duration_since(last) >= self.settle => self.commands.drain(..).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Keys typed while a hook that consumed its key still has input waiting in
+/// the `StartupQueue`, kept back so they reach the shell after that input
+/// rather than overtaking it.
+///
+/// Keys are held for at most `limit`, so a shell that never shows its
+/// prompt again doesn't swallow the keyboard.
+#[derive(Debug)]
+pub struct KeyHold<T> {
+    keys: Vec<T>,
+    since: Option<Instant>,
+    limit: Duration,
+}
+
+impl<T> KeyHold<T> {
+    pub fn new(limit: Duration) -> Self {
+        KeyHold { keys: Vec::new(), since: None, limit }
+    }
+
+    /// Starts holding keys, unless already holding.
+    pub fn start(&mut self, now: Instant) {
+        self.since.get_or_insert(now);
+    }
+
+    /// Holds `key` back if keys are being held, or hands it back to be
+    /// handled now.
+    pub fn hold(&mut self, key: T) -> Option<T> {
+        if self.since.is_none() {
+            return Some(key);
+        }
+        self.keys.push(key);
+        None
+    }
+
+    /// Returns the held keys, in the order they were typed, once nothing is
+    /// `pending` any more or they have waited too long, and stops holding.
+    pub fn release(&mut self, now: Instant, pending: bool) -> Vec<T> {
+        match self.since {
+            Some(since) if !pending || now.duration_since(since) >= self.limit => {
+                self.since = None;
+                std::mem::take(&mut self.keys)
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Finds the last command typed at `prompt` in a transcript, with the lines
+/// it printed before the next prompt.
+pub fn last_command(prompt: &str, lines: &[String]) -> Option<(String, Vec<String>)> {
+    let prompt = prompt.trim_end();
+    if prompt.is_empty() {
+        return None;
+    }
+    let command_at = |line: &str| {
+        line.strip_prefix(prompt)
+            .map(str::trim)
+            .filter(|command| !command.is_empty() && !is_marker_echo(line))
+            .map(str::to_string)
+    };
+
+    let start = lines.iter().rposition(|line| command_at(line).is_some())?;
+    let command = command_at(&lines[start])?;
+    let output = lines[start + 1..]
+        .iter()
+        .take_while(|line| !line.starts_with(prompt))
+        .cloned()
+        .collect();
+    Some((command, output))
+}
+
+/// How many characters typing `input` leaves on the command line without
+/// running them: whatever follows its last Enter.
+pub fn unsubmitted_chars(input: &str) -> usize {
+    let tail = input.rsplit(['\r', '\n']).next().unwrap_or_default();
+    tail.chars().filter(|c| !c.is_control()).count()
+}
+
+/// What `TERM` becomes when the host's is missing or has no terminfo entry.
+pub const FALLBACK_TERM: &str = "xterm-256color";
+
+/// Terminals that draw 24-bit color but don't say so once `COLORTERM` is
+/// lost on the way, as it is over SSH, which doesn't forward it.
+const TRUECOLOR_TERMS: [&str; 5] = ["xterm-kitty", "alacritty", "wezterm", "foot", "xterm-ghostty"];
+
+/// The values to give `TERM`, `COLORTERM` and `LANG` so the shell gets a
+/// terminal it can draw on: `term_override`, or a fallback for a missing
+/// or unknown `TERM`; `COLORTERM` for terminals known to have 24-bit color;
+/// a UTF-8 locale when none is set. Variables that are fine are left out.
+pub fn terminal_env(
+    var: impl Fn(&str) -> Option<String>,
+    known_term: impl Fn(&str) -> bool,
+    term_override: Option<&str>,
+) -> Vec<(&'static str, String)> {
+    let var = |key: &str| var(key).filter(|value| !value.is_empty());
+    let mut changes = Vec::new();
+
+    let term = match (term_override, var("TERM")) {
+        (Some(term), _) => term.to_string(),
+        (None, Some(term)) if known_term(&term) => term,
+        (None, _) => FALLBACK_TERM.to_string(),
+    };
+    if var("TERM").as_ref() != Some(&term) {
+        changes.push(("TERM", term.clone()));
+    }
+    if var("COLORTERM").is_none() && (term.ends_with("-direct") || TRUECOLOR_TERMS.contains(&term.as_str())) {
+        changes.push(("COLORTERM", "truecolor".to_string()));
+    }
+    if ["LC_ALL", "LC_CTYPE", "LANG"].iter().all(|key| var(key).is_none()) {
+        changes.push(("LANG", "C.UTF-8".to_string()));
+    }
+    changes
+}
+
+/// True if the terminfo database has an entry for `term`, or if there is
+/// no database to ask.
+pub fn terminfo_known(term: &str) -> bool {
+    let Some(first) = term.chars().next().filter(|_| !term.contains('/')) else {
+        return false;
+    };
+    let mut dirs: Vec<String> = Vec::new();
+    dirs.extend(std::env::var("TERMINFO"));
+    dirs.extend(std::env::var("HOME").map(|home| format!("{}/.terminfo", home)));
+    if let Ok(list) = std::env::var("TERMINFO_DIRS") {
+        // An empty entry stands for the compiled-in default
+        dirs.extend(list.split(':').map(|dir| if dir.is_empty() { "/usr/share/terminfo" } else { dir }.to_string()));
+    }
+    dirs.extend(["/etc/terminfo", "/lib/terminfo", "/usr/share/terminfo", "/usr/lib/terminfo"].map(String::from));
+
+    let dirs: Vec<&Path> = dirs.iter().map(Path::new).filter(|dir| dir.is_dir()).collect();
+    // Entries sit under their first letter, or its hex code on macOS
+    dirs.is_empty()
+        || dirs.iter().any(|dir| {
+            dir.join(first.to_string()).join(term).exists() || dir.join(format!("{:x}", first as u32)).join(term).exists()
+        })
+}
+
+pub fn is_valid_env_name(key: &str) -> bool {
+    let mut chars = key.chars();
+    matches!(chars.next(), Some(c) if c == '_' || c.is_ascii_alphabetic())
+        && chars.all(|c| c == '_' || c.is_ascii_alphanumeric())
+}
+
+/// Parses `KEY=VALUE` lines, skipping blanks, comments and an optional
+/// leading `export`.
+pub fn parse_env_assignments(content: &str) -> Vec<(String, String)> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let key = key.trim();
+            if !is_valid_env_name(key) {
+                return None;
+            }
+            Some((key.to_string(), unquote(value.trim()).to_string()))
+        })
+        .collect()
+}
+
+/// Parses what `env` prints. A line that doesn't start a new `NAME=`
+/// continues the value before it, which had a newline in it.
+pub fn parse_env_output(lines: &[String]) -> BTreeMap<String, String> {
+    let mut env = BTreeMap::new();
+    let mut last: Option<String> = None;
+    for line in lines {
+        match line.split_once('=') {
+            // Exported bash functions show up as BASH_FUNC_name%%=() { ...
+            Some((key, value)) if !key.is_empty() && !key.contains(char::is_whitespace) => {
+                env.insert(key.to_string(), value.to_string());
+                last = Some(key.to_string());
+            }
+            _ => {
+                if let Some(value) = last.as_ref().and_then(|key| env.get_mut(key)) {
+                    value.push('\n');
+                    value.push_str(line);
+                }
+            }
+        }
+    }
+    env
+}
+
+fn unquote(value: &str) -> &str {
+    for quote in ['"', '\''] {
+        if value.len() >= 2 && value.starts_with(quote) && value.ends_with(quote) {
+            return &value[1..value.len() - 1];
+        }
+    }
+    value
+}
+
+/// `value` quoted for a POSIX shell, which takes it as one word.
+pub fn single_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+fn fish_quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+fn double_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dialect_detection() {
+        assert_eq!(ShellDialect::detect("/bin/bash"), ShellDialect::Posix);
+        assert_eq!(ShellDialect::detect("/usr/local/bin/fish"), ShellDialect::Fish);
+        assert_eq!(ShellDialect::detect("nu"), ShellDialect::Nu);
+        assert_eq!(ShellDialect::detect("-tcsh"), ShellDialect::Csh);
+    }
+
+    #[test]
+    fn test_export_line_quoting() {
+        assert_eq!(
+            ShellDialect::Posix.export_line("TOKEN", "a'b"),
+            " export TOKEN='a'\\''b'\r"
+        );
+        assert_eq!(
+            ShellDialect::Fish.export_line("TOKEN", "a'b"),
+            " set -gx TOKEN 'a\\'b'\r"
+        );
+        assert_eq!(
+            ShellDialect::Nu.export_line("TOKEN", "a\"b"),
+            " $env.TOKEN = \"a\\\"b\"\r"
+        );
+    }
+
+    #[test]
+    fn test_editor_line() {
+        let path = Path::new("/tmp/paste it.txt");
+        assert_eq!(ShellDialect::Posix.editor_line(path), "${EDITOR:-vi} '/tmp/paste it.txt'");
+        assert_eq!(ShellDialect::Fish.editor_line(path), "$EDITOR '/tmp/paste it.txt'");
+        assert_eq!(ShellDialect::Nu.editor_line(path), "^$env.EDITOR \"/tmp/paste it.txt\"");
+    }
+
+    #[test]
+    fn test_startup_queue_waits_for_quiet_prompt() {
+        let start = Instant::now();
+        let settle = Duration::from_millis(300);
+        let mut queue = StartupQueue::new(vec!["cd /tmp".to_string(), "ls".to_string()], settle);
+
+        // Nothing printed yet: the shell may still be starting
+        assert!(queue.take_ready(start + settle * 10).is_empty());
+
+        queue.observe_output(start);
+        assert!(queue.take_ready(start + settle / 2).is_empty());
+        assert_eq!(
+            queue.take_ready(start + settle),
+            vec!["cd /tmp\r".to_string(), "ls\r".to_string()]
+        );
+        assert!(queue.is_empty());
+        assert!(queue.take_ready(start + settle * 2).is_empty());
+    }
+
+    #[test]
+    fn test_input_waits_for_next_prompt() {
+        let start = Instant::now();
+        let settle = Duration::from_millis(300);
+        let mut queue = StartupQueue::new(Vec::new(), settle);
+        queue.observe_output(start);
+
+        queue.push_after_output("print(1)\r".to_string());
+        assert!(queue.take_ready(start + settle).is_empty());
+        queue.observe_output(start + settle);
+        assert_eq!(queue.take_ready(start + settle * 2), vec!["print(1)\r".to_string()]);
+    }
+
+    #[test]
+    fn test_keys_held_until_input_sent() {
+        let start = Instant::now();
+        let limit = Duration::from_secs(2);
+        let mut hold = KeyHold::new(limit);
+        assert_eq!(hold.hold("a"), Some("a"));
+
+        hold.start(start);
+        assert_eq!(hold.hold("b"), None);
+        assert_eq!(hold.hold("c"), None);
+        assert!(hold.release(start, true).is_empty());
+        assert_eq!(hold.release(start, false), vec!["b", "c"]);
+        assert_eq!(hold.hold("d"), Some("d"));
+
+        // Input that never goes out doesn't keep keys forever
+        hold.start(start);
+        assert_eq!(hold.hold("e"), None);
+        assert_eq!(hold.release(start + limit, true), vec!["e"]);
+    }
+
+    #[test]
+    fn test_last_command() {
+        let transcript: Vec<String> = "~/app$ make\nok\n~/app$ cargo test\n1 passed\n2 failed\n~/app$"
+            .lines()
+            .map(str::to_string)
+            .collect();
+        assert_eq!(
+            last_command("~/app$ ", &transcript),
+            Some(("cargo test".to_string(), vec!["1 passed".to_string(), "2 failed".to_string()]))
+        );
+        assert_eq!(last_command("", &transcript), None);
+    }
+
+    #[test]
+    fn test_unsubmitted_chars() {
+        assert_eq!(unsubmitted_chars("git status"), 10);
+        assert_eq!(unsubmitted_chars("cd /tmp\rls -lä"), 6);
+        assert_eq!(unsubmitted_chars("make\r"), 0);
+        assert_eq!(unsubmitted_chars("\x03"), 0);
+    }
+
+    #[test]
+    fn test_parse_env_assignments() {
+        let vars = parse_env_assignments("# creds\nexport A=1\nB='two words'\n9BAD=x\n\n");
+        assert_eq!(
+            vars,
+            vec![
+                ("A".to_string(), "1".to_string()),
+                ("B".to_string(), "two words".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_terminal_env() {
+        let env = |pairs: &'static [(&'static str, &'static str)]| {
+            move |key: &str| pairs.iter().find(|(k, _)| *k == key).map(|(_, v)| v.to_string())
+        };
+        let known = |term: &str| ["xterm-256color", "xterm-kitty", "screen"].contains(&term);
+
+        // A known TERM and a locale need nothing
+        assert!(terminal_env(env(&[("TERM", "screen"), ("LANG", "de_DE.UTF-8")]), known, None).is_empty());
+        // An unknown or missing TERM falls back; no locale at all gets one
+        assert_eq!(
+            terminal_env(env(&[("TERM", "weird-ssh-client")]), known, None),
+            vec![("TERM", FALLBACK_TERM.to_string()), ("LANG", "C.UTF-8".to_string())]
+        );
+        assert_eq!(terminal_env(env(&[("LC_ALL", "C")]), known, None), vec![("TERM", FALLBACK_TERM.to_string())]);
+        // An override wins even over a known TERM
+        assert_eq!(
+            terminal_env(env(&[("TERM", "screen"), ("LANG", "C")]), known, Some("xterm")),
+            vec![("TERM", "xterm".to_string())]
+        );
+        // kitty over SSH lost its COLORTERM
+        assert_eq!(
+            terminal_env(env(&[("TERM", "xterm-kitty"), ("LANG", "C")]), known, None),
+            vec![("COLORTERM", "truecolor".to_string())]
+        );
+        assert!(terminal_env(env(&[("TERM", "xterm-kitty"), ("COLORTERM", "24bit"), ("LANG", "C")]), known, None).is_empty());
+        assert!(!terminfo_known("../etc/passwd"));
+    }
+
+    #[test]
+    fn test_parse_env_output() {
+        let lines: Vec<String> = ["HOME=/home/me", "MOTD=line one", "line two", "EMPTY=", "BASH_FUNC_hi%%=() {  echo hi", "}"]
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let env = parse_env_output(&lines);
+        assert_eq!(env["HOME"], "/home/me");
+        assert_eq!(env["MOTD"], "line one\nline two");
+        assert_eq!(env["EMPTY"], "");
+        assert_eq!(env["BASH_FUNC_hi%%"], "() {  echo hi\n}");
+        assert_eq!(env.len(), 4);
+    }
+}
@@ -0,0 +1,136 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::config::expand_home;
+
+/// The variable the `ssh` wrapper sends along. Stock sshd configs accept
+/// `LC_*` from clients and reject most other names.
+pub const SESSION_VAR: &str = "LC_CHATSHELL";
+
+/// Where `chatshell init` puts the `ssh` wrapper by default.
+pub fn default_wrapper_dir() -> PathBuf {
+    PathBuf::from(expand_home("~/.local/share/chatshell/bin"))
+}
+
+/// A `sh` script that runs the next `ssh` on `PATH` and, inside a chatshell
+/// session, sends `LC_CHATSHELL` to the remote host. Elsewhere it is plain
+/// `ssh`.
+pub fn wrapper_script() -> String {
+    format!(
+        r#"#!/bin/sh
+# Written by `chatshell init`. Inside a chatshell session, tells the remote
+# shell so through ${var}, for prompts that emit OSC 133 marks.
+self_dir=$(cd "$(dirname "$0")" && pwd)
+real=
+IFS=:
+for dir in $PATH; do
+    [ "$dir" = "$self_dir" ] && continue
+    if [ -x "$dir/ssh" ]; then
+        real="$dir/ssh"
+        break
+    fi
+done
+unset IFS
+if [ -z "$real" ]; then
+    echo "ssh: not found on PATH" >&2
+    exit 127
+fi
+if [ -n "$CHATSHELL_SOCKET" ]; then
+    {var}={version}
+    export {var}
+    exec "$real" -o SendEnv={var} "$@"
+fi
+exec "$real" "$@"
+"#,
+        var = SESSION_VAR,
+        version = env!("CARGO_PKG_VERSION"),
+    )
+}
+
+/// Writes the wrapper as `ssh` in `dir`, replacing an older one, and
+/// returns its path.
+pub fn install_wrapper(dir: &Path) -> Result<PathBuf> {
+    fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+    let path = dir.join("ssh");
+    fs::write(&path, wrapper_script()).with_context(|| format!("Failed to write {}", path.display()))?;
+    fs::set_permissions(&path, fs::Permissions::from_mode(0o755))
+        .with_context(|| format!("Failed to make {} executable", path.display()))?;
+    Ok(path)
+}
+
+/// Lines for the remote `~/.bashrc` that mark prompts and finished commands
+/// and report the directory with `OSC 7` when the session came through the
+/// wrapper. They run ahead of an existing `PROMPT_COMMAND`, which still sees
+/// the command's status.
+pub fn remote_snippet() -> String {
+    format!(
+        r#"if [ -n "${var}" ]; then
+    __chatshell_prompt() {{
+        local status=$?
+        printf '\033]133;D;%s\007\033]7;file://%s%s\007\033]133;A\007' "$status" "$HOSTNAME" "$PWD"
+        return $status
+    }}
+    case "$PROMPT_COMMAND" in
+        *__chatshell_prompt*) ;;
+        *) PROMPT_COMMAND="__chatshell_prompt${{PROMPT_COMMAND:+; $PROMPT_COMMAND}}" ;;
+    esac
+    PS0='\033]133;C\007'
+fi"#,
+        var = SESSION_VAR,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    #[test]
+    fn test_wrapper_runs_the_next_ssh() {
+        let dir = tempfile::tempdir().unwrap();
+        let wrapper_dir = dir.path().join("wrapper");
+        let real_dir = dir.path().join("real");
+        let wrapper = install_wrapper(&wrapper_dir).unwrap();
+        assert_eq!(fs::metadata(&wrapper).unwrap().permissions().mode() & 0o777, 0o755);
+
+        // A stand-in ssh that shows what it was given
+        fs::create_dir_all(&real_dir).unwrap();
+        let fake = real_dir.join("ssh");
+        fs::write(&fake, "#!/bin/sh\necho \"$LC_CHATSHELL|$*\"\n").unwrap();
+        fs::set_permissions(&fake, fs::Permissions::from_mode(0o755)).unwrap();
+        let path = format!("{}:{}:/usr/bin:/bin", wrapper_dir.display(), real_dir.display());
+
+        let run = |socket: Option<&str>| {
+            let mut command = Command::new(&wrapper);
+            command.arg("host").env("PATH", &path).env_remove("CHATSHELL_SOCKET").env_remove(SESSION_VAR);
+            if let Some(socket) = socket {
+                command.env("CHATSHELL_SOCKET", socket);
+            }
+            String::from_utf8(command.output().unwrap().stdout).unwrap()
+        };
+        let version = env!("CARGO_PKG_VERSION");
+        assert_eq!(run(Some("/tmp/cs.sock")), format!("{}|-o SendEnv=LC_CHATSHELL host\n", version));
+        assert_eq!(run(None), "|host\n");
+    }
+
+    #[test]
+    fn test_remote_snippet_chains_prompt_command() {
+        // Sourced twice, as a .bashrc can be, over a PROMPT_COMMAND of its own
+        let script = format!(
+            "PROMPT_COMMAND='echo \" mine $?\"'\n{snippet}\n{snippet}\ncd /tmp; false; eval \"$PROMPT_COMMAND\"",
+            snippet = remote_snippet(),
+        );
+        let output = Command::new("bash")
+            .args(["-c", &script])
+            .env(SESSION_VAR, "1")
+            .env("HOSTNAME", "board")
+            .output()
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output.stdout).unwrap(),
+            "\x1b]133;D;1\x07\x1b]7;file://board/tmp\x07\x1b]133;A\x07 mine 1\n",
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::HookAction;
+
+/// What `chatshell state save` keeps of a session, to pick it up again
+/// later with `chatshell state load`. The shell itself isn't part of it.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SessionState {
+    /// Whether each hook was on, by name.
+    pub hooks: BTreeMap<String, bool>,
+    /// Keys bound for the session only.
+    pub bindings: Vec<SavedBinding>,
+    /// Popups pinned as notes, oldest first. Toasts aren't kept.
+    pub notes: Vec<SavedNote>,
+    /// The command in the watch panel.
+    pub watch: Option<SavedWatch>,
+    /// The conversation with the assistant, oldest first.
+    pub chat: Vec<SavedMessage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedBinding {
+    pub key: String,
+    /// A single action or a chain, as the binding was made.
+    pub action: HookAction,
+    /// How much of its lifetime was left, in seconds; it lasts the session
+    /// if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub remaining_secs: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedNote {
+    pub title: String,
+    pub lines: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedMessage {
+    pub question: String,
+    pub answer: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SavedWatch {
+    pub command: String,
+    pub interval_ms: u64,
+}
+
+/// Where saved states go: `states` next to the config.
+pub fn states_dir(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join("states")
+}
+
+/// True if `name` is a single file name, so that one can't point outside
+/// the directory it is looked up in.
+pub fn valid_name(name: &str) -> bool {
+    !name.is_empty() && !name.starts_with('.') && name.chars().all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.'))
+}
+
+/// The file of the state called `name`.
+fn state_path(dir: &Path, name: &str) -> Result<PathBuf> {
+    if !valid_name(name) {
+        anyhow::bail!("Not a state name: {} (use letters, digits, '-', '_' and '.')", name);
+    }
+    Ok(dir.join(format!("{}.json", name)))
+}
+
+impl SessionState {
+    /// Saves the state as `name` in `dir`, replacing one saved before, and
+    /// returns where it went.
+    pub fn save(&self, dir: &Path, name: &str) -> Result<PathBuf> {
+        let path = state_path(dir, name)?;
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create states directory: {}", dir.display()))?;
+        let json = serde_json::to_string_pretty(self)?;
+        fs::write(&path, json).with_context(|| format!("Failed to save state: {}", path.display()))?;
+        Ok(path)
+    }
+
+    pub fn load(dir: &Path, name: &str) -> Result<Self> {
+        let path = state_path(dir, name)?;
+        if !path.exists() {
+            anyhow::bail!("No state saved as {}", name);
+        }
+        let json = fs::read_to_string(&path).with_context(|| format!("Failed to read state: {}", path.display()))?;
+        serde_json::from_str(&json).with_context(|| format!("Failed to parse state: {}", path.display()))
+    }
+
+    /// What it holds, in a line.
+    pub fn summary(&self) -> String {
+        let off = self.hooks.values().filter(|enabled| !**enabled).count();
+        format!(
+            "{} hooks ({} off), {} bindings, {} notes, {} chat messages, {}",
+            self.hooks.len(),
+            off,
+            self.bindings.len(),
+            self.notes.len(),
+            self.chat.len(),
+            match &self.watch {
+                Some(watch) => format!("watching {}", watch.command),
+                None => "no watch".to_string(),
+            }
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = SessionState {
+            hooks: BTreeMap::from([("ask".to_string(), true), ("time".to_string(), false)]),
+            bindings: vec![
+                SavedBinding { key: "f9".to_string(), action: "cmd:make".into(), remaining_secs: Some(600) },
+                SavedBinding {
+                    key: "f10".to_string(),
+                    action: HookAction::Chain(vec!["cmd:make".to_string(), "inject:make install\\n".to_string()]),
+                    remaining_secs: None,
+                },
+            ],
+            notes: vec![SavedNote { title: "Repro".to_string(), lines: vec!["curl -v :8080/health".to_string()] }],
+            watch: Some(SavedWatch { command: "kubectl get pods".to_string(), interval_ms: 2000 }),
+            chat: vec![SavedMessage { question: "Why is the pod pending?".to_string(), answer: "No node has room.".to_string() }],
+        };
+        let path = state.save(dir.path(), "incident-42").unwrap();
+        assert!(path.ends_with("incident-42.json"));
+        assert_eq!(SessionState::load(dir.path(), "incident-42").unwrap(), state);
+        assert_eq!(state.summary(), "2 hooks (1 off), 2 bindings, 1 notes, 1 chat messages, watching kubectl get pods");
+
+        assert!(SessionState::load(dir.path(), "tomorrow").is_err());
+        assert!(state.save(dir.path(), "../escape").is_err());
+        assert!(state.save(dir.path(), ".hidden").is_err());
+    }
+}
@@ -0,0 +1,326 @@
+use anyhow::{Context, Result};
+#[cfg(feature = "sqlite")]
+use rusqlite::{params, Connection};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::config::{expand_home, HistoryConfig};
+use crate::history::{Annotation, CommandRecord, LlmUsage};
+
+/// Where the history is kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// A SQLite database file.
+    #[cfg_attr(feature = "sqlite", default)]
+    Sqlite,
+    /// A directory of append-only JSON Lines files, one per kind of record.
+    /// The default when chatshell is built without the sqlite feature.
+    #[cfg_attr(not(feature = "sqlite"), default)]
+    Jsonl,
+}
+
+impl StorageBackend {
+    pub fn default_path(&self) -> &'static str {
+        match self {
+            StorageBackend::Sqlite => "~/.local/share/chatshell/history.db",
+            StorageBackend::Jsonl => "~/.local/share/chatshell/history",
+        }
+    }
+}
+
+/// Persistence for what chatshell records. Records are only ever added, so
+/// every backend can be append-only.
+pub trait Storage: std::fmt::Debug {
+    fn add_command(&mut self, record: &CommandRecord) -> Result<()>;
+
+    fn add_llm_usage(&mut self, usage: &LlmUsage) -> Result<()>;
+
+    fn add_annotation(&mut self, annotation: &Annotation) -> Result<()>;
+
+    /// Commands started at or after `since` (Unix time), oldest first.
+    fn commands_since(&self, since: i64) -> Result<Vec<CommandRecord>>;
+
+    /// LLM calls made at or after `since` (Unix time), oldest first.
+    fn llm_usage_since(&self, since: i64) -> Result<Vec<LlmUsage>>;
+
+    /// Annotations made at or after `since` (Unix time), oldest first.
+    fn annotations_since(&self, since: i64) -> Result<Vec<Annotation>>;
+}
+
+/// Opens the backend `config` declares.
+pub fn open(config: &HistoryConfig) -> Result<Box<dyn Storage>> {
+    let path = PathBuf::from(expand_home(&config.path()));
+    Ok(match config.backend {
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => Box::new(SqliteStorage::open(&path)?),
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => {
+            anyhow::bail!("chatshell was built without the sqlite feature; set [history] backend = \"jsonl\"")
+        }
+        StorageBackend::Jsonl => Box::new(JsonlStorage::open(&path)?),
+    })
+}
+
+fn create_dir(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)
+        .with_context(|| format!("Failed to create history directory: {}", dir.display()))
+}
+
+#[cfg(feature = "sqlite")]
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS commands (
+    id INTEGER PRIMARY KEY,
+    started_at INTEGER NOT NULL,
+    session TEXT NOT NULL,
+    command TEXT NOT NULL,
+    cwd TEXT,
+    repo TEXT,
+    exit_status INTEGER
+);
+CREATE INDEX IF NOT EXISTS commands_started_at ON commands (started_at);
+CREATE TABLE IF NOT EXISTS llm_usage (
+    id INTEGER PRIMARY KEY,
+    at INTEGER NOT NULL,
+    purpose TEXT NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    reply_tokens INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS annotations (
+    id INTEGER PRIMARY KEY,
+    at INTEGER NOT NULL,
+    session TEXT NOT NULL,
+    lines TEXT NOT NULL,
+    comment TEXT NOT NULL
+);
+";
+
+#[cfg(feature = "sqlite")]
+#[derive(Debug)]
+pub struct SqliteStorage {
+    connection: Connection,
+}
+
+#[cfg(feature = "sqlite")]
+impl SqliteStorage {
+    pub fn open(path: &Path) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            create_dir(parent)?;
+        }
+        let connection = Connection::open(path)
+            .with_context(|| format!("Failed to open history: {}", path.display()))?;
+        connection.execute_batch(SCHEMA)
+            .with_context(|| format!("Failed to set up history: {}", path.display()))?;
+        Ok(SqliteStorage { connection })
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl Storage for SqliteStorage {
+    fn add_command(&mut self, record: &CommandRecord) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO commands (started_at, session, command, cwd, repo, exit_status) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![record.started_at, record.session, record.command, record.cwd, record.repo, record.exit_status],
+        ).with_context(|| "Failed to record command")?;
+        Ok(())
+    }
+
+    fn add_llm_usage(&mut self, usage: &LlmUsage) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO llm_usage (at, purpose, prompt_tokens, reply_tokens) VALUES (?1, ?2, ?3, ?4)",
+            params![usage.at, usage.purpose, usage.prompt_tokens, usage.reply_tokens],
+        ).with_context(|| "Failed to record LLM usage")?;
+        Ok(())
+    }
+
+    fn add_annotation(&mut self, annotation: &Annotation) -> Result<()> {
+        self.connection.execute(
+            "INSERT INTO annotations (at, session, lines, comment) VALUES (?1, ?2, ?3, ?4)",
+            params![annotation.at, annotation.session, annotation.lines.join("\n"), annotation.comment],
+        ).with_context(|| "Failed to record annotation")?;
+        Ok(())
+    }
+
+    fn commands_since(&self, since: i64) -> Result<Vec<CommandRecord>> {
+        let mut statement = self.connection.prepare(
+            "SELECT started_at, session, command, cwd, repo, exit_status FROM commands WHERE started_at >= ?1 ORDER BY started_at, id",
+        )?;
+        let rows = statement.query_map(params![since], |row| {
+            Ok(CommandRecord {
+                started_at: row.get(0)?,
+                session: row.get(1)?,
+                command: row.get(2)?,
+                cwd: row.get(3)?,
+                repo: row.get(4)?,
+                exit_status: row.get(5)?,
+            })
+        })?;
+        rows.collect::<rusqlite::Result<_>>()
+            .with_context(|| "Failed to read history")
+    }
+
+    fn llm_usage_since(&self, since: i64) -> Result<Vec<LlmUsage>> {
+        let mut statement = self.connection.prepare(
+            "SELECT at, purpose, prompt_tokens, reply_tokens FROM llm_usage WHERE at >= ?1 ORDER BY at, id",
+        )?;
+        let rows = statement.query_map(params![since], |row| {
+            Ok(LlmUsage {
+                at: row.get(0)?,
+                purpose: row.get(1)?,
+                prompt_tokens: row.get(2)?,
+                reply_tokens: row.get(3)?,
+            })
+        })?;
+        rows.collect::<rusqlite::Result<_>>()
+            .with_context(|| "Failed to read LLM usage")
+    }
+
+    fn annotations_since(&self, since: i64) -> Result<Vec<Annotation>> {
+        let mut statement = self.connection.prepare(
+            "SELECT at, session, lines, comment FROM annotations WHERE at >= ?1 ORDER BY at, id",
+        )?;
+        let rows = statement.query_map(params![since], |row| {
+            let lines: String = row.get(2)?;
+            Ok(Annotation {
+                at: row.get(0)?,
+                session: row.get(1)?,
+                lines: lines.split('\n').map(str::to_string).collect(),
+                comment: row.get(3)?,
+            })
+        })?;
+        rows.collect::<rusqlite::Result<_>>()
+            .with_context(|| "Failed to read annotations")
+    }
+}
+
+/// Plain text storage for places where SQLite files aren't allowed, or
+/// where the history is synced between machines as text.
+#[derive(Debug)]
+pub struct JsonlStorage {
+    dir: PathBuf,
+}
+
+impl JsonlStorage {
+    pub fn open(dir: &Path) -> Result<Self> {
+        create_dir(dir)?;
+        Ok(JsonlStorage { dir: dir.to_path_buf() })
+    }
+
+    fn append<T: Serialize>(&self, file: &str, record: &T) -> Result<()> {
+        let path = self.dir.join(file);
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+        // One write per record, so concurrent sessions don't interleave lines
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// Reads the records of `file`. Lines that don't parse, such as one cut
+    /// off by a crash or a sync conflict, are skipped.
+    fn read<T: DeserializeOwned>(&self, file: &str) -> Result<Vec<T>> {
+        let path = self.dir.join(file);
+        let content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        Ok(content.lines().filter_map(|line| serde_json::from_str(line).ok()).collect())
+    }
+}
+
+impl Storage for JsonlStorage {
+    fn add_command(&mut self, record: &CommandRecord) -> Result<()> {
+        self.append("commands.jsonl", record)
+    }
+
+    fn add_llm_usage(&mut self, usage: &LlmUsage) -> Result<()> {
+        self.append("llm_usage.jsonl", usage)
+    }
+
+    fn add_annotation(&mut self, annotation: &Annotation) -> Result<()> {
+        self.append("annotations.jsonl", annotation)
+    }
+
+    fn commands_since(&self, since: i64) -> Result<Vec<CommandRecord>> {
+        let mut commands: Vec<CommandRecord> = self.read("commands.jsonl")?;
+        commands.retain(|record| record.started_at >= since);
+        // Files merged by a sync tool may be out of order
+        commands.sort_by_key(|record| record.started_at);
+        Ok(commands)
+    }
+
+    fn llm_usage_since(&self, since: i64) -> Result<Vec<LlmUsage>> {
+        let mut usage: Vec<LlmUsage> = self.read("llm_usage.jsonl")?;
+        usage.retain(|call| call.at >= since);
+        usage.sort_by_key(|call| call.at);
+        Ok(usage)
+    }
+
+    fn annotations_since(&self, since: i64) -> Result<Vec<Annotation>> {
+        let mut annotations: Vec<Annotation> = self.read("annotations.jsonl")?;
+        annotations.retain(|annotation| annotation.at >= since);
+        annotations.sort_by_key(|annotation| annotation.at);
+        Ok(annotations)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(started_at: i64, command: &str) -> CommandRecord {
+        CommandRecord {
+            started_at,
+            session: "1".to_string(),
+            command: command.to_string(),
+            cwd: None,
+            repo: None,
+            exit_status: Some(0),
+        }
+    }
+
+    #[test]
+    fn test_backends_store_records() {
+        let dir = tempfile::tempdir().unwrap();
+        #[cfg_attr(not(feature = "sqlite"), allow(unused_mut))]
+        let mut backends: Vec<Box<dyn Storage>> = vec![Box::new(JsonlStorage::open(&dir.path().join("history")).unwrap())];
+        #[cfg(feature = "sqlite")]
+        backends.push(Box::new(SqliteStorage::open(&dir.path().join("history.db")).unwrap()));
+        for mut storage in backends {
+            storage.add_command(&command(20, "make")).unwrap();
+            storage.add_command(&command(10, "ls")).unwrap();
+            storage.add_llm_usage(&LlmUsage { at: 5, purpose: "ask".to_string(), prompt_tokens: 3, reply_tokens: 1 }).unwrap();
+
+            let commands = storage.commands_since(10).unwrap();
+            assert_eq!(commands, vec![command(10, "ls"), command(20, "make")]);
+            assert_eq!(storage.commands_since(11).unwrap().len(), 1);
+            assert_eq!(storage.llm_usage_since(0).unwrap()[0].prompt_tokens, 3);
+
+            let annotation = Annotation {
+                at: 30,
+                session: "1".to_string(),
+                lines: vec!["error: pool exhausted".to_string(), "retrying".to_string()],
+                comment: "Starts after the deploy".to_string(),
+            };
+            storage.add_annotation(&annotation).unwrap();
+            assert_eq!(storage.annotations_since(0).unwrap(), vec![annotation]);
+        }
+    }
+
+    #[test]
+    fn test_jsonl_skips_broken_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut storage = JsonlStorage::open(dir.path()).unwrap();
+        storage.add_command(&command(1, "ls")).unwrap();
+        let mut file = OpenOptions::new().append(true).open(dir.path().join("commands.jsonl")).unwrap();
+        file.write_all(b"{\"started_at\": 2, \"comm").unwrap();
+        assert_eq!(storage.commands_since(0).unwrap(), vec![command(1, "ls")]);
+    }
+}
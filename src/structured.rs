@@ -0,0 +1,101 @@
+use serde::Deserialize;
+
+/// What a `cmd:` hook with `structured = true` can print instead of plain
+/// text, as a JSON object.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Request {
+    /// Title of the popup, notification or menu; the command's if unset.
+    pub title: Option<String>,
+    /// Text to show where the hook's `output` says.
+    pub popup: Option<String>,
+    /// Text for a desktop notification.
+    pub notify: Option<String>,
+    /// Text to type into the shell, as is.
+    pub send_keys: Option<String>,
+    /// Choices to pick one of, by their digit.
+    pub menu: Option<Vec<MenuItem>>,
+    /// An action to run, written as a hook's `action`.
+    pub action: Option<String>,
+}
+
+/// A choice in a `menu`: text typed into the shell when picked, or an
+/// object labelling what it does.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum MenuItem {
+    Text(String),
+    Choice(MenuChoice),
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MenuChoice {
+    pub label: String,
+    #[serde(default)]
+    pub send_keys: Option<String>,
+    #[serde(default)]
+    pub action: Option<String>,
+}
+
+impl MenuItem {
+    pub fn label(&self) -> &str {
+        match self {
+            MenuItem::Text(text) => text,
+            MenuItem::Choice(choice) => &choice.label,
+        }
+    }
+}
+
+/// The requests in what a command printed: one object, an array of them
+/// or one per line. None if anything in it isn't a request, so that it is
+/// shown as text instead.
+pub fn parse(stdout: &str) -> Option<Vec<Request>> {
+    let text = stdout.trim();
+    if !text.starts_with(['{', '[']) {
+        return None;
+    }
+    if let Ok(request) = serde_json::from_str::<Request>(text) {
+        return Some(vec![request]);
+    }
+    if let Ok(requests) = serde_json::from_str::<Vec<Request>>(text) {
+        return Some(requests);
+    }
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requests() {
+        assert_eq!(parse("{\"popup\": \"3 pods restarting\"}").unwrap(), vec![Request {
+            popup: Some("3 pods restarting".to_string()),
+            ..Request::default()
+        }]);
+
+        let lines = "{\"notify\": \"built\"}\n\n{\"send_keys\": \"./app\", \"title\": \"Run\"}\n";
+        let requests = parse(lines).unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[1].send_keys.as_deref(), Some("./app"));
+
+        let menu = parse("[{\"menu\": [\"git pull\", {\"label\": \"Deploy\", \"action\": \"cmd:./deploy\"}]}]").unwrap();
+        let items = menu[0].menu.as_ref().unwrap();
+        assert_eq!(items.iter().map(MenuItem::label).collect::<Vec<_>>(), vec!["git pull", "Deploy"]);
+        assert_eq!(items[1], MenuItem::Choice(MenuChoice {
+            label: "Deploy".to_string(),
+            send_keys: None,
+            action: Some("cmd:./deploy".to_string()),
+        }));
+
+        // Anything else is shown as it was printed
+        assert_eq!(parse("build ok"), None);
+        assert_eq!(parse("{\"status\": \"ok\"}"), None);
+        assert_eq!(parse("{\"popup\": \"one\"}\nnot json"), None);
+        assert_eq!(parse("[1, 2]"), None);
+    }
+}
@@ -0,0 +1,191 @@
+use anyhow::{Context, Result};
+use regex::Regex;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::os::unix::fs::MetadataExt;
+use std::process::{Child, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+
+use crate::config::expand_home;
+use crate::output::{Segment, SequenceScanner};
+use crate::watch::clean_line;
+
+/// How much of an existing file is shown when tailing starts.
+const INITIAL_BYTES: u64 = 16 * 1024;
+
+/// Lines kept for scrolling back.
+const MAX_TAIL_LINES: usize = 1000;
+
+#[derive(Debug)]
+enum Source {
+    File { path: String, file: File, offset: u64 },
+    Command { child: Child, output: Receiver<Vec<u8>> },
+}
+
+/// The growing end of a file, or the output of a running command.
+#[derive(Debug)]
+pub struct Tail {
+    source: Source,
+    scanner: SequenceScanner,
+    partial: String,
+    lines: VecDeque<String>,
+}
+
+impl Tail {
+    /// Follows the file at `spec`, or the output of the command after a
+    /// leading `!`.
+    pub fn open(spec: &str) -> Result<Self> {
+        let source = match spec.strip_prefix('!') {
+            Some(command) => {
+                let mut child = Command::new("/bin/sh")
+                    .arg("-c")
+                    .arg(format!("exec 2>&1\n{}", command))
+                    .stdin(Stdio::null())
+                    .stdout(Stdio::piped())
+                    .spawn()
+                    .with_context(|| format!("Failed to run: {}", command))?;
+                let mut stdout = child.stdout.take()
+                    .with_context(|| "Command has no output")?;
+                let (tx, rx) = mpsc::channel();
+                std::thread::spawn(move || {
+                    let mut buffer = [0u8; 4096];
+                    while let Ok(n) = stdout.read(&mut buffer) {
+                        if n == 0 || tx.send(buffer[..n].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                });
+                Source::Command { child, output: rx }
+            }
+            None => {
+                let path = expand_home(spec);
+                let file = File::open(&path)
+                    .with_context(|| format!("Failed to open {}", path))?;
+                let offset = file.metadata()?.len().saturating_sub(INITIAL_BYTES);
+                Source::File { path, file, offset }
+            }
+        };
+
+        let mut tail = Tail {
+            source,
+            scanner: SequenceScanner::new(),
+            partial: String::new(),
+            lines: VecDeque::new(),
+        };
+        let skip_first = matches!(tail.source, Source::File { offset, .. } if offset > 0);
+        tail.poll()?;
+        if skip_first {
+            // Started mid-file, so the first line is probably cut off
+            tail.lines.pop_front();
+        }
+        Ok(tail)
+    }
+
+    /// Reads whatever arrived since the last call and returns how many new
+    /// lines it completed.
+    pub fn poll(&mut self) -> Result<usize> {
+        let mut data = Vec::new();
+        match &mut self.source {
+            Source::File { path, file, offset } => {
+                // Start over if the file was rotated or truncated
+                if let Ok(metadata) = std::fs::metadata(&*path) {
+                    if metadata.ino() != file.metadata()?.ino() {
+                        *file = File::open(&*path)?;
+                        *offset = 0;
+                    } else if metadata.len() < *offset {
+                        *offset = 0;
+                    }
+                }
+                file.seek(SeekFrom::Start(*offset))?;
+                file.read_to_end(&mut data)
+                    .with_context(|| format!("Failed to read {}", path))?;
+                *offset += data.len() as u64;
+            }
+            Source::Command { output, .. } => {
+                while let Ok(chunk) = output.try_recv() {
+                    data.extend(chunk);
+                }
+            }
+        }
+        Ok(self.push(&data))
+    }
+
+    fn push(&mut self, data: &[u8]) -> usize {
+        // Colors and other escape sequences are dropped
+        for segment in self.scanner.scan(data) {
+            if let Segment::Text(text) = segment {
+                self.partial.push_str(&String::from_utf8_lossy(&text));
+            }
+        }
+
+        let mut added = 0;
+        while let Some(end) = self.partial.find('\n') {
+            let line = clean_line(self.partial[..end].trim_end_matches('\r'));
+            self.partial.drain(..=end);
+            self.lines.push_back(line);
+            if self.lines.len() > MAX_TAIL_LINES {
+                self.lines.pop_front();
+            }
+            added += 1;
+        }
+        added
+    }
+
+    pub fn lines(&self) -> &VecDeque<String> {
+        &self.lines
+    }
+}
+
+impl Drop for Tail {
+    fn drop(&mut self) {
+        if let Source::Command { child, .. } = &mut self.source {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+/// Splits `line` into pieces, flagging the ones `pattern` matches.
+pub fn highlight_spans<'a>(line: &'a str, pattern: &Regex) -> Vec<(&'a str, bool)> {
+    let mut spans = Vec::new();
+    let mut last = 0;
+    for found in pattern.find_iter(line).filter(|found| !found.is_empty()) {
+        if found.start() > last {
+            spans.push((&line[last..found.start()], false));
+        }
+        spans.push((found.as_str(), true));
+        last = found.end();
+    }
+    if last < line.len() {
+        spans.push((&line[last..], false));
+    }
+    spans
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    #[test]
+    fn test_tail_follows_file() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write!(file, "one\ntw").unwrap();
+        let mut tail = Tail::open(file.path().to_str().unwrap()).unwrap();
+        assert_eq!(tail.lines(), &["one"]);
+
+        write!(file, "o\n\x1b[31mthree\x1b[0m\n").unwrap();
+        assert_eq!(tail.poll().unwrap(), 2);
+        assert_eq!(tail.lines(), &["one", "two", "three"]);
+    }
+
+    #[test]
+    fn test_highlight_spans() {
+        let pattern = Regex::new("ERR[A-Z]*").unwrap();
+        assert_eq!(
+            highlight_spans("an ERROR here", &pattern),
+            vec![("an ", false), ("ERROR", true), (" here", false)]
+        );
+    }
+}
@@ -1,16 +1,42 @@
 use crossterm::{
     cursor,
-    event::{DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyModifiers},
+    event::{
+        DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers,
+        KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
+    },
     execute,
-    terminal::{disable_raw_mode, enable_raw_mode, size, EnterAlternateScreen, LeaveAlternateScreen},
+    terminal::{
+        disable_raw_mode, enable_raw_mode, size, supports_keyboard_enhancement, EnterAlternateScreen,
+        LeaveAlternateScreen,
+    },
 };
-use std::io::{stdout, Stdout, Write};
+use std::io::{self, stdout, Stdout, Write};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
 use anyhow::{Context, Result};
 
+use crate::hatch;
+
+/// A key bound to a long press that the terminal neither reports repeating
+/// nor released within this time counts as a plain press.
+const LONG_PRESS_LIMIT: Duration = Duration::from_secs(2);
+
 #[derive(Debug)]
 pub struct Terminal {
     stdout: Stdout,
     pub raw_mode_enabled: bool, // Made public for testing
+    // What the terminal was asked to report beyond legacy key encoding
+    keyboard: KeyboardEnhancementFlags,
+}
+
+/// How a key was pressed, for hooks bound to a double or long press.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Press {
+    #[default]
+    Single,
+    Double,
+    Long,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -18,6 +44,39 @@ pub struct KeyInput {
     pub code: KeyCode,
     pub modifiers: KeyModifiers,
     pub raw_bytes: Vec<u8>,
+    pub press: Press,
+}
+
+/// Splits a hook's `key_combination` into the press it is bound to and the
+/// key pattern: the same pattern twice (`ctrl+c ctrl+c`) is a double press
+/// and `hold ` in front of it a long press.
+pub fn split_trigger(combination: &str) -> (Press, String) {
+    let combination = combination.trim().to_lowercase();
+    if let Some(pattern) = combination.strip_prefix("hold ") {
+        return (Press::Long, pattern.trim().to_string());
+    }
+    match combination.split_whitespace().collect::<Vec<_>>().as_slice() {
+        [first, second] if first == second => (Press::Double, first.to_string()),
+        _ => (Press::Single, combination),
+    }
+}
+
+/// Splits a chord binding like `ctrl+a t` into the patterns of its prefix
+/// and its key. None if `combination` isn't a chord, including when both
+/// are the same key, which is a double press.
+pub fn split_chord(combination: &str) -> Option<(String, String)> {
+    let (press, pattern) = split_trigger(combination);
+    if press != Press::Single {
+        return None;
+    }
+    let (prefix, key) = pattern.split_once(char::is_whitespace)?;
+    Some((prefix.to_string(), key.trim().to_string()))
+}
+
+/// The prefix and the key of a chord binding, as if they had been pressed.
+pub fn parse_chord(combination: &str) -> Option<(KeyInput, KeyInput)> {
+    let (prefix, key) = split_chord(combination)?;
+    Some((KeyInput::parse(&prefix)?, KeyInput::parse(&key)?))
 }
 
 impl KeyInput {
@@ -26,6 +85,7 @@ impl KeyInput {
             code,
             modifiers,
             raw_bytes: Self::key_to_bytes(code, modifiers),
+            press: Press::Single,
         }
     }
 
@@ -33,99 +93,335 @@ impl KeyInput {
         Self::new(event.code, event.modifiers)
     }
 
+    /// The key a hook pattern such as `ctrl+shift+l` or `ctrl+c ctrl+c`
+    /// stands for, as if it had been pressed. None if the pattern names no
+    /// key.
+    pub fn parse(pattern: &str) -> Option<Self> {
+        let (press, pattern) = split_trigger(pattern);
+        let (code, modifiers) = parse_pattern(&pattern)?;
+        Some(Self { press, ..Self::new(code, modifiers) })
+    }
+
+    /// The pattern that binds a hook to this press, like `ctrl+shift+l`,
+    /// `esc esc` or `hold f5`, which `parse` reads back. Letters are named
+    /// in lowercase, as patterns match them in either case. None for keys
+    /// patterns have no name for.
+    pub fn pattern(&self) -> Option<String> {
+        let mut parts: Vec<String> = MODIFIER_NAMES
+            .iter()
+            .filter(|(modifier, _)| self.modifiers.contains(*modifier))
+            .map(|(_, name)| name.to_string())
+            .collect();
+        let key = match self.code {
+            // As terminals report shift+tab
+            KeyCode::BackTab if self.modifiers.contains(KeyModifiers::SHIFT) => "tab".to_string(),
+            code => key_name(code)?,
+        };
+        parts.push(key);
+        let pattern = parts.join("+");
+        Some(match self.press {
+            Press::Single => pattern,
+            Press::Double => format!("{} {}", pattern, pattern),
+            Press::Long => format!("hold {}", pattern),
+        })
+    }
+
+    /// True if this is the press a hook's `key_combination` is bound to.
+    /// A double press also counts as a plain one, for hooks bound to that.
+    pub fn matches_trigger(&self, combination: &str) -> bool {
+        let (press, pattern) = split_trigger(combination);
+        let press_matches = match press {
+            Press::Single => self.press != Press::Long,
+            press => self.press == press,
+        };
+        press_matches && self.matches_pattern(&pattern)
+    }
+
+    /// True if `other` is the same key with the same modifiers, however
+    /// either was pressed.
+    pub fn same_key(&self, other: &KeyInput) -> bool {
+        self.code == other.code && self.modifiers == other.modifiers
+    }
+
+    /// True if the key is the one a pattern like `ctrl+;`, `alt+enter`,
+    /// `shift+f5` or a bare `pageup` names. Letters match in either case.
     pub fn matches_pattern(&self, pattern: &str) -> bool {
-        let pattern_lower = pattern.to_lowercase();
-        
-        // Parse pattern like "ctrl+;" or "alt+enter" or just "esc"
-        let parts: Vec<&str> = pattern_lower.split('+').collect();
-        
-        let (modifier_parts, key_part) = if parts.len() == 1 {
-            // Single key without modifiers
-            (vec![], parts[0])
-        } else if parts.len() >= 2 {
-            // Key with modifiers
-            let (modifiers, keys) = parts.split_at(parts.len() - 1);
-            (modifiers.to_vec(), keys[0])
-        } else {
+        let Some((code, modifiers)) = parse_pattern(&pattern.to_lowercase()) else {
             return false;
         };
+        if self.modifiers != modifiers {
+            return false;
+        }
+        match (self.code, code) {
+            (KeyCode::Char(pressed), KeyCode::Char(wanted)) => pressed.to_lowercase().eq(wanted.to_lowercase()),
+            (pressed, wanted) => pressed == wanted,
+        }
+    }
 
-        // Check if modifiers match
-        let mut expected_modifiers = KeyModifiers::empty();
-        for modifier in &modifier_parts {
-            match *modifier {
-                "ctrl" => expected_modifiers |= KeyModifiers::CONTROL,
-                "alt" => expected_modifiers |= KeyModifiers::ALT,
-                "shift" => expected_modifiers |= KeyModifiers::SHIFT,
-                _ => return false,
+    /// The bytes a legacy, xterm-style terminal sends for the key, which
+    /// is what the shell understands however the key reached us. Modifiers
+    /// legacy encoding has no room for, like super, are left out.
+    fn key_to_bytes(code: KeyCode, modifiers: KeyModifiers) -> Vec<u8> {
+        let ctrl = modifiers.contains(KeyModifiers::CONTROL);
+        let alt = modifiers.contains(KeyModifiers::ALT);
+        // Cursor and function keys carry modifiers as a parameter
+        let parameter = 1
+            + modifiers.contains(KeyModifiers::SHIFT) as u8
+            + 2 * alt as u8
+            + 4 * ctrl as u8;
+        let letter = |prefix: &str, end: char| -> Vec<u8> {
+            if parameter > 1 {
+                format!("\x1b[1;{}{}", parameter, end).into_bytes()
+            } else {
+                format!("\x1b{}{}", prefix, end).into_bytes()
             }
-        }
+        };
+        let tilde = |number: u8| -> Vec<u8> {
+            if parameter > 1 {
+                format!("\x1b[{};{}~", number, parameter).into_bytes()
+            } else {
+                format!("\x1b[{}~", number).into_bytes()
+            }
+        };
 
-        if self.modifiers != expected_modifiers {
-            return false;
+        let bytes = match code {
+            KeyCode::Char(c) if ctrl => match control_byte(c) {
+                Some(byte) => vec![byte],
+                // For other characters with Ctrl, use the character as-is
+                None => c.to_string().into_bytes(),
+            },
+            KeyCode::Char(c) => c.to_string().into_bytes(),
+            KeyCode::Enter => vec![b'\r'],
+            KeyCode::Tab => vec![b'\t'],
+            KeyCode::BackTab => b"\x1b[Z".to_vec(),
+            KeyCode::Backspace => vec![127],
+            KeyCode::Esc => vec![27],
+            KeyCode::Up => return letter("[", 'A'),
+            KeyCode::Down => return letter("[", 'B'),
+            KeyCode::Right => return letter("[", 'C'),
+            KeyCode::Left => return letter("[", 'D'),
+            KeyCode::Home => return letter("[", 'H'),
+            KeyCode::End => return letter("[", 'F'),
+            KeyCode::PageUp => return tilde(5),
+            KeyCode::PageDown => return tilde(6),
+            KeyCode::Delete => return tilde(3),
+            KeyCode::Insert => return tilde(2),
+            KeyCode::F(n @ 1..=4) => return letter("O", (b'P' + n - 1) as char),
+            KeyCode::F(n @ 5..=12) => return tilde([15, 17, 18, 19, 20, 21, 23, 24][n as usize - 5]),
+            _ => return vec![], // Other keys not supported
+        };
+        if alt {
+            // ESC + key for Alt
+            [vec![27], bytes].concat()
+        } else {
+            bytes
         }
+    }
+}
 
-        // Check if key matches
-        match key_part {
-            ";" => matches!(self.code, KeyCode::Char(';')),
-            "enter" => matches!(self.code, KeyCode::Enter),
-            "tab" => matches!(self.code, KeyCode::Tab),
-            "space" => matches!(self.code, KeyCode::Char(' ')),
-            "esc" => matches!(self.code, KeyCode::Esc),
-            "backspace" => matches!(self.code, KeyCode::Backspace),
-            key if key.len() == 1 => {
-                if let Some(ch) = key.chars().next() {
-                    matches!(self.code, KeyCode::Char(c) if c.to_lowercase().next() == Some(ch))
-                } else {
-                    false
-                }
+/// The key and modifiers a lowercase pattern names, with the modifiers
+/// before the key and `+` between them. The key itself may be `+`, as in
+/// `ctrl++`.
+fn parse_pattern(pattern: &str) -> Option<(KeyCode, KeyModifiers)> {
+    let (modifier_part, key) = match pattern.strip_suffix("++") {
+        Some(modifiers) => (Some(modifiers), "+"),
+        None if pattern == "+" => (None, pattern),
+        None => match pattern.rsplit_once('+') {
+            Some((modifiers, key)) => (Some(modifiers), key),
+            None => (None, pattern),
+        },
+    };
+
+    let mut modifiers = KeyModifiers::empty();
+    for modifier in modifier_part.into_iter().flat_map(|part| part.split('+')) {
+        modifiers |= parse_modifier(modifier)?;
+    }
+    let code = match key_code(key)? {
+        // Terminals report shift+tab as a key of its own
+        KeyCode::Tab if modifiers.contains(KeyModifiers::SHIFT) => KeyCode::BackTab,
+        code => code,
+    };
+    Some((code, modifiers))
+}
+
+/// The key a name in a pattern stands for: a single character, or the
+/// name of a key that doesn't type one.
+fn key_code(name: &str) -> Option<KeyCode> {
+    let code = match name {
+        "enter" | "return" => KeyCode::Enter,
+        "tab" => KeyCode::Tab,
+        "backtab" => KeyCode::BackTab,
+        "space" => KeyCode::Char(' '),
+        "esc" | "escape" => KeyCode::Esc,
+        "backspace" => KeyCode::Backspace,
+        "up" => KeyCode::Up,
+        "down" => KeyCode::Down,
+        "left" => KeyCode::Left,
+        "right" => KeyCode::Right,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "insert" => KeyCode::Insert,
+        "delete" | "del" => KeyCode::Delete,
+        name => {
+            if let Some(number) = name.strip_prefix('f').and_then(|n| n.parse().ok()) {
+                return (1..=12).contains(&number).then_some(KeyCode::F(number));
+            }
+            let mut chars = name.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => KeyCode::Char(c),
+                _ => return None,
             }
-            _ => false,
         }
+    };
+    Some(code)
+}
+
+/// The name of a key in a pattern, the other way from `key_code`.
+fn key_name(code: KeyCode) -> Option<String> {
+    let name = match code {
+        KeyCode::Char(' ') => "space",
+        KeyCode::Char(c) if c.is_whitespace() || c.is_control() => return None,
+        KeyCode::Char(c) => return Some(c.to_lowercase().collect()),
+        KeyCode::F(number @ 1..=12) => return Some(format!("f{}", number)),
+        KeyCode::Enter => "enter",
+        KeyCode::Tab => "tab",
+        KeyCode::BackTab => "backtab",
+        KeyCode::Esc => "esc",
+        KeyCode::Backspace => "backspace",
+        KeyCode::Up => "up",
+        KeyCode::Down => "down",
+        KeyCode::Left => "left",
+        KeyCode::Right => "right",
+        KeyCode::Home => "home",
+        KeyCode::End => "end",
+        KeyCode::PageUp => "pageup",
+        KeyCode::PageDown => "pagedown",
+        KeyCode::Insert => "insert",
+        KeyCode::Delete => "delete",
+        _ => return None,
+    };
+    Some(name.to_string())
+}
+
+/// The modifiers a key pattern can name, in the order they are written.
+/// Terminals only report `super` and `meta` with the kitty keyboard
+/// protocol.
+const MODIFIER_NAMES: [(KeyModifiers, &str); 5] = [
+    (KeyModifiers::CONTROL, "ctrl"),
+    (KeyModifiers::ALT, "alt"),
+    (KeyModifiers::SHIFT, "shift"),
+    (KeyModifiers::SUPER, "super"),
+    (KeyModifiers::META, "meta"),
+];
+
+/// A modifier in a key pattern.
+fn parse_modifier(name: &str) -> Option<KeyModifiers> {
+    MODIFIER_NAMES.iter().find(|(_, known)| *known == name).map(|(modifier, _)| *modifier)
+}
+
+/// The control character ctrl+`c` types, if there is one.
+fn control_byte(c: char) -> Option<u8> {
+    match c {
+        'a'..='z' | 'A'..='Z' => Some(c.to_ascii_uppercase() as u8 - b'A' + 1),
+        '@' | ' ' => Some(0),
+        '[' => Some(27),
+        '\\' => Some(28),
+        ']' => Some(29),
+        '^' => Some(30),
+        '_' => Some(31),
+        '?' => Some(127),
+        _ => None,
     }
+}
 
-    fn key_to_bytes(code: KeyCode, modifiers: KeyModifiers) -> Vec<u8> {
-        match (code, modifiers.contains(KeyModifiers::CONTROL)) {
-            (KeyCode::Char(c), true) => {
-                // Control characters
-                if c.is_ascii_alphabetic() {
-                    let control_code = (c.to_ascii_uppercase() as u8) - b'A' + 1;
-                    vec![control_code]
-                } else {
-                    // For non-alphabetic characters with Ctrl, use the character as-is
-                    vec![c as u8]
-                }
-            }
-            (KeyCode::Char(c), false) => {
-                if modifiers.contains(KeyModifiers::ALT) {
-                    vec![27, c as u8] // ESC + char for Alt
-                } else {
-                    vec![c as u8]
+/// Turns the terminal's key events into the presses hooks are bound to. A
+/// second press of a key within `double_press` of the first is a double
+/// press. A key bound to a long press is held back until the terminal
+/// reports it repeating, which makes it a long press, or released, which
+/// makes it a plain one.
+#[derive(Debug)]
+pub struct KeyTiming {
+    double_press: Duration,
+    long_press: Vec<String>,
+    last: Option<(KeyInput, Instant)>,
+    // A press of a long-press key, waiting for its repeat or release
+    down: Option<(KeyInput, Instant)>,
+    // A long press already handled, whose repeats are ignored
+    fired: Option<KeyInput>,
+}
+
+impl KeyTiming {
+    pub fn new(double_press: Duration) -> Self {
+        KeyTiming { double_press, long_press: Vec::new(), last: None, down: None, fired: None }
+    }
+
+    /// Sets the key patterns bound to a long press. Only useful once the
+    /// terminal reports key repeats and releases.
+    pub fn set_long_press(&mut self, patterns: Vec<String>) {
+        self.long_press = patterns;
+    }
+
+    /// Returns the keys `event` completes, oldest first.
+    pub fn key_event(&mut self, event: KeyEvent, now: Instant) -> Vec<KeyInput> {
+        let mut key = KeyInput::from_event(event);
+        let fired = self.fired.as_ref().is_some_and(|fired| fired.same_key(&key));
+        let down = self.down.as_ref().is_some_and(|(down, _)| down.same_key(&key));
+        match event.kind {
+            KeyEventKind::Release => {
+                if fired {
+                    self.fired = None;
                 }
+                return match self.down.take_if(|_| down) {
+                    Some((key, at)) => vec![self.pressed(key, at)],
+                    None => Vec::new(),
+                };
             }
-            (KeyCode::Enter, _) => vec![b'\r'],
-            (KeyCode::Tab, _) => vec![b'\t'],
-            (KeyCode::Backspace, _) => vec![127],
-            (KeyCode::Esc, _) => vec![27],
-            (KeyCode::Up, _) => vec![27, 91, 65],
-            (KeyCode::Down, _) => vec![27, 91, 66],
-            (KeyCode::Right, _) => vec![27, 91, 67],
-            (KeyCode::Left, _) => vec![27, 91, 68],
-            (KeyCode::Home, _) => vec![27, 91, 72],
-            (KeyCode::End, _) => vec![27, 91, 70],
-            (KeyCode::PageUp, _) => vec![27, 91, 53, 126],
-            (KeyCode::PageDown, _) => vec![27, 91, 54, 126],
-            (KeyCode::Delete, _) => vec![27, 91, 51, 126],
-            (KeyCode::Insert, _) => vec![27, 91, 50, 126],
-            (KeyCode::F(n), _) => {
-                match n {
-                    1..=4 => vec![27, 79, 80 + (n - 1) as u8],
-                    5..=12 => vec![27, 91, 49, 53 + (n - 5) as u8, 126],
-                    _ => vec![], // Unsupported F-key
-                }
+            KeyEventKind::Repeat if fired => return Vec::new(),
+            KeyEventKind::Repeat if down => {
+                self.down = None;
+                self.last = None;
+                self.fired = Some(key.clone());
+                key.press = Press::Long;
+                return vec![key];
             }
-            _ => vec![], // Other keys not supported
+            KeyEventKind::Repeat => {}
+            // Its release may have gone unreported while a hook had the keys
+            KeyEventKind::Press => self.fired = None,
+        }
+
+        // Another key means the one held back wasn't held down after all
+        let mut keys = Vec::new();
+        if let Some((pending, at)) = self.down.take() {
+            keys.push(self.pressed(pending, at));
         }
+        if event.kind == KeyEventKind::Press && self.long_press.iter().any(|pattern| key.matches_pattern(pattern)) {
+            self.down = Some((key, now));
+        } else {
+            keys.push(self.pressed(key, now));
+        }
+        keys
+    }
+
+    /// Gives up waiting on a long-press key the terminal has said nothing
+    /// more about, and returns it as a plain press.
+    pub fn expire(&mut self, now: Instant) -> Option<KeyInput> {
+        let (key, at) = self.down.take_if(|(_, at)| now.duration_since(*at) >= LONG_PRESS_LIMIT)?;
+        Some(self.pressed(key, at))
+    }
+
+    fn pressed(&mut self, mut key: KeyInput, at: Instant) -> KeyInput {
+        let double = self.last.as_ref()
+            .is_some_and(|(last, last_at)| last.same_key(&key) && at.duration_since(*last_at) <= self.double_press);
+        if double {
+            // A third press starts over
+            key.press = Press::Double;
+            self.last = None;
+        } else {
+            self.last = Some((key.clone(), at));
+        }
+        key
     }
 }
 
@@ -134,9 +430,40 @@ impl Terminal {
         Ok(Terminal {
             stdout: stdout(),
             raw_mode_enabled: false,
+            keyboard: KeyboardEnhancementFlags::empty(),
         })
     }
 
+    /// Negotiates the kitty keyboard protocol: asks the terminal to report
+    /// keys as `flags` say, e.g. ctrl+shift+enter apart from enter, or key
+    /// repeats and releases, which long presses are told by. Returns false
+    /// if the terminal doesn't speak the protocol.
+    pub fn enhance_keyboard(&mut self, flags: KeyboardEnhancementFlags) -> Result<bool> {
+        if flags.is_empty() || !supports_keyboard_enhancement().unwrap_or(false) {
+            return Ok(false);
+        }
+        execute!(self.stdout, PushKeyboardEnhancementFlags(flags))
+            .with_context(|| "Failed to enable the kitty keyboard protocol")?;
+        self.keyboard = flags;
+        hatch::set_keyboard_enhanced(true);
+        Ok(true)
+    }
+
+    /// Goes back to legacy key encoding for a while, e.g. while a popup
+    /// reads keys, or for good when the session ends.
+    pub fn pause_key_events(&mut self, paused: bool) -> Result<()> {
+        if self.keyboard.is_empty() {
+            return Ok(());
+        }
+        hatch::set_keyboard_enhanced(!paused);
+        if paused {
+            execute!(self.stdout, PopKeyboardEnhancementFlags)
+        } else {
+            execute!(self.stdout, PushKeyboardEnhancementFlags(self.keyboard))
+        }
+        .with_context(|| "Failed to change the keyboard protocol")
+    }
+
     pub fn enter_raw_mode(&mut self) -> Result<()> {
         if !self.raw_mode_enabled {
             enable_raw_mode()
@@ -194,16 +521,109 @@ impl Terminal {
     }
 
     pub fn read_event(&self) -> Result<Event> {
-        crossterm::event::read()
-            .with_context(|| "Failed to read terminal event")
+        read_event()
     }
 
     pub fn poll_event(&self, timeout: std::time::Duration) -> Result<bool> {
-        crossterm::event::poll(timeout)
-            .with_context(|| "Failed to poll for terminal events")
+        poll_event(timeout)
     }
 }
 
+/// Terminal events, read on a thread of their own so the escape hatch is
+/// seen even while the main loop is stuck in a hook.
+struct Events {
+    receiver: Receiver<io::Result<Event>>,
+    /// An event `poll_event` found, for the next `read_event`.
+    pending: Option<Event>,
+}
+
+static EVENTS: OnceLock<Mutex<Events>> = OnceLock::new();
+
+/// The events, starting the reader on first use. It polls rather than
+/// blocking in a read, so that asking the terminal something, as
+/// `supports_keyboard_enhancement` does, gets a turn in between.
+fn events() -> std::sync::MutexGuard<'static, Events> {
+    let events = EVENTS.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel();
+        std::thread::spawn(move || loop {
+            let event = match crossterm::event::poll(Duration::from_millis(100)) {
+                Ok(false) => continue,
+                Ok(true) => crossterm::event::read(),
+                Err(e) => Err(e),
+            };
+            let failed = event.is_err();
+            if let Ok(event) = &event {
+                hatch::check(event);
+            }
+            if sender.send(event).is_err() || failed {
+                break;
+            }
+        });
+        Mutex::new(Events { receiver, pending: None })
+    });
+    events.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Waits for the next terminal event. The escape hatch key never comes
+/// back from it: the reader ends chatshell first.
+pub fn read_event() -> Result<Event> {
+    let mut events = events();
+    if let Some(event) = events.pending.take() {
+        return Ok(event);
+    }
+    let event = events.receiver.recv().map_err(|_| anyhow::anyhow!("Terminal input closed"))?;
+    event.with_context(|| "Failed to read terminal event")
+}
+
+/// True if an event arrives within `timeout`, for `read_event` to return.
+pub fn poll_event(timeout: Duration) -> Result<bool> {
+    let mut events = events();
+    if events.pending.is_some() {
+        return Ok(true);
+    }
+    match events.receiver.recv_timeout(timeout) {
+        Ok(event) => {
+            events.pending = Some(event.with_context(|| "Failed to poll for terminal events")?);
+            Ok(true)
+        }
+        Err(RecvTimeoutError::Timeout) => Ok(false),
+        Err(RecvTimeoutError::Disconnected) => anyhow::bail!("Terminal input closed"),
+    }
+}
+
+/// The `OSC 52` sequence that asks the terminal to put `text` on the
+/// clipboard. Terminals that don't support it ignore it.
+pub fn clipboard_sequence(text: &str) -> String {
+    format!("\x1b]52;c;{}\x07", encode_base64(text.as_bytes()))
+}
+
+/// The `OSC 777` sequence that asks the terminal for a desktop
+/// notification. Terminals that don't support it ignore it.
+pub fn notification_sequence(title: &str, body: &str) -> String {
+    // Control characters would end the sequence; a `;` in the title would
+    // start the body
+    let clean = |text: &str| -> String {
+        text.split_whitespace().collect::<Vec<_>>().join(" ").chars().filter(|c| !c.is_control()).collect()
+    };
+    format!("\x1b]777;notify;{};{}\x07", clean(title).replace(';', ","), clean(body))
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
 impl Drop for Terminal {
     fn drop(&mut self) {
         let _ = self.leave_raw_mode();
@@ -223,6 +643,98 @@ mod tests {
         assert!(!key.matches_pattern("ctrl+a"));
     }
 
+    #[test]
+    fn test_parse_key_pattern() {
+        let key = KeyInput::parse("Ctrl+Shift+L").unwrap();
+        assert_eq!(key.modifiers, KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+        assert!(key.matches_pattern("ctrl+shift+l"));
+        assert!(KeyInput::parse("alt+enter").unwrap().matches_pattern("alt+enter"));
+        assert_eq!(KeyInput::parse("ctrl+;").unwrap().code, KeyCode::Char(';'));
+        assert!(KeyInput::parse("hyper+x").is_none());
+        assert!(KeyInput::parse("ctrl+pgup").is_none());
+        assert!(KeyInput::parse("f13").is_none());
+        assert_eq!(KeyInput::parse("ctrl+c ctrl+c").unwrap().press, Press::Double);
+        assert_eq!(KeyInput::parse("hold ctrl+k").unwrap().press, Press::Long);
+    }
+
+    #[test]
+    fn test_named_and_bare_key_patterns() {
+        let key = |code, modifiers| KeyInput::new(code, modifiers);
+        let none = KeyModifiers::empty();
+        let keys = [
+            ("enter", key(KeyCode::Enter, none)),
+            ("tab", key(KeyCode::Tab, none)),
+            ("shift+tab", key(KeyCode::BackTab, KeyModifiers::SHIFT)),
+            ("backspace", key(KeyCode::Backspace, none)),
+            ("esc", key(KeyCode::Esc, none)),
+            ("up", key(KeyCode::Up, none)),
+            ("down", key(KeyCode::Down, none)),
+            ("ctrl+left", key(KeyCode::Left, KeyModifiers::CONTROL)),
+            ("alt+right", key(KeyCode::Right, KeyModifiers::ALT)),
+            ("home", key(KeyCode::Home, none)),
+            ("shift+end", key(KeyCode::End, KeyModifiers::SHIFT)),
+            ("ctrl+pageup", key(KeyCode::PageUp, KeyModifiers::CONTROL)),
+            ("pagedown", key(KeyCode::PageDown, none)),
+            ("delete", key(KeyCode::Delete, none)),
+            ("insert", key(KeyCode::Insert, none)),
+            ("f1", key(KeyCode::F(1), none)),
+            ("shift+f5", key(KeyCode::F(5), KeyModifiers::SHIFT)),
+            ("ctrl+f12", key(KeyCode::F(12), KeyModifiers::CONTROL)),
+            ("ctrl++", key(KeyCode::Char('+'), KeyModifiers::CONTROL)),
+            ("+", key(KeyCode::Char('+'), none)),
+            ("x", key(KeyCode::Char('x'), none)),
+        ];
+        for (pattern, key) in keys {
+            assert!(key.matches_pattern(pattern), "{} should match {:?}", pattern, key.code);
+            assert_eq!(KeyInput::parse(pattern).as_ref(), Some(&key), "{}", pattern);
+            assert_eq!(key.pattern().as_deref(), Some(pattern));
+        }
+        assert_eq!(key(KeyCode::Char('X'), KeyModifiers::SUPER | KeyModifiers::CONTROL).pattern().as_deref(), Some("ctrl+super+x"));
+        assert_eq!(key(KeyCode::Null, none).pattern(), None);
+
+        // A modifier in the pattern must be held, and a held one named
+        assert!(!key(KeyCode::F(5), none).matches_pattern("shift+f5"));
+        assert!(!key(KeyCode::Up, KeyModifiers::CONTROL).matches_pattern("up"));
+        assert!(!key(KeyCode::Left, none).matches_pattern("right"));
+        assert!(!key(KeyCode::Char('x'), none).matches_pattern("nope"));
+    }
+
+    #[test]
+    fn test_double_and_long_press() {
+        let start = Instant::now();
+        let event = |kind| KeyEvent::new_with_kind(KeyCode::Char('c'), KeyModifiers::CONTROL, kind);
+        let mut timing = KeyTiming::new(Duration::from_millis(400));
+
+        // The first press goes through as it is; a quick second one is a double
+        let first = timing.key_event(event(KeyEventKind::Press), start);
+        assert!(first[0].matches_trigger("ctrl+c"));
+        assert!(!first[0].matches_trigger("ctrl+c ctrl+c"));
+        let second = timing.key_event(event(KeyEventKind::Press), start + Duration::from_millis(300));
+        assert!(second[0].matches_trigger("ctrl+c ctrl+c"));
+        assert!(second[0].matches_trigger("ctrl+c"));
+        let third = timing.key_event(event(KeyEventKind::Press), start + Duration::from_millis(600));
+        assert_eq!(third[0].press, Press::Single);
+        let late = timing.key_event(event(KeyEventKind::Press), start + Duration::from_secs(2));
+        assert_eq!(late[0].press, Press::Single);
+
+        // A long-press key waits for its repeat or release
+        timing.set_long_press(vec!["ctrl+c".to_string()]);
+        assert!(timing.key_event(event(KeyEventKind::Press), start).is_empty());
+        let held = timing.key_event(event(KeyEventKind::Repeat), start);
+        assert!(held[0].matches_trigger("hold ctrl+c"));
+        assert!(!held[0].matches_trigger("ctrl+c"));
+        assert!(timing.key_event(event(KeyEventKind::Repeat), start).is_empty());
+        assert!(timing.key_event(event(KeyEventKind::Release), start).is_empty());
+
+        assert!(timing.key_event(event(KeyEventKind::Press), start).is_empty());
+        let tapped = timing.key_event(event(KeyEventKind::Release), start);
+        assert_eq!(tapped[0].press, Press::Single);
+
+        assert!(timing.key_event(event(KeyEventKind::Press), start).is_empty());
+        assert!(timing.expire(start + Duration::from_secs(1)).is_none());
+        assert!(timing.expire(start + LONG_PRESS_LIMIT).is_some());
+    }
+
     #[test]
     fn test_key_to_bytes() {
         let key = KeyInput::new(KeyCode::Char('a'), KeyModifiers::CONTROL);
@@ -232,6 +744,42 @@ mod tests {
         assert_eq!(key.raw_bytes, vec![13]); // Enter = \r
     }
 
+    #[test]
+    fn test_enhanced_keys_translate_to_legacy_bytes() {
+        let bytes = |code, modifiers| KeyInput::new(code, modifiers).raw_bytes;
+        assert_eq!(bytes(KeyCode::Char('é'), KeyModifiers::empty()), "é".as_bytes());
+        assert_eq!(bytes(KeyCode::Char('a'), KeyModifiers::CONTROL | KeyModifiers::ALT), vec![27, 1]);
+        assert_eq!(bytes(KeyCode::Char('['), KeyModifiers::CONTROL), vec![27]);
+        assert_eq!(bytes(KeyCode::Backspace, KeyModifiers::ALT), vec![27, 127]);
+        assert_eq!(bytes(KeyCode::Up, KeyModifiers::CONTROL), b"\x1b[1;5A");
+        assert_eq!(bytes(KeyCode::F(2), KeyModifiers::SHIFT), b"\x1b[1;2Q");
+        assert_eq!(bytes(KeyCode::F(12), KeyModifiers::empty()), b"\x1b[24~");
+        assert_eq!(bytes(KeyCode::PageUp, KeyModifiers::ALT), b"\x1b[5;3~");
+        // No legacy encoding tells these apart from the plain key
+        assert_eq!(bytes(KeyCode::Enter, KeyModifiers::CONTROL | KeyModifiers::SHIFT), vec![13]);
+        assert_eq!(bytes(KeyCode::Char('k'), KeyModifiers::SUPER), b"k");
+
+        let key = KeyInput::new(KeyCode::Char('k'), KeyModifiers::SUPER | KeyModifiers::SHIFT);
+        assert!(key.matches_pattern("super+shift+k"));
+        assert!(!key.matches_pattern("shift+k"));
+        assert_eq!(KeyInput::parse("ctrl+shift+enter").unwrap().modifiers, KeyModifiers::CONTROL | KeyModifiers::SHIFT);
+    }
+
+    #[test]
+    fn test_clipboard_sequence() {
+        assert_eq!(clipboard_sequence("hi"), "\x1b]52;c;aGk=\x07");
+        assert_eq!(encode_base64(b"chatshell"), "Y2hhdHNoZWxs");
+        assert_eq!(encode_base64(b"log"), "bG9n");
+    }
+
+    #[test]
+    fn test_notification_sequence() {
+        assert_eq!(
+            notification_sequence("Command: a; b", "line one\nline\ttwo\x07"),
+            "\x1b]777;notify;Command: a, b;line one line two\x07"
+        );
+    }
+
     #[test]
     fn test_alt_key_combination() {
         let key = KeyInput::new(KeyCode::Char('a'), KeyModifiers::ALT);
@@ -0,0 +1,137 @@
+use std::ops::Range;
+use unicode_width::{UnicodeWidthChar, UnicodeWidthStr};
+
+/// How many columns `text` takes on screen: CJK characters and most emoji
+/// take two, combining marks none.
+pub fn width(text: &str) -> usize {
+    text.width()
+}
+
+/// The start of `text` that fits in `max` columns.
+pub fn truncate(text: &str, max: usize) -> String {
+    let mut used = 0;
+    text.chars()
+        .take_while(|c| {
+            used += c.width().unwrap_or(0);
+            used <= max
+        })
+        .collect()
+}
+
+/// Breaks `text` into lines of at most `max` columns, between words where
+/// it can. Words wider than a line are broken where they run out of room.
+/// An empty `text` is one empty line.
+pub fn wrap(text: &str, max: usize) -> Vec<String> {
+    wrap_ranges(text, max).into_iter().map(|row| text[row].to_string()).collect()
+}
+
+/// The byte ranges of `text` in the lines `wrap` breaks it into, without
+/// the spaces they end in.
+pub fn wrap_ranges(text: &str, max: usize) -> Vec<Range<usize>> {
+    let max = max.max(1);
+    if width(text) <= max {
+        return std::iter::once(0..text.len()).collect();
+    }
+    let trimmed = |row: Range<usize>| row.start..row.start + text[row].trim_end_matches(' ').len();
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut at = 0;
+    let mut used = 0;
+    for word in text.split_inclusive(' ') {
+        let word_width = width(word);
+        // A space at the end of a line can hang over it
+        let fits = width(word.trim_end_matches(' '));
+        if used > 0 && used + fits > max {
+            rows.push(trimmed(start..at));
+            start = at;
+            used = 0;
+        }
+        if fits <= max - used {
+            at += word.len();
+            used += word_width;
+            continue;
+        }
+        for (offset, c) in word.char_indices() {
+            let c_width = c.width().unwrap_or(0);
+            if used + c_width > max && used > 0 {
+                rows.push(start..at + offset);
+                start = at + offset;
+                used = 0;
+            }
+            used += c_width;
+        }
+        at += word.len();
+    }
+    let last = trimmed(start..text.len());
+    if !last.is_empty() || rows.is_empty() {
+        rows.push(last);
+    }
+    rows
+}
+
+/// The byte ranges of `text` in lines of at most `max` columns, broken
+/// wherever they run out of room. An empty `text` is one empty line.
+pub fn split_at_width(text: &str, max: usize) -> Vec<Range<usize>> {
+    let max = max.max(1);
+    let mut rows = Vec::new();
+    let mut start = 0;
+    let mut used = 0;
+    for (at, c) in text.char_indices() {
+        let c_width = c.width().unwrap_or(0);
+        if used + c_width > max && used > 0 {
+            rows.push(start..at);
+            start = at;
+            used = 0;
+        }
+        used += c_width;
+    }
+    rows.push(start..text.len());
+    rows
+}
+
+/// How much of `bytes`, read from a stream, can be decoded now: all of it
+/// but a character cut off at the end, which the next read completes.
+pub fn whole_chars(bytes: &[u8]) -> usize {
+    match std::str::from_utf8(bytes) {
+        Ok(_) => bytes.len(),
+        Err(e) if e.error_len().is_none() => e.valid_up_to(),
+        // Not UTF-8 at all; decoded lossily
+        Err(_) => bytes.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrap_wide_characters() {
+        assert_eq!(width("日本語"), 6);
+        assert_eq!(width("ok 👍"), 5);
+        assert_eq!(width("e\u{301}"), 1);
+        assert_eq!(truncate("日本語", 5), "日本");
+        assert_eq!(truncate("abc", 10), "abc");
+
+        assert_eq!(wrap("", 10), vec![""]);
+        assert_eq!(wrap("build failed in 3s", 10), vec!["build", "failed in", "3s"]);
+        assert_eq!(wrap_ranges("build failed in 3s", 10), vec![0..5, 6..15, 16..18]);
+        assert_eq!(wrap("日本語のテキスト", 6), vec!["日本語", "のテキ", "スト"]);
+        // A wide character is never split across lines
+        assert_eq!(wrap("a日本", 4), vec!["a日", "本"]);
+        assert_eq!(wrap("see /var/log/chatshell/debug.log", 12), vec!["see", "/var/log/cha", "tshell/debug", ".log"]);
+        assert_eq!(wrap("🎉🎉🎉 done", 6), vec!["🎉🎉🎉", "done"]);
+        assert_eq!(split_at_width("let x = \"日本\";", 10), vec![0..9, 9..17]);
+        assert_eq!(split_at_width("", 10), vec![0..0]);
+        for line in wrap("名前: テスト 結果: 成功 時間: 12秒", 9) {
+            assert!(width(&line) <= 9, "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn test_whole_chars() {
+        let text = "ok 日本".as_bytes();
+        assert_eq!(whole_chars(text), text.len());
+        assert_eq!(whole_chars(&text[..text.len() - 1]), 6);
+        assert_eq!(whole_chars(b"\xff\xfe"), 2);
+    }
+}
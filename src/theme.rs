@@ -0,0 +1,244 @@
+use anyhow::Result;
+use crossterm::style::Color;
+
+use crate::config::UiConfig;
+
+/// A foreground color and the background it is drawn on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ColorPair {
+    pub fg: Color,
+    pub bg: Color,
+}
+
+/// The colors popups and panels are drawn in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Theme {
+    /// Frames and titles.
+    pub border: ColorPair,
+    /// What popups show.
+    pub text: ColorPair,
+    /// The selected row of a list.
+    pub selected: ColorPair,
+    /// Matches of a search.
+    pub highlight: ColorPair,
+    /// Key hints in the bottom border, on the border's background.
+    pub hint: Color,
+    /// The least contrast a line's own color must have against the text
+    /// background; 0 takes any.
+    pub min_contrast: f64,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            border: ColorPair { fg: Color::White, bg: Color::Blue },
+            text: ColorPair { fg: Color::White, bg: Color::DarkBlue },
+            selected: ColorPair { fg: Color::Black, bg: Color::Cyan },
+            highlight: ColorPair { fg: Color::Black, bg: Color::Yellow },
+            hint: Color::Yellow,
+            min_contrast: 0.0,
+        }
+    }
+}
+
+impl Theme {
+    /// The theme of `[ui.theme]`, with foregrounds that don't stand out
+    /// enough from their background by `[ui.accessibility] min_contrast`
+    /// turned black or white. Returns what was changed, to warn about.
+    pub fn from_config(config: &UiConfig) -> Result<(Theme, Vec<String>)> {
+        let mut theme = Theme::default();
+        let colors = &config.theme;
+        let set = |color: &mut Color, text: &Option<String>| -> Result<()> {
+            if let Some(text) = text {
+                *color = parse_color(text)?;
+            }
+            Ok(())
+        };
+        set(&mut theme.border.fg, &colors.border_fg)?;
+        set(&mut theme.border.bg, &colors.border_bg)?;
+        set(&mut theme.text.fg, &colors.text_fg)?;
+        set(&mut theme.text.bg, &colors.text_bg)?;
+        set(&mut theme.selected.fg, &colors.selected_fg)?;
+        set(&mut theme.selected.bg, &colors.selected_bg)?;
+        set(&mut theme.highlight.fg, &colors.highlight_fg)?;
+        set(&mut theme.highlight.bg, &colors.highlight_bg)?;
+        set(&mut theme.hint, &colors.hint)?;
+        theme.min_contrast = config.accessibility.min_contrast;
+
+        let mut changed = Vec::new();
+        let min = theme.min_contrast;
+        let border_bg = theme.border.bg;
+        let pairs = [
+            ("border", &mut theme.border.fg, theme.border.bg),
+            ("text", &mut theme.text.fg, theme.text.bg),
+            ("selected", &mut theme.selected.fg, theme.selected.bg),
+            ("highlight", &mut theme.highlight.fg, theme.highlight.bg),
+            ("hint", &mut theme.hint, border_bg),
+        ];
+        for (name, fg, bg) in pairs {
+            let ratio = contrast(*fg, bg);
+            if ratio < min {
+                *fg = readable_on(bg);
+                changed.push(format!(
+                    "{}: contrast {:.1} is below {:.1}, using {} text",
+                    name,
+                    ratio,
+                    min,
+                    if *fg == Color::Black { "black" } else { "white" }
+                ));
+            }
+        }
+        Ok((theme, changed))
+    }
+
+    /// `color` for a line of text, or the text color if it doesn't stand
+    /// out enough from the background.
+    pub fn line_color(&self, color: Color) -> Color {
+        if contrast(color, self.text.bg) < self.min_contrast {
+            self.text.fg
+        } else {
+            color
+        }
+    }
+}
+
+/// Reads a color as a name like `dark_blue`, `#rrggbb` or a number of the
+/// 256-color palette.
+pub fn parse_color(text: &str) -> Result<Color> {
+    let text = text.trim();
+    if let Some(hex) = text.strip_prefix('#') {
+        let channel = |at: usize| hex.get(at..at + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+        if let (6, Some(r), Some(g), Some(b)) = (hex.len(), channel(0), channel(2), channel(4)) {
+            return Ok(Color::Rgb { r, g, b });
+        }
+    } else if let Ok(value) = text.parse::<u8>() {
+        return Ok(Color::AnsiValue(value));
+    } else if let Ok(color) = Color::try_from(text.to_lowercase().as_str()) {
+        return Ok(color);
+    }
+    anyhow::bail!("Not a color: {} (use a name like dark_blue, #rrggbb or 0-255)", text)
+}
+
+/// What `color` looks like, going by xterm's default palette. Terminals
+/// differ in the first 16 colors, so contrast is an estimate for those.
+pub fn rgb(color: Color) -> (u8, u8, u8) {
+    const BASIC: [(u8, u8, u8); 16] = [
+        (0, 0, 0),
+        (205, 0, 0),
+        (0, 205, 0),
+        (205, 205, 0),
+        (0, 0, 238),
+        (205, 0, 205),
+        (0, 205, 205),
+        (229, 229, 229),
+        (127, 127, 127),
+        (255, 0, 0),
+        (0, 255, 0),
+        (255, 255, 0),
+        (92, 92, 255),
+        (255, 0, 255),
+        (0, 255, 255),
+        (255, 255, 255),
+    ];
+    let index = match color {
+        Color::Rgb { r, g, b } => return (r, g, b),
+        Color::Reset | Color::Black => 0,
+        Color::DarkRed => 1,
+        Color::DarkGreen => 2,
+        Color::DarkYellow => 3,
+        Color::DarkBlue => 4,
+        Color::DarkMagenta => 5,
+        Color::DarkCyan => 6,
+        Color::Grey => 7,
+        Color::DarkGrey => 8,
+        Color::Red => 9,
+        Color::Green => 10,
+        Color::Yellow => 11,
+        Color::Blue => 12,
+        Color::Magenta => 13,
+        Color::Cyan => 14,
+        Color::White => 15,
+        Color::AnsiValue(value) => value,
+    };
+    match index {
+        0..=15 => BASIC[index as usize],
+        16..=231 => {
+            let level = |n: u8| if n == 0 { 0 } else { 55 + n * 40 };
+            let n = index - 16;
+            (level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let grey = 8 + (index - 232) * 10;
+            (grey, grey, grey)
+        }
+    }
+}
+
+/// The WCAG contrast ratio of two colors, from 1 (the same) to 21 (black
+/// and white).
+pub fn contrast(a: Color, b: Color) -> f64 {
+    let luminance = |color: Color| {
+        let (r, g, b) = rgb(color);
+        let linear = |c: u8| {
+            let c = c as f64 / 255.0;
+            if c <= 0.03928 { c / 12.92 } else { ((c + 0.055) / 1.055).powf(2.4) }
+        };
+        0.2126 * linear(r) + 0.7152 * linear(g) + 0.0722 * linear(b)
+    };
+    let (a, b) = (luminance(a), luminance(b));
+    (a.max(b) + 0.05) / (a.min(b) + 0.05)
+}
+
+/// Black or white, whichever stands out more on `bg`.
+fn readable_on(bg: Color) -> Color {
+    if contrast(Color::Black, bg) >= contrast(Color::White, bg) {
+        Color::Black
+    } else {
+        Color::White
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ThemeConfig;
+
+    #[test]
+    fn test_contrast() {
+        assert!((contrast(Color::Black, Color::White) - 21.0).abs() < 0.01);
+        assert!((contrast(Color::Red, Color::Red) - 1.0).abs() < 0.01);
+        assert_eq!(parse_color("#1e1e2e").unwrap(), Color::Rgb { r: 0x1e, g: 0x1e, b: 0x2e });
+        assert_eq!(parse_color("Dark_Blue").unwrap(), Color::DarkBlue);
+        assert_eq!(parse_color("236").unwrap(), Color::AnsiValue(236));
+        assert_eq!(rgb(Color::AnsiValue(236)), (48, 48, 48));
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("blurple").is_err());
+    }
+
+    #[test]
+    fn test_theme_enforces_contrast() {
+        let mut config = UiConfig {
+            theme: ThemeConfig {
+                text_fg: Some("dark_grey".to_string()),
+                text_bg: Some("#303030".to_string()),
+                ..ThemeConfig::default()
+            },
+            ..UiConfig::default()
+        };
+        let (theme, changed) = Theme::from_config(&config).unwrap();
+        assert_eq!(theme.text.fg, Color::DarkGrey);
+        assert!(changed.is_empty());
+        assert_eq!(theme.line_color(Color::DarkRed), Color::DarkRed);
+
+        config.accessibility.min_contrast = 4.0;
+        let (theme, changed) = Theme::from_config(&config).unwrap();
+        assert_eq!(theme.text.fg, Color::White);
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0], "text: contrast 3.3 is below 4.0, using white text");
+        assert_eq!(theme.line_color(Color::DarkRed), Color::White);
+        assert_eq!(theme.line_color(Color::Yellow), Color::Yellow);
+
+        config.theme.hint = Some("nope".to_string());
+        assert!(Theme::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,535 @@
+use anyhow::{Context, Result};
+use chrono::{Local, TimeZone};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::state::valid_name;
+
+/// The formats `chatshell export` turns a transcript into.
+pub const FORMATS: [&str; 3] = ["html", "markdown", "text"];
+
+/// How big a transcript grows before it is moved aside, to the file
+/// `rotated_path` names, and started afresh.
+const MAX_TRANSCRIPT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// How often a session writes out what it added to its transcript.
+pub const FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// What a session's transcript is made of, one JSON line each.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Entry {
+    /// A session started. Unix time, in seconds.
+    Started { at: i64 },
+    /// Lines the shell printed, with their color sequences.
+    Output { lines: Vec<String> },
+    /// A question put to the assistant and its answer.
+    Exchange { question: String, answer: String },
+    /// A comment made in copy mode on lines of the output, with the lines.
+    Annotation { at: i64, lines: Vec<String>, comment: String },
+}
+
+/// Where the transcripts of sessions go: `transcripts` next to the config.
+pub fn transcripts_dir(config_path: &Path) -> PathBuf {
+    config_path.parent().unwrap_or(Path::new(".")).join("transcripts")
+}
+
+/// The file with the transcript of the session called `session`.
+pub fn transcript_path(dir: &Path, session: &str) -> Result<PathBuf> {
+    if !valid_name(session) {
+        anyhow::bail!("Not a session name: {} (use letters, digits, '-', '_' and '.')", session);
+    }
+    Ok(dir.join(format!("{}.jsonl", session)))
+}
+
+/// Where the transcript in `path` goes once it is too big: next to it,
+/// e.g. `work.1.jsonl` for `work.jsonl`, in place of the one before.
+pub fn rotated_path(path: &Path) -> PathBuf {
+    path.with_extension("1.jsonl")
+}
+
+/// A session's transcript, open while the session runs. Entries are
+/// buffered until `flush`, which the session calls now and then and at
+/// its end.
+#[derive(Debug)]
+pub struct TranscriptFile {
+    path: PathBuf,
+    file: BufWriter<File>,
+    size: u64,
+    limit: u64,
+}
+
+impl TranscriptFile {
+    /// Opens the transcript in `path`, which only the user can read, for a
+    /// session starting now.
+    pub fn open(path: &Path) -> Result<Self> {
+        Self::open_with_limit(path, MAX_TRANSCRIPT_BYTES)
+    }
+
+    fn open_with_limit(path: &Path, limit: u64) -> Result<Self> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("Failed to create transcripts directory: {}", dir.display()))?;
+        }
+        let file = open_file(path)?;
+        let size = file.metadata().map(|metadata| metadata.len()).unwrap_or(0);
+        let mut transcript = TranscriptFile { path: path.to_path_buf(), file: BufWriter::new(file), size, limit };
+        transcript.append(&Entry::Started { at: Local::now().timestamp() })?;
+        Ok(transcript)
+    }
+
+    pub fn append(&mut self, entry: &Entry) -> Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        if self.size > 0 && self.size + line.len() as u64 > self.limit {
+            self.rotate()?;
+        }
+        self.file.write_all(line.as_bytes()).with_context(|| format!("Failed to write transcript: {}", self.path.display()))?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.file.flush().with_context(|| format!("Failed to write transcript: {}", self.path.display()))
+    }
+
+    fn rotate(&mut self) -> Result<()> {
+        self.flush()?;
+        let rotated = rotated_path(&self.path);
+        fs::rename(&self.path, &rotated).with_context(|| format!("Failed to move transcript to {}", rotated.display()))?;
+        self.file = BufWriter::new(open_file(&self.path)?);
+        self.size = 0;
+        Ok(())
+    }
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    OpenOptions::new()
+        .append(true)
+        .create(true)
+        .mode(0o600)
+        .open(path)
+        .with_context(|| format!("Failed to open transcript: {}", path.display()))
+}
+
+/// The transcript in `path`, oldest first, with the part moved aside
+/// before it if there is one.
+pub fn load(path: &Path) -> Result<Vec<Entry>> {
+    let rotated = rotated_path(path);
+    let mut entries = match rotated.exists() {
+        true => read_entries(&rotated)?,
+        false => Vec::new(),
+    };
+    entries.extend(read_entries(path)?);
+    Ok(entries)
+}
+
+fn read_entries(path: &Path) -> Result<Vec<Entry>> {
+    let text = fs::read_to_string(path).with_context(|| format!("Failed to read transcript: {}", path.display()))?;
+    text.lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).with_context(|| format!("Invalid transcript line in {}: {}", path.display(), line)))
+        .collect()
+}
+
+/// Turns a transcript into something to share.
+pub trait Exporter {
+    fn export(&self, entries: &[Entry]) -> String;
+}
+
+/// The exporter for `format`, one of `FORMATS`.
+pub fn exporter(format: &str) -> Option<Box<dyn Exporter>> {
+    match format {
+        "html" => Some(Box::new(HtmlExporter)),
+        "markdown" | "md" => Some(Box::new(MarkdownExporter)),
+        "text" => Some(Box::new(TextExporter)),
+        _ => None,
+    }
+}
+
+// A transcript with the output of one stretch of the session together
+enum Block<'a> {
+    Started(i64),
+    Output(Vec<&'a str>),
+    Exchange { question: &'a str, answer: &'a str },
+    Annotation { lines: &'a [String], comment: &'a str },
+}
+
+fn blocks(entries: &[Entry]) -> Vec<Block<'_>> {
+    let mut blocks = Vec::new();
+    for entry in entries {
+        match entry {
+            Entry::Started { at } => blocks.push(Block::Started(*at)),
+            Entry::Output { lines } => {
+                if let Some(Block::Output(output)) = blocks.last_mut() {
+                    output.extend(lines.iter().map(String::as_str));
+                } else {
+                    blocks.push(Block::Output(lines.iter().map(String::as_str).collect()));
+                }
+            }
+            Entry::Exchange { question, answer } => blocks.push(Block::Exchange { question, answer }),
+            Entry::Annotation { lines, comment, .. } => blocks.push(Block::Annotation { lines, comment }),
+        }
+    }
+    blocks
+}
+
+fn started(at: i64) -> String {
+    match Local.timestamp_opt(at, 0).single() {
+        Some(time) => format!("Session started {}", time.format("%Y-%m-%d %H:%M")),
+        None => "Session started".to_string(),
+    }
+}
+
+/// Plain text, without colors.
+pub struct TextExporter;
+
+impl Exporter for TextExporter {
+    fn export(&self, entries: &[Entry]) -> String {
+        let mut text = String::new();
+        for block in blocks(entries) {
+            match block {
+                Block::Started(at) => text.push_str(&format!("--- {} ---\n\n", started(at))),
+                Block::Output(lines) => {
+                    for line in lines {
+                        text.push_str(&strip_sgr(line));
+                        text.push('\n');
+                    }
+                    text.push('\n');
+                }
+                Block::Exchange { question, answer } => {
+                    text.push_str(&format!("Q: {}\n\n{}\n\n", question, answer.trim_end()));
+                }
+                Block::Annotation { lines, comment } => {
+                    text.push_str(&format!("Note: {}\n", comment.trim_end()));
+                    for line in lines {
+                        text.push_str(&format!("  > {}\n", strip_sgr(line)));
+                    }
+                    text.push('\n');
+                }
+            }
+        }
+        text
+    }
+}
+
+/// Markdown, with the output in code blocks and the assistant's answers
+/// as it wrote them.
+pub struct MarkdownExporter;
+
+impl Exporter for MarkdownExporter {
+    fn export(&self, entries: &[Entry]) -> String {
+        let mut text = String::new();
+        for block in blocks(entries) {
+            match block {
+                Block::Started(at) => text.push_str(&format!("## {}\n\n", started(at))),
+                Block::Output(lines) => text.push_str(&code_block(lines.iter().copied())),
+                Block::Exchange { question, answer } => {
+                    text.push_str(&format!("> **Question:** {}\n\n{}\n\n", question, answer.trim_end()));
+                }
+                Block::Annotation { lines, comment } => {
+                    text.push_str(&format!("**Note:** {}\n\n", comment.trim_end()));
+                    text.push_str(&code_block(lines.iter().map(String::as_str)));
+                }
+            }
+        }
+        text
+    }
+}
+
+/// `lines` without their colors in a fenced code block.
+fn code_block<'a>(lines: impl Iterator<Item = &'a str>) -> String {
+    let lines: Vec<String> = lines.map(strip_sgr).collect();
+    // Longer than any run of backticks in the output
+    let longest = lines.iter().map(|line| longest_run(line, '`')).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    format!("{}console\n{}\n{}\n\n", fence, lines.join("\n"), fence)
+}
+
+fn longest_run(text: &str, c: char) -> usize {
+    text.split(|other| other != c).map(str::len).max().unwrap_or(0)
+}
+
+/// A page of HTML that keeps the output's colors, with the styles inline
+/// so that it survives being pasted into a wiki.
+pub struct HtmlExporter;
+
+const PRE_STYLE: &str = "background:#1e1e1e;color:#d4d4d4;padding:0.8em;overflow-x:auto;\
+                         font-family:monospace;line-height:1.3";
+
+impl Exporter for HtmlExporter {
+    fn export(&self, entries: &[Entry]) -> String {
+        let mut html = String::from(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>chatshell session</title>\n</head>\n<body>\n",
+        );
+        for block in blocks(entries) {
+            match block {
+                Block::Started(at) => html.push_str(&format!("<h2>{}</h2>\n", escape_html(&started(at)))),
+                Block::Output(lines) => {
+                    let lines: Vec<String> = lines.iter().map(|line| ansi_to_html(line)).collect();
+                    html.push_str(&format!("<pre style=\"{}\">{}</pre>\n", PRE_STYLE, lines.join("\n")));
+                }
+                Block::Exchange { question, answer } => {
+                    html.push_str(&format!(
+                        "<p><strong>Question:</strong> {}</p>\n<pre style=\"white-space:pre-wrap\">{}</pre>\n",
+                        escape_html(question),
+                        escape_html(answer.trim_end())
+                    ));
+                }
+                Block::Annotation { lines, comment } => {
+                    let lines: Vec<String> = lines.iter().map(|line| ansi_to_html(line)).collect();
+                    html.push_str(&format!(
+                        "<blockquote>\n<p><strong>Note:</strong> {}</p>\n<pre style=\"{}\">{}</pre>\n</blockquote>\n",
+                        escape_html(comment.trim_end()),
+                        PRE_STYLE,
+                        lines.join("\n")
+                    ));
+                }
+            }
+        }
+        html.push_str("</body>\n</html>\n");
+        html
+    }
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Splits `line` into its text and the parameters of the CSI sequences in
+/// between, e.g. `31` of `ESC[31m`, with the sequence's final byte.
+fn sequences(line: &str) -> Vec<(&str, Option<(&str, char)>)> {
+    let mut parts = Vec::new();
+    let mut rest = line;
+    while let Some(start) = rest.find("\x1b[") {
+        let after = &rest[start + 2..];
+        let Some(end) = after.find(|c: char| ('@'..='~').contains(&c)) else {
+            break;
+        };
+        let final_byte = after[end..].chars().next().expect("found above");
+        parts.push((&rest[..start], Some((&after[..end], final_byte))));
+        rest = &after[end + final_byte.len_utf8()..];
+    }
+    parts.push((rest, None));
+    parts
+}
+
+/// `line` without its escape sequences.
+pub fn strip_sgr(line: &str) -> String {
+    sequences(line).into_iter().map(|(text, _)| text).collect()
+}
+
+/// The colors and attributes set by SGR sequences.
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Pen {
+    fg: Option<String>,
+    bg: Option<String>,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Pen {
+    fn apply(&mut self, params: &str) {
+        let codes: Vec<u16> = params.split([';', ':']).map(|code| code.parse().unwrap_or(0)).collect();
+        let mut i = 0;
+        while i < codes.len() {
+            match codes[i] {
+                0 => *self = Pen::default(),
+                1 => self.bold = true,
+                3 => self.italic = true,
+                4 => self.underline = true,
+                22 => self.bold = false,
+                23 => self.italic = false,
+                24 => self.underline = false,
+                code @ 30..=37 => self.fg = Some(palette(code as u8 - 30)),
+                code @ 90..=97 => self.fg = Some(palette(code as u8 - 90 + 8)),
+                code @ 40..=47 => self.bg = Some(palette(code as u8 - 40)),
+                code @ 100..=107 => self.bg = Some(palette(code as u8 - 100 + 8)),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                code @ (38 | 48) => {
+                    let color = match codes.get(i + 1) {
+                        Some(5) => {
+                            i += 2;
+                            codes.get(i).map(|&n| palette(n.min(255) as u8))
+                        }
+                        Some(2) => {
+                            i += 4;
+                            match codes.get(i - 2..=i) {
+                                Some(&[r, g, b]) => Some(format!("#{:02x}{:02x}{:02x}", r.min(255), g.min(255), b.min(255))),
+                                _ => None,
+                            }
+                        }
+                        _ => None,
+                    };
+                    if code == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+    }
+
+    fn style(&self) -> String {
+        let mut style = Vec::new();
+        if let Some(fg) = &self.fg {
+            style.push(format!("color:{}", fg));
+        }
+        if let Some(bg) = &self.bg {
+            style.push(format!("background:{}", bg));
+        }
+        if self.bold {
+            style.push("font-weight:bold".to_string());
+        }
+        if self.italic {
+            style.push("font-style:italic".to_string());
+        }
+        if self.underline {
+            style.push("text-decoration:underline".to_string());
+        }
+        style.join(";")
+    }
+}
+
+/// The color of xterm's 256-color palette entry `n`.
+fn palette(n: u8) -> String {
+    const BASIC: [&str; 16] = [
+        "#000000", "#cd3131", "#0dbc79", "#e5e510", "#2472c8", "#bc3fbc", "#11a8cd", "#e5e5e5",
+        "#666666", "#f14c4c", "#23d18b", "#f5f543", "#3b8eea", "#d670d6", "#29b8db", "#ffffff",
+    ];
+    match n {
+        0..=15 => BASIC[n as usize].to_string(),
+        16..=231 => {
+            let level = |v: u8| if v == 0 { 0 } else { 55 + v * 40 };
+            let n = n - 16;
+            format!("#{:02x}{:02x}{:02x}", level(n / 36), level(n / 6 % 6), level(n % 6))
+        }
+        _ => {
+            let gray = 8 + (n - 232) * 10;
+            format!("#{:02x}{:02x}{:02x}", gray, gray, gray)
+        }
+    }
+}
+
+/// `line` as HTML, its colors turned into styled spans.
+pub fn ansi_to_html(line: &str) -> String {
+    let mut html = String::new();
+    let mut pen = Pen::default();
+    for (text, sequence) in sequences(line) {
+        if !text.is_empty() {
+            let style = pen.style();
+            if style.is_empty() {
+                html.push_str(&escape_html(text));
+            } else {
+                html.push_str(&format!("<span style=\"{}\">{}</span>", style, escape_html(text)));
+            }
+        }
+        if let Some((params, 'm')) = sequence {
+            pen.apply(params);
+        }
+    }
+    html
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Vec<Entry> {
+        vec![
+            Entry::Started { at: 0 },
+            Entry::Output { lines: vec!["~/app$ cargo test".to_string()] },
+            Entry::Output { lines: vec!["test result: \x1b[32mok\x1b[0m. 3 <passed>".to_string()] },
+            Entry::Exchange { question: "why & how".to_string(), answer: "Run `cargo test -- --nocapture`.\n".to_string() },
+            Entry::Annotation {
+                at: 60,
+                lines: vec!["test result: \x1b[32mok\x1b[0m. 3 <passed>".to_string()],
+                comment: "flaky <sometimes>".to_string(),
+            },
+        ]
+    }
+
+    #[test]
+    fn test_ansi_to_html() {
+        assert_eq!(ansi_to_html("a \x1b[1;31mred\x1b[0m <b>"), "a <span style=\"color:#cd3131;font-weight:bold\">red</span> &lt;b&gt;");
+        assert_eq!(ansi_to_html("\x1b[38;5;196mx\x1b[39;48;2;0;128;255my"), "<span style=\"color:#ff0000\">x</span><span style=\"background:#0080ff\">y</span>");
+        assert_eq!(ansi_to_html("\x1b[90mgray\x1b[m"), "<span style=\"color:#666666\">gray</span>");
+        assert_eq!(strip_sgr("\x1b[32mok\x1b[0m done\x1b["), "ok done\x1b[");
+    }
+
+    #[test]
+    fn test_exporters() {
+        let entries = sample();
+        let text = exporter("text").unwrap().export(&entries);
+        assert!(text.contains("~/app$ cargo test\ntest result: ok. 3 <passed>\n\nQ: why & how\n\nRun `cargo test -- --nocapture`.\n"), "{}", text);
+        assert!(text.ends_with("Note: flaky <sometimes>\n  > test result: ok. 3 <passed>\n\n"), "{}", text);
+
+        let markdown = exporter("markdown").unwrap().export(&entries);
+        assert!(markdown.contains("```console\n~/app$ cargo test\ntest result: ok. 3 <passed>\n```\n"), "{}", markdown);
+        assert!(markdown.contains("> **Question:** why & how\n\nRun `cargo test -- --nocapture`.\n"), "{}", markdown);
+        let fenced = MarkdownExporter.export(&[Entry::Output { lines: vec!["```".to_string()] }]);
+        assert_eq!(fenced, "````console\n```\n````\n\n");
+        assert!(markdown.ends_with("**Note:** flaky <sometimes>\n\n```console\ntest result: ok. 3 <passed>\n```\n\n"), "{}", markdown);
+
+        let html = exporter("html").unwrap().export(&entries);
+        assert!(html.contains("~/app$ cargo test\ntest result: <span style=\"color:#0dbc79\">ok</span>. 3 &lt;passed&gt;</pre>"), "{}", html);
+        assert!(html.contains("<strong>Question:</strong> why &amp; how"), "{}", html);
+        assert!(html.contains("<strong>Note:</strong> flaky &lt;sometimes&gt;</p>"), "{}", html);
+        assert!(exporter("pdf").is_none());
+    }
+
+    #[test]
+    fn test_transcript_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let transcripts = transcripts_dir(&dir.path().join("config.toml"));
+        assert!(transcript_path(&transcripts, "../elsewhere").is_err());
+        let path = transcript_path(&transcripts, "incident-4711").unwrap();
+        assert!(load(&path).is_err());
+        let mut transcript = TranscriptFile::open(&path).unwrap();
+        for entry in &sample()[1..] {
+            transcript.append(entry).unwrap();
+        }
+        // Buffered until flushed
+        assert!(load(&path).unwrap().is_empty());
+        transcript.flush().unwrap();
+        let entries = load(&path).unwrap();
+        assert!(matches!(entries[0], Entry::Started { .. }));
+        assert_eq!(entries[1..], sample()[1..]);
+    }
+
+    #[test]
+    fn test_transcripts_are_rotated() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.jsonl");
+        let mut transcript = TranscriptFile::open_with_limit(&path, 200).unwrap();
+        let output = |n: usize| Entry::Output { lines: vec![format!("line {} of a long build", n)] };
+        for n in 0..10 {
+            transcript.append(&output(n)).unwrap();
+        }
+        transcript.flush().unwrap();
+        assert!(fs::metadata(&path).unwrap().len() <= 200);
+        assert!(fs::metadata(rotated_path(&path)).unwrap().len() <= 200);
+        // What was moved aside before that is gone
+        let entries = load(&path).unwrap();
+        assert!(entries.len() < 11);
+        assert_eq!(entries.last(), Some(&output(9)));
+    }
+}
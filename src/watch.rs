@@ -0,0 +1,183 @@
+use anyhow::{Context, Result};
+use crossterm::style::Color;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::sync::mpsc::UnboundedSender;
+use tokio::task::JoinHandle;
+
+use crate::diff::{self, DiffLine};
+
+/// Interval used when none is given.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(2);
+
+/// At most this many output lines are kept for the panel.
+const MAX_WATCH_LINES: usize = 200;
+
+/// The output of one run of a watched command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchOutput {
+    pub id: u64,
+    pub lines: Vec<String>,
+}
+
+/// A command run repeatedly in the background, outside the shell.
+#[derive(Debug)]
+pub struct Watch {
+    pub id: u64,
+    pub command: String,
+    pub interval: Duration,
+    // Latest output, each line flagged if it changed since the previous run
+    lines: Vec<(String, bool)>,
+    task: JoinHandle<()>,
+}
+
+impl Watch {
+    /// Starts running `command` every `interval`, sending each output to `tx`.
+    pub fn start(id: u64, command: String, interval: Duration, tx: UnboundedSender<WatchOutput>) -> Self {
+        let task = {
+            let command = command.clone();
+            tokio::spawn(async move {
+                loop {
+                    let lines = run_once(&command).await.unwrap_or_else(|e| vec![format!("{:#}", e)]);
+                    if tx.send(WatchOutput { id, lines }).is_err() {
+                        break;
+                    }
+                    tokio::time::sleep(interval).await;
+                }
+            })
+        };
+        Watch {
+            id,
+            command,
+            interval,
+            lines: Vec::new(),
+            task,
+        }
+    }
+
+    /// Records a new output, flagging the lines that differ from the last one.
+    /// The first output has nothing to compare against.
+    pub fn update(&mut self, lines: Vec<String>) {
+        if self.lines.is_empty() {
+            self.lines = lines.into_iter().map(|line| (line, false)).collect();
+            return;
+        }
+        let previous: Vec<String> = self.lines.drain(..).map(|(line, _)| line).collect();
+        self.lines = diff::diff_lines(&previous, &lines)
+            .into_iter()
+            .filter_map(|line| match line {
+                DiffLine::Same(text) => Some((text, false)),
+                DiffLine::Added(text) => Some((text, true)),
+                DiffLine::Removed(_) | DiffLine::Skipped(_) => None,
+            })
+            .collect();
+    }
+
+    pub fn title(&self) -> String {
+        format!("every {}s: {}", self.interval.as_secs_f64(), self.command)
+    }
+
+    /// The panel content, changed lines highlighted if `highlight` is set.
+    pub fn panel_lines(&self, highlight: bool) -> Vec<(String, Option<Color>)> {
+        self.lines
+            .iter()
+            .map(|(line, changed)| (line.clone(), (highlight && *changed).then_some(Color::Yellow)))
+            .collect()
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+/// Runs `command` with `/bin/sh` and returns its stdout followed by its stderr.
+pub async fn run_once(command: &str) -> Result<Vec<String>> {
+    let output = tokio::process::Command::new("/bin/sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::null())
+        .kill_on_drop(true)
+        .output()
+        .await
+        .with_context(|| format!("Failed to run watched command: {}", command))?;
+
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    );
+    Ok(text.lines().take(MAX_WATCH_LINES).map(clean_line).collect())
+}
+
+/// Expands tabs and drops control characters, which would upset the panel.
+pub fn clean_line(line: &str) -> String {
+    let mut clean = String::new();
+    for c in line.chars() {
+        if c == '\t' {
+            clean.push(' ');
+            while !clean.chars().count().is_multiple_of(8) {
+                clean.push(' ');
+            }
+        } else if !c.is_control() {
+            clean.push(c);
+        }
+    }
+    clean
+}
+
+/// Parses an interval such as `2`, `2s`, `500ms`, `1m` or `1h`. Bare
+/// numbers are seconds.
+pub fn parse_interval(text: &str) -> Result<Duration> {
+    let text = text.trim();
+    let (number, scale) = if let Some(ms) = text.strip_suffix("ms") {
+        (ms, 0.001)
+    } else if let Some(s) = text.strip_suffix('s') {
+        (s, 1.0)
+    } else if let Some(m) = text.strip_suffix('m') {
+        (m, 60.0)
+    } else if let Some(h) = text.strip_suffix('h') {
+        (h, 3600.0)
+    } else {
+        (text, 1.0)
+    };
+    let value: f64 = number.trim().parse()
+        .with_context(|| format!("Invalid interval: {}", text))?;
+    match Duration::try_from_secs_f64(value * scale) {
+        Ok(interval) if interval >= Duration::from_millis(100) => Ok(interval),
+        _ => anyhow::bail!("Interval must be at least 100ms: {}", text),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_interval() {
+        assert_eq!(parse_interval("2").unwrap(), Duration::from_secs(2));
+        assert_eq!(parse_interval("500ms").unwrap(), Duration::from_millis(500));
+        assert_eq!(parse_interval(" 1m ").unwrap(), Duration::from_secs(60));
+        assert_eq!(parse_interval("1.5h").unwrap(), Duration::from_secs(5400));
+        assert!(parse_interval("0").is_err());
+        assert!(parse_interval("-1").is_err());
+        assert!(parse_interval("soon").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_watch_highlights_changes() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let mut watch = Watch::start(1, "printf 'a\\tb\\n'; echo err >&2".to_string(), DEFAULT_INTERVAL, tx);
+        let output = rx.recv().await.unwrap();
+        assert_eq!(output.lines, vec!["a       b", "err"]);
+
+        watch.update(output.lines);
+        watch.update(vec!["a       b".to_string(), "ok".to_string()]);
+        assert_eq!(
+            watch.panel_lines(true),
+            vec![("a       b".to_string(), None), ("ok".to_string(), Some(Color::Yellow))]
+        );
+        assert!(watch.panel_lines(false).iter().all(|(_, color)| color.is_none()));
+    }
+}
@@ -1,207 +1,2934 @@
 use crossterm::{
     cursor,
-    event::{Event, KeyCode, KeyEvent},
-    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
-    terminal::{Clear, ClearType},
+    event::{Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+    style::{Attribute, Color, Print, ResetColor, SetAttribute},
+    terminal::{Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
     QueueableCommand,
 };
-use std::io::{stdout, Write};
+use std::collections::VecDeque;
+use std::io::{stderr, stdout, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use anyhow::Result;
+use chrono::{DateTime, Local};
+use nix::sys::signal::Signal;
+use ratatui::backend::{Backend, CrosstermBackend};
+use ratatui::buffer::Buffer;
+use ratatui::layout::{Alignment, Constraint, Layout, Rect};
+use ratatui::style::{Modifier, Style as CellStyle};
+use ratatui::symbols::border;
+use ratatui::text::{Line, Span as TextSpan};
+use ratatui::widgets::{Block, List, ListItem, ListState, Padding, Paragraph, StatefulWidget, Widget};
+use regex::Regex;
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::clock::{Clock, SystemClock};
+use crate::config::HookConfig;
+use crate::drafts::{self, Draft, AUTOSAVE_INTERVAL};
+use crate::errors::Error;
+use crate::highlight::{Highlighter, Span};
+use crate::i18n::{tr, tr_args};
+use crate::inputs::{self, InputHistory};
+use crate::markdown::{self, Emphasis};
+use crate::pane::{self, SplitDirection};
+use crate::ports::{self, Port};
+use crate::process::{self, fuzzy_matches, Process, Scope};
+use crate::screen::Snapshot;
+use crate::scrollback::{search_pattern, Scrollback};
+use crate::tail::{highlight_spans, Tail};
+use crate::terminal::{self, clipboard_sequence, notification_sequence, KeyInput};
+use crate::text;
+use crate::theme::{ColorPair, Theme};
+
+/// How popups and panels are drawn.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Style {
+    /// Draw without colors, for `NO_COLOR`.
+    pub monochrome: bool,
+    /// Draw without colors or box drawing, and print notifications as plain
+    /// lines instead of showing popups, for `--plain`.
+    pub plain: bool,
+    /// The colors to draw in, from `[ui.theme]`.
+    pub theme: Theme,
+    /// Mark what colors set apart in other ways too: colored lines in bold,
+    /// matches underlined and the selected row with `›`, for
+    /// `[ui.accessibility] markers`.
+    pub markers: bool,
+}
+
+impl Style {
+    /// The style for the environment: monochrome when `NO_COLOR` is set to
+    /// anything but an empty string (see no-color.org).
+    pub fn detect(plain: bool) -> Self {
+        let monochrome = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+        Style { monochrome, plain, ..Style::default() }
+    }
+
+    pub fn colors(&self) -> bool {
+        !self.monochrome && !self.plain
+    }
+
+    pub fn borders(&self) -> &'static Borders {
+        if self.plain {
+            &ASCII_BORDERS
+        } else {
+            &BOX_BORDERS
+        }
+    }
+
+    /// The borders as ratatui draws them.
+    fn border_set(&self) -> border::Set {
+        let borders = self.borders();
+        border::Set {
+            top_left: borders.top_left,
+            top_right: borders.top_right,
+            bottom_left: borders.bottom_left,
+            bottom_right: borders.bottom_right,
+            vertical_left: borders.vertical,
+            vertical_right: borders.vertical,
+            horizontal_top: borders.horizontal,
+            horizontal_bottom: borders.horizontal,
+        }
+    }
+}
+
+/// The characters a window's frame is drawn with.
+#[derive(Debug)]
+pub struct Borders {
+    pub top_left: &'static str,
+    pub top_right: &'static str,
+    pub bottom_left: &'static str,
+    pub bottom_right: &'static str,
+    pub horizontal: &'static str,
+    pub vertical: &'static str,
+    pub left_tee: &'static str,
+    pub right_tee: &'static str,
+}
+
+const BOX_BORDERS: Borders = Borders {
+    top_left: "┌",
+    top_right: "┐",
+    bottom_left: "└",
+    bottom_right: "┘",
+    horizontal: "─",
+    vertical: "│",
+    left_tee: "├",
+    right_tee: "┤",
+};
+
+/// How many closed popups `builtin:reopen_last_popup` can bring back.
+const CLOSED_POPUP_LIMIT: usize = 10;
+
+// For terminals without line drawing characters, like serial consoles
+const ASCII_BORDERS: Borders = Borders {
+    top_left: "+",
+    top_right: "+",
+    bottom_left: "+",
+    bottom_right: "+",
+    horizontal: "-",
+    vertical: "|",
+    left_tee: "+",
+    right_tee: "+",
+};
 
 #[derive(Debug)]
 pub struct WindowManager {
     pub terminal_size: (u16, u16), // (cols, rows)
+    /// Rows showing inline images, which popups stay off when they can.
+    pub avoid_rows: Vec<(u16, u16)>,
+    pub style: Style,
+    /// The line the shell's cursor is on, printed again after a plain
+    /// notification.
+    pub cursor_line: String,
+    /// Popups closed this session, oldest first.
+    pub closed: VecDeque<ClosedPopup>,
+    /// Popups pinned with `p`, for the session to take and draw.
+    pub pinned: Vec<Note>,
+    /// Colors code blocks in LLM replies; None leaves them plain.
+    pub highlighter: Option<Highlighter>,
+    /// The text of each row of the screen, put back where popups were.
+    pub screen_rows: Vec<String>,
+    /// Set when a popup is closed, for the session to have a full-screen
+    /// program draw itself again.
+    pub popup_closed: bool,
+    /// Where what was entered in input popups is kept; None keeps nothing.
+    pub input_history: Option<PathBuf>,
+    pub chat: ChatView,
+    /// The session's clock, which times toasts and stamps closed popups.
+    pub clock: Arc<dyn Clock>,
+}
+
+/// How long a hook's `toast` output, and most other toasts, stay on the
+/// screen.
+pub const TOAST_LIFETIME: Duration = Duration::from_secs(5);
+
+/// A popup pinned with `p`, which the session keeps drawn in a corner of
+/// the screen until it is closed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Note {
+    pub title: String,
+    pub lines: Vec<(String, Option<Color>)>,
+    /// When the note goes away by itself; pinned notes never do.
+    pub expires: Option<Instant>,
+}
+
+impl Note {
+    /// A note that shows `content` for `TOAST_LIFETIME` from `now` without
+    /// waiting for a key.
+    pub fn toast(title: &str, content: &str, now: Instant) -> Self {
+        Note::toast_for(title, content, TOAST_LIFETIME, now)
+    }
+
+    /// A note that shows `content` for `lifetime` from `now`.
+    pub fn toast_for(title: &str, content: &str, lifetime: Duration, now: Instant) -> Self {
+        Note {
+            title: title.to_string(),
+            lines: content.lines().map(|line| (line.to_string(), None)).collect(),
+            expires: Some(now + lifetime),
+        }
+    }
+
+    pub fn expired(&self, now: Instant) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+}
+
+/// Where a pinned note is drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopRight,
+    BottomRight,
+    BottomLeft,
+    TopLeft,
+}
+
+impl Corner {
+    /// Clockwise from the top right, the watch panel's corner.
+    pub const ALL: [Corner; 4] = [Corner::TopRight, Corner::BottomRight, Corner::BottomLeft, Corner::TopLeft];
+
+    /// The next corner clockwise.
+    pub fn next(self) -> Corner {
+        let index = Corner::ALL.iter().position(|corner| *corner == self).unwrap_or(0);
+        Corner::ALL[(index + 1) % Corner::ALL.len()]
+    }
+}
+
+/// A popup that was shown and closed, kept so it can be opened again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClosedPopup {
+    pub title: String,
+    pub lines: Vec<(String, Option<Color>)>,
+    pub closed: DateTime<Local>,
+}
+
+/// How long something streams into a live popup before the popup is
+/// shown, so that what is done quickly just pops up at the end.
+pub const LIVE_POPUP_DELAY: Duration = Duration::from_millis(500);
+
+/// Most lines a live popup keeps; it only shows the last ones, and the
+/// whole output pops up once it is done.
+const MAX_LIVE_POPUP_LINES: usize = 1000;
+
+/// A popup whose content grows while it is shown, as a command's output or
+/// the assistant's reply comes in. It doesn't wait for keys. Once what
+/// feeds it is done it is closed, for the whole of it to be shown the
+/// usual way.
+#[derive(Debug)]
+pub struct LivePopup {
+    pub title: String,
+    /// The last lines so far; the last one may still grow.
+    pub lines: Vec<String>,
+    // Where it was drawn last
+    window: Option<Window>,
+}
+
+impl LivePopup {
+    pub fn new(title: &str) -> Self {
+        LivePopup { title: title.to_string(), lines: vec![String::new()], window: None }
+    }
+
+    /// Adds `text`, which may start or end in the middle of a line.
+    pub fn append(&mut self, text: &str) {
+        let mut parts = text.split('\n').map(|part| part.trim_end_matches('\r'));
+        if let (Some(first), Some(last)) = (parts.next(), self.lines.last_mut()) {
+            last.push_str(first);
+        }
+        self.lines.extend(parts.map(str::to_string));
+        let excess = self.lines.len().saturating_sub(MAX_LIVE_POPUP_LINES);
+        self.lines.drain(..excess);
+    }
+
+    /// Applies an update sent through a `PopupHandle`. Returns true once
+    /// the popup is finished.
+    pub fn apply(&mut self, update: PopupUpdate) -> bool {
+        match update {
+            PopupUpdate::Append(text) => self.append(&text),
+            PopupUpdate::Title(title) => self.title = title,
+            PopupUpdate::Finish => return true,
+        }
+        false
+    }
+}
+
+/// Most questions and answers the chat view keeps.
+const MAX_CHAT_MESSAGES: usize = 50;
+
+/// A question to the assistant and its answer, as the chat view shows it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChatMessage {
+    pub question: String,
+    /// The answer so far; it grows while it comes in.
+    pub answer: String,
+}
+
+/// The session's conversation with the assistant. While it is open it is
+/// drawn in a column beside the shell, and answers go there instead of
+/// into popups.
+#[derive(Debug, Default)]
+pub struct ChatView {
+    pub messages: VecDeque<ChatMessage>,
+    /// Where it is drawn; None while it is closed.
+    pub layout: Option<pane::Layout>,
+}
+
+impl ChatView {
+    pub fn is_open(&self) -> bool {
+        self.layout.is_some()
+    }
+
+    /// Starts an answer to `question`, for `append` to fill in.
+    pub fn ask(&mut self, question: &str) {
+        if self.messages.len() == MAX_CHAT_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(ChatMessage { question: question.trim().to_string(), answer: String::new() });
+    }
+
+    pub fn append(&mut self, text: &str) {
+        if let Some(message) = self.messages.back_mut() {
+            message.answer.push_str(&text.replace('\r', ""));
+        }
+    }
+
+    /// Drops the last question, whose answer never came.
+    pub fn discard_last(&mut self) {
+        self.messages.pop_back();
+    }
+
+    /// The last `height` rows of the conversation wrapped to `width`, and
+    /// whether each belongs to a question.
+    pub fn rows(&self, width: usize, height: usize) -> Vec<(String, bool)> {
+        let mut rows = Vec::new();
+        for (index, message) in self.messages.iter().enumerate() {
+            if index > 0 {
+                rows.push((String::new(), false));
+            }
+            for line in message.question.lines() {
+                rows.extend(text::wrap(&format!("> {}", line), width).into_iter().map(|row| (row, true)));
+            }
+            for line in message.answer.trim_end().lines() {
+                rows.extend(text::wrap(line, width).into_iter().map(|row| (row, false)));
+            }
+        }
+        if rows.is_empty() {
+            rows = text::wrap(&tr("chat-empty"), width).into_iter().map(|row| (row, false)).collect();
+        }
+        rows.split_off(rows.len().saturating_sub(height))
+    }
+}
+
+/// A change to a live popup, sent by what feeds it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PopupUpdate {
+    Append(String),
+    Title(String),
+    /// Nothing more is coming; the popup closes.
+    Finish,
+}
+
+/// The end of a live popup that a task feeds, from whichever thread or
+/// task it runs on. The session draws what it sends.
+#[derive(Debug, Clone)]
+pub struct PopupHandle {
+    id: u64,
+    tx: UnboundedSender<(u64, PopupUpdate)>,
+}
+
+impl PopupHandle {
+    pub fn new(id: u64, tx: UnboundedSender<(u64, PopupUpdate)>) -> Self {
+        PopupHandle { id, tx }
+    }
+
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    // A popup whose session is gone has no one left to show it to
+    fn send(&self, update: PopupUpdate) {
+        let _ = self.tx.send((self.id, update));
+    }
+
+    pub fn append(&self, text: &str) {
+        self.send(PopupUpdate::Append(text.to_string()));
+    }
+
+    pub fn set_title(&self, title: &str) {
+        self.send(PopupUpdate::Title(title.to_string()));
+    }
+
+    pub fn finish(&self) {
+        self.send(PopupUpdate::Finish);
+    }
+}
+
+/// The end of the chat view's last answer that a task feeds, like a
+/// `PopupHandle` for a live popup.
+#[derive(Debug, Clone)]
+pub struct ChatHandle {
+    tx: UnboundedSender<String>,
+}
+
+impl ChatHandle {
+    pub fn new(tx: UnboundedSender<String>) -> Self {
+        ChatHandle { tx }
+    }
+
+    pub fn append(&self, text: &str) {
+        let _ = self.tx.send(text.to_string());
+    }
 }
 
 #[derive(Debug)]
 pub struct Window {
     pub title: String,
     pub content: Vec<String>,
+    /// Foreground color per content line; lines without one are white.
+    pub colors: Vec<Option<Color>>,
+    /// Colors within content lines, for lines with more than one, such as
+    /// highlighted code. Lines without spans are drawn in their `colors`.
+    pub spans: Vec<Vec<Span>>,
+    /// Bold and italic stretches of content lines, as markdown has them.
+    pub emphasis: Vec<Vec<Emphasis>>,
     pub x: u16,
     pub y: u16,
     pub width: u16,
     pub height: u16,
 }
 
+// What a frame of copy mode shows
+struct CopyFrame<'a> {
+    lines: &'a VecDeque<String>,
+    first_line: usize,
+    cursor: usize,
+    selection: Range<usize>,
+    marked: &'a [Range<usize>],
+}
+
 impl WindowManager {
     pub fn new() -> Result<Self> {
         let terminal_size = crossterm::terminal::size()?;
-        Ok(WindowManager { terminal_size })
+        Ok(WindowManager {
+            terminal_size,
+            avoid_rows: Vec::new(),
+            style: Style::default(),
+            cursor_line: String::new(),
+            closed: VecDeque::new(),
+            pinned: Vec::new(),
+            highlighter: None,
+            screen_rows: Vec::new(),
+            popup_closed: false,
+            input_history: None,
+            chat: ChatView::default(),
+            clock: Arc::new(SystemClock),
+        })
     }
 
     pub fn show_popup(&mut self, title: &str, content: &str) -> Result<()> {
-        // Split content into lines and calculate window dimensions
-        let lines: Vec<String> = content.lines().map(|s| s.to_string()).collect();
-        let content_width = lines.iter().map(|line| line.len()).max().unwrap_or(0);
-        let min_width = title.len() + 4; // Account for borders and padding
-        
-        let window_width = std::cmp::max(content_width + 4, min_width) as u16;
-        let window_height = (lines.len() + 4) as u16; // Content + borders + padding
-        
-        // Center the window
-        let x = (self.terminal_size.0.saturating_sub(window_width)) / 2;
-        let y = (self.terminal_size.1.saturating_sub(window_height)) / 2;
-        
-        let window = Window {
-            title: title.to_string(),
-            content: lines,
-            x,
-            y,
-            width: window_width,
-            height: window_height,
-        };
+        let lines = content.lines().map(|line| (line.to_string(), None)).collect();
+        self.show_colored_popup(title, lines)
+    }
 
-        self.draw_window(&window)?;
-        self.wait_for_close()?;
+    /// Like `show_popup`, laying out `content` as markdown, for LLM replies.
+    /// Code blocks are highlighted by their language.
+    pub fn show_markdown_popup(&mut self, title: &str, content: &str) -> Result<()> {
+        let markdown::Rendered { lines, emphasis, code_blocks } = markdown::render(content);
+        let mut spans = vec![Vec::new(); lines.len()];
+        for block in code_blocks.iter().filter(|block| !block.language.is_empty()) {
+            let Some(highlighter) = &self.highlighter else {
+                break;
+            };
+            let code: Vec<&str> = lines[block.lines.clone()].iter().map(|(line, _)| &line[block.indent..]).collect();
+            let Some(colored) = highlighter.highlight(&code, &block.language) else {
+                continue;
+            };
+            for (row, line_spans) in block.lines.clone().zip(colored) {
+                let indent = block.indent;
+                spans[row] = line_spans.into_iter().map(|(range, color)| (range.start + indent..range.end + indent, color)).collect();
+            }
+        }
+        let result = self.present_popup(title, lines.clone(), spans, emphasis);
+        self.remember_closed(title, lines);
+        result
+    }
+
+    /// Like `show_popup`, with a foreground color for each line.
+    pub fn show_colored_popup(&mut self, title: &str, lines: Vec<(String, Option<Color>)>) -> Result<()> {
+        let result = self.present_popup(title, lines.clone(), Vec::new(), Vec::new());
+        self.remember_closed(title, lines);
+        result
+    }
+
+    /// Draws the popup and waits for ESC, or `p` to pin it, without
+    /// keeping it once closed.
+    fn present_popup(&mut self, title: &str, lines: Vec<(String, Option<Color>)>, spans: Vec<Vec<Span>>, emphasis: Vec<Vec<Emphasis>>) -> Result<()> {
+        let note = Note { title: title.to_string(), lines: lines.clone(), expires: None };
+        let (content, colors): (Vec<String>, _) = lines.into_iter().unzip();
+        if self.style.plain {
+            return self.notify(title, &content);
+        }
+        let window = self.layout_styled_window(title, content, colors, spans, emphasis);
+
+        self.draw_window_with_hint(&window, &tr("popup-close-pin"))?;
+        let pinned = self.wait_for_close_or_pin()?;
         self.clear_window(&window)?;
-        
+        if pinned {
+            self.pinned.push(note);
+        }
+
         Ok(())
     }
 
-    fn draw_window(&self, window: &Window) -> Result<()> {
-        let mut stdout = stdout();
-        
-        // Save cursor position
-        stdout.queue(cursor::SavePosition)?;
-        
-        // Draw window background and borders
-        for row in 0..window.height {
-            stdout.queue(cursor::MoveTo(window.x, window.y + row))?;
-            
-            if row == 0 {
-                // Top border
-                stdout.queue(SetBackgroundColor(Color::Blue))?;
-                stdout.queue(SetForegroundColor(Color::White))?;
-                stdout.queue(Print("┌"))?;
-                for _ in 1..window.width - 1 {
-                    stdout.queue(Print("─"))?;
-                }
-                stdout.queue(Print("┐"))?;
-            } else if row == 1 {
-                // Title row
-                stdout.queue(SetBackgroundColor(Color::Blue))?;
-                stdout.queue(SetForegroundColor(Color::White))?;
-                stdout.queue(Print("│"))?;
-                
-                let title_padding = ((window.width - 2) as usize).saturating_sub(window.title.len());
-                let left_padding = title_padding / 2;
-                let right_padding = title_padding - left_padding;
-                
-                for _ in 0..left_padding {
-                    stdout.queue(Print(" "))?;
-                }
-                stdout.queue(Print(&window.title))?;
-                for _ in 0..right_padding {
-                    stdout.queue(Print(" "))?;
-                }
-                
-                stdout.queue(Print("│"))?;
-            } else if row == 2 {
-                // Separator row
-                stdout.queue(SetBackgroundColor(Color::Blue))?;
-                stdout.queue(SetForegroundColor(Color::White))?;
-                stdout.queue(Print("├"))?;
-                for _ in 1..window.width - 1 {
-                    stdout.queue(Print("─"))?;
-                }
-                stdout.queue(Print("┤"))?;
-            } else if row == window.height - 1 {
-                // Bottom border
-                stdout.queue(SetBackgroundColor(Color::Blue))?;
-                stdout.queue(SetForegroundColor(Color::White))?;
-                stdout.queue(Print("└"))?;
-                for _ in 1..window.width - 1 {
-                    stdout.queue(Print("─"))?;
-                }
-                stdout.queue(Print("┘"))?;
-            } else {
-                // Content rows
-                stdout.queue(SetBackgroundColor(Color::Blue))?;
-                stdout.queue(SetForegroundColor(Color::White))?;
-                stdout.queue(Print("│"))?;
-                
-                stdout.queue(SetBackgroundColor(Color::DarkBlue))?;
-                stdout.queue(SetForegroundColor(Color::White))?;
-                
-                let content_row = row - 3; // Account for title and borders
-                if content_row < window.content.len() as u16 {
-                    let line = &window.content[content_row as usize];
-                    stdout.queue(Print(" "))?; // Left padding
-                    stdout.queue(Print(line))?;
-                    
-                    // Right padding
-                    let line_len = line.len();
-                    let available_width = (window.width - 3) as usize; // -3 for borders and left padding
-                    if line_len < available_width {
-                        for _ in 0..(available_width - line_len) {
-                            stdout.queue(Print(" "))?;
-                        }
-                    }
-                } else {
-                    // Empty content row
-                    for _ in 0..window.width - 2 {
-                        stdout.queue(Print(" "))?;
+    /// Shows `message` in a free corner for `duration`, like a pinned
+    /// popup that goes away by itself. Keys go on to the shell meanwhile,
+    /// so it suits news that needs no answer, like a command being done.
+    pub fn show_toast(&mut self, message: &str, duration: Duration) {
+        self.pinned.push(Note::toast_for(&tr("toast-title"), message, duration, self.clock.now()));
+    }
+
+    /// The popups pinned since last asked, and the toasts shown, to draw
+    /// as notes.
+    pub fn take_pinned(&mut self) -> Vec<Note> {
+        std::mem::take(&mut self.pinned)
+    }
+
+    fn remember_closed(&mut self, title: &str, lines: Vec<(String, Option<Color>)>) {
+        if self.closed.len() == CLOSED_POPUP_LIMIT {
+            self.closed.pop_front();
+        }
+        self.closed.push_back(ClosedPopup { title: title.to_string(), lines, closed: self.clock.local() });
+    }
+
+    /// Opens the popups closed this session again, newest first. Left and
+    /// Right step to older and newer ones.
+    pub fn reopen_closed_popups(&mut self) -> Result<()> {
+        let Some(last) = self.closed.len().checked_sub(1) else {
+            return self.present_popup(&tr("reopen-title"), vec![(tr("reopen-empty"), None)], Vec::new(), Vec::new());
+        };
+        if self.style.plain {
+            let popup = &self.closed[last];
+            let content: Vec<String> = popup.lines.iter().map(|(line, _)| line.clone()).collect();
+            return self.notify(&reopen_title(&self.closed, last), &content);
+        }
+        let mut index = last;
+        loop {
+            let mut lines = self.closed[index].lines.clone();
+            lines.push((String::new(), None));
+            lines.push((tr("reopen-hint"), Some(Color::Yellow)));
+            let (content, colors): (Vec<String>, _) = lines.into_iter().unzip();
+            let window = self.layout_colored_window(&reopen_title(&self.closed, index), content, colors);
+            self.draw_window(&window)?;
+
+            let next = loop {
+                let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                    continue;
+                };
+                match code {
+                    KeyCode::Esc => break None,
+                    KeyCode::Char('p') => {
+                        let popup = &self.closed[index];
+                        self.pinned.push(Note { title: popup.title.clone(), lines: popup.lines.clone(), expires: None });
+                        break None;
                     }
+                    KeyCode::Left | KeyCode::Up if index > 0 => break Some(index - 1),
+                    KeyCode::Right | KeyCode::Down if index < last => break Some(index + 1),
+                    _ => {}
                 }
-                
-                stdout.queue(SetBackgroundColor(Color::Blue))?;
-                stdout.queue(SetForegroundColor(Color::White))?;
-                stdout.queue(Print("│"))?;
+            };
+            self.clear_window(&window)?;
+            match next {
+                Some(next) => index = next,
+                None => return Ok(()),
             }
         }
-        
-        // Draw close instruction at bottom
-        let close_msg = "Press ESC to close";
-        let close_x = window.x + window.width - close_msg.len() as u16 - 2;
-        let close_y = window.y + window.height - 1;
-        
-        stdout.queue(cursor::MoveTo(close_x, close_y))?;
-        stdout.queue(SetBackgroundColor(Color::Blue))?;
-        stdout.queue(SetForegroundColor(Color::Yellow))?;
-        stdout.queue(Print(close_msg))?;
-        
-        stdout.queue(ResetColor)?;
-        stdout.flush()?;
-        
-        Ok(())
     }
 
-    fn wait_for_close(&self) -> Result<()> {
+    /// Shows `error` with the reference of its entry in the debug log at
+    /// `log`. `c` copies the details to the clipboard and closes the popup.
+    pub fn show_error(&mut self, error: &Error, reference: &str, log: Option<&Path>) -> Result<()> {
+        let mut lines: Vec<String> = error.to_string().lines().map(str::to_string).collect();
+        lines.push(String::new());
+        lines.push(match log {
+            Some(log) => tr_args("error-reference", &[("reference", reference.into()), ("log", log.display().to_string().into())]),
+            None => tr_args("error-reference-unlogged", &[("reference", reference.into())]),
+        });
+        if self.style.plain {
+            return self.notify(&error.title(), &lines);
+        }
+        lines.push(tr("error-copy"));
+        let window = self.layout_window(&error.title(), lines);
+
+        self.draw_window(&window)?;
         loop {
-            match crossterm::event::read()? {
-                Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => {
+            match terminal::read_event()? {
+                Event::Key(KeyEvent { code: KeyCode::Esc, .. }) => break,
+                Event::Key(KeyEvent { code: KeyCode::Char('c'), .. }) => {
+                    let details = format!("[{}] {}", reference, error.details());
+                    stdout().queue(Print(clipboard_sequence(&details)))?;
                     break;
                 }
-                _ => {
-                    // Ignore other events
-                }
+                _ => {}
             }
         }
+        self.clear_window(&window)
+    }
+
+    /// Prints `content` into the terminal above the shell's prompt.
+    pub fn print_inline(&self, title: &str, content: &str) -> Result<()> {
+        let lines: Vec<String> = content.lines().map(str::to_string).collect();
+        self.notify(title, &lines)
+    }
+
+    /// Asks the terminal to show a desktop notification.
+    pub fn show_notification(&self, title: &str, content: &str) -> Result<()> {
+        let mut stdout = stdout();
+        stdout.queue(Print(notification_sequence(title, content)))?;
+        stdout.flush()?;
         Ok(())
     }
 
-    fn clear_window(&self, window: &Window) -> Result<()> {
+    /// Prints a notification to stderr on lines of its own, then the line
+    /// the shell's cursor was on, so the prompt and what was typed at it are
+    /// where they were.
+    fn notify(&self, title: &str, lines: &[String]) -> Result<()> {
         let mut stdout = stdout();
-        
-        // Clear the window area
-        for row in 0..window.height {
-            stdout.queue(cursor::MoveTo(window.x, window.y + row))?;
-            stdout.queue(Clear(ClearType::UntilNewLine))?;
+        stdout.queue(Print("\r"))?;
+        stdout.queue(Clear(ClearType::CurrentLine))?;
+        stdout.flush()?;
+
+        let mut stderr = stderr();
+        // Raw mode doesn't turn \n into \r\n
+        write!(stderr, "[{}]", title)?;
+        for line in lines {
+            if line.is_empty() {
+                write!(stderr, "\r\n")?;
+            } else {
+                write!(stderr, " {}\r\n", line)?;
+            }
         }
-        
-        // Restore cursor position
-        stdout.queue(cursor::RestorePosition)?;
-        stdout.queue(ResetColor)?;
+        if lines.is_empty() {
+            write!(stderr, "\r\n")?;
+        }
+        stderr.flush()?;
+
+        stdout.queue(Print(&self.cursor_line))?;
         stdout.flush()?;
-        
         Ok(())
     }
-}
 
-impl Default for WindowManager {
-    fn default() -> Self {
-        WindowManager::new().unwrap_or(WindowManager {
-            terminal_size: (80, 24),
-        })
+    /// The colors of `pair`, or the terminal's own without colors.
+    fn paint(&self, pair: ColorPair) -> CellStyle {
+        match self.style.colors() {
+            true => CellStyle::new().fg(pair.fg.into()).bg(pair.bg.into()),
+            false => CellStyle::new(),
+        }
+    }
+
+    fn paint_fg(&self, color: Color) -> CellStyle {
+        match self.style.colors() {
+            true => CellStyle::new().fg(color.into()),
+            false => CellStyle::new(),
+        }
+    }
+
+    /// The style of a line of text in its own `color`, if it has one. With
+    /// markers on, such lines are bold as well.
+    fn line_style(&self, color: Option<Color>) -> CellStyle {
+        let theme = &self.style.theme;
+        let Some(color) = color else {
+            return self.paint(theme.text);
+        };
+        let style = self.paint(theme.text).patch(self.paint_fg(theme.line_color(color)));
+        match self.style.markers && !self.style.plain {
+            true => style.add_modifier(Modifier::BOLD),
+            false => style,
+        }
+    }
+
+    /// `line` in the colors of its spans, and what they leave out in
+    /// `style`.
+    fn span_line<'a>(&self, line: &'a str, spans: &[Span], style: CellStyle) -> Line<'a> {
+        let mut styled = Vec::with_capacity(spans.len() * 2 + 1);
+        let mut at = 0;
+        for (range, color) in spans {
+            if range.start > at {
+                styled.push(TextSpan::styled(&line[at..range.start], style));
+            }
+            styled.push(TextSpan::styled(&line[range.clone()], style.patch(self.paint_fg(self.style.theme.line_color(*color)))));
+            at = range.end;
+        }
+        styled.push(TextSpan::styled(&line[at..], style));
+        Line::from(styled)
+    }
+
+    /// The style of a row of a list, in the selected colors if it is, and
+    /// in reverse video if asked.
+    fn row_style(&self, reverse: bool, selected: bool) -> CellStyle {
+        let pair = if selected { self.style.theme.selected } else { self.style.theme.text };
+        match reverse {
+            true => self.paint(pair).add_modifier(Modifier::REVERSED),
+            false => self.paint(pair),
+        }
+    }
+
+    /// What a row starts with: `›` on the selected one with markers on.
+    fn selection_lead(&self, selected: bool) -> &'static str {
+        match selected && self.style.markers {
+            true if self.style.plain => ">",
+            true => "›",
+            false => " ",
+        }
+    }
+
+    /// How many content lines fit in a popup on this terminal.
+    pub fn max_content_lines(&self) -> usize {
+        (self.terminal_size.1 as usize).saturating_sub(6).max(3)
+    }
+
+    /// How wide a popup's lines can be before it runs off the screen.
+    pub fn max_content_width(&self) -> usize {
+        (self.terminal_size.0 as usize).saturating_sub(6).max(20)
+    }
+
+    /// Lists `items` for picking one. Typing filters the list and the
+    /// arrows select; returns the index of the item picked with Enter, or
+    /// None after ESC. Until something is typed, the first nine can also be
+    /// picked with their digit key.
+    pub fn show_menu(&mut self, title: &str, items: &[String]) -> Result<Option<usize>> {
+        let window = self.menu_window(title, items);
+        let mut picker = Picker::default();
+        let choice = loop {
+            let listed = picker.listed(items);
+            self.draw_menu(&window, title, items, &listed, &picker)?;
+
+            let Event::Key(KeyEvent { code, modifiers, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            if let Some(choice) = picker.key(code, modifiers, &listed) {
+                break choice;
+            }
+        };
+        self.clear_window(&window)?;
+        Ok(choice)
+    }
+
+    /// A window for a menu of `items`, sized for all of them so that it
+    /// keeps its size while they are filtered.
+    fn menu_window(&self, title: &str, items: &[String]) -> Window {
+        let (cols, rows) = self.terminal_size;
+        // The filter line and the borders take 3 rows
+        let shown = items.len().min(self.max_content_lines()).max(1);
+        let height = (shown as u16 + 3).min(rows);
+        let longest = items.iter().map(|item| text::width(item) + 4).max().unwrap_or(0);
+        let inner = longest.max(text::width(title) + 2).max(text::width(&tr("menu-hint")) + 2).max(20);
+        let width = (inner as u16 + 2).min(cols);
+        Window {
+            title: title.to_string(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        }
+    }
+
+    fn draw_menu(&self, window: &Window, title: &str, items: &[String], listed: &[usize], picker: &Picker) -> Result<()> {
+        let header = match listed.is_empty() {
+            true => vec![format!("> {}_  {}", picker.filter, tr("palette-none"))],
+            false => vec![format!("> {}_", picker.filter)],
+        };
+        let rows: Vec<String> = listed
+            .iter()
+            .enumerate()
+            .map(|(row, &i)| match picker.filter.is_empty() && row < 9 {
+                true => format!("{}. {}", row + 1, items[i]),
+                false => format!("   {}", items[i]),
+            })
+            .collect();
+        self.draw_list(window, title, &header, &rows, picker.index, &tr("menu-hint"))
+    }
+
+    /// Asks `message` with yes and no to pick from, `default` selected.
+    /// `y` and `n` answer at once, the arrows and Tab move between the two
+    /// and Enter takes the selected one. ESC answers no.
+    pub fn show_confirm(&mut self, title: &str, message: &str, default: bool) -> Result<bool> {
+        let mut yes = default;
+        let layout = |yes: bool| {
+            let mut lines: Vec<String> = message.lines().map(str::to_string).collect();
+            lines.push(String::new());
+            lines.push(confirm_buttons(yes));
+            self.layout_window(title, lines)
+        };
+        let mut window = layout(yes);
+        let answer = loop {
+            self.draw_window_with_hint(&window, &tr("confirm-hint"))?;
+            let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            if let Some(answer) = confirm_key(code, &mut yes) {
+                break answer;
+            }
+            window = layout(yes);
+        };
+        self.clear_window(&window)?;
+        Ok(answer)
+    }
+
+    /// Asks for a line of text. Returns None if ESC was pressed or nothing
+    /// was entered.
+    ///
+    /// The text is saved as a draft while it is typed. If a previous popup
+    /// of the same kind was never closed, its draft can be restored with
+    /// ctrl+r. What is entered is kept in the input history, which up and
+    /// down step through and ctrl+r searches.
+    pub fn show_input(&mut self, title: &str, label: &str) -> Result<Option<String>> {
+        let text = self.show_input_or_empty(title, label)?;
+        Ok(text.filter(|text| !text.trim().is_empty()))
     }
-} 
\ No newline at end of file
+
+    /// Like `show_input`, but Enter on an empty line returns it. Only ESC
+    /// returns None.
+    pub fn show_input_or_empty(&mut self, title: &str, label: &str) -> Result<Option<String>> {
+        self.show_input_with(title, label, "", Some(label))
+    }
+
+    /// Like `show_input_or_empty`, starting with `initial` in the field.
+    /// Unless `history` is None, what is entered is kept under the title
+    /// and `history`, normally the label, or a name that stays the same
+    /// for a label that doesn't. Up and down step through what was entered
+    /// before, from the newest, and ctrl+r searches it.
+    pub fn show_input_with(&mut self, title: &str, label: &str, initial: &str, history: Option<&str>) -> Result<Option<String>> {
+        let field_width = std::cmp::max(text::width(label), 50);
+        let max_field = (self.terminal_size.0 as usize).saturating_sub(8).max(10);
+        let mut input = initial.to_string();
+        // Read again for each popup so that sessions don't drop what the
+        // others entered. One that can't be read is left alone.
+        let kind = history;
+        let mut inputs = match (kind, &self.input_history) {
+            (Some(_), Some(path)) => InputHistory::load(path).unwrap_or_default(),
+            _ => InputHistory::default(),
+        };
+        let history = kind.map_or(&[][..], |kind| inputs.entries(title, kind)).to_vec();
+        // How far back in `history` the field is, 0 being the newest
+        let mut recalled: Option<usize> = None;
+        // The ctrl+r query and the entry it found
+        let mut search: Option<(String, Option<usize>)> = None;
+        let mut shown: Option<Window> = None;
+        let mut draft = Draft::new(&drafts::drafts_dir(), title, kind.unwrap_or(label));
+        let mut unrestored = draft.load();
+
+        let result = loop {
+            let line = match &search {
+                Some((query, found)) => tr_args("input-search", &[
+                    ("query", query.as_str().into()),
+                    ("match", found.map_or("", |i| history[i].as_str()).into()),
+                ]),
+                None => input.clone(),
+            };
+            // Keep the end of long input visible
+            let visible: String = {
+                let chars: Vec<char> = line.chars().collect();
+                let start = chars.len().saturating_sub(max_field.saturating_sub(3));
+                chars[start..].iter().collect()
+            };
+            let field = format!("> {}_", visible);
+            let mut lines = vec![label.to_string()];
+            if let Some((_, saved)) = &unrestored {
+                let saved: DateTime<Local> = (*saved).into();
+                lines.push(tr_args("draft-offer", &[("time", saved.format("%H:%M").to_string().into())]));
+            }
+            lines.push(String::new());
+            lines.push(format!("{:<width$}", field, width = field_width.min(max_field)));
+            let window = self.layout_window(title, lines);
+            if let Some(previous) = shown.take() {
+                if previous.width != window.width || previous.height != window.height {
+                    self.clear_window(&previous)?;
+                }
+            }
+            self.draw_window(&window)?;
+            shown = Some(window);
+
+            // Wake up now and then so a draft is saved even while idle
+            let _ = draft.autosave(&input, Instant::now());
+            if !terminal::poll_event(AUTOSAVE_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(KeyEvent { code, modifiers, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            let control = modifiers.contains(KeyModifiers::CONTROL);
+            if let Some((query, found)) = &mut search {
+                match code {
+                    KeyCode::Esc => search = None,
+                    KeyCode::Enter => {
+                        if let Some(i) = *found {
+                            input = history[i].clone();
+                            recalled = None;
+                        }
+                        search = None;
+                    }
+                    KeyCode::Char('r') if control => {
+                        // Stays on the oldest match once there are no more
+                        *found = inputs::search(&history, query, found.unwrap_or(history.len())).or(*found);
+                    }
+                    KeyCode::Backspace => {
+                        query.pop();
+                        *found = inputs::search(&history, query, history.len());
+                    }
+                    KeyCode::Char(c) if !control => {
+                        query.push(c);
+                        *found = inputs::search(&history, query, history.len());
+                    }
+                    _ => {}
+                }
+                continue;
+            }
+            match code {
+                KeyCode::Esc => break None,
+                KeyCode::Enter => break Some(input.clone()),
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char('u') if control => input.clear(),
+                KeyCode::Char('r') if control => match unrestored.take() {
+                    Some((text, _)) => input = text,
+                    None if !history.is_empty() => search = Some((String::new(), history.len().checked_sub(1))),
+                    None => {}
+                },
+                KeyCode::Up if !history.is_empty() => {
+                    let back = recalled.map_or(0, |back| (back + 1).min(history.len() - 1));
+                    input = history[history.len() - 1 - back].clone();
+                    recalled = Some(back);
+                }
+                KeyCode::Down => match recalled {
+                    Some(0) => {
+                        input = initial.to_string();
+                        recalled = None;
+                    }
+                    Some(back) => {
+                        input = history[history.len() - back].clone();
+                        recalled = Some(back - 1);
+                    }
+                    None => {}
+                },
+                KeyCode::Char(c) if !control => input.push(c),
+                _ => {}
+            }
+        };
+
+        // Closed on purpose, so there's nothing to recover
+        draft.discard();
+        if let Some(window) = shown {
+            self.clear_window(&window)?;
+        }
+        if let (Some(text), Some(kind)) = (result.as_deref().filter(|text| !text.trim().is_empty()), kind) {
+            let _ = inputs.add(title, kind, text);
+        }
+        Ok(result)
+    }
+
+    /// Lets `text` be edited over several lines until ESC or `close_key` is
+    /// pressed, and returns it. While the keys rest, changes are handed to
+    /// `save` so that they survive chatshell dying; the last ones are on
+    /// closing.
+    pub fn show_editor(
+        &mut self,
+        title: &str,
+        text: &str,
+        close_key: &str,
+        mut save: impl FnMut(&str) -> Result<()>,
+    ) -> Result<String> {
+        let (cols, rows) = self.terminal_size;
+        let width = cols.min(64);
+        let height = rows.min(EDITOR_ROWS as u16 + 4);
+        let mut window = Window {
+            title: text::truncate(title, width.saturating_sub(4) as usize),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+        let mut area = TextArea::new(text);
+        let mut changed = false;
+        loop {
+            window.content = area.rows(height.saturating_sub(4) as usize, width.saturating_sub(4) as usize);
+            self.draw_window_with_hint(&window, &tr("editor-hint"))?;
+
+            if !terminal::poll_event(AUTOSAVE_INTERVAL)? {
+                if std::mem::take(&mut changed) {
+                    save(&area.text())?;
+                }
+                continue;
+            }
+            let Event::Key(key) = terminal::read_event()? else {
+                continue;
+            };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            if key.code == KeyCode::Esc || KeyInput::from_event(key).matches_pattern(close_key) {
+                break;
+            }
+            changed |= area.key(key.code, key.modifiers);
+        }
+        if changed {
+            save(&area.text())?;
+        }
+        self.clear_window(&window)?;
+        Ok(area.text())
+    }
+
+    /// Draws `popup` as it is now, over what it showed before, with its
+    /// last lines if not all of them fit. Keys go on to the shell.
+    pub fn draw_live_popup(&mut self, popup: &mut LivePopup) -> Result<()> {
+        let (cols, rows) = self.terminal_size;
+        let width = (cols * 3 / 4).max(30).min(cols);
+        let height = (rows / 2).max(6).min(rows);
+        let inner = width.saturating_sub(4) as usize;
+        let shown = popup.lines.len().min(height.saturating_sub(4) as usize);
+        let window = Window {
+            title: text::truncate(&popup.title, inner),
+            content: popup.lines[popup.lines.len() - shown..].iter().map(|line| text::truncate(line, inner)).collect(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+        if let Some(previous) = popup.window.take() {
+            if (previous.x, previous.y, previous.width, previous.height) != (window.x, window.y, window.width, window.height) {
+                self.clear_window(&previous)?;
+            }
+        }
+        self.draw_window_with_hint(&window, &tr("live-popup-hint"))?;
+        popup.window = Some(window);
+        Ok(())
+    }
+
+    /// Takes `popup` off the screen, if it was drawn.
+    pub fn close_live_popup(&mut self, popup: &mut LivePopup) -> Result<()> {
+        match popup.window.take() {
+            Some(window) => self.clear_window(&window),
+            None => Ok(()),
+        }
+    }
+
+    /// Draws the chat view, if it is open: its title, a border on the
+    /// shell's side and the end of the conversation.
+    pub fn draw_chat(&self) -> Result<()> {
+        let Some(layout) = self.chat.layout else {
+            return Ok(());
+        };
+        let mut stdout = stdout();
+        let area = layout.pane;
+        let content = layout.content();
+        let borders = self.style.borders();
+        stdout.queue(cursor::SavePosition)?;
+
+        let mut title = text::truncate(&format!("{} {} ", borders.horizontal, tr("assistant-title")), area.width as usize);
+        title.push_str(&borders.horizontal.repeat((area.width as usize).saturating_sub(text::width(&title))));
+        stdout.queue(cursor::MoveTo(area.x, area.y))?;
+        stdout.queue(Print(title))?;
+
+        let rows = self.chat.rows(content.width as usize, content.height as usize);
+        for row in 0..content.height {
+            if layout.direction == SplitDirection::Vertical {
+                stdout.queue(cursor::MoveTo(area.x, content.y + row))?;
+                stdout.queue(Print(borders.vertical))?;
+            }
+            let (line, question) = rows.get(row as usize).map(|(line, question)| (line.as_str(), *question)).unwrap_or_default();
+            let padding = (content.width as usize).saturating_sub(text::width(line));
+            stdout.queue(cursor::MoveTo(content.x, content.y + row))?;
+            if question && !self.style.plain {
+                stdout.queue(SetAttribute(Attribute::Bold))?;
+            }
+            stdout.queue(Print(format!("{}{}", line, " ".repeat(padding))))?;
+            stdout.queue(SetAttribute(Attribute::Reset))?;
+        }
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Blanks the area of the chat view, if it is open.
+    pub fn clear_chat(&self) -> Result<()> {
+        let Some(layout) = self.chat.layout else {
+            return Ok(());
+        };
+        let mut stdout = stdout();
+        let area = layout.pane;
+        stdout.queue(cursor::SavePosition)?;
+        for row in 0..area.height {
+            stdout.queue(cursor::MoveTo(area.x, area.y + row))?;
+            stdout.queue(Print(" ".repeat(area.width as usize)))?;
+        }
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Draws a small box in the top right corner without waiting for a key,
+    /// for content that stays on screen while the shell is used. Returns the
+    /// window so it can be cleared later.
+    pub fn draw_panel(&self, title: &str, lines: Vec<(String, Option<Color>)>) -> Result<Window> {
+        let window = self.panel_window(title, lines);
+        self.paint_panel(&window, None)?;
+        Ok(window)
+    }
+
+    /// Like `draw_panel`, but when the panel keeps its place and size only
+    /// the lines that differ from `previous` are drawn again.
+    pub fn update_panel(&self, previous: &Window, title: &str, lines: Vec<(String, Option<Color>)>) -> Result<Window> {
+        let window = self.panel_window(title, lines);
+        self.paint_panel(&window, changed_rows(previous, &window).as_deref())?;
+        Ok(window)
+    }
+
+    /// Draws a pinned note like a panel, in `corner`. The bottom row is
+    /// left to the shell's prompt.
+    pub fn draw_note(&self, note: &Note, corner: Corner) -> Result<Window> {
+        let window = self.note_window(note, corner);
+        self.paint_panel(&window, None)?;
+        Ok(window)
+    }
+
+    fn note_window(&self, note: &Note, corner: Corner) -> Window {
+        let mut window = self.panel_window(&note.title, note.lines.clone());
+        if matches!(corner, Corner::BottomLeft | Corner::TopLeft) {
+            window.x = 0;
+        }
+        if matches!(corner, Corner::BottomRight | Corner::BottomLeft) {
+            window.y = self.terminal_size.1.saturating_sub(window.height + 1);
+        }
+        window
+    }
+
+    /// Where a panel with this content goes, and what it shows.
+    fn panel_window(&self, title: &str, lines: Vec<(String, Option<Color>)>) -> Window {
+        let cols = self.terminal_size.0 as usize;
+        let max_width = (cols * 2 / 5).max(20).min(cols);
+        let max_lines = (self.terminal_size.1 as usize / 3).max(3);
+        let inner = max_width.saturating_sub(4);
+        let clip = |line: &str| text::truncate(line, inner);
+
+        let title = clip(title);
+        let (content, colors): (Vec<String>, Vec<Option<Color>>) = lines
+            .into_iter()
+            .take(max_lines)
+            .map(|(line, color)| (clip(&line), color))
+            .unzip();
+        let content_width = content
+            .iter()
+            .chain(std::iter::once(&title))
+            .map(|line| text::width(line))
+            .max()
+            .unwrap_or(0);
+        let width = (content_width + 4) as u16;
+        Window {
+            title,
+            x: self.terminal_size.0.saturating_sub(width),
+            y: 0,
+            width,
+            height: (content.len() + 2) as u16,
+            content,
+            colors,
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+        }
+    }
+
+    /// Draws the panel, or only the given content rows of it.
+    fn paint_panel(&self, window: &Window, rows: Option<&[usize]>) -> Result<()> {
+        let mut buffer = Buffer::empty(area(window));
+        let lines: Vec<Line> = window
+            .content
+            .iter()
+            .enumerate()
+            .map(|(row, line)| Line::styled(line.as_str(), self.line_style(window.colors.get(row).copied().flatten())))
+            .collect();
+        let frame = self.frame().title(format!(" {}", window.title)).padding(Padding::left(1));
+        Paragraph::new(lines).block(frame).render(buffer.area, &mut buffer);
+        // Content rows are below the top border
+        let rows: Option<Vec<u16>> = rows.map(|rows| rows.iter().map(|row| *row as u16 + 1).collect());
+        self.flush(&mut stdout(), &buffer, rows.as_deref())
+    }
+
+    /// Blanks the area of a panel drawn by `draw_panel`.
+    pub fn clear_panel(&self, window: &Window) -> Result<()> {
+        let mut stdout = stdout();
+        stdout.queue(cursor::SavePosition)?;
+        for row in 0..window.height {
+            stdout.queue(cursor::MoveTo(window.x, window.y + row))?;
+            stdout.queue(Print(" ".repeat(window.width as usize)))?;
+        }
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Shows the end of `tail` in an overlay that keeps following it until
+    /// ESC. Space pauses and resumes, arrows and page keys scroll, and `/`
+    /// sets a regex to highlight.
+    pub fn show_tail(&mut self, title: &str, tail: &mut Tail) -> Result<()> {
+        let (cols, rows) = self.terminal_size;
+        let width = (cols * 3 / 4).max(30).min(cols);
+        let height = (rows / 2).max(6).min(rows);
+        let window = Window {
+            title: title.to_string(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+        let view = height.saturating_sub(2) as usize;
+
+        let mut following = true;
+        // Lines between the bottom of the view and the end of the tail
+        let mut scroll = 0usize;
+        let mut highlight: Option<Regex> = None;
+        loop {
+            let added = tail.poll()?;
+            if !following {
+                // Keep a paused view on the same lines
+                scroll += added;
+            }
+            let total = tail.lines().len();
+            scroll = scroll.min(total.saturating_sub(view));
+            let end = total - scroll;
+            let lines: Vec<&String> = tail.lines().range(end.saturating_sub(view)..end).collect();
+            self.draw_tail(&window, &lines, following, highlight.as_ref())?;
+
+            if !terminal::poll_event(Duration::from_millis(200))? {
+                continue;
+            }
+            let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            match code {
+                KeyCode::Esc => break,
+                KeyCode::Char(' ') | KeyCode::Char('p') => {
+                    following = !following;
+                    if following {
+                        scroll = 0;
+                    }
+                }
+                KeyCode::Up | KeyCode::Char('k') => {
+                    following = false;
+                    scroll += 1;
+                }
+                KeyCode::PageUp => {
+                    following = false;
+                    scroll += view;
+                }
+                KeyCode::Down | KeyCode::Char('j') => scroll = scroll.saturating_sub(1),
+                KeyCode::PageDown => scroll = scroll.saturating_sub(view),
+                KeyCode::Char('/') => {
+                    match self.show_input(&tr("highlight-title"), &tr("highlight-prompt"))? {
+                        Some(text) => match Regex::new(&text) {
+                            Ok(pattern) => highlight = Some(pattern),
+                            Err(e) => self.show_popup(&tr("highlight-title"), &e.to_string())?,
+                        },
+                        None => highlight = None,
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        // The cursor is restored after every frame, so only blank the area
+        let mut stdout = stdout();
+        stdout.queue(cursor::SavePosition)?;
+        for row in 0..window.height {
+            stdout.queue(cursor::MoveTo(window.x, window.y + row))?;
+            stdout.queue(Print(" ".repeat(window.width as usize)))?;
+        }
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn draw_tail(&self, window: &Window, lines: &[&String], following: bool, highlight: Option<&Regex>) -> Result<()> {
+        let theme = &self.style.theme;
+        let rows = lines
+            .iter()
+            .map(|line| {
+                let spans = match highlight {
+                    Some(pattern) => highlight_spans(line, pattern),
+                    None => vec![(line.as_str(), false)],
+                };
+                let mut styled = vec![TextSpan::raw(" ")];
+                for (text, matched) in spans {
+                    let mut style = self.paint(theme.text);
+                    if matched {
+                        style = self.paint(theme.highlight);
+                        // Without colors, matches stand out in reverse video
+                        if self.style.monochrome && !self.style.plain {
+                            style = style.add_modifier(Modifier::REVERSED);
+                        }
+                        if self.style.markers {
+                            style = style.add_modifier(Modifier::UNDERLINED);
+                        }
+                    }
+                    styled.push(TextSpan::styled(text, style));
+                }
+                ListItem::new(Line::from(styled))
+            })
+            .collect();
+        let state = if following { tr("tail-following") } else { tr("tail-paused") };
+        let title = format!("[{}] {}", state, window.title);
+        self.draw_frame(window, &title, &tr("tail-hint"), Vec::new(), rows, None)
+    }
+
+    /// Shows recent screens of the shell over the whole terminal, starting
+    /// with the newest. Left and right step back and forth, ESC closes.
+    /// It is drawn on the terminal's alternate screen, which gives the
+    /// shell's screen back untouched, unless a program running in the shell
+    /// has that already; then the area is blanked when it closes.
+    pub fn show_wayback(&mut self, snapshots: &[Snapshot], alternate_screen: bool) -> Result<()> {
+        let Some(last) = snapshots.len().checked_sub(1) else {
+            return self.show_popup(&tr("wayback-title"), &tr("wayback-empty"));
+        };
+        if self.style.plain {
+            return self.notify(&wayback_title(snapshots, last), &snapshots[last].lines);
+        }
+        let (cols, rows) = self.terminal_size;
+        let window = Window {
+            title: String::new(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: 0,
+            y: 0,
+            width: cols,
+            height: rows.max(3),
+        };
+
+        if !alternate_screen {
+            stdout().queue(EnterAlternateScreen)?;
+        }
+        let mut index = last;
+        loop {
+            self.draw_wayback(&window, snapshots, index)?;
+            let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            match code {
+                KeyCode::Esc | KeyCode::Char('q') => break,
+                KeyCode::Left | KeyCode::Up | KeyCode::Char('h') | KeyCode::Char('k') => {
+                    index = index.saturating_sub(1);
+                }
+                KeyCode::Right | KeyCode::Down | KeyCode::Char('l') | KeyCode::Char('j') => {
+                    index = (index + 1).min(last);
+                }
+                KeyCode::Home => index = 0,
+                KeyCode::End => index = last,
+                _ => {}
+            }
+        }
+
+        let mut stdout = stdout();
+        if alternate_screen {
+            stdout.queue(cursor::SavePosition)?;
+            for row in 0..window.height {
+                stdout.queue(cursor::MoveTo(window.x, window.y + row))?;
+                stdout.queue(Print(" ".repeat(window.width as usize)))?;
+            }
+            stdout.queue(cursor::RestorePosition)?;
+        } else {
+            stdout.queue(LeaveAlternateScreen)?;
+        }
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn draw_wayback(&self, window: &Window, snapshots: &[Snapshot], index: usize) -> Result<()> {
+        let view = window.height.saturating_sub(2) as usize;
+        // The bottom of a screen is where the last output was
+        let lines = &snapshots[index].lines;
+        let rows = lines[lines.len().saturating_sub(view)..]
+            .iter()
+            .map(|line| ListItem::new(format!(" {}", line)))
+            .collect();
+        let title = wayback_title(snapshots, index);
+        self.draw_frame(window, &title, &tr("wayback-hint"), Vec::new(), rows, None)
+    }
+
+    /// Browses `lines`, the shell's output, over the whole terminal with a
+    /// cursor on the newest line. `v` starts selecting lines from the
+    /// cursor, `y` copies the selection, or the cursor's line, and `c` asks
+    /// for a comment on it. ESC clears the selection, then closes. Lines
+    /// are numbered from `first_line`, and those in `annotated` are marked.
+    /// Returns the comments made, with the lines they are on.
+    pub fn show_copy_mode(
+        &mut self,
+        lines: &VecDeque<String>,
+        first_line: usize,
+        annotated: &[Range<usize>],
+        alternate_screen: bool,
+    ) -> Result<Vec<(Range<usize>, String)>> {
+        let Some(last) = lines.len().checked_sub(1) else {
+            self.show_popup(&tr("copy-mode-title"), &tr("copy-mode-empty"))?;
+            return Ok(Vec::new());
+        };
+        let (cols, rows) = self.terminal_size;
+        let window = Window {
+            title: String::new(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: 0,
+            y: 0,
+            width: cols,
+            height: rows.max(3),
+        };
+        let view = window.height.saturating_sub(2) as usize;
+
+        if !alternate_screen {
+            stdout().queue(EnterAlternateScreen)?;
+        }
+        let mut comments: Vec<(Range<usize>, String)> = Vec::new();
+        let mut cursor = last;
+        let mut anchor: Option<usize> = None;
+        let mut status: Option<String> = None;
+        loop {
+            let selection = match anchor {
+                Some(anchor) => anchor.min(cursor)..anchor.max(cursor) + 1,
+                None => cursor..cursor + 1,
+            };
+            let marked: Vec<Range<usize>> = annotated
+                .iter()
+                .cloned()
+                .chain(comments.iter().map(|(range, _)| range.clone()))
+                .collect();
+            let frame = CopyFrame { lines, first_line, cursor, selection: selection.clone(), marked: &marked };
+            self.draw_copy_mode(&window, &frame, status.as_deref())?;
+            let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            status = None;
+            match code {
+                KeyCode::Esc if anchor.is_some() => anchor = None,
+                KeyCode::Esc | KeyCode::Char('q') => break,
+                KeyCode::Up | KeyCode::Char('k') => cursor = cursor.saturating_sub(1),
+                KeyCode::Down | KeyCode::Char('j') => cursor = (cursor + 1).min(last),
+                KeyCode::PageUp => cursor = cursor.saturating_sub(view),
+                KeyCode::PageDown => cursor = (cursor + view).min(last),
+                KeyCode::Home | KeyCode::Char('g') => cursor = 0,
+                KeyCode::End | KeyCode::Char('G') => cursor = last,
+                KeyCode::Char('v') | KeyCode::Char('V') => {
+                    anchor = match anchor {
+                        Some(_) => None,
+                        None => Some(cursor),
+                    };
+                }
+                KeyCode::Char('y') => {
+                    let text: Vec<&str> = lines.range(selection.clone()).map(String::as_str).collect();
+                    stdout().queue(Print(clipboard_sequence(&text.join("\n"))))?;
+                    status = Some(tr_args("copy-mode-copied", &[("count", selection.len().into())]));
+                    anchor = None;
+                }
+                KeyCode::Char('c') => {
+                    let label = tr_args("copy-mode-comment-prompt", &[("count", selection.len().into())]);
+                    // Kept as one kind however many lines are commented on
+                    let comment = self.show_input_with(&tr("copy-mode-comment-title"), &label, "", Some("comment"))?;
+                    if let Some(comment) = comment.filter(|comment| !comment.trim().is_empty()) {
+                        let numbered = first_line + selection.start..first_line + selection.end;
+                        comments.push((numbered, comment));
+                        status = Some(tr("copy-mode-commented"));
+                        anchor = None;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let mut stdout = stdout();
+        if alternate_screen {
+            stdout.queue(cursor::SavePosition)?;
+            for row in 0..window.height {
+                stdout.queue(cursor::MoveTo(window.x, window.y + row))?;
+                stdout.queue(Print(" ".repeat(window.width as usize)))?;
+            }
+            stdout.queue(cursor::RestorePosition)?;
+        } else {
+            stdout.queue(LeaveAlternateScreen)?;
+        }
+        stdout.flush()?;
+        Ok(comments)
+    }
+
+    fn draw_copy_mode(&self, window: &Window, frame: &CopyFrame, status: Option<&str>) -> Result<()> {
+        let theme = &self.style.theme;
+        let rows = frame
+            .lines
+            .iter()
+            .enumerate()
+            .map(|(index, line)| {
+                let numbered = frame.first_line + index;
+                let mark = match frame.marked.iter().any(|range| range.contains(&numbered)) {
+                    true if self.style.plain => "*",
+                    true => "▌",
+                    false => " ",
+                };
+                let selected = frame.selection.contains(&index);
+                let mut style = match (index == frame.cursor, selected) {
+                    (true, _) => self.paint(theme.selected),
+                    (false, true) => self.paint(theme.highlight),
+                    (false, false) => self.paint(theme.text),
+                };
+                // Without colors, the selection stands out in reverse video
+                if selected && !self.style.colors() {
+                    style = style.add_modifier(Modifier::REVERSED);
+                }
+                let lead = self.selection_lead(index == frame.cursor);
+                ListItem::new(Line::from(vec![
+                    TextSpan::styled(mark, self.paint(theme.text).patch(self.paint_fg(theme.hint))),
+                    TextSpan::styled(format!("{}{}", lead, line), style),
+                ]))
+            })
+            .collect();
+        let title = tr_args("copy-mode-position", &[
+            ("line", (frame.cursor + 1).into()),
+            ("count", frame.lines.len().into()),
+        ]);
+        let hint = status.map(str::to_string).unwrap_or_else(|| tr("copy-mode-hint"));
+        self.draw_frame(window, &title, &hint, Vec::new(), rows, Some(frame.cursor))
+    }
+
+    /// Asks what to look for in `scrollback`, the shell's output, and lists
+    /// the lines that have it with the matches highlighted, newest
+    /// selected. Enter or `c` copies the selected line, `/` searches again
+    /// and ESC closes.
+    pub fn show_scrollback_search(&mut self, scrollback: &Scrollback) -> Result<()> {
+        let Some(query) = self.show_input(&tr("scrollback-title"), &tr("scrollback-prompt"))? else {
+            return Ok(());
+        };
+        let mut query = query;
+        let (cols, rows) = self.terminal_size;
+        let width = (cols * 9 / 10).max(40).min(cols);
+        let height = (rows * 2 / 3).max(8).min(rows);
+        let window = Window {
+            title: String::new(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+
+        loop {
+            let pattern = match search_pattern(&query) {
+                Ok(pattern) => pattern,
+                Err(e) => return self.show_popup(&tr("scrollback-title"), &e.to_string()),
+            };
+            let lines = scrollback.lines();
+            let found: Vec<&str> = scrollback.matches(&pattern).into_iter().map(|index| lines[index].as_str()).collect();
+            if found.is_empty() {
+                let content = tr_args("scrollback-none", &[("query", query.clone().into())]);
+                return self.show_popup(&tr("scrollback-title"), &content);
+            }
+            if self.style.plain {
+                let found: Vec<String> = found.iter().map(|line| line.to_string()).collect();
+                return self.notify(&tr("scrollback-title"), &found);
+            }
+
+            let view = window.height.saturating_sub(2) as usize;
+            let mut index = found.len() - 1;
+            let mut status: Option<String> = None;
+            let searched = loop {
+                self.draw_matches(&window, &query, &found, &pattern, index, status.as_deref())?;
+                let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                    continue;
+                };
+                status = None;
+                match code {
+                    KeyCode::Esc | KeyCode::Char('q') => break None,
+                    KeyCode::Up | KeyCode::Char('k') => index = index.saturating_sub(1),
+                    KeyCode::Down | KeyCode::Char('j') => index = (index + 1).min(found.len() - 1),
+                    KeyCode::PageUp => index = index.saturating_sub(view),
+                    KeyCode::PageDown => index = (index + view).min(found.len() - 1),
+                    KeyCode::Home => index = 0,
+                    KeyCode::End => index = found.len() - 1,
+                    KeyCode::Enter | KeyCode::Char('c') => {
+                        stdout().queue(Print(clipboard_sequence(found[index])))?;
+                        status = Some(tr("scrollback-copied"));
+                    }
+                    KeyCode::Char('/') => {
+                        self.blank(&window)?;
+                        let prompt = tr("scrollback-prompt");
+                        break self.show_input_with(&tr("scrollback-title"), &prompt, &query, Some(&prompt))?;
+                    }
+                    _ => {}
+                }
+            };
+            self.blank(&window)?;
+            match searched.filter(|text| !text.trim().is_empty()) {
+                Some(text) => query = text,
+                None => return Ok(()),
+            }
+        }
+    }
+
+    fn draw_matches(
+        &self,
+        window: &Window,
+        query: &str,
+        found: &[&str],
+        pattern: &Regex,
+        index: usize,
+        status: Option<&str>,
+    ) -> Result<()> {
+        let theme = &self.style.theme;
+        let rows = found
+            .iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let selected = row == index;
+                let base = self.row_style(selected && self.style.monochrome, selected);
+                let mut styled = vec![TextSpan::styled(self.selection_lead(selected), base)];
+                for (span, matched) in highlight_spans(line, pattern) {
+                    let mut style = base;
+                    if matched {
+                        style = style.patch(self.paint(theme.highlight));
+                        // and matches in underline
+                        if self.style.monochrome || self.style.markers {
+                            style = style.add_modifier(Modifier::UNDERLINED);
+                        }
+                    }
+                    styled.push(TextSpan::styled(span, style));
+                }
+                ListItem::new(Line::from(styled)).style(base)
+            })
+            .collect();
+        let title = tr_args("scrollback-position", &[
+            ("query", query.into()),
+            ("position", (index + 1).into()),
+            ("count", found.len().into()),
+        ]);
+        let hint = status.map(str::to_string).unwrap_or_else(|| tr("scrollback-hint"));
+        self.draw_frame(window, &title, &hint, Vec::new(), rows, Some(index))
+    }
+
+    /// Lists the processes the shell started, or all of the user's with
+    /// Tab, refreshed every second until ESC. Typing filters the list,
+    /// the arrows select a process and ctrl+t, ctrl+k and ctrl+z send it
+    /// SIGTERM, SIGKILL and SIGSTOP. Without a shell, all of the user's
+    /// processes are listed.
+    pub fn show_process_picker(&mut self, shell: Option<i32>) -> Result<()> {
+        let (cols, rows) = self.terminal_size;
+        let width = (cols * 3 / 4).max(40).min(cols);
+        let height = (rows * 2 / 3).max(8).min(rows);
+        let window = Window {
+            title: String::new(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+
+        let mut scope = if shell.is_some() { Scope::Shell } else { Scope::Mine };
+        let mut filter = String::new();
+        // Kept by pid, so the selection stays put as the list changes
+        let mut selected: Option<i32> = None;
+        let mut status: Option<String> = None;
+        loop {
+            let processes = process::list();
+            let listed = match (scope, shell) {
+                (Scope::Shell, Some(shell)) => process::descendants(&processes, shell),
+                _ => process::mine(&processes),
+            };
+            let listed: Vec<(usize, &Process)> = listed
+                .into_iter()
+                .filter(|(_, process)| fuzzy_matches(&filter, &format!("{} {}", process.pid, process.command)))
+                .collect();
+            let index = selected
+                .and_then(|pid| listed.iter().position(|(_, process)| process.pid == pid))
+                .unwrap_or(0);
+            selected = listed.get(index).map(|(_, process)| process.pid);
+            self.draw_process_picker(&window, &listed, index, &filter, scope, status.as_deref())?;
+
+            if !terminal::poll_event(Duration::from_secs(1))? {
+                continue;
+            }
+            let Event::Key(KeyEvent { code, modifiers, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            status = None;
+            let control = modifiers.contains(KeyModifiers::CONTROL);
+            let signal = match code {
+                KeyCode::Char('t') if control => Some(Signal::SIGTERM),
+                KeyCode::Char('k') if control => Some(Signal::SIGKILL),
+                KeyCode::Char('z') if control => Some(Signal::SIGSTOP),
+                _ => None,
+            };
+            if let (Some(signal), Some(pid)) = (signal, selected) {
+                status = Some(match process::send_signal(pid, signal) {
+                    Ok(()) => tr_args("processes-sent", &[
+                        ("signal", signal.as_str().into()),
+                        ("pid", pid.to_string().into()),
+                    ]),
+                    Err(e) => format!("{:#}", e),
+                });
+                continue;
+            }
+            match code {
+                KeyCode::Esc => break,
+                KeyCode::Up => {
+                    if let Some((_, process)) = index.checked_sub(1).and_then(|i| listed.get(i)) {
+                        selected = Some(process.pid);
+                    }
+                }
+                KeyCode::Down => {
+                    if let Some((_, process)) = listed.get(index + 1) {
+                        selected = Some(process.pid);
+                    }
+                }
+                KeyCode::Tab if shell.is_some() => {
+                    scope = match scope {
+                        Scope::Shell => Scope::Mine,
+                        Scope::Mine => Scope::Shell,
+                    };
+                }
+                KeyCode::Backspace => {
+                    filter.pop();
+                }
+                KeyCode::Char('u') if control => filter.clear(),
+                KeyCode::Char(c) if !control => filter.push(c),
+                _ => {}
+            }
+        }
+
+        self.blank(&window)
+    }
+
+    fn draw_process_picker(
+        &self,
+        window: &Window,
+        listed: &[(usize, &Process)],
+        index: usize,
+        filter: &str,
+        scope: Scope,
+        status: Option<&str>,
+    ) -> Result<()> {
+        let mut header = vec![
+            format!("> {}_", filter),
+            format!("{:>7} S {}", "PID", tr("processes-command")),
+        ];
+        if listed.is_empty() {
+            header.push(tr("processes-none"));
+        }
+        let rows: Vec<String> = listed
+            .iter()
+            .map(|(depth, process)| {
+                format!("{:>7} {} {}{}", process.pid, process.state, "  ".repeat(*depth), process.command)
+            })
+            .collect();
+
+        let count = [("count", listed.len().into())];
+        let scope = match scope {
+            Scope::Shell => tr_args("processes-shell", &count),
+            Scope::Mine => tr_args("processes-mine", &count),
+        };
+        let title = format!("{} · {}", tr("processes-title"), scope);
+        let hint = status.map(str::to_string).unwrap_or_else(|| tr("processes-hint"));
+        self.draw_list(window, &title, &header, &rows, index, &hint)
+    }
+
+    /// Lists every hook with what runs it and whether it is on. Space or
+    /// Enter turns the selected one on or off and passes it to `save`; ESC
+    /// closes.
+    pub fn show_hook_manager(
+        &mut self,
+        hooks: &mut [HookConfig],
+        save: &mut dyn FnMut(&HookConfig) -> Result<()>,
+    ) -> Result<()> {
+        let (cols, rows) = self.terminal_size;
+        let width = (cols * 3 / 4).max(40).min(cols);
+        let height = (rows * 2 / 3).max(8).min(rows);
+        let window = Window {
+            title: String::new(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+
+        let mut index = 0;
+        let mut status: Option<String> = None;
+        loop {
+            self.draw_hook_manager(&window, hooks, index, status.as_deref())?;
+            let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            status = None;
+            match code {
+                KeyCode::Esc => break,
+                KeyCode::Up => index = index.saturating_sub(1),
+                KeyCode::Down => index = (index + 1).min(hooks.len().saturating_sub(1)),
+                KeyCode::Char(' ') | KeyCode::Enter => {
+                    let Some(hook) = hooks.get_mut(index) else {
+                        continue;
+                    };
+                    hook.enabled = !hook.enabled;
+                    if let Err(e) = save(hook) {
+                        // Left as it is saved, so the list doesn't lie
+                        hook.enabled = !hook.enabled;
+                        status = Some(format!("{:#}", e));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.blank(&window)
+    }
+
+    fn draw_hook_manager(&self, window: &Window, hooks: &[HookConfig], index: usize, status: Option<&str>) -> Result<()> {
+        let header = vec![format!("    {:<20} {:<24} {}", tr("hook-manager-trigger"), tr("hook-manager-name"), tr("hook-manager-action"))];
+        let rows: Vec<String> = hooks
+            .iter()
+            .map(|hook| {
+                let trigger = match hook.event {
+                    Some(event) => event.name(),
+                    None => hook.key_combination.as_str(),
+                };
+                let mark = if hook.enabled { "x" } else { " " };
+                format!("[{}] {:<20} {:<24} {}", mark, trigger, hook.name, hook.action)
+            })
+            .collect();
+        let count = [
+            ("enabled", hooks.iter().filter(|hook| hook.enabled).count().into()),
+            ("count", hooks.len().into()),
+        ];
+        let title = format!("{} · {}", tr("hook-manager-title"), tr_args("hook-manager-count", &count));
+        let hint = status.map(str::to_string).unwrap_or_else(|| tr("hook-manager-hint"));
+        self.draw_list(window, &title, &header, &rows, index, &hint)
+    }
+
+    /// Lists `entries`, each a name and the keys bound to it, for picking
+    /// one. Typing filters the list and the arrows select; returns the
+    /// index of the entry picked with Enter, or None after ESC.
+    pub fn show_palette(&mut self, entries: &[(String, String)]) -> Result<Option<usize>> {
+        let (cols, rows) = self.terminal_size;
+        let width = (cols * 2 / 3).max(40).min(cols);
+        let height = (rows / 2).max(8).min(rows);
+        let window = Window {
+            title: String::new(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+
+        let texts: Vec<String> = entries.iter().map(|(name, keys)| format!("{} {}", name, keys)).collect();
+        let mut picker = Picker { digits: false, ..Picker::default() };
+        let choice = loop {
+            let listed = picker.listed(&texts);
+            self.draw_palette(&window, entries, &listed, picker.index, &picker.filter)?;
+
+            let Event::Key(KeyEvent { code, modifiers, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            if let Some(choice) = picker.key(code, modifiers, &listed) {
+                break choice;
+            }
+        };
+
+        self.blank(&window)?;
+        Ok(choice)
+    }
+
+    fn draw_palette(
+        &self,
+        window: &Window,
+        entries: &[(String, String)],
+        listed: &[usize],
+        index: usize,
+        filter: &str,
+    ) -> Result<()> {
+        let mut header = vec![format!("> {}_", filter)];
+        if listed.is_empty() {
+            header.push(tr("palette-none"));
+        }
+        // Names on the left, keys lined up on the right
+        let inner = (window.width as usize).saturating_sub(4);
+        let rows: Vec<String> = listed
+            .iter()
+            .map(|&i| {
+                let (name, keys) = &entries[i];
+                let room = inner.saturating_sub(keys.chars().count() + 1);
+                let name: String = name.chars().take(room).collect();
+                format!("{:<room$} {}", name, keys, room = room)
+            })
+            .collect();
+        self.draw_list(window, &tr("palette-title"), &header, &rows, index, &tr("palette-hint"))
+    }
+
+    /// Lists the listening sockets and connections of the shell and the
+    /// processes it started, refreshed every second until ESC. `c` copies
+    /// the selected one's address, where it listens or what it talks to,
+    /// and `k` sends the process holding it SIGTERM, `K` SIGKILL. Without a
+    /// shell, the sockets of all of the user's processes are listed.
+    pub fn show_ports(&mut self, shell: Option<i32>) -> Result<()> {
+        let (cols, rows) = self.terminal_size;
+        // Addresses take room; leave only a margin
+        let width = (cols * 9 / 10).max(40).min(cols);
+        let height = (rows * 2 / 3).max(8).min(rows);
+        let window = Window {
+            title: String::new(),
+            content: Vec::new(),
+            colors: Vec::new(),
+            spans: Vec::new(),
+            emphasis: Vec::new(),
+            x: (cols - width) / 2,
+            y: self.place_vertically(height),
+            width,
+            height,
+        };
+
+        // Kept by inode, so the selection stays put as the list changes
+        let mut selected: Option<u64> = None;
+        let mut status: Option<String> = None;
+        loop {
+            let processes = process::list();
+            let owners: Vec<&Process> = match shell {
+                Some(shell) => processes
+                    .iter()
+                    .filter(|process| process.pid == shell)
+                    .chain(process::descendants(&processes, shell).into_iter().map(|(_, process)| process))
+                    .collect(),
+                None => process::mine(&processes).into_iter().map(|(_, process)| process).collect(),
+            };
+            let listed = ports::ports(&owners);
+            let index = selected
+                .and_then(|inode| listed.iter().position(|port| port.socket.inode == inode))
+                .unwrap_or(0);
+            selected = listed.get(index).map(|port| port.socket.inode);
+            self.draw_ports(&window, &listed, index, status.as_deref())?;
+
+            if !terminal::poll_event(Duration::from_secs(1))? {
+                continue;
+            }
+            let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? else {
+                continue;
+            };
+            status = None;
+            match code {
+                KeyCode::Esc => break,
+                KeyCode::Up => {
+                    if let Some(port) = index.checked_sub(1).and_then(|i| listed.get(i)) {
+                        selected = Some(port.socket.inode);
+                    }
+                }
+                KeyCode::Down => {
+                    if let Some(port) = listed.get(index + 1) {
+                        selected = Some(port.socket.inode);
+                    }
+                }
+                KeyCode::Char('c') => {
+                    if let Some(port) = listed.get(index) {
+                        let address = port.socket.address().to_string();
+                        stdout().queue(Print(clipboard_sequence(&address)))?;
+                        status = Some(tr_args("ports-copied", &[("address", address.into())]));
+                    }
+                }
+                KeyCode::Char(key @ ('k' | 'K')) => {
+                    if let Some(port) = listed.get(index) {
+                        let signal = if key == 'K' { Signal::SIGKILL } else { Signal::SIGTERM };
+                        status = Some(match process::send_signal(port.pid, signal) {
+                            Ok(()) => tr_args("processes-sent", &[
+                                ("signal", signal.as_str().into()),
+                                ("pid", port.pid.to_string().into()),
+                            ]),
+                            Err(e) => format!("{:#}", e),
+                        });
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        self.blank(&window)
+    }
+
+    fn draw_ports(&self, window: &Window, listed: &[Port], index: usize, status: Option<&str>) -> Result<()> {
+        let mut header = vec![format!(
+            "{:<4} {:<21} {:<21} {:>7} {}",
+            "",
+            tr("ports-local"),
+            tr("ports-remote"),
+            "PID",
+            tr("processes-command")
+        )];
+        if listed.is_empty() {
+            header.push(tr("ports-none"));
+        }
+        let rows: Vec<String> = listed
+            .iter()
+            .map(|port| {
+                let remote = if port.socket.listening {
+                    tr("ports-listening")
+                } else {
+                    port.socket.remote.to_string()
+                };
+                format!(
+                    "{:<4} {:<21} {:<21} {:>7} {}",
+                    port.socket.protocol,
+                    port.socket.local.to_string(),
+                    remote,
+                    port.pid,
+                    port.command
+                )
+            })
+            .collect();
+
+        let listening = listed.iter().filter(|port| port.socket.listening).count();
+        let count = tr_args("ports-count", &[
+            ("listening", listening.into()),
+            ("connected", (listed.len() - listening).into()),
+        ]);
+        let title = format!("{} · {}", tr("ports-title"), count);
+        let hint = status.map(str::to_string).unwrap_or_else(|| tr("ports-hint"));
+        self.draw_list(window, &title, &header, &rows, index, &hint)
+    }
+
+    /// Draws `header` and then as many of `rows` as fit, scrolled to keep
+    /// the selected one at `index` in view, with `hint` in the bottom
+    /// border.
+    fn draw_list(
+        &self,
+        window: &Window,
+        title: &str,
+        header: &[String],
+        rows: &[String],
+        index: usize,
+        hint: &str,
+    ) -> Result<()> {
+        let header = header.iter().map(|line| Line::from(format!(" {}", line))).collect();
+        let rows = rows
+            .iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let selected = row == index;
+                // Without colors, the selection stands out in reverse video
+                let reverse = selected && self.style.monochrome && !self.style.plain;
+                let lead = self.selection_lead(selected);
+                ListItem::new(format!("{}{}", lead, line)).style(self.row_style(reverse, selected))
+            })
+            .collect();
+        self.draw_frame(window, title, hint, header, rows, Some(index))
+    }
+
+    /// Draws an overlay framed with `title` in its top border and `hint` in
+    /// its bottom one: the `header` lines, then as many of `rows` as fit,
+    /// scrolled to keep `selected` in view.
+    fn draw_frame(
+        &self,
+        window: &Window,
+        title: &str,
+        hint: &str,
+        header: Vec<Line>,
+        rows: Vec<ListItem>,
+        selected: Option<usize>,
+    ) -> Result<()> {
+        let mut buffer = Buffer::empty(area(window));
+        let frame = self.frame().title(format!(" {} ", title)).title_bottom(format!(" {} ", hint));
+        let inner = frame.inner(buffer.area);
+        frame.render(buffer.area, &mut buffer);
+
+        let [header_area, rows_area] = Layout::vertical([Constraint::Length(header.len() as u16), Constraint::Min(0)]).areas(inner);
+        Paragraph::new(header).render(header_area, &mut buffer);
+        let mut state = ListState::default().with_selected(selected);
+        StatefulWidget::render(List::new(rows), rows_area, &mut buffer, &mut state);
+        self.flush(&mut stdout(), &buffer, None)
+    }
+
+    /// Blanks an overlay that restores the cursor after every frame.
+    fn blank(&self, window: &Window) -> Result<()> {
+        let mut stdout = stdout();
+        stdout.queue(cursor::SavePosition)?;
+        for row in 0..window.height {
+            stdout.queue(cursor::MoveTo(window.x, window.y + row))?;
+            stdout.queue(Print(" ".repeat(window.width as usize)))?;
+        }
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    fn layout_window(&self, title: &str, lines: Vec<String>) -> Window {
+        self.layout_colored_window(title, lines, Vec::new())
+    }
+
+    /// Lays out a popup no wider than the screen, wrapping lines that
+    /// don't fit. A wrapped line keeps its color on every row.
+    fn layout_colored_window(&self, title: &str, lines: Vec<String>, colors: Vec<Option<Color>>) -> Window {
+        self.layout_styled_window(title, lines, colors, Vec::new(), Vec::new())
+    }
+
+    /// Like `layout_colored_window`, with spans of color within lines and
+    /// bold or italic stretches of them. Lines with spans are broken where
+    /// they run out of room rather than between words, as they are mostly
+    /// code.
+    fn layout_styled_window(
+        &self,
+        title: &str,
+        lines: Vec<String>,
+        colors: Vec<Option<Color>>,
+        spans: Vec<Vec<Span>>,
+        emphasis: Vec<Vec<Emphasis>>,
+    ) -> Window {
+        // Borders and padding take 4 columns
+        let max_inner = (self.terminal_size.0 as usize).saturating_sub(4).max(1);
+        let title = text::truncate(title, max_inner);
+        let mut content = Vec::with_capacity(lines.len());
+        let mut wrapped_colors = Vec::with_capacity(lines.len());
+        let mut wrapped_spans = Vec::with_capacity(lines.len());
+        let mut wrapped_emphasis = Vec::with_capacity(lines.len());
+        for (index, line) in lines.iter().enumerate() {
+            let color = colors.get(index).copied().flatten();
+            let line_spans = spans.get(index).filter(|spans| !spans.is_empty());
+            let line_emphasis = emphasis.get(index).map_or(&[][..], Vec::as_slice);
+            let rows = match line_spans {
+                Some(_) => text::split_at_width(line, max_inner),
+                None => text::wrap_ranges(line, max_inner),
+            };
+            for row in rows {
+                wrapped_spans.push(line_spans.map_or_else(Vec::new, |spans| clip(spans, &row)));
+                wrapped_emphasis.push(clip(line_emphasis, &row));
+                content.push(line[row].to_string());
+                wrapped_colors.push(color);
+            }
+        }
+        let content_width = content.iter().map(|line| text::width(line)).max().unwrap_or(0);
+        
+        let window_width = (content_width.max(text::width(&title)) + 4) as u16;
+        let window_height = (content.len() + 4) as u16; // Content + borders + padding
+        
+        // Center the window
+        let x = (self.terminal_size.0.saturating_sub(window_width)) / 2;
+        let y = self.place_vertically(window_height);
+        
+        Window {
+            title,
+            content,
+            colors: wrapped_colors,
+            spans: wrapped_spans,
+            emphasis: wrapped_emphasis,
+            x,
+            y,
+            width: window_width,
+            height: window_height,
+        }
+    }
+
+    /// The top row for a window `height` rows tall: centered, or as close
+    /// to the center as it can be without covering an image.
+    fn place_vertically(&self, height: u16) -> u16 {
+        let centered = self.terminal_size.1.saturating_sub(height) / 2;
+        let last = self.terminal_size.1.saturating_sub(height);
+        let clear = |y: u16| {
+            self.avoid_rows.iter().all(|(top, bottom)| y > *bottom || y + height <= *top)
+        };
+        (0..=last)
+            .filter(|y| clear(*y))
+            .min_by_key(|y| y.abs_diff(centered))
+            .unwrap_or(centered)
+    }
+
+    fn draw_window(&self, window: &Window) -> Result<()> {
+        self.draw_window_with_hint(window, &tr("popup-close"))
+    }
+
+    /// Like `draw_window`, with `hint` in the bottom border.
+    fn draw_window_with_hint(&self, window: &Window, hint: &str) -> Result<()> {
+        self.write_window(&mut stdout(), window, hint)
+    }
+
+    /// Draws a popup of `content` into `out` as `show_popup` would, without
+    /// waiting for a key, for benchmarks and tests.
+    pub fn render_popup(&self, out: &mut impl Write, title: &str, content: &str) -> Result<()> {
+        let window = self.layout_window(title, content.lines().map(str::to_string).collect());
+        self.write_window(out, &window, &tr("popup-close-pin"))
+    }
+
+    fn write_window(&self, stdout: &mut impl Write, window: &Window, hint: &str) -> Result<()> {
+        self.flush(stdout, &self.window_buffer(window, hint), None)
+    }
+
+    /// The cells of a popup: its title boxed above its content, and `hint`
+    /// at the right of the bottom border.
+    fn window_buffer(&self, window: &Window, hint: &str) -> Buffer {
+        let theme = &self.style.theme;
+        let borders = self.style.borders();
+        let mut buffer = Buffer::empty(area(window));
+
+        // The title has a box of its own, which the content's box joins
+        let [title_area, _] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(buffer.area);
+        let title_box = Block::bordered().border_set(self.style.border_set()).style(self.paint(theme.border));
+        Paragraph::new(window.title.as_str()).alignment(Alignment::Center).block(title_box).render(title_area, &mut buffer);
+
+        let lines: Vec<Line> = window
+            .content
+            .iter()
+            .enumerate()
+            .map(|(row, line)| {
+                let style = self.line_style(window.colors.get(row).copied().flatten());
+                match (window.spans.get(row).filter(|spans| !spans.is_empty()), window.emphasis.get(row).filter(|emphasis| !emphasis.is_empty())) {
+                    (Some(spans), _) => self.span_line(line, spans, style),
+                    (None, Some(emphasis)) => emphasis_line(line, emphasis, style),
+                    (None, None) => Line::styled(line.as_str(), style),
+                }
+            })
+            .collect();
+        let joined = border::Set { top_left: borders.left_tee, top_right: borders.right_tee, ..self.style.border_set() };
+        let hint = Line::from(vec![
+            TextSpan::styled(hint, self.paint(theme.border).patch(self.paint_fg(theme.hint))),
+            TextSpan::raw(borders.horizontal),
+        ]);
+        let content_box = self.frame().border_set(joined).padding(Padding::horizontal(1)).title_bottom(hint.right_aligned());
+        let content_area = Rect { y: buffer.area.y + 2, height: buffer.area.height.saturating_sub(2), ..buffer.area };
+        Paragraph::new(lines).block(content_box).render(content_area, &mut buffer);
+        buffer
+    }
+
+    /// A frame in the border colors around the text colors.
+    fn frame(&self) -> Block<'static> {
+        let theme = &self.style.theme;
+        Block::bordered()
+            .border_set(self.style.border_set())
+            .border_style(self.paint(theme.border))
+            .title_style(self.paint(theme.border))
+            .style(self.paint(theme.text))
+    }
+
+    /// Writes the cells of `buffer`, or only its `rows`, over the screen
+    /// and puts the cursor back.
+    fn flush(&self, stdout: &mut impl Write, buffer: &Buffer, rows: Option<&[u16]>) -> Result<()> {
+        stdout.queue(cursor::SavePosition)?;
+        // Cells under the right half of wide characters are left alone
+        let mut covered = 0;
+        let cells = buffer.content.iter().enumerate().filter_map(|(index, cell)| {
+            if covered > 0 {
+                covered -= 1;
+                return None;
+            }
+            covered = text::width(cell.symbol()).saturating_sub(1);
+            let (x, y) = buffer.pos_of(index);
+            let shown = rows.is_none_or(|rows| rows.contains(&(y - buffer.area.y)));
+            shown.then_some((x, y, cell))
+        });
+        CrosstermBackend::new(&mut *stdout).draw(cells)?;
+        stdout.queue(cursor::RestorePosition)?;
+        stdout.flush()?;
+        Ok(())
+    }
+
+    /// Waits for ESC, or `p` to pin the popup; true if it was pinned.
+    fn wait_for_close_or_pin(&self) -> Result<bool> {
+        loop {
+            if let Event::Key(KeyEvent { code, .. }) = terminal::read_event()? {
+                match code {
+                    KeyCode::Esc => return Ok(false),
+                    KeyCode::Char('p') => return Ok(true),
+                    _ => {}
+                }
+            }
+        }
+    }
+
+    fn clear_window(&mut self, window: &Window) -> Result<()> {
+        let mut stdout = stdout();
+        self.write_uncovered(&mut stdout, window)?;
+        stdout.flush()?;
+        self.popup_closed = true;
+        Ok(())
+    }
+
+    /// Puts back the text that was under `window`, as far as the screen
+    /// rows say, and the cursor where it was.
+    fn write_uncovered(&self, stdout: &mut impl Write, window: &Window) -> Result<()> {
+        stdout.queue(ResetColor)?;
+        for row in 0..window.height {
+            let y = window.y + row;
+            stdout.queue(cursor::MoveTo(window.x, y))?;
+            stdout.queue(Clear(ClearType::UntilNewLine))?;
+            let text: String = self
+                .screen_rows
+                .get(y as usize)
+                .map(|line| line.chars().skip(window.x as usize).collect())
+                .unwrap_or_default();
+            if !text.is_empty() {
+                stdout.queue(Print(text))?;
+            }
+        }
+        stdout.queue(cursor::RestorePosition)?;
+        Ok(())
+    }
+}
+
+impl Default for WindowManager {
+    fn default() -> Self {
+        WindowManager::new().unwrap_or(WindowManager {
+            terminal_size: (80, 24),
+            avoid_rows: Vec::new(),
+            style: Style::default(),
+            cursor_line: String::new(),
+            closed: VecDeque::new(),
+            pinned: Vec::new(),
+            highlighter: None,
+            screen_rows: Vec::new(),
+            popup_closed: false,
+            input_history: None,
+            chat: ChatView::default(),
+            clock: Arc::new(SystemClock),
+        })
+    }
+}
+
+/// Which of the closed popups is shown, what it was and when it closed.
+fn reopen_title(closed: &VecDeque<ClosedPopup>, index: usize) -> String {
+    let popup = &closed[index];
+    tr_args("reopen-position", &[
+        ("position", (index + 1).into()),
+        ("count", closed.len().into()),
+        ("time", popup.closed.format("%H:%M:%S").to_string().into()),
+        ("title", popup.title.as_str().into()),
+    ])
+}
+
+/// Which of the snapshots is shown and when it was taken.
+fn wayback_title(snapshots: &[Snapshot], index: usize) -> String {
+    let snapshot = &snapshots[index];
+    let mut title = tr_args("wayback-position", &[
+        ("position", (index + 1).into()),
+        ("count", snapshots.len().into()),
+        ("time", snapshot.taken.format("%H:%M:%S").to_string().into()),
+    ]);
+    if snapshot.alternate {
+        title.push_str(&format!(" · {}", tr("wayback-full-screen")));
+    }
+    title
+}
+
+/// What was typed to filter a list picked from, and which of the items
+/// it leaves is selected.
+#[derive(Debug)]
+struct Picker {
+    filter: String,
+    index: usize,
+    // Whether digit keys pick the first nine items until something is
+    // typed, as menus number them
+    digits: bool,
+}
+
+impl Default for Picker {
+    fn default() -> Self {
+        Picker { filter: String::new(), index: 0, digits: true }
+    }
+}
+
+impl Picker {
+    /// The indexes of the `items` the filter matches, those with it as
+    /// typed before scattered matches, keeping the selection among them.
+    fn listed(&mut self, items: &[String]) -> Vec<usize> {
+        let filter = self.filter.to_lowercase();
+        let mut listed: Vec<usize> = (0..items.len()).filter(|&i| fuzzy_matches(&filter, &items[i])).collect();
+        listed.sort_by_key(|&i| !items[i].to_lowercase().contains(&filter));
+        self.index = self.index.min(listed.len().saturating_sub(1));
+        listed
+    }
+
+    /// Takes a key. Returns the item picked once one is, or Some(None) once
+    /// the list is closed with ESC.
+    fn key(&mut self, code: KeyCode, modifiers: KeyModifiers, listed: &[usize]) -> Option<Option<usize>> {
+        let control = modifiers.contains(KeyModifiers::CONTROL);
+        match code {
+            KeyCode::Esc => return Some(None),
+            KeyCode::Enter => return listed.get(self.index).map(|&item| Some(item)),
+            KeyCode::Up => self.index = self.index.saturating_sub(1),
+            KeyCode::Down => self.index = (self.index + 1).min(listed.len().saturating_sub(1)),
+            KeyCode::Home => self.index = 0,
+            KeyCode::End => self.index = listed.len().saturating_sub(1),
+            KeyCode::Backspace => {
+                self.filter.pop();
+                self.index = 0;
+            }
+            KeyCode::Char('u') if control => {
+                self.filter.clear();
+                self.index = 0;
+            }
+            KeyCode::Char(c @ '1'..='9') if self.digits && self.filter.is_empty() => {
+                let row = c as usize - '1' as usize;
+                return listed.get(row).map(|&item| Some(item));
+            }
+            KeyCode::Char(c) if !control => {
+                self.filter.push(c);
+                self.index = 0;
+            }
+            _ => {}
+        }
+        None
+    }
+}
+
+/// How many rows of text the editor shows at most.
+const EDITOR_ROWS: usize = 12;
+
+/// Text edited over several lines, with the cursor at a row and a column
+/// counted in characters.
+#[derive(Debug)]
+struct TextArea {
+    lines: Vec<String>,
+    row: usize,
+    col: usize,
+    // The first row shown
+    top: usize,
+}
+
+impl TextArea {
+    /// Starts with the cursor at the end of `text`.
+    fn new(text: &str) -> Self {
+        let lines: Vec<String> = text.split('\n').map(str::to_string).collect();
+        let row = lines.len() - 1;
+        let col = lines[row].chars().count();
+        TextArea { lines, row, col, top: 0 }
+    }
+
+    fn text(&self) -> String {
+        self.lines.join("\n")
+    }
+
+    /// Where in the cursor's line the character at `col` starts.
+    fn offset(&self, col: usize) -> usize {
+        let line = &self.lines[self.row];
+        line.char_indices().nth(col).map_or(line.len(), |(i, _)| i)
+    }
+
+    fn line_len(&self) -> usize {
+        self.lines[self.row].chars().count()
+    }
+
+    /// Takes a key. Returns true if the text changed.
+    fn key(&mut self, code: KeyCode, modifiers: KeyModifiers) -> bool {
+        let control = modifiers.contains(KeyModifiers::CONTROL);
+        match code {
+            KeyCode::Char(c) if !control => {
+                let at = self.offset(self.col);
+                self.lines[self.row].insert(at, c);
+                self.col += 1;
+                return true;
+            }
+            KeyCode::Enter => {
+                let at = self.offset(self.col);
+                let rest = self.lines[self.row].split_off(at);
+                self.lines.insert(self.row + 1, rest);
+                self.row += 1;
+                self.col = 0;
+                return true;
+            }
+            KeyCode::Backspace if self.col > 0 => {
+                self.col -= 1;
+                let at = self.offset(self.col);
+                self.lines[self.row].remove(at);
+                return true;
+            }
+            KeyCode::Backspace if self.row > 0 => {
+                let line = self.lines.remove(self.row);
+                self.row -= 1;
+                self.col = self.line_len();
+                self.lines[self.row].push_str(&line);
+                return true;
+            }
+            KeyCode::Delete if self.col < self.line_len() => {
+                let at = self.offset(self.col);
+                self.lines[self.row].remove(at);
+                return true;
+            }
+            KeyCode::Delete if self.row + 1 < self.lines.len() => {
+                let line = self.lines.remove(self.row + 1);
+                self.lines[self.row].push_str(&line);
+                return true;
+            }
+            KeyCode::Left if self.col > 0 => self.col -= 1,
+            KeyCode::Left if self.row > 0 => {
+                self.row -= 1;
+                self.col = self.line_len();
+            }
+            KeyCode::Right if self.col < self.line_len() => self.col += 1,
+            KeyCode::Right if self.row + 1 < self.lines.len() => {
+                self.row += 1;
+                self.col = 0;
+            }
+            KeyCode::Up if self.row > 0 => {
+                self.row -= 1;
+                self.col = self.col.min(self.line_len());
+            }
+            KeyCode::Down if self.row + 1 < self.lines.len() => {
+                self.row += 1;
+                self.col = self.col.min(self.line_len());
+            }
+            KeyCode::Home => self.col = 0,
+            KeyCode::End => self.col = self.line_len(),
+            _ => {}
+        }
+        false
+    }
+
+    /// The `height` rows to show, `width` columns each, scrolled to keep
+    /// the cursor in view. The cursor is drawn as `_` and its line shows
+    /// its end if it doesn't fit.
+    fn rows(&mut self, height: usize, width: usize) -> Vec<String> {
+        let height = height.max(1);
+        self.top = self.top.min(self.row).max((self.row + 1).saturating_sub(height));
+        (self.top..self.lines.len().min(self.top + height))
+            .map(|row| match row == self.row {
+                true => {
+                    let chars: Vec<char> = self.lines[row].chars().collect();
+                    let start = self.col.saturating_sub(width.saturating_sub(1));
+                    let line: String = chars[start..self.col].iter().chain(['_'].iter()).chain(chars[self.col..].iter()).collect();
+                    text::truncate(&line, width)
+                }
+                false => text::truncate(&self.lines[row], width),
+            })
+            .collect()
+    }
+}
+
+/// The yes and no of a question, the selected one in brackets.
+fn confirm_buttons(yes: bool) -> String {
+    let (yes_label, no_label) = (tr("confirm-yes"), tr("confirm-no"));
+    match yes {
+        true => format!("[ {} ]   {}  ", yes_label, no_label),
+        false => format!("  {}   [ {} ]", yes_label, no_label),
+    }
+}
+
+/// Takes a key in a yes/no question: the answer once there is one, or
+/// None after moving the selection if the key asked to.
+fn confirm_key(code: KeyCode, yes: &mut bool) -> Option<bool> {
+    match code {
+        KeyCode::Char('y' | 'Y') => Some(true),
+        KeyCode::Char('n' | 'N') | KeyCode::Esc => Some(false),
+        KeyCode::Enter => Some(*yes),
+        KeyCode::Left | KeyCode::Right | KeyCode::Tab | KeyCode::BackTab => {
+            *yes = !*yes;
+            None
+        }
+        _ => None,
+    }
+}
+
+/// The parts of `ranges` that fall in `row` of a line, counted from the
+/// start of the row.
+fn clip<T: Copy>(ranges: &[(Range<usize>, T)], row: &Range<usize>) -> Vec<(Range<usize>, T)> {
+    ranges
+        .iter()
+        .filter(|(range, _)| range.start < row.end && range.end > row.start)
+        .map(|(range, value)| (range.start.max(row.start) - row.start..range.end.min(row.end) - row.start, *value))
+        .collect()
+}
+
+/// `line` in `style`, with the bold and italic stretches of `emphasis`.
+fn emphasis_line<'a>(line: &'a str, emphasis: &[Emphasis], style: CellStyle) -> Line<'a> {
+    let mut styled = Vec::with_capacity(emphasis.len() * 2 + 1);
+    let mut at = 0;
+    for (range, modifier) in emphasis {
+        if range.start > at {
+            styled.push(TextSpan::styled(&line[at..range.start], style));
+        }
+        styled.push(TextSpan::styled(&line[range.clone()], style.add_modifier(*modifier)));
+        at = range.end;
+    }
+    styled.push(TextSpan::styled(&line[at..], style));
+    Line::from(styled)
+}
+
+/// Where `window` is on the screen.
+fn area(window: &Window) -> Rect {
+    Rect::new(window.x, window.y, window.width, window.height)
+}
+
+/// The content rows of `next` that differ from `previous`, or None if the
+/// panel moved, changed size or got a new title and must be drawn whole.
+fn changed_rows(previous: &Window, next: &Window) -> Option<Vec<usize>> {
+    let same_frame = (previous.x, previous.y, previous.width, previous.height) == (next.x, next.y, next.width, next.height)
+        && previous.title == next.title;
+    if !same_frame {
+        return None;
+    }
+    let rows = (0..next.content.len())
+        .filter(|&row| {
+            previous.content.get(row) != next.content.get(row)
+                || previous.colors.get(row) != next.colors.get(row)
+        })
+        .collect();
+    Some(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn test_manager() -> WindowManager {
+        WindowManager {
+            terminal_size: (80, 24),
+            avoid_rows: Vec::new(),
+            style: Style::default(),
+            cursor_line: String::new(),
+            closed: VecDeque::new(),
+            pinned: Vec::new(),
+            highlighter: None,
+            screen_rows: Vec::new(),
+            popup_closed: false,
+            input_history: None,
+            chat: ChatView::default(),
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    #[test]
+    fn test_windows_avoid_images() {
+        let mut manager = WindowManager { avoid_rows: vec![(8, 14)], ..test_manager() };
+        assert_eq!(manager.place_vertically(6), 15);
+        manager.avoid_rows = vec![(0, 23)];
+        assert_eq!(manager.place_vertically(6), 9);
+    }
+
+    #[test]
+    fn test_styles() {
+        let monochrome = Style { monochrome: true, ..Style::default() };
+        assert!(Style::default().colors());
+        assert!(!monochrome.colors());
+        assert_eq!(monochrome.borders().vertical, "│");
+
+        let plain = Style { plain: true, ..Style::default() };
+        assert!(!plain.colors());
+        assert_eq!(plain.borders().vertical, "|");
+        let b = plain.borders();
+        let all = [b.top_left, b.top_right, b.bottom_left, b.bottom_right, b.horizontal, b.vertical, b.left_tee, b.right_tee];
+        assert!(all.iter().all(|part| part.is_ascii()));
+    }
+
+    #[test]
+    fn test_panel_updates_only_changed_rows() {
+        let manager = test_manager();
+        let lines = |texts: &[&str]| texts.iter().map(|text| (text.to_string(), None)).collect::<Vec<_>>();
+        let previous = manager.panel_window("every 2s: df", lines(&["/ 40%", "/home 71%"]));
+
+        let next = manager.panel_window("every 2s: df", lines(&["/ 40%", "/home 72%"]));
+        assert_eq!(changed_rows(&previous, &next), Some(vec![1]));
+        let same = manager.panel_window("every 2s: df", lines(&["/ 40%", "/home 71%"]));
+        assert_eq!(changed_rows(&previous, &same), Some(vec![]));
+
+        // A longer line widens the panel, which moves it
+        let wider = manager.panel_window("every 2s: df", lines(&["/ 40%", "/home 71%", "/var/lib/docker 9%"]));
+        assert_eq!(changed_rows(&previous, &wider), None);
+    }
+
+    #[test]
+    fn test_popups_wrap_to_the_screen() {
+        let manager = WindowManager { terminal_size: (24, 24), ..test_manager() };
+        let window = manager.layout_colored_window(
+            "デプロイ",
+            vec!["ok".to_string(), "エラー: 接続がタイムアウトしました".to_string()],
+            vec![None, Some(Color::Red)],
+        );
+        assert_eq!(window.width, 24);
+        assert_eq!(window.x, 0);
+        assert_eq!(window.content, vec!["ok", "エラー:", "接続がタイムアウトし", "ました"]);
+        assert_eq!(window.colors, vec![None, Some(Color::Red), Some(Color::Red), Some(Color::Red)]);
+        assert_eq!(window.height, 8);
+
+        // Wide characters count twice when sizing
+        let window = manager.layout_window("?", vec!["日本語".to_string()]);
+        assert_eq!(window.width, 10);
+    }
+
+    #[test]
+    fn test_popup_cells() {
+        let manager = WindowManager { style: Style { plain: true, ..Style::default() }, ..test_manager() };
+        let window = manager.layout_window("Build", vec!["ok".to_string(), "1 warn".to_string()]);
+        let buffer = manager.window_buffer(&window, "ESC");
+        let rows: Vec<String> = (0..buffer.area.height)
+            .map(|y| {
+                let cells = &buffer.content[(y * buffer.area.width) as usize..((y + 1) * buffer.area.width) as usize];
+                cells.iter().map(|cell| cell.symbol()).collect()
+            })
+            .collect();
+        assert_eq!(rows, [
+            "+--------+",
+            "|  Build |",
+            "+--------+",
+            "| ok     |",
+            "| 1 warn |",
+            "+----ESC-+",
+        ]);
+    }
+
+    #[test]
+    fn test_emphasis_follows_wrapping() {
+        let manager = WindowManager { terminal_size: (14, 24), ..test_manager() };
+        let markdown::Rendered { lines, emphasis, .. } = markdown::render("build **failed in** 3s");
+        let lines = lines.into_iter().map(|(line, _)| line).collect();
+        let window = manager.layout_styled_window("CI", lines, Vec::new(), Vec::new(), emphasis);
+        assert_eq!(window.content, vec!["build", "failed in", "3s"]);
+        assert_eq!(window.emphasis, vec![vec![], vec![(0..9, Modifier::BOLD)], vec![]]);
+
+        let buffer = manager.window_buffer(&window, "ESC");
+        let (x, y) = (buffer.area.x + 2, buffer.area.y + 3);
+        let bold = |row: u16| buffer.get(x, y + row).modifier.contains(Modifier::BOLD);
+        assert!(!bold(0));
+        assert!(bold(1));
+    }
+
+    #[test]
+    fn test_closing_puts_back_the_screen() {
+        let rows = vec!["$ htop".to_string(), "  PID USER      CPU%".to_string(), "    1 root       0.3".to_string()];
+        let manager = WindowManager { screen_rows: rows, ..test_manager() };
+        let window = manager.layout_window("Note", vec!["hi".to_string()]);
+        let window = Window { x: 2, y: 1, height: 3, ..window };
+        let mut out = Vec::new();
+        manager.write_uncovered(&mut out, &window).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.contains("PID USER      CPU%"), "{:?}", out);
+        assert!(out.contains("  1 root       0.3"), "{:?}", out);
+        // Rows above the popup are left alone, and the blank ones under it
+        assert!(!out.contains("htop"), "{:?}", out);
+    }
+
+    #[test]
+    fn test_closed_popups_are_kept() {
+        let clock = Arc::new(MockClock::default());
+        let mut manager = WindowManager { clock: clock.clone(), ..test_manager() };
+        for number in 0..CLOSED_POPUP_LIMIT + 2 {
+            manager.remember_closed(&format!("Answer {}", number), vec![(number.to_string(), None)]);
+            clock.advance(Duration::from_secs(60));
+        }
+        assert_eq!(manager.closed[0].closed + chrono::Duration::minutes(CLOSED_POPUP_LIMIT as i64 - 1), manager.closed[CLOSED_POPUP_LIMIT - 1].closed);
+        assert_eq!(manager.closed[CLOSED_POPUP_LIMIT - 1].closed + chrono::Duration::minutes(1), clock.local());
+        assert_eq!(manager.closed.len(), CLOSED_POPUP_LIMIT);
+        assert_eq!(manager.closed[0].title, "Answer 2");
+        let last = CLOSED_POPUP_LIMIT - 1;
+        assert_eq!(manager.closed[last].lines, vec![((CLOSED_POPUP_LIMIT + 1).to_string(), None)]);
+        let title = reopen_title(&manager.closed, last);
+        assert!(title.contains("Answer 11"), "{}", title);
+        assert!(title.contains(&format!("{}/{}", CLOSED_POPUP_LIMIT, CLOSED_POPUP_LIMIT)), "{}", title);
+    }
+
+    #[test]
+    fn test_notes_go_to_corners() {
+        let manager = test_manager();
+        let note = Note { title: "Checklist".to_string(), lines: vec![("1. cargo fmt".to_string(), None); 2], expires: None };
+        let top_right = manager.note_window(&note, Corner::TopRight);
+        assert_eq!((top_right.x + top_right.width, top_right.y), (80, 0));
+        let bottom_left = manager.note_window(&note, Corner::BottomLeft);
+        assert_eq!((bottom_left.x, bottom_left.y + bottom_left.height), (0, 23));
+        assert_eq!(Corner::TopLeft.next(), Corner::TopRight);
+        assert_eq!(Corner::TopRight.next(), Corner::BottomRight);
+    }
+
+    #[test]
+    fn test_toasts_go_away_by_themselves() {
+        let clock = Arc::new(MockClock::default());
+        let mut manager = WindowManager { clock: clock.clone(), ..test_manager() };
+        manager.show_toast("Config reloaded", Duration::from_secs(2));
+        let toasts = manager.take_pinned();
+        assert_eq!(toasts.len(), 1);
+        assert_eq!(toasts[0].lines, vec![("Config reloaded".to_string(), None)]);
+        clock.advance(Duration::from_secs(1));
+        assert!(!toasts[0].expired(clock.now()));
+        clock.advance(Duration::from_secs(1));
+        assert!(toasts[0].expired(clock.now()));
+        assert!(manager.take_pinned().is_empty());
+    }
+
+    #[test]
+    fn test_menu_picking() {
+        let items: Vec<String> = ["Europe/Berlin", "America/New_York", "Asia/Tokyo", "UTC"].map(String::from).to_vec();
+        let none = KeyModifiers::empty();
+        let mut picker = Picker::default();
+        let listed = picker.listed(&items);
+        assert_eq!(listed, vec![0, 1, 2, 3]);
+        assert_eq!(picker.key(KeyCode::Up, none, &listed), None);
+        assert_eq!(picker.key(KeyCode::Down, none, &listed), None);
+        assert_eq!(picker.key(KeyCode::Enter, none, &listed), Some(Some(1)));
+        assert_eq!(picker.key(KeyCode::Char('3'), none, &listed), Some(Some(2)));
+        assert_eq!(picker.key(KeyCode::Char('9'), none, &listed), None);
+
+        // Typing filters, matches as typed first, and digits are typed too
+        for c in "or".chars() {
+            picker.key(KeyCode::Char(c), none, &listed);
+        }
+        let listed = picker.listed(&items);
+        assert_eq!(listed, vec![1, 0]);
+        assert_eq!(picker.key(KeyCode::End, none, &listed), None);
+        assert_eq!(picker.key(KeyCode::Down, none, &listed), None);
+        assert_eq!(picker.key(KeyCode::Enter, none, &listed), Some(Some(0)));
+        picker.key(KeyCode::Char('1'), none, &listed);
+        assert_eq!(picker.filter, "or1");
+        let listed = picker.listed(&items);
+        assert_eq!(picker.key(KeyCode::Enter, none, &listed), None);
+        picker.key(KeyCode::Char('u'), KeyModifiers::CONTROL, &listed);
+        assert_eq!(picker.listed(&items).len(), 4);
+        assert_eq!(picker.key(KeyCode::Esc, none, &listed), Some(None));
+
+        // A menu keeps the size of the whole list
+        let window = test_manager().menu_window("Time zone", &items);
+        assert_eq!((window.width, window.height), (38, 7));
+        let long: Vec<String> = (0..40).map(|i| format!("item {}", i)).collect();
+        assert_eq!(test_manager().menu_window("Pick", &long).height, 21);
+    }
+
+    #[test]
+    fn test_confirm_keys() {
+        let mut yes = false;
+        assert_eq!(confirm_key(KeyCode::Enter, &mut yes), Some(false));
+        assert_eq!(confirm_key(KeyCode::Tab, &mut yes), None);
+        assert_eq!(confirm_buttons(yes), "[ Yes ]   No  ");
+        assert_eq!(confirm_key(KeyCode::Enter, &mut yes), Some(true));
+        assert_eq!(confirm_key(KeyCode::Char('n'), &mut yes), Some(false));
+        assert_eq!(confirm_key(KeyCode::Esc, &mut yes), Some(false));
+        assert_eq!(confirm_key(KeyCode::Char('x'), &mut yes), None);
+        assert_eq!(confirm_buttons(false), "  Yes   [ No ]");
+    }
+
+    #[test]
+    fn test_live_popup() {
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let handle = PopupHandle::new(7, tx);
+        handle.append("Compiling a\r\nCompil");
+        handle.append("ing b\n");
+        handle.set_title("cargo build");
+        handle.append("Finished");
+        handle.finish();
+
+        let mut popup = LivePopup::new("make");
+        let mut finished = false;
+        while let Ok((id, update)) = rx.try_recv() {
+            assert_eq!(id, 7);
+            finished = popup.apply(update);
+        }
+        assert!(finished);
+        assert_eq!(popup.title, "cargo build");
+        assert_eq!(popup.lines, ["Compiling a", "Compiling b", "Finished"]);
+
+        // A long build keeps only its last lines
+        popup.append(&"warning\n".repeat(MAX_LIVE_POPUP_LINES));
+        assert_eq!(popup.lines.len(), MAX_LIVE_POPUP_LINES);
+        assert_eq!(popup.lines[popup.lines.len() - 2], "warning");
+    }
+
+    #[test]
+    fn test_chat_view() {
+        let mut chat = ChatView::default();
+        assert_eq!(chat.rows(80, 5), [(tr("chat-empty"), false)]);
+
+        chat.ask("why did make fail?");
+        chat.append("A header is\r\nmis");
+        chat.append("sing.\n");
+        chat.ask("and now?");
+        chat.append("Still");
+        assert_eq!(
+            chat.rows(12, 10),
+            [
+                ("> why did".to_string(), true),
+                ("make fail?".to_string(), true),
+                ("A header is".to_string(), false),
+                ("missing.".to_string(), false),
+                (String::new(), false),
+                ("> and now?".to_string(), true),
+                ("Still".to_string(), false),
+            ]
+        );
+        assert_eq!(chat.rows(12, 2), [("> and now?".to_string(), true), ("Still".to_string(), false)]);
+
+        chat.discard_last();
+        assert_eq!(chat.messages.len(), 1);
+        for i in 0..MAX_CHAT_MESSAGES {
+            chat.ask(&i.to_string());
+        }
+        assert_eq!(chat.messages.len(), MAX_CHAT_MESSAGES);
+        assert_eq!(chat.messages[0].question, "0");
+        assert!(!chat.is_open());
+    }
+
+    #[test]
+    fn test_text_area() {
+        let mut area = TextArea::new("db-02\nOPS-4711");
+        let none = KeyModifiers::NONE;
+        assert_eq!(area.rows(5, 20), ["db-02", "OPS-4711_"]);
+        assert!(!area.key(KeyCode::Up, none));
+        assert!(area.key(KeyCode::Char('x'), none));
+        assert!(area.key(KeyCode::Enter, none));
+        assert!(area.key(KeyCode::Char('é'), none));
+        assert_eq!(area.text(), "db-02x\né\nOPS-4711");
+        assert_eq!(area.rows(5, 20), ["db-02x", "é_", "OPS-4711"]);
+
+        // Joining lines again, and scrolled to keep the cursor in view
+        area.key(KeyCode::Home, none);
+        assert!(area.key(KeyCode::Backspace, none));
+        assert_eq!(area.text(), "db-02xé\nOPS-4711");
+        assert_eq!(area.rows(1, 20), ["db-02x_é"]);
+        area.key(KeyCode::Down, none);
+        assert_eq!(area.rows(1, 20), ["OPS-47_11"]);
+        assert_eq!(area.rows(1, 4), ["-47_"]);
+        area.key(KeyCode::End, none);
+        assert!(!area.key(KeyCode::Delete, none));
+        area.key(KeyCode::Up, none);
+        assert!(area.key(KeyCode::Delete, none));
+        assert_eq!(area.text(), "db-02xéOPS-4711");
+    }
+}
@@ -20,6 +20,7 @@ async fn test_pty_shell_spawning() -> Result<()> {
         command: "/bin/bash".to_string(),
         args: vec!["-i".to_string()],
         env: None,
+        ..Default::default()
     };
 
     let pty = PtySession::spawn(&shell_config)?;
@@ -113,6 +114,7 @@ async fn test_vi_editor_interaction() -> Result<()> {
         command: "/bin/bash".to_string(),
         args: vec!["-i".to_string()],
         env: None,
+        ..Default::default()
     };
 
     let pty = PtySession::spawn(&shell_config)?;
@@ -157,7 +159,7 @@ async fn test_vi_editor_interaction() -> Result<()> {
 #[test]
 fn test_hook_system() {
     let hooks = create_default_hooks();
-    let hook_manager = HookManager::from_configs(hooks);
+    let mut hook_manager = HookManager::from_configs(hooks);
     
     // Test help hook (Ctrl+;)
     let help_key = KeyInput::new(KeyCode::Char(';'), KeyModifiers::CONTROL);
@@ -181,7 +183,7 @@ fn test_hook_system() {
 /// Test configuration loading and saving
 #[test]
 fn test_config_operations() -> Result<()> {
-    let mut temp_file = NamedTempFile::new()?;
+    let temp_file = NamedTempFile::new()?;
     let config_path = temp_file.path().to_string_lossy().to_string();
     
     // Create a test config
@@ -190,16 +192,19 @@ fn test_config_operations() -> Result<()> {
             command: "/bin/zsh".to_string(),
             args: vec!["-l".to_string()],
             env: Some([("TEST_VAR".to_string(), "test_value".to_string())].into()),
+            ..Default::default()
         },
         hooks: vec![
             HookConfig {
                 name: "test_hook".to_string(),
                 key_combination: "ctrl+x".to_string(),
-                action: "echo 'test'".to_string(),
+                action: "echo 'test'".into(),
                 description: Some("Test hook".to_string()),
                 enabled: true,
+                ..Default::default()
             }
         ],
+        ..Default::default()
     };
     
     // Save config
@@ -225,6 +230,7 @@ async fn test_pty_resize() -> Result<()> {
         command: "/bin/bash".to_string(),
         args: vec!["-i".to_string()],
         env: None,
+        ..Default::default()
     };
 
     let pty = PtySession::spawn(&shell_config)?;
@@ -256,6 +262,7 @@ async fn test_signal_handling() -> Result<()> {
         command: "/bin/bash".to_string(),
         args: vec!["-i".to_string()],
         env: None,
+        ..Default::default()
     };
 
     let pty = PtySession::spawn(&shell_config)?;
@@ -288,6 +295,7 @@ async fn test_command_history_navigation() -> Result<()> {
         command: "/bin/bash".to_string(),
         args: vec!["-i".to_string()],
         env: None,
+        ..Default::default()
     };
 
     let pty = PtySession::spawn(&shell_config)?;
@@ -331,6 +339,7 @@ async fn test_tab_completion() -> Result<()> {
         command: "/bin/bash".to_string(),
         args: vec!["-i".to_string()],
         env: None,
+        ..Default::default()
     };
 
     let pty = PtySession::spawn(&shell_config)?;
@@ -387,9 +396,10 @@ fn test_custom_hook_execution() -> Result<()> {
     let hook_config = HookConfig {
         name: "date_hook".to_string(),
         key_combination: "ctrl+d".to_string(),
-        action: "cmd:date".to_string(),
+        action: "cmd:date".into(),
         description: Some("Show current date".to_string()),
         enabled: true,
+        ..Default::default()
     };
     
     hook_manager.add_hook(hook_config);
@@ -410,6 +420,7 @@ async fn test_rapid_key_sequences() -> Result<()> {
         command: "/bin/bash".to_string(),
         args: vec!["-i".to_string()],
         env: None,
+        ..Default::default()
     };
 
     let pty = PtySession::spawn(&shell_config)?;
@@ -1,6 +1,6 @@
 use proptest::prelude::*;
 use crossterm::event::{KeyCode, KeyModifiers};
-use chatshell::terminal::KeyInput;
+use chatshell::terminal::{parse_chord, KeyInput, Press};
 
 // Simple property test for basic key functionality
 proptest! {
@@ -82,4 +82,123 @@ proptest! {
             prop_assert!(key_input.raw_bytes.len() > 3);
         }
     }
-}
\ No newline at end of file
+}
+
+// Every letter with every mix of modifiers, and every function key
+#[test]
+fn test_all_key_combinations_have_bytes() {
+    let mut keys = Vec::new();
+    for c in 'a'..='z' {
+        for &ctrl in &[false, true] {
+            for &alt in &[false, true] {
+                for &shift in &[false, true] {
+                    let mut modifiers = KeyModifiers::empty();
+                    if ctrl { modifiers |= KeyModifiers::CONTROL; }
+                    if alt { modifiers |= KeyModifiers::ALT; }
+                    if shift { modifiers |= KeyModifiers::SHIFT; }
+                    keys.push(KeyInput::new(KeyCode::Char(c), modifiers));
+                }
+            }
+        }
+    }
+    for f_num in 1..=12 {
+        keys.push(KeyInput::new(KeyCode::F(f_num), KeyModifiers::empty()));
+    }
+
+    assert_eq!(keys.len(), 26 * 8 + 12);
+    assert!(keys.iter().all(|key| !key.raw_bytes.is_empty()));
+}
+
+// Keys a pattern can name, with the modifiers a pattern can name. Letters
+// are lowercase, as patterns are, and shift+tab is left to BackTab, as
+// terminals report it
+fn any_key() -> impl Strategy<Value = KeyInput> {
+    let code = prop_oneof![
+        proptest::char::range('!', '~').prop_filter("lowercase", |c| !c.is_ascii_uppercase()).prop_map(KeyCode::Char),
+        prop::sample::select(vec![' ', 'é', 'ß', '日', '€']).prop_map(KeyCode::Char),
+        (1u8..=12).prop_map(KeyCode::F),
+        prop::sample::select(vec![
+            KeyCode::Enter, KeyCode::Tab, KeyCode::BackTab, KeyCode::Esc, KeyCode::Backspace,
+            KeyCode::Up, KeyCode::Down, KeyCode::Left, KeyCode::Right, KeyCode::Home, KeyCode::End,
+            KeyCode::PageUp, KeyCode::PageDown, KeyCode::Insert, KeyCode::Delete,
+        ]),
+    ];
+    let modifiers = (0u8..32).prop_map(|bits| {
+        let all = [KeyModifiers::CONTROL, KeyModifiers::ALT, KeyModifiers::SHIFT, KeyModifiers::SUPER, KeyModifiers::META];
+        all.iter().enumerate().filter(|(i, _)| bits & (1 << i) != 0).fold(KeyModifiers::empty(), |all, (_, m)| all | *m)
+    });
+    (code, modifiers)
+        .prop_filter("shift+tab is backtab", |(code, modifiers)| !(*code == KeyCode::Tab && modifiers.contains(KeyModifiers::SHIFT)))
+        .prop_map(|(code, modifiers)| KeyInput::new(code, modifiers))
+}
+
+fn any_press() -> impl Strategy<Value = Press> {
+    prop_oneof![Just(Press::Single), Just(Press::Double), Just(Press::Long)]
+}
+
+// Patterns read back as the keys they were written for
+proptest! {
+    #[test]
+    fn test_patterns_round_trip(key in any_key(), press in any_press()) {
+        let key = KeyInput { press, ..key };
+        let pattern = key.pattern().unwrap();
+        prop_assert_eq!(KeyInput::parse(&pattern), Some(key.clone()), "{}", pattern);
+        prop_assert!(key.matches_trigger(&pattern), "{}", pattern);
+        // However it is capitalized
+        prop_assert_eq!(KeyInput::parse(&pattern.to_ascii_uppercase()).map(|parsed| parsed.modifiers), Some(key.modifiers));
+    }
+
+    #[test]
+    fn test_chords_round_trip(prefix in any_key(), key in any_key()) {
+        prop_assume!(!prefix.same_key(&key));
+        let chord = format!("{} {}", prefix.pattern().unwrap(), key.pattern().unwrap());
+        prop_assert_eq!(parse_chord(&chord), Some((prefix, key)), "{}", chord);
+    }
+
+    // The same key twice is a double press rather than a chord
+    #[test]
+    fn test_repeated_key_is_no_chord(key in any_key()) {
+        let pattern = key.pattern().unwrap();
+        let twice = format!("{} {}", pattern, pattern);
+        prop_assert!(parse_chord(&twice).is_none());
+        prop_assert_eq!(KeyInput::parse(&twice).map(|parsed| parsed.press), Some(Press::Double));
+    }
+
+    #[test]
+    fn test_unknown_modifiers_are_rejected(
+        modifier in "[a-z]{1,8}".prop_filter("not a modifier", |name| !["ctrl", "alt", "shift", "super", "meta"].contains(&name.as_str())),
+        key in any_key(),
+    ) {
+        let pattern = format!("{}+{}", modifier, key.pattern().unwrap());
+        prop_assert!(KeyInput::parse(&pattern).is_none(), "{}", pattern);
+    }
+
+    #[test]
+    fn test_unknown_key_names_are_rejected(
+        name in "[a-z]{2,10}".prop_filter("not a key name", |name| {
+            let named = ["enter", "return", "tab", "backtab", "space", "esc", "escape", "backspace", "up", "down",
+                "left", "right", "home", "end", "pageup", "pagedown", "insert", "delete", "del", "hold"];
+            !named.contains(&name.as_str())
+        }),
+        number in 13u32..1000,
+    ) {
+        prop_assert!(KeyInput::parse(&name).is_none(), "{}", name);
+        prop_assert!(KeyInput::parse(&format!("ctrl+{}", name)).is_none(), "ctrl+{}", name);
+        let function_key = format!("f{}", number);
+        prop_assert!(KeyInput::parse(&function_key).is_none(), "{}", function_key);
+        prop_assert!(KeyInput::parse("ctrl+").is_none());
+    }
+
+    // Whatever is typed into a config, parsing it never panics
+    #[test]
+    fn test_parsing_arbitrary_patterns(pattern in "[a-z0-9+ ]{0,24}|\\PC{0,16}") {
+        let _ = KeyInput::parse(&pattern);
+        let _ = parse_chord(&pattern);
+        if let Some(key) = KeyInput::parse(&pattern) {
+            // What parsed writes back to a pattern for the same key
+            if let Some(written) = key.pattern() {
+                prop_assert!(KeyInput::parse(&written).is_some_and(|again| again.same_key(&key)), "{} -> {}", pattern, written);
+            }
+        }
+    }
+}